@@ -15,8 +15,17 @@ fn bench_render(c: &mut Criterion) {
     let scene_path = scene_path.to_str().unwrap();
 
     let pixel_samples = 16;
-    let (mut camera, render_scene, _) =
-        common::importer::import(&log, &scene_path, &common::DEFAULT_RESOLUTION, false);
+    let (mut camera, render_scene, _) = common::importer::import(
+        &log,
+        &scene_path,
+        &[],
+        None,
+        &common::DEFAULT_RESOLUTION,
+        false,
+        None,
+        None,
+        None,
+    );
     let sampler = pathtracer::sampler::SamplerBuilder::new(
         &log,
         pixel_samples,
@@ -39,6 +48,7 @@ fn bench_bounds(c: &mut Criterion) {
         o: nalgebra::Point3::origin(),
         d: nalgebra::Vector3::new(1.0, 1.0, 1.0),
         t_max: f32::INFINITY,
+        time: 0.0,
     };
     let inv_dir = nalgebra::Vector3::new(1.0f32 / r.d.x, 1.0f32 / r.d.y, 1.0f32 / r.d.z);
     let dir_is_neg = [inv_dir.x < 0.0, inv_dir.y < 0.0, inv_dir.z < 0.0];
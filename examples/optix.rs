@@ -9,8 +9,17 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let log = slog::Logger::root(drain.fuse(), o!());
     let scene_path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("data/cornell-box.xml");
     let scene_path = scene_path.to_str().unwrap();
-    let (mut camera, render_scene, _) =
-        common::importer::import(&log, &scene_path, &common::DEFAULT_RESOLUTION, false);
+    let (mut camera, render_scene, _) = common::importer::import(
+        &log,
+        &scene_path,
+        &[],
+        None,
+        &common::DEFAULT_RESOLUTION,
+        false,
+        None,
+        None,
+        None,
+    );
 
     let mut accel = pathtracer::gpu::optix::OptixAccelerator::new(&render_scene)?;
 
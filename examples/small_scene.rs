@@ -10,8 +10,17 @@ fn main() {
     let scene_path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("data/cornell-box.xml");
     let scene_path = scene_path.to_str().unwrap();
     let pixel_samples = 16;
-    let (mut camera, render_scene, _) =
-        common::importer::import(&log, &scene_path, &common::DEFAULT_RESOLUTION, false);
+    let (mut camera, render_scene, _) = common::importer::import(
+        &log,
+        &scene_path,
+        &[],
+        None,
+        &common::DEFAULT_RESOLUTION,
+        false,
+        None,
+        None,
+        None,
+    );
     let sampler = pathtracer::sampler::SamplerBuilder::new(
         &log,
         pixel_samples,
@@ -0,0 +1,123 @@
+use crate::{
+    common::openexr::{save_exr, ExrPrecision},
+    common::Camera,
+    pathtracer::{integrator::PathIntegrator, RenderScene},
+    viewer::camera::look_at_pivot,
+};
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+
+/// where the camera is and what it looks at in one keyframe of a camera path
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct Keyframe {
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+}
+
+/// how the camera moves across the frames of an animation
+pub enum CameraPath {
+    /// a full orbit about the vertical axis through `pivot`, starting where the camera is. the
+    /// frame after the last is the first again, so the frames loop
+    Turntable { pivot: glm::Vec3 },
+    /// keyframes spread evenly over the animation, the camera moving in straight lines between
+    /// them
+    Keyframes(Vec<Keyframe>),
+}
+
+impl CameraPath {
+    /// reads the keyframes of a camera path from a json list, e.g.
+    /// `[{"eye": [0, 1, 5], "target": [0, 0, 0]}, {"eye": [5, 1, 0], "target": [0, 0, 0]}]`
+    pub fn load(path: &Path) -> Result<Self> {
+        let file =
+            std::fs::File::open(path).with_context(|| format!("opening camera path {:?}", path))?;
+        let keyframes: Vec<Keyframe> = serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| format!("parsing camera path {:?}", path))?;
+        if keyframes.is_empty() {
+            return Err(anyhow!("camera path {:?} has no keyframes", path));
+        }
+
+        Ok(CameraPath::Keyframes(keyframes))
+    }
+
+    /// where the camera starting at `start` is in frame `frame` of `n_frames`
+    pub fn cam_to_world(
+        &self,
+        start: &na::Isometry3<f32>,
+        frame: usize,
+        n_frames: usize,
+    ) -> na::Isometry3<f32> {
+        match self {
+            CameraPath::Turntable { pivot } => {
+                let angle = 2.0 * std::f32::consts::PI * frame as f32 / n_frames as f32;
+                let cam_pos = glm::rotate_vec3(
+                    &(start.translation.vector - pivot),
+                    angle,
+                    &glm::vec3(0.0, 1.0, 0.0),
+                );
+                look_at_pivot(&(cam_pos + pivot), pivot)
+            }
+            CameraPath::Keyframes(keyframes) => {
+                let u = if n_frames > 1 {
+                    frame as f32 / (n_frames - 1) as f32
+                } else {
+                    0.0
+                };
+                let segment = u * (keyframes.len() - 1) as f32;
+                let i = (segment as usize).min(keyframes.len() - 1);
+                let (from, to) = (&keyframes[i], &keyframes[(i + 1).min(keyframes.len() - 1)]);
+                let t = segment - i as f32;
+                let lerp = |a: &[f32; 3], b: &[f32; 3]| {
+                    glm::lerp(&glm::make_vec3(a), &glm::make_vec3(b), t)
+                };
+                look_at_pivot(&lerp(&from.eye, &to.eye), &lerp(&from.target, &to.target))
+            }
+        }
+    }
+}
+
+/// `output_path` with the frame number appended to its name, e.g. render_0012.png
+fn frame_path(output_path: &Path, frame: usize) -> PathBuf {
+    let stem = output_path.file_stem().unwrap().to_string_lossy();
+    let extension = output_path.extension().unwrap().to_string_lossy();
+    output_path.with_file_name(format!("{}_{:04}.{}", stem, frame, extension))
+}
+
+/// renders `n_frames` frames of `camera` moving along `path`, each saved next to `output_path`
+/// under its frame number, as openexr images of `exr` precision when given
+pub fn run(
+    log: slog::Logger,
+    render_scene: RenderScene,
+    mut camera: Camera,
+    integrator: PathIntegrator,
+    path: &CameraPath,
+    n_frames: usize,
+    output_path: PathBuf,
+    exr: Option<ExrPrecision>,
+) -> Result<()> {
+    let log = log.new(o!("module" => "animation"));
+    let output_path = match exr {
+        Some(_) => output_path.with_extension("exr"),
+        None => output_path,
+    };
+    let start = camera.cam_to_world;
+    for frame in 0..n_frames {
+        camera.cam_to_world = path.cam_to_world(&start, frame, n_frames);
+        camera.film.clear();
+        integrator.render(&camera, &render_scene);
+
+        let frame_path = frame_path(&output_path, frame);
+        match exr {
+            Some(precision) => save_exr(&frame_path, &[("render", &camera.film)], precision)?,
+            None => camera.film.save_image(&frame_path)?,
+        }
+        info!(
+            log,
+            "saved frame {} of {} to {:?}",
+            frame + 1,
+            n_frames,
+            frame_path
+        );
+    }
+
+    Ok(())
+}
@@ -1,7 +1,7 @@
 use super::math::*;
 use super::ray::Ray;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct TBounds2<T: na::Scalar> {
     pub p_min: na::Point2<T>,
     pub p_max: na::Point2<T>,
@@ -163,6 +163,18 @@ impl<T: na::RealField> TBounds3<T> {
 }
 
 impl Bounds3 {
+    /// bounds of the eight corners after transforming them by `t`
+    pub fn transform(&self, t: &na::Projective3<f32>) -> Self {
+        (0..8).fold(Bounds3::empty(), |bounds, corner| {
+            let p = na::Point3::new(
+                self[corner & 1 != 0].x,
+                self[corner & 2 != 0].y,
+                self[corner & 4 != 0].z,
+            );
+            Bounds3::union_p(&bounds, &(t * p))
+        })
+    }
+
     pub fn intersect_p(&self, r: &Ray) -> Option<(f32, f32)> {
         let mut t0 = 0.0;
         let mut t1 = r.t_max;
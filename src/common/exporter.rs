@@ -0,0 +1,665 @@
+//! writes scenes back out as gltf, so modified or merged scenes can be saved and shared. meshes
+//! are either kept in object space with a node placing each of their copies, or baked into world
+//! space. materials are written as metallic roughness parameters with the extensions the gltf
+//! importer reads back, textures are taken at a single point of the surface
+
+use super::{math::gamma_correct, spectrum::Spectrum, Camera, Projection};
+use anyhow::{anyhow, Context, Result};
+use serde_json::json;
+use std::{collections::BTreeSet, path::Path};
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_VERSION: u32 = 2;
+const GLB_JSON_CHUNK: u32 = 0x4E4F_534A;
+const GLB_BIN_CHUNK: u32 = 0x004E_4942;
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const TRIANGLES: u32 = 4;
+
+/// index of refraction gltf materials have without `KHR_materials_ior`
+const DEFAULT_IOR: f32 = 1.5;
+
+/// metallic roughness parameters approximating a material of the scene
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportedMaterial {
+    /// linear base color
+    pub base_color: Spectrum,
+    pub metallic: f32,
+    /// perceptual roughness, the square root of the microfacet alpha
+    pub roughness: f32,
+    pub ior: f32,
+    /// fraction of light passing through the surface, tinted by the base color
+    pub transmission: f32,
+    /// absorption per world unit of the medium inside transmissive materials
+    pub absorption: Option<Spectrum>,
+    /// light passing diffusely through thin surfaces, e.g. leaves
+    pub diffuse_transmission: Option<Spectrum>,
+    /// strength and tint of the dielectric reflection
+    pub specular: Option<(f32, Spectrum)>,
+    pub sheen: Option<Spectrum>,
+    /// strength and perceptual roughness of the clear coat
+    pub clearcoat: Option<(f32, f32)>,
+    /// radiance of emissive meshes
+    pub emission: Spectrum,
+    pub double_sided: bool,
+}
+
+impl Default for ExportedMaterial {
+    fn default() -> Self {
+        Self {
+            base_color: Spectrum::new(1.0),
+            metallic: 0.0,
+            roughness: 1.0,
+            ior: DEFAULT_IOR,
+            transmission: 0.0,
+            absorption: None,
+            diffuse_transmission: None,
+            specular: None,
+            sheen: None,
+            clearcoat: None,
+            emission: Spectrum::new(0.0),
+            double_sided: false,
+        }
+    }
+}
+
+fn color_json(color: &Spectrum) -> serde_json::Value {
+    json!([color.r(), color.g(), color.b()])
+}
+
+impl ExportedMaterial {
+    /// `self` weighted by `1 - amount` blended with `other` weighted by `amount`, the optional
+    /// layers are taken from the one weighing more
+    pub fn lerp(&self, other: &Self, amount: f32) -> Self {
+        let mix = |a: f32, b: f32| (1.0 - amount) * a + amount * b;
+        let mix_color = |a: Spectrum, b: Spectrum| a * (1.0 - amount) + b * amount;
+        let heavier = if amount < 0.5 { self } else { other };
+        Self {
+            base_color: mix_color(self.base_color, other.base_color),
+            metallic: mix(self.metallic, other.metallic),
+            roughness: mix(self.roughness, other.roughness),
+            ior: mix(self.ior, other.ior),
+            transmission: mix(self.transmission, other.transmission),
+            absorption: heavier.absorption,
+            diffuse_transmission: heavier.diffuse_transmission,
+            specular: heavier.specular,
+            sheen: heavier.sheen,
+            clearcoat: heavier.clearcoat,
+            emission: mix_color(self.emission, other.emission),
+            double_sided: self.double_sided || other.double_sided,
+        }
+    }
+
+    /// the gltf material, the extensions it needs are added to `extensions_used`
+    fn to_json(&self, extensions_used: &mut BTreeSet<String>) -> serde_json::Value {
+        // the importer reads base color factors as srgb
+        let mut material = json!({
+            "pbrMetallicRoughness": {
+                "baseColorFactor": [
+                    gamma_correct(self.base_color.r()),
+                    gamma_correct(self.base_color.g()),
+                    gamma_correct(self.base_color.b()),
+                    1.0
+                ],
+                "metallicFactor": self.metallic,
+                "roughnessFactor": self.roughness,
+            },
+            "doubleSided": self.double_sided,
+        });
+        let mut extensions = serde_json::Map::new();
+
+        // emissive factors only go up to 1.0, KHR_materials_emissive_strength scales them past it
+        let emissive_strength = self.emission.max_component_value();
+        if emissive_strength > 0.0 {
+            material["emissiveFactor"] = color_json(&(self.emission / emissive_strength.max(1.0)));
+            if emissive_strength > 1.0 {
+                extensions.insert(
+                    "KHR_materials_emissive_strength".to_owned(),
+                    json!({ "emissiveStrength": emissive_strength }),
+                );
+            }
+        }
+        if self.ior != DEFAULT_IOR {
+            extensions.insert("KHR_materials_ior".to_owned(), json!({ "ior": self.ior }));
+        }
+        if self.transmission > 0.0 {
+            extensions.insert(
+                "KHR_materials_transmission".to_owned(),
+                json!({ "transmissionFactor": self.transmission }),
+            );
+        }
+        if let Some(absorption) = self.absorption {
+            // light keeps the attenuation color after traveling one world unit
+            extensions.insert(
+                "KHR_materials_volume".to_owned(),
+                json!({
+                    "thicknessFactor": 1.0,
+                    "attenuationDistance": 1.0,
+                    "attenuationColor": color_json(&(absorption * -1.0).exp()),
+                }),
+            );
+        }
+        if let Some(transmitted) = self.diffuse_transmission {
+            let factor = transmitted.max_component_value();
+            if factor > 0.0 {
+                extensions.insert(
+                    "KHR_materials_diffuse_transmission".to_owned(),
+                    json!({
+                        "diffuseTransmissionFactor": factor,
+                        "diffuseTransmissionColorFactor": color_json(&(transmitted / factor)),
+                    }),
+                );
+            }
+        }
+        if let Some((weight, color)) = self.specular {
+            extensions.insert(
+                "KHR_materials_specular".to_owned(),
+                json!({ "specularFactor": weight, "specularColorFactor": color_json(&color) }),
+            );
+        }
+        if let Some(color) = self.sheen {
+            extensions.insert(
+                "KHR_materials_sheen".to_owned(),
+                json!({ "sheenColorFactor": color_json(&color) }),
+            );
+        }
+        if let Some((weight, roughness)) = self.clearcoat {
+            extensions.insert(
+                "KHR_materials_clearcoat".to_owned(),
+                json!({ "clearcoatFactor": weight, "clearcoatRoughnessFactor": roughness }),
+            );
+        }
+
+        if !extensions.is_empty() {
+            extensions_used.extend(extensions.keys().cloned());
+            material["extensions"] = serde_json::Value::Object(extensions);
+        }
+        material
+    }
+}
+
+/// a mesh in its object space and the transforms of its copies
+#[derive(Clone, Debug)]
+pub struct ExportedMesh {
+    /// three per triangle
+    pub indices: Vec<u32>,
+    pub pos: Vec<na::Point3<f32>>,
+    pub normal: Vec<na::Vector3<f32>>,
+    pub uv: Vec<na::Point2<f32>>,
+    /// linear vertex colors
+    pub colors: Vec<na::Vector3<f32>>,
+    /// index into the materials of the export, the gltf default material when `None`
+    pub material: Option<usize>,
+    /// object to world transforms of the copies, written as one node each
+    pub instances: Vec<na::Projective3<f32>>,
+}
+
+impl ExportedMesh {
+    /// a copy of the mesh in world space for each of its instances. mirroring transforms reverse
+    /// the winding of the triangles, which gltf only accounts for on nodes
+    fn baked(&self) -> Vec<Self> {
+        self.instances
+            .iter()
+            .map(|obj_to_world| {
+                let linear = obj_to_world
+                    .matrix()
+                    .fixed_slice::<na::U3, na::U3>(0, 0)
+                    .clone_owned();
+                // normals go to world space by the inverse transpose
+                let normal_to_world = linear
+                    .try_inverse()
+                    .map_or_else(na::Matrix3::identity, |inverse| inverse.transpose());
+                let mut indices = self.indices.clone();
+                if linear.determinant() < 0.0 {
+                    for triangle in indices.chunks_exact_mut(3) {
+                        triangle.swap(1, 2);
+                    }
+                }
+
+                Self {
+                    indices,
+                    pos: self.pos.iter().map(|p| obj_to_world * p).collect(),
+                    normal: self
+                        .normal
+                        .iter()
+                        .map(|n| (normal_to_world * n).normalize())
+                        .collect(),
+                    uv: self.uv.clone(),
+                    colors: self.colors.clone(),
+                    material: self.material,
+                    instances: vec![na::Projective3::identity()],
+                }
+            })
+            .collect()
+    }
+}
+
+/// the binary buffer of an export and the views and accessors into it
+#[derive(Default)]
+struct BufferBuilder {
+    data: Vec<u8>,
+    views: Vec<serde_json::Value>,
+    accessors: Vec<serde_json::Value>,
+}
+
+impl BufferBuilder {
+    /// every component is 4 bytes long, so the views stay aligned without padding
+    fn push_view(&mut self, bytes: Vec<u8>, target: u32) -> usize {
+        self.views.push(json!({
+            "buffer": 0,
+            "byteOffset": self.data.len(),
+            "byteLength": bytes.len(),
+            "target": target,
+        }));
+        self.data.extend(bytes);
+        self.views.len() - 1
+    }
+
+    /// accessor of `kind` over `values`, `components` of them per element. the bounds are
+    /// required for positions
+    fn push_floats(
+        &mut self,
+        values: &[f32],
+        components: usize,
+        kind: &str,
+        with_bounds: bool,
+    ) -> usize {
+        let bytes = values
+            .iter()
+            .flat_map(|value| value.to_le_bytes().to_vec())
+            .collect();
+        let mut accessor = json!({
+            "bufferView": self.push_view(bytes, ARRAY_BUFFER),
+            "componentType": FLOAT,
+            "count": values.len() / components,
+            "type": kind,
+        });
+        if with_bounds {
+            let mut min = vec![f32::INFINITY; components];
+            let mut max = vec![f32::NEG_INFINITY; components];
+            for element in values.chunks_exact(components) {
+                for (axis, &value) in element.iter().enumerate() {
+                    min[axis] = min[axis].min(value);
+                    max[axis] = max[axis].max(value);
+                }
+            }
+            accessor["min"] = json!(min);
+            accessor["max"] = json!(max);
+        }
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn push_indices(&mut self, indices: &[u32]) -> usize {
+        let bytes = indices
+            .iter()
+            .flat_map(|index| index.to_le_bytes().to_vec())
+            .collect();
+        self.accessors.push(json!({
+            "bufferView": self.push_view(bytes, ELEMENT_ARRAY_BUFFER),
+            "componentType": UNSIGNED_INT,
+            "count": indices.len(),
+            "type": "SCALAR",
+        }));
+        self.accessors.len() - 1
+    }
+}
+
+fn camera_json(log: &slog::Logger, projection: &Projection) -> serde_json::Value {
+    let perspective = |projection: &na::Perspective3<f32>| {
+        json!({
+            "type": "perspective",
+            "perspective": {
+                "aspectRatio": projection.aspect(),
+                "yfov": projection.fovy(),
+                "znear": projection.znear(),
+                "zfar": projection.zfar(),
+            },
+        })
+    };
+    match projection {
+        Projection::Perspective(projection) => perspective(projection),
+        Projection::Orthographic(projection) => json!({
+            "type": "orthographic",
+            "orthographic": {
+                "xmag": 0.5 * (projection.right() - projection.left()),
+                "ymag": 0.5 * (projection.top() - projection.bottom()),
+                "znear": projection.znear(),
+                "zfar": projection.zfar(),
+            },
+        }),
+        Projection::Spherical { preview }
+        | Projection::Fisheye { preview, .. }
+        | Projection::Realistic { preview, .. } => {
+            warn!(
+                log,
+                "gltf has no panoramic or lens cameras, exporting the preview's perspective"
+            );
+            perspective(preview)
+        }
+    }
+}
+
+fn matrix_json(transform: &na::Matrix4<f32>) -> Option<serde_json::Value> {
+    // column major like nalgebra, identities are left out
+    if *transform == na::Matrix4::identity() {
+        None
+    } else {
+        Some(json!(transform.as_slice()))
+    }
+}
+
+/// meshes, materials and camera of a scene to write to gltf, see `RenderScene::to_export` and
+/// `ViewerScene::to_export`
+#[derive(Clone, Debug, Default)]
+pub struct SceneExport {
+    pub meshes: Vec<ExportedMesh>,
+    pub materials: Vec<ExportedMaterial>,
+    camera: Option<(na::Isometry3<f32>, Projection)>,
+}
+
+impl SceneExport {
+    pub fn with_camera(mut self, camera: &Camera) -> Self {
+        self.camera = Some((camera.cam_to_world, camera.cam_to_screen.clone()));
+        self
+    }
+
+    /// adds the meshes and materials of `other`, keeping the camera of `self` if it has one
+    pub fn merge(&mut self, other: SceneExport) {
+        let material_offset = self.materials.len();
+        self.materials.extend(other.materials);
+        self.meshes.extend(other.meshes.into_iter().map(|mut mesh| {
+            mesh.material = mesh.material.map(|material| material + material_offset);
+            mesh
+        }));
+        if self.camera.is_none() {
+            self.camera = other.camera;
+        }
+    }
+
+    /// bakes every copy of the meshes into world space, for tools that don't instance
+    pub fn flatten(&mut self) {
+        self.meshes = self.meshes.iter().flat_map(ExportedMesh::baked).collect();
+    }
+
+    /// the gltf json without its buffer, and the contents of the buffer
+    fn to_json(&self, log: &slog::Logger) -> (serde_json::Value, Vec<u8>) {
+        let mut buffer = BufferBuilder::default();
+        let mut extensions_used = BTreeSet::new();
+        let materials: Vec<_> = self
+            .materials
+            .iter()
+            .map(|material| material.to_json(&mut extensions_used))
+            .collect();
+
+        let mut meshes = vec![];
+        let mut nodes = vec![];
+        let mut skipped = 0;
+        for mesh in &self.meshes {
+            if mesh.indices.len() < 3 || mesh.instances.is_empty() {
+                skipped += 1;
+                continue;
+            }
+
+            let pos: Vec<f32> = mesh
+                .pos
+                .iter()
+                .flat_map(|p| p.coords.as_slice().to_vec())
+                .collect();
+            let mut attributes = json!({ "POSITION": buffer.push_floats(&pos, 3, "VEC3", true) });
+            if mesh.normal.len() == mesh.pos.len() {
+                let normal: Vec<f32> = mesh
+                    .normal
+                    .iter()
+                    .flat_map(|n| n.normalize().as_slice().to_vec())
+                    .collect();
+                attributes["NORMAL"] = json!(buffer.push_floats(&normal, 3, "VEC3", false));
+            }
+            if mesh.uv.len() == mesh.pos.len() {
+                let uv: Vec<f32> = mesh.uv.iter().flat_map(|uv| vec![uv.x, uv.y]).collect();
+                attributes["TEXCOORD_0"] = json!(buffer.push_floats(&uv, 2, "VEC2", false));
+            }
+            if mesh.colors.len() == mesh.pos.len() {
+                let colors: Vec<f32> = mesh
+                    .colors
+                    .iter()
+                    .flat_map(|color| color.as_slice().to_vec())
+                    .collect();
+                attributes["COLOR_0"] = json!(buffer.push_floats(&colors, 3, "VEC3", false));
+            }
+
+            let mut primitive = json!({
+                "attributes": attributes,
+                "indices": buffer.push_indices(&mesh.indices),
+                "mode": TRIANGLES,
+            });
+            if let Some(material) = mesh.material {
+                primitive["material"] = json!(material);
+            }
+            meshes.push(json!({ "primitives": [primitive] }));
+
+            for obj_to_world in &mesh.instances {
+                let mut node = json!({ "mesh": meshes.len() - 1 });
+                if let Some(matrix) = matrix_json(obj_to_world.matrix()) {
+                    node["matrix"] = matrix;
+                }
+                nodes.push(node);
+            }
+        }
+        if skipped > 0 {
+            warn!(
+                log,
+                "skipped {} meshes without triangles or copies", skipped
+            );
+        }
+
+        let mut cameras = vec![];
+        if let Some((cam_to_world, projection)) = &self.camera {
+            cameras.push(camera_json(log, projection));
+            let mut node = json!({ "name": "camera", "camera": 0 });
+            if let Some(matrix) = matrix_json(&cam_to_world.to_homogeneous()) {
+                node["matrix"] = matrix;
+            }
+            nodes.push(node);
+        }
+        debug!(
+            log,
+            "exporting {} meshes placed by {} nodes and {} materials",
+            meshes.len(),
+            nodes.len() - cameras.len(),
+            materials.len()
+        );
+
+        let mut root = json!({
+            "asset": { "version": "2.0", "generator": "pathtracer-rs" },
+            "scene": 0,
+            "scenes": [{ "nodes": (0..nodes.len()).collect::<Vec<_>>() }],
+            "nodes": nodes,
+        });
+        for (name, values) in vec![
+            ("meshes", meshes),
+            ("materials", materials),
+            ("cameras", cameras),
+            ("accessors", buffer.accessors),
+            ("bufferViews", buffer.views),
+        ] {
+            if !values.is_empty() {
+                root[name] = json!(values);
+            }
+        }
+        if !extensions_used.is_empty() {
+            root["extensionsUsed"] = json!(extensions_used);
+        }
+
+        (root, buffer.data)
+    }
+
+    /// the scene as a binary gltf, the buffer packed after the json
+    pub fn to_glb(&self, log: &slog::Logger) -> Vec<u8> {
+        let (mut json, mut data) = self.to_json(log);
+        if !data.is_empty() {
+            json["buffers"] = json!([{ "byteLength": data.len() }]);
+        }
+        // chunks are padded to 4 bytes, the json with spaces
+        let mut json = serde_json::to_vec(&json).unwrap();
+        json.resize((json.len() + 3) / 4 * 4, b' ');
+        data.resize((data.len() + 3) / 4 * 4, 0);
+
+        let mut chunks = vec![(GLB_JSON_CHUNK, json)];
+        if !data.is_empty() {
+            chunks.push((GLB_BIN_CHUNK, data));
+        }
+        let length = 12
+            + chunks
+                .iter()
+                .map(|(_, chunk)| 8 + chunk.len())
+                .sum::<usize>();
+        let mut glb = Vec::with_capacity(length);
+        glb.extend_from_slice(GLB_MAGIC);
+        glb.extend_from_slice(&GLB_VERSION.to_le_bytes());
+        glb.extend_from_slice(&(length as u32).to_le_bytes());
+        for (kind, chunk) in chunks {
+            glb.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            glb.extend_from_slice(&kind.to_le_bytes());
+            glb.extend(chunk);
+        }
+        glb
+    }
+
+    /// writes a `.glb`, or a `.gltf` with its buffer in a `.bin` file of the same name
+    pub fn save(&self, log: &slog::Logger, path: &Path) -> Result<()> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("glb") => std::fs::write(path, self.to_glb(log))
+                .with_context(|| format!("writing {}", path.display())),
+            Some("gltf") => {
+                let (mut json, data) = self.to_json(log);
+                if !data.is_empty() {
+                    let bin_path = path.with_extension("bin");
+                    std::fs::write(&bin_path, &data)
+                        .with_context(|| format!("writing {}", bin_path.display()))?;
+                    json["buffers"] = json!([{
+                        "uri": bin_path.file_name().unwrap().to_string_lossy().replace(' ', "%20"),
+                        "byteLength": data.len(),
+                    }]);
+                }
+                std::fs::write(path, serde_json::to_vec_pretty(&json)?)
+                    .with_context(|| format!("writing {}", path.display()))
+            }
+            _ => Err(anyhow!(
+                "scenes can only be exported to .gltf or .glb files, not {}",
+                path.display()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle(instances: Vec<na::Projective3<f32>>) -> ExportedMesh {
+        ExportedMesh {
+            indices: vec![0, 1, 2],
+            pos: vec![
+                na::Point3::new(0.0, 0.0, 0.0),
+                na::Point3::new(1.0, 0.0, 0.0),
+                na::Point3::new(0.0, 1.0, 0.0),
+            ],
+            normal: vec![na::Vector3::z(); 3],
+            uv: vec![],
+            colors: vec![],
+            material: Some(0),
+            instances,
+        }
+    }
+
+    fn mirrored() -> na::Projective3<f32> {
+        na::Projective3::from_matrix_unchecked(glm::translation(&glm::vec3(2.0, 0.0, 0.0)))
+            * na::Projective3::from_matrix_unchecked(glm::scaling(&glm::vec3(-1.0, 1.0, 1.0)))
+    }
+
+    #[test]
+    fn writes_instances_and_materials_to_glb() {
+        let log = slog::Logger::root(slog::Discard, o!());
+        let export = SceneExport {
+            meshes: vec![triangle(vec![na::Projective3::identity(), mirrored()])],
+            materials: vec![ExportedMaterial {
+                base_color: Spectrum::from_floats(0.8, 0.5, 0.2),
+                transmission: 1.0,
+                ior: 1.33,
+                absorption: Some(Spectrum::new(0.5)),
+                emission: Spectrum::new(4.0),
+                ..Default::default()
+            }],
+            camera: None,
+        };
+
+        let (document, buffers, _) = gltf::import_slice(&export.to_glb(&log)).unwrap();
+        assert_eq!(document.meshes().count(), 1);
+        let transforms: Vec<_> = document
+            .nodes()
+            .map(|node| node.transform().matrix())
+            .collect();
+        assert_eq!(transforms.len(), 2);
+        assert_eq!(transforms[1][3][0], 2.0);
+        assert_eq!(transforms[1][0][0], -1.0);
+
+        let primitive = document
+            .meshes()
+            .next()
+            .unwrap()
+            .primitives()
+            .next()
+            .unwrap();
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        assert_eq!(reader.read_positions().unwrap().count(), 3);
+        assert_eq!(reader.read_normals().unwrap().count(), 3);
+        assert!(reader.read_tex_coords(0).is_none());
+
+        let material = primitive.material();
+        let base_color =
+            Spectrum::from_slice_4(&material.pbr_metallic_roughness().base_color_factor(), true);
+        assert!((base_color.r() - 0.8).abs() < 1e-4);
+        assert_eq!(material.ior(), Some(1.33));
+        assert_eq!(material.emissive_factor(), [1.0, 1.0, 1.0]);
+
+        let (json, _) = export.to_json(&log);
+        let extensions = &json["materials"][0]["extensions"];
+        assert_eq!(
+            extensions["KHR_materials_emissive_strength"]["emissiveStrength"],
+            4.0
+        );
+        let attenuation = extensions["KHR_materials_volume"]["attenuationColor"][0]
+            .as_f64()
+            .unwrap() as f32;
+        assert!((-attenuation.ln() - 0.5).abs() < 1e-4);
+        assert_eq!(json["extensionsUsed"].as_array().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn flattening_bakes_copies_into_world_space() {
+        let mut export = SceneExport::default();
+        export.merge(SceneExport {
+            meshes: vec![triangle(vec![na::Projective3::identity(), mirrored()])],
+            materials: vec![ExportedMaterial::default()],
+            camera: None,
+        });
+        export.merge(SceneExport {
+            meshes: vec![triangle(vec![na::Projective3::identity()])],
+            materials: vec![ExportedMaterial::default()],
+            camera: None,
+        });
+        assert_eq!(export.meshes[1].material, Some(1));
+
+        export.flatten();
+        assert_eq!(export.meshes.len(), 3);
+        let baked = &export.meshes[1];
+        assert_eq!(baked.pos[1], na::Point3::new(1.0, 0.0, 0.0));
+        assert_eq!(baked.indices, vec![0, 2, 1]);
+        assert_eq!(baked.instances, vec![na::Projective3::identity()]);
+        assert_eq!(export.meshes[0].indices, vec![0, 1, 2]);
+    }
+}
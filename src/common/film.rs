@@ -1,13 +1,23 @@
 use super::spectrum::Spectrum;
+use super::tonemap::ToneMap;
 use super::{bounds::Bounds2i, filter::Filter};
 use crate::common::filter::FilterInterface;
+use anyhow::anyhow;
 use image::RgbaImage;
 use itertools::Itertools;
-use std::sync::RwLock;
+use std::io::Write;
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    RwLock,
+};
+
+/// 16 bit rgba image, for saved images keeping more precision than 8 bits
+pub type Rgba16Image = image::ImageBuffer<image::Rgba<u16>, Vec<u16>>;
 
 #[derive(Clone, Debug)]
 struct FilmTilePixel {
     contrib_sum: Spectrum,
+    alpha_sum: f32,
     filter_wight_sum: f32,
 }
 
@@ -15,14 +25,18 @@ impl FilmTilePixel {
     pub fn new() -> Self {
         Self {
             contrib_sum: Spectrum::new(0.0),
+            alpha_sum: 0.0,
             filter_wight_sum: 0.0,
         }
     }
 }
 
+/// pixels one worker splats the samples of a tile into on its own, without locking the film.
+/// they reach as far past the tile as the filter does, overlapping the neighbouring tiles
 pub struct FilmTile {
     pixels: Vec<FilmTilePixel>,
     pixel_bounds: Bounds2i,
+    sample_bounds: Bounds2i,
     filter_radius: na::Vector2<f32>,
     inv_filter_radius: na::Vector2<f32>,
     filter_table: [f32; FILTER_TABLE_WIDTH * FILTER_TABLE_WIDTH],
@@ -31,12 +45,14 @@ pub struct FilmTile {
 impl FilmTile {
     pub fn new(
         pixel_bounds: Bounds2i,
+        sample_bounds: Bounds2i,
         filter_radius: na::Vector2<f32>,
         filter_table: [f32; FILTER_TABLE_WIDTH * FILTER_TABLE_WIDTH],
     ) -> Self {
         Self {
             pixels: vec![FilmTilePixel::new(); pixel_bounds.area() as usize],
             pixel_bounds,
+            sample_bounds,
             filter_radius,
             inv_filter_radius: na::Vector2::new(1. / filter_radius.x, 1. / filter_radius.y),
             filter_table,
@@ -56,8 +72,9 @@ impl FilmTile {
         return &mut self.pixels[offset as usize];
     }
 
-    // TODO: use more sophisticated image reconstruction techniques
-    pub fn add_sample(&mut self, p_film: &na::Point2<f32>, l: &Spectrum) {
+    /// splats radiance `l` onto the pixels around `p_film`, with the coverage `alpha` of the
+    /// scene along the sample's camera ray
+    pub fn add_sample(&mut self, p_film: &na::Point2<f32>, l: &Spectrum, alpha: f32) {
         let p_film_discrete = p_film - na::Vector2::new(0.5, 0.5);
         let p0 = na::Point2::new(
             (p_film_discrete.x - self.filter_radius.x).ceil() as i32,
@@ -100,6 +117,7 @@ impl FilmTile {
                 let filter_weight = self.filter_table[offset];
                 let pixel = self.get_pixel_mut(&na::Point2::new(x, y));
                 pixel.contrib_sum += *l * filter_weight;
+                pixel.alpha_sum += alpha * filter_weight;
                 pixel.filter_wight_sum += filter_weight;
             }
         }
@@ -114,18 +132,45 @@ impl FilmTile {
 #[derive(Debug, Clone)]
 struct FilmPixel {
     xyz: [f32; 3],
+    alpha: f32,
     filter_weight_sum: f32,
     splat_xyz: f32, // TODO: atomic?
 }
 
+impl FilmPixel {
+    /// reciprocal of the filter weight the samples left, 0 for pixels none of them reached, such
+    /// as the ones outside the crop window
+    fn inv_weight(&self) -> f32 {
+        if self.filter_weight_sum != 0.0 {
+            1.0 / self.filter_weight_sum
+        } else {
+            0.0
+        }
+    }
+}
+
 const FILTER_TABLE_WIDTH: usize = 16;
 
 pub struct Film {
     pixels: RwLock<Vec<FilmPixel>>,
     pub resolution: glm::UVec2,
     pixel_bounds: Bounds2i,
+    // pixels the samples are taken for, the rest of the film is left as it is
+    crop_bounds: RwLock<Bounds2i>,
     filter_table: [f32; FILTER_TABLE_WIDTH * FILTER_TABLE_WIDTH],
     filter: Box<Filter>,
+    // sample bounds of the tiles handed out and not merged back yet
+    active_tiles: RwLock<Vec<Bounds2i>>,
+    completed_tiles: AtomicUsize,
+    exposure: RwLock<f32>,
+    tone_map: RwLock<ToneMap>,
+    // whether the images keep the alpha, opaque otherwise
+    write_alpha: AtomicBool,
+    // whether png and tiff images are saved with 16 bits per channel instead of 8
+    sixteen_bit: AtomicBool,
+    // pixel bounds of the tiles merged since they were last taken, only kept while the film is
+    // streamed somewhere
+    updated_regions: RwLock<Option<Vec<Bounds2i>>>,
 }
 
 impl Film {
@@ -142,44 +187,102 @@ impl Film {
                 offset += 1;
             }
         }
+        let pixel_bounds = Bounds2i {
+            p_min: na::Point2::new(0, 0),
+            p_max: na::Point2::new(resolution.x as i32, resolution.y as i32),
+        };
         Self {
             pixels: RwLock::new(vec![
                 FilmPixel {
                     xyz: [0.0, 0.0, 0.0],
+                    alpha: 0.0,
                     filter_weight_sum: 0.0,
                     splat_xyz: 0.0
                 };
                 (resolution.x * resolution.y) as usize
             ]),
             resolution: *resolution,
-            pixel_bounds: Bounds2i {
-                p_min: na::Point2::new(0, 0),
-                p_max: na::Point2::new(resolution.x as i32, resolution.y as i32),
-            },
+            pixel_bounds,
+            crop_bounds: RwLock::new(pixel_bounds),
             filter_table,
             filter,
+            active_tiles: RwLock::new(Vec::new()),
+            completed_tiles: AtomicUsize::new(0),
+            exposure: RwLock::new(1.0),
+            tone_map: RwLock::new(ToneMap::default()),
+            write_alpha: AtomicBool::new(false),
+            sixteen_bit: AtomicBool::new(false),
+            updated_regions: RwLock::new(None),
         }
     }
 
+    /// clears the pixels inside the crop window, the rest keep what was rendered before
     pub fn clear(&self) {
-        for pixel in self.pixels.write().unwrap().iter_mut() {
-            *pixel = FilmPixel {
+        let crop_bounds = self.crop_bounds();
+        let mut pixels = self.pixels.write().unwrap();
+        for (x, y) in (crop_bounds.p_min.x..crop_bounds.p_max.x)
+            .cartesian_product(crop_bounds.p_min.y..crop_bounds.p_max.y)
+        {
+            pixels[self.get_pixel_offset(x, y)] = FilmPixel {
                 xyz: [0.0, 0.0, 0.0],
+                alpha: 0.0,
                 filter_weight_sum: 0.0,
                 splat_xyz: 0.0,
             }
         }
+        self.completed_tiles.store(0, Ordering::Relaxed);
+    }
+
+    /// restricts rendering to the pixels between `min` and `max`, in fractions of the film's width
+    /// and height from its top left corner. the images keep the full resolution
+    pub fn set_crop_window(&self, min: &na::Point2<f32>, max: &na::Point2<f32>) {
+        let resolution = na::Vector2::new(self.resolution.x as f32, self.resolution.y as f32);
+        let crop_bounds = Bounds2i {
+            p_min: na::Point2::new(
+                (resolution.x * min.x.clamp(0.0, 1.0)).ceil() as i32,
+                (resolution.y * min.y.clamp(0.0, 1.0)).ceil() as i32,
+            ),
+            p_max: na::Point2::new(
+                (resolution.x * max.x.clamp(0.0, 1.0)).ceil() as i32,
+                (resolution.y * max.y.clamp(0.0, 1.0)).ceil() as i32,
+            ),
+        };
+        // at least a pixel, so there's always something to render
+        let crop_bounds = Bounds2i {
+            p_min: crop_bounds.p_min,
+            p_max: na::Point2::new(
+                crop_bounds.p_max.x.max(crop_bounds.p_min.x + 1),
+                crop_bounds.p_max.y.max(crop_bounds.p_min.y + 1),
+            ),
+        }
+        .intersect(&self.pixel_bounds);
+        *self.crop_bounds.write().unwrap() = crop_bounds;
+    }
+
+    /// renders the whole film again
+    pub fn reset_crop_window(&self) {
+        *self.crop_bounds.write().unwrap() = self.pixel_bounds;
+    }
+
+    pub fn crop_bounds(&self) -> Bounds2i {
+        *self.crop_bounds.read().unwrap()
+    }
+
+    pub fn filter(&self) -> &Filter {
+        &self.filter
     }
 
+    /// pixels to take samples in, covering the crop window and the reach of the filter around it
     pub fn get_sample_bounds(&self) -> Bounds2i {
+        let crop_bounds = self.crop_bounds();
         Bounds2i {
             p_min: na::Point2::new(
-                (0.5 - self.filter.radius().x).floor() as i32,
-                (0.5 - self.filter.radius().y).floor() as i32,
+                (crop_bounds.p_min.x as f32 + 0.5 - self.filter.radius().x).floor() as i32,
+                (crop_bounds.p_min.y as f32 + 0.5 - self.filter.radius().y).floor() as i32,
             ),
             p_max: na::Point2::new(
-                (self.resolution.x as f32 - 0.5 + self.filter.radius().x).ceil() as i32,
-                (self.resolution.y as f32 - 0.5 + self.filter.radius().y).ceil() as i32,
+                (crop_bounds.p_max.x as f32 - 0.5 + self.filter.radius().x).ceil() as i32,
+                (crop_bounds.p_max.y as f32 - 0.5 + self.filter.radius().y).ceil() as i32,
             ),
         }
     }
@@ -190,6 +293,8 @@ impl Film {
         ((p.x - self.pixel_bounds.p_min.x) + (p.y - self.pixel_bounds.p_min.y) * width) as usize
     }
 
+    /// empty tile for the pixels the samples in `sample_bounds` reach, to be handed back to
+    /// `merge_film_tile` once rendered
     pub fn get_film_tile(&self, sample_bounds: &Bounds2i) -> Box<FilmTile> {
         let bounds = Bounds2i {
             p_min: na::Point2::new(
@@ -201,18 +306,32 @@ impl Film {
                 (sample_bounds.p_max.y as f32 - 0.5 + self.filter.radius().y).floor() as i32 + 1,
             ),
         }
-        .intersect(&self.pixel_bounds);
+        .intersect(&self.crop_bounds());
 
+        self.active_tiles.write().unwrap().push(*sample_bounds);
         Box::new(FilmTile::new(
             bounds,
+            *sample_bounds,
             *self.filter.radius(),
             self.filter_table,
         ))
     }
 
+    /// adds a rendered tile into the film, the only time the film's pixels are locked while
+    /// rendering
     pub fn merge_film_tile(&self, tile: Box<FilmTile>) {
-        let mut pixels = self.pixels.write().unwrap();
+        {
+            let mut active_tiles = self.active_tiles.write().unwrap();
+            if let Some(idx) = active_tiles.iter().position(|b| *b == tile.sample_bounds) {
+                active_tiles.swap_remove(idx);
+            }
+        }
+        self.completed_tiles.fetch_add(1, Ordering::Relaxed);
         let pixel_bounds = tile.get_pixel_bounds();
+        if let Some(regions) = self.updated_regions.write().unwrap().as_mut() {
+            regions.push(pixel_bounds);
+        }
+        let mut pixels = self.pixels.write().unwrap();
         for (x, y) in (pixel_bounds.p_min.x..pixel_bounds.p_max.x)
             .cartesian_product(pixel_bounds.p_min.y..pixel_bounds.p_max.y)
         {
@@ -223,50 +342,452 @@ impl Film {
             merge_pixel.xyz[0] += tile_pixel.contrib_sum.r();
             merge_pixel.xyz[1] += tile_pixel.contrib_sum.g();
             merge_pixel.xyz[2] += tile_pixel.contrib_sum.b();
+            merge_pixel.alpha += tile_pixel.alpha_sum;
             merge_pixel.filter_weight_sum += tile_pixel.filter_wight_sum;
         }
     }
 
+    /// splits the sample bounds into tiles of at most `tile_size` pixels a side, in row major order
+    pub fn tiles(&self, tile_size: i32) -> Vec<Bounds2i> {
+        let sample_bounds = self.get_sample_bounds();
+        let sample_extent = sample_bounds.diagonal();
+        let num_tiles = na::Point2::new(
+            (sample_extent.x + tile_size - 1) / tile_size,
+            (sample_extent.y + tile_size - 1) / tile_size,
+        );
+
+        (0..num_tiles.y)
+            .cartesian_product(0..num_tiles.x)
+            .map(|(y, x)| {
+                let x0 = sample_bounds.p_min.x + x * tile_size;
+                let x1 = std::cmp::min(x0 + tile_size, sample_bounds.p_max.x);
+                let y0 = sample_bounds.p_min.y + y * tile_size;
+                let y1 = std::cmp::min(y0 + tile_size, sample_bounds.p_max.y);
+
+                Bounds2i {
+                    p_min: na::Point2::new(x0, y0),
+                    p_max: na::Point2::new(x1, y1),
+                }
+            })
+            .collect()
+    }
+
+    /// sample bounds of the tiles currently being rendered
+    pub fn active_tiles(&self) -> Vec<Bounds2i> {
+        self.active_tiles.read().unwrap().clone()
+    }
+
+    /// starts keeping the regions of the tiles merged into the film, for `take_updated_regions`
+    pub fn track_updated_regions(&self) {
+        self.updated_regions
+            .write()
+            .unwrap()
+            .get_or_insert_with(Vec::new);
+    }
+
+    /// pixel bounds of the tiles merged since the last call, to send only what changed to a
+    /// display. empty unless `track_updated_regions` was called
+    pub fn take_updated_regions(&self) -> Vec<Bounds2i> {
+        self.updated_regions
+            .write()
+            .unwrap()
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// number of tiles merged back since the film was created or last cleared
+    pub fn completed_tiles(&self) -> usize {
+        self.completed_tiles.load(Ordering::Relaxed)
+    }
+
+    /// accumulates the samples of another film of the same resolution into this one
+    pub fn merge(&self, other: &Film) {
+        assert_eq!(
+            self.resolution, other.resolution,
+            "can only merge films of the same resolution"
+        );
+        let mut pixels = self.pixels.write().unwrap();
+        let other_pixels = other.pixels.read().unwrap();
+        for (pixel, other_pixel) in pixels.iter_mut().zip(other_pixels.iter()) {
+            pixel.xyz[0] += other_pixel.xyz[0];
+            pixel.xyz[1] += other_pixel.xyz[1];
+            pixel.xyz[2] += other_pixel.xyz[2];
+            pixel.alpha += other_pixel.alpha;
+            pixel.filter_weight_sum += other_pixel.filter_weight_sum;
+            pixel.splat_xyz += other_pixel.splat_xyz;
+        }
+    }
+
+    /// scales the accumulated radiance, e.g. for exposure adjustment
+    pub fn scale(&self, scale: f32) {
+        for pixel in self.pixels.write().unwrap().iter_mut() {
+            pixel.xyz[0] *= scale;
+            pixel.xyz[1] *= scale;
+            pixel.xyz[2] *= scale;
+            pixel.splat_xyz *= scale;
+        }
+    }
+
+    pub fn save<F>(
+        &self,
+        path: &std::path::Path,
+        format: image::ImageFormat,
+        transform: F,
+    ) -> image::ImageResult<()>
+    where
+        F: Fn(Spectrum) -> Spectrum,
+    {
+        let image = image::DynamicImage::ImageRgba8(self.to_rgba_image_with(transform));
+        let image = match format {
+            image::ImageFormat::Jpeg | image::ImageFormat::Bmp => {
+                image::DynamicImage::ImageRgb8(image.to_rgb())
+            }
+            _ => image,
+        };
+
+        image.save_with_format(path, format)
+    }
+
     pub fn to_rgba_image(&self) -> RgbaImage {
+        self.to_rgba_image_with(|l| l)
+    }
+
+    /// scale applied to the radiance when converting to an image, the accumulated samples are
+    /// left untouched
+    pub fn set_exposure(&self, exposure: f32) {
+        *self.exposure.write().unwrap() = exposure;
+    }
+
+    pub fn exposure(&self) -> f32 {
+        *self.exposure.read().unwrap()
+    }
+
+    /// tone mapping and encoding of the exposed radiance in ldr images, the accumulated samples
+    /// are left untouched
+    pub fn set_tone_map(&self, tone_map: ToneMap) {
+        *self.tone_map.write().unwrap() = tone_map;
+    }
+
+    pub fn tone_map(&self) -> ToneMap {
+        *self.tone_map.read().unwrap()
+    }
+
+    /// saves images with the coverage of the scene as their alpha, transparent where the camera
+    /// rays miss it
+    pub fn set_write_alpha(&self, write_alpha: bool) {
+        self.write_alpha.store(write_alpha, Ordering::Relaxed);
+    }
+
+    pub fn write_alpha(&self) -> bool {
+        self.write_alpha.load(Ordering::Relaxed)
+    }
+
+    /// saves png and tiff images with 16 bits per channel, for grading them further without
+    /// banding
+    pub fn set_sixteen_bit(&self, sixteen_bit: bool) {
+        self.sixteen_bit.store(sixteen_bit, Ordering::Relaxed);
+    }
+
+    pub fn sixteen_bit(&self) -> bool {
+        self.sixteen_bit.load(Ordering::Relaxed)
+    }
+
+    /// saves the film as a png or tiff image picked by the extension of `path`, with 8 or 16 bits
+    /// per channel as set by `set_sixteen_bit`
+    pub fn save_image(&self, path: &std::path::Path) -> anyhow::Result<()> {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        match (extension.as_deref(), self.sixteen_bit()) {
+            (Some("png"), false) | (Some("tif"), false) | (Some("tiff"), false) => {
+                self.to_rgba_image().save(path)?
+            }
+            (Some("png"), true) => {
+                image::DynamicImage::ImageRgba16(self.to_rgba16_image()).save(path)?
+            }
+            (Some("tif"), true) | (Some("tiff"), true) => {
+                let image = self.to_rgba16_image();
+                let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+                tiff::encoder::TiffEncoder::new(file)?
+                    .write_image::<tiff::encoder::colortype::RGBA16>(
+                        image.width(),
+                        image.height(),
+                        &image.into_raw(),
+                    )?;
+            }
+            _ => return Err(anyhow!("can't save images as {:?}, only png or tiff", path)),
+        }
+        Ok(())
+    }
+
+    /// exposure of a camera set to `iso`, `f_stop` and a shutter open for `shutter_speed`
+    /// seconds, for radiance in physical units. a sunlit scene comes out around middle gray at the
+    /// settings of the sunny 16 rule, and iso 100 at f/1 for a second leaves the radiance about
+    /// as it is
+    pub fn physical_exposure(iso: f32, f_stop: f32, shutter_speed: f32) -> f32 {
+        let ev100 = (f_stop * f_stop / shutter_speed * 100.0 / iso).log2();
+        // saturation based sensitivity, leaving headroom above the brightest exposed value
+        1.0 / (1.2 * ev100.exp2())
+    }
+
+    /// converts to an image after applying the exposure and `transform` to the reconstructed
+    /// radiance of every pixel, then the tone map. with alpha written the radiance is divided by
+    /// the alpha first, the colors of png images not being premultiplied
+    pub fn to_rgba_image_with<F>(&self, transform: F) -> RgbaImage
+    where
+        F: Fn(Spectrum) -> Spectrum,
+    {
         let mut image = RgbaImage::new(self.resolution.x, self.resolution.y);
+        let tone_map = self.tone_map();
+        self.for_each_display_pixel(transform, |x, y, l, alpha| {
+            let mut rgba = tone_map.to_image_rgba(&l);
+            if let Some(alpha) = alpha {
+                rgba[3] = (alpha * 255.0 + 0.5) as u8;
+            }
+            image.put_pixel(x, y, rgba);
+        });
+
+        image
+    }
+
+    /// `to_rgba_image` with 16 bits per channel
+    pub fn to_rgba16_image(&self) -> Rgba16Image {
+        let mut image = Rgba16Image::new(self.resolution.x, self.resolution.y);
+        let tone_map = self.tone_map();
+        self.for_each_display_pixel(
+            |l| l,
+            |x, y, l, alpha| {
+                let mut rgba = tone_map.to_image_rgba16(&l);
+                if let Some(alpha) = alpha {
+                    rgba[3] = (alpha * 65535.0 + 0.5) as u16;
+                }
+                image.put_pixel(x, y, rgba);
+            },
+        );
+
+        image
+    }
+
+    /// hands `put` the exposed radiance of every pixel after `transform`, before the tone map,
+    /// and its alpha when it's written, which the radiance is divided by
+    fn for_each_display_pixel<F, P>(&self, transform: F, mut put: P)
+    where
+        F: Fn(Spectrum) -> Spectrum,
+        P: FnMut(u32, u32, Spectrum, Option<f32>),
+    {
+        let pixels = self.pixels.read().unwrap();
+        let exposure = self.exposure();
+        let write_alpha = self.write_alpha();
         for (x, y) in (self.pixel_bounds.p_min.x..self.pixel_bounds.p_max.x)
             .cartesian_product(self.pixel_bounds.p_min.y..self.pixel_bounds.p_max.y)
         {
             let offset = self.get_pixel_offset(x, y);
-            let pixel = &self.pixels.read().unwrap()[offset];
-            let inv_wt = 1. / pixel.filter_weight_sum;
-            image.put_pixel(
+            let pixel = &pixels[offset];
+            let alpha = (pixel.alpha * pixel.inv_weight()).clamp(0.0, 1.0);
+            let inv_wt = if write_alpha && alpha > 0.0 {
+                exposure * pixel.inv_weight() / alpha
+            } else {
+                exposure * pixel.inv_weight()
+            };
+            put(
                 x as u32,
                 y as u32,
-                (Spectrum::from_floats(
+                transform(Spectrum::from_floats(
                     pixel.xyz[0] * inv_wt,
                     pixel.xyz[1] * inv_wt,
                     pixel.xyz[2] * inv_wt,
-                ))
-                .to_image_rgba(),
+                )),
+                if write_alpha { Some(alpha) } else { None },
             );
         }
+    }
 
-        image
+    /// bakes the display transform of `to_rgba_image_with`, exposure, `transform` and the tone
+    /// map, into a `size`^3 .cube lut over linear radiance in [0, `domain_max`], so the
+    /// exact look of the saved images can be applied to the raw radiance elsewhere. the lut holds
+    /// the encoded values before quantization to 8 bits
+    pub fn save_display_lut<F>(
+        &self,
+        path: &std::path::Path,
+        size: usize,
+        domain_max: f32,
+        transform: F,
+    ) -> std::io::Result<()>
+    where
+        F: Fn(Spectrum) -> Spectrum,
+    {
+        assert!(size >= 2, "a lut needs at least two entries per axis");
+        let exposure = self.exposure();
+        let tone_map = self.tone_map();
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+
+        writeln!(file, "TITLE \"pathtracer-rs display transform\"")?;
+        writeln!(file, "LUT_3D_SIZE {}", size)?;
+        writeln!(file, "DOMAIN_MIN 0.0 0.0 0.0")?;
+        writeln!(
+            file,
+            "DOMAIN_MAX {} {} {}",
+            domain_max, domain_max, domain_max
+        )?;
+
+        // red varies fastest
+        let value = |i: usize| i as f32 / (size - 1) as f32 * domain_max;
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let l =
+                        transform(Spectrum::from_floats(value(r), value(g), value(b)) * exposure);
+                    let [r, g, b] = tone_map.encode(&l);
+                    writeln!(file, "{:.6} {:.6} {:.6}", r, g, b)?;
+                }
+            }
+        }
+
+        file.flush()
     }
 
-    pub fn to_channel_updates(&self) -> [Vec<f32>; 3] {
+    /// linear radiance of every pixel row by row with the exposure applied, as `to_rgba_image`
+    /// saves it before the srgb encoding. the negative lobes of some filters can ring below 0
+    /// next to bright edges, which is clamped away
+    pub fn to_rgb_floats(&self) -> Vec<[f32; 3]> {
         let pixels = self.pixels.read().unwrap();
-        let mut r = Vec::with_capacity(self.pixel_bounds.area() as usize);
-        let mut g = Vec::with_capacity(self.pixel_bounds.area() as usize);
-        let mut b = Vec::with_capacity(self.pixel_bounds.area() as usize);
-        for (y, x) in (self.pixel_bounds.p_min.y..self.pixel_bounds.p_max.y)
+        let exposure = self.exposure();
+        (self.pixel_bounds.p_min.y..self.pixel_bounds.p_max.y)
             .cartesian_product(self.pixel_bounds.p_min.x..self.pixel_bounds.p_max.x)
+            .map(|(y, x)| {
+                let pixel = &pixels[self.get_pixel_offset(x, y)];
+                let inv_wt = exposure * pixel.inv_weight();
+                [
+                    (pixel.xyz[0] * inv_wt).max(0.0),
+                    (pixel.xyz[1] * inv_wt).max(0.0),
+                    (pixel.xyz[2] * inv_wt).max(0.0),
+                ]
+            })
+            .collect()
+    }
+
+    /// coverage of the scene in every pixel row by row, 0 where all the camera rays missed it
+    pub fn to_alpha(&self) -> Vec<f32> {
+        let pixels = self.pixels.read().unwrap();
+        (self.pixel_bounds.p_min.y..self.pixel_bounds.p_max.y)
+            .cartesian_product(self.pixel_bounds.p_min.x..self.pixel_bounds.p_max.x)
+            .map(|(y, x)| {
+                let pixel = &pixels[self.get_pixel_offset(x, y)];
+                (pixel.alpha * pixel.inv_weight()).clamp(0.0, 1.0)
+            })
+            .collect()
+    }
+
+    /// the exposed linear radiance and the alpha of the pixels in `bounds` row by row, one
+    /// channel at a time as display servers take them
+    pub fn to_channel_updates(&self, bounds: &Bounds2i) -> [Vec<f32>; 4] {
+        let bounds = bounds.intersect(&self.pixel_bounds);
+        let pixels = self.pixels.read().unwrap();
+        let exposure = self.exposure();
+        let area = bounds.area().max(0) as usize;
+        let mut r = Vec::with_capacity(area);
+        let mut g = Vec::with_capacity(area);
+        let mut b = Vec::with_capacity(area);
+        let mut a = Vec::with_capacity(area);
+        for (y, x) in
+            (bounds.p_min.y..bounds.p_max.y).cartesian_product(bounds.p_min.x..bounds.p_max.x)
         {
             let offset = self.get_pixel_offset(x, y);
             let pixel = &pixels[offset];
-            let inv_wt = 1. / pixel.filter_weight_sum;
+            let inv_wt = exposure * pixel.inv_weight();
 
             r.push(pixel.xyz[0] * inv_wt);
             g.push(pixel.xyz[1] * inv_wt);
             b.push(pixel.xyz[2] * inv_wt);
+            a.push((pixel.alpha * pixel.inv_weight()).clamp(0.0, 1.0));
         }
 
-        [r, g, b]
+        [r, g, b, a]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::filter::BoxFilter;
+
+    /// 4x4 film with every sample of its bottom right quarter rendered at radiance 1
+    fn cropped_film() -> Film {
+        let film = Film::new(
+            &glm::vec2(4, 4),
+            Box::new(Filter::Box(BoxFilter::new(&na::Vector2::new(0.5, 0.5)))),
+        );
+        film.set_crop_window(&na::Point2::new(0.5, 0.5), &na::Point2::new(1.0, 1.0));
+        for sample_bounds in film.tiles(16) {
+            let mut tile = film.get_film_tile(&sample_bounds);
+            for (x, y) in (sample_bounds.p_min.x..sample_bounds.p_max.x)
+                .cartesian_product(sample_bounds.p_min.y..sample_bounds.p_max.y)
+            {
+                let p_film = na::Point2::new(x as f32 + 0.5, y as f32 + 0.5);
+                tile.add_sample(&p_film, &Spectrum::new(1.0), 1.0);
+            }
+            film.merge_film_tile(tile);
+        }
+
+        film
+    }
+
+    fn assert_cropped(film: &Film) {
+        // the crop window covers the pixels from (2, 2) on
+        let expected = |x: i32, y: i32| -> f32 {
+            if x >= 2 && y >= 2 {
+                1.0
+            } else {
+                0.0
+            }
+        };
+
+        let rgb = film.to_rgb_floats();
+        let alpha = film.to_alpha();
+        let [r, g, b, a] = film.to_channel_updates(&film.pixel_bounds);
+        for (y, x) in (0..4).cartesian_product(0..4) {
+            let idx = (y * 4 + x) as usize;
+            assert_eq!(rgb[idx], [expected(x, y); 3], "rgb of pixel ({}, {})", x, y);
+            assert_eq!(alpha[idx], expected(x, y), "alpha of pixel ({}, {})", x, y);
+            assert_eq!(
+                [r[idx], g[idx], b[idx], a[idx]],
+                [expected(x, y); 4],
+                "channels of pixel ({}, {})",
+                x,
+                y
+            );
+        }
+
+        film.set_write_alpha(true);
+        film.for_each_display_pixel(
+            |l| l,
+            |x, y, l, alpha| {
+                let (x, y) = (x as i32, y as i32);
+                assert_eq!(
+                    [l.r(), l.g(), l.b()],
+                    [expected(x, y); 3],
+                    "pixel ({}, {})",
+                    x,
+                    y
+                );
+                assert_eq!(alpha, Some(expected(x, y)), "alpha of pixel ({}, {})", x, y);
+            },
+        );
+    }
+
+    #[test]
+    fn test_unsampled_pixels_are_black() {
+        assert_cropped(&cropped_film());
+    }
+
+    #[test]
+    fn test_merged_unsampled_pixels_are_black() {
+        let film = Film::new(
+            &glm::vec2(4, 4),
+            Box::new(Filter::Box(BoxFilter::new(&na::Vector2::new(0.5, 0.5)))),
+        );
+        film.merge(&cropped_film());
+        assert_cropped(&film);
     }
 }
@@ -6,15 +6,42 @@ pub trait FilterInterface {
     fn radius(&self) -> &na::Vector2<f32>;
 }
 
-#[derive(Delegate)]
+/// reconstruction filter weighting the samples splatted onto the pixels around them
+#[derive(Clone, Debug, Delegate)]
 #[delegate(FilterInterface)]
 pub enum Filter {
+    Box(BoxFilter),
     Sinc(LanczosSincFilter),
     Triangle(TriangleFilter),
     Guassian(GuassianFilter),
+    Mitchell(MitchellFilter),
+    BlackmanHarris(BlackmanHarrisFilter),
+}
+
+/// every sample counts fully towards the pixels within the radius, 0.5 keeping it to its own
+#[derive(Clone, Debug)]
+pub struct BoxFilter {
+    radius: na::Vector2<f32>,
+}
+
+impl BoxFilter {
+    pub fn new(radius: &na::Vector2<f32>) -> Self {
+        Self { radius: *radius }
+    }
+}
+
+impl FilterInterface for BoxFilter {
+    fn evaluate(&self, _p: &na::Point2<f32>) -> f32 {
+        1.0
+    }
+
+    fn radius(&self) -> &na::Vector2<f32> {
+        &self.radius
+    }
 }
 
 // TODO: implement this
+#[derive(Clone, Debug)]
 pub struct LanczosSincFilter {
     radius: na::Vector2<f32>,
 }
@@ -35,16 +62,14 @@ impl FilterInterface for LanczosSincFilter {
     }
 }
 
+#[derive(Clone, Debug)]
 pub struct TriangleFilter {
     radius: na::Vector2<f32>,
 }
 
 impl TriangleFilter {
-    pub fn new() -> Self {
-        const RADIUS: f32 = 1.0;
-        Self {
-            radius: na::Vector2::new(RADIUS, RADIUS),
-        }
+    pub fn new(radius: &na::Vector2<f32>) -> Self {
+        Self { radius: *radius }
     }
 }
 
@@ -58,19 +83,23 @@ impl FilterInterface for TriangleFilter {
     }
 }
 
+/// gaussian of falloff `alpha`, shifted down to reach 0 at the radius
+#[derive(Clone, Debug)]
 pub struct GuassianFilter {
     alpha: f32,
-    exp: f32,
+    exp: na::Vector2<f32>,
     radius: na::Vector2<f32>,
 }
 
 impl GuassianFilter {
-    pub fn new(alpha: f32) -> Self {
-        let radius = 2.0;
+    pub fn new(radius: &na::Vector2<f32>, alpha: f32) -> Self {
         Self {
             alpha,
-            exp: (-alpha * radius * radius).exp(),
-            radius: na::Vector2::new(radius, radius),
+            exp: na::Vector2::new(
+                (-alpha * radius.x * radius.x).exp(),
+                (-alpha * radius.y * radius.y).exp(),
+            ),
+            radius: *radius,
         }
     }
 
@@ -81,7 +110,85 @@ impl GuassianFilter {
 
 impl FilterInterface for GuassianFilter {
     fn evaluate(&self, p: &na::Point2<f32>) -> f32 {
-        self.guassian(p.x, self.exp) * self.guassian(p.y, self.exp)
+        self.guassian(p.x, self.exp.x) * self.guassian(p.y, self.exp.y)
+    }
+
+    fn radius(&self) -> &na::Vector2<f32> {
+        &self.radius
+    }
+}
+
+/// mitchell-netravali cubic, trading blur (`b`) against ringing (`c`). its negative lobes keep
+/// edges sharper than the gaussian, 1/3 each being the usual balance
+#[derive(Clone, Debug)]
+pub struct MitchellFilter {
+    radius: na::Vector2<f32>,
+    inv_radius: na::Vector2<f32>,
+    b: f32,
+    c: f32,
+}
+
+impl MitchellFilter {
+    pub fn new(radius: &na::Vector2<f32>, b: f32, c: f32) -> Self {
+        Self {
+            radius: *radius,
+            inv_radius: na::Vector2::new(1.0 / radius.x, 1.0 / radius.y),
+            b,
+            c,
+        }
+    }
+
+    /// the cubic over [-2, 2]
+    fn mitchell(&self, x: f32) -> f32 {
+        let (b, c) = (self.b, self.c);
+        let x = (2.0 * x).abs();
+        if x > 1.0 {
+            ((-b - 6.0 * c) * x * x * x
+                + (6.0 * b + 30.0 * c) * x * x
+                + (-12.0 * b - 48.0 * c) * x
+                + (8.0 * b + 24.0 * c))
+                * (1.0 / 6.0)
+        } else {
+            ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                + (6.0 - 2.0 * b))
+                * (1.0 / 6.0)
+        }
+    }
+}
+
+impl FilterInterface for MitchellFilter {
+    fn evaluate(&self, p: &na::Point2<f32>) -> f32 {
+        self.mitchell(p.x * self.inv_radius.x) * self.mitchell(p.y * self.inv_radius.y)
+    }
+
+    fn radius(&self) -> &na::Vector2<f32> {
+        &self.radius
+    }
+}
+
+/// four term blackman-harris window, about as smooth as the gaussian but falling off more
+/// steeply, leaving less blur for its radius
+#[derive(Clone, Debug)]
+pub struct BlackmanHarrisFilter {
+    radius: na::Vector2<f32>,
+}
+
+impl BlackmanHarrisFilter {
+    pub fn new(radius: &na::Vector2<f32>) -> Self {
+        Self { radius: *radius }
+    }
+
+    /// the window centered on 0, reaching about 0 at `radius`
+    fn blackman_harris(x: f32, radius: f32) -> f32 {
+        let t = std::f32::consts::PI * x.abs().min(radius) / radius;
+        0.35875 + 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() + 0.01168 * (3.0 * t).cos()
+    }
+}
+
+impl FilterInterface for BlackmanHarrisFilter {
+    fn evaluate(&self, p: &na::Point2<f32>) -> f32 {
+        Self::blackman_harris(p.x, self.radius.x) * Self::blackman_harris(p.y, self.radius.y)
     }
 
     fn radius(&self) -> &na::Vector2<f32> {
@@ -0,0 +1,471 @@
+//! scene descriptions in this renderer's own format, toml or json files that gather everything a
+//! render depends on: the geometry, either a scene file of another format or meshes placed by
+//! the description, material overrides, lights, the camera and the settings of the film,
+//! sampler and integrator. e.g.
+//!
+//! ```toml
+//! scene = "sponza/Sponza.gltf"
+//! materials = "materials.json"
+//!
+//! [camera]
+//! position = [8.0, 1.5, 0.0]
+//! target = [0.0, 2.0, 0.0]
+//! fov = 60.0
+//!
+//! [film]
+//! resolution = [1280, 720]
+//! tone_map = "filmic"
+//!
+//! [sampler]
+//! samples = 64
+//!
+//! [[lights]]
+//! type = "environment"
+//! file = "sky.hdr"
+//! ```
+
+use super::{library::LibraryEntry, library::MaterialLibrary, mitsuba, ply, AssetResolver};
+use super::{Camera, Shutter, BVH_CACHE_EXTENSION};
+use crate::common::{Projection, DEFAULT_Z_FAR, DEFAULT_Z_NEAR};
+use crate::pathtracer::accelerator::BVHCache;
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// whether the file at `path` is a scene description rather than a scene of another format
+pub fn is_description(path: &Path) -> bool {
+    path.extension()
+        .map_or(false, |ext| ext == "toml" || ext == "json")
+}
+
+fn white() -> [f32; 3] {
+    [1.0; 3]
+}
+
+fn unit() -> f32 {
+    1.0
+}
+
+fn unit_scale() -> [f32; 3] {
+    [1.0; 3]
+}
+
+fn y_up() -> [f32; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+/// materials of the described scene, a json material library next to the description or the
+/// library entries written out in it
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum MaterialsDescription {
+    Library(String),
+    Inline(HashMap<String, LibraryEntry>),
+}
+
+/// placement of a mesh, scaled first, then rotated about the x, y and z axes in that order by
+/// angles in degrees, then translated
+#[derive(Debug, Deserialize)]
+pub struct TransformDescription {
+    #[serde(default)]
+    pub translate: [f32; 3],
+    #[serde(default)]
+    pub rotate: [f32; 3],
+    #[serde(default = "unit_scale")]
+    pub scale: [f32; 3],
+}
+
+impl Default for TransformDescription {
+    fn default() -> Self {
+        Self {
+            translate: [0.0; 3],
+            rotate: [0.0; 3],
+            scale: unit_scale(),
+        }
+    }
+}
+
+impl TransformDescription {
+    pub fn matrix(&self) -> na::Projective3<f32> {
+        let rotation = na::UnitQuaternion::from_euler_angles(
+            self.rotate[0].to_radians(),
+            self.rotate[1].to_radians(),
+            self.rotate[2].to_radians(),
+        );
+        na::Projective3::from_matrix_unchecked(
+            glm::translation(&glm::make_vec3(&self.translate))
+                * rotation.to_homogeneous()
+                * glm::scaling(&glm::make_vec3(&self.scale)),
+        )
+    }
+}
+
+/// a mesh file placed in the scene, shaded with the named material of the library
+#[derive(Debug, Deserialize)]
+pub struct MeshDescription {
+    pub file: String,
+    pub material: Option<String>,
+    #[serde(default)]
+    pub transform: TransformDescription,
+}
+
+/// lights added to the scene on top of the ones it has already, `color` is scaled by `intensity`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LightDescription {
+    /// light from all around the scene, an equirect hdr image turned about the y axis by
+    /// `rotation` degrees, or uniform without a `file`
+    Environment {
+        file: Option<String>,
+        #[serde(default = "white")]
+        color: [f32; 3],
+        #[serde(default = "unit")]
+        intensity: f32,
+        #[serde(default)]
+        rotation: f32,
+    },
+    Point {
+        position: [f32; 3],
+        #[serde(default = "white")]
+        color: [f32; 3],
+        #[serde(default = "unit")]
+        intensity: f32,
+    },
+    /// light travelling along `direction` from infinitely far away, like sunlight
+    Directional {
+        direction: [f32; 3],
+        #[serde(default = "white")]
+        color: [f32; 3],
+        #[serde(default = "unit")]
+        intensity: f32,
+    },
+}
+
+/// the camera to render from, the scene's own camera unless a `position` is set. the settings
+/// of the lens and the projection are those of the command line arguments of the same name
+#[derive(Debug, Default, Deserialize)]
+pub struct CameraDescription {
+    /// name or index of the camera of a gltf scene to render from
+    pub name: Option<String>,
+    pub position: Option<[f32; 3]>,
+    #[serde(default)]
+    pub target: [f32; 3],
+    #[serde(default = "y_up")]
+    pub up: [f32; 3],
+    /// vertical field of view in degrees
+    pub fov: Option<f32>,
+    pub lens_radius: Option<f32>,
+    pub focal_distance: Option<f32>,
+    pub orthographic: Option<f32>,
+    pub panorama: Option<String>,
+    pub fisheye_fov: Option<f32>,
+}
+
+/// see the command line arguments of the same name
+#[derive(Debug, Default, Deserialize)]
+pub struct FilmDescription {
+    pub resolution: Option<[u32; 2]>,
+    pub filter: Option<String>,
+    pub filter_radius: Option<f32>,
+    pub crop_window: Option<[f32; 4]>,
+    pub exposure: Option<f32>,
+    #[serde(default)]
+    pub auto_exposure: bool,
+    pub tone_map: Option<String>,
+    pub gamma: Option<f32>,
+    pub bit_depth: Option<u32>,
+    #[serde(default)]
+    pub alpha: bool,
+}
+
+/// see the command line arguments of the same name
+#[derive(Debug, Default, Deserialize)]
+pub struct SamplerDescription {
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    pub samples: Option<usize>,
+    pub strata: Option<[usize; 2]>,
+    /// stratified samples are jittered in their strata unless this is false
+    pub jitter: Option<bool>,
+    pub scramble: Option<String>,
+    #[serde(default)]
+    pub blue_noise: bool,
+}
+
+/// see the command line arguments of the same name
+#[derive(Debug, Default, Deserialize)]
+pub struct IntegratorDescription {
+    pub max_depth: Option<i32>,
+    pub light_samples: Option<usize>,
+    pub bsdf_samples: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SceneDescription {
+    /// gltf, mitsuba or pbrt scene holding the geometry, relative to the description
+    pub scene: Option<String>,
+    /// obj meshes making up the scene when there's no `scene` file
+    #[serde(default)]
+    pub meshes: Vec<MeshDescription>,
+    pub materials: Option<MaterialsDescription>,
+    /// material variant of a gltf scene
+    pub variant: Option<String>,
+    /// animation times in seconds the shutter opens and closes at
+    pub shutter: Option<[f32; 2]>,
+    pub max_texture_size: Option<u32>,
+    /// adds the default environment to the scene, like `--default_lights`
+    #[serde(default)]
+    pub default_lights: bool,
+    #[serde(default)]
+    pub lights: Vec<LightDescription>,
+    #[serde(default)]
+    pub camera: CameraDescription,
+    #[serde(default)]
+    pub film: FilmDescription,
+    #[serde(default)]
+    pub sampler: SamplerDescription,
+    #[serde(default)]
+    pub integrator: IntegratorDescription,
+}
+
+impl SceneDescription {
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("reading scene description {:?}", path))?;
+        let description = if path.extension().map_or(false, |ext| ext == "toml") {
+            toml::from_str(&source).map_err(anyhow::Error::from)
+        } else {
+            serde_json::from_str(&source).map_err(anyhow::Error::from)
+        };
+
+        description.with_context(|| format!("parsing scene description {:?}", path))
+    }
+
+    pub fn shutter(&self) -> Option<Shutter> {
+        self.shutter.map(|[open, close]| Shutter {
+            open: open.min(close),
+            close: open.max(close),
+        })
+    }
+}
+
+impl MaterialsDescription {
+    /// the library of these materials, images are looked up relative to the library file or
+    /// the description the materials are written out in
+    pub fn into_library(self, resolver: &AssetResolver) -> Result<MaterialLibrary> {
+        match self {
+            MaterialsDescription::Library(name) => MaterialLibrary::load(&resolver.resolve(&name)),
+            MaterialsDescription::Inline(entries) => Ok(MaterialLibrary::from_entries(
+                resolver.scene_path(),
+                entries,
+            )),
+        }
+    }
+}
+
+/// a mesh of the description loaded from its file
+pub struct PlacedMesh {
+    pub name: String,
+    pub mesh: mitsuba::Mesh,
+    pub obj_to_world: na::Projective3<f32>,
+    pub material: Option<String>,
+}
+
+fn load_mesh(resolver: &AssetResolver, description: &MeshDescription) -> Result<PlacedMesh> {
+    let path = resolver.resolve(&description.file);
+    if !path.exists() {
+        return Err(anyhow!("missing mesh {:?}", path));
+    }
+    let mesh = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("obj") => mitsuba::load_obj(&path),
+        Some("ply") => ply::load_ply(&path)?,
+        _ => return Err(anyhow!("unsupported mesh format {:?}", path)),
+    };
+
+    Ok(PlacedMesh {
+        name: description.file.clone(),
+        mesh,
+        obj_to_world: description.transform.matrix(),
+        material: description.material.clone(),
+    })
+}
+
+/// the camera of the description, looking from its `position` at its `target`, or `camera`
+/// with only the field of view of the description
+fn described_camera(
+    description: &CameraDescription,
+    camera: Camera,
+    resolution: &na::Vector2<f32>,
+) -> Camera {
+    let camera = match description.fov {
+        Some(fov) => camera.with_projection(Projection::Perspective(na::Perspective3::new(
+            resolution.x / resolution.y,
+            fov.to_radians(),
+            DEFAULT_Z_NEAR,
+            DEFAULT_Z_FAR,
+        ))),
+        None => camera,
+    };
+    match description.position {
+        Some(position) => {
+            let mut camera = camera;
+            camera.cam_to_world = na::Isometry3::look_at_rh(
+                &na::Point3::from(glm::make_vec3(&position)),
+                &na::Point3::from(glm::make_vec3(&description.target)),
+                &glm::make_vec3(&description.up),
+            )
+            .inverse();
+            camera
+        }
+        None => camera,
+    }
+}
+
+/// imports the scene a description references or places, `material_library` replaces the
+/// materials of the description itself, and like the other arguments it comes from the
+/// command line, or the description's settings when left out there, see `super::import`
+pub fn from_description(
+    log: &slog::Logger,
+    resolver: &AssetResolver,
+    search_paths: &[PathBuf],
+    material_library: Option<&MaterialLibrary>,
+    resolution: &na::Vector2<f32>,
+    default_lights: bool,
+    max_texture_size: Option<u32>,
+    variant: Option<&str>,
+    camera: Option<&str>,
+    shutter: Option<Shutter>,
+) -> (
+    Camera,
+    crate::pathtracer::RenderScene,
+    crate::viewer::renderer::ViewerScene,
+) {
+    let mut description = SceneDescription::load(resolver.scene_path()).unwrap();
+    let described_library = match (material_library, description.materials.take()) {
+        (None, Some(materials)) => materials
+            .into_library(resolver)
+            .map_err(|err| {
+                warn!(
+                    log,
+                    "failed loading the materials of the description: {:?}", err
+                );
+            })
+            .ok(),
+        _ => None,
+    };
+    let material_library = material_library.or_else(|| described_library.as_ref());
+
+    let (scene_camera, mut render_scene, viewer_scene) = match &description.scene {
+        Some(scene) => {
+            if !description.meshes.is_empty() {
+                warn!(
+                    log,
+                    "ignoring the meshes of the description, its geometry comes from {:?}", scene
+                );
+            }
+            let scene_path = resolver.resolve(scene);
+            super::import(
+                log,
+                &scene_path.to_string_lossy(),
+                search_paths,
+                material_library,
+                resolution,
+                default_lights,
+                max_texture_size,
+                variant,
+                camera,
+                shutter,
+            )
+        }
+        None => {
+            let meshes: Vec<PlacedMesh> = description
+                .meshes
+                .iter()
+                .filter_map(|mesh| {
+                    load_mesh(resolver, mesh)
+                        .map_err(|err| warn!(log, "skipping mesh {:?}: {:?}", mesh.file, err))
+                        .ok()
+                })
+                .collect();
+            let bvh_cache = BVHCache::load(&log, &resolver.cache_path(BVH_CACHE_EXTENSION));
+            let render_scene = crate::pathtracer::RenderScene::from_description(
+                &log,
+                &meshes,
+                material_library,
+                default_lights,
+                &bvh_cache,
+            );
+            bvh_cache.save();
+            let camera = super::gltf::get_default_camera(&render_scene.world_bound(), resolution);
+            let viewer_scene = crate::viewer::renderer::ViewerScene::from_description(meshes);
+
+            (camera, render_scene, viewer_scene)
+        }
+    };
+
+    render_scene.add_described_lights(&log, &description.lights, resolver);
+    let camera = described_camera(&description.camera, scene_camera, resolution);
+
+    (camera, render_scene, viewer_scene)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_toml_and_json_alike() {
+        let toml: SceneDescription = toml::from_str(
+            r#"
+            scene = "box.gltf"
+
+            [film]
+            resolution = [320, 240]
+
+            [[lights]]
+            type = "point"
+            position = [0.0, 2.0, 0.0]
+            intensity = 5.0
+
+            [materials.floor]
+            type = "matte"
+            color = [0.5, 0.5, 0.5]
+            "#,
+        )
+        .unwrap();
+        let json: SceneDescription = serde_json::from_str(
+            r#"{
+                "scene": "box.gltf",
+                "film": {"resolution": [320, 240]},
+                "lights": [{"type": "point", "position": [0.0, 2.0, 0.0], "intensity": 5.0}],
+                "materials": {"floor": {"type": "matte", "color": [0.5, 0.5, 0.5]}}
+            }"#,
+        )
+        .unwrap();
+
+        for description in &[toml, json] {
+            assert_eq!(description.scene.as_deref(), Some("box.gltf"));
+            assert_eq!(description.film.resolution, Some([320, 240]));
+            assert!(matches!(
+                description.lights[0],
+                LightDescription::Point { color, intensity, .. }
+                    if color == [1.0; 3] && intensity == 5.0
+            ));
+            assert!(matches!(
+                &description.materials,
+                Some(MaterialsDescription::Inline(entries)) if entries.contains_key("floor")
+            ));
+        }
+    }
+
+    #[test]
+    fn places_meshes_scaled_rotated_then_translated() {
+        let transform = TransformDescription {
+            translate: [1.0, 0.0, 0.0],
+            rotate: [0.0, 0.0, 90.0],
+            scale: [2.0, 2.0, 2.0],
+        };
+        let p = transform.matrix() * na::Point3::new(1.0, 0.0, 0.0);
+        assert!((p - na::Point3::new(1.0, 2.0, 0.0)).norm() < 1e-5);
+    }
+}
@@ -1,66 +1,115 @@
-use crate::common::{bounds::Bounds3, Camera, DEFAULT_Z_FAR, DEFAULT_Z_NEAR};
+use super::BVH_CACHE_EXTENSION;
+use crate::common::{bounds::Bounds3, Camera, Projection, Shutter, DEFAULT_Z_FAR, DEFAULT_Z_NEAR};
+use crate::pathtracer::accelerator::BVHCache;
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+
+/// vertical field of view showing the frame the camera was authored for at the aspect ratio of
+/// the film. a wider film keeps the vertical extent and a narrower one the horizontal extent, so
+/// resolution overrides never crop the authored shot
+fn fit_yfov(yfov: f32, authored_aspect: Option<f32>, film_aspect: f32) -> f32 {
+    match authored_aspect {
+        Some(aspect) if film_aspect < aspect => {
+            2.0 * ((0.5 * yfov).tan() * aspect / film_aspect).atan()
+        }
+        _ => yfov,
+    }
+}
+
+/// whether `selected` is the index of `camera`, or the name of it or of the `node` placing it
+fn is_selected_camera(camera: &gltf::Camera, node: &gltf::Node, selected: &str) -> bool {
+    selected.parse::<usize>().ok() == Some(camera.index())
+        || camera.name() == Some(selected)
+        || node.name() == Some(selected)
+}
 
 fn find_camera(
-    parent_transform: &na::Transform3<f32>,
+    parent_transforms: &[na::Projective3<f32>; 2],
     current_node: &gltf::Node,
+    animations: &NodeAnimations,
+    shutter: &Shutter,
     resolution: &glm::Vec2,
+    selected: Option<&str>,
 ) -> Option<Camera> {
-    let current_transform = *parent_transform * trans_from_gltf(current_node.transform());
-    if let Some(camera) = current_node.camera() {
-        if let gltf::camera::Projection::Perspective(projection) = camera.projection() {
-            let zfar = if let Some(far) = projection.zfar() {
-                far
-            } else {
-                DEFAULT_Z_FAR
-            };
-            // TODO: maybe incorporate this in the future
-            // let aspect_ratio = if let Some(aspect) = projection.aspect_ratio() {
-            //     aspect
-            // } else {
-            //     std::f32::consts::FRAC_PI_2
-            // };
-            return Some(Camera::new(
-                &na::try_convert(current_transform).unwrap(),
-                &na::Perspective3::new(
-                    resolution.x / resolution.y,
-                    projection.yfov(),
+    let current_transforms = [
+        parent_transforms[0] * animated_trans_from_gltf(current_node, animations, shutter.open),
+        parent_transforms[1] * animated_trans_from_gltf(current_node, animations, shutter.close),
+    ];
+    if let Some(camera) = current_node.camera().filter(|camera| {
+        selected.map_or(true, |selected| {
+            is_selected_camera(camera, current_node, selected)
+        })
+    }) {
+        let film_aspect = resolution.x / resolution.y;
+        let projection = match camera.projection() {
+            gltf::camera::Projection::Perspective(projection) => {
+                Projection::Perspective(na::Perspective3::new(
+                    film_aspect,
+                    fit_yfov(projection.yfov(), projection.aspect_ratio(), film_aspect),
                     projection.znear(),
-                    zfar,
-                ),
-                &resolution,
-            ));
-        } else {
-            for child in current_node.children() {
-                return find_camera(&current_transform, &child, &resolution);
+                    // a missing zfar means an infinite projection
+                    projection.zfar().unwrap_or(DEFAULT_Z_FAR),
+                ))
             }
-
-            None
-        }
-    } else {
-        for child in current_node.children() {
-            return find_camera(&current_transform, &child, &resolution);
-        }
-
-        None
+            gltf::camera::Projection::Orthographic(projection) => {
+                // fitted like the field of view, xmag and ymag are half the width and height
+                let authored_aspect = projection.xmag() / projection.ymag();
+                let height = if film_aspect < authored_aspect {
+                    2.0 * projection.xmag() / film_aspect
+                } else {
+                    2.0 * projection.ymag()
+                };
+                Projection::orthographic(film_aspect, height, projection.znear(), projection.zfar())
+            }
+        };
+        return Some(
+            Camera::new(
+                &na::try_convert(current_transforms[0]).unwrap(),
+                &projection,
+                &resolution,
+            )
+            .with_motion(*shutter, &na::try_convert(current_transforms[1]).unwrap()),
+        );
     }
+
+    current_node.children().find_map(|child| {
+        find_camera(
+            &current_transforms,
+            &child,
+            animations,
+            shutter,
+            &resolution,
+            selected,
+        )
+    })
 }
 
-pub fn get_camera(
+/// the first camera in the scenes of the document, or the one `selected` by name or index, if
+/// there's any, moving along its animation while the shutter is open
+pub fn get_document_camera(
     document: &gltf::Document,
-    world_bound: &Bounds3,
+    animations: &NodeAnimations,
+    shutter: &Shutter,
     resolution: &glm::Vec2,
-) -> Camera {
-    let mut camera = get_default_camera(&world_bound, &resolution);
-    'scene: for scene in document.scenes() {
+    selected: Option<&str>,
+) -> Option<Camera> {
+    for scene in document.scenes() {
         for node in scene.nodes() {
-            if let Some(curr_cam) = find_camera(&na::Transform3::identity(), &node, &resolution) {
-                camera = curr_cam;
-                break 'scene;
+            let identity = na::Projective3::identity();
+            if let Some(camera) = find_camera(
+                &[identity, identity],
+                &node,
+                animations,
+                shutter,
+                &resolution,
+                selected,
+            ) {
+                return Some(camera);
             }
         }
     }
 
-    camera
+    None
 }
 
 pub fn get_default_camera(world_bound: &Bounds3, resolution: &glm::Vec2) -> Camera {
@@ -71,47 +120,545 @@ pub fn get_default_camera(world_bound: &Bounds3, resolution: &glm::Vec2) -> Came
             &na::Vector3::new(0.0, 1.0, 0.0),
         )
         .inverse(),
-        &na::Perspective3::new(
+        &Projection::Perspective(na::Perspective3::new(
             resolution.x / resolution.y,
             std::f32::consts::FRAC_PI_2 * (resolution.y / resolution.x),
             DEFAULT_Z_NEAR,
             DEFAULT_Z_FAR,
-        ),
+        )),
         &resolution,
     )
 }
 
 pub fn trans_from_gltf(transform: gltf::scene::Transform) -> na::Projective3<f32> {
     let (translation, rotation, scaling) = transform.decomposed();
+    trans_from_trs(&translation, &rotation, &scaling)
+}
 
-    let t = glm::translation(&glm::make_vec3(&translation));
-    let r = glm::quat_to_mat4(&glm::make_quat(&rotation));
-    let s = glm::scaling(&glm::make_vec3(&scaling));
+fn trans_from_trs(
+    translation: &[f32; 3],
+    rotation: &[f32; 4],
+    scaling: &[f32; 3],
+) -> na::Projective3<f32> {
+    let t = glm::translation(&glm::make_vec3(translation));
+    let r = glm::quat_to_mat4(&glm::make_quat(rotation));
+    let s = glm::scaling(&glm::make_vec3(scaling));
 
     na::Projective3::from_matrix_unchecked(t * r * s)
 }
 
+/// keyframes of one animated property of a node
+struct Track<T> {
+    times: Vec<f32>,
+    values: Vec<T>,
+    step: bool,
+}
+
+impl<T: Copy> Track<T> {
+    fn new(times: Vec<f32>, values: Vec<T>, interpolation: gltf::animation::Interpolation) -> Self {
+        Self {
+            times,
+            // only the values of cubic splines are kept, their tangents are dropped and the
+            // values interpolated linearly
+            values: match interpolation {
+                gltf::animation::Interpolation::CubicSpline => {
+                    values.into_iter().skip(1).step_by(3).collect()
+                }
+                _ => values,
+            },
+            step: matches!(interpolation, gltf::animation::Interpolation::Step),
+        }
+    }
+
+    fn sample<F: Fn(&T, &T, f32) -> T>(&self, time: f32, lerp: F) -> T {
+        let next = self
+            .times
+            .iter()
+            .position(|&t| t > time)
+            .unwrap_or(self.times.len())
+            .min(self.values.len());
+        if next == 0 {
+            return self.values[0];
+        }
+        if next == self.values.len() || self.step {
+            return self.values[next - 1];
+        }
+
+        let (t0, t1) = (self.times[next - 1], self.times[next]);
+        lerp(
+            &self.values[next - 1],
+            &self.values[next],
+            (time - t0) / (t1 - t0),
+        )
+    }
+}
+
+/// animated translation, rotation and scale of a node
+#[derive(Default)]
+pub struct NodeAnimation {
+    translation: Option<Track<[f32; 3]>>,
+    rotation: Option<Track<[f32; 4]>>,
+    scale: Option<Track<[f32; 3]>>,
+}
+
+/// animations by the index of the node they move
+pub type NodeAnimations = HashMap<usize, NodeAnimation>;
+
+/// the node transforms driven by the animations of the document, morph target weights are ignored
+pub fn node_animations_from_gltf(
+    document: &gltf::Document,
+    buffers: &[gltf::buffer::Data],
+) -> NodeAnimations {
+    let mut animations = NodeAnimations::new();
+    for channel in document
+        .animations()
+        .flat_map(|animation| animation.channels())
+    {
+        let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+        let (times, outputs) = match (reader.read_inputs(), reader.read_outputs()) {
+            (Some(times), Some(outputs)) => (times.collect::<Vec<f32>>(), outputs),
+            _ => continue,
+        };
+        if times.is_empty() {
+            continue;
+        }
+
+        let interpolation = channel.sampler().interpolation();
+        let animation = animations
+            .entry(channel.target().node().index())
+            .or_default();
+        match outputs {
+            gltf::animation::util::ReadOutputs::Translations(translations) => {
+                animation.translation =
+                    Some(Track::new(times, translations.collect(), interpolation));
+            }
+            gltf::animation::util::ReadOutputs::Rotations(rotations) => {
+                animation.rotation = Some(Track::new(
+                    times,
+                    rotations.into_f32().collect(),
+                    interpolation,
+                ));
+            }
+            gltf::animation::util::ReadOutputs::Scales(scales) => {
+                animation.scale = Some(Track::new(times, scales.collect(), interpolation));
+            }
+            gltf::animation::util::ReadOutputs::MorphTargetWeights(_) => {}
+        }
+    }
+
+    animations
+}
+
+fn lerp3(a: &[f32; 3], b: &[f32; 3], t: f32) -> [f32; 3] {
+    ((1.0 - t) * glm::make_vec3(a) + t * glm::make_vec3(b)).into()
+}
+
+/// local transform of `node` at `time`, properties without animation stay at rest
+pub fn animated_trans_from_gltf(
+    node: &gltf::Node,
+    animations: &NodeAnimations,
+    time: f32,
+) -> na::Projective3<f32> {
+    let animation = match animations.get(&node.index()) {
+        Some(animation) => animation,
+        None => return trans_from_gltf(node.transform()),
+    };
+
+    let (translation, rotation, scaling) = node.transform().decomposed();
+    let translation = animation
+        .translation
+        .as_ref()
+        .map_or(translation, |track| track.sample(time, lerp3));
+    let rotation = animation.rotation.as_ref().map_or(rotation, |track| {
+        track.sample(time, |a, b, t| {
+            let a = na::UnitQuaternion::new_normalize(glm::make_quat(a));
+            let b = na::UnitQuaternion::new_normalize(glm::make_quat(b));
+            a.slerp(&b, t).coords.into()
+        })
+    });
+    let scaling = animation
+        .scale
+        .as_ref()
+        .map_or(scaling, |track| track.sample(time, lerp3));
+
+    trans_from_trs(&translation, &rotation, &scaling)
+}
+
+/// the gltf image as an image of the `image` crate, `None` when its pixels don't match its size
+fn dynamic_image_from_gltf(image: &gltf::image::Data) -> Option<image::DynamicImage> {
+    let (width, height) = (image.width, image.height);
+    let pixels = image.pixels.clone();
+    let wide_pixels = || {
+        image
+            .pixels
+            .chunks_exact(2)
+            .map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]]))
+            .collect::<Vec<_>>()
+    };
+    match image.format {
+        gltf::image::Format::R8 => {
+            image::GrayImage::from_raw(width, height, pixels).map(image::DynamicImage::ImageLuma8)
+        }
+        gltf::image::Format::R8G8 => image::GrayAlphaImage::from_raw(width, height, pixels)
+            .map(image::DynamicImage::ImageLumaA8),
+        gltf::image::Format::R8G8B8 => {
+            image::RgbImage::from_raw(width, height, pixels).map(image::DynamicImage::ImageRgb8)
+        }
+        gltf::image::Format::R8G8B8A8 => {
+            image::RgbaImage::from_raw(width, height, pixels).map(image::DynamicImage::ImageRgba8)
+        }
+        gltf::image::Format::B8G8R8 => {
+            image::ImageBuffer::from_raw(width, height, pixels).map(image::DynamicImage::ImageBgr8)
+        }
+        gltf::image::Format::B8G8R8A8 => {
+            image::ImageBuffer::from_raw(width, height, pixels).map(image::DynamicImage::ImageBgra8)
+        }
+        gltf::image::Format::R16 => image::ImageBuffer::from_raw(width, height, wide_pixels())
+            .map(image::DynamicImage::ImageLuma16),
+        gltf::image::Format::R16G16 => image::ImageBuffer::from_raw(width, height, wide_pixels())
+            .map(image::DynamicImage::ImageLumaA16),
+        gltf::image::Format::R16G16B16 => {
+            image::ImageBuffer::from_raw(width, height, wide_pixels())
+                .map(image::DynamicImage::ImageRgb16)
+        }
+        gltf::image::Format::R16G16B16A16 => {
+            image::ImageBuffer::from_raw(width, height, wide_pixels())
+                .map(image::DynamicImage::ImageRgba16)
+        }
+    }
+}
+
+fn gltf_image_from_dynamic(dynamic_image: image::DynamicImage) -> gltf::image::Data {
+    use image::GenericImageView;
+
+    let (width, height) = (dynamic_image.width(), dynamic_image.height());
+    let wide_bytes = |values: Vec<u16>| {
+        values
+            .into_iter()
+            .flat_map(|v| v.to_ne_bytes().to_vec())
+            .collect()
+    };
+    let (format, pixels) = match dynamic_image {
+        image::DynamicImage::ImageLuma8(i) => (gltf::image::Format::R8, i.into_raw()),
+        image::DynamicImage::ImageLumaA8(i) => (gltf::image::Format::R8G8, i.into_raw()),
+        image::DynamicImage::ImageRgb8(i) => (gltf::image::Format::R8G8B8, i.into_raw()),
+        image::DynamicImage::ImageRgba8(i) => (gltf::image::Format::R8G8B8A8, i.into_raw()),
+        image::DynamicImage::ImageBgr8(i) => (gltf::image::Format::B8G8R8, i.into_raw()),
+        image::DynamicImage::ImageBgra8(i) => (gltf::image::Format::B8G8R8A8, i.into_raw()),
+        image::DynamicImage::ImageLuma16(i) => (gltf::image::Format::R16, wide_bytes(i.into_raw())),
+        image::DynamicImage::ImageLumaA16(i) => {
+            (gltf::image::Format::R16G16, wide_bytes(i.into_raw()))
+        }
+        image::DynamicImage::ImageRgb16(i) => {
+            (gltf::image::Format::R16G16B16, wide_bytes(i.into_raw()))
+        }
+        image::DynamicImage::ImageRgba16(i) => {
+            (gltf::image::Format::R16G16B16A16, wide_bytes(i.into_raw()))
+        }
+    };
+
+    gltf::image::Data {
+        pixels,
+        format,
+        width,
+        height,
+    }
+}
+
+fn downsample_gltf_image(log: &slog::Logger, image: &mut gltf::image::Data, max_texture_size: u32) {
+    if image.width <= max_texture_size && image.height <= max_texture_size {
+        return;
+    }
+
+    let dynamic_image =
+        dynamic_image_from_gltf(image).expect("image dimensions do not match pixel data");
+    *image = gltf_image_from_dynamic(crate::common::importer::downsample_image(
+        log,
+        dynamic_image,
+        Some(max_texture_size),
+    ));
+}
+
+/// decodes the percent escapes of a uri, e.g. the `%20` spaces of file names
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' && i + 2 < bytes.len() {
+            std::str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// file a buffer or image uri points at, relative ones looked up by `resolver` next to the
+/// scene and in its search paths
+pub fn resolve_uri(resolver: &super::AssetResolver, uri: &str) -> std::path::PathBuf {
+    let uri = percent_decode(uri);
+    if uri.starts_with("file://") {
+        std::path::PathBuf::from(&uri["file://".len()..])
+    } else if uri.starts_with("file:") {
+        std::path::PathBuf::from(&uri["file:".len()..])
+    } else {
+        resolver.resolve(&uri)
+    }
+}
+
+/// contents of a buffer or image uri, either embedded as base64 or a file found by `resolver`
+fn read_uri(resolver: &super::AssetResolver, uri: &str) -> Result<Vec<u8>> {
+    if uri.starts_with("data:") {
+        let encoded = uri
+            .splitn(2, ";base64,")
+            .nth(1)
+            .ok_or_else(|| anyhow!("data uri isn't base64 encoded"))?;
+        return base64::decode(encoded).context("decoding base64 data uri");
+    }
+
+    let path = resolve_uri(resolver, uri);
+    std::fs::read(&path).with_context(|| format!("reading {:?}", path))
+}
+
+/// a gltf scene with its json, loaded without the file uri resolution of the gltf crate
+pub struct GltfFile {
+    pub document: gltf::Document,
+    blob: Option<Vec<u8>>,
+    /// json document of the scene, for extensions the gltf crate does not parse
+    pub json: serde_json::Value,
+}
+
+impl GltfFile {
+    pub fn open(path: &std::path::Path) -> Result<Self> {
+        let raw = std::fs::read(path).with_context(|| format!("reading {:?}", path))?;
+        let (json, blob) = if raw.starts_with(b"glTF") {
+            let glb = gltf::Glb::from_slice(&raw)?;
+            (glb.json.into_owned(), glb.bin.map(|bin| bin.into_owned()))
+        } else {
+            (raw, None)
+        };
+
+        let json: serde_json::Value = serde_json::from_slice(&json)?;
+        let document = gltf::Document::from_json(serde_json::from_value(json.clone())?)?;
+
+        Ok(Self {
+            document,
+            blob,
+            json,
+        })
+    }
+
+    /// loads the buffers of the document, external ones found by `resolver`
+    pub fn buffers(&mut self, resolver: &super::AssetResolver) -> Result<Vec<gltf::buffer::Data>> {
+        let mut buffers = vec![];
+        for buffer in self.document.buffers() {
+            let mut data = match buffer.source() {
+                gltf::buffer::Source::Uri(uri) => read_uri(resolver, uri)?,
+                gltf::buffer::Source::Bin => self
+                    .blob
+                    .take()
+                    .ok_or_else(|| anyhow!("buffer {} has no binary chunk", buffer.index()))?,
+            };
+            if data.len() < buffer.length() {
+                return Err(anyhow!(
+                    "buffer {} is {} bytes long, expected {}",
+                    buffer.index(),
+                    data.len(),
+                    buffer.length()
+                ));
+            }
+            // accessors may read up to the next multiple of four
+            while data.len() % 4 != 0 {
+                data.push(0);
+            }
+            buffers.push(gltf::buffer::Data(data));
+        }
+
+        Ok(buffers)
+    }
+
+    /// decodes every image of the document
+    pub fn images(
+        &self,
+        resolver: &super::AssetResolver,
+        buffers: &[gltf::buffer::Data],
+    ) -> Result<Vec<gltf::image::Data>> {
+        let mut images = vec![];
+        for gltf_image in self.document.images() {
+            let data = match gltf_image.source() {
+                gltf::image::Source::View { view, .. } => {
+                    let buffer = &buffers[view.buffer().index()];
+                    buffer[view.offset()..view.offset() + view.length()].to_vec()
+                }
+                gltf::image::Source::Uri { uri, .. } => read_uri(resolver, uri)?,
+            };
+            let decoded = image::load_from_memory(&data)
+                .with_context(|| format!("decoding image {}", gltf_image.index()))?;
+            images.push(gltf_image_from_dynamic(decoded));
+        }
+
+        Ok(images)
+    }
+}
+
+/// raw json of every material, including the extensions of its texture infos
+fn materials_json_from_gltf(root: &serde_json::Value) -> Vec<serde_json::Value> {
+    root["materials"]
+        .as_array()
+        .map_or(vec![], |materials| materials.clone())
+}
+
+/// material index of every mesh primitive the `KHR_materials_variants` variant named `variant`
+/// remaps, keyed by mesh and primitive index. primitives without a mapping keep their material
+fn variant_materials_from_gltf(
+    log: &slog::Logger,
+    root: &serde_json::Value,
+    variant: &str,
+) -> HashMap<(usize, usize), usize> {
+    let names: Vec<&str> = root["extensions"]["KHR_materials_variants"]["variants"]
+        .as_array()
+        .map_or(vec![], |variants| {
+            variants
+                .iter()
+                .map(|variant| variant["name"].as_str().unwrap_or(""))
+                .collect()
+        });
+    let variant_idx = match names.iter().position(|name| *name == variant) {
+        Some(idx) => idx as u64,
+        None => {
+            warn!(
+                log,
+                "material variant {:?} not found, available variants: {:?}", variant, names
+            );
+            return HashMap::new();
+        }
+    };
+
+    let mut variant_materials = HashMap::new();
+    let meshes = root["meshes"].as_array().map_or(&[][..], |m| m.as_slice());
+    for (mesh_idx, mesh) in meshes.iter().enumerate() {
+        let primitives = mesh["primitives"]
+            .as_array()
+            .map_or(&[][..], |p| p.as_slice());
+        for (prim_idx, prim) in primitives.iter().enumerate() {
+            let mappings = prim["extensions"]["KHR_materials_variants"]["mappings"].as_array();
+            let material = mappings.into_iter().flatten().find_map(|mapping| {
+                let variants = mapping["variants"].as_array()?;
+                if variants.iter().any(|v| v.as_u64() == Some(variant_idx)) {
+                    mapping["material"].as_u64()
+                } else {
+                    None
+                }
+            });
+            if let Some(material) = material {
+                variant_materials.insert((mesh_idx, prim_idx), material as usize);
+            }
+        }
+    }
+    debug!(
+        log,
+        "material variant {:?} remaps {} primitives",
+        variant,
+        variant_materials.len()
+    );
+
+    variant_materials
+}
+
 pub fn from_gltf(
     log: &slog::Logger,
-    path: &str,
+    resolver: &super::AssetResolver,
+    material_library: Option<&super::library::MaterialLibrary>,
     resolution: &na::Vector2<f32>,
     default_lights: bool,
+    max_texture_size: Option<u32>,
+    variant: Option<&str>,
+    camera: Option<&str>,
+    shutter: Option<Shutter>,
 ) -> (
     Camera,
     crate::pathtracer::RenderScene,
     crate::viewer::renderer::ViewerScene,
 ) {
-    let (document, buffers, images) = gltf::import(path).unwrap();
+    // external buffers and images are looked up next to the scene, then in the search paths
+    let path = resolver.scene_path();
+    let mut gltf_file = GltfFile::open(path).unwrap();
+    let buffers = gltf_file.buffers(resolver).unwrap();
+    let mut images = gltf_file.images(resolver, &buffers).unwrap();
+    let GltfFile { document, json, .. } = gltf_file;
+    if let Some(max_texture_size) = max_texture_size {
+        for image in &mut images {
+            downsample_gltf_image(&log, image, max_texture_size);
+        }
+    }
+    let materials_json = materials_json_from_gltf(&json);
+    let variant_materials = variant.map_or_else(HashMap::new, |variant| {
+        variant_materials_from_gltf(&log, &json, variant)
+    });
+    // without a shutter the scene stays at rest instead of posing at the start of its animations
+    let animations = shutter.map_or_else(NodeAnimations::new, |_| {
+        node_animations_from_gltf(&document, &buffers)
+    });
+    let shutter = shutter.unwrap_or_default();
+    let mut document_camera =
+        get_document_camera(&document, &animations, &shutter, &resolution, camera);
+    if let (None, Some(camera)) = (&document_camera, camera) {
+        let cameras = document
+            .cameras()
+            .map(|camera| (camera.index(), camera.name().unwrap_or_default().to_owned()))
+            .collect::<Vec<_>>();
+        warn!(
+            log,
+            "no camera {:?} in the scene, rendering from the first of: {:?}", camera, cameras
+        );
+        document_camera = get_document_camera(&document, &animations, &shutter, &resolution, None);
+    }
+    let viewer_scene =
+        crate::viewer::renderer::ViewerScene::from_gltf(&document, &buffers, &images);
+    let bvh_cache = BVHCache::load(&log, &resolver.cache_path(BVH_CACHE_EXTENSION));
+    // the render scene takes the images, their textures decode them on first use
     let render_scene = crate::pathtracer::RenderScene::from_gltf(
         &log,
         &document,
         &buffers,
-        &images,
+        images,
+        &materials_json,
+        &variant_materials,
+        material_library,
         default_lights,
+        document_camera.as_ref(),
+        &animations,
+        &shutter,
+        &bvh_cache,
     );
-    let camera = get_camera(&document, &render_scene.world_bound(), &resolution);
-    let viewer_scene =
-        crate::viewer::renderer::ViewerScene::from_gltf(&document, &buffers, &images);
+    bvh_cache.save();
+    let camera = document_camera
+        .unwrap_or_else(|| get_default_camera(&render_scene.world_bound(), &resolution));
 
     (camera, render_scene, viewer_scene)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_percent_escaped_uris() {
+        assert_eq!(percent_decode("my%20texture.png"), "my texture.png");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz.bin"), "%zz.bin");
+    }
+}
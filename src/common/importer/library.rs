@@ -0,0 +1,242 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// a color parameter, either a gray value, an rgb triple, an image path relative to the
+/// library file, or a texture combining other parameters
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum SpectrumParam {
+    Value(f32),
+    Rgb([f32; 3]),
+    Image(String),
+    Texture(Box<SpectrumTextureDefinition>),
+}
+
+/// a scalar parameter, either a value, an image path relative to the library file, or a texture
+/// combining other parameters
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum FloatParam {
+    Value(f32),
+    Image(String),
+    Texture(Box<FloatTextureDefinition>),
+}
+
+/// layered colors, e.g. dirt over a base color
+/// `{"type": "mix", "textures": [[0.8, 0.8, 0.8], [0.2, 0.15, 0.1]], "amount": "dirt.png"}`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SpectrumTextureDefinition {
+    /// product of both colors
+    Scale { textures: [SpectrumParam; 2] },
+    /// blends from the first to the second color by `amount`
+    Mix {
+        textures: [SpectrumParam; 2],
+        #[serde(default = "half")]
+        amount: FloatParam,
+    },
+    /// remaps `input` through a gradient of `[position, [r, g, b]]` stops
+    Ramp {
+        input: FloatParam,
+        stops: Vec<(f32, [f32; 3])>,
+    },
+    /// color of the mesh vertices, white on meshes without vertex colors
+    VertexColor,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FloatTextureDefinition {
+    /// product of both values
+    Scale { textures: [FloatParam; 2] },
+    /// blends from the first to the second value by `amount`
+    Mix {
+        textures: [FloatParam; 2],
+        #[serde(default = "half")]
+        amount: FloatParam,
+    },
+    /// remaps `input` through a gradient of `[position, value]` stops
+    Ramp {
+        input: FloatParam,
+        stops: Vec<(f32, f32)>,
+    },
+}
+
+fn white() -> SpectrumParam {
+    SpectrumParam::Value(1.0)
+}
+
+fn gray() -> SpectrumParam {
+    SpectrumParam::Value(0.5)
+}
+
+fn glass_ior() -> FloatParam {
+    FloatParam::Value(1.5)
+}
+
+fn zero() -> FloatParam {
+    FloatParam::Value(0.0)
+}
+
+/// microfacet models break down at exactly zero roughness
+fn smooth() -> FloatParam {
+    FloatParam::Value(0.001)
+}
+
+fn half() -> FloatParam {
+    FloatParam::Value(0.5)
+}
+
+fn unit_scale() -> f32 {
+    1.0
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MaterialDefinition {
+    Matte {
+        #[serde(default = "white")]
+        color: SpectrumParam,
+        /// oren-nayar roughness in degrees, lambertian when absent
+        sigma: Option<FloatParam>,
+    },
+    Mirror,
+    Glass {
+        #[serde(default = "white")]
+        reflect: SpectrumParam,
+        #[serde(default = "white")]
+        transmit: SpectrumParam,
+        #[serde(default = "glass_ior")]
+        ior: FloatParam,
+        /// microfacet alpha, smooth glass when absent
+        roughness: Option<FloatParam>,
+        /// microfacet distribution, `ggx` or `beckmann`
+        distribution: Option<String>,
+    },
+    /// either a named `preset` (gold, copper, aluminum, iron, silver) or an explicit `eta` and `k`
+    Metal {
+        preset: Option<String>,
+        eta: Option<SpectrumParam>,
+        k: Option<SpectrumParam>,
+        #[serde(default = "white")]
+        reflectance: SpectrumParam,
+        #[serde(default = "smooth")]
+        roughness: FloatParam,
+        /// anisotropic roughness along the surface tangent and bitangent, overriding `roughness`
+        roughness_u: Option<FloatParam>,
+        roughness_v: Option<FloatParam>,
+        /// rotation of the tangent frame in turns, turning the direction of anisotropic highlights
+        rotation: Option<FloatParam>,
+        /// microfacet distribution, `ggx` or `beckmann`
+        distribution: Option<String>,
+    },
+    Substrate {
+        #[serde(default = "gray")]
+        diffuse: SpectrumParam,
+        #[serde(default = "gray")]
+        specular: SpectrumParam,
+        #[serde(default = "smooth")]
+        roughness: FloatParam,
+        /// microfacet distribution, `ggx` or `beckmann`
+        distribution: Option<String>,
+    },
+    Disney {
+        #[serde(default = "white")]
+        color: SpectrumParam,
+        #[serde(default = "zero")]
+        metallic: FloatParam,
+        #[serde(default = "half")]
+        roughness: FloatParam,
+        #[serde(default = "glass_ior")]
+        ior: FloatParam,
+    },
+    /// thin surface scattering diffusely to both sides
+    Translucent {
+        #[serde(default = "white")]
+        color: SpectrumParam,
+        #[serde(default = "gray")]
+        reflect: SpectrumParam,
+        #[serde(default = "gray")]
+        transmit: SpectrumParam,
+    },
+    /// ground that only shows the shadows and reflections of the scene, for compositing renders
+    /// onto photographs. `color` is the diffuse color the rest of the scene sees bouncing off it
+    ShadowCatcher {
+        #[serde(default = "gray")]
+        color: SpectrumParam,
+        #[serde(default = "zero")]
+        reflect: FloatParam,
+    },
+    /// blends two other materials of the library by name
+    Mix {
+        materials: [String; 2],
+        #[serde(default = "half")]
+        amount: FloatParam,
+    },
+}
+
+/// heights moving the surfaces of a material along their normals, diced finely when the scene
+/// is built, e.g. `"displacement": {"height": "bricks_height.png", "scale": 0.02}`
+#[derive(Debug, Deserialize)]
+pub struct DisplacementDefinition {
+    pub height: FloatParam,
+    #[serde(default = "unit_scale")]
+    pub scale: f32,
+}
+
+/// a material and the displacement of the surfaces using it, side by side in the same object
+#[derive(Debug, Deserialize)]
+pub struct LibraryEntry {
+    #[serde(flatten)]
+    material: MaterialDefinition,
+    displacement: Option<DisplacementDefinition>,
+}
+
+/// named material definitions loaded from a json file, used to override the materials of
+/// imported scenes with the same name, e.g.
+/// `{"floor": {"type": "substrate", "diffuse": [0.2, 0.1, 0.05], "roughness": 0.1}}`
+#[derive(Debug, Default)]
+pub struct MaterialLibrary {
+    pub path: PathBuf,
+    pub materials: HashMap<String, MaterialDefinition>,
+    pub displacements: HashMap<String, DisplacementDefinition>,
+}
+
+impl MaterialLibrary {
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("opening material library {:?}", path))?;
+        let entries: HashMap<String, LibraryEntry> =
+            serde_json::from_reader(std::io::BufReader::new(file))
+                .with_context(|| format!("parsing material library {:?}", path))?;
+
+        Ok(Self::from_entries(path, entries))
+    }
+
+    /// a library of entries defined in the file at `path`, which their images are relative to
+    pub fn from_entries(path: &Path, entries: HashMap<String, LibraryEntry>) -> Self {
+        let mut materials = HashMap::new();
+        let mut displacements = HashMap::new();
+        for (name, entry) in entries {
+            if let Some(displacement) = entry.displacement {
+                displacements.insert(name.clone(), displacement);
+            }
+            materials.insert(name, entry.material);
+        }
+
+        Self {
+            path: path.to_path_buf(),
+            materials,
+            displacements,
+        }
+    }
+
+    /// resolves an image path referenced by the library
+    pub fn resolve(&self, name: &str) -> PathBuf {
+        self.path
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(name)
+    }
+}
@@ -1,12 +1,13 @@
-use crate::common::Camera;
+use super::BVH_CACHE_EXTENSION;
+use crate::common::{Camera, Projection};
+use crate::pathtracer::accelerator::BVHCache;
 use genmesh::generators::IndexedPolygon;
 use genmesh::generators::SharedVertex;
 use genmesh::Triangulate;
 use heck::SnakeCase;
-use quick_xml::de::from_reader;
+use quick_xml::de::from_str;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::BufReader;
 use std::io::Read;
 use wavefront_obj::obj;
 
@@ -15,6 +16,8 @@ pub struct Mesh {
     pub pos: Vec<na::Point3<f32>>,
     pub normal: Vec<na::Vector3<f32>>,
     pub uv: Vec<na::Point2<f32>>,
+    /// linear colors of the vertices, empty for meshes without any
+    pub colors: Vec<na::Vector3<f32>>,
 }
 
 pub fn gen_rectangle() -> Mesh {
@@ -34,6 +37,7 @@ pub fn gen_rectangle() -> Mesh {
             .map(|v| na::Vector3::from(v.normal))
             .collect(),
         uv: vec![],
+        colors: vec![],
     }
 }
 
@@ -54,6 +58,53 @@ pub fn gen_cube() -> Mesh {
             .map(|v| na::Vector3::from(v.normal))
             .collect(),
         uv: vec![],
+        colors: vec![],
+    }
+}
+
+/// unit disk facing +z
+pub fn gen_disk() -> Mesh {
+    let circle = genmesh::generators::Circle::new(32);
+    Mesh {
+        indices: circle
+            .indexed_polygon_iter()
+            .triangulate()
+            .map(|tr| na::Vector3::new(tr.x as u32, tr.y as u32, tr.z as u32))
+            .collect(),
+        pos: circle
+            .shared_vertex_iter()
+            .map(|v| na::Point3::from(na::Vector3::from(v.pos)))
+            .collect(),
+        normal: circle
+            .shared_vertex_iter()
+            .map(|v| na::Vector3::from(v.normal))
+            .collect(),
+        uv: vec![],
+        colors: vec![],
+    }
+}
+
+/// capped tube of `radius` around the z axis from z = 0 to 1, the caps only show in previews
+pub fn gen_cylinder(radius: f32) -> Mesh {
+    let transform = na::Matrix4::new_nonuniform_scaling(&na::Vector3::new(radius, radius, 0.5))
+        .append_translation(&na::Vector3::new(0.0, 0.0, 0.5));
+    let cylinder = genmesh::generators::Cylinder::new(32);
+    Mesh {
+        indices: cylinder
+            .indexed_polygon_iter()
+            .triangulate()
+            .map(|tr| na::Vector3::new(tr.x as u32, tr.y as u32, tr.z as u32))
+            .collect(),
+        pos: cylinder
+            .shared_vertex_iter()
+            .map(|v| transform.transform_point(&na::Point3::from(na::Vector3::from(v.pos))))
+            .collect(),
+        normal: cylinder
+            .shared_vertex_iter()
+            .map(|v| na::Vector3::from(v.normal))
+            .collect(),
+        uv: vec![],
+        colors: vec![],
     }
 }
 
@@ -75,15 +126,66 @@ pub fn gen_sphere(center: &na::Point3<f32>, radius: f32) -> Mesh {
             .map(|v| na::Vector3::from(v.normal))
             .collect(),
         uv: vec![],
+        colors: vec![],
+    }
+}
+
+/// loads the strands of a mitsuba hair file, either the ascii format with one vertex per line
+/// and blank lines between strands, or the binary format where a vertex with an infinite x
+/// coordinate starts a new strand
+pub fn load_hair(file_path: &std::path::Path) -> Vec<Vec<na::Point3<f32>>> {
+    let mut input = Vec::new();
+    {
+        let mut file = File::open(file_path).unwrap();
+        let _ = file.read_to_end(&mut input);
+    }
+
+    let mut strands = Vec::new();
+    let mut strand = Vec::new();
+    let header = b"BINARY_HAIR";
+    if input.starts_with(header) {
+        let data = &input[header.len()..];
+        let vertex_count = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+        let floats: Vec<f32> = data[4..]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        let mut i = 0;
+        let mut read = 0;
+        while read < vertex_count && i + 2 < floats.len() {
+            if floats[i].is_infinite() {
+                // the marker is followed by the first vertex of the next strand
+                strands.push(std::mem::take(&mut strand));
+                i += 1;
+                continue;
+            }
+            strand.push(na::Point3::new(floats[i], floats[i + 1], floats[i + 2]));
+            i += 3;
+            read += 1;
+        }
+    } else {
+        for line in String::from_utf8_lossy(&input).lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                strands.push(std::mem::take(&mut strand));
+                continue;
+            }
+            if line.starts_with('#') {
+                continue;
+            }
+            let coords: Vec<f32> = line
+                .split_whitespace()
+                .map(|v| v.parse::<f32>().unwrap())
+                .collect();
+            strand.push(na::Point3::new(coords[0], coords[1], coords[2]));
+        }
     }
+    strands.push(strand);
+    strands.retain(|strand| strand.len() > 1);
+    strands
 }
 
-pub fn load_obj(scene_path: &str, filename: &str) -> Mesh {
-    let file_path = std::path::Path::new(scene_path)
-        .parent()
-        .unwrap_or_else(|| std::path::Path::new(""))
-        .join(filename);
-    let file_path = file_path.to_str().unwrap();
+pub fn load_obj(file_path: &std::path::Path) -> Mesh {
     let mut input = String::new();
 
     // load the data directly into memory; no buffering nor streaming
@@ -147,9 +249,67 @@ pub fn load_obj(scene_path: &str, filename: &str) -> Mesh {
             .iter()
             .map(|t| na::Point2::new(t.u as f32, t.v as f32))
             .collect(),
+        colors: vec![],
     }
 }
 
+/// numbers of an attribute, separated by commas and/or whitespace
+fn parse_floats(value: &str) -> Vec<f32> {
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().unwrap())
+        .collect()
+}
+
+/// an rgb given as three values or as a single gray value. spectra given as `wavelength:value`
+/// pairs are reduced to the gray of their average value
+fn parse_rgb(value: &str) -> [f32; 3] {
+    if value.contains(':') {
+        let values: Vec<f32> = value
+            .split(',')
+            .filter_map(|sample| sample.split(':').nth(1))
+            .map(|v| v.trim().parse().unwrap())
+            .collect();
+        let average = values.iter().sum::<f32>() / values.len().max(1) as f32;
+        return [average; 3];
+    }
+    match parse_floats(value).as_slice() {
+        [gray] => [*gray; 3],
+        [r, g, b] => [*r, *g, *b],
+        _ => panic!("invalid rgb value {:?}", value),
+    }
+}
+
+/// replaces the `$name` references of mitsuba 2 scenes by the values of their `<default>`s
+pub(crate) fn substitute_defaults(xml: &str) -> String {
+    let mut defaults = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find("<default ") {
+        let end = rest[start..]
+            .find('>')
+            .map_or(rest.len(), |end| start + end);
+        let element = &rest[start..end];
+        let attribute = |name: &str| {
+            let start = element.find(&format!("{}=\"", name))? + name.len() + 2;
+            let end = element[start..].find('"')? + start;
+            Some(element[start..end].to_string())
+        };
+        if let (Some(name), Some(value)) = (attribute("name"), attribute("value")) {
+            defaults.push((name, value));
+        }
+        rest = &rest[end..];
+    }
+
+    // longer names first so `$spp` doesn't replace the start of `$spp_max`
+    defaults.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+    let mut xml = xml.to_string();
+    for (name, value) in defaults {
+        xml = xml.replace(&format!("${}", name), &value);
+    }
+    xml
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Float {
     pub name: String,
@@ -256,35 +416,127 @@ mod integer {
     }
 }
 
+/// one of the elements a `<transform>` is built from, all of them given by their attributes
 #[derive(Debug, Deserialize)]
-pub struct Matrix {
-    value: String,
+pub struct TransformOp {
+    value: Option<String>,
+    x: Option<String>,
+    y: Option<String>,
+    z: Option<String>,
+    angle: Option<String>,
+    origin: Option<String>,
+    target: Option<String>,
+    up: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Transform {
-    name: String,
-    matrix: Matrix,
+impl TransformOp {
+    /// the vector of `value` or of the `x`, `y` and `z` attributes, the missing ones taking
+    /// `default`
+    fn vector(&self, default: f32) -> na::Vector3<f32> {
+        if let Some(value) = &self.value {
+            match parse_floats(value).as_slice() {
+                [v] => return na::Vector3::new(*v, *v, *v),
+                [x, y, z] => return na::Vector3::new(*x, *y, *z),
+                _ => panic!("invalid transform value {:?}", value),
+            }
+        }
+        let component = |c: &Option<String>| c.as_ref().map_or(default, |c| c.parse().unwrap());
+        na::Vector3::new(component(&self.x), component(&self.y), component(&self.z))
+    }
+
+    fn point(value: &Option<String>) -> Option<na::Point3<f32>> {
+        let coords = parse_floats(value.as_ref()?);
+        Some(na::Point3::new(coords[0], coords[1], coords[2]))
+    }
+
+    fn matrix(&self, kind: &str) -> na::Matrix4<f32> {
+        match kind {
+            "matrix" => na::Matrix4::from_row_slice(&parse_floats(
+                self.value.as_ref().expect("matrix is missing its value"),
+            )),
+            "translate" => na::Matrix4::new_translation(&self.vector(0.0)),
+            "scale" => na::Matrix4::new_nonuniform_scaling(&self.vector(1.0)),
+            "rotate" => {
+                let angle: f32 = self.angle.as_ref().map_or(0.0, |a| a.parse().unwrap());
+                na::Matrix4::from_axis_angle(
+                    &na::Unit::new_normalize(self.vector(0.0)),
+                    angle.to_radians(),
+                )
+            }
+            // mitsuba's look at maps +z to the direction looked at and +y close to `up`
+            "lookat" => {
+                let origin = Self::point(&self.origin).expect("lookat is missing its origin");
+                let target = Self::point(&self.target).expect("lookat is missing its target");
+                let up = Self::point(&self.up).map_or(na::Vector3::y(), |up| up.coords);
+                let dir = (target - origin).normalize();
+                let left = up.cross(&dir).normalize();
+                let new_up = dir.cross(&left);
+                let mut matrix = na::Matrix4::identity();
+                matrix
+                    .fixed_slice_mut::<na::U3, na::U1>(0, 0)
+                    .copy_from(&left);
+                matrix
+                    .fixed_slice_mut::<na::U3, na::U1>(0, 1)
+                    .copy_from(&new_up);
+                matrix
+                    .fixed_slice_mut::<na::U3, na::U1>(0, 2)
+                    .copy_from(&dir);
+                matrix
+                    .fixed_slice_mut::<na::U3, na::U1>(0, 3)
+                    .copy_from(&origin.coords);
+                matrix
+            }
+            _ => unreachable!(),
+        }
+    }
 }
 
+fn identity() -> na::Projective3<f32> {
+    na::Projective3::identity()
+}
+
+/// a transform built from its child elements in order, each one applied after the ones before
+/// it like mitsuba does
 mod transform {
-    use super::Transform;
-    use serde::de::{Deserialize, Deserializer};
+    use super::TransformOp;
+    use serde::de::Deserializer;
 
     pub fn deserialize<'de, D>(deserializer: D) -> Result<na::Projective3<f32>, D::Error>
     where
         D: Deserializer<'de>,
     {
-        let transform = Transform::deserialize(deserializer)?;
-        let matrix: Vec<f32> = transform
-            .matrix
-            .value
-            .split(" ")
-            .map(|s| s.parse().unwrap())
-            .collect();
-        let mat = na::Matrix4::from_row_slice(&matrix);
-        let transform = na::Projective3::from_matrix_unchecked(mat);
-        Ok(transform)
+        struct ChildVisitor;
+        impl<'de> serde::de::Visitor<'de> for ChildVisitor {
+            type Value = na::Matrix4<f32>;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("Map of transform elements")
+            }
+
+            fn visit_map<M>(self, mut access: M) -> Result<Self::Value, M::Error>
+            where
+                M: serde::de::MapAccess<'de>,
+            {
+                let mut matrix = na::Matrix4::identity();
+
+                while let Some(key) = access.next_key::<String>()? {
+                    match key.as_str() {
+                        "matrix" | "translate" | "scale" | "rotate" | "lookat" => {
+                            let op = access.next_value::<TransformOp>()?;
+                            matrix = op.matrix(&key) * matrix;
+                        }
+                        _ => {
+                            access.next_value::<serde::de::IgnoredAny>()?;
+                        }
+                    }
+                }
+
+                Ok(matrix)
+            }
+        }
+
+        let matrix = deserializer.deserialize_any(ChildVisitor {})?;
+        Ok(na::Projective3::from_matrix_unchecked(matrix))
     }
 }
 
@@ -294,6 +546,38 @@ pub struct TwoSided {
     pub bsdf: Box<BSDF>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct BumpMap {
+    pub id: Option<String>,
+
+    #[serde(flatten, rename = "float", deserialize_with = "de_floats")]
+    pub float_params: HashMap<String, f32>,
+
+    pub texture: Texture,
+    pub bsdf: Box<BSDF>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Coating {
+    pub id: Option<String>,
+
+    #[serde(flatten, rename = "float", deserialize_with = "de_floats")]
+    pub float_params: HashMap<String, f32>,
+
+    pub bsdf: Box<BSDF>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Blend {
+    pub id: Option<String>,
+
+    #[serde(flatten, rename = "float", deserialize_with = "de_floats")]
+    pub float_params: HashMap<String, f32>,
+
+    pub texture: Option<Texture>,
+    pub bsdf: Vec<BSDF>,
+}
+
 fn default_rgb_one() -> [f32; 3] {
     [1.0, 1.0, 1.0]
 }
@@ -317,7 +601,9 @@ where
         type Value = HashMap<String, [f32; 3]>;
 
         fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-            formatter.write_str("Map of children elements - filtering for fields with `rgb` suffix")
+            formatter.write_str(
+                "Map of children elements - filtering for fields with `rgb` or `spectrum` suffix",
+            )
         }
 
         fn visit_map<M>(self, mut access: M) -> Result<Self::Value, M::Error>
@@ -327,11 +613,9 @@ where
             let mut hm = HashMap::<String, [f32; 3]>::new();
 
             while let Some(key) = access.next_key::<String>()? {
-                if key.ends_with("rgb") {
+                if key.ends_with("rgb") || key.ends_with("spectrum") {
                     let rgb = access.next_value::<Rgb>().unwrap();
-                    let color: Vec<f32> =
-                        rgb.value.split(", ").map(|s| s.parse().unwrap()).collect();
-                    hm.insert(rgb.name.to_snake_case(), [color[0], color[1], color[2]]);
+                    hm.insert(rgb.name.to_snake_case(), parse_rgb(&rgb.value));
                 }
             }
 
@@ -365,16 +649,32 @@ pub enum BSDF {
     TwoSided(TwoSided),
     #[serde(rename = "diffuse")]
     Diffuse(Diffuse),
+    #[serde(rename = "roughdiffuse")]
+    RoughDiffuse(Material),
     #[serde(rename = "conductor")]
     Conductor(Material),
     #[serde(rename = "roughconductor")]
     RoughConductor(Material),
     #[serde(rename = "dielectric")]
     Dielectric(Material),
+    #[serde(rename = "roughdielectric")]
+    RoughDielectric(Material),
     #[serde(rename = "plastic")]
     Plastic(Material),
     #[serde(rename = "roughplastic")]
     RoughPlastic(Material),
+    #[serde(rename = "bumpmap")]
+    BumpMap(BumpMap),
+    #[serde(rename = "coating")]
+    Coating(Coating),
+    #[serde(rename = "blendbsdf")]
+    Blend(Blend),
+    #[serde(rename = "hair")]
+    Hair(Material),
+    #[serde(rename = "principled")]
+    Principled(Material),
+    #[serde(rename = "thindielectric")]
+    ThinDielectric(Material),
 }
 
 #[macro_export]
@@ -407,11 +707,19 @@ mod bsdf {
                 map,
                 BSDF::TwoSided,
                 BSDF::Diffuse,
+                BSDF::RoughDiffuse,
                 BSDF::Conductor,
                 BSDF::RoughConductor,
                 BSDF::Dielectric,
+                BSDF::RoughDielectric,
                 BSDF::Plastic,
-                BSDF::RoughPlastic
+                BSDF::RoughPlastic,
+                BSDF::BumpMap,
+                BSDF::Coating,
+                BSDF::Blend,
+                BSDF::Hair,
+                BSDF::Principled,
+                BSDF::ThinDielectric
             )
         }
         Ok(map)
@@ -433,8 +741,7 @@ mod rgb {
         D: Deserializer<'de>,
     {
         let rgb = Rgb::deserialize(deserializer)?;
-        let color: Vec<f32> = rgb.value.split(", ").map(|s| s.parse().unwrap()).collect();
-        Ok([color[0], color[1], color[2]])
+        Ok(super::parse_rgb(&rgb.value))
     }
 }
 
@@ -443,13 +750,47 @@ mod rgb {
 pub enum Emitter {
     #[serde(rename = "area")]
     Area {
-        #[serde(with = "rgb")]
+        #[serde(alias = "spectrum", with = "rgb")]
+        rgb: [f32; 3],
+    },
+    /// point light at `point`, or at the origin of `transform` when it has no position
+    #[serde(rename = "point")]
+    Point {
+        #[serde(default = "identity", with = "transform")]
+        transform: na::Projective3<f32>,
+
+        point: Option<Point>,
+
+        #[serde(default = "default_rgb_one", alias = "spectrum", with = "rgb")]
+        rgb: [f32; 3],
+    },
+    #[serde(rename = "spot")]
+    Spot {
+        #[serde(default = "identity", with = "transform")]
+        transform: na::Projective3<f32>,
+
+        #[serde(default = "default_rgb_one", alias = "spectrum", with = "rgb")]
+        rgb: [f32; 3],
+    },
+    /// light arriving from infinitely far along `vector`, +z of `transform` without one
+    #[serde(rename = "directional")]
+    Directional {
+        #[serde(default = "identity", with = "transform")]
+        transform: na::Projective3<f32>,
+
+        vector: Option<Point>,
+
+        #[serde(default = "default_rgb_one", alias = "spectrum", with = "rgb")]
+        rgb: [f32; 3],
+    },
+    #[serde(rename = "constant")]
+    Constant {
+        #[serde(default = "default_rgb_one", alias = "spectrum", with = "rgb")]
         rgb: [f32; 3],
     },
-    Point,
     #[serde(rename = "envmap")]
     EnvMap {
-        #[serde(with = "transform")]
+        #[serde(default = "identity", with = "transform")]
         transform: na::Projective3<f32>,
 
         #[serde(rename = "string", with = "string")]
@@ -457,6 +798,8 @@ pub enum Emitter {
     },
     #[serde(rename = "sunsky")]
     SunSky,
+    #[serde(other)]
+    Unsupported,
 }
 
 #[derive(Debug, Deserialize)]
@@ -558,7 +901,7 @@ mod bool {
 pub enum Shape {
     #[serde(rename = "rectangle")]
     Rectangle {
-        #[serde(with = "transform")]
+        #[serde(default = "identity", with = "transform")]
         transform: na::Projective3<f32>,
 
         #[serde(rename = "ref")]
@@ -571,9 +914,38 @@ pub enum Shape {
     },
     #[serde(rename = "cube")]
     Cube {
-        #[serde(with = "transform")]
+        #[serde(default = "identity", with = "transform")]
+        transform: na::Projective3<f32>,
+
+        #[serde(rename = "ref")]
+        material: Option<Reference>,
+
+        #[serde(rename = "bsdf")]
+        bsdf: Option<BSDF>,
+
+        emitter: Option<Emitter>,
+    },
+    #[serde(rename = "disk")]
+    Disk {
+        #[serde(default = "identity", with = "transform")]
+        transform: na::Projective3<f32>,
+
+        #[serde(rename = "ref")]
+        material: Option<Reference>,
+
+        #[serde(rename = "bsdf")]
+        bsdf: Option<BSDF>,
+
+        emitter: Option<Emitter>,
+    },
+    #[serde(rename = "cylinder")]
+    Cylinder {
+        #[serde(default = "identity", with = "transform")]
         transform: na::Projective3<f32>,
 
+        #[serde(rename = "float")]
+        radius: Float,
+
         #[serde(rename = "ref")]
         material: Option<Reference>,
 
@@ -600,7 +972,7 @@ pub enum Shape {
     },
     #[serde(rename = "obj")]
     Obj {
-        #[serde(with = "transform")]
+        #[serde(default = "identity", with = "transform")]
         transform: na::Projective3<f32>,
         #[serde(rename = "boolean", default, with = "bool")]
         face_normals: bool,
@@ -616,14 +988,62 @@ pub enum Shape {
         #[serde(rename = "string", with = "string")]
         filename: String,
     },
+    #[serde(rename = "ply")]
+    Ply {
+        #[serde(default = "identity", with = "transform")]
+        transform: na::Projective3<f32>,
+        #[serde(rename = "boolean", default, with = "bool")]
+        face_normals: bool,
+
+        #[serde(rename = "ref")]
+        material: Option<Reference>,
+
+        #[serde(rename = "bsdf")]
+        bsdf: Option<BSDF>,
+
+        emitter: Option<Emitter>,
+
+        #[serde(rename = "string", with = "string")]
+        filename: String,
+    },
+    #[serde(rename = "hair")]
+    Hair {
+        #[serde(default = "identity", with = "transform")]
+        transform: na::Projective3<f32>,
+
+        #[serde(rename = "float")]
+        radius: Float,
+
+        #[serde(rename = "ref")]
+        material: Option<Reference>,
+
+        #[serde(rename = "bsdf")]
+        bsdf: Option<BSDF>,
+
+        #[serde(rename = "string", with = "string")]
+        filename: String,
+    },
+    /// shapes of other types, e.g. mitsuba's own serialized meshes, are skipped
+    #[serde(other)]
+    Unsupported,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 pub struct Film {
     #[serde(flatten, rename = "integer", deserialize_with = "de_integers")]
     pub integer_params: HashMap<String, i32>,
 }
 
+/// the integrator and sampler settings are only logged, the command line decides them
+#[derive(Debug, Deserialize)]
+pub struct Settings {
+    #[serde(rename = "type")]
+    pub kind: String,
+
+    #[serde(flatten, rename = "integer", deserialize_with = "de_integers")]
+    pub integer_params: HashMap<String, i32>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Sensor {
     #[serde(rename = "type")]
@@ -632,9 +1052,15 @@ pub struct Sensor {
     #[serde(flatten, rename = "float", deserialize_with = "de_floats")]
     pub float_params: HashMap<String, f32>,
 
-    #[serde(with = "transform")]
+    #[serde(flatten, rename = "string", deserialize_with = "de_strings")]
+    pub string_params: HashMap<String, String>,
+
+    pub sampler: Option<Settings>,
+
+    #[serde(default = "identity", with = "transform")]
     pub transform: na::Projective3<f32>,
 
+    #[serde(default)]
     pub film: Film,
 }
 
@@ -659,6 +1085,7 @@ pub enum Texture {
 #[derive(Debug, Deserialize)]
 pub struct Scene {
     pub version: String,
+    pub integrator: Option<Settings>,
     pub sensor: Sensor,
     #[serde(rename = "bsdf", with = "bsdf")]
     pub bsdfs: HashMap<String, BSDF>,
@@ -667,53 +1094,155 @@ pub struct Scene {
     #[serde(default, rename = "emitter")]
     pub emitters: Vec<Emitter>,
     #[serde(skip)]
-    pub path: String,
+    pub resolver: super::AssetResolver,
+    #[serde(skip)]
+    pub max_texture_size: Option<u32>,
+    #[serde(skip)]
+    pub texture_cache: std::sync::Arc<crate::pathtracer::texture_cache::TextureCache>,
 }
 
-fn get_camera(scene: &Scene, resolution: &na::Vector2<f32>) -> Camera {
+fn get_camera(log: &slog::Logger, scene: &Scene, resolution: &na::Vector2<f32>) -> Camera {
     let params = &scene.sensor.float_params;
-    let width = scene.sensor.film.integer_params["width"];
-    let height = scene.sensor.film.integer_params["height"];
-    let fov = params["fov"].to_radians();
+    let film = &scene.sensor.film.integer_params;
+    // mitsuba's default film is 768x576
+    let width = *film.get("width").unwrap_or(&768) as f32;
+    let height = *film.get("height").unwrap_or(&576) as f32;
     // right to left hand coordinate conversion
     // FIXME: should probably figure out what's wrong with the overall transformation
     let rotation = na::Rotation3::new(na::Vector3::new(0.0, -std::f32::consts::PI, 0.0));
     // dunno why I need to do this, but sometimes convert to isometry fails even when scaling is 1.0
     let mut sim_cam_to_world: na::Similarity3<f32> =
-        na::try_convert(scene.sensor.transform * rotation).unwrap();
+        na::try_convert(scene.sensor.transform * rotation).unwrap_or_else(|| {
+            warn!(
+                log,
+                "sensor transform isn't a similarity (mirrored or sheared), ignoring it"
+            );
+            na::Similarity3::identity()
+        });
+    // orthographic sensors see the [-1, 1] square along the film's width, scaled by their transform
+    let scaling = sim_cam_to_world.scaling();
     if !(sim_cam_to_world.scaling() == 1.0) {
         sim_cam_to_world.set_scaling(1.0);
     }
     let cam_to_world = sim_cam_to_world.isometry;
-    Camera::new(
-        &cam_to_world,
-        &na::Perspective3::new(
-            resolution.x / resolution.y,
-            fov * (height as f32 / width as f32),
-            0.01,
-            10000.0,
-        ),
-        &resolution,
-    )
+
+    // the field of view spans the film's width unless `fovAxis` picks another of its extents
+    let fov = params.get("fov").unwrap_or(&90.0).to_radians();
+    let fov_from = |extent: f32| 2.0 * ((fov / 2.0).tan() * height / extent).atan();
+    let fov_axis = scene.sensor.string_params.get("fov_axis");
+    let fovy = match fov_axis.map_or("x", String::as_str) {
+        "x" => fov_from(width),
+        "y" => fov,
+        "diagonal" => fov_from(width.hypot(height)),
+        "smaller" => fov_from(width.min(height)),
+        "larger" => fov_from(width.max(height)),
+        axis => {
+            warn!(log, "unsupported fov axis {:?}, using x", axis);
+            fov_from(width)
+        }
+    };
+
+    let aspect = resolution.x / resolution.y;
+    let projection = match scene.sensor.kind.as_str() {
+        "perspective" | "thinlens" => {
+            Projection::Perspective(na::Perspective3::new(aspect, fovy, 0.01, 10000.0))
+        }
+        "orthographic" => {
+            Projection::orthographic(aspect, 2.0 * scaling * height / width, 0.01, 10000.0)
+        }
+        kind => {
+            warn!(log, "unsupported sensor {:?}, using perspective", kind);
+            Projection::Perspective(na::Perspective3::new(aspect, fovy, 0.01, 10000.0))
+        }
+    };
+    let camera = Camera::new(&cam_to_world, &projection, &resolution);
+    if scene.sensor.kind == "thinlens" {
+        camera.with_lens(
+            *params.get("aperture_radius").unwrap_or(&0.03),
+            *params.get("focus_distance").unwrap_or(&1.0),
+        )
+    } else {
+        camera
+    }
+}
+
+/// the scene's film and sampling settings aren't applied, the command line decides them, but
+/// they're logged along with the flags that match them
+fn log_settings(log: &slog::Logger, scene: &Scene) {
+    let film = &scene.sensor.film.integer_params;
+    if let (Some(width), Some(height)) = (film.get("width"), film.get("height")) {
+        info!(
+            log,
+            "mitsuba film is {}x{}, render with --resolution {}x{} to match",
+            width,
+            height,
+            width,
+            height
+        );
+    }
+    let samples = scene
+        .sensor
+        .sampler
+        .as_ref()
+        .and_then(|sampler| sampler.integer_params.get("sample_count"));
+    if let Some(samples) = samples {
+        info!(
+            log,
+            "mitsuba sampler takes {} samples, render with --samples {} to match", samples, samples
+        );
+    }
+    let max_depth = scene
+        .integrator
+        .as_ref()
+        .and_then(|integrator| integrator.integer_params.get("max_depth"));
+    // mitsuba's -1 is unbounded
+    if let Some(max_depth) = max_depth.filter(|max_depth| **max_depth >= 0) {
+        info!(
+            log,
+            "mitsuba integrator has a depth of {}, render with --max_depth {} to match",
+            max_depth,
+            max_depth
+        );
+    }
 }
 
 pub fn from_mitsuba(
     log: &slog::Logger,
-    path: &str,
+    resolver: super::AssetResolver,
+    material_library: Option<&super::library::MaterialLibrary>,
     resolution: &na::Vector2<f32>,
+    max_texture_size: Option<u32>,
 ) -> (
     Camera,
     crate::pathtracer::RenderScene,
     crate::viewer::renderer::ViewerScene,
 ) {
-    let file = File::open(path).unwrap();
-    let file = BufReader::new(file);
-
-    let mut scene: Scene = from_reader(file).unwrap();
-    scene.path = String::from(path);
-
-    let camera = get_camera(&scene, &resolution);
-    let render_scene = crate::pathtracer::RenderScene::from_mitsuba(&log, &scene);
+    let mut xml = String::new();
+    File::open(resolver.scene_path())
+        .unwrap()
+        .read_to_string(&mut xml)
+        .unwrap();
+
+    let mut scene: Scene = from_str(&substitute_defaults(&xml)).unwrap();
+    scene.resolver = resolver;
+    scene.max_texture_size = max_texture_size;
+    scene.texture_cache = std::sync::Arc::new(crate::pathtracer::texture_cache::TextureCache::new(
+        &log,
+        crate::pathtracer::texture_cache::DEFAULT_BUDGET,
+    ));
+
+    log_settings(&log, &scene);
+
+    let camera = get_camera(&log, &scene, &resolution);
+    let bvh_cache = BVHCache::load(&log, &scene.resolver.cache_path(BVH_CACHE_EXTENSION));
+    let render_scene = crate::pathtracer::RenderScene::from_mitsuba(
+        &log,
+        &scene,
+        material_library,
+        &camera,
+        &bvh_cache,
+    );
+    bvh_cache.save();
     let viewer_scene = crate::viewer::renderer::ViewerScene::from_mitsuba(&scene);
 
     (camera, render_scene, viewer_scene)
@@ -1,25 +1,133 @@
-use super::Camera;
+use super::{Camera, Shutter};
 
+pub mod description;
 pub mod gltf;
+pub mod library;
 pub mod mitsuba;
+pub mod pbrt;
+pub mod ply;
+pub mod resolver;
+pub mod validate;
 
+pub use resolver::AssetResolver;
+
+/// extension of the bvh caches kept next to scenes
+const BVH_CACHE_EXTENSION: &str = "bvhcache";
+
+/// imports a scene file or a zip archive containing one, `search_paths` are additional
+/// directories to look for the scene and its assets in, materials defined in `material_library`
+/// replace the scene's materials of the same name. `variant` selects a named material variant of
+/// gltf scenes using `KHR_materials_variants` and `camera` the gltf camera to render from, by
+/// name or index. `shutter` poses animated gltf scenes at the times the shutter opens and
+/// closes, blurring whatever moves in between. scene descriptions import the scene they
+/// reference or place, see `description`, and a lone ply mesh is a scene of its own
 pub fn import(
     log: &slog::Logger,
     path: &str,
+    search_paths: &[std::path::PathBuf],
+    material_library: Option<&library::MaterialLibrary>,
     resolution: &na::Vector2<f32>,
     default_lights: bool,
+    max_texture_size: Option<u32>,
+    variant: Option<&str>,
+    camera: Option<&str>,
+    shutter: Option<Shutter>,
 ) -> (
     Camera,
     crate::pathtracer::RenderScene,
     crate::viewer::renderer::ViewerScene,
 ) {
-    let ext = std::path::Path::new(path).extension().unwrap();
+    let resolver = AssetResolver::new(&log, path, search_paths).unwrap();
+    let ext = resolver.scene_path().extension().unwrap();
 
     if ext == "gltf" || ext == "glb" {
-        gltf::from_gltf(&log, &path, &resolution, default_lights)
-    } else if ext == "xml" {
-        mitsuba::from_mitsuba(&log, &path, &resolution)
+        gltf::from_gltf(
+            &log,
+            &resolver,
+            material_library,
+            &resolution,
+            default_lights,
+            max_texture_size,
+            variant,
+            camera,
+            shutter,
+        )
+    } else if ext == "xml" || ext == "pbrt" || ext == "ply" {
+        if let Some(variant) = variant {
+            warn!(
+                log,
+                "ignoring material variant {:?}, only gltf scenes have variants", variant
+            );
+        }
+        if let Some(camera) = camera {
+            warn!(
+                log,
+                "ignoring camera {:?}, only gltf scenes can pick their camera", camera
+            );
+        }
+        if shutter.is_some() {
+            warn!(log, "ignoring shutter, only gltf scenes are animated");
+        }
+        if ext == "pbrt" {
+            pbrt::from_pbrt(
+                &log,
+                resolver,
+                material_library,
+                &resolution,
+                max_texture_size,
+            )
+        } else if ext == "ply" {
+            ply::from_ply(&log, &resolver, &resolution, default_lights)
+        } else {
+            mitsuba::from_mitsuba(
+                &log,
+                resolver,
+                material_library,
+                &resolution,
+                max_texture_size,
+            )
+        }
+    } else if description::is_description(resolver.scene_path()) {
+        description::from_description(
+            &log,
+            &resolver,
+            search_paths,
+            material_library,
+            &resolution,
+            default_lights,
+            max_texture_size,
+            variant,
+            camera,
+            shutter,
+        )
     } else {
         panic!("unsupported format!");
     }
 }
+
+/// shrinks images whose largest dimension exceeds `max_texture_size`, preserving aspect ratio
+pub fn downsample_image(
+    log: &slog::Logger,
+    image: image::DynamicImage,
+    max_texture_size: Option<u32>,
+) -> image::DynamicImage {
+    use image::GenericImageView;
+
+    if let Some(max_size) = max_texture_size {
+        let (width, height) = image.dimensions();
+        if width > max_size || height > max_size {
+            let image = image.resize(max_size, max_size, image::imageops::FilterType::Lanczos3);
+            debug!(
+                log,
+                "downsampled texture from {}x{} to {}x{}",
+                width,
+                height,
+                image.width(),
+                image.height()
+            );
+            return image;
+        }
+    }
+
+    image
+}
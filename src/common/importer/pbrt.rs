@@ -0,0 +1,1342 @@
+//! scenes in the pbrt-v3 and pbrt-v4 scene description formats. the directives are parsed into
+//! descriptions of the camera, textures, materials, shapes and lights they declare, with the
+//! graphics state resolved so every shape and light carries its own transform and material
+
+use super::mitsuba::{self, Mesh};
+use super::ply;
+use super::BVH_CACHE_EXTENSION;
+use crate::common::{Camera, Projection, DEFAULT_Z_FAR, DEFAULT_Z_NEAR};
+use crate::pathtracer::accelerator::BVHCache;
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// quoted string, without the quotes
+    Str(String),
+    /// directive names, numbers and bare booleans
+    Word(String),
+    Open,
+    Close,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '#' => {
+                while let Some((_, c)) = chars.next() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '[' => tokens.push(Token::Open),
+            ']' => tokens.push(Token::Close),
+            '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, 'n')) => string.push('\n'),
+                            Some((_, 't')) => string.push('\t'),
+                            Some((_, c)) => string.push(c),
+                            None => break,
+                        },
+                        Some((_, c)) => string.push(c),
+                        None => return Err(anyhow!("unterminated string at byte {}", start)),
+                    }
+                }
+                tokens.push(Token::Str(string));
+            }
+            c if c.is_whitespace() => {}
+            _ => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(i, c)) = chars.peek() {
+                    if c.is_whitespace() || c == '[' || c == ']' || c == '"' || c == '#' {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                tokens.push(Token::Word(source[start..end].to_string()));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+pub enum ParamValue {
+    Numbers(Vec<f32>),
+    Integers(Vec<i32>),
+    Strings(Vec<String>),
+    Bools(Vec<bool>),
+}
+
+/// a `"type name" value` pair of a directive's parameter list
+#[derive(Debug, Clone)]
+pub struct Param {
+    pub kind: String,
+    pub name: String,
+    pub value: ParamValue,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ParamSet {
+    pub params: Vec<Param>,
+}
+
+/// wavelengths in nanometers the r, g and b channels are taken at when reducing spectra to rgb
+const RGB_WAVELENGTHS: [f32; 3] = [610.0, 550.0, 465.0];
+
+/// color of a blackbody at `temperature` kelvin, normalized so its largest channel is one
+fn blackbody_rgb(temperature: f32) -> [f32; 3] {
+    if temperature <= 0.0 {
+        return [0.0; 3];
+    }
+    let planck = |lambda: f32| {
+        let c = 299_792_458.0f64;
+        let h = 6.626_069_57e-34f64;
+        let kb = 1.380_648_8e-23f64;
+        let l = lambda as f64 * 1e-9;
+        (2.0 * h * c * c) / (l.powi(5) * ((h * c / (l * kb * temperature as f64)).exp() - 1.0))
+    };
+    let le = RGB_WAVELENGTHS.map(planck);
+    let max = le.iter().cloned().fold(0.0, f64::max);
+    le.map(|le| (le / max) as f32)
+}
+
+/// rgb of a spectrum given as wavelength and value pairs, interpolated linearly between them
+fn sampled_rgb(samples: &[f32]) -> [f32; 3] {
+    let samples: Vec<_> = samples.chunks_exact(2).map(|s| (s[0], s[1])).collect();
+    RGB_WAVELENGTHS.map(
+        |lambda| match samples.iter().position(|(l, _)| *l >= lambda) {
+            Some(0) => samples[0].1,
+            Some(i) => {
+                let (l0, v0) = samples[i - 1];
+                let (l1, v1) = samples[i];
+                v0 + (v1 - v0) * (lambda - l0) / (l1 - l0)
+            }
+            None => samples.last().map_or(0.0, |(_, v)| *v),
+        },
+    )
+}
+
+impl ParamSet {
+    fn get(&self, name: &str) -> Option<&Param> {
+        self.params.iter().find(|param| param.name == name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn floats(&self, name: &str) -> Option<&[f32]> {
+        match &self.get(name)?.value {
+            ParamValue::Numbers(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    /// the first value of a float parameter, spectra and points aren't taken
+    pub fn float(&self, name: &str) -> Option<f32> {
+        let param = self.get(name)?;
+        match &param.value {
+            ParamValue::Numbers(values) if param.kind == "float" => values.first().cloned(),
+            _ => None,
+        }
+    }
+
+    pub fn integers(&self, name: &str) -> Option<&[i32]> {
+        match &self.get(name)?.value {
+            ParamValue::Integers(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn integer(&self, name: &str) -> Option<i32> {
+        self.integers(name)?.first().cloned()
+    }
+
+    fn strings_of_kind(&self, name: &str, kind: &str) -> Option<&[String]> {
+        let param = self.get(name)?;
+        match &param.value {
+            ParamValue::Strings(values) if param.kind == kind => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn strings(&self, name: &str) -> Option<&[String]> {
+        self.strings_of_kind(name, "string")
+    }
+
+    pub fn string(&self, name: &str) -> Option<&str> {
+        self.strings(name)?.first().map(String::as_str)
+    }
+
+    /// name of the texture bound to the parameter
+    pub fn texture(&self, name: &str) -> Option<&str> {
+        self.strings_of_kind(name, "texture")?
+            .first()
+            .map(String::as_str)
+    }
+
+    /// name of a spectrum built into pbrt, e.g. `metal-Cu-eta`, or of a spectrum file
+    pub fn named_spectrum(&self, name: &str) -> Option<&str> {
+        self.strings_of_kind(name, "spectrum")?
+            .first()
+            .map(String::as_str)
+    }
+
+    pub fn bool(&self, name: &str) -> Option<bool> {
+        match &self.get(name)?.value {
+            ParamValue::Bools(values) => values.first().cloned(),
+            _ => None,
+        }
+    }
+
+    pub fn points(&self, name: &str) -> Option<Vec<na::Point3<f32>>> {
+        Some(
+            self.floats(name)?
+                .chunks_exact(3)
+                .map(|p| na::Point3::new(p[0], p[1], p[2]))
+                .collect(),
+        )
+    }
+
+    pub fn point(&self, name: &str) -> Option<na::Point3<f32>> {
+        self.points(name)?.first().cloned()
+    }
+
+    pub fn vectors(&self, name: &str) -> Option<Vec<na::Vector3<f32>>> {
+        Some(
+            self.floats(name)?
+                .chunks_exact(3)
+                .map(|v| na::Vector3::new(v[0], v[1], v[2]))
+                .collect(),
+        )
+    }
+
+    pub fn point2s(&self, name: &str) -> Option<Vec<na::Point2<f32>>> {
+        Some(
+            self.floats(name)?
+                .chunks_exact(2)
+                .map(|p| na::Point2::new(p[0], p[1]))
+                .collect(),
+        )
+    }
+
+    /// color of an rgb, blackbody or sampled spectrum parameter, scalars are taken as grays
+    pub fn rgb(&self, name: &str) -> Option<[f32; 3]> {
+        let param = self.get(name)?;
+        match (param.kind.as_str(), &param.value) {
+            ("rgb", ParamValue::Numbers(v)) | ("color", ParamValue::Numbers(v)) if v.len() >= 3 => {
+                Some([v[0], v[1], v[2]])
+            }
+            // pbrt-v3 scales the normalized blackbody by a second value
+            ("blackbody", ParamValue::Numbers(v)) if !v.is_empty() => {
+                let scale = v.get(1).cloned().unwrap_or(1.0);
+                Some(blackbody_rgb(v[0]).map(|c| scale * c))
+            }
+            ("spectrum", ParamValue::Numbers(v)) if v.len() >= 2 => Some(sampled_rgb(v)),
+            ("float", ParamValue::Numbers(v)) if !v.is_empty() => Some([v[0]; 3]),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Texture {
+    pub class: String,
+    pub params: ParamSet,
+}
+
+#[derive(Debug, Clone)]
+pub struct Material {
+    /// name given by `MakeNamedMaterial`
+    pub name: Option<String>,
+    pub kind: String,
+    pub params: ParamSet,
+}
+
+#[derive(Debug, Clone)]
+pub struct Shape {
+    pub kind: String,
+    pub params: Arc<ParamSet>,
+    pub obj_to_world: na::Projective3<f32>,
+    pub reverse_orientation: bool,
+    /// index into the scene's materials
+    pub material: usize,
+    /// parameters of the diffuse area light the shape emits with
+    pub area_light: Option<Arc<ParamSet>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Light {
+    pub kind: String,
+    pub params: ParamSet,
+    pub light_to_world: na::Projective3<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Directive {
+    pub kind: String,
+    pub params: ParamSet,
+}
+
+#[derive(Debug, Clone)]
+pub struct Scene {
+    pub camera: Directive,
+    /// camera to world of pbrt's camera, which looks down +z
+    pub camera_to_world: na::Matrix4<f32>,
+    pub film: Directive,
+    pub sampler: Option<Directive>,
+    pub integrator: Option<Directive>,
+    pub float_textures: HashMap<String, Texture>,
+    pub spectrum_textures: HashMap<String, Texture>,
+    /// the first material is the default of shapes declared before any other
+    pub materials: Vec<Material>,
+    pub named_materials: HashMap<String, usize>,
+    pub shapes: Vec<Shape>,
+    pub lights: Vec<Light>,
+    /// mirrors the world when it's seen through a camera that doesn't, see `world_flip`
+    pub world_flip: na::Matrix4<f32>,
+    pub resolver: super::AssetResolver,
+    pub max_texture_size: Option<u32>,
+    pub texture_cache: std::sync::Arc<crate::pathtracer::texture_cache::TextureCache>,
+}
+
+impl Scene {
+    pub fn material_index(&self, name: &str) -> Option<usize> {
+        self.named_materials.get(name).cloned()
+    }
+}
+
+/// pbrt's camera looks down +z with +x to the right of the image, which makes its camera space
+/// left handed while this renderer's camera looks down -z in a right handed one. no rotation
+/// turns one into the other, so the world is mirrored along z instead, unless the scene's
+/// camera transform already mirrors it, e.g. by the `Scale -1 1 1` many scenes start with
+fn world_flip(camera_to_world: &na::Matrix4<f32>) -> na::Matrix4<f32> {
+    if camera_to_world
+        .fixed_slice::<na::U3, na::U3>(0, 0)
+        .determinant()
+        < 0.0
+    {
+        na::Matrix4::identity()
+    } else {
+        na::Matrix4::new_nonuniform_scaling(&na::Vector3::new(1.0, 1.0, -1.0))
+    }
+}
+
+/// world to camera of a camera at `pos` looking at `look`, as pbrt's `LookAt`
+fn look_at(
+    pos: &na::Point3<f32>,
+    look: &na::Point3<f32>,
+    up: &na::Vector3<f32>,
+) -> na::Matrix4<f32> {
+    let dir = (look - pos).normalize();
+    let right = up.normalize().cross(&dir);
+    if right.norm_squared() == 0.0 {
+        return na::Matrix4::identity();
+    }
+    let right = right.normalize();
+    let new_up = dir.cross(&right);
+    let camera_to_world = na::Matrix4::from_columns(&[
+        right.to_homogeneous(),
+        new_up.to_homogeneous(),
+        dir.to_homogeneous(),
+        pos.to_homogeneous(),
+    ]);
+    camera_to_world
+        .try_inverse()
+        .unwrap_or_else(na::Matrix4::identity)
+}
+
+struct Tokens {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Tokens {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn string(&mut self, directive: &str) -> Result<String> {
+        match self.next() {
+            Some(Token::Str(string)) => Ok(string),
+            token => Err(anyhow!("{} expects a string, found {:?}", directive, token)),
+        }
+    }
+
+    fn word_number(word: &str) -> Result<f32> {
+        word.parse::<f32>()
+            .with_context(|| format!("parsing number {:?}", word))
+    }
+
+    /// `n` numbers, bare or in brackets
+    fn numbers(&mut self, directive: &str, n: usize) -> Result<Vec<f32>> {
+        let mut numbers = Vec::with_capacity(n);
+        if self.peek() == Some(&Token::Open) {
+            self.next();
+            loop {
+                match self.next() {
+                    Some(Token::Close) => break,
+                    Some(Token::Word(word)) => numbers.push(Self::word_number(&word)?),
+                    token => {
+                        return Err(anyhow!("{} expects numbers, found {:?}", directive, token))
+                    }
+                }
+            }
+        } else {
+            for _ in 0..n {
+                match self.next() {
+                    Some(Token::Word(word)) => numbers.push(Self::word_number(&word)?),
+                    token => {
+                        return Err(anyhow!("{} expects numbers, found {:?}", directive, token))
+                    }
+                }
+            }
+        }
+        if numbers.len() != n {
+            return Err(anyhow!(
+                "{} expects {} numbers, found {}",
+                directive,
+                n,
+                numbers.len()
+            ));
+        }
+
+        Ok(numbers)
+    }
+
+    /// the parameter list following a directive, each parameter starts with a string naming
+    /// its type and name
+    fn params(&mut self) -> Result<ParamSet> {
+        let mut params = ParamSet::default();
+        while let Some(Token::Str(declaration)) = self.peek() {
+            let mut parts = declaration.split_whitespace();
+            let (kind, name) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(kind), Some(name), None) => (kind.to_string(), name.to_string()),
+                _ => break,
+            };
+            self.next();
+
+            let mut values = Vec::new();
+            match self.next() {
+                Some(Token::Open) => loop {
+                    match self.next() {
+                        Some(Token::Close) => break,
+                        Some(token) => values.push(token),
+                        None => return Err(anyhow!("unterminated values of {:?}", name)),
+                    }
+                },
+                Some(token) => values.push(token),
+                None => return Err(anyhow!("missing value of {:?}", name)),
+            }
+
+            let value = match kind.as_str() {
+                "integer" => ParamValue::Integers(
+                    values
+                        .iter()
+                        .map(|value| match value {
+                            Token::Word(word) => word
+                                .parse::<i32>()
+                                .with_context(|| format!("parsing integer {:?}", word)),
+                            token => Err(anyhow!("{:?} expects integers, found {:?}", name, token)),
+                        })
+                        .collect::<Result<_>>()?,
+                ),
+                "bool" => ParamValue::Bools(
+                    values
+                        .iter()
+                        .map(|value| match value {
+                            Token::Word(word) | Token::Str(word) => Ok(word == "true"),
+                            token => Err(anyhow!("{:?} expects booleans, found {:?}", name, token)),
+                        })
+                        .collect::<Result<_>>()?,
+                ),
+                _ if values.iter().all(|value| matches!(value, Token::Str(_))) => {
+                    ParamValue::Strings(
+                        values
+                            .into_iter()
+                            .filter_map(|value| match value {
+                                Token::Str(string) => Some(string),
+                                _ => None,
+                            })
+                            .collect(),
+                    )
+                }
+                _ => ParamValue::Numbers(
+                    values
+                        .iter()
+                        .map(|value| match value {
+                            Token::Word(word) => Self::word_number(word),
+                            token => Err(anyhow!("{:?} expects numbers, found {:?}", name, token)),
+                        })
+                        .collect::<Result<_>>()?,
+                ),
+            };
+            params.params.push(Param {
+                kind: match kind.as_str() {
+                    "point" => "point3".to_string(),
+                    "vector" => "vector3".to_string(),
+                    "normal" => "normal3".to_string(),
+                    _ => kind,
+                },
+                name,
+                value,
+            });
+        }
+
+        Ok(params)
+    }
+}
+
+#[derive(Clone)]
+struct GraphicsState {
+    ctm: na::Matrix4<f32>,
+    reverse_orientation: bool,
+    material: usize,
+    area_light: Option<Arc<ParamSet>>,
+}
+
+struct Parser<'a> {
+    log: &'a slog::Logger,
+    resolver: &'a super::AssetResolver,
+    state: GraphicsState,
+    /// saved states, flagged when only the transform is restored
+    stack: Vec<(GraphicsState, bool)>,
+    /// transforms after `ActiveTransform EndTime` only move the shutter close, which isn't
+    /// supported, so they're skipped
+    end_time_only: bool,
+    coordinate_systems: HashMap<String, na::Matrix4<f32>>,
+    objects: HashMap<String, Vec<Shape>>,
+    current_object: Option<(String, Vec<Shape>)>,
+    camera: Directive,
+    camera_to_world: na::Matrix4<f32>,
+    film: Directive,
+    sampler: Option<Directive>,
+    integrator: Option<Directive>,
+    float_textures: HashMap<String, Texture>,
+    spectrum_textures: HashMap<String, Texture>,
+    materials: Vec<Material>,
+    named_materials: HashMap<String, usize>,
+    shapes: Vec<Shape>,
+    lights: Vec<Light>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(log: &'a slog::Logger, resolver: &'a super::AssetResolver) -> Self {
+        Self {
+            log,
+            resolver,
+            state: GraphicsState {
+                ctm: na::Matrix4::identity(),
+                reverse_orientation: false,
+                material: 0,
+                area_light: None,
+            },
+            stack: vec![],
+            end_time_only: false,
+            coordinate_systems: HashMap::new(),
+            objects: HashMap::new(),
+            current_object: None,
+            camera: Directive {
+                kind: "perspective".to_string(),
+                params: ParamSet::default(),
+            },
+            camera_to_world: na::Matrix4::identity(),
+            film: Directive {
+                kind: "rgb".to_string(),
+                params: ParamSet::default(),
+            },
+            sampler: None,
+            integrator: None,
+            float_textures: HashMap::new(),
+            spectrum_textures: HashMap::new(),
+            // pbrt's default material is a gray diffuse one
+            materials: vec![Material {
+                name: None,
+                kind: "diffuse".to_string(),
+                params: ParamSet::default(),
+            }],
+            named_materials: HashMap::new(),
+            shapes: vec![],
+            lights: vec![],
+        }
+    }
+
+    fn transform(&mut self, transform: na::Matrix4<f32>) {
+        if !self.end_time_only {
+            self.state.ctm *= transform;
+        }
+    }
+
+    fn push(&mut self, transform_only: bool) {
+        self.stack.push((self.state.clone(), transform_only));
+    }
+
+    fn pop(&mut self, directive: &str) {
+        match self.stack.pop() {
+            Some((state, true)) => self.state.ctm = state.ctm,
+            Some((state, false)) => self.state = state,
+            None => warn!(self.log, "unmatched {}", directive),
+        }
+    }
+
+    fn add_shape(&mut self, kind: String, params: ParamSet) {
+        let shape = Shape {
+            kind,
+            params: Arc::new(params),
+            obj_to_world: na::Projective3::from_matrix_unchecked(self.state.ctm),
+            reverse_orientation: self.state.reverse_orientation,
+            material: self.state.material,
+            area_light: self.state.area_light.clone(),
+        };
+        match &mut self.current_object {
+            Some((_, shapes)) => shapes.push(shape),
+            None => self.shapes.push(shape),
+        }
+    }
+
+    fn add_material(&mut self, name: Option<String>, kind: String, params: ParamSet) -> usize {
+        self.materials.push(Material { name, kind, params });
+        self.materials.len() - 1
+    }
+
+    fn parse_file(&mut self, path: &std::path::Path) -> Result<()> {
+        let source =
+            std::fs::read_to_string(path).with_context(|| format!("reading {:?}", path))?;
+        debug!(self.log, "parsing pbrt file {:?}", path);
+        self.parse(&source)
+            .with_context(|| format!("parsing {:?}", path))
+    }
+
+    fn parse(&mut self, source: &str) -> Result<()> {
+        let mut tokens = Tokens {
+            tokens: tokenize(source)?,
+            pos: 0,
+        };
+
+        while let Some(token) = tokens.next() {
+            let directive = match token {
+                Token::Word(directive) => directive,
+                token => {
+                    warn!(self.log, "skipping unexpected {:?}", token);
+                    continue;
+                }
+            };
+            let directive = directive.as_str();
+
+            match directive {
+                "Identity" => self.state.ctm = na::Matrix4::identity(),
+                "Translate" => {
+                    let v = tokens.numbers(directive, 3)?;
+                    self.transform(na::Matrix4::new_translation(&na::Vector3::new(
+                        v[0], v[1], v[2],
+                    )));
+                }
+                "Scale" => {
+                    let v = tokens.numbers(directive, 3)?;
+                    self.transform(na::Matrix4::new_nonuniform_scaling(&na::Vector3::new(
+                        v[0], v[1], v[2],
+                    )));
+                }
+                "Rotate" => {
+                    let v = tokens.numbers(directive, 4)?;
+                    let axis = na::Vector3::new(v[1], v[2], v[3]);
+                    if axis.norm_squared() > 0.0 {
+                        self.transform(na::Matrix4::from_axis_angle(
+                            &na::Unit::new_normalize(axis),
+                            v[0].to_radians(),
+                        ));
+                    }
+                }
+                "LookAt" => {
+                    let v = tokens.numbers(directive, 9)?;
+                    self.transform(look_at(
+                        &na::Point3::new(v[0], v[1], v[2]),
+                        &na::Point3::new(v[3], v[4], v[5]),
+                        &na::Vector3::new(v[6], v[7], v[8]),
+                    ));
+                }
+                // the matrices are given column by column
+                "Transform" => {
+                    let v = tokens.numbers(directive, 16)?;
+                    if !self.end_time_only {
+                        self.state.ctm = na::Matrix4::from_column_slice(&v);
+                    }
+                }
+                "ConcatTransform" => {
+                    let v = tokens.numbers(directive, 16)?;
+                    self.transform(na::Matrix4::from_column_slice(&v));
+                }
+                "CoordinateSystem" => {
+                    let name = tokens.string(directive)?;
+                    self.coordinate_systems.insert(name, self.state.ctm);
+                }
+                "CoordSysTransform" => {
+                    let name = tokens.string(directive)?;
+                    match self.coordinate_systems.get(&name) {
+                        Some(ctm) => self.state.ctm = *ctm,
+                        None => warn!(self.log, "unknown coordinate system {:?}", name),
+                    }
+                }
+                "TransformTimes" => {
+                    tokens.numbers(directive, 2)?;
+                }
+                "ActiveTransform" => match tokens.next() {
+                    Some(Token::Word(time)) => {
+                        if time == "EndTime" {
+                            warn!(
+                                self.log,
+                                "ignoring transforms of the shutter close, pbrt scenes aren't motion blurred"
+                            );
+                        }
+                        self.end_time_only = time == "EndTime";
+                    }
+                    token => {
+                        return Err(anyhow!("ActiveTransform expects a time, found {:?}", token))
+                    }
+                },
+                "ReverseOrientation" => {
+                    self.state.reverse_orientation = !self.state.reverse_orientation
+                }
+                "Option" => {
+                    tokens.params()?;
+                }
+                "ColorSpace" => {
+                    let name = tokens.string(directive)?;
+                    if name != "srgb" {
+                        warn!(
+                            self.log,
+                            "ignoring color space {:?}, colors are taken as linear srgb", name
+                        );
+                    }
+                }
+                "Camera" => {
+                    let kind = tokens.string(directive)?;
+                    let params = tokens.params()?;
+                    self.camera_to_world = self
+                        .state
+                        .ctm
+                        .try_inverse()
+                        .unwrap_or_else(na::Matrix4::identity);
+                    self.coordinate_systems
+                        .insert("camera".to_string(), self.camera_to_world);
+                    self.camera = Directive { kind, params };
+                }
+                "Film" | "Sampler" | "Integrator" | "PixelFilter" | "Accelerator" => {
+                    let kind = tokens.string(directive)?;
+                    let params = tokens.params()?;
+                    let settings = Some(Directive { kind, params });
+                    match directive {
+                        "Film" => self.film = settings.unwrap(),
+                        "Sampler" => self.sampler = settings,
+                        "Integrator" => self.integrator = settings,
+                        _ => {}
+                    }
+                }
+                "MakeNamedMedium" => {
+                    let name = tokens.string(directive)?;
+                    tokens.params()?;
+                    warn!(
+                        self.log,
+                        "ignoring medium {:?}, media aren't supported", name
+                    );
+                }
+                "MediumInterface" => {
+                    tokens.string(directive)?;
+                    if let Some(Token::Str(_)) = tokens.peek() {
+                        tokens.next();
+                    }
+                }
+                "WorldBegin" => {
+                    self.state.ctm = na::Matrix4::identity();
+                    self.coordinate_systems
+                        .insert("world".to_string(), self.state.ctm);
+                }
+                "WorldEnd" => {}
+                "AttributeBegin" => self.push(false),
+                "AttributeEnd" => self.pop(directive),
+                "TransformBegin" => self.push(true),
+                "TransformEnd" => self.pop(directive),
+                "Attribute" => {
+                    let target = tokens.string(directive)?;
+                    tokens.params()?;
+                    warn!(
+                        self.log,
+                        "ignoring attributes of {:?}, set them on each directive instead", target
+                    );
+                }
+                "ObjectBegin" => {
+                    let name = tokens.string(directive)?;
+                    self.push(false);
+                    self.current_object = Some((name, vec![]));
+                }
+                "ObjectEnd" => {
+                    if let Some((name, shapes)) = self.current_object.take() {
+                        self.objects.insert(name, shapes);
+                    }
+                    self.pop(directive);
+                }
+                // instances are flattened into copies of their shapes
+                "ObjectInstance" => {
+                    let name = tokens.string(directive)?;
+                    match self.objects.get(&name) {
+                        Some(shapes) => {
+                            let instance_to_world =
+                                na::Projective3::from_matrix_unchecked(self.state.ctm);
+                            for shape in shapes {
+                                self.shapes.push(Shape {
+                                    obj_to_world: instance_to_world * shape.obj_to_world,
+                                    ..shape.clone()
+                                });
+                            }
+                        }
+                        None => warn!(self.log, "unknown object {:?}", name),
+                    }
+                }
+                "Texture" => {
+                    let name = tokens.string(directive)?;
+                    let kind = tokens.string(directive)?;
+                    let class = tokens.string(directive)?;
+                    let params = tokens.params()?;
+                    let texture = Texture { class, params };
+                    if kind == "float" {
+                        self.float_textures.insert(name, texture);
+                    } else {
+                        self.spectrum_textures.insert(name, texture);
+                    }
+                }
+                "Material" => {
+                    let kind = tokens.string(directive)?;
+                    let params = tokens.params()?;
+                    self.state.material = self.add_material(None, kind, params);
+                }
+                "MakeNamedMaterial" => {
+                    let name = tokens.string(directive)?;
+                    let params = tokens.params()?;
+                    let kind = params.string("type").unwrap_or("diffuse").to_string();
+                    let index = self.add_material(Some(name.clone()), kind, params);
+                    self.named_materials.insert(name, index);
+                }
+                "NamedMaterial" => {
+                    let name = tokens.string(directive)?;
+                    match self.named_materials.get(&name) {
+                        Some(index) => self.state.material = *index,
+                        None => warn!(self.log, "unknown material {:?}", name),
+                    }
+                }
+                "LightSource" => {
+                    let kind = tokens.string(directive)?;
+                    let params = tokens.params()?;
+                    self.lights.push(Light {
+                        kind,
+                        params,
+                        light_to_world: na::Projective3::from_matrix_unchecked(self.state.ctm),
+                    });
+                }
+                "AreaLightSource" => {
+                    let kind = tokens.string(directive)?;
+                    let params = tokens.params()?;
+                    if kind != "diffuse" {
+                        warn!(self.log, "unsupported area light {:?}, using diffuse", kind);
+                    }
+                    self.state.area_light = Some(Arc::new(params));
+                }
+                "Shape" => {
+                    let kind = tokens.string(directive)?;
+                    let params = tokens.params()?;
+                    self.add_shape(kind, params);
+                }
+                "Include" | "Import" => {
+                    let name = tokens.string(directive)?;
+                    let path = self.resolver.resolve(&name);
+                    self.parse_file(&path)?;
+                }
+                _ => {
+                    warn!(self.log, "skipping unknown directive {:?}", directive);
+                    while let Some(token) = tokens.peek() {
+                        if let Token::Word(word) = token {
+                            if word.starts_with(char::is_uppercase) {
+                                break;
+                            }
+                        }
+                        tokens.next();
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn into_scene(
+        self,
+        resolver: super::AssetResolver,
+        max_texture_size: Option<u32>,
+        texture_cache: Arc<crate::pathtracer::texture_cache::TextureCache>,
+    ) -> Scene {
+        let flip = world_flip(&self.camera_to_world);
+        let flip_projective = na::Projective3::from_matrix_unchecked(flip);
+        let shapes = self
+            .shapes
+            .into_iter()
+            .map(|shape| Shape {
+                obj_to_world: flip_projective * shape.obj_to_world,
+                ..shape
+            })
+            .collect();
+        let lights = self
+            .lights
+            .into_iter()
+            .map(|light| Light {
+                light_to_world: flip_projective * light.light_to_world,
+                ..light
+            })
+            .collect();
+
+        Scene {
+            camera: self.camera,
+            camera_to_world: self.camera_to_world,
+            film: self.film,
+            sampler: self.sampler,
+            integrator: self.integrator,
+            float_textures: self.float_textures,
+            spectrum_textures: self.spectrum_textures,
+            materials: self.materials,
+            named_materials: self.named_materials,
+            shapes,
+            lights,
+            world_flip: flip,
+            resolver,
+            max_texture_size,
+            texture_cache,
+        }
+    }
+}
+
+/// parses the pbrt scene the resolver points to, along with the files it includes
+pub fn load_scene(
+    log: &slog::Logger,
+    resolver: super::AssetResolver,
+    max_texture_size: Option<u32>,
+) -> Result<Scene> {
+    let mut parser = Parser::new(log, &resolver);
+    parser.parse_file(resolver.scene_path())?;
+    if !parser.stack.is_empty() {
+        warn!(log, "{} attribute blocks left open", parser.stack.len());
+    }
+    let scene = parser.into_scene(
+        resolver.clone(),
+        max_texture_size,
+        Arc::new(crate::pathtracer::texture_cache::TextureCache::new(
+            &log,
+            crate::pathtracer::texture_cache::DEFAULT_BUDGET,
+        )),
+    );
+    debug!(
+        log,
+        "parsed pbrt scene";
+        "shapes" => scene.shapes.len(),
+        "lights" => scene.lights.len(),
+        "materials" => scene.materials.len()
+    );
+
+    Ok(scene)
+}
+
+/// per vertex normals averaged from the faces around each vertex, weighted by their area
+pub fn vertex_normals(
+    indices: &[na::Vector3<u32>],
+    pos: &[na::Point3<f32>],
+) -> Vec<na::Vector3<f32>> {
+    let mut normals = vec![na::Vector3::zeros(); pos.len()];
+    for triangle in indices {
+        let [i0, i1, i2] = [
+            triangle[0] as usize,
+            triangle[1] as usize,
+            triangle[2] as usize,
+        ];
+        let n = (pos[i1] - pos[i0]).cross(&(pos[i2] - pos[i0]));
+        normals[i0] += n;
+        normals[i1] += n;
+        normals[i2] += n;
+    }
+    normals
+        .into_iter()
+        .map(|n| n.try_normalize(0.0).unwrap_or_else(na::Vector3::z))
+        .collect()
+}
+
+/// drops vertex attributes that don't match the vertices, and the whole mesh if its indices
+/// run past them
+fn checked_mesh(log: &slog::Logger, mut mesh: Mesh) -> Option<Mesh> {
+    let n_vertices = mesh.pos.len();
+    if mesh
+        .indices
+        .iter()
+        .any(|triangle| triangle.iter().any(|i| *i as usize >= n_vertices))
+    {
+        warn!(
+            log,
+            "skipping mesh indexing past its {} vertices", n_vertices
+        );
+        return None;
+    }
+    if !mesh.normal.is_empty() && mesh.normal.len() != n_vertices {
+        warn!(
+            log,
+            "ignoring normals of a mesh that don't match its vertices"
+        );
+        mesh.normal.clear();
+    }
+    if !mesh.uv.is_empty() && mesh.uv.len() != n_vertices {
+        warn!(log, "ignoring uvs of a mesh that don't match its vertices");
+        mesh.uv.clear();
+    }
+
+    Some(mesh)
+}
+
+/// the triangles of a mesh shape in object space, or the tessellation of an analytic shape that
+/// stands in for it in previews. `None` for shapes without a surface to tessellate. ply files
+/// are looked up with `resolver`
+pub fn shape_mesh(
+    log: &slog::Logger,
+    resolver: &super::AssetResolver,
+    shape: &Shape,
+) -> Option<Mesh> {
+    let params = &shape.params;
+    match shape.kind.as_str() {
+        "trianglemesh" | "loopsubdiv" => {
+            if shape.kind == "loopsubdiv" {
+                warn!(
+                    log,
+                    "loop subdivision isn't supported, rendering the control mesh"
+                );
+            }
+            let pos = params.points("P")?;
+            let indices = match params.integers("indices") {
+                Some(indices) => indices
+                    .chunks_exact(3)
+                    .map(|t| na::Vector3::new(t[0] as u32, t[1] as u32, t[2] as u32))
+                    .collect(),
+                // a single triangle may leave out its indices
+                None if pos.len() == 3 => vec![na::Vector3::new(0, 1, 2)],
+                None => {
+                    warn!(log, "skipping triangle mesh without indices");
+                    return None;
+                }
+            };
+            checked_mesh(
+                log,
+                Mesh {
+                    indices,
+                    normal: params.vectors("N").unwrap_or_default(),
+                    uv: params
+                        .point2s("uv")
+                        .or_else(|| params.point2s("st"))
+                        .unwrap_or_default(),
+                    colors: vec![],
+                    pos,
+                },
+            )
+        }
+        // quads of the corners p00, p10, p01 and p11, split along their diagonal
+        "bilinearmesh" => {
+            let pos = params.points("P")?;
+            let indices = match params.integers("indices") {
+                Some(indices) => indices
+                    .chunks_exact(4)
+                    .flat_map(|q| {
+                        let q = [q[0] as u32, q[1] as u32, q[2] as u32, q[3] as u32];
+                        vec![
+                            na::Vector3::new(q[0], q[1], q[3]),
+                            na::Vector3::new(q[0], q[3], q[2]),
+                        ]
+                    })
+                    .collect(),
+                None if pos.len() == 4 => {
+                    vec![na::Vector3::new(0, 1, 3), na::Vector3::new(0, 3, 2)]
+                }
+                None => {
+                    warn!(log, "skipping bilinear mesh without indices");
+                    return None;
+                }
+            };
+            checked_mesh(
+                log,
+                Mesh {
+                    indices,
+                    normal: params.vectors("N").unwrap_or_default(),
+                    uv: params.point2s("uv").unwrap_or_default(),
+                    colors: vec![],
+                    pos,
+                },
+            )
+        }
+        "sphere" => Some(mitsuba::gen_sphere(
+            &na::Point3::origin(),
+            params.float("radius").unwrap_or(1.0),
+        )),
+        "disk" => {
+            let mut mesh = mitsuba::gen_disk();
+            let radius = params.float("radius").unwrap_or(1.0);
+            let height = params.float("height").unwrap_or(0.0);
+            for p in &mut mesh.pos {
+                *p = na::Point3::new(radius * p.x, radius * p.y, height);
+            }
+            Some(mesh)
+        }
+        "cylinder" => {
+            let mut mesh = mitsuba::gen_cylinder(params.float("radius").unwrap_or(1.0));
+            let z_min = params.float("zmin").unwrap_or(-1.0);
+            let z_max = params.float("zmax").unwrap_or(1.0);
+            for p in &mut mesh.pos {
+                p.z = z_min + p.z * (z_max - z_min);
+            }
+            Some(mesh)
+        }
+        "plymesh" => {
+            let filename = params.string("filename").unwrap_or_default();
+            if params.texture("displacement").is_some() {
+                warn!(log, "ignoring displacement of ply mesh {:?}", filename);
+            }
+            match ply::load_ply(&resolver.resolve(filename)) {
+                Ok(mesh) => checked_mesh(log, mesh),
+                Err(err) => {
+                    warn!(log, "skipping ply mesh {:?}: {:?}", filename, err);
+                    None
+                }
+            }
+        }
+        "curve" => None,
+        kind => {
+            warn!(log, "skipping unsupported shape {:?}", kind);
+            None
+        }
+    }
+}
+
+/// the scene's camera on a film of `resolution`, pbrt's field of view spans the shorter side of
+/// the image
+fn get_camera(log: &slog::Logger, scene: &Scene, resolution: &na::Vector2<f32>) -> Camera {
+    let params = &scene.camera.params;
+    let aspect = resolution.x / resolution.y;
+    let flip_z = na::Matrix4::new_nonuniform_scaling(&na::Vector3::new(1.0, 1.0, -1.0));
+    let cam_to_world = scene.world_flip * scene.camera_to_world * flip_z;
+    let mut sim_cam_to_world: na::Similarity3<f32> = na::try_convert(
+        na::Projective3::from_matrix_unchecked(cam_to_world),
+    )
+    .unwrap_or_else(|| {
+        warn!(log, "camera transform isn't rigid, ignoring its shear");
+        na::Similarity3::identity()
+    });
+    if !(sim_cam_to_world.scaling() == 1.0) {
+        sim_cam_to_world.set_scaling(1.0);
+    }
+    let cam_to_world = sim_cam_to_world.isometry;
+
+    let perspective = |fov: f32| {
+        let fov = fov.to_radians();
+        let fovy = if aspect >= 1.0 {
+            fov
+        } else {
+            2.0 * ((fov / 2.0).tan() / aspect).atan()
+        };
+        Projection::Perspective(na::Perspective3::new(
+            aspect,
+            fovy,
+            DEFAULT_Z_NEAR,
+            DEFAULT_Z_FAR,
+        ))
+    };
+    let fov = params.float("fov").unwrap_or(90.0);
+    let camera = match scene.camera.kind.as_str() {
+        "perspective" => Camera::new(&cam_to_world, &perspective(fov), resolution),
+        "orthographic" => {
+            let height = match params.floats("screenwindow") {
+                Some(window) if window.len() == 4 => window[3] - window[2],
+                _ if aspect >= 1.0 => 2.0,
+                _ => 2.0 / aspect,
+            };
+            Camera::new(
+                &cam_to_world,
+                &Projection::orthographic(aspect, height, DEFAULT_Z_NEAR, DEFAULT_Z_FAR),
+                resolution,
+            )
+        }
+        "environment" | "spherical" => {
+            Camera::new(&cam_to_world, &perspective(fov), resolution).spherical()
+        }
+        "realistic" => {
+            let camera = Camera::new(&cam_to_world, &perspective(fov), resolution);
+            let lens_file = params.string("lensfile").unwrap_or_default();
+            match crate::pathtracer::lens::LensSystem::load(
+                &scene.resolver.resolve(lens_file),
+                Some(params.float("aperturediameter").unwrap_or(1.0)),
+                scene.film.params.float("diagonal").unwrap_or(35.0),
+                params.float("focusdistance").unwrap_or(10.0),
+            ) {
+                Ok(lens) => camera.realistic(lens),
+                Err(err) => {
+                    warn!(log, "failed loading lens, using a pinhole: {:?}", err);
+                    camera
+                }
+            }
+        }
+        kind => {
+            warn!(log, "unsupported camera {:?}, using perspective", kind);
+            Camera::new(&cam_to_world, &perspective(fov), resolution)
+        }
+    };
+
+    match params.float("lensradius") {
+        Some(lens_radius) if lens_radius > 0.0 => {
+            camera.with_lens(lens_radius, params.float("focaldistance").unwrap_or(1e6))
+        }
+        _ => camera,
+    }
+}
+
+/// the scene's film and sampling settings aren't applied, the command line decides them, but
+/// they're logged along with the flags that match them
+fn log_settings(log: &slog::Logger, scene: &Scene) {
+    let film = &scene.film.params;
+    if let (Some(x), Some(y)) = (film.integer("xresolution"), film.integer("yresolution")) {
+        info!(
+            log,
+            "pbrt film is {}x{}, render with --resolution {}x{} to match", x, y, x, y
+        );
+    }
+    if let Some(samples) = scene
+        .sampler
+        .as_ref()
+        .and_then(|sampler| sampler.params.integer("pixelsamples"))
+    {
+        info!(
+            log,
+            "pbrt sampler takes {} samples, render with --samples {} to match", samples, samples
+        );
+    }
+    if let Some(integrator) = &scene.integrator {
+        let max_depth = integrator.params.integer("maxdepth").unwrap_or(5);
+        info!(
+            log,
+            "pbrt integrator is {:?} of depth {}, render with --max_depth {} to match",
+            integrator.kind,
+            max_depth,
+            max_depth
+        );
+    }
+}
+
+pub fn from_pbrt(
+    log: &slog::Logger,
+    resolver: super::AssetResolver,
+    material_library: Option<&super::library::MaterialLibrary>,
+    resolution: &na::Vector2<f32>,
+    max_texture_size: Option<u32>,
+) -> (
+    Camera,
+    crate::pathtracer::RenderScene,
+    crate::viewer::renderer::ViewerScene,
+) {
+    let scene = load_scene(&log, resolver, max_texture_size).unwrap();
+    log_settings(&log, &scene);
+
+    let camera = get_camera(&log, &scene, &resolution);
+    let bvh_cache = BVHCache::load(&log, &scene.resolver.cache_path(BVH_CACHE_EXTENSION));
+    let render_scene = crate::pathtracer::RenderScene::from_pbrt(
+        &log,
+        &scene,
+        material_library,
+        &camera,
+        &bvh_cache,
+    );
+    bvh_cache.save();
+    let viewer_scene = crate::viewer::renderer::ViewerScene::from_pbrt(&scene);
+
+    (camera, render_scene, viewer_scene)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> (Vec<Shape>, HashMap<String, usize>) {
+        let log = slog::Logger::root(slog::Discard, o!());
+        let resolver = super::super::AssetResolver::default();
+        let mut parser = Parser::new(&log, &resolver);
+        parser.parse(source).unwrap();
+        (parser.shapes, parser.named_materials)
+    }
+
+    #[test]
+    fn parses_parameter_lists() {
+        let (shapes, _) = parse(
+            r#"
+            # a comment
+            Shape "trianglemesh" "integer indices" [0 1 2] "point3 P" [0 0 0 1 0 0 1 1 0]
+                "rgb color" [0.1 0.2 0.3] "bool flag" true "string name" "tri"
+                "blackbody L" 6500
+            "#,
+        );
+        let params = &shapes[0].params;
+        assert_eq!(params.integers("indices"), Some(&[0, 1, 2][..]));
+        assert_eq!(params.points("P").unwrap().len(), 3);
+        assert_eq!(params.rgb("color"), Some([0.1, 0.2, 0.3]));
+        assert_eq!(params.bool("flag"), Some(true));
+        assert_eq!(params.string("name"), Some("tri"));
+        let l = params.rgb("L").unwrap();
+        assert!(l.iter().cloned().fold(0.0, f32::max) == 1.0 && l[2] > 0.5);
+    }
+
+    #[test]
+    fn resolves_graphics_state() {
+        let (shapes, named_materials) = parse(
+            r#"
+            WorldBegin
+            MakeNamedMaterial "red" "string type" "diffuse" "rgb reflectance" [1 0 0]
+            AttributeBegin
+              Translate 1 0 0
+              NamedMaterial "red"
+              Shape "sphere"
+            AttributeEnd
+            Shape "sphere"
+            "#,
+        );
+        assert_eq!(shapes.len(), 2);
+        assert_eq!(shapes[0].material, named_materials["red"]);
+        assert_eq!(shapes[1].material, 0);
+        assert_eq!(
+            shapes[0].obj_to_world * na::Point3::origin(),
+            na::Point3::new(1.0, 0.0, 0.0)
+        );
+        assert_eq!(
+            shapes[1].obj_to_world * na::Point3::origin(),
+            na::Point3::origin()
+        );
+    }
+
+    #[test]
+    fn flips_the_world_unless_the_camera_mirrors() {
+        assert_eq!(world_flip(&na::Matrix4::identity())[(2, 2)], -1.0);
+        let mirrored = na::Matrix4::new_nonuniform_scaling(&na::Vector3::new(-1.0, 1.0, 1.0));
+        assert_eq!(world_flip(&mirrored), na::Matrix4::identity());
+    }
+}
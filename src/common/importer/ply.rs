@@ -0,0 +1,463 @@
+//! reads the triangles of ply meshes, in the ascii or either of the binary encodings. polygons
+//! are split into fans of triangles, elements other than the vertices and faces are skipped
+
+use super::{description::PlacedMesh, mitsuba::Mesh, AssetResolver, BVH_CACHE_EXTENSION};
+use crate::common::{math::inverse_gamma_correct, Camera};
+use crate::pathtracer::accelerator::BVHCache;
+use anyhow::{anyhow, Context, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn from_name(name: &str) -> Result<Self> {
+        Ok(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return Err(anyhow!("unknown ply property type {:?}", name)),
+        })
+    }
+
+    /// value of a full color channel of this type, integer colors span the whole range
+    fn color_range(self) -> f64 {
+        match self {
+            Scalar::U8 => std::u8::MAX as f64,
+            Scalar::U16 => std::u16::MAX as f64,
+            _ => 1.0,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar(Scalar),
+    /// a count of the first type followed by as many items of the second
+    List(Scalar, Scalar),
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<(String, Property)>,
+}
+
+/// reads the values of a ply body one at a time, whatever its encoding
+struct Reader<'a> {
+    format: Format,
+    data: &'a [u8],
+    offset: usize,
+    tokens: std::str::SplitAsciiWhitespace<'a>,
+}
+
+impl<'a> Reader<'a> {
+    fn new(format: Format, data: &'a [u8]) -> Result<Self> {
+        let text = match format {
+            Format::Ascii => std::str::from_utf8(data).context("ascii ply body isn't utf-8")?,
+            _ => "",
+        };
+        Ok(Self {
+            format,
+            data,
+            offset: 0,
+            tokens: text.split_ascii_whitespace(),
+        })
+    }
+
+    /// the next `size` bytes of a binary body, in little endian order
+    fn bytes(&mut self, size: usize) -> Result<[u8; 8]> {
+        let bytes = self
+            .data
+            .get(self.offset..self.offset + size)
+            .ok_or_else(|| anyhow!("ply body ends early"))?;
+        self.offset += size;
+        let mut buffer = [0; 8];
+        buffer[..size].copy_from_slice(bytes);
+        if self.format == Format::BinaryBigEndian {
+            buffer[..size].reverse();
+        }
+        Ok(buffer)
+    }
+
+    fn read(&mut self, scalar: Scalar) -> Result<f64> {
+        if self.format == Format::Ascii {
+            let token = self
+                .tokens
+                .next()
+                .ok_or_else(|| anyhow!("ply body ends early"))?;
+            return token
+                .parse()
+                .with_context(|| format!("invalid ply value {:?}", token));
+        }
+
+        let b = self.bytes(scalar.size())?;
+        Ok(match scalar {
+            Scalar::I8 => b[0] as i8 as f64,
+            Scalar::U8 => b[0] as f64,
+            Scalar::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+            Scalar::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+            Scalar::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::F64 => f64::from_le_bytes(b),
+        })
+    }
+
+    fn skip(&mut self, scalar: Scalar) -> Result<()> {
+        match self.format {
+            Format::Ascii => self.read(scalar).map(|_| ()),
+            _ if self.offset + scalar.size() <= self.data.len() => {
+                self.offset += scalar.size();
+                Ok(())
+            }
+            _ => Err(anyhow!("ply body ends early")),
+        }
+    }
+}
+
+/// the header's format and elements, and the offset of the body following it
+fn parse_header(data: &[u8]) -> Result<(Format, Vec<Element>, usize)> {
+    const END_HEADER: &[u8] = b"end_header";
+    let end = data
+        .windows(END_HEADER.len())
+        .position(|window| window == END_HEADER)
+        .ok_or_else(|| anyhow!("ply header isn't terminated"))?;
+    let mut body = end + END_HEADER.len();
+    // the header ends with a line break of either convention
+    if data.get(body) == Some(&b'\r') {
+        body += 1;
+    }
+    if data.get(body) == Some(&b'\n') {
+        body += 1;
+    }
+
+    let header = std::str::from_utf8(&data[..end]).context("ply header isn't utf-8")?;
+    let mut lines = header.lines();
+    if lines.next().map(str::trim) != Some("ply") {
+        return Err(anyhow!("not a ply file"));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", "ascii", _] => format = Some(Format::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(Format::BinaryLittleEndian),
+            ["format", "binary_big_endian", _] => format = Some(Format::BinaryBigEndian),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .with_context(|| format!("invalid count of ply element {:?}", name))?,
+                properties: vec![],
+            }),
+            ["property", "list", count, item, name] => elements
+                .last_mut()
+                .ok_or_else(|| anyhow!("ply property {:?} outside of an element", name))?
+                .properties
+                .push((
+                    name.to_string(),
+                    Property::List(Scalar::from_name(count)?, Scalar::from_name(item)?),
+                )),
+            ["property", scalar, name] => elements
+                .last_mut()
+                .ok_or_else(|| anyhow!("ply property {:?} outside of an element", name))?
+                .properties
+                .push((
+                    name.to_string(),
+                    Property::Scalar(Scalar::from_name(scalar)?),
+                )),
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(anyhow!("invalid ply header line {:?}", line)),
+        }
+    }
+
+    let format = format.ok_or_else(|| anyhow!("ply header has no format"))?;
+    Ok((format, elements, body))
+}
+
+/// the mesh in the ply `data`, with its normals, uvs and colors when every vertex has them.
+/// colors are stored gamma corrected like the images of scans, they're made linear
+pub fn parse_ply(data: &[u8]) -> Result<Mesh> {
+    let (format, elements, body) = parse_header(data)?;
+    let mut reader = Reader::new(format, &data[body..])?;
+
+    let mut mesh = Mesh {
+        indices: vec![],
+        pos: vec![],
+        normal: vec![],
+        uv: vec![],
+        colors: vec![],
+    };
+    for element in &elements {
+        let is_vertex = element.name == "vertex";
+        let is_face = element.name == "face";
+        for _ in 0..element.count {
+            let mut values = [0.0f32; 11];
+            let mut has = [false; 11];
+            for (name, property) in &element.properties {
+                match property {
+                    Property::Scalar(scalar) => {
+                        let slot = match name.as_str() {
+                            "x" => Some(0),
+                            "y" => Some(1),
+                            "z" => Some(2),
+                            "nx" => Some(3),
+                            "ny" => Some(4),
+                            "nz" => Some(5),
+                            "u" | "s" | "texture_u" | "texture_s" => Some(6),
+                            "v" | "t" | "texture_v" | "texture_t" => Some(7),
+                            "red" | "r" | "diffuse_red" => Some(8),
+                            "green" | "g" | "diffuse_green" => Some(9),
+                            "blue" | "b" | "diffuse_blue" => Some(10),
+                            _ => None,
+                        };
+                        match slot {
+                            Some(slot) if is_vertex && slot >= 8 => {
+                                let value = reader.read(*scalar)? / scalar.color_range();
+                                values[slot] = inverse_gamma_correct(value as f32);
+                                has[slot] = true;
+                            }
+                            Some(slot) if is_vertex => {
+                                values[slot] = reader.read(*scalar)? as f32;
+                                has[slot] = true;
+                            }
+                            _ => reader.skip(*scalar)?,
+                        }
+                    }
+                    Property::List(count, item) => {
+                        let count = reader.read(*count)? as usize;
+                        if is_face && (name == "vertex_indices" || name == "vertex_index") {
+                            let polygon = (0..count)
+                                .map(|_| reader.read(*item).map(|i| i as u32))
+                                .collect::<Result<Vec<_>>>()?;
+                            for i in 1..polygon.len().saturating_sub(1) {
+                                mesh.indices.push(na::Vector3::new(
+                                    polygon[0],
+                                    polygon[i],
+                                    polygon[i + 1],
+                                ));
+                            }
+                        } else {
+                            for _ in 0..count {
+                                reader.skip(*item)?;
+                            }
+                        }
+                    }
+                }
+            }
+
+            if is_vertex {
+                mesh.pos
+                    .push(na::Point3::new(values[0], values[1], values[2]));
+                if has[3] && has[4] && has[5] {
+                    mesh.normal
+                        .push(na::Vector3::new(values[3], values[4], values[5]));
+                }
+                if has[6] && has[7] {
+                    mesh.uv.push(na::Point2::new(values[6], values[7]));
+                }
+                if has[8] && has[9] && has[10] {
+                    mesh.colors
+                        .push(na::Vector3::new(values[8], values[9], values[10]));
+                }
+            }
+        }
+    }
+
+    let n_vertices = mesh.pos.len();
+    if mesh
+        .indices
+        .iter()
+        .any(|t| t.iter().any(|i| *i as usize >= n_vertices))
+    {
+        return Err(anyhow!("ply face indexes past its {} vertices", n_vertices));
+    }
+    if mesh.normal.len() != n_vertices {
+        mesh.normal.clear();
+    }
+    if mesh.uv.len() != n_vertices {
+        mesh.uv.clear();
+    }
+    if mesh.colors.len() != n_vertices {
+        mesh.colors.clear();
+    }
+
+    Ok(mesh)
+}
+
+pub fn load_ply(file_path: &std::path::Path) -> Result<Mesh> {
+    let data = std::fs::read(file_path).with_context(|| format!("reading {:?}", file_path))?;
+    parse_ply(&data).with_context(|| format!("parsing {:?}", file_path))
+}
+
+/// a lone ply mesh as a scene, for quick looks at scans and other geometry. the mesh keeps its
+/// vertex colors under the default material and is framed by the default camera
+pub fn from_ply(
+    log: &slog::Logger,
+    resolver: &AssetResolver,
+    resolution: &na::Vector2<f32>,
+    default_lights: bool,
+) -> (
+    Camera,
+    crate::pathtracer::RenderScene,
+    crate::viewer::renderer::ViewerScene,
+) {
+    let meshes = vec![PlacedMesh {
+        name: resolver.scene_path().to_string_lossy().into_owned(),
+        mesh: load_ply(resolver.scene_path()).unwrap(),
+        obj_to_world: na::Projective3::identity(),
+        material: None,
+    }];
+    if !default_lights {
+        warn!(
+            log,
+            "ply scenes have no lights of their own, see --default_lights"
+        );
+    }
+
+    let bvh_cache = BVHCache::load(&log, &resolver.cache_path(BVH_CACHE_EXTENSION));
+    let render_scene = crate::pathtracer::RenderScene::from_description(
+        &log,
+        &meshes,
+        None,
+        default_lights,
+        &bvh_cache,
+    );
+    bvh_cache.save();
+    let camera = super::gltf::get_default_camera(&render_scene.world_bound(), resolution);
+    let viewer_scene = crate::viewer::renderer::ViewerScene::from_description(meshes);
+
+    (camera, render_scene, viewer_scene)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUAD: &str = "ply
+format ascii 1.0
+comment a unit quad
+element vertex 4
+property float x
+property float y
+property float z
+property float u
+property float v
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 0 0
+1 0 0 1 0
+1 1 0 1 1
+0 1 0 0 1
+4 0 1 2 3
+";
+
+    #[test]
+    fn splits_ascii_polygons_into_triangles() {
+        let mesh = parse_ply(QUAD.as_bytes()).unwrap();
+        assert_eq!(mesh.pos.len(), 4);
+        assert_eq!(mesh.uv[2], na::Point2::new(1.0, 1.0));
+        assert!(mesh.normal.is_empty());
+        assert_eq!(
+            mesh.indices,
+            vec![na::Vector3::new(0, 1, 2), na::Vector3::new(0, 2, 3)]
+        );
+    }
+
+    #[test]
+    fn reads_binary_encodings() {
+        for (format, big_endian) in &[("binary_little_endian", false), ("binary_big_endian", true)]
+        {
+            let mut data = format!(
+                "ply\nformat {} 1.0\nelement vertex 3\nproperty float x\nproperty float y\n\
+                 property float z\nelement face 1\nproperty list uchar uint vertex_indices\n\
+                 end_header\n",
+                format
+            )
+            .into_bytes();
+            let float = |v: f32| {
+                if *big_endian {
+                    v.to_be_bytes()
+                } else {
+                    v.to_le_bytes()
+                }
+            };
+            for v in &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 2.0, 0.0] {
+                data.extend_from_slice(&float(*v));
+            }
+            data.push(3);
+            for i in 0..3u32 {
+                data.extend_from_slice(&if *big_endian {
+                    i.to_be_bytes()
+                } else {
+                    i.to_le_bytes()
+                });
+            }
+
+            let mesh = parse_ply(&data).unwrap();
+            assert_eq!(mesh.pos[2], na::Point3::new(0.0, 2.0, 0.0));
+            assert_eq!(mesh.indices, vec![na::Vector3::new(0, 1, 2)]);
+        }
+    }
+
+    #[test]
+    fn reads_vertex_colors_as_linear() {
+        let data = "ply
+format ascii 1.0
+element vertex 3
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+property uchar alpha
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255 0 0 255
+1 0 0 0 255 0 255
+0 1 0 255 255 255 128
+3 0 1 2
+";
+        let mesh = parse_ply(data.as_bytes()).unwrap();
+        assert_eq!(mesh.colors.len(), 3);
+        assert!((mesh.colors[0] - na::Vector3::new(1.0, 0.0, 0.0)).norm() < 1e-5);
+        assert!((mesh.colors[2] - na::Vector3::new(1.0, 1.0, 1.0)).norm() < 1e-5);
+        assert!(parse_ply(QUAD.as_bytes()).unwrap().colors.is_empty());
+    }
+}
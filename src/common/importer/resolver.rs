@@ -0,0 +1,141 @@
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+
+const SCENE_EXTENSIONS: [&str; 4] = ["xml", "gltf", "glb", "pbrt"];
+
+/// locates a scene and the assets it references. scenes can be loose files, self contained
+/// .glb binaries, or packed into a .zip archive, which is unpacked into a temporary directory
+/// before loading. relative asset names are looked up next to the scene first, then in each
+/// of the search paths in order
+#[derive(Debug, Default, Clone)]
+pub struct AssetResolver {
+    scene_path: PathBuf,
+    /// the scene file or the archive it was unpacked from
+    source_path: PathBuf,
+    search_paths: Vec<PathBuf>,
+}
+
+impl AssetResolver {
+    pub fn new(log: &slog::Logger, path: &str, search_paths: &[PathBuf]) -> Result<Self> {
+        let log = log.new(o!("module" => "resolver"));
+        let mut resolver = Self {
+            scene_path: PathBuf::new(),
+            source_path: PathBuf::new(),
+            search_paths: search_paths.to_vec(),
+        };
+
+        let path = resolver
+            .find(Path::new(path))
+            .ok_or_else(|| anyhow!("unable to find scene {}", path))?;
+
+        resolver.scene_path = if path.extension().map_or(false, |ext| ext == "zip") {
+            let scene_path = unpack_archive(&path)?;
+            debug!(
+                log,
+                "unpacked archive {:?}, found scene {:?}", path, scene_path
+            );
+            // assets referenced by the archive may also live beside the archive itself
+            if let Some(parent) = path.parent() {
+                resolver.search_paths.insert(0, parent.to_path_buf());
+            }
+            scene_path
+        } else {
+            path.clone()
+        };
+        resolver.source_path = path;
+
+        Ok(resolver)
+    }
+
+    /// path of the scene file on disk
+    pub fn scene_path(&self) -> &Path {
+        &self.scene_path
+    }
+
+    /// directory of the scene file
+    pub fn scene_dir(&self) -> &Path {
+        self.scene_path
+            .parent()
+            .unwrap_or_else(|| std::path::Path::new(""))
+    }
+
+    /// file next to the scene, or the archive it came in, named after it with `extension`
+    /// appended, for data derived from the scene that's worth keeping between runs
+    pub fn cache_path(&self, extension: &str) -> PathBuf {
+        let mut name = self
+            .source_path
+            .file_name()
+            .unwrap_or_default()
+            .to_os_string();
+        name.push(".");
+        name.push(extension);
+        self.source_path.with_file_name(name)
+    }
+
+    /// resolves an asset name referenced by the scene, falling back to the path next to the
+    /// scene when it isn't found anywhere so the error that follows names a sensible location
+    pub fn resolve(&self, name: &str) -> PathBuf {
+        let relative = self.scene_dir().join(name);
+        if relative.exists() {
+            return relative;
+        }
+        self.find(Path::new(name)).unwrap_or(relative)
+    }
+
+    fn find(&self, name: &Path) -> Option<PathBuf> {
+        if name.exists() {
+            return Some(name.to_path_buf());
+        }
+        if name.is_absolute() {
+            return None;
+        }
+        self.search_paths
+            .iter()
+            .map(|dir| dir.join(name))
+            .find(|path| path.exists())
+    }
+}
+
+/// extracts a zip archive into the temporary directory and returns the scene file inside it,
+/// preferring the shallowest scene file and breaking ties by name
+fn unpack_archive(path: &Path) -> Result<PathBuf> {
+    let file = std::fs::File::open(path).with_context(|| format!("opening {:?}", path))?;
+    let mut archive = zip::ZipArchive::new(file).with_context(|| format!("reading {:?}", path))?;
+
+    let stem = path.file_stem().unwrap_or_default();
+    let out_dir = std::env::temp_dir().join("pathtracer-rs").join(stem);
+    std::fs::create_dir_all(&out_dir)?;
+
+    let mut scenes = Vec::new();
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        // skips entries that would escape the output directory
+        let name = match entry.enclosed_name() {
+            Some(name) => name.to_path_buf(),
+            None => continue,
+        };
+        let out_path = out_dir.join(&name);
+        if entry.is_dir() {
+            std::fs::create_dir_all(&out_path)?;
+            continue;
+        }
+        if let Some(parent) = out_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut out_file = std::fs::File::create(&out_path)?;
+        std::io::copy(&mut entry, &mut out_file)?;
+
+        if name
+            .extension()
+            .map_or(false, |ext| SCENE_EXTENSIONS.iter().any(|e| ext == *e))
+        {
+            scenes.push(name);
+        }
+    }
+
+    scenes.sort_by_key(|name| (name.components().count(), name.clone()));
+    scenes
+        .first()
+        .map(|name| out_dir.join(name))
+        .ok_or_else(|| anyhow!("no scene file found in archive {:?}", path))
+}
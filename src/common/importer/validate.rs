@@ -0,0 +1,741 @@
+//! checks a scene for problems that would otherwise only show up after a long render, without
+//! building the render scene. the scene description is walked directly, so scenes the importers
+//! would fail on can still be checked
+
+use super::{
+    description,
+    gltf::{resolve_uri, trans_from_gltf, GltfFile},
+    mitsuba, pbrt, ply, AssetResolver,
+};
+use anyhow::{anyhow, Context, Result};
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum Issue {
+    /// a texture file referenced by a material can't be found
+    MissingTexture {
+        material: String,
+        path: PathBuf,
+    },
+    /// an emissive mesh whose surface has no area, it never emits any light
+    ZeroAreaEmitter {
+        mesh: String,
+    },
+    ZeroIntensityLight {
+        light: String,
+    },
+    /// a transform with infinite or NaN entries, everything below it is skipped
+    NonFiniteTransform {
+        node: String,
+    },
+    /// an image textured mesh without texture coordinates samples a single texel everywhere
+    MissingUVs {
+        mesh: String,
+        material: String,
+    },
+    DegenerateCamera {
+        camera: String,
+        reason: &'static str,
+    },
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Issue::MissingTexture { material, path } => write!(
+                f,
+                "material {} references missing texture {:?}",
+                material, path
+            ),
+            Issue::ZeroAreaEmitter { mesh } => write!(f, "emissive mesh {} has zero area", mesh),
+            Issue::ZeroIntensityLight { light } => {
+                write!(f, "light {} has zero intensity", light)
+            }
+            Issue::NonFiniteTransform { node } => {
+                write!(f, "transform of {} is not finite", node)
+            }
+            Issue::MissingUVs { mesh, material } => write!(
+                f,
+                "mesh {} has no uvs but its material {} uses image textures",
+                mesh, material
+            ),
+            Issue::DegenerateCamera { camera, reason } => {
+                write!(f, "camera {} is degenerate: {}", camera, reason)
+            }
+        }
+    }
+}
+
+/// loads the scene description at `path` and lists the issues found in it
+pub fn validate(
+    log: &slog::Logger,
+    path: &str,
+    search_paths: &[std::path::PathBuf],
+) -> Result<Vec<Issue>> {
+    let resolver = AssetResolver::new(&log, path, search_paths)?;
+    let ext = resolver.scene_path().extension().unwrap_or_default();
+
+    let issues = if ext == "gltf" || ext == "glb" {
+        validate_gltf(&resolver)?
+    } else if ext == "xml" {
+        validate_mitsuba(&resolver)?
+    } else if ext == "pbrt" {
+        validate_pbrt(log, &resolver)?
+    } else if ext == "ply" {
+        // a lone mesh has nothing to check beyond being readable
+        ply::load_ply(resolver.scene_path())?;
+        vec![]
+    } else if description::is_description(resolver.scene_path()) {
+        validate_description(log, &resolver, search_paths)?
+    } else {
+        return Err(anyhow!("unsupported format {:?}", ext));
+    };
+    debug!(
+        log,
+        "validated {:?}, {} issues",
+        resolver.scene_path(),
+        issues.len()
+    );
+
+    Ok(issues)
+}
+
+fn is_finite(transform: &na::Projective3<f32>) -> bool {
+    transform.matrix().iter().all(|v| v.is_finite())
+}
+
+/// surface area of the triangles after `transform`
+fn mesh_area<I>(triangles: I, pos: &[na::Point3<f32>], transform: &na::Projective3<f32>) -> f32
+where
+    I: IntoIterator<Item = [usize; 3]>,
+{
+    triangles
+        .into_iter()
+        .map(|[i0, i1, i2]| {
+            let p0 = transform * pos[i0];
+            let p1 = transform * pos[i1];
+            let p2 = transform * pos[i2];
+            0.5 * (p1 - p0).cross(&(p2 - p0)).norm()
+        })
+        .sum()
+}
+
+fn gltf_name(kind: &str, name: Option<&str>, index: usize) -> String {
+    match name {
+        Some(name) => format!("{} {:?}", kind, name),
+        None => format!("{} #{}", kind, index),
+    }
+}
+
+fn gltf_material_name(material: &gltf::Material) -> String {
+    match material.index() {
+        Some(index) => gltf_name("material", material.name(), index),
+        None => "default material".to_string(),
+    }
+}
+
+fn gltf_material_textures<'a>(material: &gltf::Material<'a>) -> Vec<gltf::Texture<'a>> {
+    let pbr = material.pbr_metallic_roughness();
+    vec![
+        pbr.base_color_texture().map(|info| info.texture()),
+        pbr.metallic_roughness_texture().map(|info| info.texture()),
+        material.normal_texture().map(|info| info.texture()),
+        material.occlusion_texture().map(|info| info.texture()),
+        material.emissive_texture().map(|info| info.texture()),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+fn validate_gltf(resolver: &AssetResolver) -> Result<Vec<Issue>> {
+    let mut gltf_file = GltfFile::open(resolver.scene_path())
+        .with_context(|| format!("reading {:?}", resolver.scene_path()))?;
+    let buffers = gltf_file
+        .buffers(resolver)
+        .context("loading gltf buffers")?;
+    let document = gltf_file.document;
+    let mut issues = Vec::new();
+
+    for material in document.materials() {
+        for texture in gltf_material_textures(&material) {
+            // embedded images are always there
+            if let gltf::image::Source::Uri { uri, .. } = texture.source().source() {
+                let path = resolve_uri(resolver, uri);
+                if !uri.starts_with("data:") && !path.exists() {
+                    issues.push(Issue::MissingTexture {
+                        material: gltf_material_name(&material),
+                        path,
+                    });
+                }
+            }
+        }
+    }
+
+    for scene in document.scenes() {
+        for node in scene.nodes() {
+            validate_gltf_node(&na::Projective3::identity(), &node, &buffers, &mut issues);
+        }
+    }
+
+    Ok(issues)
+}
+
+fn validate_gltf_node(
+    parent_transform: &na::Projective3<f32>,
+    node: &gltf::Node,
+    buffers: &[gltf::buffer::Data],
+    issues: &mut Vec<Issue>,
+) {
+    let transform = *parent_transform * trans_from_gltf(node.transform());
+    if !is_finite(&transform) {
+        issues.push(Issue::NonFiniteTransform {
+            node: gltf_name("node", node.name(), node.index()),
+        });
+        return;
+    }
+
+    if let Some(camera) = node.camera() {
+        let reason = match camera.projection() {
+            gltf::camera::Projection::Perspective(projection) => {
+                if projection.yfov() <= 0.0 || projection.yfov() >= std::f32::consts::PI {
+                    Some("field of view out of range")
+                } else if projection.znear() <= 0.0 {
+                    Some("near plane not in front of the camera")
+                } else if projection
+                    .zfar()
+                    .map_or(false, |zfar| zfar <= projection.znear())
+                {
+                    Some("far plane not behind the near plane")
+                } else if projection
+                    .aspect_ratio()
+                    .map_or(false, |aspect| aspect <= 0.0)
+                {
+                    Some("aspect ratio not positive")
+                } else {
+                    None
+                }
+            }
+            gltf::camera::Projection::Orthographic(projection) => {
+                if projection.xmag() == 0.0 || projection.ymag() == 0.0 {
+                    Some("view has no area")
+                } else if projection.znear() < 0.0 {
+                    Some("near plane behind the camera")
+                } else if projection.zfar() <= projection.znear() {
+                    Some("far plane not behind the near plane")
+                } else {
+                    None
+                }
+            }
+        };
+        let reason = reason.or_else(|| {
+            if na::try_convert::<_, na::Isometry3<f32>>(transform).is_none() {
+                Some("transform is not a rigid motion")
+            } else {
+                None
+            }
+        });
+        if let Some(reason) = reason {
+            issues.push(Issue::DegenerateCamera {
+                camera: gltf_name("camera", camera.name(), camera.index()),
+                reason,
+            });
+        }
+    }
+
+    if let Some(light) = node.light() {
+        let color = light.color();
+        if light.intensity() * color[0].max(color[1]).max(color[2]) <= 0.0 {
+            issues.push(Issue::ZeroIntensityLight {
+                light: gltf_name("light", light.name(), light.index()),
+            });
+        }
+    }
+
+    if let Some(mesh) = node.mesh() {
+        let mesh_name = gltf_name("mesh", mesh.name(), mesh.index());
+        for prim in mesh.primitives() {
+            let material = prim.material();
+            let reader = prim.reader(|buffer| Some(&buffers[buffer.index()]));
+            let pos: Vec<_> = reader.read_positions().map_or(vec![], |pos| {
+                pos.map(|p| na::Point3::new(p[0], p[1], p[2])).collect()
+            });
+            let indices: Vec<usize> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().map(|i| i as usize).collect(),
+                None => (0..pos.len()).collect(),
+            };
+
+            let emissive = material.emissive_factor().iter().any(|v| *v > 0.0);
+            if emissive {
+                let triangles = indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]);
+                if mesh_area(triangles, &pos, &transform) <= 0.0 {
+                    issues.push(Issue::ZeroAreaEmitter {
+                        mesh: mesh_name.clone(),
+                    });
+                }
+            }
+
+            if reader.read_tex_coords(0).is_none() && !gltf_material_textures(&material).is_empty()
+            {
+                issues.push(Issue::MissingUVs {
+                    mesh: mesh_name.clone(),
+                    material: gltf_material_name(&material),
+                });
+            }
+        }
+    }
+
+    for child in node.children() {
+        validate_gltf_node(&transform, &child, buffers, issues);
+    }
+}
+
+/// image textures of the bsdf and the bsdfs nested in it
+fn bsdf_bitmaps(bsdf: &mitsuba::BSDF) -> Vec<&str> {
+    let mut textures = Vec::new();
+    let mut nested = Vec::new();
+    match bsdf {
+        mitsuba::BSDF::TwoSided(bsdf) => nested.push(bsdf.bsdf.as_ref()),
+        mitsuba::BSDF::Diffuse(bsdf) => textures.extend(&bsdf.texture),
+        mitsuba::BSDF::RoughDiffuse(bsdf)
+        | mitsuba::BSDF::Conductor(bsdf)
+        | mitsuba::BSDF::RoughConductor(bsdf)
+        | mitsuba::BSDF::Dielectric(bsdf)
+        | mitsuba::BSDF::RoughDielectric(bsdf)
+        | mitsuba::BSDF::Plastic(bsdf)
+        | mitsuba::BSDF::RoughPlastic(bsdf)
+        | mitsuba::BSDF::ThinDielectric(bsdf)
+        | mitsuba::BSDF::Principled(bsdf)
+        | mitsuba::BSDF::Hair(bsdf) => textures.extend(&bsdf.texture),
+        mitsuba::BSDF::BumpMap(bsdf) => {
+            textures.push(&bsdf.texture);
+            nested.push(bsdf.bsdf.as_ref());
+        }
+        mitsuba::BSDF::Coating(bsdf) => nested.push(bsdf.bsdf.as_ref()),
+        mitsuba::BSDF::Blend(bsdf) => {
+            textures.extend(&bsdf.texture);
+            nested.extend(&bsdf.bsdf);
+        }
+    }
+
+    let mut bitmaps: Vec<&str> = textures
+        .into_iter()
+        .filter_map(|texture| match texture {
+            mitsuba::Texture::BitMap { string_params } => {
+                string_params.get("filename").map(String::as_str)
+            }
+            _ => None,
+        })
+        .collect();
+    for bsdf in nested {
+        bitmaps.extend(bsdf_bitmaps(bsdf));
+    }
+
+    bitmaps
+}
+
+fn validate_mitsuba_bsdf(
+    resolver: &AssetResolver,
+    name: &str,
+    bsdf: &mitsuba::BSDF,
+    issues: &mut Vec<Issue>,
+) {
+    for filename in bsdf_bitmaps(bsdf) {
+        let path = resolver.resolve(filename);
+        if !path.exists() {
+            issues.push(Issue::MissingTexture {
+                material: name.to_string(),
+                path,
+            });
+        }
+    }
+}
+
+fn validate_mitsuba(resolver: &AssetResolver) -> Result<Vec<Issue>> {
+    let xml = std::fs::read_to_string(resolver.scene_path())
+        .with_context(|| format!("reading {:?}", resolver.scene_path()))?;
+    let scene: mitsuba::Scene = quick_xml::de::from_str(&mitsuba::substitute_defaults(&xml))?;
+    let mut issues = Vec::new();
+
+    let sensor = &scene.sensor;
+    let fov = sensor.float_params.get("fov");
+    let film_size = |name: &str, default: i32| {
+        sensor
+            .film
+            .integer_params
+            .get(name)
+            .cloned()
+            .unwrap_or(default)
+    };
+    let reason = if !is_finite(&sensor.transform) {
+        Some("transform is not finite")
+    } else if fov.map_or(false, |fov| *fov <= 0.0 || *fov >= 180.0) {
+        Some("field of view out of range")
+    } else if film_size("width", 768) <= 0 || film_size("height", 576) <= 0 {
+        Some("film has no area")
+    } else if na::try_convert::<_, na::Similarity3<f32>>(sensor.transform).is_none() {
+        Some("transform is not a similarity")
+    } else {
+        None
+    };
+    if let Some(reason) = reason {
+        issues.push(Issue::DegenerateCamera {
+            camera: "sensor".to_string(),
+            reason,
+        });
+    }
+
+    for (id, bsdf) in &scene.bsdfs {
+        validate_mitsuba_bsdf(resolver, &format!("{:?}", id), bsdf, &mut issues);
+    }
+
+    for (idx, shape) in scene.shapes.iter().enumerate() {
+        let (name, transform, material, bsdf, emitter) = match shape {
+            mitsuba::Shape::Rectangle {
+                transform,
+                material,
+                bsdf,
+                emitter,
+            } => ("rectangle", *transform, material, bsdf, emitter.as_ref()),
+            mitsuba::Shape::Cube {
+                transform,
+                material,
+                bsdf,
+                emitter,
+            } => ("cube", *transform, material, bsdf, emitter.as_ref()),
+            mitsuba::Shape::Disk {
+                transform,
+                material,
+                bsdf,
+                emitter,
+            } => ("disk", *transform, material, bsdf, emitter.as_ref()),
+            mitsuba::Shape::Cylinder {
+                transform,
+                material,
+                bsdf,
+                emitter,
+                ..
+            } => ("cylinder", *transform, material, bsdf, emitter.as_ref()),
+            mitsuba::Shape::Sphere {
+                material,
+                bsdf,
+                emitter,
+                ..
+            } => (
+                "sphere",
+                na::Projective3::identity(),
+                material,
+                bsdf,
+                emitter.as_ref(),
+            ),
+            mitsuba::Shape::Obj {
+                transform,
+                material,
+                bsdf,
+                emitter,
+                ..
+            } => ("obj", *transform, material, bsdf, emitter.as_ref()),
+            mitsuba::Shape::Ply {
+                transform,
+                material,
+                bsdf,
+                emitter,
+                ..
+            } => ("ply", *transform, material, bsdf, emitter.as_ref()),
+            mitsuba::Shape::Unsupported => continue,
+            mitsuba::Shape::Hair {
+                transform,
+                material,
+                bsdf,
+                ..
+            } => ("hair", *transform, material, bsdf, None),
+        };
+        let name = format!("{} #{}", name, idx);
+        if !is_finite(&transform) {
+            issues.push(Issue::NonFiniteTransform { node: name });
+            continue;
+        }
+
+        let material_name = match material {
+            Some(material) => format!("{:?}", material.id),
+            None => format!("embedded in {}", name),
+        };
+        let bitmaps = if let Some(bsdf) = bsdf {
+            validate_mitsuba_bsdf(resolver, &material_name, bsdf, &mut issues);
+            bsdf_bitmaps(bsdf)
+        } else {
+            material
+                .as_ref()
+                .and_then(|material| scene.bsdfs.get(&material.id))
+                .map_or(vec![], bsdf_bitmaps)
+        };
+
+        let mesh = match shape {
+            mitsuba::Shape::Rectangle { .. } => mitsuba::gen_rectangle(),
+            mitsuba::Shape::Cube { .. } => mitsuba::gen_cube(),
+            mitsuba::Shape::Disk { .. } => mitsuba::gen_disk(),
+            mitsuba::Shape::Cylinder { radius, .. } => mitsuba::gen_cylinder(radius.value),
+            mitsuba::Shape::Sphere { point, radius, .. } => {
+                mitsuba::gen_sphere(point, radius.value)
+            }
+            mitsuba::Shape::Obj { filename, .. } => {
+                let path = resolver.resolve(filename);
+                if !path.exists() {
+                    return Err(anyhow!("{} references missing mesh {:?}", name, path));
+                }
+                mitsuba::load_obj(&path)
+            }
+            mitsuba::Shape::Ply { filename, .. } => {
+                let path = resolver.resolve(filename);
+                if !path.exists() {
+                    return Err(anyhow!("{} references missing mesh {:?}", name, path));
+                }
+                ply::load_ply(&path)?
+            }
+            // curves have no uvs and can't emit
+            mitsuba::Shape::Hair { .. } | mitsuba::Shape::Unsupported => continue,
+        };
+
+        if let Some(mitsuba::Emitter::Area { rgb }) = emitter {
+            if rgb.iter().all(|v| *v <= 0.0) {
+                issues.push(Issue::ZeroIntensityLight {
+                    light: format!("on {}", name),
+                });
+            }
+            let triangles = mesh
+                .indices
+                .iter()
+                .map(|t| [t[0] as usize, t[1] as usize, t[2] as usize]);
+            if mesh_area(triangles, &mesh.pos, &transform) <= 0.0 {
+                issues.push(Issue::ZeroAreaEmitter { mesh: name.clone() });
+            }
+        }
+
+        // analytic shapes are rendered with their own parametric uvs
+        let analytic = matches!(
+            shape,
+            mitsuba::Shape::Rectangle { .. }
+                | mitsuba::Shape::Disk { .. }
+                | mitsuba::Shape::Cylinder { .. }
+                | mitsuba::Shape::Sphere { .. }
+        );
+        if mesh.uv.is_empty() && !analytic && !bitmaps.is_empty() {
+            issues.push(Issue::MissingUVs {
+                mesh: name,
+                material: material_name,
+            });
+        }
+    }
+
+    for emitter in &scene.emitters {
+        if let mitsuba::Emitter::EnvMap { filename, .. } = emitter {
+            let path = resolver.resolve(filename);
+            if !path.exists() {
+                issues.push(Issue::MissingTexture {
+                    material: "of the environment map".to_string(),
+                    path,
+                });
+            }
+        }
+    }
+
+    Ok(issues)
+}
+
+/// image files of the textures a pbrt material binds directly
+fn pbrt_material_images(
+    resolver: &AssetResolver,
+    scene: &pbrt::Scene,
+    material: &pbrt::Material,
+) -> Vec<PathBuf> {
+    material
+        .params
+        .params
+        .iter()
+        .filter(|param| param.kind == "texture")
+        .filter_map(|param| material.params.texture(&param.name))
+        .filter_map(|name| {
+            scene
+                .spectrum_textures
+                .get(name)
+                .or_else(|| scene.float_textures.get(name))
+        })
+        .filter(|texture| texture.class == "imagemap")
+        .filter_map(|texture| texture.params.string("filename"))
+        .map(|filename| resolver.resolve(filename))
+        .collect()
+}
+
+fn validate_pbrt(log: &slog::Logger, resolver: &AssetResolver) -> Result<Vec<Issue>> {
+    let scene = pbrt::load_scene(log, resolver.clone(), None)
+        .with_context(|| format!("reading {:?}", resolver.scene_path()))?;
+    let mut issues = Vec::new();
+
+    let camera = &scene.camera;
+    let fov = camera.params.float("fov").unwrap_or(90.0);
+    let film_size = |name: &str| scene.film.params.integer(name).unwrap_or(1);
+    let reason = if !scene.camera_to_world.iter().all(|v| v.is_finite()) {
+        Some("transform is not finite")
+    } else if camera.kind == "perspective" && (fov <= 0.0 || fov >= 180.0) {
+        Some("field of view out of range")
+    } else if film_size("xresolution") <= 0 || film_size("yresolution") <= 0 {
+        Some("film has no area")
+    } else {
+        None
+    };
+    if let Some(reason) = reason {
+        issues.push(Issue::DegenerateCamera {
+            camera: format!("{:?}", camera.kind),
+            reason,
+        });
+    }
+
+    for (idx, material) in scene.materials.iter().enumerate() {
+        let name = match &material.name {
+            Some(name) => format!("{:?}", name),
+            None => format!("{} #{}", material.kind, idx),
+        };
+        for path in pbrt_material_images(resolver, &scene, material) {
+            if !path.exists() {
+                issues.push(Issue::MissingTexture {
+                    material: name.clone(),
+                    path,
+                });
+            }
+        }
+    }
+
+    for (idx, shape) in scene.shapes.iter().enumerate() {
+        let name = format!("{} #{}", shape.kind, idx);
+        if !is_finite(&shape.obj_to_world) {
+            issues.push(Issue::NonFiniteTransform { node: name });
+            continue;
+        }
+        if let Some(area_light) = &shape.area_light {
+            let l = area_light.rgb("L").unwrap_or([1.0; 3]);
+            let scale = area_light.float("scale").unwrap_or(1.0);
+            if scale <= 0.0 || l.iter().all(|v| *v <= 0.0) {
+                issues.push(Issue::ZeroIntensityLight {
+                    light: format!("on {}", name),
+                });
+            }
+        }
+    }
+
+    for (idx, light) in scene.lights.iter().enumerate() {
+        let name = format!("{} #{}", light.kind, idx);
+        if !is_finite(&light.light_to_world) {
+            issues.push(Issue::NonFiniteTransform { node: name });
+            continue;
+        }
+        let intensity = light
+            .params
+            .rgb("I")
+            .or_else(|| light.params.rgb("L"))
+            .unwrap_or([1.0; 3]);
+        let scale = light.params.float("scale").unwrap_or(1.0);
+        if scale <= 0.0 || intensity.iter().all(|v| *v <= 0.0) {
+            issues.push(Issue::ZeroIntensityLight {
+                light: name.clone(),
+            });
+        }
+        if let Some(filename) = light
+            .params
+            .string("filename")
+            .or_else(|| light.params.string("mapname"))
+        {
+            let path = resolver.resolve(filename);
+            if !path.exists() {
+                issues.push(Issue::MissingTexture {
+                    material: format!("of {}", name),
+                    path,
+                });
+            }
+        }
+    }
+
+    Ok(issues)
+}
+
+/// checks the scene a description references or the meshes it places, along with the lights and
+/// the camera the description adds
+fn validate_description(
+    log: &slog::Logger,
+    resolver: &AssetResolver,
+    search_paths: &[std::path::PathBuf],
+) -> Result<Vec<Issue>> {
+    let description = description::SceneDescription::load(resolver.scene_path())?;
+    let mut issues = Vec::new();
+
+    if let Some(scene) = &description.scene {
+        issues.extend(validate(
+            log,
+            &resolver.resolve(scene).to_string_lossy(),
+            search_paths,
+        )?);
+    } else {
+        for (idx, mesh) in description.meshes.iter().enumerate() {
+            let path = resolver.resolve(&mesh.file);
+            if !path.exists() {
+                return Err(anyhow!("mesh #{} references missing mesh {:?}", idx, path));
+            }
+            if !is_finite(&mesh.transform.matrix()) {
+                issues.push(Issue::NonFiniteTransform {
+                    node: format!("mesh #{}", idx),
+                });
+            }
+        }
+    }
+
+    for (idx, light) in description.lights.iter().enumerate() {
+        let name = format!("#{} of the description", idx);
+        let (color, intensity) = match light {
+            description::LightDescription::Environment {
+                file,
+                color,
+                intensity,
+                ..
+            } => {
+                if let Some(file) = file {
+                    let path = resolver.resolve(file);
+                    if !path.exists() {
+                        issues.push(Issue::MissingTexture {
+                            material: format!("of light {}", name),
+                            path,
+                        });
+                    }
+                }
+                (color, intensity)
+            }
+            description::LightDescription::Point {
+                color, intensity, ..
+            }
+            | description::LightDescription::Directional {
+                color, intensity, ..
+            } => (color, intensity),
+        };
+        if *intensity <= 0.0 || color.iter().all(|v| *v <= 0.0) {
+            issues.push(Issue::ZeroIntensityLight { light: name });
+        }
+    }
+
+    let camera = &description.camera;
+    let reason = if camera
+        .position
+        .map_or(false, |position| position == camera.target)
+    {
+        Some("position and target coincide")
+    } else if camera.fov.map_or(false, |fov| fov <= 0.0 || fov >= 180.0) {
+        Some("field of view out of range")
+    } else {
+        None
+    };
+    if let Some(reason) = reason {
+        issues.push(Issue::DegenerateCamera {
+            camera: "of the description".to_string(),
+            reason,
+        });
+    }
+
+    Ok(issues)
+}
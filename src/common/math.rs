@@ -183,6 +183,61 @@ pub fn spherical_phi(v: &na::Vector3<f32>) -> f32 {
     }
 }
 
+/// direction at the spherical coordinates given in the frame `x`, `y`, `z`
+pub fn spherical_direction(
+    sin_theta: f32,
+    cos_theta: f32,
+    phi: f32,
+    x: &na::Vector3<f32>,
+    y: &na::Vector3<f32>,
+    z: &na::Vector3<f32>,
+) -> na::Vector3<f32> {
+    sin_theta * phi.cos() * x + sin_theta * phi.sin() * y + cos_theta * z
+}
+
+/// clarberg's equal-area octahedral mapping from the unit square to the unit sphere, every region
+/// of the square covers a solid angle proportional to its area
+pub fn equal_area_square_to_sphere(p: &na::Point2<f32>) -> na::Vector3<f32> {
+    let u = 2.0 * p.x - 1.0;
+    let v = 2.0 * p.y - 1.0;
+    let (up, vp) = (u.abs(), v.abs());
+
+    // signed distance from the diagonal separating the upper and lower hemisphere
+    let signed_distance = 1.0 - (up + vp);
+    let r = 1.0 - signed_distance.abs();
+    let phi = if r == 0.0 { 1.0 } else { (vp - up) / r + 1.0 } * std::f32::consts::FRAC_PI_4;
+
+    let z = (1.0 - r * r).copysign(signed_distance);
+    let cos_phi = phi.cos().copysign(u);
+    let sin_phi = phi.sin().copysign(v);
+    let scale = r * (2.0 - r * r).max(0.0).sqrt();
+
+    na::Vector3::new(cos_phi * scale, sin_phi * scale, z)
+}
+
+/// inverse of `equal_area_square_to_sphere`, `d` must be normalized
+pub fn equal_area_sphere_to_square(d: &na::Vector3<f32>) -> na::Point2<f32> {
+    let (x, y, z) = (d.x.abs(), d.y.abs(), d.z.abs());
+    let r = (1.0 - z).max(0.0).sqrt();
+
+    let a = x.max(y);
+    let b = if a == 0.0 { 0.0 } else { x.min(y) / a };
+    let mut phi = b.atan() * std::f32::consts::FRAC_2_PI;
+    if x < y {
+        phi = 1.0 - phi;
+    }
+
+    let mut v = phi * r;
+    let mut u = r - v;
+    if d.z < 0.0 {
+        std::mem::swap(&mut u, &mut v);
+        u = 1.0 - u;
+        v = 1.0 - v;
+    }
+
+    na::Point2::new(0.5 * (u.copysign(d.x) + 1.0), 0.5 * (v.copysign(d.y) + 1.0))
+}
+
 pub fn find_interval<T: Fn(usize) -> bool>(size: usize, pred: T) -> usize {
     let mut first = 0;
     let mut len = size;
@@ -253,6 +308,55 @@ where
     x * (T::one() - a) + y * a
 }
 
+/// approximation of the error function (Abramowitz and Stegun 7.1.26)
+pub fn erf(x: f32) -> f32 {
+    let a1 = 0.254829592;
+    let a2 = -0.284496736;
+    let a3 = 1.421413741;
+    let a4 = -1.453152027;
+    let a5 = 1.061405429;
+    let p = 0.3275911;
+
+    let sign = x.signum();
+    let x = x.abs();
+
+    let t = 1.0 / (1.0 + p * x);
+    let y = 1.0 - (((((a5 * t + a4) * t) + a3) * t + a2) * t + a1) * t * (-x * x).exp();
+
+    sign * y
+}
+
+/// approximation of the inverse error function (Giles 2010)
+pub fn erf_inv(x: f32) -> f32 {
+    let x = x.clamp(-0.99999, 0.99999);
+    let mut w = -((1.0 - x) * (1.0 + x)).ln();
+    let mut p;
+    if w < 5.0 {
+        w -= 2.5;
+        p = 2.81022636e-08;
+        p = 3.43273939e-07 + p * w;
+        p = -3.5233877e-06 + p * w;
+        p = -4.39150654e-06 + p * w;
+        p = 0.00021858087 + p * w;
+        p = -0.00125372503 + p * w;
+        p = -0.00417768164 + p * w;
+        p = 0.246640727 + p * w;
+        p = 1.50140941 + p * w;
+    } else {
+        w = w.sqrt() - 3.0;
+        p = -0.000200214257;
+        p = 0.000100950558 + p * w;
+        p = 0.00134934322 + p * w;
+        p = -0.00367342844 + p * w;
+        p = 0.00573950773 + p * w;
+        p = -0.0076224613 + p * w;
+        p = 0.00943887047 + p * w;
+        p = 1.00167406 + p * w;
+        p = 2.83297682 + p * w;
+    }
+    p * x
+}
+
 pub fn cantor_pairing(x: usize, y: usize) -> usize {
     (x + y) * (x + y + 1) / 2 + y
 }
@@ -297,4 +401,32 @@ mod tests {
         let x = x.unwrap();
         assert_eq!(x, glm::vec2(0.0, 2.0));
     }
+
+    #[test]
+    fn test_equal_area_mapping() {
+        for i in 0..16 {
+            for j in 0..16 {
+                let p = na::Point2::new((i as f32 + 0.5) / 16.0, (j as f32 + 0.5) / 16.0);
+                let d = equal_area_square_to_sphere(&p);
+                assert!((d.norm() - 1.0).abs() < 1e-4);
+
+                let q = equal_area_sphere_to_square(&d);
+                assert!((p - q).norm() < 1e-4);
+            }
+        }
+
+        // each quadrant of the square covers one octant of the upper and lower hemisphere
+        let d = equal_area_square_to_sphere(&na::Point2::new(0.5, 0.5));
+        assert!((d.z - 1.0).abs() < 1e-6);
+        let d = equal_area_square_to_sphere(&na::Point2::new(1.0, 1.0));
+        assert!((d.z + 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_erf_inv() {
+        for i in -20..=20 {
+            let x = i as f32 * 0.1;
+            assert!((erf_inv(erf(x)) - x).abs() < 1e-3);
+        }
+    }
 }
@@ -1,65 +1,308 @@
 pub mod bounds;
+pub mod exporter;
 pub mod film;
 pub mod filter;
 pub mod importer;
 pub mod math;
+pub mod openexr;
 pub mod ray;
 pub mod spectrum;
+pub mod tonemap;
+pub mod transform;
 
+use crate::pathtracer::lens::LensSystem;
 use film::Film;
 use filter::{Filter, GuassianFilter};
 use slog::Drain;
+use std::sync::Arc;
 
 lazy_static::lazy_static! {
     pub static ref DEFAULT_RESOLUTION: glm::Vec2 = glm::vec2(640.0, 480.0);
 }
 
-static DEFAULT_Z_NEAR: f32 = 0.01;
-static DEFAULT_Z_FAR: f32 = 1000.0;
+pub static DEFAULT_Z_NEAR: f32 = 0.01;
+pub static DEFAULT_Z_FAR: f32 = 1000.0;
+
+/// animation times in seconds the shutter opens and closes at, rays are spread across them
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Shutter {
+    pub open: f32,
+    pub close: f32,
+}
+
+impl Shutter {
+    /// the time a fraction `u` of the way through the exposure
+    pub fn time(&self, u: f32) -> f32 {
+        self.open + u * (self.close - self.open)
+    }
+}
+
+/// how the camera maps its space onto the screen
+#[derive(Clone, Debug)]
+pub enum Projection {
+    Perspective(na::Perspective3<f32>),
+    /// parallel rays along the view direction, for technical drawings and isometric views
+    Orthographic(na::Orthographic3<f32>),
+    /// every direction around the camera on an equirect film, with the view direction in the
+    /// middle and up at the top. the real time preview shows `preview` instead
+    Spherical {
+        preview: na::Perspective3<f32>,
+    },
+    /// equidistant fisheye, the angle off the view direction growing with the distance from the
+    /// center of the film up to half of `fov` (in radians) at the corners
+    Fisheye {
+        fov: f32,
+        preview: na::Perspective3<f32>,
+    },
+    /// rays traced through the elements of a real lens in front of the film
+    Realistic {
+        lens: Arc<LensSystem>,
+        preview: na::Perspective3<f32>,
+    },
+}
+
+impl Projection {
+    /// orthographic projection showing `height` of the scene from bottom to top
+    pub fn orthographic(aspect: f32, height: f32, znear: f32, zfar: f32) -> Self {
+        let (half_width, half_height) = (0.5 * height * aspect, 0.5 * height);
+        Projection::Orthographic(na::Orthographic3::new(
+            -half_width,
+            half_width,
+            -half_height,
+            half_height,
+            znear,
+            zfar,
+        ))
+    }
+
+    /// the perspective projection the real time preview shows for `self`, a right angle wide
+    /// for the projections without one
+    pub fn preview(&self, aspect: f32) -> na::Perspective3<f32> {
+        match self {
+            Projection::Perspective(preview)
+            | Projection::Spherical { preview }
+            | Projection::Fisheye { preview, .. }
+            | Projection::Realistic { preview, .. } => *preview,
+            Projection::Orthographic(orthographic) => na::Perspective3::new(
+                aspect,
+                std::f32::consts::FRAC_PI_2,
+                orthographic.znear().max(DEFAULT_Z_NEAR),
+                orthographic.zfar(),
+            ),
+        }
+    }
+
+    pub fn to_projective(&self) -> na::Projective3<f32> {
+        match self {
+            Projection::Perspective(perspective) => perspective.to_projective(),
+            Projection::Orthographic(orthographic) => orthographic.to_projective(),
+            Projection::Spherical { preview }
+            | Projection::Fisheye { preview, .. }
+            | Projection::Realistic { preview, .. } => preview.to_projective(),
+        }
+    }
+
+    pub fn to_homogeneous(&self) -> na::Matrix4<f32> {
+        match self {
+            Projection::Perspective(perspective) => perspective.to_homogeneous(),
+            Projection::Orthographic(orthographic) => orthographic.to_homogeneous(),
+            Projection::Spherical { preview }
+            | Projection::Fisheye { preview, .. }
+            | Projection::Realistic { preview, .. } => preview.to_homogeneous(),
+        }
+    }
+
+    pub fn project_point(&self, p: &na::Point3<f32>) -> na::Point3<f32> {
+        match self {
+            Projection::Perspective(perspective) => perspective.project_point(p),
+            Projection::Orthographic(orthographic) => orthographic.project_point(p),
+            Projection::Spherical { preview }
+            | Projection::Fisheye { preview, .. }
+            | Projection::Realistic { preview, .. } => preview.project_point(p),
+        }
+    }
+
+    pub fn unproject_point(&self, p: &na::Point3<f32>) -> na::Point3<f32> {
+        match self {
+            Projection::Perspective(perspective) => perspective.unproject_point(p),
+            Projection::Orthographic(orthographic) => orthographic.unproject_point(p),
+            Projection::Spherical { preview }
+            | Projection::Fisheye { preview, .. }
+            | Projection::Realistic { preview, .. } => preview.unproject_point(p),
+        }
+    }
+}
 
 pub struct Camera {
     pub cam_to_world: na::Isometry3<f32>,
-    pub cam_to_screen: na::Perspective3<f32>,
+    pub cam_to_screen: Projection,
     pub screen_to_raster: na::Affine3<f32>,
     pub raster_to_screen: na::Affine3<f32>,
 
-    pub dx_camera: na::Vector3<f32>,
-    pub dy_camera: na::Vector3<f32>,
-
     pub film: Film,
+
+    pub shutter: Shutter,
+    /// movement of the camera in its own space while the shutter is open, kept relative so
+    /// moving the camera around keeps the blur
+    pub motion: na::Isometry3<f32>,
+
+    /// radius of the thin lens, 0 for a pinhole with everything in focus
+    pub lens_radius: f32,
+    /// distance along the view direction of the plane in focus
+    pub focal_distance: f32,
+    /// offset of the film in fractions of its width and height, moving the framing without
+    /// turning the camera so verticals stay parallel
+    pub lens_shift: na::Vector2<f32>,
+    /// angles in radians the plane in focus is turned about the x and y axes by
+    pub lens_tilt: na::Vector2<f32>,
 }
 
 impl Camera {
     pub fn new(
         cam_to_world: &na::Isometry3<f32>,
-        cam_to_screen: &na::Perspective3<f32>,
+        cam_to_screen: &Projection,
         resolution: &glm::Vec2,
     ) -> Camera {
-        let screen_to_raster = glm::scaling(&glm::vec3(resolution.x, resolution.y, 1.0))
-            * glm::scaling(&glm::vec3(1.0 / (2.0), 1.0 / (-2.0), 1.0))
-            * glm::translation(&glm::vec3(1.0, -1.0, 0.0));
-        let screen_to_raster = na::Affine3::from_matrix_unchecked(screen_to_raster);
+        let (screen_to_raster, raster_to_screen) =
+            Self::raster_transforms(resolution, &na::Vector2::zeros());
         let resolution = glm::vec2(resolution.x as u32, resolution.y as u32);
-        let raster_to_screen = screen_to_raster.inverse();
-        let raster_to_camera = cam_to_screen.to_projective().inverse() * raster_to_screen;
-        let dx_camera = raster_to_camera * na::Point3::new(1.0, 0.0, 0.0)
-            - raster_to_camera * na::Point3::origin();
-        let dy_camera = raster_to_camera * na::Point3::new(0.0, 1.0, 0.0)
-            - raster_to_camera * na::Point3::origin();
 
         Self {
             cam_to_world: *cam_to_world,
-            cam_to_screen: *cam_to_screen,
+            cam_to_screen: cam_to_screen.clone(),
             screen_to_raster,
             raster_to_screen,
-            dx_camera,
-            dy_camera,
             film: Film::new(
                 &resolution,
-                Box::new(Filter::Guassian(GuassianFilter::new(2.))),
+                Box::new(Filter::Guassian(GuassianFilter::new(
+                    &na::Vector2::new(2.0, 2.0),
+                    2.0,
+                ))),
             ),
+            shutter: Shutter::default(),
+            motion: na::Isometry3::identity(),
+            lens_radius: 0.0,
+            focal_distance: 1e6,
+            lens_shift: na::Vector2::zeros(),
+            lens_tilt: na::Vector2::zeros(),
         }
     }
+
+    /// screen to raster and back for a film of `resolution` shifted by `lens_shift`
+    fn raster_transforms(
+        resolution: &glm::Vec2,
+        lens_shift: &na::Vector2<f32>,
+    ) -> (na::Affine3<f32>, na::Affine3<f32>) {
+        let screen_to_raster = glm::scaling(&glm::vec3(resolution.x, resolution.y, 1.0))
+            * glm::scaling(&glm::vec3(1.0 / (2.0), 1.0 / (-2.0), 1.0))
+            * glm::translation(&glm::vec3(1.0, -1.0, 0.0))
+            * glm::translation(&glm::vec3(-2.0 * lens_shift.x, -2.0 * lens_shift.y, 0.0));
+        let screen_to_raster = na::Affine3::from_matrix_unchecked(screen_to_raster);
+        (screen_to_raster, screen_to_raster.inverse())
+    }
+
+    /// the same camera seeing through `cam_to_screen` instead
+    pub fn with_projection(mut self, cam_to_screen: Projection) -> Self {
+        self.cam_to_screen = cam_to_screen;
+        self
+    }
+
+    /// the same camera seeing all around it on an equirect film
+    pub fn spherical(self) -> Self {
+        let preview = self.cam_to_screen.preview(self.film_aspect());
+        self.with_projection(Projection::Spherical { preview })
+    }
+
+    /// the same camera seeing through a fisheye lens `fov` radians wide across the film diagonal
+    pub fn fisheye(self, fov: f32) -> Self {
+        let preview = self.cam_to_screen.preview(self.film_aspect());
+        self.with_projection(Projection::Fisheye { fov, preview })
+    }
+
+    /// the same camera seeing through `lens`
+    pub fn realistic(self, lens: LensSystem) -> Self {
+        let preview = self.cam_to_screen.preview(self.film_aspect());
+        self.with_projection(Projection::Realistic {
+            lens: Arc::new(lens),
+            preview,
+        })
+    }
+
+    fn film_aspect(&self) -> f32 {
+        self.film.resolution.x as f32 / self.film.resolution.y as f32
+    }
+
+    /// camera with a thin lens of `lens_radius` focused at `focal_distance`, blurring what's
+    /// nearer or farther
+    pub fn with_lens(mut self, lens_radius: f32, focal_distance: f32) -> Self {
+        self.lens_radius = lens_radius;
+        self.focal_distance = focal_distance;
+        self
+    }
+
+    /// camera reconstructing its image with `filter`, on a fresh film
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.film = Film::new(&self.film.resolution, Box::new(filter));
+        self
+    }
+
+    /// camera with its film shifted by `lens_shift` and the plane in focus tilted by `lens_tilt`,
+    /// as through a tilt-shift lens
+    pub fn with_tilt_shift(
+        mut self,
+        lens_shift: &na::Vector2<f32>,
+        lens_tilt: &na::Vector2<f32>,
+    ) -> Self {
+        let resolution = glm::vec2(self.film.resolution.x as f32, self.film.resolution.y as f32);
+        let (screen_to_raster, raster_to_screen) = Self::raster_transforms(&resolution, lens_shift);
+        self.screen_to_raster = screen_to_raster;
+        self.raster_to_screen = raster_to_screen;
+        self.lens_shift = *lens_shift;
+        self.lens_tilt = *lens_tilt;
+        self
+    }
+
+    /// normal of the plane in focus, facing away from the camera
+    pub fn focal_plane_normal(&self) -> na::Vector3<f32> {
+        na::Rotation3::from_euler_angles(self.lens_tilt.x, self.lens_tilt.y, 0.0)
+            * -na::Vector3::z()
+    }
+
+    /// projection of the real time preview, shifted along with the film
+    pub fn preview_projection(&self) -> na::Matrix4<f32> {
+        glm::translation(&glm::vec3(
+            -2.0 * self.lens_shift.x,
+            -2.0 * self.lens_shift.y,
+            0.0,
+        )) * self.cam_to_screen.to_homogeneous()
+    }
+
+    /// camera moving to `cam_to_world_close` by the time the shutter closes
+    pub fn with_motion(
+        mut self,
+        shutter: Shutter,
+        cam_to_world_close: &na::Isometry3<f32>,
+    ) -> Self {
+        self.shutter = shutter;
+        self.motion = self.cam_to_world.inverse() * cam_to_world_close;
+        self
+    }
+
+    /// where the camera is a fraction `u` of the way through the exposure
+    pub fn cam_to_world_at(&self, u: f32) -> na::Isometry3<f32> {
+        if u == 0.0 {
+            return self.cam_to_world;
+        }
+
+        let rotation = na::UnitQuaternion::identity()
+            .try_slerp(&self.motion.rotation, u, 1e-6)
+            .unwrap_or_else(na::UnitQuaternion::identity);
+        self.cam_to_world
+            * na::Isometry3::from_parts(
+                na::Translation3::from(u * self.motion.translation.vector),
+                rotation,
+            )
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -90,12 +333,12 @@ mod tests {
     fn cam_with_look_at(eye: &na::Point3<f32>, center: &na::Point3<f32>) -> Camera {
         Camera::new(
             &na::Isometry3::look_at_rh(eye, center, &glm::vec3(0.0, 1.0, 0.0)).inverse(),
-            &na::Perspective3::new(
+            &Projection::Perspective(na::Perspective3::new(
                 DEFAULT_RESOLUTION.x / DEFAULT_RESOLUTION.y,
                 std::f32::consts::FRAC_PI_2,
                 DEFAULT_Z_NEAR,
                 DEFAULT_Z_FAR,
-            ),
+            )),
             &DEFAULT_RESOLUTION,
         )
     }
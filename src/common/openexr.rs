@@ -0,0 +1,67 @@
+use super::film::Film;
+use exr::prelude::*;
+use std::path::Path;
+
+/// bits per channel of saved openexr images
+#[derive(Clone, Copy, Debug)]
+pub enum ExrPrecision {
+    /// half floats, half the size and plenty for display
+    Half,
+    Float,
+}
+
+/// saves the linear radiance of `films` as one openexr image, each film a layer under its name,
+/// e.g. the render with its aovs. a single film is saved as a plain rgb image. films writing
+/// alpha get an alpha channel, their colors premultiplied by it as compositors expect
+pub fn save_exr(path: &Path, films: &[(&str, &Film)], precision: ExrPrecision) -> Result<()> {
+    let resolution = films[0].1.resolution;
+    let layers = films
+        .iter()
+        .map(|(name, film)| {
+            assert_eq!(
+                film.resolution, resolution,
+                "the layers of an exr image share its resolution"
+            );
+            let pixels = film.to_rgb_floats();
+            let channel = |values: Vec<f32>, channel_name: &str| {
+                let samples = match precision {
+                    ExrPrecision::Half => {
+                        FlatSamples::F16(values.into_iter().map(f16::from_f32).collect())
+                    }
+                    ExrPrecision::Float => FlatSamples::F32(values),
+                };
+                AnyChannel::new(channel_name, samples)
+            };
+            let color = |i: usize| pixels.iter().map(|pixel| pixel[i]).collect::<Vec<_>>();
+            let mut channels = vec![
+                channel(color(0), "R"),
+                channel(color(1), "G"),
+                channel(color(2), "B"),
+            ];
+            if film.write_alpha() {
+                channels.push(channel(film.to_alpha(), "A"));
+            }
+            let attributes = if films.len() == 1 {
+                LayerAttributes::default()
+            } else {
+                LayerAttributes::named(*name)
+            };
+
+            Layer::new(
+                (resolution.x as usize, resolution.y as usize),
+                attributes,
+                Encoding::FAST_LOSSLESS,
+                AnyChannels::sort(channels.into()),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let image = Image::from_layers(
+        ImageAttributes::new(IntegerBounds::from_dimensions((
+            resolution.x as usize,
+            resolution.y as usize,
+        ))),
+        layers,
+    );
+    image.write().to_file(path)
+}
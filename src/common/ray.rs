@@ -3,6 +3,8 @@ pub struct Ray {
     pub o: na::Point3<f32>,
     pub d: na::Vector3<f32>,
     pub t_max: f32,
+    /// animation time the ray is traced at
+    pub time: f32,
 }
 
 #[derive(Clone, Debug)]
@@ -125,6 +125,14 @@ impl RGBSpectrum {
             self.b().sqrt(),
         ))
     }
+
+    pub fn exp(&self) -> Self {
+        Self(na::Vector3::new(
+            self.r().exp(),
+            self.g().exp(),
+            self.b().exp(),
+        ))
+    }
 }
 
 impl AddAssign for RGBSpectrum {
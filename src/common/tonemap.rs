@@ -0,0 +1,164 @@
+use super::{math::gamma_correct, spectrum::Spectrum};
+
+/// curve compressing the exposed radiance into the range a display shows, applied before the
+/// encoding of ldr images
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapper {
+    /// clips everything brighter than white, leaving the rest as it is
+    Clamp,
+    Reinhard,
+    /// john hable's curve from uncharted 2
+    Filmic,
+    /// stephen hill's fit of the aces reference rendering and sdr output transforms
+    Aces,
+    /// the agx base look of blender, desaturating highlights towards white instead of skewing
+    /// their hue
+    Agx,
+}
+
+impl ToneMapper {
+    pub const ALL: [ToneMapper; 5] = [
+        ToneMapper::Clamp,
+        ToneMapper::Reinhard,
+        ToneMapper::Filmic,
+        ToneMapper::Aces,
+        ToneMapper::Agx,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "clamp" => Some(ToneMapper::Clamp),
+            "reinhard" => Some(ToneMapper::Reinhard),
+            "filmic" => Some(ToneMapper::Filmic),
+            "aces" => Some(ToneMapper::Aces),
+            "agx" => Some(ToneMapper::Agx),
+            _ => None,
+        }
+    }
+
+    /// the one after this in `ALL`, wrapping around
+    pub fn next(&self) -> Self {
+        let i = Self::ALL.iter().position(|mapper| mapper == self).unwrap();
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+
+    /// linear radiance in [0, 1] for linear radiance `l`
+    pub fn apply(&self, l: &Spectrum) -> Spectrum {
+        let v = na::Vector3::new(l.r(), l.g(), l.b()).map(|c| c.max(0.0));
+        let v = match self {
+            ToneMapper::Clamp => v,
+            ToneMapper::Reinhard => v.map(|c| c / (1.0 + c)),
+            ToneMapper::Filmic => {
+                const EXPOSURE_BIAS: f32 = 2.0;
+                const WHITE: f32 = 11.2;
+                v.map(|c| hable(EXPOSURE_BIAS * c) / hable(WHITE))
+            }
+            ToneMapper::Aces => {
+                // the fit darkens middle gray, brightened back as three.js does
+                let fit = (na::Matrix3::from_row_slice(&ACES_INPUT) * v / 0.6).map(|c| {
+                    (c * (c + 0.0245786) - 0.000090537)
+                        / (c * (0.983729 * c + 0.4329510) + 0.238081)
+                });
+                na::Matrix3::from_row_slice(&ACES_OUTPUT) * fit
+            }
+            ToneMapper::Agx => {
+                // log encoded between these stops around middle gray
+                const MIN_EV: f32 = -12.47393;
+                const MAX_EV: f32 = 4.026069;
+                let encoded = (na::Matrix3::from_row_slice(&AGX_INSET) * v).map(|c| {
+                    let x =
+                        (c.max(1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+                    // polynomial fit of the agx sigmoid
+                    let (x2, x4) = (x * x, x * x * x * x);
+                    15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x
+                        + 0.4298 * x2
+                        + 0.1191 * x
+                        - 0.00232
+                });
+                (na::Matrix3::from_row_slice(&AGX_OUTSET) * encoded).map(|c| c.max(0.0).powf(2.2))
+            }
+        };
+        let v = v.map(|c| c.clamp(0.0, 1.0));
+        Spectrum::from_floats(v.x, v.y, v.z)
+    }
+}
+
+// row major, from linear srgb into the aces working space and back
+#[rustfmt::skip]
+const ACES_INPUT: [f32; 9] = [
+    0.59719, 0.35458, 0.04823,
+    0.07600, 0.90834, 0.01566,
+    0.02840, 0.13383, 0.83777,
+];
+#[rustfmt::skip]
+const ACES_OUTPUT: [f32; 9] = [
+    1.60475, -0.53108, -0.07367,
+    -0.10208, 1.10813, -0.00605,
+    -0.00327, -0.07276, 1.07602,
+];
+
+// row major, into the agx log space and back
+#[rustfmt::skip]
+const AGX_INSET: [f32; 9] = [
+    0.842479062, 0.0784336, 0.0792237451,
+    0.0423282423, 0.878468636, 0.0791661275,
+    0.0423756549, 0.0784336, 0.879142974,
+];
+#[rustfmt::skip]
+const AGX_OUTSET: [f32; 9] = [
+    1.196879, -0.0980208811, -0.0990297441,
+    -0.0528968518, 1.15190313, -0.0989611768,
+    -0.0529716355, -0.0980434501, 1.15107367,
+];
+
+fn hable(x: f32) -> f32 {
+    const A: f32 = 0.15;
+    const B: f32 = 0.50;
+    const C: f32 = 0.10;
+    const D: f32 = 0.20;
+    const E: f32 = 0.02;
+    const F: f32 = 0.30;
+    (x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F) - E / F
+}
+
+/// how the film turns exposed radiance into the values of ldr images
+#[derive(Clone, Copy, Debug)]
+pub struct ToneMap {
+    pub mapper: ToneMapper,
+    /// gamma the values are encoded with, the srgb curve when `None`
+    pub gamma: Option<f32>,
+}
+
+impl Default for ToneMap {
+    fn default() -> Self {
+        Self {
+            mapper: ToneMapper::Clamp,
+            gamma: None,
+        }
+    }
+}
+
+impl ToneMap {
+    /// tone mapped and encoded values in [0, 1] for linear radiance `l`
+    pub fn encode(&self, l: &Spectrum) -> [f32; 3] {
+        let l = self.mapper.apply(l);
+        let encode = |c: f32| match self.gamma {
+            Some(gamma) => c.powf(1.0 / gamma),
+            None => gamma_correct(c),
+        };
+        [encode(l.r()), encode(l.g()), encode(l.b())]
+    }
+
+    pub fn to_image_rgba(&self, l: &Spectrum) -> image::Rgba<u8> {
+        let [r, g, b] = self.encode(l);
+        let quantize = |c: f32| (c * 255.0 + 0.5).clamp(0.0, 255.0) as u8;
+        image::Rgba([quantize(r), quantize(g), quantize(b), 255])
+    }
+
+    /// like `to_image_rgba` for 16 bit images, keeping more of the precision of the encoding
+    pub fn to_image_rgba16(&self, l: &Spectrum) -> image::Rgba<u16> {
+        let [r, g, b] = self.encode(l);
+        let quantize = |c: f32| (c * 65535.0 + 0.5).clamp(0.0, 65535.0) as u16;
+        image::Rgba([quantize(r), quantize(g), quantize(b), 65535])
+    }
+}
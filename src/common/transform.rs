@@ -0,0 +1,161 @@
+use super::bounds::Bounds3;
+
+/// times the motion bounds are sampled at across the shutter, rotations can sweep outside the
+/// bounds at both ends
+const MOTION_BOUND_STEPS: usize = 32;
+
+/// transform moving from `start` at `start_time` to `end` at `end_time`. translation, rotation
+/// and scale are interpolated separately so the shape doesn't shear on the way
+#[derive(Clone, Debug)]
+pub struct AnimatedTransform {
+    start: na::Projective3<f32>,
+    end: na::Projective3<f32>,
+    start_time: f32,
+    end_time: f32,
+    animated: bool,
+    translation: [na::Vector3<f32>; 2],
+    rotation: [na::UnitQuaternion<f32>; 2],
+    scale: [na::Matrix3<f32>; 2],
+}
+
+impl AnimatedTransform {
+    pub fn new(
+        start: &na::Projective3<f32>,
+        start_time: f32,
+        end: &na::Projective3<f32>,
+        end_time: f32,
+    ) -> Self {
+        let (t0, r0, s0) = decompose(start);
+        let (t1, r1, s1) = decompose(end);
+        Self {
+            start: *start,
+            end: *end,
+            start_time,
+            end_time,
+            animated: start.matrix() != end.matrix() && end_time > start_time,
+            translation: [t0, t1],
+            rotation: [r0, r1],
+            scale: [s0, s1],
+        }
+    }
+
+    /// transform that stays put at all times
+    pub fn fixed(transform: &na::Projective3<f32>) -> Self {
+        Self::new(transform, 0.0, transform, 0.0)
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.animated
+    }
+
+    pub fn interpolate(&self, time: f32) -> na::Projective3<f32> {
+        if !self.animated || time <= self.start_time {
+            return self.start;
+        }
+        if time >= self.end_time {
+            return self.end;
+        }
+
+        let dt = (time - self.start_time) / (self.end_time - self.start_time);
+        let translation = (1.0 - dt) * self.translation[0] + dt * self.translation[1];
+        let rotation = self.rotation[0]
+            .try_slerp(&self.rotation[1], dt, 1e-6)
+            .unwrap_or_else(|| self.rotation[0].nlerp(&self.rotation[1], dt));
+        let scale = (1.0 - dt) * self.scale[0] + dt * self.scale[1];
+
+        let mut m = na::Matrix4::identity();
+        m.fixed_slice_mut::<na::U3, na::U3>(0, 0)
+            .copy_from(&(rotation.to_rotation_matrix().matrix() * scale));
+        m.fixed_slice_mut::<na::U3, na::U1>(0, 3)
+            .copy_from(&translation);
+        na::Projective3::from_matrix_unchecked(m)
+    }
+
+    /// bounds of `b` swept along the whole motion
+    pub fn motion_bounds(&self, b: &Bounds3) -> Bounds3 {
+        if !self.animated {
+            return b.transform(&self.start);
+        }
+
+        (0..=MOTION_BOUND_STEPS).fold(Bounds3::empty(), |bounds, step| {
+            let dt = step as f32 / MOTION_BOUND_STEPS as f32;
+            let time = self.start_time + dt * (self.end_time - self.start_time);
+            Bounds3::union(&bounds, &b.transform(&self.interpolate(time)))
+        })
+    }
+}
+
+/// splits `transform` into a translation, a rotation and the remaining scale (and shear) by polar
+/// decomposition
+fn decompose(
+    transform: &na::Projective3<f32>,
+) -> (na::Vector3<f32>, na::UnitQuaternion<f32>, na::Matrix3<f32>) {
+    let m = transform.matrix();
+    let translation = m.fixed_slice::<na::U3, na::U1>(0, 3).into_owned();
+    let linear = m.fixed_slice::<na::U3, na::U3>(0, 0).into_owned();
+
+    // averaging with the inverse transpose converges to the closest orthogonal matrix
+    let mut rotation = linear;
+    for _ in 0..100 {
+        let next = match rotation.transpose().try_inverse() {
+            Some(inverse_transpose) => 0.5 * (rotation + inverse_transpose),
+            None => break,
+        };
+        let change = (rotation - next)
+            .iter()
+            .fold(0.0f32, |change, x| change.max(x.abs()));
+        rotation = next;
+        if change < 1e-4 {
+            break;
+        }
+    }
+    // mirroring is left to the scale, quaternions only hold proper rotations
+    if rotation.determinant() < 0.0 {
+        rotation = -rotation;
+    }
+    let scale = rotation.transpose() * linear;
+
+    (
+        translation,
+        na::UnitQuaternion::from_rotation_matrix(&na::Rotation3::from_matrix_unchecked(rotation)),
+        scale,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpolate_rotation() {
+        let start = na::Projective3::identity();
+        let end = na::convert(na::Similarity3::new(
+            na::Vector3::new(2.0, 0.0, 0.0),
+            na::Vector3::z() * std::f32::consts::FRAC_PI_2,
+            3.0,
+        ));
+        let transform = AnimatedTransform::new(&start, 0.0, &end, 1.0);
+        assert!(transform.is_animated());
+
+        // halfway the rotation is halfway too instead of shrinking the lerped matrix
+        let halfway = transform.interpolate(0.5) * na::Vector3::x();
+        let angle = std::f32::consts::FRAC_PI_4;
+        let expected = 2.0 * na::Vector3::new(angle.cos(), angle.sin(), 0.0);
+        assert!((halfway - expected).norm() < 1e-4);
+
+        let p = na::Point3::new(1.0, 2.0, 3.0);
+        assert!((transform.interpolate(-1.0) * p - start * p).norm() < 1e-4);
+        assert!((transform.interpolate(2.0) * p - end * p).norm() < 1e-4);
+    }
+
+    #[test]
+    fn test_fixed_transform() {
+        let transform =
+            AnimatedTransform::fixed(&na::convert(na::Translation3::new(1.0, 2.0, 3.0)));
+        assert!(!transform.is_animated());
+        assert_eq!(
+            transform.interpolate(0.5) * na::Point3::origin(),
+            na::Point3::new(1.0, 2.0, 3.0)
+        );
+    }
+}
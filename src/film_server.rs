@@ -0,0 +1,135 @@
+use crate::{common::film::Film, pathtracer::integrator::TILE_SIZE};
+use std::io::Cursor;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tiny_http::{Header, Request, Response, Server};
+
+const JPEG_QUALITY: u8 = 90;
+
+const INDEX_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head><title>pathtracer-rs</title></head>
+<body style="background: #202020; color: #e0e0e0; font-family: monospace">
+<div id="progress"></div>
+<img id="render" src="/render.jpg">
+<script>
+setInterval(function () {
+    document.getElementById("render").src = "/render.jpg?t=" + Date.now();
+    fetch("/progress").then(r => r.json()).then(p => {
+        document.getElementById("progress").textContent = p.completed_tiles + " / " +
+            p.total_tiles + " tiles, " + p.elapsed_seconds.toFixed(1) + "s";
+    });
+}, 2000);
+</script>
+</body>
+</html>
+"#;
+
+#[derive(Serialize)]
+struct Progress {
+    completed_tiles: usize,
+    total_tiles: usize,
+    active_tiles: usize,
+    elapsed_seconds: f32,
+}
+
+/// minimal http endpoint serving the film of a headless render, so long renders on remote
+/// machines can be followed from a browser. `/` is a page refreshing itself, `/render.png` and
+/// `/render.jpg` are the current film and `/progress` is a json summary of the tiles done
+pub struct FilmServer {
+    server: Server,
+    log: slog::Logger,
+}
+
+impl FilmServer {
+    pub fn new(log: &slog::Logger, address: &str) -> anyhow::Result<Self> {
+        let log = log.new(o!("module" => "film_server"));
+        let server = Server::http(address)
+            .map_err(|e| anyhow::anyhow!("unable to listen on {}: {}", address, e))?;
+        info!(log, "serving film at http://{}", address);
+
+        Ok(Self { server, log })
+    }
+
+    /// answers requests for `film` on a separate thread while `render` runs
+    pub fn serve_while<F: FnOnce()>(self, film: &Film, render: F) {
+        let done = AtomicBool::new(false);
+        crossbeam::scope(|s| {
+            s.spawn(|_| self.serve(film, &done));
+            render();
+            done.store(true, Ordering::Relaxed);
+        })
+        .unwrap();
+    }
+
+    fn serve(&self, film: &Film, done: &AtomicBool) {
+        let start = Instant::now();
+        let total_tiles = film.tiles(TILE_SIZE).len();
+
+        while !done.load(Ordering::Relaxed) {
+            let request = match self.server.recv_timeout(Duration::from_millis(100)) {
+                Ok(Some(request)) => request,
+                Ok(None) => continue,
+                Err(e) => {
+                    error!(self.log, "failed receiving request: {:?}", e);
+                    return;
+                }
+            };
+
+            let progress = Progress {
+                completed_tiles: film.completed_tiles(),
+                total_tiles,
+                active_tiles: film.active_tiles().len(),
+                elapsed_seconds: start.elapsed().as_secs_f32(),
+            };
+            self.respond(request, film, &progress);
+        }
+    }
+
+    fn respond(&self, request: Request, film: &Film, progress: &Progress) {
+        // query strings are only used by the page to get around caching
+        let path = request.url().split('?').next().unwrap_or("").to_owned();
+        trace!(self.log, "request for {}", path);
+
+        let response = match path.as_str() {
+            "/" | "/index.html" => with_content_type(
+                Response::from_string(INDEX_PAGE),
+                "text/html; charset=utf-8",
+            ),
+            "/render.png" => with_content_type(
+                Response::from_data(encode(film, image::ImageOutputFormat::Png)),
+                "image/png",
+            ),
+            "/render.jpg" => with_content_type(
+                Response::from_data(encode(film, image::ImageOutputFormat::Jpeg(JPEG_QUALITY))),
+                "image/jpeg",
+            ),
+            "/progress" => with_content_type(
+                Response::from_string(serde_json::to_string(progress).unwrap()),
+                "application/json",
+            ),
+            _ => Response::from_string("not found").with_status_code(404),
+        };
+
+        if let Err(e) = request.respond(response) {
+            warn!(self.log, "failed responding to {}: {:?}", path, e);
+        }
+    }
+}
+
+fn with_content_type(
+    response: Response<Cursor<Vec<u8>>>,
+    content_type: &str,
+) -> Response<Cursor<Vec<u8>>> {
+    response.with_header(Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap())
+}
+
+fn encode(film: &Film, format: image::ImageOutputFormat) -> Vec<u8> {
+    // jpeg has no alpha channel
+    let image = image::DynamicImage::ImageRgb8(
+        image::DynamicImage::ImageRgba8(film.to_rgba_image()).to_rgb(),
+    );
+    let mut buffer = Vec::new();
+    image.write_to(&mut buffer, format).unwrap();
+    buffer
+}
@@ -1,15 +1,18 @@
 use itertools::Itertools;
 
 use crate::{
+    common::bounds::Bounds2i,
     common::film::Film,
+    common::openexr::{save_exr, ExrPrecision},
     common::Camera,
     pathtracer::{integrator::PathIntegrator, RenderScene},
 };
 use std::ffi::CString;
 use std::net::TcpStream;
 use std::net::UdpSocket;
+use std::time::{Duration, Instant};
 use std::{io::Write, sync::atomic::AtomicBool, sync::atomic::Ordering};
-use std::{path::PathBuf, sync::Arc, sync::RwLock};
+use std::{path::Path, path::PathBuf, sync::Arc, sync::RwLock};
 
 trait Serialize {
     fn to_buffer(&self, buffer: &mut Vec<u8>);
@@ -82,18 +85,17 @@ struct TevControlCreateImage {
 }
 
 impl TevControlCreateImage {
-    fn new_message(resolution: &na::Vector2<u32>, name: &str) -> Vec<u8> {
+    fn new_message(resolution: &na::Vector2<u32>, name: &str, channels: &[&str]) -> Vec<u8> {
         TevControlCreateImage {
             grab_focus: true,
             image_name: CString::new(name).unwrap(),
             width: resolution.x as i32,
             height: resolution.y as i32,
-            n_channels: 3,
-            channel_names: vec![
-                CString::new("r").unwrap(),
-                CString::new("g").unwrap(),
-                CString::new("b").unwrap(),
-            ],
+            n_channels: channels.len() as i32,
+            channel_names: channels
+                .iter()
+                .map(|channel| CString::new(*channel).unwrap())
+                .collect(),
         }
         .make_message()
     }
@@ -123,19 +125,21 @@ struct TevControlUpdateImage {
 }
 
 impl TevControlUpdateImage {
-    fn new_message(film: &Film, name: &str) -> Vec<Vec<u8>> {
-        const CHANNEL_NAMES: [&str; 3] = ["r", "g", "b"];
+    /// updates of the first `channels` of the pixels of `film` in `bounds`, in chunks small
+    /// enough for tev to take
+    fn new_message(film: &Film, name: &str, bounds: &Bounds2i, channels: &[&str]) -> Vec<Vec<u8>> {
         let mut bufs = Vec::new();
-        let channels = film.to_channel_updates();
+        let channel_updates = film.to_channel_updates(bounds);
+        let cols = (bounds.p_max.x - bounds.p_min.x) as usize;
+        let rows = (bounds.p_max.y - bounds.p_min.y) as usize;
         const CHUNK_DIM: usize = 100;
-        for (idx, channel) in channels.iter().enumerate() {
-            for (x, y) in (0..film.resolution.x as usize)
+        for (channel_name, channel) in channels.iter().zip(channel_updates.iter()) {
+            for (x, y) in (0..cols)
                 .step_by(CHUNK_DIM)
-                .cartesian_product((0..film.resolution.y as usize).step_by(CHUNK_DIM))
+                .cartesian_product((0..rows).step_by(CHUNK_DIM))
             {
-                let cols = film.resolution.x as usize;
-                let chunk_rows = CHUNK_DIM.min(film.resolution.y as usize - y);
-                let chunk_cols = CHUNK_DIM.min(film.resolution.x as usize - x);
+                let chunk_rows = CHUNK_DIM.min(rows - y);
+                let chunk_cols = CHUNK_DIM.min(cols - x);
                 let mut chunk_buf = Vec::new();
                 for row in y..(y + chunk_rows) {
                     chunk_buf.extend_from_slice(
@@ -145,11 +149,11 @@ impl TevControlUpdateImage {
 
                 bufs.push(
                     TevControlUpdateImage {
-                        grab_focus: true,
+                        grab_focus: false,
                         image_name: CString::new(name).unwrap(),
-                        channel: CString::new(CHANNEL_NAMES[idx]).unwrap(),
-                        x: x as i32,
-                        y: y as i32,
+                        channel: CString::new(*channel_name).unwrap(),
+                        x: bounds.p_min.x + x as i32,
+                        y: bounds.p_min.y + y as i32,
                         width: chunk_cols as i32,
                         height: chunk_rows as i32,
                         image_data: chunk_buf,
@@ -177,6 +181,25 @@ impl Serialize for TevControlUpdateImage {
     }
 }
 
+/// how many progressive passes of the sampler's samples per pixel a render takes, and which of
+/// them are saved along the way
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RenderPasses {
+    /// passes to render, as many as fit in `time_limit` when `None`
+    pub passes: Option<usize>,
+    /// saves the image after every this many passes, under the number of passes taken
+    pub snapshot_interval: Option<usize>,
+    /// no pass starts that would likely finish after this long
+    pub time_limit: Option<Duration>,
+}
+
+/// where the snapshot after `pass` passes is saved, numbered next to `output_path`
+fn snapshot_path(output_path: &Path, pass: usize) -> PathBuf {
+    let stem = output_path.file_stem().unwrap().to_string_lossy();
+    let extension = output_path.extension().unwrap().to_string_lossy();
+    output_path.with_file_name(format!("{}_pass{:04}.{}", stem, pass, extension))
+}
+
 pub fn run(
     log: slog::Logger,
     render_scene: RenderScene,
@@ -184,48 +207,205 @@ pub fn run(
     integrator: PathIntegrator,
     server_address: &str,
     output_path: PathBuf,
+    albedo_path: Option<PathBuf>,
+    film_server_address: Option<&str>,
+    exr: Option<ExrPrecision>,
+    passes: RenderPasses,
 ) -> anyhow::Result<()> {
+    let save_snapshot = |camera: &Camera, pass: usize| -> anyhow::Result<()> {
+        match exr {
+            Some(precision) => {
+                let path = snapshot_path(&output_path.with_extension("exr"), pass);
+                save_exr(&path, &[("render", &camera.film)], precision)?;
+            }
+            None => camera.film.save_image(&snapshot_path(&output_path, pass))?,
+        }
+        Ok(())
+    };
+
+    let render_passes = |camera: &Camera| {
+        let start = Instant::now();
+        let mut pass = 0;
+        loop {
+            let pass_start = Instant::now();
+            integrator.render_pass(camera, &render_scene, pass);
+            pass += 1;
+
+            if passes
+                .snapshot_interval
+                .map_or(false, |interval| pass % interval == 0)
+            {
+                match save_snapshot(camera, pass) {
+                    Ok(()) => info!(log, "saved snapshot after {} passes", pass),
+                    Err(e) => warn!(log, "failed saving snapshot: {:?}", e),
+                }
+            }
+            if passes.passes.map_or(false, |n_passes| pass >= n_passes) {
+                break;
+            }
+            // the next pass takes about as long as the last one did
+            if let Some(time_limit) = passes.time_limit {
+                if start.elapsed() + pass_start.elapsed() > time_limit {
+                    info!(
+                        log,
+                        "stopping after {} passes in {:?}, the time limit is {:?}",
+                        pass,
+                        start.elapsed(),
+                        time_limit
+                    );
+                    break;
+                }
+            }
+        }
+    };
+
+    let render = |camera: &Camera| match film_server_address {
+        #[cfg(feature = "film_server")]
+        Some(address) => match crate::film_server::FilmServer::new(&log, address) {
+            Ok(server) => server.serve_while(&camera.film, || render_passes(camera)),
+            Err(e) => {
+                warn!(log, "not serving film: {:?}", e);
+                render_passes(camera);
+            }
+        },
+        #[cfg(not(feature = "film_server"))]
+        Some(_) => {
+            warn!(
+                log,
+                "built without the film_server feature, not serving film"
+            );
+            render_passes(camera);
+        }
+        None => render_passes(camera),
+    };
+
+    // an exr render keeps the albedo as a layer instead of a separate image
+    let save = |camera: &Camera| -> anyhow::Result<()> {
+        let albedo = albedo_path
+            .as_ref()
+            .map(|_| integrator.render_albedo(camera, &render_scene));
+        match exr {
+            Some(precision) => {
+                let mut layers = vec![("render", &camera.film)];
+                if let Some(albedo) = &albedo {
+                    layers.push(("albedo", albedo));
+                }
+                save_exr(&output_path.with_extension("exr"), &layers, precision)?;
+            }
+            None => {
+                camera.film.save_image(&output_path)?;
+                if let (Some(albedo), Some(albedo_path)) = (&albedo, &albedo_path) {
+                    albedo.to_rgba_image().save(albedo_path)?;
+                }
+            }
+        }
+        Ok(())
+    };
+
+    let save_ray_stats = || {
+        if let Some(ray_stats) = integrator.ray_stats() {
+            for (name, values) in vec![
+                ("bounces", ray_stats.average_bounces()),
+                ("shadow_rays", ray_stats.average_shadow_rays()),
+            ] {
+                // the images are normalized, the maximum gives them a scale
+                let max = values.iter().cloned().fold(0.0, f32::max);
+                info!(log, "maximum average {} per sample: {:?}", name, max);
+                ray_stats
+                    .to_image(&values)
+                    .save(output_path.with_file_name(format!("{}.png", name)))
+                    .unwrap();
+            }
+            for (name, values) in vec![
+                ("nodes_visited", ray_stats.average_nodes_visited()),
+                ("primitives_tested", ray_stats.average_primitives_tested()),
+            ] {
+                let max = values.iter().cloned().fold(0.0, f32::max);
+                info!(log, "maximum average {} per sample: {:?}", name, max);
+                ray_stats
+                    .to_heat_map(&values)
+                    .save(output_path.with_file_name(format!("{}.png", name)))
+                    .unwrap();
+            }
+        }
+    };
+
     if let Ok(mut stream) = TcpStream::connect(server_address) {
+        info!(
+            log,
+            "streaming the render to the display server at {}", server_address
+        );
+        let channels: &'static [&str] = if camera.film.write_alpha() {
+            &["r", "g", "b", "a"]
+        } else {
+            &["r", "g", "b"]
+        };
+        // only the tiles merged since the last update are sent
+        camera.film.track_updated_regions();
         let camera_master = Arc::new(RwLock::new(camera));
         let camera = camera_master.clone();
         let rendering_done_master = Arc::new(AtomicBool::new(false));
         let rendering_done = rendering_done_master.clone();
         let camera = camera.read().unwrap();
 
-        stream.write(&TevControlCreateImage::new_message(&camera.film.resolution, "render")[..])?;
+        stream.write_all(
+            &TevControlCreateImage::new_message(&camera.film.resolution, "render", channels)[..],
+        )?;
 
-        let progressive_thread = std::thread::spawn(move || -> anyhow::Result<()> {
+        let stream_log = log.clone();
+        let progressive_thread = std::thread::spawn(move || {
             let rendering_done = rendering_done_master;
             let camera = camera_master.read().unwrap();
+            let mut send = |regions: &[Bounds2i]| -> std::io::Result<()> {
+                for region in regions {
+                    for buf in
+                        TevControlUpdateImage::new_message(&camera.film, "render", region, channels)
+                    {
+                        stream.write_all(&buf[..])?;
+                    }
+                }
+                Ok(())
+            };
+
             while !rendering_done.load(Ordering::Relaxed) {
-                let buffers = TevControlUpdateImage::new_message(&camera.film, "render");
-                for buf in buffers {
-                    stream.write(&buf[..])?;
+                if let Err(e) = send(&camera.film.take_updated_regions()) {
+                    // a closed display doesn't stop the render
+                    warn!(
+                        stream_log,
+                        "lost the display server, rendering on without it: {:?}", e
+                    );
+                    return;
                 }
-                std::thread::sleep(std::time::Duration::from_secs(2));
+                std::thread::sleep(Duration::from_millis(250));
             }
 
-            let buffers = TevControlUpdateImage::new_message(&camera.film, "render");
-            for buf in buffers {
-                stream.write(&buf[..])?;
+            let film_bounds = Bounds2i {
+                p_min: na::Point2::new(0, 0),
+                p_max: na::Point2::new(
+                    camera.film.resolution.x as i32,
+                    camera.film.resolution.y as i32,
+                ),
+            };
+            if let Err(e) = send(&[film_bounds]) {
+                warn!(stream_log, "failed sending the render: {:?}", e);
             }
-
-            Ok(())
         });
 
-        integrator.render(&camera, &render_scene);
+        render(&camera);
         rendering_done.store(true, Ordering::Relaxed);
 
-        progressive_thread.join().unwrap()?;
+        progressive_thread.join().unwrap();
 
-        camera.film.to_rgba_image().save(&output_path).unwrap();
+        save(&camera)?;
+        save_ray_stats();
     } else {
         warn!(
             log,
             "could not conenct to display server, falling back to one shot rendering"
         );
-        integrator.render(&camera, &render_scene);
-        camera.film.to_rgba_image().save(&output_path).unwrap();
+        render(&camera);
+        save(&camera)?;
+        save_ray_stats();
     };
 
     Ok(())
@@ -252,7 +432,7 @@ mod tests {
     fn test_tev_control_create_image() {
         let resolution = na::Vector2::new(1920, 1080);
         let image_name = "render";
-        let message = TevControlCreateImage::new_message(&resolution, image_name);
+        let message = TevControlCreateImage::new_message(&resolution, image_name, &["r", "g", "b"]);
 
         let mut curr_idx = 0;
 
@@ -18,7 +18,10 @@ extern crate serde_derive;
 extern crate nalgebra as na;
 extern crate nalgebra_glm as glm;
 
+pub mod animation;
 pub mod common;
+#[cfg(feature = "film_server")]
+pub mod film_server;
 pub mod headless;
 pub mod pathtracer;
 pub mod viewer;
@@ -11,9 +11,17 @@ extern crate nalgebra as na;
 
 use anyhow::Result;
 use clap::clap_app;
+use pathtracer_rs::common::bounds::Bounds2i;
+use pathtracer_rs::common::filter::{
+    BlackmanHarrisFilter, BoxFilter, Filter, GuassianFilter, MitchellFilter, TriangleFilter,
+};
+use pathtracer_rs::common::importer::description::{is_description, SceneDescription};
+use pathtracer_rs::pathtracer::sampler::{
+    halton::HaltonScrambling, plot, stratified::square_strata, SamplerBuilder,
+};
 use pathtracer_rs::*;
 use slog::Drain;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::iter::FromIterator;
 use std::path::Path;
 use std::str::FromStr;
@@ -32,22 +40,442 @@ fn parse_resolution(res_str: &str) -> Result<na::Vector2<f32>> {
     }
 }
 
+fn parse_strata(strata_str: &str) -> Result<(usize, usize)> {
+    let xy = strata_str.split("x").collect::<Vec<_>>();
+    if xy.len() != 2 {
+        Err(anyhow!("invalid strata string"))
+    } else {
+        Ok((xy[0].parse::<usize>()?, xy[1].parse::<usize>()?))
+    }
+}
+
+fn parse_shutter(shutter_str: &str) -> Result<common::Shutter> {
+    let times = shutter_str.split(",").collect::<Vec<_>>();
+    if times.len() != 2 {
+        Err(anyhow!("invalid shutter string"))
+    } else {
+        let open = times[0].parse::<f32>()?;
+        let close = times[1].parse::<f32>()?;
+
+        Ok(common::Shutter {
+            open: open.min(close),
+            close: open.max(close),
+        })
+    }
+}
+
+fn parse_offset(offset_str: &str) -> Result<na::Vector2<f32>> {
+    let xy = offset_str.split(",").collect::<Vec<_>>();
+    if xy.len() != 2 {
+        Err(anyhow!("invalid offset string"))
+    } else {
+        Ok(na::Vector2::new(
+            xy[0].parse::<f32>()?,
+            xy[1].parse::<f32>()?,
+        ))
+    }
+}
+
+/// corners of a crop window written as min x, min y, max x, max y, e.g. 0.25,0.25,0.75,0.5
+fn parse_crop_window(crop_str: &str) -> Result<(na::Point2<f32>, na::Point2<f32>)> {
+    let values = crop_str
+        .split(",")
+        .map(str::parse::<f32>)
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    if values.len() != 4 || values[0] >= values[2] || values[1] >= values[3] {
+        Err(anyhow!("invalid crop window string"))
+    } else {
+        Ok((
+            na::Point2::new(values[0], values[1]),
+            na::Point2::new(values[2], values[3]),
+        ))
+    }
+}
+
+/// command line arguments, falling back to the settings of the scene description for the ones
+/// left out of the command line
+struct Arguments<'a> {
+    matches: &'a clap::ArgMatches<'a>,
+    /// settings of the description, written as the arguments they stand in for
+    described: HashMap<&'static str, String>,
+}
+
+impl<'a> Arguments<'a> {
+    fn new(matches: &'a clap::ArgMatches<'a>, described: HashMap<&'static str, String>) -> Self {
+        Self { matches, described }
+    }
+
+    fn value_of(&self, name: &str) -> Option<&str> {
+        match self.described.get(name) {
+            Some(value) if self.matches.occurrences_of(name) == 0 => Some(value),
+            _ => self.matches.value_of(name),
+        }
+    }
+
+    fn is_present(&self, name: &str) -> bool {
+        self.matches.is_present(name) || self.described.contains_key(name)
+    }
+
+    /// whether the argument was given rather than left to its default
+    fn is_given(&self, name: &str) -> bool {
+        self.matches.occurrences_of(name) > 0 || self.described.contains_key(name)
+    }
+}
+
+/// the settings of a scene description, written as the command line arguments they stand in for
+fn described_arguments(description: &SceneDescription) -> HashMap<&'static str, String> {
+    let mut arguments = HashMap::new();
+    let mut set = |name: &'static str, value: Option<String>| {
+        if let Some(value) = value {
+            arguments.insert(name, value);
+        }
+    };
+    let flag = |present: bool| Some(String::new()).filter(|_| present);
+    let number = |value: Option<f32>| value.map(|value| value.to_string());
+
+    set("variant", description.variant.clone());
+    set(
+        "shutter",
+        description
+            .shutter
+            .map(|[open, close]| format!("{},{}", open, close)),
+    );
+    set(
+        "max_texture_size",
+        description.max_texture_size.map(|size| size.to_string()),
+    );
+    set("default_lights", flag(description.default_lights));
+
+    let camera = &description.camera;
+    set("scene_camera", camera.name.clone());
+    set("lens_radius", number(camera.lens_radius));
+    set("focal_distance", number(camera.focal_distance));
+    set("orthographic", number(camera.orthographic));
+    set("panorama", camera.panorama.clone());
+    set("fisheye_fov", number(camera.fisheye_fov));
+
+    let film = &description.film;
+    set(
+        "resolution",
+        film.resolution
+            .map(|[width, height]| format!("{}x{}", width, height)),
+    );
+    set("filter", film.filter.clone());
+    set("filter_radius", number(film.filter_radius));
+    set(
+        "crop_window",
+        film.crop_window.map(|window| {
+            window
+                .iter()
+                .map(f32::to_string)
+                .collect::<Vec<_>>()
+                .join(",")
+        }),
+    );
+    set("exposure", number(film.exposure));
+    set("auto_exposure", flag(film.auto_exposure));
+    set("tone_map", film.tone_map.clone());
+    set("gamma", number(film.gamma));
+    set("bit_depth", film.bit_depth.map(|bits| bits.to_string()));
+    set("alpha", flag(film.alpha));
+
+    let sampler = &description.sampler;
+    set("sampler", sampler.kind.clone());
+    set(
+        "samples",
+        sampler.samples.map(|samples| samples.to_string()),
+    );
+    set(
+        "strata",
+        sampler.strata.map(|[x, y]| format!("{}x{}", x, y)),
+    );
+    set("no_jitter", flag(sampler.jitter == Some(false)));
+    set("scramble", sampler.scramble.clone());
+    set("blue_noise", flag(sampler.blue_noise));
+
+    let integrator = &description.integrator;
+    set(
+        "max_depth",
+        integrator.max_depth.map(|depth| depth.to_string()),
+    );
+    set(
+        "light_samples",
+        integrator.light_samples.map(|samples| samples.to_string()),
+    );
+    set(
+        "bsdf_samples",
+        integrator.bsdf_samples.map(|samples| samples.to_string()),
+    );
+
+    arguments
+}
+
+/// the sampler picked by the sampler arguments of `matches`
+fn sampler_builder(
+    log: &slog::Logger,
+    matches: &Arguments,
+    pixel_samples: usize,
+    sample_bounds: &Bounds2i,
+) -> SamplerBuilder {
+    let sampler = match matches.value_of("sampler").unwrap() {
+        "stratified" => {
+            let (x_strata, y_strata) = match matches.value_of("strata") {
+                Some(strata_str) => parse_strata(strata_str).unwrap_or_else(|_| {
+                    warn!(log, "failed parsing strata, falling back to a square grid");
+                    square_strata(pixel_samples)
+                }),
+                None => square_strata(pixel_samples),
+            };
+            SamplerBuilder::stratified(log, x_strata, y_strata, !matches.is_present("no_jitter"))
+        }
+        "halton" => {
+            let scrambling = match matches.value_of("scramble").unwrap() {
+                "none" => HaltonScrambling::None,
+                "permutations" => HaltonScrambling::Permutations,
+                name => {
+                    if name != "owen" {
+                        warn!(log, "unknown scrambling {:?}, falling back to owen", name);
+                    }
+                    HaltonScrambling::Owen
+                }
+            };
+            SamplerBuilder::halton(log, pixel_samples, sample_bounds, scrambling)
+        }
+        "pmj02" => SamplerBuilder::pmj02(log, pixel_samples),
+        "cmj" => SamplerBuilder::cmj(log, pixel_samples),
+        name => {
+            if name != "sobol" {
+                warn!(log, "unknown sampler {:?}, falling back to sobol", name);
+            }
+            SamplerBuilder::new(log, pixel_samples, sample_bounds)
+        }
+    };
+    if matches.is_present("blue_noise") {
+        SamplerBuilder::blue_noise(sampler)
+    } else {
+        sampler
+    }
+}
+
+fn parse_pixel(pixel_str: &str) -> Result<na::Point2<i32>> {
+    let xy = pixel_str.split(",").collect::<Vec<_>>();
+    if xy.len() != 2 {
+        Err(anyhow!("invalid pixel string"))
+    } else {
+        Ok(na::Point2::new(
+            xy[0].parse::<i32>()?,
+            xy[1].parse::<i32>()?,
+        ))
+    }
+}
+
+fn export(log: &slog::Logger, matches: &clap::ArgMatches) -> Result<()> {
+    let scene_path = matches.value_of("SCENE").unwrap();
+    let asset_paths = matches.values_of("asset_path").map_or(vec![], |paths| {
+        paths.map(std::path::PathBuf::from).collect()
+    });
+    let material_library = matches
+        .value_of("material_library")
+        .map(|path| common::importer::library::MaterialLibrary::load(Path::new(path)))
+        .transpose()?;
+    // the camera keeps the aspect of the renders it frames
+    let resolution = match matches.value_of("resolution") {
+        Some(res_str) => parse_resolution(res_str)?,
+        None => *common::DEFAULT_RESOLUTION,
+    };
+
+    let (camera, render_scene, viewer_scene) = common::importer::import(
+        &log,
+        &scene_path,
+        &asset_paths,
+        material_library.as_ref(),
+        &resolution,
+        false,
+        None,
+        matches.value_of("variant"),
+        matches.value_of("scene_camera"),
+        None,
+    );
+    let mut export = if matches.is_present("preview") {
+        viewer_scene.to_export()
+    } else {
+        render_scene.to_export(&log)
+    }
+    .with_camera(&camera);
+    if matches.is_present("flatten") {
+        export.flatten();
+    }
+
+    let output = Path::new(matches.value_of("output").unwrap());
+    export.save(&log, output)?;
+    info!(
+        log,
+        "exported {} meshes and {} materials to {}",
+        export.meshes.len(),
+        export.materials.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+fn plot_samples(log: &slog::Logger, matches: &clap::ArgMatches) -> Result<()> {
+    let n_samples = matches.value_of("samples").unwrap().parse::<usize>()?;
+    let pixel = parse_pixel(matches.value_of("pixel").unwrap())?;
+    let dimension = matches.value_of("dimension").unwrap().parse::<usize>()?;
+    let resolution = match matches.value_of("resolution") {
+        Some(res_str) => parse_resolution(res_str)?,
+        None => *common::DEFAULT_RESOLUTION,
+    };
+    let sample_bounds = Bounds2i {
+        p_min: na::Point2::new(0, 0),
+        p_max: na::Point2::new(resolution.x as i32, resolution.y as i32),
+    };
+    if pixel.x < 0
+        || pixel.y < 0
+        || pixel.x >= sample_bounds.p_max.x
+        || pixel.y >= sample_bounds.p_max.y
+    {
+        return Err(anyhow!("pixel {:?} is outside the image", pixel));
+    }
+
+    let mut sampler = sampler_builder(
+        log,
+        &Arguments::new(matches, HashMap::new()),
+        n_samples,
+        &sample_bounds,
+    )
+    .build();
+    let samples = plot::pixel_samples(&mut sampler, &pixel, dimension, n_samples);
+    let output_path = matches.value_of("output").unwrap();
+    std::fs::write(output_path, plot::scatter_svg(&samples))?;
+    info!(
+        log,
+        "plotted {} samples of dimensions {} and {} in pixel {:?} to {}",
+        samples.len(),
+        dimension,
+        dimension + 1,
+        pixel,
+        output_path
+    );
+    Ok(())
+}
+
+fn validate(log: &slog::Logger, matches: &clap::ArgMatches) -> Result<()> {
+    let scene_path = matches.value_of("SCENE").unwrap();
+    let asset_paths = matches.values_of("asset_path").map_or(vec![], |paths| {
+        paths.map(std::path::PathBuf::from).collect()
+    });
+
+    let issues = common::importer::validate::validate(&log, &scene_path, &asset_paths)?;
+    for issue in &issues {
+        warn!(log, "{}", issue);
+    }
+
+    if issues.is_empty() {
+        info!(log, "no issues found in {}", scene_path);
+        Ok(())
+    } else {
+        Err(anyhow!("found {} issues in {}", issues.len(), scene_path))
+    }
+}
+
 fn main() -> anyhow::Result<()> {
     let matches = clap_app!(pathtracer_rs =>
         (version: "1.0")
         (author: "Eric F. <eric1221bday@gmail.com>")
         (about: "Rust path tracer")
-        (@arg SCENE: +required "Sets the input scene to use")
-        (@arg output: -o --output +takes_value +required "Sets the output directory to save renders at")
+        (@setting SubcommandsNegateReqs)
+        (@arg SCENE: +required "Sets the input scene to use, either a scene file or a zip archive containing one")
+        (@arg asset_path: -a --asset_path +takes_value +multiple number_of_values(1) "Additional directory to search for the scene and its assets, can be repeated")
+        (@arg output: -o --output +takes_value +required "Sets the output directory to save renders at, or the png or tiff file to save the render as")
         (@arg samples: -s --samples default_value("1") "Number of samples path tracer to take per pixel (sampler dependent)")
+        (@arg sampler: --sampler default_value("sobol") "Sampler to take the samples with, sobol, stratified, halton, pmj02 or cmj")
+        (@arg strata: --strata +takes_value "Strata per pixel of the stratified sampler, e.g. 4x2, the squarest grid holding --samples by default")
+        (@arg no_jitter: --no_jitter "Place the samples of the stratified sampler at the centers of their strata")
+        (@arg check_dimensions: --check_dimensions "Warn when a bounce of a path draws more sampler dimensions than set aside for it, or a different number on different paths")
+        (@arg blue_noise: --blue_noise "Dither the samples of each pixel by a blue noise mask, evening out the noise of low sample counts")
+        (@arg scramble: --scramble default_value("owen") "Scrambling of the halton sampler, owen, permutations or none")
         (@arg resolution: -r --resolution +takes_value "Resolution of the window")
         (@arg camera_controller: -c --camera default_value("orbit") "Camera movement type")
         (@arg max_depth: -d --max_depth default_value("15") "Maximum ray tracing depth")
+        (@arg light_samples: --light_samples default_value("1") "Number of light samples for direct lighting per shading point")
+        (@arg bsdf_samples: --bsdf_samples default_value("1") "Number of bsdf samples for direct lighting per shading point")
         (@arg log_level: -l --log_level default_value("INFO") "Application wide log level")
         (@arg module_log: -m --module_log default_value("all") "Module names to log, (all for every module)")
+        (@arg material_library: --material_library +takes_value "JSON file of named materials overriding scene materials with the same name")
+        (@arg variant: --variant +takes_value "Name of the KHR_materials_variants material variant to load for gltf scenes")
+        (@arg scene_camera: --scene_camera +takes_value "Name or index of the gltf camera to render from, the first one in the scene by default")
+        (@arg shutter: --shutter +takes_value "Animation times in seconds the shutter opens and closes at, e.g. 0.0,0.04, to motion blur animated gltf scenes")
+        (@arg lens_radius: --lens_radius +takes_value "Radius of the camera lens, blurring what's out of focus (0 for a pinhole camera)")
+        (@arg focal_distance: --focal_distance +takes_value "Distance of the plane the camera lens focuses on, the distance to the origin by default")
+        (@arg lens_shift: --lens_shift +takes_value "Shift of the film in fractions of its width and height, e.g. 0,0.2 to frame higher while keeping verticals parallel")
+        (@arg lens_tilt: --lens_tilt +takes_value "Angles in degrees to tilt the plane in focus of the camera lens by about its horizontal and vertical axes, e.g. 10,0")
+        (@arg orthographic: --orthographic +takes_value "Render with an orthographic projection showing this much of the scene from bottom to top, e.g. for technical or isometric views")
+        (@arg panorama: --panorama +takes_value "Render all around the camera, spherical for an equirect 360 panorama or fisheye, the preview keeps the perspective view")
+        (@arg fisheye_fov: --fisheye_fov default_value("180") "Field of view in degrees of the fisheye panorama across the image diagonal")
+        (@arg lens_file: --lens_file +takes_value "Render through the lens elements described in this file (radius, thickness, index of refraction and aperture per line in millimeters, as pbrt's lens files), focused at --focal_distance")
+        (@arg aperture_diameter: --aperture_diameter +takes_value "Diameter in millimeters to narrow the aperture stop of --lens_file to")
+        (@arg film_diagonal: --film_diagonal default_value("35") "Diagonal in millimeters of the film behind --lens_file")
+        (@arg filter: --filter default_value("gaussian") "Pixel reconstruction filter weighting the samples, gaussian, mitchell, blackman_harris, triangle or box")
+        (@arg filter_radius: --filter_radius +takes_value "Radius in pixels of the reconstruction filter, 2 by default and 0.5 for the box filter")
+        (@arg crop_window: --crop_window +takes_value "Render only the region between these fractions of the image width and height, as min x, min y, max x, max y from the top left, e.g. 0.25,0.25,0.75,0.5")
+        (@arg max_texture_size: --max_texture_size +takes_value "Downsample textures larger than this size (in pixels) on import")
+        (@arg texture_cache: --texture_cache +takes_value "Memory budget in MB for image textures paged in on demand, 512 by default")
+        (@arg interocular: --interocular +takes_value "Eye separation of the anaglyph preview, a thirtieth of the convergence distance by default")
+        (@arg convergence: --convergence +takes_value "Distance of zero parallax of the anaglyph preview, the distance to the origin by default")
         (@arg default_lights: --default_lights "Add default lights into the scene")
+        (@arg auto_exposure: --auto_exposure "Estimate the image exposure from a quick prepass before rendering, overriding --iso, --f_stop and --shutter_speed")
+        (@arg iso: --iso default_value("100") "Film speed of the physical exposure, with --f_stop and --shutter_speed")
+        (@arg f_stop: --f_stop default_value("1") "Relative aperture of the physical exposure, e.g. 16 for f/16")
+        (@arg shutter_speed: --shutter_speed default_value("1") "Seconds the shutter stays open for the physical exposure, e.g. 1/125")
+        (@arg exposure: --exposure +takes_value "Stops to brighten the image by on top of the camera exposure, negative to darken it")
+        (@arg tone_map: --tone_map default_value("clamp") "Tone mapper compressing highlights in png images and the preview, clamp, reinhard, filmic, aces or agx")
+        (@arg gamma: --gamma +takes_value "Gamma to encode png images and the preview with, the srgb curve by default")
         (@arg headless: --headless "run pathtracer in headless mode")
+        (@arg passes: --passes +takes_value "Number of progressive passes of --samples each to render, 1 by default or as many as fit in --time_limit (headless only)")
+        (@arg snapshot_interval: --snapshot_interval +takes_value "Also save the render after every this many passes, numbered by the passes taken, e.g. for convergence series (headless only)")
+        (@arg time_limit: --time_limit +takes_value "Seconds to render passes for, stopping before a pass that would run past them (headless only)")
+        (@arg camera_path: --camera_path +takes_value "Render the frames of an animation along the keyframes of a JSON camera path, or turntable to orbit the origin, saved as numbered renders")
+        (@arg frames: --frames default_value("60") "Number of frames of the --camera_path animation")
+        (@arg exr: --exr "Save renders as OpenEXR images of linear radiance instead of png, with the albedo of --albedo as a layer (headless only)")
+        (@arg bit_depth: --bit_depth default_value("8") "Bits per channel of png and tiff renders, 8 or 16")
+        (@arg half_float: --half_float "Save the OpenEXR images of --exr with half float channels")
+        (@arg alpha: --alpha "Save renders with an alpha channel, the background transparent instead of showing the environment, which still lights the scene")
+        (@arg albedo: --albedo "Also save the albedo of the first hit as albedo.png for denoising (headless only)")
+        (@arg ray_stats: --ray_stats "Also save the average bounces and shadow rays per pixel as bounces.png and shadow_rays.png, and heat maps of the BVH nodes visited and primitives tested as nodes_visited.png and primitives_tested.png (headless only)")
         (@arg server: --server default_value("127.0.0.1:14158") "tev server address and port for remote rendering")
+        (@arg embree: --embree "Trace the meshes of the scene with embree instead of the native BVH (needs the embree feature)")
+        (@arg film_server: --film_server +takes_value "Address to serve the film being rendered over http at, e.g. 0.0.0.0:8080 (headless only, needs the film_server feature)")
+        (@subcommand validate =>
+            (about: "Loads the scene and reports problems in it without rendering")
+            (@arg SCENE: +required "Sets the input scene to check, either a scene file or a zip archive containing one")
+            (@arg asset_path: -a --asset_path +takes_value +multiple number_of_values(1) "Additional directory to search for the scene and its assets, can be repeated")
+        )
+        (@subcommand export =>
+            (about: "Loads the scene and saves its meshes, materials and camera as a gltf or glb file, e.g. to share scenes merged by a scene description")
+            (@arg SCENE: +required "Sets the input scene to export, either a scene file or a zip archive containing one")
+            (@arg output: +required "Sets the gltf or glb file to save the scene as, gltf files keep their buffer in a bin file next to them")
+            (@arg asset_path: -a --asset_path +takes_value +multiple number_of_values(1) "Additional directory to search for the scene and its assets, can be repeated")
+            (@arg material_library: --material_library +takes_value "JSON file of named materials overriding scene materials with the same name")
+            (@arg variant: --variant +takes_value "Name of the KHR_materials_variants material variant to load for gltf scenes")
+            (@arg scene_camera: --scene_camera +takes_value "Name or index of the gltf camera to export, the first one in the scene by default")
+            (@arg resolution: -r --resolution +takes_value "Resolution of the renders the exported camera frames")
+            (@arg flatten: --flatten "Bake every copy of instanced meshes into world space instead of placing them with instance nodes")
+            (@arg preview: --preview "Export the meshes of the real time preview instead, their materials only keeping the tint of transmissive meshes")
+        )
+        (@subcommand plot_samples =>
+            (about: "Plots the samples a sampler takes in one pixel as an svg scatter plot, to check their stratification")
+            (@arg output: +required "Sets the svg file to write the plot to")
+            (@arg samples: -s --samples default_value("64") "Number of samples to plot, the samples per pixel of the sampler")
+            (@arg pixel: -p --pixel default_value("0,0") "Pixel to take the samples in, e.g. 12,7")
+            (@arg dimension: --dimension default_value("0") "First of the two sampler dimensions plotted against each other")
+            (@arg resolution: -r --resolution +takes_value "Resolution of the image the pixel is in, which the sobol and halton sequences are spread over")
+            (@arg sampler: --sampler default_value("sobol") "Sampler to take the samples with, sobol, stratified, halton, pmj02 or cmj")
+            (@arg strata: --strata +takes_value "Strata per pixel of the stratified sampler, e.g. 4x2, the squarest grid holding --samples by default")
+            (@arg no_jitter: --no_jitter "Place the samples of the stratified sampler at the centers of their strata")
+            (@arg blue_noise: --blue_noise "Dither the samples of each pixel by a blue noise mask, evening out the noise of low sample counts")
+            (@arg scramble: --scramble default_value("owen") "Scrambling of the halton sampler, owen, permutations or none")
+        )
     )
     .get_matches();
 
@@ -66,14 +494,39 @@ fn main() -> anyhow::Result<()> {
     let ctrl = drain.ctrl();
     let log = slog::Logger::root(drain.fuse(), o!());
 
+    if let Some(matches) = matches.subcommand_matches("validate") {
+        return validate(&log, matches);
+    }
+    if let Some(matches) = matches.subcommand_matches("export") {
+        return export(&log, matches);
+    }
+    if let Some(matches) = matches.subcommand_matches("plot_samples") {
+        return plot_samples(&log, matches);
+    }
+
     let scene_path = matches.value_of("SCENE").unwrap();
-    let output_path = Path::new(matches.value_of("output").unwrap()).join("render.png");
-    let pixel_samples = matches
-        .value_of("samples")
-        .unwrap()
-        .parse::<usize>()
-        .unwrap();
-    let resolution = if let Some(res_str) = matches.value_of("resolution") {
+    let asset_paths = matches.values_of("asset_path").map_or(vec![], |paths| {
+        paths.map(std::path::PathBuf::from).collect()
+    });
+    // the settings of scene descriptions stand in for the arguments left out of the command line
+    let described = if is_description(Path::new(scene_path)) {
+        let resolver = common::importer::AssetResolver::new(&log, scene_path, &asset_paths)?;
+        described_arguments(&SceneDescription::load(resolver.scene_path())?)
+    } else {
+        HashMap::new()
+    };
+    let args = Arguments::new(&matches, described);
+    // renders are saved in the output directory unless it names an image file
+    let output_path = Path::new(args.value_of("output").unwrap());
+    let output_path = match output_path
+        .extension()
+        .and_then(|extension| extension.to_str())
+    {
+        Some("png") | Some("tif") | Some("tiff") => output_path.to_path_buf(),
+        _ => output_path.join("render.png"),
+    };
+    let pixel_samples = args.value_of("samples").unwrap().parse::<usize>().unwrap();
+    let resolution = if let Some(res_str) = args.value_of("resolution") {
         parse_resolution(&res_str).unwrap_or_else(|_| {
             warn!(
                 log,
@@ -84,7 +537,7 @@ fn main() -> anyhow::Result<()> {
     } else {
         *common::DEFAULT_RESOLUTION
     };
-    let max_depth = matches
+    let max_depth = args
         .value_of("max_depth")
         .unwrap()
         .parse::<i32>()
@@ -96,24 +549,413 @@ fn main() -> anyhow::Result<()> {
             MAX_DEPTH
         });
 
-    let default_lights = matches.is_present("default_lights");
+    let light_samples = args
+        .value_of("light_samples")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap_or_else(|_| {
+            warn!(
+                log,
+                "failed parsing light samples, falling back to one light sample"
+            );
+            1
+        });
+    let bsdf_samples = args
+        .value_of("bsdf_samples")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap_or_else(|_| {
+            warn!(
+                log,
+                "failed parsing bsdf samples, falling back to one bsdf sample"
+            );
+            1
+        });
+
+    let default_lights = args.is_present("default_lights");
+    let max_texture_size = args.value_of("max_texture_size").and_then(|size_str| {
+        size_str.parse::<u32>().ok().or_else(|| {
+            warn!(
+                log,
+                "failed parsing max texture size, textures will not be downsampled"
+            );
+            None
+        })
+    });
+
+    let shutter = args.value_of("shutter").and_then(|shutter_str| {
+        parse_shutter(&shutter_str)
+            .map_err(|_| {
+                warn!(
+                    log,
+                    "failed parsing shutter string, the scene will be rendered at rest"
+                );
+            })
+            .ok()
+    });
+
+    let material_library = args.value_of("material_library").and_then(|path| {
+        common::importer::library::MaterialLibrary::load(Path::new(path))
+            .map_err(|err| {
+                warn!(
+                    log,
+                    "failed loading material library, scene materials will be used: {:?}", err
+                );
+            })
+            .ok()
+    });
 
-    let (camera, render_scene, viewer_scene) =
-        common::importer::import(&log, &scene_path, &resolution, default_lights);
-    let sampler = pathtracer::sampler::SamplerBuilder::new(
+    let (camera, render_scene, viewer_scene) = common::importer::import(
         &log,
-        pixel_samples,
-        &camera.film.get_sample_bounds(),
+        &scene_path,
+        &asset_paths,
+        material_library.as_ref(),
+        &resolution,
+        default_lights,
+        max_texture_size,
+        args.value_of("variant"),
+        args.value_of("scene_camera"),
+        shutter,
     );
+    let parse_lens = |name| {
+        args.value_of(name).and_then(|lens_str| {
+            lens_str.parse::<f32>().ok().or_else(|| {
+                warn!(log, "failed parsing {}, keeping the scene's lens", name);
+                None
+            })
+        })
+    };
+    let lens_radius = parse_lens("lens_radius");
+    let focal_distance = parse_lens("focal_distance");
+    let camera = if lens_radius.is_some() || focal_distance.is_some() {
+        let focal_distance = focal_distance.unwrap_or_else(|| {
+            if camera.lens_radius > 0.0 {
+                camera.focal_distance
+            } else {
+                camera.cam_to_world.translation.vector.norm()
+            }
+        });
+        let lens_radius = lens_radius.unwrap_or(camera.lens_radius);
+        camera.with_lens(lens_radius, focal_distance)
+    } else {
+        camera
+    };
+    let parse_tilt_shift = |name| {
+        args.value_of(name).and_then(|offset_str| {
+            parse_offset(offset_str)
+                .map_err(|_| warn!(log, "failed parsing {}, keeping the lens straight", name))
+                .ok()
+        })
+    };
+    let lens_shift = parse_tilt_shift("lens_shift");
+    let lens_tilt = parse_tilt_shift("lens_tilt");
+    let camera = if lens_shift.is_some() || lens_tilt.is_some() {
+        let lens_shift = lens_shift.unwrap_or_else(na::Vector2::zeros);
+        let lens_tilt = lens_tilt.unwrap_or_else(na::Vector2::zeros);
+        camera.with_tilt_shift(
+            &lens_shift,
+            &na::Vector2::new(lens_tilt.x.to_radians(), lens_tilt.y.to_radians()),
+        )
+    } else {
+        camera
+    };
+    let camera = match args.value_of("orthographic") {
+        Some(height_str) => match height_str.parse::<f32>() {
+            Ok(height) => camera.with_projection(common::Projection::orthographic(
+                resolution.x / resolution.y,
+                height,
+                common::DEFAULT_Z_NEAR,
+                common::DEFAULT_Z_FAR,
+            )),
+            Err(_) => {
+                warn!(
+                    log,
+                    "failed parsing orthographic view height, keeping the scene's projection"
+                );
+                camera
+            }
+        },
+        None => camera,
+    };
+    let camera = match args.value_of("panorama") {
+        Some("spherical") => camera.spherical(),
+        Some("fisheye") => match args.value_of("fisheye_fov").unwrap().parse::<f32>() {
+            Ok(fov) => camera.fisheye(fov.to_radians()),
+            Err(_) => {
+                warn!(
+                    log,
+                    "failed parsing fisheye field of view, falling back to 180"
+                );
+                camera.fisheye(std::f32::consts::PI)
+            }
+        },
+        Some(name) => {
+            warn!(
+                log,
+                "unknown panorama {:?}, keeping the scene's projection", name
+            );
+            camera
+        }
+        None => camera,
+    };
+    let camera = match args.value_of("lens_file") {
+        Some(lens_path) => {
+            let focal_distance =
+                focal_distance.unwrap_or_else(|| camera.cam_to_world.translation.vector.norm());
+            let film_diagonal = args
+                .value_of("film_diagonal")
+                .unwrap()
+                .parse::<f32>()
+                .unwrap_or_else(|_| {
+                    warn!(log, "failed parsing film diagonal, falling back to 35");
+                    35.0
+                });
+            match pathtracer::lens::LensSystem::load(
+                Path::new(lens_path),
+                parse_lens("aperture_diameter"),
+                film_diagonal,
+                focal_distance,
+            ) {
+                Ok(lens) => camera.realistic(lens),
+                Err(err) => {
+                    warn!(
+                        log,
+                        "failed loading lens, keeping the scene's projection: {:?}", err
+                    );
+                    camera
+                }
+            }
+        }
+        None => camera,
+    };
+    let filter_radius = args.value_of("filter_radius").and_then(|radius_str| {
+        radius_str
+            .parse::<f32>()
+            .map_err(|_| {
+                warn!(
+                    log,
+                    "failed parsing filter radius, using the filter's default"
+                )
+            })
+            .ok()
+    });
+    let filter_radius = |default: f32| {
+        let radius = filter_radius.unwrap_or(default);
+        na::Vector2::new(radius, radius)
+    };
+    let camera = match args.value_of("filter").unwrap() {
+        "gaussian" => camera.with_filter(Filter::Guassian(GuassianFilter::new(
+            &filter_radius(2.0),
+            2.0,
+        ))),
+        "mitchell" => camera.with_filter(Filter::Mitchell(MitchellFilter::new(
+            &filter_radius(2.0),
+            1.0 / 3.0,
+            1.0 / 3.0,
+        ))),
+        "blackman_harris" => camera.with_filter(Filter::BlackmanHarris(BlackmanHarrisFilter::new(
+            &filter_radius(2.0),
+        ))),
+        "triangle" => {
+            camera.with_filter(Filter::Triangle(TriangleFilter::new(&filter_radius(2.0))))
+        }
+        "box" => camera.with_filter(Filter::Box(BoxFilter::new(&filter_radius(0.5)))),
+        name => {
+            warn!(
+                log,
+                "unknown filter {:?}, keeping the gaussian filter", name
+            );
+            camera
+        }
+    };
+    if let Some(crop_str) = args.value_of("crop_window") {
+        match parse_crop_window(crop_str) {
+            Ok((min, max)) => camera.film.set_crop_window(&min, &max),
+            Err(_) => warn!(log, "failed parsing crop window, rendering the whole image"),
+        }
+    }
+    if let Some(budget) = args.value_of("texture_cache") {
+        match budget.parse::<usize>() {
+            Ok(budget) => render_scene.texture_cache.set_budget(budget * 1024 * 1024),
+            Err(_) => warn!(
+                log,
+                "failed parsing texture cache budget, using the default budget"
+            ),
+        }
+    }
+    #[cfg(feature = "embree")]
+    let render_scene = if args.is_present("embree") && shutter.is_none() {
+        render_scene.with_embree(&log)
+    } else {
+        render_scene
+    };
+    if args.is_present("embree") {
+        if cfg!(not(feature = "embree")) {
+            warn!(
+                log,
+                "built without the embree feature, tracing with the native bvh"
+            );
+        } else if shutter.is_some() {
+            warn!(
+                log,
+                "embree traces instances at rest, tracing motion blur with the native bvh"
+            );
+        }
+    }
+    let sampler = sampler_builder(&log, &args, pixel_samples, &camera.film.get_sample_bounds());
     let mut integrator = pathtracer::integrator::PathIntegrator::new(&log, sampler, max_depth);
+    integrator.set_sample_splitting(light_samples, bsdf_samples);
     integrator.preprocess(&render_scene);
+    if args.is_present("ray_stats") {
+        integrator.enable_ray_stats(&camera.film.resolution);
+    }
+    if args.is_present("check_dimensions") {
+        integrator.enable_dimension_check();
+    }
+    if args.is_present("alpha") {
+        integrator.set_transparent_background(true);
+        camera.film.set_write_alpha(true);
+    }
+
+    let parse_exposure = |name: &str, default: f32| {
+        // shutter speeds are usually written as fractions
+        let parsed = args
+            .value_of(name)
+            .unwrap()
+            .splitn(2, '/')
+            .map(str::parse::<f32>)
+            .collect::<Vec<_>>();
+        match parsed.as_slice() {
+            [Ok(value)] => *value,
+            [Ok(numerator), Ok(denominator)] => numerator / denominator,
+            _ => {
+                warn!(log, "failed parsing {}, falling back to {}", name, default);
+                default
+            }
+        }
+    };
+    if ["iso", "f_stop", "shutter_speed"]
+        .iter()
+        .any(|name| args.is_given(name))
+    {
+        let exposure = common::film::Film::physical_exposure(
+            parse_exposure("iso", 100.0),
+            parse_exposure("f_stop", 1.0),
+            parse_exposure("shutter_speed", 1.0),
+        );
+        info!(log, "setting exposure to {:?}", exposure);
+        camera.film.set_exposure(exposure);
+    }
+    if args.is_present("auto_exposure") {
+        let exposure = integrator.estimate_exposure(&camera, &render_scene);
+        info!(log, "setting exposure to {:?}", exposure);
+        camera.film.set_exposure(exposure);
+    }
+    if let Some(stops) = args.value_of("exposure") {
+        match stops.parse::<f32>() {
+            Ok(stops) => camera
+                .film
+                .set_exposure(camera.film.exposure() * stops.exp2()),
+            Err(_) => warn!(
+                log,
+                "failed parsing exposure compensation, leaving the exposure"
+            ),
+        }
+    }
+    let mapper = args.value_of("tone_map").unwrap();
+    let tone_map = common::tonemap::ToneMap {
+        mapper: common::tonemap::ToneMapper::from_name(mapper).unwrap_or_else(|| {
+            warn!(log, "unknown tone mapper {:?}, clamping instead", mapper);
+            common::tonemap::ToneMapper::Clamp
+        }),
+        gamma: args.value_of("gamma").and_then(|gamma| {
+            gamma
+                .parse::<f32>()
+                .map_err(|_| warn!(log, "failed parsing gamma, encoding as srgb"))
+                .ok()
+        }),
+    };
+    camera.film.set_tone_map(tone_map);
+    match args.value_of("bit_depth").unwrap() {
+        "8" => {}
+        "16" => camera.film.set_sixteen_bit(true),
+        bit_depth => warn!(
+            log,
+            "unsupported bit depth {:?}, saving 8 bit images instead", bit_depth
+        ),
+    }
 
     debug!(log, "camera starting at: {:?}", camera.cam_to_world);
 
-    let headless = matches.is_present("headless");
+    let headless = args.is_present("headless");
+
+    let exr = if args.is_present("exr") {
+        Some(if args.is_present("half_float") {
+            common::openexr::ExrPrecision::Half
+        } else {
+            common::openexr::ExrPrecision::Float
+        })
+    } else {
+        None
+    };
+
+    if let Some(camera_path) = args.value_of("camera_path") {
+        // the orbit pivots about the origin too
+        let camera_path = if camera_path == "turntable" {
+            animation::CameraPath::Turntable {
+                pivot: na::Vector3::zeros(),
+            }
+        } else {
+            animation::CameraPath::load(Path::new(camera_path))?
+        };
+        let n_frames = args.value_of("frames").unwrap().parse::<usize>()?;
 
-    if headless {
-        let server_address = matches.value_of("server").unwrap();
+        animation::run(
+            log,
+            render_scene,
+            camera,
+            integrator,
+            &camera_path,
+            n_frames,
+            output_path,
+            exr,
+        )?;
+    } else if headless {
+        let server_address = args.value_of("server").unwrap();
+        let albedo_path = if args.is_present("albedo") {
+            Some(output_path.with_file_name("albedo.png"))
+        } else {
+            None
+        };
+
+        let parse_count = |name: &str| {
+            args.value_of(name).and_then(|count| {
+                count
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&count| count > 0)
+                    .or_else(|| {
+                        warn!(log, "failed parsing {}, ignoring it", name);
+                        None
+                    })
+            })
+        };
+        let time_limit = args.value_of("time_limit").and_then(|seconds| {
+            seconds
+                .parse::<f32>()
+                .ok()
+                .filter(|&seconds| seconds > 0.0)
+                .map(std::time::Duration::from_secs_f32)
+                .or_else(|| {
+                    warn!(log, "failed parsing time_limit, rendering without one");
+                    None
+                })
+        });
+        let passes = headless::RenderPasses {
+            passes: parse_count("passes").or(if time_limit.is_some() { None } else { Some(1) }),
+            snapshot_interval: parse_count("snapshot_interval"),
+            time_limit,
+        };
 
         headless::run(
             log,
@@ -122,9 +964,25 @@ fn main() -> anyhow::Result<()> {
             integrator,
             server_address,
             output_path,
+            albedo_path,
+            args.value_of("film_server"),
+            exr,
+            passes,
         )?;
     } else {
-        let camera_controller_type = matches.value_of("camera_controller").unwrap();
+        let camera_controller_type = args.value_of("camera_controller").unwrap();
+        let parse_distance = |name| {
+            args.value_of(name).and_then(|distance_str| {
+                distance_str.parse::<f32>().ok().or_else(|| {
+                    warn!(log, "failed parsing {}, following the camera instead", name);
+                    None
+                })
+            })
+        };
+        let stereo = viewer::anaglyph::StereoParameters {
+            interocular_distance: parse_distance("interocular"),
+            convergence_distance: parse_distance("convergence"),
+        };
 
         viewer::run(
             log,
@@ -140,6 +998,7 @@ fn main() -> anyhow::Result<()> {
             max_depth,
             init_log_level,
             allowed_modules,
+            stereo,
         );
     }
 
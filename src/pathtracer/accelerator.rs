@@ -1,8 +1,28 @@
 use super::primitive::{Primitive, SyncPrimitive};
 use super::SurfaceMediumInteraction;
 use crate::common::bounds::Bounds3;
+use crate::common::math::gamma;
 use crate::common::ray::Ray;
-use std::{sync::Arc, time::Instant};
+#[cfg(not(feature = "disable_rayon"))]
+use rayon::prelude::*;
+use std::{
+    cell::Cell,
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+/// scenes with at least this many primitives are built as an hlbvh, trading some trace speed for
+/// a build that runs in parallel and doesn't sort the primitives at every level
+const HLBVH_MIN_PRIMITIVES: usize = 1 << 18;
+/// bits per axis of the morton codes the hlbvh sorts the primitive centroids by
+const MORTON_BITS: u32 = 10;
+/// leading morton bits grouping primitives into treelets that are built independently
+const TREELET_BITS: u32 = 12;
+const N_BUCKETS: usize = 12;
 
 struct BVHPrimitiveInfo {
     pub prim_num: usize,
@@ -65,6 +85,29 @@ fn find_max_depth(root: &BVHBuildNode) -> usize {
     1 + left_depth.max(right_depth)
 }
 
+#[derive(Copy, Clone)]
+struct MortonPrimitive {
+    primitive_index: usize,
+    morton_code: u32,
+}
+
+/// spreads the lower 10 bits of `x` out to every third bit
+fn left_shift_3(mut x: u32) -> u32 {
+    if x == (1 << 10) {
+        x -= 1;
+    }
+    x = (x | (x << 16)) & 0b00000011000000000000000011111111;
+    x = (x | (x << 8)) & 0b00000011000000001111000000001111;
+    x = (x | (x << 4)) & 0b00000011000011000011000011000011;
+    x = (x | (x << 2)) & 0b00001001001001001001001001001001;
+    x
+}
+
+/// interleaves the bits of the coordinates of `v`, which lie in [0, 1024]
+fn encode_morton_3(v: &na::Vector3<f32>) -> u32 {
+    (left_shift_3(v.z as u32) << 2) | (left_shift_3(v.y as u32) << 1) | left_shift_3(v.x as u32)
+}
+
 #[derive(Copy, Clone)]
 struct BucketInfo {
     count: usize,
@@ -81,12 +124,14 @@ impl BucketInfo {
 }
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 union LinearBVHOffset {
     primitives_offset: u32,
     second_child_offset: u32,
 }
 
 #[repr(C, align(32))]
+#[derive(Clone, Copy)]
 struct LinearBVHNode {
     bounds: Bounds3,
     offset: LinearBVHOffset,
@@ -94,9 +139,444 @@ struct LinearBVHNode {
     axis: u8,
 }
 
+/// four children of the binary tree collapsed into one node, their bounds stored by axis so the
+/// four slab tests run side by side
+#[repr(C, align(64))]
+#[derive(Clone, Copy)]
+struct QBVHNode {
+    /// minimum and maximum of the child bounds, by axis then lane
+    bounds: [[[f32; 4]; 3]; 2],
+    /// node index of interior children, first primitive of leaves
+    children: [u32; 4],
+    /// primitives of leaf children, 0 for interior children
+    num_prims: [u16; 4],
+}
+
+impl QBVHNode {
+    fn empty() -> Self {
+        let empty = Bounds3::empty();
+        Self {
+            bounds: [
+                [[empty.p_min.x; 4], [empty.p_min.y; 4], [empty.p_min.z; 4]],
+                [[empty.p_max.x; 4], [empty.p_max.y; 4], [empty.p_max.z; 4]],
+            ],
+            children: [0; 4],
+            num_prims: [0; 4],
+        }
+    }
+
+    fn set_bounds(&mut self, lane: usize, bounds: &Bounds3) {
+        for axis in 0..3 {
+            self.bounds[0][axis][lane] = bounds.p_min[axis];
+            self.bounds[1][axis][lane] = bounds.p_max[axis];
+        }
+    }
+
+    /// which of the four children the ray hits before `t_max`, and the distance it enters them
+    /// at. written lane by lane over arrays so it compiles down to simd
+    #[inline]
+    fn intersect_p(&self, ray: &RayLanes, t_max: f32) -> ([bool; 4], [f32; 4]) {
+        let mut t0 = [0.0f32; 4];
+        let mut t1 = [t_max; 4];
+        for axis in 0..3 {
+            let near = &self.bounds[ray.dir_is_neg[axis]][axis];
+            let far = &self.bounds[1 - ray.dir_is_neg[axis]][axis];
+            for lane in 0..4 {
+                t0[lane] = t0[lane].max((near[lane] - ray.o[axis]) * ray.inv_dir[axis]);
+                // padded like `Bounds3::intersect_p_precomp` for robustness
+                t1[lane] = t1[lane]
+                    .min((far[lane] - ray.o[axis]) * ray.inv_dir[axis] * (1.0 + 2.0 * gamma(3)));
+            }
+        }
+
+        let mut hit = [false; 4];
+        for lane in 0..4 {
+            hit[lane] = t0[lane] <= t1[lane];
+        }
+        (hit, t0)
+    }
+}
+
+/// ray split by axis for the four wide slab tests
+struct RayLanes {
+    o: [f32; 3],
+    inv_dir: [f32; 3],
+    dir_is_neg: [usize; 3],
+}
+
+impl RayLanes {
+    fn new(r: &Ray) -> Self {
+        let inv_dir = [1.0 / r.d.x, 1.0 / r.d.y, 1.0 / r.d.z];
+        Self {
+            o: [r.o.x, r.o.y, r.o.z],
+            inv_dir,
+            dir_is_neg: [
+                (inv_dir[0] < 0.0) as usize,
+                (inv_dir[1] < 0.0) as usize,
+                (inv_dir[2] < 0.0) as usize,
+            ],
+        }
+    }
+}
+
+/// lanes ordered by `t`, nearest first
+fn nearest_first(t: &[f32; 4]) -> [usize; 4] {
+    let mut order = [0, 1, 2, 3];
+    order.sort_unstable_by(|&a, &b| t[a].partial_cmp(&t[b]).unwrap());
+    order
+}
+
+/// collapses the flattened binary tree four wide, opening up the largest interior children
+/// until there are four
+fn collapse_bvh(nodes: &[LinearBVHNode]) -> Vec<QBVHNode> {
+    let mut wide_nodes = Vec::with_capacity(nodes.len() / 2 + 1);
+    if let Some(root) = nodes.first() {
+        if root.num_prims > 0 {
+            let mut node = QBVHNode::empty();
+            node.set_bounds(0, &root.bounds);
+            node.children[0] = unsafe { root.offset.primitives_offset };
+            node.num_prims[0] = root.num_prims;
+            wide_nodes.push(node);
+        } else {
+            collapse_node(nodes, 0, &mut wide_nodes);
+        }
+    }
+
+    wide_nodes
+}
+
+fn collapse_node(nodes: &[LinearBVHNode], idx: usize, wide_nodes: &mut Vec<QBVHNode>) -> u32 {
+    let children = |i: usize| {
+        [i + 1, unsafe { nodes[i].offset.second_child_offset }
+            as usize]
+    };
+
+    let mut lanes = children(idx).to_vec();
+    while lanes.len() < 4 {
+        let open = lanes
+            .iter()
+            .enumerate()
+            .filter(|(_, i)| nodes[**i].num_prims == 0)
+            .max_by(|(_, a), (_, b)| {
+                nodes[**a]
+                    .bounds
+                    .surface_area()
+                    .partial_cmp(&nodes[**b].bounds.surface_area())
+                    .unwrap()
+            })
+            .map(|(lane, _)| lane);
+        match open {
+            Some(lane) => {
+                let [first, second] = children(lanes[lane]);
+                lanes[lane] = first;
+                lanes.insert(lane + 1, second);
+            }
+            None => break,
+        }
+    }
+
+    let my_offset = wide_nodes.len();
+    wide_nodes.push(QBVHNode::empty());
+    for (lane, &i) in lanes.iter().enumerate() {
+        let child = &nodes[i];
+        wide_nodes[my_offset].set_bounds(lane, &child.bounds);
+        if child.num_prims > 0 {
+            wide_nodes[my_offset].children[lane] = unsafe { child.offset.primitives_offset };
+            wide_nodes[my_offset].num_prims[lane] = child.num_prims;
+        } else {
+            wide_nodes[my_offset].children[lane] = collapse_node(nodes, i, wide_nodes);
+        }
+    }
+
+    my_offset as u32
+}
+
+/// refitted trees are rebuilt once their sah cost exceeds the cost after the last build by this
+/// factor
+pub const DEFAULT_QUALITY_THRESHOLD: f32 = 1.5;
+
+/// bumped whenever the layout of the cache files or of the trees in them changes
+const BVH_CACHE_VERSION: u32 = 1;
+const BVH_CACHE_MAGIC: &[u8; 8] = b"PTBVHCCH";
+
+/// a built tree, with the input index of each primitive in the order its leaves index them
+struct CachedTree {
+    nodes: Vec<LinearBVHNode>,
+    order: Vec<u32>,
+}
+
+#[derive(Default)]
+struct BVHCacheState {
+    trees: HashMap<u64, Arc<CachedTree>>,
+    /// trees taken or built this run, the ones saved
+    used: HashSet<u64>,
+    built: bool,
+}
+
+/// trees built for a scene, kept in a file next to it so later runs skip building them while the
+/// geometry stays the same. trees are looked up by a hash of the bounds of their primitives,
+/// which is all the build depends on
+pub struct BVHCache {
+    path: PathBuf,
+    state: Mutex<BVHCacheState>,
+    log: slog::Logger,
+}
+
+impl BVHCache {
+    /// the cache in the file at `path`, empty if it's missing or unreadable
+    pub fn load(log: &slog::Logger, path: &Path) -> Self {
+        let log = log.new(o!("module" => "bvh_cache"));
+        let trees = match std::fs::File::open(path) {
+            Ok(file) => read_trees(&mut BufReader::new(file)).unwrap_or_else(|err| {
+                warn!(log, "ignoring unreadable bvh cache {:?}: {}", path, err);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        debug!(
+            log,
+            "loaded {:?} cached bvh trees from {:?}",
+            trees.len(),
+            path
+        );
+
+        Self {
+            path: path.to_path_buf(),
+            state: Mutex::new(BVHCacheState {
+                trees,
+                ..Default::default()
+            }),
+            log,
+        }
+    }
+
+    /// writes the trees used this run back to the file, if any had to be built or dropped
+    pub fn save(&self) {
+        let state = self.state.lock().unwrap();
+        if !state.built && state.used.len() == state.trees.len() {
+            return;
+        }
+
+        let trees: Vec<_> = state
+            .trees
+            .iter()
+            .filter(|(key, _)| state.used.contains(*key))
+            .collect();
+        let written = std::fs::File::create(&self.path)
+            .and_then(|file| write_trees(&mut BufWriter::new(file), &trees));
+        match written {
+            Ok(()) => debug!(
+                self.log,
+                "saved {:?} bvh trees to {:?}",
+                trees.len(),
+                self.path
+            ),
+            Err(err) => warn!(self.log, "failed saving bvh cache {:?}: {}", self.path, err),
+        }
+    }
+
+    fn key(primitive_info: &[BVHPrimitiveInfo], max_prims_in_node: usize) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        BVH_CACHE_VERSION.hash(&mut hasher);
+        max_prims_in_node.hash(&mut hasher);
+        primitive_info.len().hash(&mut hasher);
+        for pi in primitive_info {
+            for axis in 0..3 {
+                pi.bounds.p_min[axis].to_bits().hash(&mut hasher);
+                pi.bounds.p_max[axis].to_bits().hash(&mut hasher);
+            }
+        }
+
+        hasher.finish()
+    }
+
+    fn get(&self, key: u64, num_prims: usize) -> Option<Arc<CachedTree>> {
+        let mut state = self.state.lock().unwrap();
+        let tree = Arc::clone(state.trees.get(&key)?);
+        // guards against hash collisions with trees of other sizes
+        if tree.order.len() != num_prims || tree.order.iter().any(|&i| i as usize >= num_prims) {
+            return None;
+        }
+
+        state.used.insert(key);
+        Some(tree)
+    }
+
+    fn insert(&self, key: u64, nodes: &[LinearBVHNode], order: &[usize]) {
+        let mut state = self.state.lock().unwrap();
+        state.trees.insert(
+            key,
+            Arc::new(CachedTree {
+                nodes: nodes.to_vec(),
+                order: order.iter().map(|&i| i as u32).collect(),
+            }),
+        );
+        state.used.insert(key);
+        state.built = true;
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> std::io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> std::io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_trees(reader: &mut impl Read) -> std::io::Result<HashMap<u64, Arc<CachedTree>>> {
+    let invalid = |msg| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != BVH_CACHE_MAGIC {
+        return Err(invalid("not a bvh cache"));
+    }
+    if read_u32(reader)? != BVH_CACHE_VERSION {
+        return Err(invalid("bvh cache of another version"));
+    }
+
+    let mut trees = HashMap::new();
+    for _ in 0..read_u64(reader)? {
+        let key = read_u64(reader)?;
+        // read one by one, so corrupt counts run out of file instead of memory
+        let mut nodes = vec![];
+        for _ in 0..read_u64(reader)? {
+            let mut bounds = [0.0f32; 6];
+            for value in bounds.iter_mut() {
+                *value = f32::from_bits(read_u32(reader)?);
+            }
+            let offset = read_u32(reader)?;
+            let mut num_prims = [0u8; 2];
+            reader.read_exact(&mut num_prims)?;
+            let mut axis = [0u8; 1];
+            reader.read_exact(&mut axis)?;
+            nodes.push(LinearBVHNode {
+                bounds: Bounds3::new(
+                    na::Point3::new(bounds[0], bounds[1], bounds[2]),
+                    na::Point3::new(bounds[3], bounds[4], bounds[5]),
+                ),
+                offset: LinearBVHOffset {
+                    primitives_offset: offset,
+                },
+                num_prims: u16::from_le_bytes(num_prims),
+                axis: axis[0],
+            });
+        }
+        let mut order = vec![];
+        for _ in 0..read_u64(reader)? {
+            order.push(read_u32(reader)?);
+        }
+        trees.insert(key, Arc::new(CachedTree { nodes, order }));
+    }
+
+    Ok(trees)
+}
+
+fn write_trees(writer: &mut impl Write, trees: &[(&u64, &Arc<CachedTree>)]) -> std::io::Result<()> {
+    writer.write_all(BVH_CACHE_MAGIC)?;
+    writer.write_all(&BVH_CACHE_VERSION.to_le_bytes())?;
+    writer.write_all(&(trees.len() as u64).to_le_bytes())?;
+    for (key, tree) in trees {
+        writer.write_all(&key.to_le_bytes())?;
+        writer.write_all(&(tree.nodes.len() as u64).to_le_bytes())?;
+        for node in &tree.nodes {
+            for p in [node.bounds.p_min, node.bounds.p_max].iter() {
+                for axis in 0..3 {
+                    writer.write_all(&p[axis].to_bits().to_le_bytes())?;
+                }
+            }
+            // both offsets are the same u32
+            writer.write_all(&unsafe { node.offset.primitives_offset }.to_le_bytes())?;
+            writer.write_all(&node.num_prims.to_le_bytes())?;
+            writer.write_all(&[node.axis])?;
+        }
+        writer.write_all(&(tree.order.len() as u64).to_le_bytes())?;
+        for i in &tree.order {
+            writer.write_all(&i.to_le_bytes())?;
+        }
+    }
+
+    writer.flush()
+}
+
+/// bvh work done by the traversals of one thread, for the traversal heat maps. primitives
+/// include the instances of the top level bvh, embree's traversal isn't counted
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TraversalStats {
+    pub nodes_visited: u64,
+    pub primitives_tested: u64,
+}
+
+thread_local! {
+    static TRAVERSAL_STATS: Cell<TraversalStats> = Cell::new(TraversalStats::default());
+}
+
+/// bvh work done on this thread since the last call
+pub fn take_traversal_stats() -> TraversalStats {
+    TRAVERSAL_STATS.with(|stats| stats.replace(TraversalStats::default()))
+}
+
+/// counted once per traversal rather than per node, to keep it off the inner loops
+fn count_traversal(nodes_visited: u64, primitives_tested: u64) {
+    TRAVERSAL_STATS.with(|stats| {
+        let mut s = stats.get();
+        s.nodes_visited += nodes_visited;
+        s.primitives_tested += primitives_tested;
+        stats.set(s);
+    });
+}
+
+/// closest hit of a ray traced in a batch, `found` is false for rays that hit nothing
+#[derive(Default)]
+pub struct Hit<'a> {
+    pub found: bool,
+    pub isect: SurfaceMediumInteraction<'a>,
+}
+
+/// what scenes are traced with, the bvh unless another backend is enabled
+pub trait Accelerator {
+    fn intersect<'a>(&'a self, r: &mut Ray, isect: &mut SurfaceMediumInteraction<'a>) -> bool;
+    fn intersect_p(&self, r: &Ray) -> bool;
+
+    /// closest hits of a batch of rays, `hits[i]` for `rays[i]`. backends that can trace
+    /// coherent rays together override this, the rest trace them one by one
+    fn intersect_n<'a>(&'a self, rays: &mut [Ray], hits: &mut [Hit<'a>]) {
+        for (r, hit) in rays.iter_mut().zip(hits.iter_mut()) {
+            hit.found = self.intersect(r, &mut hit.isect);
+        }
+    }
+
+    /// whether each of a batch of rays is blocked before its `t_max`
+    fn intersect_p_n(&self, rays: &[Ray], occluded: &mut [bool]) {
+        for (r, occluded) in rays.iter().zip(occluded.iter_mut()) {
+            *occluded = self.intersect_p(r);
+        }
+    }
+}
+
+pub trait SyncAccelerator: Accelerator + Send + Sync {}
+impl<T> SyncAccelerator for T where T: Accelerator + Send + Sync {}
+
+/// what `BVH::update` did to bring the tree up to date with its primitives
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BVHUpdate {
+    Refit,
+    Rebuild,
+}
+
 pub struct BVH {
     primitives: Vec<Arc<dyn SyncPrimitive>>,
     nodes: Box<[LinearBVHNode]>,
+    /// the binary nodes collapsed four wide, which is what rays traverse
+    wide_nodes: Vec<QBVHNode>,
+    max_prims_in_node: usize,
+    build_cost: f32,
+    quality_threshold: f32,
+    log: slog::Logger,
 }
 
 impl BVH {
@@ -104,6 +584,25 @@ impl BVH {
         log: &slog::Logger,
         primitives: Vec<Arc<dyn SyncPrimitive>>,
         max_prims_in_node: &usize,
+    ) -> Self {
+        BVH::build(log, primitives, max_prims_in_node, None)
+    }
+
+    /// like `new`, taking the tree from `cache` when it has one for the same primitive bounds
+    pub fn with_cache(
+        log: &slog::Logger,
+        primitives: Vec<Arc<dyn SyncPrimitive>>,
+        max_prims_in_node: &usize,
+        cache: &BVHCache,
+    ) -> Self {
+        BVH::build(log, primitives, max_prims_in_node, Some(cache))
+    }
+
+    fn build(
+        log: &slog::Logger,
+        primitives: Vec<Arc<dyn SyncPrimitive>>,
+        max_prims_in_node: &usize,
+        cache: Option<&BVHCache>,
     ) -> Self {
         let start = Instant::now();
 
@@ -113,6 +612,11 @@ impl BVH {
             return Self {
                 primitives,
                 nodes: Box::new([]),
+                wide_nodes: vec![],
+                max_prims_in_node: *max_prims_in_node,
+                build_cost: 0.0,
+                quality_threshold: DEFAULT_QUALITY_THRESHOLD,
+                log,
             };
         }
 
@@ -123,18 +627,72 @@ impl BVH {
             primitive_info.push(BVHPrimitiveInfo::new(i, primitives[i].world_bound()))
         }
 
+        let key = cache.map(|_| BVHCache::key(&primitive_info, *max_prims_in_node));
+        let cached = cache
+            .zip(key)
+            .and_then(|(cache, key)| cache.get(key, primitives.len()));
+        let (nodes, order) = match cached {
+            Some(tree) => {
+                debug!(log, "bvh tree taken from the cache");
+                (
+                    tree.nodes.clone().into_boxed_slice(),
+                    tree.order.iter().map(|&i| i as usize).collect::<Vec<_>>(),
+                )
+            }
+            None => {
+                let (nodes, order) = BVH::build_nodes(&log, &mut primitive_info, max_prims_in_node);
+                if let (Some(cache), Some(key)) = (cache, key) {
+                    cache.insert(key, &nodes, &order);
+                }
+                (nodes, order)
+            }
+        };
+        let ordered_prims = order.iter().map(|&i| Arc::clone(&primitives[i])).collect();
+
+        debug!(log, "bvh tree took {:?} to construct", start.elapsed());
+        let mut bvh = Self {
+            primitives: ordered_prims,
+            wide_nodes: collapse_bvh(&nodes),
+            nodes,
+            max_prims_in_node: *max_prims_in_node,
+            build_cost: 0.0,
+            quality_threshold: DEFAULT_QUALITY_THRESHOLD,
+            log,
+        };
+        bvh.build_cost = bvh.sah_cost();
+        debug!(bvh.log, "bvh sah cost: {:?}", bvh.build_cost);
+
+        bvh
+    }
+
+    /// the flattened nodes and the order of the primitives they index
+    fn build_nodes(
+        log: &slog::Logger,
+        primitive_info: &mut Vec<BVHPrimitiveInfo>,
+        max_prims_in_node: &usize,
+    ) -> (Box<[LinearBVHNode]>, Vec<usize>) {
         let mut total_nodes = 0usize;
-        let mut ordered_prims = Vec::<Arc<dyn SyncPrimitive>>::with_capacity(primitives.len());
-
-        let root = BVH::recursive_build(
-            &mut primitive_info,
-            max_prims_in_node,
-            0,
-            primitives.len(),
-            &mut total_nodes,
-            &mut ordered_prims,
-            &primitives,
-        );
+        let mut ordered_prims = Vec::with_capacity(primitive_info.len());
+
+        let root = if primitive_info.len() >= HLBVH_MIN_PRIMITIVES {
+            BVH::hlbvh_build(
+                log,
+                primitive_info,
+                max_prims_in_node,
+                &mut total_nodes,
+                &mut ordered_prims,
+            )
+        } else {
+            let num_prims = primitive_info.len();
+            BVH::recursive_build(
+                primitive_info,
+                max_prims_in_node,
+                0,
+                num_prims,
+                &mut total_nodes,
+                &mut ordered_prims,
+            )
+        };
 
         debug!(log, "bvh tree"; "max depth" =>
             find_max_depth(&root)
@@ -144,13 +702,7 @@ impl BVH {
         let mut offset = 0usize;
         BVH::flatten_bvh_tree(&root, &mut nodes, &mut offset);
 
-        let duration = start.elapsed();
-        debug!(log, "bvh tree took {:?} to construct", duration);
-        let nodes = unsafe { nodes.assume_init() };
-        Self {
-            primitives: ordered_prims,
-            nodes,
-        }
+        (unsafe { nodes.assume_init() }, ordered_prims)
     }
 
     fn recursive_build(
@@ -159,8 +711,7 @@ impl BVH {
         start: usize,
         end: usize,
         total_size: &mut usize,
-        ordered_prims: &mut Vec<Arc<dyn SyncPrimitive>>,
-        primitives: &Vec<Arc<dyn SyncPrimitive>>,
+        ordered_prims: &mut Vec<usize>,
     ) -> Box<BVHBuildNode> {
         *total_size += 1;
 
@@ -174,7 +725,7 @@ impl BVH {
             let first_prim_offset = ordered_prims.len();
             for i in start..end {
                 let prim_num = primitive_info[i].prim_num;
-                ordered_prims.push(Arc::clone(&primitives[prim_num]));
+                ordered_prims.push(prim_num);
             }
 
             return Box::new(BVHBuildNode::new_leaf(first_prim_offset, num_prims, bounds));
@@ -191,7 +742,7 @@ impl BVH {
                 let first_prim_offset = ordered_prims.len();
                 for i in start..end {
                     let prim_num = primitive_info[i].prim_num;
-                    ordered_prims.push(Arc::clone(&primitives[prim_num]));
+                    ordered_prims.push(prim_num);
                 }
 
                 return Box::new(BVHBuildNode::new_leaf(first_prim_offset, num_prims, bounds));
@@ -203,7 +754,6 @@ impl BVH {
                         a.centroid[dim].partial_cmp(&b.centroid[dim]).unwrap()
                     });
                 } else {
-                    const N_BUCKETS: usize = 12;
                     let mut buckets = [BucketInfo::new(); N_BUCKETS];
 
                     for i in start..end {
@@ -270,7 +820,7 @@ impl BVH {
                         let first_prim_offset = ordered_prims.len();
                         for i in start..end {
                             let prim_num = primitive_info[i].prim_num;
-                            ordered_prims.push(Arc::clone(&primitives[prim_num]));
+                            ordered_prims.push(prim_num);
                         }
 
                         return Box::new(BVHBuildNode::new_leaf(
@@ -290,7 +840,6 @@ impl BVH {
                         mid,
                         total_size,
                         ordered_prims,
-                        primitives,
                     ),
                     BVH::recursive_build(
                         primitive_info,
@@ -299,13 +848,229 @@ impl BVH {
                         end,
                         total_size,
                         ordered_prims,
-                        primitives,
                     ),
                 ));
             }
         }
     }
 
+    /// linear bvh over the morton order of the primitives. the treelets under the leading morton
+    /// bits are split at the bits of the codes in parallel, only the few nodes above them are
+    /// built with the sah
+    fn hlbvh_build(
+        log: &slog::Logger,
+        primitive_info: &[BVHPrimitiveInfo],
+        max_prims_in_node: &usize,
+        total_nodes: &mut usize,
+        ordered_prims: &mut Vec<usize>,
+    ) -> Box<BVHBuildNode> {
+        let centroid_bounds = primitive_info.iter().fold(Bounds3::empty(), |bounds, pi| {
+            Bounds3::union_p(&bounds, &pi.centroid)
+        });
+
+        let morton_scale = (1 << MORTON_BITS) as f32;
+        let morton_prim = |pi: &BVHPrimitiveInfo| MortonPrimitive {
+            primitive_index: pi.prim_num,
+            morton_code: encode_morton_3(&(morton_scale * centroid_bounds.offset(&pi.centroid))),
+        };
+        let mut morton_prims: Vec<MortonPrimitive>;
+        #[cfg(feature = "disable_rayon")]
+        {
+            morton_prims = primitive_info.iter().map(morton_prim).collect();
+            morton_prims.sort_unstable_by_key(|mp| mp.morton_code);
+        }
+        #[cfg(not(feature = "disable_rayon"))]
+        {
+            morton_prims = primitive_info.par_iter().map(morton_prim).collect();
+            morton_prims.par_sort_unstable_by_key(|mp| mp.morton_code);
+        }
+
+        let treelet_mask = ((1u32 << TREELET_BITS) - 1) << (3 * MORTON_BITS - TREELET_BITS);
+        let mut treelet_ranges = vec![];
+        let mut start = 0;
+        for end in 1..=morton_prims.len() {
+            if end == morton_prims.len()
+                || morton_prims[start].morton_code & treelet_mask
+                    != morton_prims[end].morton_code & treelet_mask
+            {
+                treelet_ranges.push((start, end));
+                start = end;
+            }
+        }
+        debug!(log, "hlbvh treelets: {:?}", treelet_ranges.len());
+
+        let first_bit = (3 * MORTON_BITS - TREELET_BITS) as i32 - 1;
+        let build_treelet = |&(start, end): &(usize, usize)| {
+            let mut nodes = 0usize;
+            let root = BVH::emit_lbvh(
+                primitive_info,
+                &morton_prims[start..end],
+                start,
+                first_bit,
+                *max_prims_in_node,
+                &mut nodes,
+            );
+            (root, nodes)
+        };
+        let treelets: Vec<(Box<BVHBuildNode>, usize)>;
+        #[cfg(feature = "disable_rayon")]
+        {
+            treelets = treelet_ranges.iter().map(build_treelet).collect();
+        }
+        #[cfg(not(feature = "disable_rayon"))]
+        {
+            treelets = treelet_ranges.par_iter().map(build_treelet).collect();
+        }
+
+        ordered_prims.extend(morton_prims.iter().map(|mp| mp.primitive_index));
+
+        let mut roots = Vec::with_capacity(treelets.len());
+        for (root, nodes) in treelets {
+            *total_nodes += nodes;
+            roots.push(root);
+        }
+
+        BVH::build_upper_sah(roots, total_nodes)
+    }
+
+    /// splits the sorted `morton_prims` where `bit_index` and the bits below it flip. leaves
+    /// index the primitives from `first_prim_offset`, their position in the whole morton order
+    fn emit_lbvh(
+        primitive_info: &[BVHPrimitiveInfo],
+        morton_prims: &[MortonPrimitive],
+        first_prim_offset: usize,
+        bit_index: i32,
+        max_prims_in_node: usize,
+        total_nodes: &mut usize,
+    ) -> Box<BVHBuildNode> {
+        let num_prims = morton_prims.len();
+        if bit_index < 0 || num_prims <= max_prims_in_node {
+            *total_nodes += 1;
+            let bounds = morton_prims.iter().fold(Bounds3::empty(), |bounds, mp| {
+                Bounds3::union(&bounds, &primitive_info[mp.primitive_index].bounds)
+            });
+
+            return Box::new(BVHBuildNode::new_leaf(first_prim_offset, num_prims, bounds));
+        }
+
+        let mask = 1 << bit_index;
+        if morton_prims[0].morton_code & mask == morton_prims[num_prims - 1].morton_code & mask {
+            // all on the same side of this plane
+            return BVH::emit_lbvh(
+                primitive_info,
+                morton_prims,
+                first_prim_offset,
+                bit_index - 1,
+                max_prims_in_node,
+                total_nodes,
+            );
+        }
+
+        // the codes are sorted, so the bit flips exactly once
+        let (mut lo, mut hi) = (0, num_prims - 1);
+        while lo + 1 < hi {
+            let mid = (lo + hi) / 2;
+            if morton_prims[mid].morton_code & mask == 0 {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+
+        *total_nodes += 1;
+        let (left, right) = morton_prims.split_at(hi);
+        Box::new(BVHBuildNode::new_interior(
+            (bit_index % 3) as usize,
+            BVH::emit_lbvh(
+                primitive_info,
+                left,
+                first_prim_offset,
+                bit_index - 1,
+                max_prims_in_node,
+                total_nodes,
+            ),
+            BVH::emit_lbvh(
+                primitive_info,
+                right,
+                first_prim_offset + hi,
+                bit_index - 1,
+                max_prims_in_node,
+                total_nodes,
+            ),
+        ))
+    }
+
+    /// joins the treelet roots with the sah
+    fn build_upper_sah(
+        mut roots: Vec<Box<BVHBuildNode>>,
+        total_nodes: &mut usize,
+    ) -> Box<BVHBuildNode> {
+        if roots.len() == 1 {
+            return roots.pop().unwrap();
+        }
+        *total_nodes += 1;
+
+        let centroid =
+            |node: &BVHBuildNode| node.bounds.p_min + 0.5 * (node.bounds.p_max - node.bounds.p_min);
+        let bounds = roots.iter().fold(Bounds3::empty(), |bounds, node| {
+            Bounds3::union(&bounds, &node.bounds)
+        });
+        let centroid_bounds = roots.iter().fold(Bounds3::empty(), |bounds, node| {
+            Bounds3::union_p(&bounds, &centroid(node))
+        });
+        let dim = centroid_bounds.maximum_extent();
+
+        let (left, right) = if centroid_bounds.p_max[dim] == centroid_bounds.p_min[dim] {
+            let right = roots.split_off(roots.len() / 2);
+            (roots, right)
+        } else {
+            let bucket = |node: &BVHBuildNode| {
+                ((N_BUCKETS as f32 * centroid_bounds.offset(&centroid(node))[dim]) as usize)
+                    .min(N_BUCKETS - 1)
+            };
+            let mut buckets = [BucketInfo::new(); N_BUCKETS];
+            for node in &roots {
+                let b = bucket(node);
+                buckets[b].count += 1;
+                buckets[b].bounds = Bounds3::union(&buckets[b].bounds, &node.bounds);
+            }
+
+            let mut min_cost = std::f32::INFINITY;
+            let mut min_cost_split_bucket = 0usize;
+            for i in 0..(N_BUCKETS - 1) {
+                let (b0, count0) = buckets[..=i]
+                    .iter()
+                    .fold((Bounds3::empty(), 0), |(b, n), bucket| {
+                        (Bounds3::union(&b, &bucket.bounds), n + bucket.count)
+                    });
+                let (b1, count1) = buckets[i + 1..]
+                    .iter()
+                    .fold((Bounds3::empty(), 0), |(b, n), bucket| {
+                        (Bounds3::union(&b, &bucket.bounds), n + bucket.count)
+                    });
+                let cost = 1.0
+                    + (count0 as f32 * b0.surface_area() + count1 as f32 * b1.surface_area())
+                        / bounds.surface_area();
+                if cost < min_cost {
+                    min_cost = cost;
+                    min_cost_split_bucket = i;
+                }
+            }
+
+            // the lowest and highest centroids land in the first and last bucket, so both sides
+            // of any split hold a treelet
+            roots
+                .into_iter()
+                .partition(|node| bucket(node) <= min_cost_split_bucket)
+        };
+
+        Box::new(BVHBuildNode::new_interior(
+            dim,
+            BVH::build_upper_sah(left, total_nodes),
+            BVH::build_upper_sah(right, total_nodes),
+        ))
+    }
+
     fn flatten_bvh_tree(
         node: &Box<BVHBuildNode>,
         linear_nodes: &mut Box<[std::mem::MaybeUninit<LinearBVHNode>]>,
@@ -345,6 +1110,101 @@ impl BVH {
         my_offset
     }
 
+    /// primitives in the order of the tree, the indices used by `replace_primitives`
+    pub fn primitives(&self) -> &[Arc<dyn SyncPrimitive>] {
+        &self.primitives
+    }
+
+    pub fn set_quality_threshold(&mut self, quality_threshold: f32) {
+        self.quality_threshold = quality_threshold;
+    }
+
+    /// expected cost of tracing a ray through the tree under the surface area heuristic, with
+    /// the same unit traversal and intersection costs the build uses
+    pub fn sah_cost(&self) -> f32 {
+        let root_area = match self.nodes.first() {
+            Some(root) if root.bounds.surface_area() > 0.0 => root.bounds.surface_area(),
+            _ => return 0.0,
+        };
+
+        self.nodes
+            .iter()
+            .map(|node| {
+                let cost = if node.num_prims > 0 {
+                    node.num_prims as f32
+                } else {
+                    1.0
+                };
+                cost * node.bounds.surface_area() / root_area
+            })
+            .sum()
+    }
+
+    /// swaps primitives in place, e.g. after moving or deforming them, and updates the tree
+    pub fn replace_primitives<I>(&mut self, edits: I) -> BVHUpdate
+    where
+        I: IntoIterator<Item = (usize, Arc<dyn SyncPrimitive>)>,
+    {
+        for (index, primitive) in edits {
+            self.primitives[index] = primitive;
+        }
+
+        self.update()
+    }
+
+    /// refits the node bounds to the current primitive bounds. refitting keeps the topology, so
+    /// large motions leave overlapping nodes behind that slow traversal down. the tree is rebuilt
+    /// once its sah cost degrades past the quality threshold
+    pub fn update(&mut self) -> BVHUpdate {
+        let start = Instant::now();
+        self.refit();
+        let cost = self.sah_cost();
+
+        if cost > self.build_cost * self.quality_threshold {
+            info!(
+                self.log,
+                "bvh sah cost went from {:?} to {:?}, rebuilding", self.build_cost, cost
+            );
+            let primitives = std::mem::take(&mut self.primitives);
+            let quality_threshold = self.quality_threshold;
+            *self = BVH::new(&self.log, primitives, &self.max_prims_in_node);
+            self.quality_threshold = quality_threshold;
+
+            BVHUpdate::Rebuild
+        } else {
+            debug!(
+                self.log,
+                "bvh refit took {:?}, sah cost {:?}",
+                start.elapsed(),
+                cost
+            );
+
+            BVHUpdate::Refit
+        }
+    }
+
+    /// updates the node bounds bottom up to the current primitive bounds, keeping the topology.
+    /// `update` also rebuilds the tree once refitting degraded it too far
+    pub fn refit(&mut self) {
+        // nodes are laid out depth first, children always come after their parent
+        for i in (0..self.nodes.len()).rev() {
+            let node = &self.nodes[i];
+            let bounds = if node.num_prims > 0 {
+                let first = unsafe { node.offset.primitives_offset } as usize;
+                self.primitives[first..first + node.num_prims as usize]
+                    .iter()
+                    .fold(Bounds3::empty(), |b, prim| {
+                        Bounds3::union(&b, &prim.world_bound())
+                    })
+            } else {
+                let second = unsafe { node.offset.second_child_offset } as usize;
+                Bounds3::union(&self.nodes[i + 1].bounds, &self.nodes[second].bounds)
+            };
+            self.nodes[i].bounds = bounds;
+        }
+        self.wide_nodes = collapse_bvh(&self.nodes);
+    }
+
     pub fn get_bounding_boxes(&self) -> Vec<Bounds3> {
         let mut bounds = Vec::with_capacity(self.nodes.len());
         for node in self.nodes.as_ref() {
@@ -355,123 +1215,273 @@ impl BVH {
     }
 }
 
-impl Primitive for BVH {
-    fn intersect<'a>(&'a self, r: &mut Ray, mut isect: &mut SurfaceMediumInteraction<'a>) -> bool {
-        if self.nodes.is_empty() {
-            return false;
+impl Accelerator for BVH {
+    fn intersect<'a>(&'a self, r: &mut Ray, isect: &mut SurfaceMediumInteraction<'a>) -> bool {
+        Primitive::intersect(self, r, isect)
+    }
+
+    fn intersect_p(&self, r: &Ray) -> bool {
+        Primitive::intersect_p(self, r)
+    }
+
+    fn intersect_n<'a>(&'a self, rays: &mut [Ray], hits: &mut [Hit<'a>]) {
+        for (rays, hits) in rays
+            .chunks_mut(PACKET_SIZE)
+            .zip(hits.chunks_mut(PACKET_SIZE))
+        {
+            self.intersect_packet(rays, hits);
         }
+    }
 
-        let mut hit = false;
-        let inv_dir = na::Vector3::new(1.0f32 / r.d.x, 1.0f32 / r.d.y, 1.0f32 / r.d.z);
-        let dir_is_neg = [inv_dir.x < 0.0, inv_dir.y < 0.0, inv_dir.z < 0.0];
+    fn intersect_p_n(&self, rays: &[Ray], occluded: &mut [bool]) {
+        for (rays, occluded) in rays
+            .chunks(PACKET_SIZE)
+            .zip(occluded.chunks_mut(PACKET_SIZE))
+        {
+            self.intersect_p_packet(rays, occluded);
+        }
+    }
+}
+
+/// entries on the traversal stack, each node pushes at most three more than it pops
+const MAX_TO_VISIT: usize = 3 * 64 + 1;
+/// rays traversing the tree together, one bit of a mask each
+const PACKET_SIZE: usize = 64;
+
+/// mask of the first `n` rays of a packet
+fn packet_mask(n: usize) -> u64 {
+    if n >= PACKET_SIZE {
+        !0
+    } else {
+        (1 << n) - 1
+    }
+}
+
+/// indices of the rays set in `mask`
+fn rays_in(mut mask: u64) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        if mask == 0 {
+            None
+        } else {
+            let i = mask.trailing_zeros() as usize;
+            mask &= mask - 1;
+            Some(i)
+        }
+    })
+}
+
+impl BVH {
+    /// the rays of a packet go down the tree together, each node is loaded once for all the rays
+    /// still inside it. children are visited nearest first by the nearest ray entering them
+    fn intersect_packet<'a>(&'a self, rays: &mut [Ray], hits: &mut [Hit<'a>]) {
+        for hit in hits.iter_mut() {
+            hit.found = false;
+        }
+        if self.wide_nodes.is_empty() {
+            return;
+        }
 
-        let mut to_visit_offset = 0;
-        let mut curr_node_idx = 0;
-        let mut nodes_to_visit = [0; 64];
-        loop {
-            let node = &self.nodes[curr_node_idx];
-
-            if node.bounds.intersect_p_precomp(r, &inv_dir, &dir_is_neg) {
-                if node.num_prims > 0 {
-                    for i in 0..node.num_prims {
-                        unsafe {
-                            if self.primitives[node.offset.primitives_offset as usize + i as usize]
-                                .as_ref()
-                                .intersect(r, &mut isect)
-                            {
-                                hit = true;
+        let lanes = rays.iter().map(RayLanes::new).collect::<Vec<_>>();
+        let (mut nodes_visited, mut primitives_tested) = (0, 0);
+        let mut nodes_to_visit = [(0u32, 0u64); MAX_TO_VISIT];
+        nodes_to_visit[0] = (0, packet_mask(rays.len()));
+        let mut to_visit_offset = 1;
+        while to_visit_offset > 0 {
+            to_visit_offset -= 1;
+            let (node_idx, active) = nodes_to_visit[to_visit_offset];
+            let node = &self.wide_nodes[node_idx as usize];
+
+            let mut child_rays = [0u64; 4];
+            let mut t_nearest = [std::f32::INFINITY; 4];
+            for i in rays_in(active) {
+                nodes_visited += 1;
+                let (lane_hit, t_near) = node.intersect_p(&lanes[i], rays[i].t_max);
+                for lane in 0..4 {
+                    if lane_hit[lane] {
+                        child_rays[lane] |= 1 << i;
+                        t_nearest[lane] = t_nearest[lane].min(t_near[lane]);
+                    }
+                }
+            }
+            let order = nearest_first(&t_nearest);
+
+            for &lane in order.iter() {
+                if child_rays[lane] != 0 && node.num_prims[lane] > 0 {
+                    let first = node.children[lane] as usize;
+                    let primitives = &self.primitives[first..first + node.num_prims[lane] as usize];
+                    for i in rays_in(child_rays[lane]) {
+                        primitives_tested += primitives.len() as u64;
+                        for primitive in primitives {
+                            if primitive.intersect(&mut rays[i], &mut hits[i].isect) {
+                                hits[i].found = true;
                             }
                         }
                     }
+                }
+            }
+            // farthest first, so the nearest child is visited next
+            for &lane in order.iter().rev() {
+                if child_rays[lane] != 0 && node.num_prims[lane] == 0 {
+                    nodes_to_visit[to_visit_offset] = (node.children[lane], child_rays[lane]);
+                    to_visit_offset += 1;
+                }
+            }
+        }
+
+        count_traversal(nodes_visited, primitives_tested);
+    }
+
+    /// shadow rays of a packet go down the tree together, dropping out as they're blocked
+    fn intersect_p_packet(&self, rays: &[Ray], occluded: &mut [bool]) {
+        let mut blocked = 0u64;
+        let (mut nodes_visited, mut primitives_tested) = (0, 0);
+        if !self.wide_nodes.is_empty() {
+            let lanes = rays.iter().map(RayLanes::new).collect::<Vec<_>>();
+            let mut nodes_to_visit = [(0u32, 0u64); MAX_TO_VISIT];
+            nodes_to_visit[0] = (0, packet_mask(rays.len()));
+            let mut to_visit_offset = 1;
+            while to_visit_offset > 0 {
+                to_visit_offset -= 1;
+                let (node_idx, active) = nodes_to_visit[to_visit_offset];
+                let node = &self.wide_nodes[node_idx as usize];
+
+                let mut child_rays = [0u64; 4];
+                for i in rays_in(active & !blocked) {
+                    nodes_visited += 1;
+                    let (lane_hit, _) = node.intersect_p(&lanes[i], rays[i].t_max);
+                    for lane in 0..4 {
+                        if lane_hit[lane] {
+                            child_rays[lane] |= 1 << i;
+                        }
+                    }
+                }
 
-                    if to_visit_offset == 0 {
-                        break;
+                for lane in 0..4 {
+                    if child_rays[lane] == 0 {
+                        continue;
                     }
-                    to_visit_offset -= 1;
-                    curr_node_idx = nodes_to_visit[to_visit_offset];
-                } else {
-                    if dir_is_neg[node.axis as usize] {
-                        nodes_to_visit[to_visit_offset] = curr_node_idx + 1;
-                        unsafe {
-                            curr_node_idx = node.offset.second_child_offset as usize;
+
+                    if node.num_prims[lane] > 0 {
+                        let first = node.children[lane] as usize;
+                        let primitives =
+                            &self.primitives[first..first + node.num_prims[lane] as usize];
+                        for i in rays_in(child_rays[lane] & !blocked) {
+                            for primitive in primitives {
+                                primitives_tested += 1;
+                                if primitive.intersect_p(&rays[i]) {
+                                    blocked |= 1 << i;
+                                    break;
+                                }
+                            }
                         }
                     } else {
-                        unsafe {
-                            nodes_to_visit[to_visit_offset] =
-                                node.offset.second_child_offset as usize;
+                        nodes_to_visit[to_visit_offset] = (node.children[lane], child_rays[lane]);
+                        to_visit_offset += 1;
+                    }
+                }
+            }
+        }
+
+        count_traversal(nodes_visited, primitives_tested);
+
+        for (i, occluded) in occluded.iter_mut().enumerate() {
+            *occluded = blocked & (1 << i) != 0;
+        }
+    }
+}
+
+impl Primitive for BVH {
+    fn intersect<'a>(&'a self, r: &mut Ray, mut isect: &mut SurfaceMediumInteraction<'a>) -> bool {
+        if self.wide_nodes.is_empty() {
+            return false;
+        }
+
+        let mut hit = false;
+        let ray = RayLanes::new(r);
+        let (mut nodes_visited, mut primitives_tested) = (0, 0);
+
+        let mut nodes_to_visit = [(0u32, 0.0f32); MAX_TO_VISIT];
+        let mut to_visit_offset = 1;
+        while to_visit_offset > 0 {
+            to_visit_offset -= 1;
+            let (node_idx, t_enter) = nodes_to_visit[to_visit_offset];
+            // a closer hit was found since the node was pushed
+            if t_enter > r.t_max {
+                continue;
+            }
+
+            nodes_visited += 1;
+            let node = &self.wide_nodes[node_idx as usize];
+            let (lane_hit, t_near) = node.intersect_p(&ray, r.t_max);
+            let order = nearest_first(&t_near);
+
+            for &lane in order.iter() {
+                if lane_hit[lane] && node.num_prims[lane] > 0 {
+                    let first = node.children[lane] as usize;
+                    primitives_tested += node.num_prims[lane] as u64;
+                    for primitive in &self.primitives[first..first + node.num_prims[lane] as usize]
+                    {
+                        if primitive.intersect(r, &mut isect) {
+                            hit = true;
                         }
-                        curr_node_idx += 1;
                     }
-                    to_visit_offset += 1;
                 }
-            } else {
-                if to_visit_offset == 0 {
-                    break;
+            }
+            // farthest first, so the nearest child is visited next
+            for &lane in order.iter().rev() {
+                if lane_hit[lane] && node.num_prims[lane] == 0 && t_near[lane] <= r.t_max {
+                    nodes_to_visit[to_visit_offset] = (node.children[lane], t_near[lane]);
+                    to_visit_offset += 1;
                 }
-                to_visit_offset -= 1;
-                curr_node_idx = nodes_to_visit[to_visit_offset];
             }
         }
 
+        count_traversal(nodes_visited, primitives_tested);
         hit
     }
 
     fn intersect_p(&self, r: &Ray) -> bool {
-        if self.nodes.is_empty() {
+        if self.wide_nodes.is_empty() {
             return false;
         }
 
-        let inv_dir = na::Vector3::new(1.0f32 / r.d.x, 1.0f32 / r.d.y, 1.0f32 / r.d.z);
-        let dir_is_neg = [inv_dir.x < 0.0, inv_dir.y < 0.0, inv_dir.z < 0.0];
+        let ray = RayLanes::new(r);
+        let (mut nodes_visited, mut primitives_tested) = (0, 0);
 
-        let mut to_visit_offset = 0;
-        let mut curr_node_idx = 0;
-        let mut nodes_to_visit = [0; 64];
-        loop {
-            let node = &self.nodes[curr_node_idx];
-
-            if node.bounds.intersect_p_precomp(r, &inv_dir, &dir_is_neg) {
-                if node.num_prims > 0 {
-                    for i in 0..node.num_prims {
-                        unsafe {
-                            if self.primitives[node.offset.primitives_offset as usize + i as usize]
-                                .intersect_p(r)
-                            {
-                                return true;
-                            }
-                        }
-                    }
+        let mut nodes_to_visit = [0u32; MAX_TO_VISIT];
+        let mut to_visit_offset = 1;
+        let occluded = 'traversal: loop {
+            if to_visit_offset == 0 {
+                break false;
+            }
+            to_visit_offset -= 1;
+            nodes_visited += 1;
+            let node = &self.wide_nodes[nodes_to_visit[to_visit_offset] as usize];
+            let (lane_hit, _) = node.intersect_p(&ray, r.t_max);
 
-                    if to_visit_offset == 0 {
-                        break;
-                    }
-                    to_visit_offset -= 1;
-                    curr_node_idx = nodes_to_visit[to_visit_offset];
-                } else {
-                    if dir_is_neg[node.axis as usize] {
-                        nodes_to_visit[to_visit_offset] = curr_node_idx + 1;
-                        unsafe {
-                            curr_node_idx = node.offset.second_child_offset as usize;
-                        }
-                    } else {
-                        unsafe {
-                            nodes_to_visit[to_visit_offset] =
-                                node.offset.second_child_offset as usize;
+            for lane in 0..4 {
+                if !lane_hit[lane] {
+                    continue;
+                }
+
+                if node.num_prims[lane] > 0 {
+                    let first = node.children[lane] as usize;
+                    for primitive in &self.primitives[first..first + node.num_prims[lane] as usize]
+                    {
+                        primitives_tested += 1;
+                        if primitive.intersect_p(r) {
+                            break 'traversal true;
                         }
-                        curr_node_idx += 1;
                     }
+                } else {
+                    nodes_to_visit[to_visit_offset] = node.children[lane];
                     to_visit_offset += 1;
                 }
-            } else {
-                if to_visit_offset == 0 {
-                    break;
-                }
-                to_visit_offset -= 1;
-                curr_node_idx = nodes_to_visit[to_visit_offset];
             }
-        }
+        };
 
-        false
+        count_traversal(nodes_visited, primitives_tested);
+        occluded
     }
 
     fn world_bound(&self) -> Bounds3 {
@@ -486,10 +1496,11 @@ impl Primitive for BVH {
         unimplemented!()
     }
 
-    fn compute_scattering_functions(
+    fn compute_scattering_functions<'a>(
         &self,
-        _si: &mut SurfaceMediumInteraction,
+        _si: &mut SurfaceMediumInteraction<'a>,
         _mode: super::TransportMode,
+        _arena: &'a super::arena::MemoryArena,
     ) {
         unimplemented!()
     }
@@ -497,4 +1508,291 @@ impl Primitive for BVH {
     fn get_area_light(&self) -> Option<&super::light::DiffuseAreaLight> {
         unimplemented!()
     }
+
+    fn for_each_geometric<'a>(
+        &'a self,
+        f: &mut dyn FnMut(&'a super::primitive::GeometricPrimitive),
+    ) {
+        for primitive in &self.primitives {
+            primitive.for_each_geometric(f);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pathtracer::{
+        arena::MemoryArena, light::DiffuseAreaLight, material::Material, TransportMode,
+    };
+    use rand::{Rng, SeedableRng};
+
+    /// stands in for a shape, its hits only record which ball was hit
+    struct Ball {
+        center: na::Point3<f32>,
+        radius: f32,
+    }
+
+    impl Ball {
+        fn hit(&self, r: &Ray) -> Option<f32> {
+            let oc = r.o - self.center;
+            let a = r.d.norm_squared();
+            let b = 2.0 * oc.dot(&r.d);
+            let c = oc.norm_squared() - self.radius * self.radius;
+            let discriminant = b * b - 4.0 * a * c;
+            if discriminant < 0.0 {
+                return None;
+            }
+            let root = discriminant.sqrt();
+            [(-b - root) / (2.0 * a), (-b + root) / (2.0 * a)]
+                .iter()
+                .copied()
+                .find(|&t| t > 0.0 && t < r.t_max)
+        }
+    }
+
+    impl Primitive for Ball {
+        fn intersect<'a>(&'a self, r: &mut Ray, isect: &mut SurfaceMediumInteraction<'a>) -> bool {
+            match self.hit(r) {
+                Some(t) => {
+                    r.t_max = t;
+                    isect.primitive = Some(self);
+                    true
+                }
+                None => false,
+            }
+        }
+
+        fn intersect_p(&self, r: &Ray) -> bool {
+            self.hit(r).is_some()
+        }
+
+        fn world_bound(&self) -> Bounds3 {
+            let extent = na::Vector3::repeat(self.radius);
+            Bounds3::new(self.center - extent, self.center + extent)
+        }
+
+        fn get_material(&self) -> &Material {
+            unreachable!("balls are never shaded")
+        }
+
+        fn compute_scattering_functions<'a>(
+            &self,
+            _si: &mut SurfaceMediumInteraction<'a>,
+            _mode: TransportMode,
+            _arena: &'a MemoryArena,
+        ) {
+            unreachable!("balls are never shaded")
+        }
+
+        fn get_area_light(&self) -> Option<&DiffuseAreaLight> {
+            None
+        }
+    }
+
+    fn random_point(rng: &mut impl Rng, extent: f32) -> na::Point3<f32> {
+        na::Point3::new(
+            rng.gen_range(-extent, extent),
+            rng.gen_range(-extent, extent),
+            rng.gen_range(-extent, extent),
+        )
+    }
+
+    fn random_bvh(rng: &mut impl Rng, num_balls: usize, max_prims_in_node: usize) -> BVH {
+        let primitives = (0..num_balls)
+            .map(|_| {
+                Arc::new(Ball {
+                    center: random_point(rng, 10.0),
+                    radius: rng.gen_range(0.1, 1.0),
+                }) as Arc<dyn SyncPrimitive>
+            })
+            .collect();
+        let log = slog::Logger::root(slog::Discard, o!());
+        BVH::new(&log, primitives, &max_prims_in_node)
+    }
+
+    /// rays from around the balls towards them, some of them cut short
+    fn random_ray(rng: &mut impl Rng) -> Ray {
+        let o = random_point(rng, 15.0);
+        Ray {
+            o,
+            d: random_point(rng, 10.0) - o,
+            t_max: if rng.gen_bool(0.5) {
+                std::f32::INFINITY
+            } else {
+                rng.gen_range(0.0, 1.5)
+            },
+            time: 0.0,
+        }
+    }
+
+    fn hit_primitive(isect: &SurfaceMediumInteraction) -> Option<*const u8> {
+        isect
+            .primitive
+            .map(|primitive| primitive as *const dyn Primitive as *const u8)
+    }
+
+    /// closest hit found by walking the binary nodes the wide ones were collapsed from
+    fn intersect_binary<'a>(
+        bvh: &'a BVH,
+        r: &mut Ray,
+        isect: &mut SurfaceMediumInteraction<'a>,
+    ) -> bool {
+        if bvh.nodes.is_empty() {
+            return false;
+        }
+
+        let inv_dir = na::Vector3::new(1.0 / r.d.x, 1.0 / r.d.y, 1.0 / r.d.z);
+        let dir_is_neg = [inv_dir.x < 0.0, inv_dir.y < 0.0, inv_dir.z < 0.0];
+        let mut hit = false;
+        let mut nodes_to_visit = vec![0usize];
+        while let Some(current) = nodes_to_visit.pop() {
+            let node = &bvh.nodes[current];
+            if !node.bounds.intersect_p_precomp(r, &inv_dir, &dir_is_neg) {
+                continue;
+            }
+
+            if node.num_prims > 0 {
+                let first = unsafe { node.offset.primitives_offset } as usize;
+                for primitive in &bvh.primitives[first..first + node.num_prims as usize] {
+                    if primitive.intersect(r, isect) {
+                        hit = true;
+                    }
+                }
+            } else {
+                let second = unsafe { node.offset.second_child_offset } as usize;
+                if dir_is_neg[node.axis as usize] {
+                    nodes_to_visit.push(current + 1);
+                    nodes_to_visit.push(second);
+                } else {
+                    nodes_to_visit.push(second);
+                    nodes_to_visit.push(current + 1);
+                }
+            }
+        }
+
+        hit
+    }
+
+    /// lanes of wide nodes left over when a binary node has fewer than four grandchildren
+    fn padded_lanes(bvh: &BVH) -> Vec<(usize, usize)> {
+        let mut padded = Vec::new();
+        for (idx, node) in bvh.wide_nodes.iter().enumerate() {
+            for lane in 0..4 {
+                // the root is never a child, so interior children never point at node 0
+                if node.num_prims[lane] == 0 && node.children[lane] == 0 {
+                    padded.push((idx, lane));
+                }
+            }
+        }
+
+        padded
+    }
+
+    #[test]
+    fn test_wide_bvh_matches_binary() {
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(0);
+        let mut num_padded = 0;
+        // the small trees leave most lanes of their wide nodes empty
+        for &(num_balls, max_prims_in_node) in
+            [(1, 1), (2, 1), (3, 1), (5, 1), (7, 2), (200, 4), (1000, 1)].iter()
+        {
+            let bvh = random_bvh(&mut rng, num_balls, max_prims_in_node);
+
+            // every primitive is in exactly one leaf lane
+            let mut covered = vec![0; num_balls];
+            for node in &bvh.wide_nodes {
+                for lane in 0..4 {
+                    let first = node.children[lane] as usize;
+                    for idx in first..first + node.num_prims[lane] as usize {
+                        covered[idx] += 1;
+                    }
+                }
+            }
+            assert!(covered.iter().all(|&count| count == 1));
+
+            let padded = padded_lanes(&bvh);
+            num_padded += padded.len();
+            for &(idx, lane) in &padded {
+                let bounds = &bvh.wide_nodes[idx].bounds;
+                assert!((0..3).all(|axis| bounds[0][axis][lane] > bounds[1][axis][lane]));
+            }
+
+            for _ in 0..2000 {
+                let ray = random_ray(&mut rng);
+                for &(idx, lane) in &padded {
+                    let (lane_hit, _) =
+                        bvh.wide_nodes[idx].intersect_p(&RayLanes::new(&ray), ray.t_max);
+                    assert!(!lane_hit[lane]);
+                }
+
+                let mut wide_ray = ray.clone();
+                let mut wide_isect = SurfaceMediumInteraction::default();
+                let wide_hit = Primitive::intersect(&bvh, &mut wide_ray, &mut wide_isect);
+                let mut binary_ray = ray.clone();
+                let mut binary_isect = SurfaceMediumInteraction::default();
+                let binary_hit = intersect_binary(&bvh, &mut binary_ray, &mut binary_isect);
+
+                assert_eq!(wide_hit, binary_hit);
+                assert_eq!(wide_ray.t_max, binary_ray.t_max);
+                assert_eq!(hit_primitive(&wide_isect), hit_primitive(&binary_isect));
+                assert_eq!(Primitive::intersect_p(&bvh, &ray), binary_hit);
+            }
+        }
+        assert!(num_padded > 0);
+    }
+
+    /// camera rays of a pixel, spreading a little from one origin, and incoherent ones
+    fn packet_rays(rng: &mut impl Rng, n: usize, coherent: bool) -> Vec<Ray> {
+        let o = random_point(rng, 15.0);
+        let target = random_point(rng, 5.0);
+        (0..n)
+            .map(|_| {
+                if coherent {
+                    let mut ray = random_ray(rng);
+                    ray.o = o;
+                    ray.d = target + random_point(rng, 2.0).coords - o;
+                    ray
+                } else {
+                    random_ray(rng)
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_packets_match_single_rays() {
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(1);
+        let bvh = random_bvh(&mut rng, 500, 4);
+        // partial packets leave the high bits of the masks unset, and batches past the packet
+        // size are split up
+        for &n in [1, 3, 37, PACKET_SIZE, 100, 3 * PACKET_SIZE].iter() {
+            for &coherent in [true, false].iter() {
+                let rays = packet_rays(&mut rng, n, coherent);
+
+                let mut traced = rays.clone();
+                let mut hits = Vec::new();
+                hits.resize_with(n, Hit::default);
+                let mut occluded = vec![false; n];
+                if n <= PACKET_SIZE {
+                    bvh.intersect_packet(&mut traced, &mut hits);
+                    bvh.intersect_p_packet(&rays, &mut occluded);
+                } else {
+                    Accelerator::intersect_n(&bvh, &mut traced, &mut hits);
+                    Accelerator::intersect_p_n(&bvh, &rays, &mut occluded);
+                }
+
+                for (i, ray) in rays.iter().enumerate() {
+                    let mut single_ray = ray.clone();
+                    let mut isect = SurfaceMediumInteraction::default();
+                    let found = Primitive::intersect(&bvh, &mut single_ray, &mut isect);
+
+                    assert_eq!(hits[i].found, found);
+                    assert_eq!(traced[i].t_max, single_ray.t_max);
+                    assert_eq!(hit_primitive(&hits[i].isect), hit_primitive(&isect));
+                    assert_eq!(occluded[i], Primitive::intersect_p(&bvh, ray));
+                }
+            }
+        }
+    }
 }
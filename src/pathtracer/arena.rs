@@ -0,0 +1,38 @@
+/// bump allocator for the scattering functions built at every intersection. each render thread
+/// owns one and resets it once a camera sample is done with its allocations, so building a bsdf
+/// never goes through the global allocator. destructors of values allocated here never run, so
+/// only types without owned heap data belong in it
+#[derive(Default)]
+pub struct MemoryArena {
+    bump: bumpalo::Bump,
+}
+
+impl MemoryArena {
+    pub fn new() -> Self {
+        Self {
+            bump: bumpalo::Bump::new(),
+        }
+    }
+
+    pub fn alloc<T>(&self, value: T) -> &mut T {
+        self.bump.alloc(value)
+    }
+
+    /// moves every item of `iter` into a contiguous slice
+    pub fn alloc_iter<T, I>(&self, iter: I) -> &mut [T]
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        self.bump.alloc_slice_fill_iter(iter)
+    }
+
+    /// frees everything allocated so far while keeping the largest chunk around for reuse
+    pub fn reset(&mut self) {
+        self.bump.reset();
+    }
+
+    pub fn allocated_bytes(&self) -> usize {
+        self.bump.allocated_bytes()
+    }
+}
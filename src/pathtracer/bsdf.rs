@@ -1,28 +1,36 @@
 use super::{
+    arena::MemoryArena,
     bxdf::{BxDF, BxDFInterface, BxDFType},
     interaction::SurfaceMediumInteraction,
 };
 use crate::common::spectrum::Spectrum;
 
 const MAX_BXDFS: usize = 8;
-pub struct BSDF {
+/// scattering at a single intersection, lobes reference data allocated from the render thread's
+/// arena and live as long as it does
+pub struct BSDF<'a> {
     pub eta: f32,
+    /// absorption coefficient per world unit of the volume behind the surface, for transmissive
+    /// materials enclosing an absorbing medium
+    pub absorption: Option<Spectrum>,
     ns: na::Vector3<f32>,
     ng: na::Vector3<f32>,
     ss: na::Vector3<f32>,
     ts: na::Vector3<f32>,
     n_bxdfs: usize,
-    bxdfs: [Option<BxDF>; MAX_BXDFS],
+    bxdfs: [Option<BxDF<'a>>; MAX_BXDFS],
     log: slog::Logger,
 }
 
-impl BSDF {
+impl<'a> BSDF<'a> {
     pub fn new(log: &slog::Logger, si: &SurfaceMediumInteraction, eta: f32) -> Self {
-        let log = log.new(o!());
+        // cloning only bumps a reference count, a child logger would allocate for every hit
+        let log = log.clone();
         let ns = si.shading.n;
         let ss = si.shading.dpdu.normalize();
         Self {
             eta,
+            absorption: None,
             ns,
             ng: si.general.n,
             ss,
@@ -45,12 +53,24 @@ impl BSDF {
         num
     }
 
-    pub fn add(&mut self, b: BxDF) {
+    pub fn add(&mut self, b: BxDF<'a>) {
         debug_assert!(self.n_bxdfs < MAX_BXDFS);
         self.bxdfs[self.n_bxdfs] = Some(b);
         self.n_bxdfs += 1;
     }
 
+    /// moves the lobes into the arena, for materials wrapping the lobes of another material
+    pub fn into_bxdfs(self, arena: &'a MemoryArena) -> &'a [BxDF<'a>] {
+        let n_bxdfs = self.n_bxdfs;
+        let mut bxdfs = self.bxdfs;
+        arena.alloc_iter(
+            bxdfs
+                .iter_mut()
+                .take(n_bxdfs)
+                .map(|bxdf| bxdf.take().unwrap()),
+        )
+    }
+
     pub fn world_to_local(&self, v: &na::Vector3<f32>) -> na::Vector3<f32> {
         na::Vector3::new(v.dot(&self.ss), v.dot(&self.ts), v.dot(&self.ns))
     }
@@ -220,4 +240,25 @@ impl BSDF {
             0.0
         }
     }
+
+    /// directional-hemispherical reflectance seen from `wo_w`, summed over the matching lobes
+    pub fn rho(
+        &self,
+        wo_w: &na::Vector3<f32>,
+        n_samples: usize,
+        samples: &[na::Point2<f32>],
+        flags: BxDFType,
+    ) -> Spectrum {
+        let wo = self.world_to_local(&wo_w);
+        let mut r = Spectrum::new(0.0);
+
+        for i in 0..self.n_bxdfs {
+            let bxdf = self.bxdfs[i].as_ref().unwrap();
+            if bxdf.matches_flags(flags) {
+                r += bxdf.rho(&wo, n_samples, samples);
+            }
+        }
+
+        r
+    }
 }
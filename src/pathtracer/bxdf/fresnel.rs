@@ -15,6 +15,7 @@ pub enum Fresnel {
     Dielectric(FresnelDielectric),
     Conductor(FresnelConductor),
     Disney(super::super::material::disney::DisneyFresnel),
+    ThinFilm(FresnelThinFilm),
     NoOp(FresnelNoOp),
 }
 
@@ -63,6 +64,132 @@ fn fr_conductor(cos_theta_i: f32, eta_i: Spectrum, eta_t: Spectrum, k: Spectrum)
     0.5 * (rp + rs)
 }
 
+/// representative wavelengths in nm used to evaluate the interference for each rgb channel
+const THIN_FILM_WAVELENGTHS: [f32; 3] = [650.0, 510.0, 475.0];
+
+/// cosine of the refracted angle going from medium a to b, complex to cover conductors and tir
+fn complex_cos_t(
+    eta_a: num::Complex<f32>,
+    cos_a: num::Complex<f32>,
+    eta_b: num::Complex<f32>,
+) -> num::Complex<f32> {
+    let one = num::Complex::new(1.0, 0.0);
+    let ratio = eta_a / eta_b;
+    (one - ratio * ratio * (one - cos_a * cos_a)).sqrt()
+}
+
+/// s and p polarized amplitude reflection coefficients at the boundary between a and b
+fn complex_r(
+    eta_a: num::Complex<f32>,
+    cos_a: num::Complex<f32>,
+    eta_b: num::Complex<f32>,
+    cos_b: num::Complex<f32>,
+) -> (num::Complex<f32>, num::Complex<f32>) {
+    let r_s = (eta_a * cos_a - eta_b * cos_b) / (eta_a * cos_a + eta_b * cos_b);
+    let r_p = (eta_b * cos_a - eta_a * cos_b) / (eta_b * cos_a + eta_a * cos_b);
+    (r_s, r_p)
+}
+
+/// reflectance of a film of `film_eta` and `thickness` (nm) sitting on a substrate with complex
+/// ior `eta_t + i k`, summing the airy series of internal reflections for each polarization
+pub fn fr_thin_film(
+    cos_theta_i: f32,
+    eta_i: f32,
+    film_eta: f32,
+    thickness: f32,
+    eta_t: f32,
+    k: f32,
+    wavelength: f32,
+) -> f32 {
+    let n_1 = num::Complex::new(eta_i, 0.0);
+    let n_2 = num::Complex::new(film_eta, 0.0);
+    let n_3 = num::Complex::new(eta_t, k);
+
+    let cos_1 = num::Complex::new(cos_theta_i.clamp(0.0, 1.0), 0.0);
+    let cos_2 = complex_cos_t(n_1, cos_1, n_2);
+    let cos_3 = complex_cos_t(n_1, cos_1, n_3);
+
+    let (r12_s, r12_p) = complex_r(n_1, cos_1, n_2, cos_2);
+    let (r23_s, r23_p) = complex_r(n_2, cos_2, n_3, cos_3);
+
+    // phase difference accumulated by one round trip through the film
+    let delta =
+        num::Complex::new(0.0, 4.0 * std::f32::consts::PI * thickness / wavelength) * n_2 * cos_2;
+    let phase = delta.exp();
+
+    let r_s = (r12_s + r23_s * phase) / (1.0 + r12_s * r23_s * phase);
+    let r_p = (r12_p + r23_p * phase) / (1.0 + r12_p * r23_p * phase);
+
+    ((r_s.norm_sqr() + r_p.norm_sqr()) / 2.0).clamp(0.0, 1.0)
+}
+
+pub struct FresnelThinFilm {
+    eta_i: f32,
+    film_eta: f32,
+    thickness: f32,
+    eta_t: Spectrum,
+    k: Spectrum,
+}
+
+impl FresnelThinFilm {
+    /// film over a dielectric base
+    pub fn dielectric(eta_i: f32, film_eta: f32, thickness: f32, eta_t: f32) -> Self {
+        Self {
+            eta_i,
+            film_eta,
+            thickness,
+            eta_t: Spectrum::new(eta_t),
+            k: Spectrum::new(0.0),
+        }
+    }
+
+    /// film over a conductor base
+    pub fn conductor(
+        eta_i: f32,
+        film_eta: f32,
+        thickness: f32,
+        eta_t: Spectrum,
+        k: Spectrum,
+    ) -> Self {
+        Self {
+            eta_i,
+            film_eta,
+            thickness,
+            eta_t,
+            k,
+        }
+    }
+}
+
+impl FresnelInterface for FresnelThinFilm {
+    fn evaluate(&self, cos_i: f32) -> Spectrum {
+        let is_conductor = !self.k.is_black();
+        let eta_t = [self.eta_t.r(), self.eta_t.g(), self.eta_t.b()];
+        let k = [self.k.r(), self.k.g(), self.k.b()];
+
+        let mut reflectance = [0.0; 3];
+        for i in 0..3 {
+            // the film is between the two media, so leaving a dielectric sees it in reverse order
+            let (eta_i, eta_t) = if cos_i < 0.0 && !is_conductor {
+                (eta_t[i], self.eta_i)
+            } else {
+                (self.eta_i, eta_t[i])
+            };
+            reflectance[i] = fr_thin_film(
+                cos_i.abs(),
+                eta_i,
+                self.film_eta,
+                self.thickness,
+                eta_t,
+                k[i],
+                THIN_FILM_WAVELENGTHS[i],
+            );
+        }
+
+        Spectrum::from_floats(reflectance[0], reflectance[1], reflectance[2])
+    }
+}
+
 pub struct FresnelDielectric {
     eta_i: f32,
     eta_t: f32,
@@ -0,0 +1,407 @@
+use super::{abs_cos_theta, fresnel::fr_dielectric, BxDFInterface, BxDFType};
+use crate::common::spectrum::Spectrum;
+use std::f32::consts::PI;
+
+const P_MAX: usize = 3;
+const SQRT_PI_OVER_8: f32 = 0.626657069;
+
+fn sqr(x: f32) -> f32 {
+    x * x
+}
+
+fn safe_sqrt(x: f32) -> f32 {
+    x.max(0.0).sqrt()
+}
+
+fn safe_asin(x: f32) -> f32 {
+    x.clamp(-1.0, 1.0).asin()
+}
+
+/// modified bessel function of the first kind
+fn i0(x: f32) -> f32 {
+    let mut val = 0.0;
+    let mut x2i = 1.0;
+    let mut ifact = 1.0f32;
+    let mut i4 = 1.0;
+    for i in 0..10 {
+        if i > 1 {
+            ifact *= i as f32;
+        }
+        val += x2i / (i4 * sqr(ifact));
+        x2i *= x * x;
+        i4 *= 4.0;
+    }
+    val
+}
+
+fn log_i0(x: f32) -> f32 {
+    if x > 12.0 {
+        x + 0.5 * (-(2.0 * PI).ln() + (1.0 / x).ln() + 1.0 / (8.0 * x))
+    } else {
+        i0(x).ln()
+    }
+}
+
+/// longitudinal scattering function
+fn mp(cos_theta_i: f32, cos_theta_o: f32, sin_theta_i: f32, sin_theta_o: f32, v: f32) -> f32 {
+    let a = cos_theta_i * cos_theta_o / v;
+    let b = sin_theta_i * sin_theta_o / v;
+    if v <= 0.1 {
+        (log_i0(a) - b - 1.0 / v + 0.6931 + (1.0 / (2.0 * v)).ln()).exp()
+    } else {
+        ((-b).exp() * i0(a)) / ((1.0 / v).sinh() * 2.0 * v)
+    }
+}
+
+/// attenuation for each of the scattering paths, the last entry sums up all higher order paths
+fn ap(cos_theta_o: f32, eta: f32, h: f32, t: Spectrum) -> [Spectrum; P_MAX + 1] {
+    let cos_gamma_o = safe_sqrt(1.0 - h * h);
+    let cos_theta = cos_theta_o * cos_gamma_o;
+    let f = fr_dielectric(cos_theta, 1.0, eta);
+
+    let mut ap = [Spectrum::new(0.0); P_MAX + 1];
+    ap[0] = Spectrum::new(f);
+    ap[1] = sqr(1.0 - f) * t;
+    for p in 2..P_MAX {
+        ap[p] = ap[p - 1] * t * f;
+    }
+    ap[P_MAX] = ap[P_MAX - 1] * t * f / (Spectrum::new(1.0) - t * f);
+    ap
+}
+
+fn phi(p: usize, gamma_o: f32, gamma_t: f32) -> f32 {
+    2.0 * p as f32 * gamma_t - 2.0 * gamma_o + p as f32 * PI
+}
+
+fn logistic(x: f32, s: f32) -> f32 {
+    let x = x.abs();
+    (-x / s).exp() / (s * sqr(1.0 + (-x / s).exp()))
+}
+
+fn logistic_cdf(x: f32, s: f32) -> f32 {
+    1.0 / (1.0 + (-x / s).exp())
+}
+
+fn trimmed_logistic(x: f32, s: f32, a: f32, b: f32) -> f32 {
+    logistic(x, s) / (logistic_cdf(b, s) - logistic_cdf(a, s))
+}
+
+/// azimuthal scattering function
+fn np(phi_: f32, p: usize, s: f32, gamma_o: f32, gamma_t: f32) -> f32 {
+    let mut dphi = phi_ - phi(p, gamma_o, gamma_t);
+    while dphi > PI {
+        dphi -= 2.0 * PI;
+    }
+    while dphi < -PI {
+        dphi += 2.0 * PI;
+    }
+    trimmed_logistic(dphi, s, -PI, PI)
+}
+
+fn sample_trimmed_logistic(u: f32, s: f32, a: f32, b: f32) -> f32 {
+    let k = logistic_cdf(b, s) - logistic_cdf(a, s);
+    let x = -s * (1.0 / (u * k + logistic_cdf(a, s)) - 1.0).ln();
+    x.clamp(a, b)
+}
+
+fn compact_1_by_1(mut x: u32) -> u32 {
+    x &= 0x55555555;
+    x = (x ^ (x >> 1)) & 0x33333333;
+    x = (x ^ (x >> 2)) & 0x0f0f0f0f;
+    x = (x ^ (x >> 4)) & 0x00ff00ff;
+    x = (x ^ (x >> 8)) & 0x0000ffff;
+    x
+}
+
+/// splits one uniform sample into two by deinterleaving its bits
+fn demux_float(f: f32) -> [f32; 2] {
+    let v = (f as f64 * (1u64 << 32) as f64) as u64;
+    let bits = [compact_1_by_1(v as u32), compact_1_by_1((v >> 1) as u32)];
+    [
+        bits[0] as f32 / (1u32 << 16) as f32,
+        bits[1] as f32 / (1u32 << 16) as f32,
+    ]
+}
+
+/// absorption coefficient from melanin concentrations
+pub fn sigma_a_from_concentration(ce: f32, cp: f32) -> Spectrum {
+    const EUMELANIN_SIGMA_A: [f32; 3] = [0.419, 0.697, 1.37];
+    const PHEOMELANIN_SIGMA_A: [f32; 3] = [0.187, 0.4, 1.05];
+    Spectrum::from_floats(
+        ce * EUMELANIN_SIGMA_A[0] + cp * PHEOMELANIN_SIGMA_A[0],
+        ce * EUMELANIN_SIGMA_A[1] + cp * PHEOMELANIN_SIGMA_A[1],
+        ce * EUMELANIN_SIGMA_A[2] + cp * PHEOMELANIN_SIGMA_A[2],
+    )
+}
+
+/// absorption coefficient that roughly produces the desired multiple scattered color
+pub fn sigma_a_from_reflectance(c: &Spectrum, beta_n: f32) -> Spectrum {
+    let denom = 5.969 - 0.215 * beta_n + 2.532 * sqr(beta_n) - 10.73 * beta_n.powi(3)
+        + 5.574 * beta_n.powi(4)
+        + 0.245 * beta_n.powi(5);
+    let sigma_a = |c: f32| sqr(c.ln() / denom);
+    Spectrum::from_floats(sigma_a(c.r()), sigma_a(c.g()), sigma_a(c.b()))
+}
+
+/// Chiang et al. 2016 hair scattering model with R, TT, TRT and a residual lobe,
+/// expects the local x axis to run along the curve
+pub struct HairBSDF {
+    h: f32,
+    gamma_o: f32,
+    eta: f32,
+    sigma_a: Spectrum,
+    v: [f32; P_MAX + 1],
+    s: f32,
+    sin_2k_alpha: [f32; 3],
+    cos_2k_alpha: [f32; 3],
+}
+
+impl HairBSDF {
+    /// `h` is the offset across the curve width in [-1, 1], `alpha` is the scale tilt in degrees
+    pub fn new(h: f32, eta: f32, sigma_a: Spectrum, beta_m: f32, beta_n: f32, alpha: f32) -> Self {
+        let mut v = [0.0; P_MAX + 1];
+        v[0] = sqr(0.726 * beta_m + 0.812 * sqr(beta_m) + 3.7 * beta_m.powi(20));
+        v[1] = 0.25 * v[0];
+        v[2] = 4.0 * v[0];
+        for p in 3..=P_MAX {
+            v[p] = v[2];
+        }
+
+        let s = SQRT_PI_OVER_8 * (0.265 * beta_n + 1.194 * sqr(beta_n) + 5.372 * beta_n.powi(22));
+
+        let mut sin_2k_alpha = [0.0; 3];
+        let mut cos_2k_alpha = [0.0; 3];
+        sin_2k_alpha[0] = alpha.to_radians().sin();
+        cos_2k_alpha[0] = safe_sqrt(1.0 - sqr(sin_2k_alpha[0]));
+        for i in 1..3 {
+            sin_2k_alpha[i] = 2.0 * cos_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+            cos_2k_alpha[i] = sqr(cos_2k_alpha[i - 1]) - sqr(sin_2k_alpha[i - 1]);
+        }
+
+        Self {
+            h,
+            gamma_o: safe_asin(h),
+            eta,
+            sigma_a,
+            v,
+            s,
+            sin_2k_alpha,
+            cos_2k_alpha,
+        }
+    }
+
+    /// accounts for the scales on the hair surface tilting each lobe
+    fn tilt(&self, p: usize, sin_theta_o: f32, cos_theta_o: f32) -> (f32, f32) {
+        let (sin_theta_op, cos_theta_op) = match p {
+            0 => (
+                sin_theta_o * self.cos_2k_alpha[1] - cos_theta_o * self.sin_2k_alpha[1],
+                cos_theta_o * self.cos_2k_alpha[1] + sin_theta_o * self.sin_2k_alpha[1],
+            ),
+            1 => (
+                sin_theta_o * self.cos_2k_alpha[0] + cos_theta_o * self.sin_2k_alpha[0],
+                cos_theta_o * self.cos_2k_alpha[0] - sin_theta_o * self.sin_2k_alpha[0],
+            ),
+            2 => (
+                sin_theta_o * self.cos_2k_alpha[2] + cos_theta_o * self.sin_2k_alpha[2],
+                cos_theta_o * self.cos_2k_alpha[2] - sin_theta_o * self.sin_2k_alpha[2],
+            ),
+            _ => (sin_theta_o, cos_theta_o),
+        };
+        (sin_theta_op, cos_theta_op.abs())
+    }
+
+    /// transmittance through the fiber and the refracted azimuthal angle
+    fn transmittance(&self, sin_theta_o: f32, cos_theta_o: f32) -> (Spectrum, f32) {
+        let sin_theta_t = sin_theta_o / self.eta;
+        let cos_theta_t = safe_sqrt(1.0 - sqr(sin_theta_t));
+
+        let etap = (self.eta * self.eta - sqr(sin_theta_o)).sqrt() / cos_theta_o;
+        let sin_gamma_t = self.h / etap;
+        let cos_gamma_t = safe_sqrt(1.0 - sqr(sin_gamma_t));
+        let gamma_t = safe_asin(sin_gamma_t);
+
+        let t = (self.sigma_a * (-2.0 * cos_gamma_t / cos_theta_t)).exp();
+        (t, gamma_t)
+    }
+
+    fn compute_ap_pdf(&self, cos_theta_o: f32) -> [f32; P_MAX + 1] {
+        let sin_theta_o = safe_sqrt(1.0 - cos_theta_o * cos_theta_o);
+        let (t, _) = self.transmittance(sin_theta_o, cos_theta_o);
+        let ap = ap(cos_theta_o, self.eta, self.h, t);
+
+        let sum_y: f32 = ap.iter().map(|ap| ap.y()).sum();
+        let mut ap_pdf = [0.0; P_MAX + 1];
+        for i in 0..=P_MAX {
+            ap_pdf[i] = ap[i].y() / sum_y;
+        }
+        ap_pdf
+    }
+
+    fn pdf_from_angles(
+        &self,
+        sin_theta_o: f32,
+        cos_theta_o: f32,
+        sin_theta_i: f32,
+        cos_theta_i: f32,
+        dphi: f32,
+        gamma_t: f32,
+    ) -> f32 {
+        let ap_pdf = self.compute_ap_pdf(cos_theta_o);
+        let mut pdf = 0.0;
+        for p in 0..P_MAX {
+            let (sin_theta_op, cos_theta_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+            pdf += mp(
+                cos_theta_i,
+                cos_theta_op,
+                sin_theta_i,
+                sin_theta_op,
+                self.v[p],
+            ) * ap_pdf[p]
+                * np(dphi, p, self.s, self.gamma_o, gamma_t);
+        }
+        pdf += mp(
+            cos_theta_i,
+            cos_theta_o,
+            sin_theta_i,
+            sin_theta_o,
+            self.v[P_MAX],
+        ) * ap_pdf[P_MAX]
+            * (1.0 / (2.0 * PI));
+        pdf
+    }
+}
+
+impl BxDFInterface for HairBSDF {
+    fn f(&self, wo: &na::Vector3<f32>, wi: &na::Vector3<f32>) -> Spectrum {
+        let sin_theta_o = wo.x;
+        let cos_theta_o = safe_sqrt(1.0 - sqr(sin_theta_o));
+        let phi_o = wo.z.atan2(wo.y);
+
+        let sin_theta_i = wi.x;
+        let cos_theta_i = safe_sqrt(1.0 - sqr(sin_theta_i));
+        let phi_i = wi.z.atan2(wi.y);
+
+        let (t, gamma_t) = self.transmittance(sin_theta_o, cos_theta_o);
+        let phi_ = phi_i - phi_o;
+        let ap = ap(cos_theta_o, self.eta, self.h, t);
+
+        let mut fsum = Spectrum::new(0.0);
+        for p in 0..P_MAX {
+            let (sin_theta_op, cos_theta_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+            fsum += ap[p]
+                * mp(
+                    cos_theta_i,
+                    cos_theta_op,
+                    sin_theta_i,
+                    sin_theta_op,
+                    self.v[p],
+                )
+                * np(phi_, p, self.s, self.gamma_o, gamma_t);
+        }
+        fsum += ap[P_MAX]
+            * mp(
+                cos_theta_i,
+                cos_theta_o,
+                sin_theta_i,
+                sin_theta_o,
+                self.v[P_MAX],
+            )
+            / (2.0 * PI);
+
+        if abs_cos_theta(&wi) > 0.0 {
+            fsum = fsum / abs_cos_theta(&wi);
+        }
+        fsum
+    }
+
+    fn sample_f(
+        &self,
+        wo: &na::Vector3<f32>,
+        wi: &mut na::Vector3<f32>,
+        u: &na::Point2<f32>,
+        pdf: &mut f32,
+        sampled_type: &mut Option<BxDFType>,
+    ) -> Spectrum {
+        let sin_theta_o = wo.x;
+        let cos_theta_o = safe_sqrt(1.0 - sqr(sin_theta_o));
+        let phi_o = wo.z.atan2(wo.y);
+
+        // four samples are needed, so each of the two dimensions is split in half
+        let mut u = [demux_float(u[0]), demux_float(u[1])];
+
+        // choose which lobe to sample
+        let ap_pdf = self.compute_ap_pdf(cos_theta_o);
+        let mut p = 0;
+        while p < P_MAX {
+            if u[0][0] < ap_pdf[p] {
+                break;
+            }
+            u[0][0] -= ap_pdf[p];
+            p += 1;
+        }
+
+        // sample the longitudinal angle around the tilted lobe
+        let (sin_theta_op, cos_theta_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+        u[1][0] = u[1][0].max(1e-5);
+        let cos_theta =
+            1.0 + self.v[p] * (u[1][0] + (1.0 - u[1][0]) * (-2.0 / self.v[p]).exp()).ln();
+        let sin_theta = safe_sqrt(1.0 - sqr(cos_theta));
+        let cos_phi = (2.0 * PI * u[1][1]).cos();
+        let sin_theta_i = -cos_theta * sin_theta_op + sin_theta * cos_phi * cos_theta_op;
+        let cos_theta_i = safe_sqrt(1.0 - sqr(sin_theta_i));
+
+        // sample the azimuthal angle
+        let (_, gamma_t) = self.transmittance(sin_theta_o, cos_theta_o);
+        let dphi = if p < P_MAX {
+            phi(p, self.gamma_o, gamma_t) + sample_trimmed_logistic(u[0][1], self.s, -PI, PI)
+        } else {
+            2.0 * PI * u[0][1]
+        };
+
+        let phi_i = phi_o + dphi;
+        *wi = na::Vector3::new(
+            sin_theta_i,
+            cos_theta_i * phi_i.cos(),
+            cos_theta_i * phi_i.sin(),
+        );
+
+        *pdf = self.pdf_from_angles(
+            sin_theta_o,
+            cos_theta_o,
+            sin_theta_i,
+            cos_theta_i,
+            dphi,
+            gamma_t,
+        );
+
+        if let Some(sampled_type) = sampled_type {
+            *sampled_type = self.get_type();
+        }
+
+        self.f(&wo, &wi)
+    }
+
+    fn get_type(&self) -> BxDFType {
+        BxDFType::BSDF_GLOSSY | BxDFType::BSDF_REFLECTION | BxDFType::BSDF_TRANSMISSION
+    }
+
+    fn pdf(&self, wo: &na::Vector3<f32>, wi: &na::Vector3<f32>) -> f32 {
+        let sin_theta_o = wo.x;
+        let cos_theta_o = safe_sqrt(1.0 - sqr(sin_theta_o));
+        let phi_o = wo.z.atan2(wo.y);
+
+        let sin_theta_i = wi.x;
+        let cos_theta_i = safe_sqrt(1.0 - sqr(sin_theta_i));
+        let phi_i = wi.z.atan2(wi.y);
+
+        let (_, gamma_t) = self.transmittance(sin_theta_o, cos_theta_o);
+        self.pdf_from_angles(
+            sin_theta_o,
+            cos_theta_o,
+            sin_theta_i,
+            cos_theta_i,
+            phi_i - phi_o,
+            gamma_t,
+        )
+    }
+}
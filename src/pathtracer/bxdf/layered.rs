@@ -0,0 +1,571 @@
+use super::{
+    abs_cos_theta, cos_theta, fresnel::fr_dielectric, refract, same_hemisphere, BxDF,
+    BxDFInterface, BxDFType,
+};
+use crate::{
+    common::math::{face_forward, lerp, power_heuristic},
+    common::spectrum::Spectrum,
+    pathtracer::sampling::Random,
+    pathtracer::TransportMode,
+};
+use rand::{Rng, SeedableRng};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+struct LayerSample {
+    f: Spectrum,
+    wi: na::Vector3<f32>,
+    pdf: f32,
+    sampled_type: BxDFType,
+}
+
+impl LayerSample {
+    fn is_valid(&self) -> bool {
+        !self.f.is_black() && self.pdf > 0.0 && self.wi.z != 0.0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Layer {
+    Top,
+    Bottom,
+}
+
+fn adjoint(mode: TransportMode) -> TransportMode {
+    match mode {
+        TransportMode::Radiance => TransportMode::Importance,
+        TransportMode::Importance => TransportMode::Radiance,
+    }
+}
+
+/// transmittance through a slab of unit extinction coefficient
+fn tr(dz: f32, w: &na::Vector3<f32>) -> f32 {
+    if dz.abs() <= std::f32::MIN_POSITIVE {
+        1.0
+    } else {
+        (-(dz / w.z).abs()).exp()
+    }
+}
+
+/// the random walk has to be deterministic for a given pair of directions
+fn seeded_rng(a: &na::Vector3<f32>, b: &na::Vector3<f32>) -> Random {
+    let mut hasher = DefaultHasher::new();
+    for c in a.iter().chain(b.iter()) {
+        c.to_bits().hash(&mut hasher);
+    }
+
+    Random::seed_from_u64(hasher.finish())
+}
+
+/// smooth dielectric boundary that can be sampled for reflection or transmission only
+struct DielectricInterface {
+    eta: f32,
+}
+
+impl DielectricInterface {
+    fn sample_f(
+        &self,
+        wo: &na::Vector3<f32>,
+        uc: f32,
+        mode: TransportMode,
+        flags: BxDFType,
+    ) -> Option<LayerSample> {
+        let r = fr_dielectric(cos_theta(&wo), 1.0, self.eta);
+        let t = 1.0 - r;
+        let pr = if flags.contains(BxDFType::BSDF_REFLECTION) {
+            r
+        } else {
+            0.0
+        };
+        let pt = if flags.contains(BxDFType::BSDF_TRANSMISSION) {
+            t
+        } else {
+            0.0
+        };
+        if pr == 0.0 && pt == 0.0 {
+            return None;
+        }
+
+        if uc < pr / (pr + pt) {
+            let wi = na::Vector3::new(-wo.x, -wo.y, wo.z);
+            Some(LayerSample {
+                f: Spectrum::new(r / abs_cos_theta(&wi)),
+                wi,
+                pdf: pr / (pr + pt),
+                sampled_type: BxDFType::BSDF_REFLECTION | BxDFType::BSDF_SPECULAR,
+            })
+        } else {
+            let entering = cos_theta(&wo) > 0.0;
+            let eta_i = if entering { 1.0 } else { self.eta };
+            let eta_t = if entering { self.eta } else { 1.0 };
+
+            let mut wi = glm::zero();
+            if !refract(
+                &wo,
+                &face_forward(&na::Vector3::new(0.0, 0.0, 1.0), &wo),
+                eta_i / eta_t,
+                &mut wi,
+            ) {
+                return None;
+            }
+
+            let mut ft = t / abs_cos_theta(&wi);
+            if mode == TransportMode::Radiance {
+                ft *= (eta_i * eta_i) / (eta_t * eta_t);
+            }
+
+            Some(LayerSample {
+                f: Spectrum::new(ft),
+                wi,
+                pdf: pt / (pr + pt),
+                sampled_type: BxDFType::BSDF_TRANSMISSION | BxDFType::BSDF_SPECULAR,
+            })
+        }
+    }
+}
+
+/// lobes of the base material, evaluated in the same local frame as the coating
+struct BxDFStack<'a> {
+    bxdfs: &'a [BxDF<'a>],
+}
+
+impl<'a> BxDFStack<'a> {
+    fn f(&self, wo: &na::Vector3<f32>, wi: &na::Vector3<f32>) -> Spectrum {
+        let reflect = same_hemisphere(&wo, &wi);
+        let mut f = Spectrum::new(0.0);
+        for bxdf in self.bxdfs {
+            if (reflect && bxdf.get_type().contains(BxDFType::BSDF_REFLECTION))
+                || (!reflect && bxdf.get_type().contains(BxDFType::BSDF_TRANSMISSION))
+            {
+                f += bxdf.f(&wo, &wi);
+            }
+        }
+
+        f
+    }
+
+    fn pdf(&self, wo: &na::Vector3<f32>, wi: &na::Vector3<f32>) -> f32 {
+        if self.bxdfs.is_empty() {
+            return 0.0;
+        }
+
+        self.bxdfs
+            .iter()
+            .map(|bxdf| bxdf.pdf(&wo, &wi))
+            .sum::<f32>()
+            / self.bxdfs.len() as f32
+    }
+
+    fn sample_f(
+        &self,
+        wo: &na::Vector3<f32>,
+        uc: f32,
+        u: &na::Point2<f32>,
+        flags: BxDFType,
+    ) -> Option<LayerSample> {
+        if self.bxdfs.is_empty() {
+            return None;
+        }
+
+        let n = self.bxdfs.len();
+        let bxdf = &self.bxdfs[((uc * n as f32) as usize).min(n - 1)];
+        let mut wi = glm::zero();
+        let mut pdf = 0.0;
+        let mut sampled_type = Some(bxdf.get_type());
+        let mut f = bxdf.sample_f(&wo, &mut wi, &u, &mut pdf, &mut sampled_type);
+        let mut sampled_type = sampled_type.unwrap();
+
+        if pdf == 0.0 {
+            return None;
+        }
+
+        // the sampled lobe might be both reflective and transmissive, resolve from the direction
+        if sampled_type.contains(BxDFType::BSDF_REFLECTION | BxDFType::BSDF_TRANSMISSION) {
+            sampled_type &= if same_hemisphere(&wo, &wi) {
+                !BxDFType::BSDF_TRANSMISSION
+            } else {
+                !BxDFType::BSDF_REFLECTION
+            };
+        }
+        if !flags.intersects(sampled_type) {
+            return None;
+        }
+
+        if !sampled_type.contains(BxDFType::BSDF_SPECULAR) && n > 1 {
+            pdf = self.pdf(&wo, &wi);
+            f = self.f(&wo, &wi);
+        } else if n > 1 {
+            pdf /= n as f32;
+        }
+
+        Some(LayerSample {
+            f,
+            wi,
+            pdf,
+            sampled_type,
+        })
+    }
+
+    fn is_specular(&self) -> bool {
+        self.bxdfs
+            .iter()
+            .all(|bxdf| bxdf.get_type().contains(BxDFType::BSDF_SPECULAR))
+    }
+
+    fn get_type(&self) -> BxDFType {
+        self.bxdfs
+            .iter()
+            .fold(BxDFType::empty(), |t, bxdf| t | bxdf.get_type())
+    }
+}
+
+/// Stochastic evaluation of a smooth dielectric coating on top of arbitrary base lobes, following
+/// the random walk formulation of Guo et al. 2018 "Position-Free Monte Carlo Simulation for
+/// Arbitrary Layered BSDFs". The coating is two sided.
+pub struct LayeredBxDF<'a> {
+    top: DielectricInterface,
+    bottom: BxDFStack<'a>,
+    thickness: f32,
+    max_depth: usize,
+    n_samples: usize,
+    mode: TransportMode,
+}
+
+impl<'a> LayeredBxDF<'a> {
+    pub fn new(
+        bottom: &'a [BxDF<'a>],
+        eta: f32,
+        thickness: f32,
+        max_depth: usize,
+        n_samples: usize,
+        mode: TransportMode,
+    ) -> Self {
+        Self {
+            top: DielectricInterface { eta },
+            bottom: BxDFStack { bxdfs: bottom },
+            thickness: thickness.max(std::f32::MIN_POSITIVE),
+            max_depth,
+            n_samples: n_samples.max(1),
+            mode,
+        }
+    }
+
+    fn layer_f(&self, layer: Layer, wo: &na::Vector3<f32>, wi: &na::Vector3<f32>) -> Spectrum {
+        match layer {
+            Layer::Top => Spectrum::new(0.0),
+            Layer::Bottom => self.bottom.f(&wo, &wi),
+        }
+    }
+
+    fn layer_pdf(&self, layer: Layer, wo: &na::Vector3<f32>, wi: &na::Vector3<f32>) -> f32 {
+        match layer {
+            Layer::Top => 0.0,
+            Layer::Bottom => self.bottom.pdf(&wo, &wi),
+        }
+    }
+
+    fn layer_sample_f(
+        &self,
+        layer: Layer,
+        wo: &na::Vector3<f32>,
+        rng: &mut Random,
+        mode: TransportMode,
+        flags: BxDFType,
+    ) -> Option<LayerSample> {
+        let sample = match layer {
+            Layer::Top => self.top.sample_f(&wo, rng.gen(), mode, flags),
+            Layer::Bottom => {
+                let uc = rng.gen();
+                let u = na::Point2::new(rng.gen(), rng.gen());
+                self.bottom.sample_f(&wo, uc, &u, flags)
+            }
+        };
+
+        sample.filter(LayerSample::is_valid)
+    }
+
+    fn layer_is_specular(&self, layer: Layer) -> bool {
+        match layer {
+            Layer::Top => true,
+            Layer::Bottom => self.bottom.is_specular(),
+        }
+    }
+}
+
+impl<'a> BxDFInterface for LayeredBxDF<'a> {
+    fn f(&self, wo: &na::Vector3<f32>, wi: &na::Vector3<f32>) -> Spectrum {
+        let (wo, wi) = if wo.z < 0.0 { (-wo, -wi) } else { (*wo, *wi) };
+
+        // leaving through the top is reflection off the whole stack, otherwise transmission
+        let (exit, non_exit, exit_z) = if same_hemisphere(&wo, &wi) {
+            (Layer::Top, Layer::Bottom, self.thickness)
+        } else {
+            (Layer::Bottom, Layer::Top, 0.0)
+        };
+
+        let mut rng = seeded_rng(&wo, &wi);
+        let mut f = Spectrum::new(0.0);
+
+        for _ in 0..self.n_samples {
+            let wos = if let Some(wos) = self.layer_sample_f(
+                Layer::Top,
+                &wo,
+                &mut rng,
+                self.mode,
+                BxDFType::BSDF_TRANSMISSION,
+            ) {
+                wos
+            } else {
+                continue;
+            };
+
+            let wis = if let Some(wis) = self.layer_sample_f(
+                exit,
+                &wi,
+                &mut rng,
+                adjoint(self.mode),
+                BxDFType::BSDF_TRANSMISSION,
+            ) {
+                wis
+            } else {
+                continue;
+            };
+
+            let mut beta = wos.f * abs_cos_theta(&wos.wi) / wos.pdf;
+            let mut z = self.thickness;
+            let mut w = wos.wi;
+
+            for depth in 0..self.max_depth {
+                if depth > 3 && beta.max_component_value() < 0.25 {
+                    let q = 0.0f32.max(1.0 - beta.max_component_value());
+                    if rng.gen::<f32>() < q {
+                        break;
+                    }
+                    beta /= 1.0 - q;
+                }
+
+                z = if z == self.thickness {
+                    0.0
+                } else {
+                    self.thickness
+                };
+                beta *= tr(self.thickness, &w);
+
+                if z == exit_z {
+                    let bs = if let Some(bs) = self.layer_sample_f(
+                        exit,
+                        &-w,
+                        &mut rng,
+                        self.mode,
+                        BxDFType::BSDF_REFLECTION,
+                    ) {
+                        bs
+                    } else {
+                        break;
+                    };
+                    beta *= bs.f * abs_cos_theta(&bs.wi) / bs.pdf;
+                    w = bs.wi;
+                } else {
+                    // next event estimation along the presampled exit direction
+                    if !self.layer_is_specular(non_exit) {
+                        let wt = if !self.layer_is_specular(exit) {
+                            power_heuristic(1, wis.pdf, 1, self.layer_pdf(non_exit, &-w, &-wis.wi))
+                        } else {
+                            1.0
+                        };
+                        f += beta
+                            * self.layer_f(non_exit, &-w, &-wis.wi)
+                            * abs_cos_theta(&wis.wi)
+                            * wt
+                            * tr(self.thickness, &wis.wi)
+                            * wis.f
+                            / wis.pdf;
+                    }
+
+                    let bs = if let Some(bs) = self.layer_sample_f(
+                        non_exit,
+                        &-w,
+                        &mut rng,
+                        self.mode,
+                        BxDFType::BSDF_REFLECTION,
+                    ) {
+                        bs
+                    } else {
+                        break;
+                    };
+                    beta *= bs.f * abs_cos_theta(&bs.wi) / bs.pdf;
+                    w = bs.wi;
+
+                    if !self.layer_is_specular(exit) {
+                        let f_exit = self.layer_f(exit, &-w, &wi);
+                        if !f_exit.is_black() {
+                            let wt = if !self.layer_is_specular(non_exit) {
+                                power_heuristic(1, bs.pdf, 1, self.layer_pdf(exit, &-w, &wi))
+                            } else {
+                                1.0
+                            };
+                            f += beta * tr(self.thickness, &bs.wi) * f_exit * wt;
+                        }
+                    }
+                }
+            }
+        }
+
+        f / self.n_samples as f32
+    }
+
+    fn sample_f(
+        &self,
+        wo: &na::Vector3<f32>,
+        wi: &mut na::Vector3<f32>,
+        u: &na::Point2<f32>,
+        pdf: &mut f32,
+        sampled_type: &mut Option<BxDFType>,
+    ) -> Spectrum {
+        let flip_wi = wo.z < 0.0;
+        let wo = if flip_wi { -wo } else { *wo };
+        *pdf = 0.0;
+
+        let bs = if let Some(bs) = self
+            .top
+            .sample_f(&wo, u[0], self.mode, BxDFType::BSDF_ALL)
+            .filter(LayerSample::is_valid)
+        {
+            bs
+        } else {
+            return Spectrum::new(0.0);
+        };
+
+        if bs.sampled_type.contains(BxDFType::BSDF_REFLECTION) {
+            *wi = if flip_wi { -bs.wi } else { bs.wi };
+            *pdf = bs.pdf;
+            if let Some(sampled_type) = sampled_type {
+                *sampled_type = bs.sampled_type;
+            }
+            return bs.f;
+        }
+
+        let mut rng = seeded_rng(&wo, &na::Vector3::new(u[0], u[1], 0.0));
+        let mut w = bs.wi;
+        let mut specular_path = true;
+        let mut f = bs.f * abs_cos_theta(&bs.wi);
+        let mut path_pdf = bs.pdf;
+        let mut z = self.thickness;
+
+        for depth in 0..self.max_depth {
+            let rr_beta = f.max_component_value() / path_pdf;
+            if depth > 3 && rr_beta < 0.25 {
+                let q = 0.0f32.max(1.0 - rr_beta);
+                if rng.gen::<f32>() < q {
+                    return Spectrum::new(0.0);
+                }
+                path_pdf *= 1.0 - q;
+            }
+
+            z = if z == self.thickness {
+                0.0
+            } else {
+                self.thickness
+            };
+            f *= tr(self.thickness, &w);
+
+            let layer = if z == 0.0 { Layer::Bottom } else { Layer::Top };
+            let bs = if let Some(bs) =
+                self.layer_sample_f(layer, &-w, &mut rng, self.mode, BxDFType::BSDF_ALL)
+            {
+                bs
+            } else {
+                return Spectrum::new(0.0);
+            };
+
+            f *= bs.f;
+            path_pdf *= bs.pdf;
+            specular_path &= bs.sampled_type.contains(BxDFType::BSDF_SPECULAR);
+            w = bs.wi;
+
+            if bs.sampled_type.contains(BxDFType::BSDF_TRANSMISSION) {
+                // the walk has left the stack
+                *wi = if flip_wi { -w } else { w };
+                *pdf = path_pdf;
+                if let Some(sampled_type) = sampled_type {
+                    let lobe = if specular_path {
+                        BxDFType::BSDF_SPECULAR
+                    } else {
+                        BxDFType::BSDF_GLOSSY
+                    };
+                    *sampled_type = if same_hemisphere(&wo, &w) {
+                        BxDFType::BSDF_REFLECTION | lobe
+                    } else {
+                        BxDFType::BSDF_TRANSMISSION | lobe
+                    };
+                }
+                return f;
+            }
+
+            f *= abs_cos_theta(&bs.wi);
+        }
+
+        *pdf = 0.0;
+        Spectrum::new(0.0)
+    }
+
+    fn get_type(&self) -> BxDFType {
+        let mut t = BxDFType::BSDF_REFLECTION | BxDFType::BSDF_GLOSSY;
+        if self.bottom.get_type().contains(BxDFType::BSDF_TRANSMISSION) {
+            t |= BxDFType::BSDF_TRANSMISSION;
+        }
+
+        t
+    }
+
+    fn pdf(&self, wo: &na::Vector3<f32>, wi: &na::Vector3<f32>) -> f32 {
+        let (wo, wi) = if wo.z < 0.0 { (-wo, -wi) } else { (*wo, *wi) };
+
+        let mut rng = seeded_rng(&wi, &wo);
+        let mut pdf_sum = 0.0;
+
+        for _ in 0..self.n_samples {
+            if same_hemisphere(&wo, &wi) {
+                // reflection off the base seen through the coating (TRT)
+                let wos = self.layer_sample_f(
+                    Layer::Top,
+                    &wo,
+                    &mut rng,
+                    self.mode,
+                    BxDFType::BSDF_TRANSMISSION,
+                );
+                let wis = self.layer_sample_f(
+                    Layer::Top,
+                    &wi,
+                    &mut rng,
+                    adjoint(self.mode),
+                    BxDFType::BSDF_TRANSMISSION,
+                );
+                if let (Some(wos), Some(wis)) = (wos, wis) {
+                    pdf_sum += self.layer_pdf(Layer::Bottom, &-wos.wi, &-wis.wi);
+                }
+            } else {
+                // transmission through the whole stack (TT)
+                let wos = self.layer_sample_f(
+                    Layer::Top,
+                    &wo,
+                    &mut rng,
+                    self.mode,
+                    BxDFType::BSDF_TRANSMISSION,
+                );
+                if let Some(wos) = wos {
+                    pdf_sum += self.layer_pdf(Layer::Bottom, &-wos.wi, &wi);
+                }
+            }
+        }
+
+        // mix with a uniform pdf to account for the paths the estimate above ignores
+        lerp(
+            1.0 / (4.0 * std::f32::consts::PI),
+            pdf_sum / self.n_samples as f32,
+            0.9,
+        )
+    }
+}
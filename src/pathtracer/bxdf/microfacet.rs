@@ -2,12 +2,16 @@ use super::{
     abs_cos_theta, cos_2_phi, cos_2_theta, cos_phi, cos_theta,
     fresnel::FresnelDielectric,
     fresnel::{Fresnel, FresnelInterface},
-    reflect, refract, same_hemisphere, sin_2_phi, sin_phi, tan_2_theta, tan_theta, BxDFInterface,
-    BxDFType,
+    reflect, refract,
+    rho::RhoTable,
+    same_hemisphere, sin_2_phi, sin_phi, tan_2_theta, tan_theta, BxDFInterface, BxDFType,
 };
 use crate::{
-    common::math::ONE_MINUS_EPSILON, common::spectrum::Spectrum,
-    pathtracer::sampling::cosine_sample_hemisphere, pathtracer::TransportMode,
+    common::math::{erf, erf_inv, ONE_MINUS_EPSILON},
+    common::spectrum::Spectrum,
+    pathtracer::arena::MemoryArena,
+    pathtracer::sampling::cosine_sample_hemisphere,
+    pathtracer::TransportMode,
 };
 use num::Zero;
 
@@ -27,60 +31,187 @@ pub trait MicrofacetDistribution {
     fn sample_wh(&self, wo: &na::Vector3<f32>, u: &na::Point2<f32>) -> na::Vector3<f32>;
 
     fn pdf(&self, wo: &na::Vector3<f32>, wh: &na::Vector3<f32>) -> f32;
+
+    /// cached directional albedo of the distribution and the alpha to look it up with, `None`
+    /// when there is no table and the albedo has to be estimated by sampling
+    fn rho_table(&self) -> Option<(&'static RhoTable, f32)> {
+        None
+    }
 }
 
-fn trowbridge_reitz_sample_11(
-    cos_theta: f32,
+/// samples a normal from the distribution of visible normals of an isotropic unit roughness ggx
+/// surface after stretching, exact method of Heitz 2018
+fn trowbridge_reitz_sample(
+    wi: &na::Vector3<f32>,
+    alpha_x: f32,
+    alpha_y: f32,
     u1: f32,
-    mut u2: f32,
-    slope_x: &mut f32,
-    slope_y: &mut f32,
-) {
-    if cos_theta > 0.9999 {
-        let r = (u1 / (1. - u1)).sqrt();
-        let phi = 6.28318530718 * u2;
+    u2: f32,
+) -> na::Vector3<f32> {
+    // transform the view direction to the hemisphere configuration
+    let vh = na::Vector3::new(alpha_x * wi.x, alpha_y * wi.y, wi.z).normalize();
+
+    // orthonormal basis around the view direction
+    let len_2 = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if len_2 > 0. {
+        na::Vector3::new(-vh.y, vh.x, 0.) / len_2.sqrt()
+    } else {
+        na::Vector3::new(1., 0., 0.)
+    };
+    let t2 = vh.cross(&t1);
+
+    // uniformly sample the projected disk, warping half of it towards the view direction
+    let r = u1.sqrt();
+    let phi = 2. * std::f32::consts::PI * u2;
+    let p1 = r * phi.cos();
+    let p2 = r * phi.sin();
+    let s = 0.5 * (1. + vh.z);
+    let p2 = (1. - s) * (1. - p1 * p1).max(0.).sqrt() + s * p2;
+
+    // reproject onto the hemisphere and unstretch
+    let nh = p1 * t1 + p2 * t2 + (1. - p1 * p1 - p2 * p2).max(0.).sqrt() * vh;
+    na::Vector3::new(alpha_x * nh.x, alpha_y * nh.y, nh.z.max(1e-6)).normalize()
+}
+
+pub struct TrowbridgeReitzDistribution {
+    alpha_x: f32,
+    alpha_y: f32,
+}
+
+impl TrowbridgeReitzDistribution {
+    pub fn new(alpha_x: f32, alpha_y: f32) -> Self {
+        Self {
+            alpha_x: alpha_x.max(0.001),
+            alpha_y: alpha_y.max(0.001),
+        }
+    }
+
+    pub fn roughness_to_alpha(roughness: f32) -> f32 {
+        let roughness = roughness.max(1e-3);
+        let x = roughness.ln();
+        1.62142
+            + 0.819955 * x
+            + 0.1734 * x * x
+            + 0.0171201 * x * x * x
+            + 0.000640711 * x * x * x * x
+    }
+}
+
+impl MicrofacetDistribution for TrowbridgeReitzDistribution {
+    fn d(&self, wh: &na::Vector3<f32>) -> f32 {
+        let tan_2_theta = tan_2_theta(&wh);
+        if tan_2_theta.is_infinite() {
+            return 0.0;
+        }
+        let cos_4_theta = cos_2_theta(&wh) * cos_2_theta(&wh);
+        let e = (cos_2_phi(&wh) / (self.alpha_x * self.alpha_x)
+            + sin_2_phi(&wh) / (self.alpha_y * self.alpha_y))
+            * tan_2_theta;
+        1.0 / (std::f32::consts::PI
+            * self.alpha_x
+            * self.alpha_y
+            * cos_4_theta
+            * (1.0 + e)
+            * (1.0 + e))
+    }
+
+    fn lambda(&self, w: &na::Vector3<f32>) -> f32 {
+        let abs_tan_theta = tan_theta(&w).abs();
+        if abs_tan_theta.is_infinite() {
+            return 0.0;
+        }
+        let alpha = ((cos_2_phi(&w) * self.alpha_x * self.alpha_x)
+            + (sin_2_phi(&w) * self.alpha_y * self.alpha_y))
+            .sqrt();
+        let alpha_2_tan_2_theta = (alpha * abs_tan_theta) * (alpha * abs_tan_theta);
+        (-1.0 + (1.0 + alpha_2_tan_2_theta).sqrt()) / 2.0
+    }
+
+    fn sample_wh(&self, wo: &na::Vector3<f32>, u: &na::Point2<f32>) -> na::Vector3<f32> {
+        let flip = wo.z < 0.;
+        let wo = if flip { -wo } else { *wo };
+        let wh = trowbridge_reitz_sample(&wo, self.alpha_x, self.alpha_y, u[0], u[1]);
+        if flip {
+            -wh
+        } else {
+            wh
+        }
+    }
+
+    fn pdf(&self, wo: &na::Vector3<f32>, wh: &na::Vector3<f32>) -> f32 {
+        self.d(wh) * self.g1(wo) * wo.dot(&wh).abs() / abs_cos_theta(wo)
+    }
+
+    fn rho_table(&self) -> Option<(&'static RhoTable, f32)> {
+        Some((
+            RhoTable::get(MicrofacetModel::TrowbridgeReitz),
+            (self.alpha_x * self.alpha_y).sqrt(),
+        ))
+    }
+}
+
+fn beckmann_sample_11(cos_theta_i: f32, u1: f32, u2: f32, slope_x: &mut f32, slope_y: &mut f32) {
+    // special case (normal incidence)
+    if cos_theta_i > 0.9999 {
+        let r = (-(1.0 - u1).ln()).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * u2;
         *slope_x = r * phi.cos();
         *slope_y = r * phi.sin();
         return;
     }
 
-    let sin_theta = 0.0f32.max(1. - cos_theta * cos_theta).sqrt();
-    let tan_theta = sin_theta / cos_theta;
-    let alpha = 1. / tan_theta;
-    let g1 = 2. / (1. + (1. + 1. / (alpha * alpha)).sqrt());
+    // the inverse of the visible slope cdf has no closed form, invert it with a few steps of
+    // bisection and newton's method
+    let sin_theta_i = 0.0f32.max(1.0 - cos_theta_i * cos_theta_i).sqrt();
+    let tan_theta_i = sin_theta_i / cos_theta_i;
+    let cot_theta_i = 1.0 / tan_theta_i;
 
-    let a = 2. * u1 / g1 - 1.;
-    let mut tmp = 1. / (a * a - 1.);
-    if tmp > 1e10 {
-        tmp = 1e10;
-    }
-    let b = tan_theta;
-    let d = 0.0f32.max(b * b * tmp * tmp - (a * a - b * b) * tmp).sqrt();
-    let slope_x_1 = b * tmp - d;
-    let slope_x_2 = b * tmp + d;
-    *slope_x = if a < 0. || slope_x_2 > (1. / tan_theta) {
-        slope_x_1
-    } else {
-        slope_x_2
-    };
+    let mut a = -1.0;
+    let mut c = erf(cot_theta_i);
+    let sample_x = u1.max(1e-6);
 
-    let s;
-    if u2 > 0.5 {
-        s = 1.;
-        u2 = 2. * (u2 - 0.5);
-    } else {
-        s = -1.;
-        u2 = 2. * (0.5 - u2);
+    let theta_i = cos_theta_i.acos();
+    let fit = 1.0 + theta_i * (-0.876 + theta_i * (0.4265 - 0.0594 * theta_i));
+    let mut b = c - (1.0 + c) * (1.0 - sample_x).powf(fit);
+
+    let sqrt_pi_inv = 1.0 / std::f32::consts::PI.sqrt();
+    let normalization =
+        1.0 / (1.0 + c + sqrt_pi_inv * tan_theta_i * (-cot_theta_i * cot_theta_i).exp());
+
+    for _ in 0..10 {
+        if !(b >= a && b <= c) {
+            b = 0.5 * (a + c);
+        }
+
+        let inv_erf = erf_inv(b);
+        let value = normalization
+            * (1.0 + b + sqrt_pi_inv * tan_theta_i * (-inv_erf * inv_erf).exp())
+            - sample_x;
+        let derivative = normalization * (1.0 - inv_erf * tan_theta_i);
+
+        if value.abs() < 1e-5 {
+            break;
+        }
+
+        if value > 0.0 {
+            c = b;
+        } else {
+            a = b;
+        }
+
+        b -= value / derivative;
     }
-    let z = (u2 * (u2 * (u2 * 0.27385 - 0.73369) + 0.46341))
-        / (u2 * (u2 * (u2 * 0.093073 + 0.309420) - 1.000000) + 0.597999);
-    *slope_y = s * z * (1. + *slope_x * *slope_x).sqrt();
 
+    *slope_x = erf_inv(b);
+    *slope_y = erf_inv(2.0 * u2.max(1e-6) - 1.0);
+
+    debug_assert!(!slope_x.is_infinite());
+    debug_assert!(!slope_x.is_nan());
     debug_assert!(!slope_y.is_infinite());
     debug_assert!(!slope_y.is_nan());
 }
 
-fn trowbridge_reitz_sample(
+fn beckmann_sample(
     wi: &na::Vector3<f32>,
     alpha_x: f32,
     alpha_y: f32,
@@ -91,7 +222,7 @@ fn trowbridge_reitz_sample(
 
     let mut slope_x = 0.0;
     let mut slope_y = 0.0;
-    trowbridge_reitz_sample_11(cos_theta(&wi_stretched), u1, u2, &mut slope_x, &mut slope_y);
+    beckmann_sample_11(cos_theta(&wi_stretched), u1, u2, &mut slope_x, &mut slope_y);
 
     let tmp = cos_phi(&wi_stretched) * slope_x - sin_phi(&wi_stretched) * slope_y;
     slope_y = sin_phi(&wi_stretched) * slope_x + cos_phi(&wi_stretched) * slope_y;
@@ -103,12 +234,12 @@ fn trowbridge_reitz_sample(
     na::Vector3::new(-slope_x, -slope_y, 1.).normalize()
 }
 
-pub struct TrowbridgeReitzDistribution {
+pub struct BeckmannDistribution {
     alpha_x: f32,
     alpha_y: f32,
 }
 
-impl TrowbridgeReitzDistribution {
+impl BeckmannDistribution {
     pub fn new(alpha_x: f32, alpha_y: f32) -> Self {
         Self {
             alpha_x: alpha_x.max(0.001),
@@ -127,22 +258,18 @@ impl TrowbridgeReitzDistribution {
     }
 }
 
-impl MicrofacetDistribution for TrowbridgeReitzDistribution {
+impl MicrofacetDistribution for BeckmannDistribution {
     fn d(&self, wh: &na::Vector3<f32>) -> f32 {
         let tan_2_theta = tan_2_theta(&wh);
         if tan_2_theta.is_infinite() {
             return 0.0;
         }
         let cos_4_theta = cos_2_theta(&wh) * cos_2_theta(&wh);
-        let e = (cos_2_phi(&wh) / (self.alpha_x * self.alpha_x)
-            + sin_2_phi(&wh) / (self.alpha_y * self.alpha_y))
-            * tan_2_theta;
-        1.0 / (std::f32::consts::PI
-            * self.alpha_x
-            * self.alpha_y
-            * cos_4_theta
-            * (1.0 + e)
-            * (1.0 + e))
+        (-tan_2_theta
+            * (cos_2_phi(&wh) / (self.alpha_x * self.alpha_x)
+                + sin_2_phi(&wh) / (self.alpha_y * self.alpha_y)))
+            .exp()
+            / (std::f32::consts::PI * self.alpha_x * self.alpha_y * cos_4_theta)
     }
 
     fn lambda(&self, w: &na::Vector3<f32>) -> f32 {
@@ -153,14 +280,17 @@ impl MicrofacetDistribution for TrowbridgeReitzDistribution {
         let alpha = ((cos_2_phi(&w) * self.alpha_x * self.alpha_x)
             + (sin_2_phi(&w) * self.alpha_y * self.alpha_y))
             .sqrt();
-        let alpha_2_tan_2_theta = (alpha * abs_tan_theta) * (alpha * abs_tan_theta);
-        (-1.0 + (1.0 + alpha_2_tan_2_theta).sqrt()) / 2.0
+        let a = 1.0 / (alpha * abs_tan_theta);
+        if a >= 1.6 {
+            return 0.0;
+        }
+        (1.0 - 1.259 * a + 0.396 * a * a) / (3.535 * a + 2.181 * a * a)
     }
 
     fn sample_wh(&self, wo: &na::Vector3<f32>, u: &na::Point2<f32>) -> na::Vector3<f32> {
         let flip = wo.z < 0.;
         let wo = if flip { -wo } else { *wo };
-        let wh = trowbridge_reitz_sample(&wo, self.alpha_x, self.alpha_y, u[0], u[1]);
+        let wh = beckmann_sample(&wo, self.alpha_x, self.alpha_y, u[0], u[1]);
         if flip {
             -wh
         } else {
@@ -171,29 +301,136 @@ impl MicrofacetDistribution for TrowbridgeReitzDistribution {
     fn pdf(&self, wo: &na::Vector3<f32>, wh: &na::Vector3<f32>) -> f32 {
         self.d(wh) * self.g1(wo) * wo.dot(&wh).abs() / abs_cos_theta(wo)
     }
+
+    fn rho_table(&self) -> Option<(&'static RhoTable, f32)> {
+        Some((
+            RhoTable::get(MicrofacetModel::Beckmann),
+            (self.alpha_x * self.alpha_y).sqrt(),
+        ))
+    }
 }
 
-pub struct MicrofacetReflection {
+/// which microfacet distribution a material builds its lobes with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MicrofacetModel {
+    TrowbridgeReitz,
+    Beckmann,
+}
+
+impl Default for MicrofacetModel {
+    fn default() -> Self {
+        MicrofacetModel::TrowbridgeReitz
+    }
+}
+
+impl MicrofacetModel {
+    /// parses the usual names of the distributions, ggx and trowbridge-reitz are the same
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "ggx" | "trowbridgereitz" | "trowbridge_reitz" | "trowbridge-reitz" => {
+                Some(MicrofacetModel::TrowbridgeReitz)
+            }
+            "beckmann" => Some(MicrofacetModel::Beckmann),
+            _ => None,
+        }
+    }
+
+    pub fn roughness_to_alpha(&self, roughness: f32) -> f32 {
+        match self {
+            MicrofacetModel::TrowbridgeReitz => {
+                TrowbridgeReitzDistribution::roughness_to_alpha(roughness)
+            }
+            MicrofacetModel::Beckmann => BeckmannDistribution::roughness_to_alpha(roughness),
+        }
+    }
+
+    pub fn distribution<'a>(
+        &self,
+        arena: &'a MemoryArena,
+        alpha_x: f32,
+        alpha_y: f32,
+    ) -> &'a dyn MicrofacetDistribution {
+        match self {
+            MicrofacetModel::TrowbridgeReitz => {
+                arena.alloc(TrowbridgeReitzDistribution::new(alpha_x, alpha_y))
+            }
+            MicrofacetModel::Beckmann => arena.alloc(BeckmannDistribution::new(alpha_x, alpha_y)),
+        }
+    }
+}
+
+pub struct MicrofacetReflection<'a> {
     r: Spectrum,
-    distribution: Box<dyn MicrofacetDistribution>,
-    fresnel: Box<Fresnel>,
+    distribution: &'a dyn MicrofacetDistribution,
+    fresnel: Fresnel,
+    multiple_scattering: Option<MultipleScattering>,
+}
+
+/// energy lost to the single scattering model, reintroduced as a diffuse-like lobe following
+/// Kulla and Conty 2017
+struct MultipleScattering {
+    table: &'static RhoTable,
+    alpha: f32,
+    e_avg: f32,
+    // tint of light bouncing between microfacets several times before leaving
+    f_ms: Spectrum,
+}
+
+impl MultipleScattering {
+    fn f(&self, wo: &na::Vector3<f32>, wi: &na::Vector3<f32>) -> Spectrum {
+        if self.e_avg >= 1. {
+            return Spectrum::zero();
+        }
+        let e_o = self.table.e(abs_cos_theta(wo), self.alpha);
+        let e_i = self.table.e(abs_cos_theta(wi), self.alpha);
+        self.f_ms * (1. - e_o) * (1. - e_i) / (std::f32::consts::PI * (1. - self.e_avg))
+    }
+
+    /// how often the cosine weighted lobe is sampled instead of the visible normals
+    fn probability(&self, wo: &na::Vector3<f32>) -> f32 {
+        (1. - self.table.e(abs_cos_theta(wo), self.alpha)).clamp(0., 0.9)
+    }
 }
 
-impl MicrofacetReflection {
+impl<'a> MicrofacetReflection<'a> {
     pub fn new(
         r: Spectrum,
-        distribution: Box<dyn MicrofacetDistribution>,
-        fresnel: Box<Fresnel>,
+        distribution: &'a dyn MicrofacetDistribution,
+        fresnel: Fresnel,
     ) -> Self {
         Self {
             r,
             distribution,
             fresnel,
+            multiple_scattering: None,
         }
     }
+
+    /// adds back the energy rough surfaces lose to single scattering, does nothing for
+    /// distributions without a cached albedo table
+    pub fn with_energy_compensation(mut self) -> Self {
+        if let Some((table, alpha)) = self.distribution.rho_table() {
+            // cosine weighted average of the fresnel term, midpoint rule
+            const N: usize = 8;
+            let f_avg = (0..N).fold(Spectrum::zero(), |acc, i| {
+                let mu = (i as f32 + 0.5) / N as f32;
+                acc + self.fresnel.evaluate(mu) * mu
+            }) * (2. / N as f32);
+            let e_avg = table.e_avg(alpha);
+            let f_ms = f_avg * f_avg * e_avg / (Spectrum::new(1.) - f_avg * (1. - e_avg));
+
+            self.multiple_scattering = Some(MultipleScattering {
+                table,
+                alpha,
+                e_avg,
+                f_ms,
+            });
+        }
+        self
+    }
 }
 
-impl BxDFInterface for MicrofacetReflection {
+impl<'a> BxDFInterface for MicrofacetReflection<'a> {
     fn f(&self, wo: &na::Vector3<f32>, wi: &na::Vector3<f32>) -> Spectrum {
         let cos_theta_o = abs_cos_theta(&wo);
         let cos_theta_i = abs_cos_theta(&wi);
@@ -207,8 +444,13 @@ impl BxDFInterface for MicrofacetReflection {
 
         let wh = wh.normalize();
         let f = self.fresnel.evaluate(wi.dot(&wh));
-        self.r * self.distribution.d(&wh) * self.distribution.g(&wo, &wi) * f
-            / (4.0 * cos_theta_i * cos_theta_o)
+        let single = self.r * self.distribution.d(&wh) * self.distribution.g(&wo, &wi) * f
+            / (4.0 * cos_theta_i * cos_theta_o);
+
+        match &self.multiple_scattering {
+            Some(ms) if same_hemisphere(wo, wi) => single + self.r * ms.f(wo, wi),
+            _ => single,
+        }
     }
 
     fn get_type(&self) -> BxDFType {
@@ -227,43 +469,98 @@ impl BxDFInterface for MicrofacetReflection {
             return Spectrum::new(0.);
         }
 
-        let wh = self.distribution.sample_wh(&wo, &u);
-        if wo.dot(&wh) < 0. {
-            return Spectrum::new(0.);
-        }
+        let p_ms = self
+            .multiple_scattering
+            .as_ref()
+            .map_or(0., |ms| ms.probability(wo));
+        let mut u = *u;
+        if u[0] < p_ms {
+            u[0] = (u[0] / p_ms).min(ONE_MINUS_EPSILON);
+            *wi = cosine_sample_hemisphere(&u);
+            if wo.z < 0. {
+                wi.z *= -1.;
+            }
+        } else {
+            u[0] = ((u[0] - p_ms) / (1. - p_ms)).min(ONE_MINUS_EPSILON);
+            let wh = self.distribution.sample_wh(&wo, &u);
+            if wo.dot(&wh) < 0. {
+                return Spectrum::new(0.);
+            }
 
-        *wi = reflect(&wo, &wh);
+            *wi = reflect(&wo, &wh);
 
-        if !same_hemisphere(wo, wi) {
-            return Spectrum::new(0.);
+            if !same_hemisphere(wo, wi) {
+                return Spectrum::new(0.);
+            }
         }
 
-        *pdf = self.distribution.pdf(wo, &wh) / (4. * wo.dot(&wh));
+        *pdf = self.pdf(wo, wi);
         self.f(wo, wi)
     }
 
+    fn rho(
+        &self,
+        wo: &na::Vector3<f32>,
+        n_samples: usize,
+        samples: &[na::Point2<f32>],
+    ) -> Spectrum {
+        match self.distribution.rho_table() {
+            // fresnel is taken at the outgoing angle, exact for the constant fresnel the
+            // tables are built with
+            Some((table, alpha)) => {
+                let cos_theta_o = abs_cos_theta(wo);
+                let e_o = table.e(cos_theta_o, alpha);
+                let single = self.fresnel.evaluate(cos_theta_o) * e_o;
+                match &self.multiple_scattering {
+                    Some(ms) => self.r * (single + ms.f_ms * (1. - e_o)),
+                    None => self.r * single,
+                }
+            }
+            None => {
+                let mut r = Spectrum::new(0.0);
+                for u in samples.iter().take(n_samples) {
+                    let mut wi = na::Vector3::zeros();
+                    let mut pdf = 0.0;
+                    let f = self.sample_f(wo, &mut wi, u, &mut pdf, &mut None);
+                    if pdf > 0.0 {
+                        r += f * abs_cos_theta(&wi) / pdf;
+                    }
+                }
+                r / (n_samples as f32)
+            }
+        }
+    }
+
     fn pdf(&self, wo: &na::Vector3<f32>, wi: &na::Vector3<f32>) -> f32 {
         if !same_hemisphere(wo, wi) {
             return 0.;
         }
         let wh = (wo + wi).normalize();
-        self.distribution.pdf(wo, &wh) / (4. * wo.dot(&wh))
+        let pdf = self.distribution.pdf(wo, &wh) / (4. * wo.dot(&wh));
+
+        match &self.multiple_scattering {
+            Some(ms) => {
+                let p_ms = ms.probability(wo);
+                (1. - p_ms) * pdf + p_ms * abs_cos_theta(wi) * std::f32::consts::FRAC_1_PI
+            }
+            None => pdf,
+        }
     }
 }
 
-pub struct MicrofacetTransmission {
+pub struct MicrofacetTransmission<'a> {
     t: Spectrum,
-    distribution: Box<dyn MicrofacetDistribution>,
+    distribution: &'a dyn MicrofacetDistribution,
     eta_a: f32,
     eta_b: f32,
     fresnel: FresnelDielectric,
     mode: TransportMode,
 }
 
-impl MicrofacetTransmission {
+impl<'a> MicrofacetTransmission<'a> {
     pub fn new(
         t: Spectrum,
-        distribution: Box<dyn MicrofacetDistribution>,
+        distribution: &'a dyn MicrofacetDistribution,
         eta_a: f32,
         eta_b: f32,
         mode: TransportMode,
@@ -279,7 +576,7 @@ impl MicrofacetTransmission {
     }
 }
 
-impl BxDFInterface for MicrofacetTransmission {
+impl<'a> BxDFInterface for MicrofacetTransmission<'a> {
     fn f(&self, wo: &na::Vector3<f32>, wi: &na::Vector3<f32>) -> Spectrum {
         if same_hemisphere(&wo, &wi) {
             return Spectrum::new(0.);
@@ -308,6 +605,8 @@ impl BxDFInterface for MicrofacetTransmission {
         let f = self.fresnel.evaluate(wo.dot(&wh));
 
         let sqrt_denom = wo.dot(&wh) + eta * wi.dot(&wh);
+        // radiance is compressed into the smaller solid angle on the denser side, importance is
+        // not, which keeps f(wo, wi) in importance mode equal to f(wi, wo) in radiance mode
         let factor = if self.mode == TransportMode::Radiance {
             1.0 / eta
         } else {
@@ -361,14 +660,15 @@ impl BxDFInterface for MicrofacetTransmission {
     }
 
     fn pdf(&self, wo: &na::Vector3<f32>, wi: &na::Vector3<f32>) -> f32 {
-        if !same_hemisphere(wo, wi) {
+        if same_hemisphere(wo, wi) {
             return 0.;
         }
 
+        // same generalized half vector as `f`, eta is the index on the side of wi over wo's
         let eta = if cos_theta(wo) > 0. {
-            self.eta_a / self.eta_b
-        } else {
             self.eta_b / self.eta_a
+        } else {
+            self.eta_a / self.eta_b
         };
         let wh = (wo + wi * eta).normalize();
 
@@ -383,47 +683,65 @@ impl BxDFInterface for MicrofacetTransmission {
     }
 }
 
-pub struct FresnelBlend {
+pub struct FresnelBlend<'a> {
     rd: Spectrum,
     rs: Spectrum,
-    distribution: Box<dyn MicrofacetDistribution>,
+    distribution: &'a dyn MicrofacetDistribution,
+    specular_probability: f32,
 }
 
-impl FresnelBlend {
-    pub fn new(rd: Spectrum, rs: Spectrum, distribution: Box<dyn MicrofacetDistribution>) -> Self {
+impl<'a> FresnelBlend<'a> {
+    pub fn new(rd: Spectrum, rs: Spectrum, distribution: &'a dyn MicrofacetDistribution) -> Self {
+        // pick lobes roughly by their albedo, the specular lobe always gets some samples since
+        // the schlick term sends it to one at grazing angles
+        let diffuse_weight = (rd * (Spectrum::new(1.) - rs)).y().max(0.);
+        let specular_weight = rs.y().max(0.);
+        let specular_probability = if diffuse_weight + specular_weight > 0. {
+            (specular_weight / (diffuse_weight + specular_weight)).clamp(0.25, 0.9)
+        } else {
+            0.5
+        };
+
         Self {
             rd,
             rs,
             distribution,
+            specular_probability,
         }
     }
 
     pub fn schlick_fresnel(&self, cos_theta: f32) -> Spectrum {
-        let pow5 = |v: f32| (v * v) * (v * v) * v;
         self.rs + pow5(1.0 - cos_theta) * (Spectrum::new(1.) - self.rs)
     }
-}
 
-impl BxDFInterface for FresnelBlend {
-    fn f(&self, wo: &na::Vector3<f32>, wi: &na::Vector3<f32>) -> Spectrum {
-        let pow5 = |v: f32| (v * v) * (v * v) * v;
-        let diffuse = (28. / (23. * std::f32::consts::PI))
+    fn diffuse(&self, wo: &na::Vector3<f32>, wi: &na::Vector3<f32>) -> Spectrum {
+        (28. / (23. * std::f32::consts::PI))
             * self.rd
             * (Spectrum::new(1.) - self.rs)
             * (1. - pow5(1. - 0.5 * abs_cos_theta(wi)))
-            * (1. - pow5(1. - 0.5 * abs_cos_theta(wo)));
+            * (1. - pow5(1. - 0.5 * abs_cos_theta(wo)))
+    }
 
+    fn specular(&self, wo: &na::Vector3<f32>, wi: &na::Vector3<f32>) -> Spectrum {
         let wh = wi + wo;
         if wh.is_zero() {
             return Spectrum::zero();
         }
 
         let wh = wh.normalize();
-        let specular = self.distribution.d(&wh)
+        self.distribution.d(&wh)
             / (4. * wi.dot(&wh).abs() * abs_cos_theta(wi).max(abs_cos_theta(wo)))
-            * self.schlick_fresnel(wi.dot(&wh));
+            * self.schlick_fresnel(wi.dot(&wh).abs())
+    }
+}
+
+fn pow5(v: f32) -> f32 {
+    (v * v) * (v * v) * v
+}
 
-        diffuse + specular
+impl<'a> BxDFInterface for FresnelBlend<'a> {
+    fn f(&self, wo: &na::Vector3<f32>, wi: &na::Vector3<f32>) -> Spectrum {
+        self.diffuse(wo, wi) + self.specular(wo, wi)
     }
 
     fn get_type(&self) -> BxDFType {
@@ -439,17 +757,19 @@ impl BxDFInterface for FresnelBlend {
         _sampled_type: &mut Option<BxDFType>,
     ) -> Spectrum {
         let mut u = *u;
-        if u[0] < 0.5 {
-            u[0] = (2. * u[0]).min(ONE_MINUS_EPSILON);
+        let p_specular = self.specular_probability;
+        if u[0] >= p_specular {
+            u[0] = ((u[0] - p_specular) / (1. - p_specular)).min(ONE_MINUS_EPSILON);
             *wi = cosine_sample_hemisphere(&u);
             if wo.z < 0. {
                 wi.z *= -1.;
             }
         } else {
-            u[0] = (2. * (u[0] - 0.5)).min(ONE_MINUS_EPSILON);
+            u[0] = (u[0] / p_specular).min(ONE_MINUS_EPSILON);
             let wh = self.distribution.sample_wh(wo, &u);
             *wi = reflect(wo, &wh);
             if !same_hemisphere(wo, wi) {
+                *pdf = 0.;
                 return Spectrum::zero();
             }
         }
@@ -463,8 +783,41 @@ impl BxDFInterface for FresnelBlend {
             return 0.;
         }
 
-        let wh = (wo + wi).normalize();
+        let wh = wo + wi;
+        if wh.is_zero() {
+            return 0.;
+        }
+        let wh = wh.normalize();
         let pdf_wh = self.distribution.pdf(wo, &wh);
-        0.5 * (abs_cos_theta(wi) * std::f32::consts::FRAC_1_PI + pdf_wh / (4. * wo.dot(&wh)))
+        let p_specular = self.specular_probability;
+        (1. - p_specular) * abs_cos_theta(wi) * std::f32::consts::FRAC_1_PI
+            + p_specular * pdf_wh / (4. * wo.dot(&wh).abs())
+    }
+
+    fn rho(
+        &self,
+        wo: &na::Vector3<f32>,
+        n_samples: usize,
+        samples: &[na::Point2<f32>],
+    ) -> Spectrum {
+        // the diffuse term is normalized so its hemispherical integral is closed form, only the
+        // glossy term needs sampling, and only from its own distribution
+        let diffuse =
+            self.rd * (Spectrum::new(1.) - self.rs) * (1. - pow5(1. - 0.5 * abs_cos_theta(wo)));
+
+        let mut specular = Spectrum::zero();
+        for u in samples.iter().take(n_samples) {
+            let wh = self.distribution.sample_wh(wo, u);
+            let wi = reflect(wo, &wh);
+            if !same_hemisphere(wo, &wi) {
+                continue;
+            }
+            let pdf = self.distribution.pdf(wo, &wh) / (4. * wo.dot(&wh).abs());
+            if pdf > 0. {
+                specular += self.specular(wo, &wi) * abs_cos_theta(&wi) / pdf;
+            }
+        }
+
+        diffuse + specular / n_samples.max(1) as f32
     }
 }
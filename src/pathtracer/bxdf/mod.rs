@@ -1,5 +1,9 @@
 pub mod fresnel;
+pub mod hair;
+pub mod layered;
 pub mod microfacet;
+pub mod rho;
+pub mod validation;
 
 use super::sampling::{
     cosine_sample_hemisphere, uniform_hemisphere_pdf, uniform_sample_hemisphere,
@@ -66,11 +70,11 @@ fn same_hemisphere(w: &na::Vector3<f32>, wp: &na::Vector3<f32>) -> bool {
     w.z * wp.z > 0.0
 }
 
-fn reflect(wo: &na::Vector3<f32>, n: &na::Vector3<f32>) -> na::Vector3<f32> {
+pub fn reflect(wo: &na::Vector3<f32>, n: &na::Vector3<f32>) -> na::Vector3<f32> {
     -wo + 2. * wo.dot(&n) * n
 }
 
-fn refract(
+pub fn refract(
     wi: &na::Vector3<f32>,
     n: &na::Vector3<f32>,
     eta: f32,
@@ -181,15 +185,21 @@ pub trait BxDFInterface {
 
 #[derive(Delegate)]
 #[delegate(BxDFInterface)]
-pub enum BxDF {
+pub enum BxDF<'a> {
     Lambertian(LambertianReflection),
+    LambertianTransmission(LambertianTransmission),
+    OrenNayar(OrenNayar),
     SpecularReflection(SpecularReflection),
     SpecularTransmission(SpecularTransmission),
     FresnelSpecular(FresnelSpecular),
-    MicrofacetReflection(microfacet::MicrofacetReflection),
-    MicrofacetTransmission(microfacet::MicrofacetTransmission),
-    FresnelBlend(microfacet::FresnelBlend),
+    MicrofacetReflection(microfacet::MicrofacetReflection<'a>),
+    MicrofacetTransmission(microfacet::MicrofacetTransmission<'a>),
+    FresnelBlend(microfacet::FresnelBlend<'a>),
     DisneyDiffuse(super::material::disney::DisneyDiffuse),
+    DisneySheen(super::material::disney::DisneySheen),
+    Layered(layered::LayeredBxDF<'a>),
+    Hair(hair::HairBSDF),
+    Scaled(ScaledBxDF<'a>),
 }
 
 pub struct LambertianReflection {
@@ -229,3 +239,178 @@ impl BxDFInterface for LambertianReflection {
         self.r
     }
 }
+
+/// diffuse scattering into the opposite hemisphere, for thin surfaces lit from behind
+pub struct LambertianTransmission {
+    t: Spectrum,
+}
+
+impl LambertianTransmission {
+    pub fn new(t: Spectrum) -> Self {
+        Self { t }
+    }
+}
+
+impl BxDFInterface for LambertianTransmission {
+    fn f(&self, _wo: &na::Vector3<f32>, _wi: &na::Vector3<f32>) -> Spectrum {
+        self.t * std::f32::consts::FRAC_1_PI
+    }
+
+    fn sample_f(
+        &self,
+        wo: &na::Vector3<f32>,
+        wi: &mut na::Vector3<f32>,
+        u: &na::Point2<f32>,
+        pdf: &mut f32,
+        _sampled_type: &mut Option<BxDFType>,
+    ) -> Spectrum {
+        *wi = cosine_sample_hemisphere(&u);
+        if wo.z > 0.0 {
+            wi.z *= -1.0;
+        }
+
+        *pdf = self.pdf(&wo, &wi);
+        self.f(&wo, &wi)
+    }
+
+    fn get_type(&self) -> BxDFType {
+        BxDFType::BSDF_TRANSMISSION | BxDFType::BSDF_DIFFUSE
+    }
+
+    fn rho(
+        &self,
+        _wo: &na::Vector3<f32>,
+        _n_samples: usize,
+        _samples: &[na::Point2<f32>],
+    ) -> Spectrum {
+        self.t
+    }
+
+    fn rho_no_wo(
+        &self,
+        _n_samples: usize,
+        _samples_1: &[na::Point2<f32>],
+        _samples_2: &[na::Point2<f32>],
+    ) -> Spectrum {
+        self.t
+    }
+
+    fn pdf(&self, wo: &na::Vector3<f32>, wi: &na::Vector3<f32>) -> f32 {
+        if !same_hemisphere(&wo, &wi) {
+            abs_cos_theta(&wi) * std::f32::consts::FRAC_1_PI
+        } else {
+            0.0
+        }
+    }
+}
+
+pub struct OrenNayar {
+    r: Spectrum,
+    a: f32,
+    b: f32,
+}
+
+impl OrenNayar {
+    /// `sigma` is the standard deviation of the microfacet orientation angle in degrees
+    pub fn new(r: Spectrum, sigma: f32) -> Self {
+        let sigma = sigma.to_radians();
+        let sigma_2 = sigma * sigma;
+        let a = 1.0 - (sigma_2 / (2.0 * (sigma_2 + 0.33)));
+        let b = 0.45 * sigma_2 / (sigma_2 + 0.09);
+        Self { r, a, b }
+    }
+}
+
+impl BxDFInterface for OrenNayar {
+    fn f(&self, wo: &na::Vector3<f32>, wi: &na::Vector3<f32>) -> Spectrum {
+        let sin_theta_i = sin_theta(&wi);
+        let sin_theta_o = sin_theta(&wo);
+
+        // compute cosine term of Oren-Nayar model
+        let mut max_cos = 0.0;
+        if sin_theta_i > 1e-4 && sin_theta_o > 1e-4 {
+            let sin_phi_i = sin_phi(&wi);
+            let cos_phi_i = cos_phi(&wi);
+            let sin_phi_o = sin_phi(&wo);
+            let cos_phi_o = cos_phi(&wo);
+            let d_cos = cos_phi_i * cos_phi_o + sin_phi_i * sin_phi_o;
+            max_cos = d_cos.max(0.0);
+        }
+
+        // compute sine and tangent terms of Oren-Nayar model
+        let (sin_alpha, tan_beta) = if abs_cos_theta(&wi) > abs_cos_theta(&wo) {
+            (sin_theta_o, sin_theta_i / abs_cos_theta(&wi))
+        } else {
+            (sin_theta_i, sin_theta_o / abs_cos_theta(&wo))
+        };
+
+        self.r * std::f32::consts::FRAC_1_PI * (self.a + self.b * max_cos * sin_alpha * tan_beta)
+    }
+
+    fn get_type(&self) -> BxDFType {
+        BxDFType::BSDF_REFLECTION | BxDFType::BSDF_DIFFUSE
+    }
+}
+
+/// weights a lobe by a spectrum, e.g. the blend amount of a mix material or a tint texture
+pub struct ScaledBxDF<'a> {
+    bxdf: &'a BxDF<'a>,
+    scale: Spectrum,
+}
+
+impl<'a> ScaledBxDF<'a> {
+    /// scaling an already scaled lobe folds the scales together, so nested mix materials don't
+    /// pile up wrappers
+    pub fn new(bxdf: &'a BxDF<'a>, scale: Spectrum) -> Self {
+        match bxdf {
+            BxDF::Scaled(scaled) => Self {
+                bxdf: scaled.bxdf,
+                scale: scaled.scale * scale,
+            },
+            bxdf => Self { bxdf, scale },
+        }
+    }
+}
+
+impl<'a> BxDFInterface for ScaledBxDF<'a> {
+    fn f(&self, wo: &na::Vector3<f32>, wi: &na::Vector3<f32>) -> Spectrum {
+        self.scale * self.bxdf.f(&wo, &wi)
+    }
+
+    fn sample_f(
+        &self,
+        wo: &na::Vector3<f32>,
+        wi: &mut na::Vector3<f32>,
+        u: &na::Point2<f32>,
+        pdf: &mut f32,
+        sampled_type: &mut Option<BxDFType>,
+    ) -> Spectrum {
+        self.scale * self.bxdf.sample_f(&wo, wi, &u, pdf, sampled_type)
+    }
+
+    fn rho(
+        &self,
+        wo: &na::Vector3<f32>,
+        n_samples: usize,
+        samples: &[na::Point2<f32>],
+    ) -> Spectrum {
+        self.scale * self.bxdf.rho(&wo, n_samples, &samples)
+    }
+
+    fn rho_no_wo(
+        &self,
+        n_samples: usize,
+        samples_1: &[na::Point2<f32>],
+        samples_2: &[na::Point2<f32>],
+    ) -> Spectrum {
+        self.scale * self.bxdf.rho_no_wo(n_samples, &samples_1, &samples_2)
+    }
+
+    fn get_type(&self) -> BxDFType {
+        self.bxdf.get_type()
+    }
+
+    fn pdf(&self, wo: &na::Vector3<f32>, wi: &na::Vector3<f32>) -> f32 {
+        self.bxdf.pdf(&wo, &wi)
+    }
+}
@@ -0,0 +1,116 @@
+use super::{
+    fresnel::{Fresnel, FresnelNoOp},
+    microfacet::{MicrofacetModel, MicrofacetReflection},
+    BxDFInterface,
+};
+use crate::common::{math::lerp, spectrum::Spectrum};
+use crate::pathtracer::arena::MemoryArena;
+
+const TABLE_SIZE: usize = 32;
+const SAMPLES_SQRT: usize = 32;
+
+lazy_static::lazy_static! {
+    static ref TROWBRIDGE_REITZ_TABLE: RhoTable = RhoTable::new(MicrofacetModel::TrowbridgeReitz);
+    static ref BECKMANN_TABLE: RhoTable = RhoTable::new(MicrofacetModel::Beckmann);
+}
+
+/// directional albedo `E(cos_theta_o, alpha)` of a microfacet reflection lobe with a fresnel of
+/// one, plus its cosine weighted average over the hemisphere `E_avg(alpha)`. the tables are
+/// integrated once with the lobe's own importance sampling and cached for the whole process
+pub struct RhoTable {
+    e: Vec<f32>,
+    e_avg: Vec<f32>,
+}
+
+fn table_value(i: usize) -> f32 {
+    (i as f32 / (TABLE_SIZE - 1) as f32).max(1e-3)
+}
+
+fn table_position(v: f32) -> (usize, f32) {
+    let x = v.clamp(0.0, 1.0) * (TABLE_SIZE - 1) as f32;
+    let i = (x.floor() as usize).min(TABLE_SIZE - 2);
+    (i, x - i as f32)
+}
+
+impl RhoTable {
+    fn new(model: MicrofacetModel) -> Self {
+        let samples: Vec<na::Point2<f32>> = (0..SAMPLES_SQRT * SAMPLES_SQRT)
+            .map(|i| {
+                na::Point2::new(
+                    ((i % SAMPLES_SQRT) as f32 + 0.5) / SAMPLES_SQRT as f32,
+                    ((i / SAMPLES_SQRT) as f32 + 0.5) / SAMPLES_SQRT as f32,
+                )
+            })
+            .collect();
+
+        let mut e = vec![0.0; TABLE_SIZE * TABLE_SIZE];
+        let mut e_avg = vec![0.0; TABLE_SIZE];
+        let arena = MemoryArena::new();
+        for alpha_idx in 0..TABLE_SIZE {
+            let alpha = table_value(alpha_idx);
+            let bxdf = MicrofacetReflection::new(
+                Spectrum::new(1.0),
+                model.distribution(&arena, alpha, alpha),
+                Fresnel::NoOp(FresnelNoOp {}),
+            );
+
+            for mu_idx in 0..TABLE_SIZE {
+                let mu = table_value(mu_idx);
+                let wo = na::Vector3::new((1.0 - mu * mu).sqrt(), 0.0, mu);
+
+                // the lobe's own rho would look the table up, so integrate explicitly
+                let mut sum = 0.0;
+                for u in &samples {
+                    let mut wi = na::Vector3::zeros();
+                    let mut pdf = 0.0;
+                    let f = bxdf.sample_f(&wo, &mut wi, u, &mut pdf, &mut None);
+                    if pdf > 0.0 {
+                        sum += f.r() * wi.z.abs() / pdf;
+                    }
+                }
+                e[alpha_idx * TABLE_SIZE + mu_idx] = (sum / samples.len() as f32).min(1.0);
+            }
+
+            // E_avg = 2 * integral of E(mu) mu over [0, 1], trapezoidal rule
+            let row = &e[alpha_idx * TABLE_SIZE..(alpha_idx + 1) * TABLE_SIZE];
+            let mut integral = 0.0;
+            for mu_idx in 0..TABLE_SIZE - 1 {
+                let (mu_0, mu_1) = (table_value(mu_idx), table_value(mu_idx + 1));
+                integral += 0.5 * (row[mu_idx] * mu_0 + row[mu_idx + 1] * mu_1) * (mu_1 - mu_0);
+            }
+            e_avg[alpha_idx] = (2.0 * integral).min(1.0);
+        }
+
+        Self { e, e_avg }
+    }
+
+    pub fn get(model: MicrofacetModel) -> &'static RhoTable {
+        match model {
+            MicrofacetModel::TrowbridgeReitz => &TROWBRIDGE_REITZ_TABLE,
+            MicrofacetModel::Beckmann => &BECKMANN_TABLE,
+        }
+    }
+
+    /// directional albedo for an outgoing direction with cosine `cos_theta`
+    pub fn e(&self, cos_theta: f32, alpha: f32) -> f32 {
+        let (mu_idx, mu_t) = table_position(cos_theta.abs());
+        let (alpha_idx, alpha_t) = table_position(alpha);
+        let at = |a: usize, m: usize| self.e[a * TABLE_SIZE + m];
+
+        lerp(
+            lerp(at(alpha_idx, mu_idx), at(alpha_idx, mu_idx + 1), mu_t),
+            lerp(
+                at(alpha_idx + 1, mu_idx),
+                at(alpha_idx + 1, mu_idx + 1),
+                mu_t,
+            ),
+            alpha_t,
+        )
+    }
+
+    /// hemispherical average of the directional albedo
+    pub fn e_avg(&self, alpha: f32) -> f32 {
+        let (alpha_idx, alpha_t) = table_position(alpha);
+        lerp(self.e_avg[alpha_idx], self.e_avg[alpha_idx + 1], alpha_t)
+    }
+}
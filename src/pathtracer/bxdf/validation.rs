@@ -0,0 +1,483 @@
+//! numerical checks for the lobes: energy conservation under a white furnace, and agreement of
+//! the directions drawn by `sample_f` with the density reported by `pdf` through a chi-square
+//! test. lobes with delta distributions can't be checked this way
+
+use super::{abs_cos_theta, BxDFInterface};
+use rand::Rng;
+
+const THETA_RES: usize = 10;
+const PHI_RES: usize = 2 * THETA_RES;
+// midpoint samples per cell side when integrating the pdf over a histogram cell
+const INTEGRATION_RES: usize = 8;
+// cells expected to receive fewer samples are pooled together, the statistic is unreliable
+// for small expected counts
+const MIN_EXPECTED_FREQUENCY: f64 = 5.0;
+
+fn sample_lobe<B, R>(
+    bxdf: &B,
+    wo: &na::Vector3<f32>,
+    rng: &mut R,
+) -> Option<(na::Vector3<f32>, f32, f32)>
+where
+    B: BxDFInterface + ?Sized,
+    R: Rng,
+{
+    let u = na::Point2::new(rng.gen::<f32>(), rng.gen::<f32>());
+    let mut wi = na::Vector3::zeros();
+    let mut pdf = 0.0;
+    let f = bxdf.sample_f(wo, &mut wi, &u, &mut pdf, &mut None);
+
+    if pdf > 0.0 && !f.is_black() {
+        Some((wi, pdf, f.max_component_value()))
+    } else {
+        None
+    }
+}
+
+/// albedo of the lobe for `wo` under uniform white illumination, estimated with the lobe's own
+/// sampling. energy conserving lobes stay at or below one in every channel
+pub fn white_furnace<B, R>(bxdf: &B, wo: &na::Vector3<f32>, n_samples: usize, rng: &mut R) -> f32
+where
+    B: BxDFInterface + ?Sized,
+    R: Rng,
+{
+    let mut sum = 0.0f64;
+    for _ in 0..n_samples {
+        if let Some((wi, pdf, f)) = sample_lobe(bxdf, wo, rng) {
+            sum += (f * abs_cos_theta(&wi) / pdf) as f64;
+        }
+    }
+
+    (sum / n_samples as f64) as f32
+}
+
+/// largest relative difference between the pdf `sample_f` reports for a direction and the one
+/// `pdf` evaluates for it
+pub fn pdf_mismatch<B, R>(bxdf: &B, wo: &na::Vector3<f32>, n_samples: usize, rng: &mut R) -> f32
+where
+    B: BxDFInterface + ?Sized,
+    R: Rng,
+{
+    let mut max_error = 0.0f32;
+    for _ in 0..n_samples {
+        if let Some((wi, pdf, _)) = sample_lobe(bxdf, wo, rng) {
+            let error = (bxdf.pdf(wo, &wi) - pdf).abs() / pdf;
+            max_error = max_error.max(error);
+        }
+    }
+
+    max_error
+}
+
+#[derive(Debug)]
+pub struct ChiSquareResult {
+    pub statistic: f64,
+    pub degrees_of_freedom: usize,
+    /// probability of a statistic at least this large if sampling matched the pdf
+    pub p_value: f64,
+}
+
+fn direction(theta: f64, phi: f64) -> na::Vector3<f32> {
+    na::Vector3::new(
+        (theta.sin() * phi.cos()) as f32,
+        (theta.sin() * phi.sin()) as f32,
+        theta.cos() as f32,
+    )
+}
+
+/// compares a histogram over the sphere of `n_samples` directions drawn by `sample_f` against
+/// the frequencies expected from integrating `pdf` over each cell
+pub fn chi_square_test<B, R>(
+    bxdf: &B,
+    wo: &na::Vector3<f32>,
+    n_samples: usize,
+    rng: &mut R,
+) -> ChiSquareResult
+where
+    B: BxDFInterface + ?Sized,
+    R: Rng,
+{
+    let pi = std::f64::consts::PI;
+    let mut observed = vec![0.0f64; THETA_RES * PHI_RES];
+    for _ in 0..n_samples {
+        if let Some((wi, _, _)) = sample_lobe(bxdf, wo, rng) {
+            let wi = wi.normalize();
+            let theta = (wi.z as f64).max(-1.0).min(1.0).acos();
+            let mut phi = (wi.y as f64).atan2(wi.x as f64);
+            if phi < 0.0 {
+                phi += 2.0 * pi;
+            }
+            let theta_idx = ((theta / pi * THETA_RES as f64) as usize).min(THETA_RES - 1);
+            let phi_idx = ((phi / (2.0 * pi) * PHI_RES as f64) as usize).min(PHI_RES - 1);
+            observed[theta_idx * PHI_RES + phi_idx] += 1.0;
+        }
+    }
+
+    let d_theta = pi / THETA_RES as f64;
+    let d_phi = 2.0 * pi / PHI_RES as f64;
+    let mut expected = vec![0.0f64; THETA_RES * PHI_RES];
+    for theta_idx in 0..THETA_RES {
+        for phi_idx in 0..PHI_RES {
+            let mut integral = 0.0;
+            for i in 0..INTEGRATION_RES {
+                for j in 0..INTEGRATION_RES {
+                    let theta =
+                        d_theta * (theta_idx as f64 + (i as f64 + 0.5) / INTEGRATION_RES as f64);
+                    let phi = d_phi * (phi_idx as f64 + (j as f64 + 0.5) / INTEGRATION_RES as f64);
+                    integral += bxdf.pdf(wo, &direction(theta, phi)) as f64 * theta.sin();
+                }
+            }
+            expected[theta_idx * PHI_RES + phi_idx] = integral * d_theta * d_phi
+                / (INTEGRATION_RES * INTEGRATION_RES) as f64
+                * n_samples as f64;
+        }
+    }
+
+    let mut cells: Vec<(f64, f64)> = expected.into_iter().zip(observed).collect();
+    cells.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+    let mut statistic = 0.0;
+    let mut degrees_of_freedom = 0;
+    let (mut pooled_expected, mut pooled_observed) = (0.0, 0.0);
+    for (expected, observed) in cells {
+        if expected == 0.0 {
+            if observed > 0.0 {
+                // samples where the pdf claims none can be drawn
+                return ChiSquareResult {
+                    statistic: std::f64::INFINITY,
+                    degrees_of_freedom: 0,
+                    p_value: 0.0,
+                };
+            }
+        } else if expected < MIN_EXPECTED_FREQUENCY
+            || (pooled_expected > 0.0 && pooled_expected < MIN_EXPECTED_FREQUENCY)
+        {
+            // cells are sorted, so the pool keeps growing until it is large enough
+            pooled_expected += expected;
+            pooled_observed += observed;
+        } else {
+            statistic += (observed - expected) * (observed - expected) / expected;
+            degrees_of_freedom += 1;
+        }
+    }
+    if pooled_expected > 0.0 {
+        statistic += (pooled_observed - pooled_expected) * (pooled_observed - pooled_expected)
+            / pooled_expected;
+        degrees_of_freedom += 1;
+    }
+    degrees_of_freedom = degrees_of_freedom.max(2) - 1;
+
+    ChiSquareResult {
+        statistic,
+        degrees_of_freedom,
+        p_value: gamma_q(degrees_of_freedom as f64 / 2.0, statistic / 2.0),
+    }
+}
+
+/// log of the gamma function, lanczos approximation
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let mut series = 1.000000000190015;
+    for (i, c) in COEFFICIENTS.iter().enumerate() {
+        series += c / (x + 1.0 + i as f64);
+    }
+
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+/// regularized upper incomplete gamma function Q(a, x)
+fn gamma_q(a: f64, x: f64) -> f64 {
+    const MAX_ITERATIONS: usize = 1000;
+    const EPSILON: f64 = 1e-12;
+    const FP_MIN: f64 = 1e-300;
+
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let prefactor = (-x + a * x.ln() - ln_gamma(a)).exp();
+
+    if x < a + 1.0 {
+        // series for the lower function P(a, x)
+        let mut ap = a;
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        for _ in 0..MAX_ITERATIONS {
+            ap += 1.0;
+            term *= x / ap;
+            sum += term;
+            if term.abs() < sum.abs() * EPSILON {
+                break;
+            }
+        }
+        1.0 - sum * prefactor
+    } else {
+        // continued fraction for Q(a, x), modified lentz
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / FP_MIN;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..MAX_ITERATIONS {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < FP_MIN {
+                d = FP_MIN;
+            }
+            c = b + an / c;
+            if c.abs() < FP_MIN {
+                c = FP_MIN;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < EPSILON {
+                break;
+            }
+        }
+        prefactor * h
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::spectrum::Spectrum;
+    use crate::pathtracer::{
+        arena::MemoryArena,
+        bxdf::{
+            fresnel::{Fresnel, FresnelNoOp},
+            hair::HairBSDF,
+            layered::LayeredBxDF,
+            microfacet::{
+                FresnelBlend, MicrofacetModel, MicrofacetReflection, MicrofacetTransmission,
+            },
+            BxDF, LambertianReflection, LambertianTransmission, OrenNayar, ScaledBxDF,
+        },
+        material::disney::{DisneyDiffuse, DisneySheen},
+        TransportMode,
+    };
+    use rand::SeedableRng;
+
+    const N_SAMPLES: usize = 100_000;
+    const SIGNIFICANCE: f64 = 0.01;
+
+    fn outgoing_directions() -> Vec<na::Vector3<f32>> {
+        [0.2f32, 0.6, 0.95]
+            .iter()
+            .map(|cos_theta| {
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                na::Vector3::new(sin_theta * 0.8, sin_theta * 0.6, *cos_theta)
+            })
+            .collect()
+    }
+
+    fn check_albedo(name: &str, bxdf: &BxDF, wo: &na::Vector3<f32>, rng: &mut impl Rng) {
+        let albedo = white_furnace(bxdf, wo, N_SAMPLES, rng);
+        assert!(
+            albedo <= 1.01,
+            "{} gains energy at {:?}: {}",
+            name,
+            wo,
+            albedo
+        );
+    }
+
+    fn check_lobes(bxdfs: &[(&str, BxDF)]) {
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(0);
+        let wos = outgoing_directions();
+        // sidak correction, keeps the chance of any false failure at the significance level
+        let n_tests = (bxdfs.len() * wos.len()) as f64;
+        let threshold = 1.0 - (1.0 - SIGNIFICANCE).powf(1.0 / n_tests);
+
+        for (name, bxdf) in bxdfs {
+            for wo in &wos {
+                check_albedo(name, bxdf, wo, &mut rng);
+
+                let mismatch = pdf_mismatch(bxdf, wo, 1000, &mut rng);
+                assert!(
+                    mismatch < 1e-3,
+                    "{} pdf mismatch at {:?}: {}",
+                    name,
+                    wo,
+                    mismatch
+                );
+
+                let result = chi_square_test(bxdf, wo, N_SAMPLES, &mut rng);
+                assert!(
+                    result.p_value > threshold,
+                    "{} sampling doesn't match its pdf at {:?}: {:?}",
+                    name,
+                    wo,
+                    result
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_gamma_q() {
+        // chi-square with 2 degrees of freedom has the survival function exp(-x / 2)
+        for x in &[0.1, 1.0, 4.0, 20.0] {
+            approx::assert_relative_eq!(gamma_q(1.0, x / 2.0), (-x / 2.0f64).exp(), epsilon = 1e-9);
+        }
+        approx::assert_relative_eq!(gamma_q(0.5, 0.0), 1.0);
+    }
+
+    #[test]
+    fn test_diffuse_lobes() {
+        check_lobes(&[
+            (
+                "lambertian",
+                BxDF::Lambertian(LambertianReflection::new(Spectrum::new(1.0))),
+            ),
+            (
+                "oren nayar",
+                BxDF::OrenNayar(OrenNayar::new(Spectrum::new(1.0), 20.0)),
+            ),
+            (
+                "disney diffuse",
+                BxDF::DisneyDiffuse(DisneyDiffuse::new(Spectrum::new(1.0))),
+            ),
+            (
+                "disney sheen",
+                BxDF::DisneySheen(DisneySheen::new(Spectrum::new(1.0))),
+            ),
+            (
+                "lambertian transmission",
+                BxDF::LambertianTransmission(LambertianTransmission::new(Spectrum::new(1.0))),
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_scaled_lobe() {
+        let lambertian = BxDF::Lambertian(LambertianReflection::new(Spectrum::new(1.0)));
+        let oren_nayar = BxDF::OrenNayar(OrenNayar::new(Spectrum::new(1.0), 20.0));
+        let scaled = BxDF::Scaled(ScaledBxDF::new(&oren_nayar, Spectrum::new(0.5)));
+        check_lobes(&[
+            (
+                "scaled lambertian",
+                BxDF::Scaled(ScaledBxDF::new(&lambertian, Spectrum::new(0.5))),
+            ),
+            (
+                "nested scaled oren nayar",
+                BxDF::Scaled(ScaledBxDF::new(&scaled, Spectrum::new(0.5))),
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_hair_lobe() {
+        // the hair frame runs along x, so the outgoing directions cover a range of inclinations
+        // to the fiber as well
+        check_lobes(&[
+            (
+                "hair",
+                BxDF::Hair(HairBSDF::new(0.3, 1.55, Spectrum::new(0.25), 0.3, 0.3, 2.0)),
+            ),
+            (
+                "rough hair",
+                BxDF::Hair(HairBSDF::new(-0.6, 1.55, Spectrum::new(0.5), 0.6, 0.6, 0.0)),
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_layered_lobe() {
+        // the coating reflects specularly and the walk reports the pdf of the path it took
+        // rather than the marginal `pdf`, so only energy conservation can be checked
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(0);
+        let base = [BxDF::Lambertian(LambertianReflection::new(Spectrum::new(
+            1.0,
+        )))];
+        let layered = BxDF::Layered(LayeredBxDF::new(
+            &base,
+            1.5,
+            0.01,
+            10,
+            1,
+            TransportMode::Radiance,
+        ));
+        for wo in &outgoing_directions() {
+            check_albedo("layered", &layered, wo, &mut rng);
+            check_albedo("layered from below", &layered, &-wo, &mut rng);
+        }
+    }
+
+    #[test]
+    fn test_microfacet_lobes() {
+        let arena = MemoryArena::new();
+        let mut bxdfs = Vec::new();
+        for (name, model) in &[
+            ("trowbridge reitz", MicrofacetModel::TrowbridgeReitz),
+            ("beckmann", MicrofacetModel::Beckmann),
+        ] {
+            bxdfs.push((
+                *name,
+                BxDF::MicrofacetReflection(MicrofacetReflection::new(
+                    Spectrum::new(1.0),
+                    model.distribution(&arena, 0.5, 0.3),
+                    Fresnel::NoOp(FresnelNoOp {}),
+                )),
+            ));
+        }
+        bxdfs.push((
+            "fresnel blend",
+            BxDF::FresnelBlend(FresnelBlend::new(
+                Spectrum::new(0.5),
+                Spectrum::new(0.04),
+                MicrofacetModel::TrowbridgeReitz.distribution(&arena, 0.4, 0.4),
+            )),
+        ));
+
+        check_lobes(&bxdfs);
+    }
+
+    #[test]
+    fn test_microfacet_transmission() {
+        let arena = MemoryArena::new();
+        let distribution = MicrofacetModel::TrowbridgeReitz.distribution(&arena, 0.3, 0.3);
+        // radiance gets scaled by the relative index squared, only importance is bounded by one
+        check_lobes(&[(
+            "microfacet transmission",
+            BxDF::MicrofacetTransmission(MicrofacetTransmission::new(
+                Spectrum::new(1.0),
+                distribution,
+                1.0,
+                1.5,
+                TransportMode::Importance,
+            )),
+        )]);
+
+        let radiance = MicrofacetTransmission::new(
+            Spectrum::new(1.0),
+            distribution,
+            1.0,
+            1.5,
+            TransportMode::Radiance,
+        );
+        let importance = MicrofacetTransmission::new(
+            Spectrum::new(1.0),
+            distribution,
+            1.0,
+            1.5,
+            TransportMode::Importance,
+        );
+        let wo = na::Vector3::new(0.3, 0.2, 0.8).normalize();
+        let wi = na::Vector3::new(-0.1, -0.3, -0.9).normalize();
+        for (wo, wi) in &[(wo, wi), (wi, wo)] {
+            let f = radiance.f(wo, wi).y();
+            assert!(f > 0.0);
+            approx::assert_relative_eq!(f, importance.f(wi, wo).y(), max_relative = 1e-4);
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use super::{
+    accelerator::{Accelerator, BVH},
+    primitive::{Primitive, SyncPrimitive},
+    RenderScene, SurfaceMediumInteraction,
+};
+use crate::common::ray::Ray;
+use std::{collections::HashSet, sync::Arc, time::Instant};
+
+/// geometry id of rays that hit nothing
+const INVALID_GEOMETRY_ID: u32 = std::u32::MAX;
+/// embree hits are traced again by the instance they're on a little past where embree found them,
+/// to shade them with the same interaction the native bvh gives
+const RETRACE_MARGIN: f32 = 1e-4;
+
+fn to_cgmath(v: &na::Vector3<f32>) -> cgmath::Vector3<f32> {
+    cgmath::Vector3::new(v.x, v.y, v.z)
+}
+
+/// the opaque instanced meshes of a scene copied out to world space and traced by embree, the
+/// primitives embree doesn't handle (masked meshes, emissive and displaced meshes, analytic
+/// shapes) are kept in a bvh of their own
+pub struct EmbreeAccelerator {
+    scene: embree_rs::CommittedScene<'static>,
+    /// the instance each embree geometry was copied from, by geometry id
+    instances: Vec<Arc<dyn SyncPrimitive>>,
+    rest: BVH,
+}
+
+// committed embree scenes can be traced from any number of threads
+unsafe impl Send for EmbreeAccelerator {}
+unsafe impl Sync for EmbreeAccelerator {}
+
+impl EmbreeAccelerator {
+    pub fn new(log: &slog::Logger, scene: &RenderScene) -> Self {
+        let log = log.new(o!("module" => "embree"));
+        let start = Instant::now();
+
+        // the committed scene borrows the device and the scene, which are kept for as long as
+        // the program runs
+        let device: &'static embree_rs::Device = Box::leak(Box::new(embree_rs::Device::new()));
+        let mut embree_scene = embree_rs::Scene::new(device);
+        let mut instances = vec![];
+        let mut traced = HashSet::new();
+
+        for instanced in &scene.instanced_meshes {
+            let mesh = &instanced.mesh;
+            // alpha masked hits would need testing against the mask
+            if mesh.alpha_mask.is_some() {
+                continue;
+            }
+
+            for (obj_to_world, primitive) in instanced
+                .instances
+                .iter()
+                .zip(&instanced.instance_primitives)
+            {
+                let mut triangles =
+                    embree_rs::TriangleMesh::unanimated(device, mesh.indices.len(), mesh.pos.len());
+                {
+                    let mut vertices = triangles.vertex_buffer.map();
+                    for (i, p) in mesh.pos.iter().enumerate() {
+                        let p = obj_to_world * p;
+                        vertices[i] = cgmath::Vector4::new(p.x, p.y, p.z, 0.0);
+                    }
+                    let mut indices = triangles.index_buffer.map();
+                    for (i, tri) in mesh.indices.iter().enumerate() {
+                        indices[i] = cgmath::Vector3::new(tri[0], tri[1], tri[2]);
+                    }
+                }
+                let mut geometry = embree_rs::Geometry::Triangle(triangles);
+                geometry.commit();
+                embree_scene.attach_geometry(geometry);
+
+                traced.insert(Arc::as_ptr(primitive) as *const u8);
+                instances.push(Arc::clone(primitive));
+            }
+        }
+
+        let rest = scene
+            .scene
+            .primitives()
+            .iter()
+            .filter(|primitive| !traced.contains(&(Arc::as_ptr(primitive) as *const u8)))
+            .cloned()
+            .collect::<Vec<_>>();
+        debug!(log, "primitives left to the bvh: {:?}", rest.len());
+        let rest = BVH::new(&log, rest, &4);
+
+        let embree_scene: &'static embree_rs::Scene<'static> = Box::leak(Box::new(embree_scene));
+        let scene = embree_scene.commit();
+        info!(
+            log,
+            "embree scene of {:?} instances took {:?} to build",
+            instances.len(),
+            start.elapsed()
+        );
+
+        Self {
+            scene,
+            instances,
+            rest,
+        }
+    }
+}
+
+impl Accelerator for EmbreeAccelerator {
+    fn intersect<'a>(&'a self, r: &mut Ray, isect: &mut SurfaceMediumInteraction<'a>) -> bool {
+        let mut ray_hit = embree_rs::RayHit::new(embree_rs::Ray::segment(
+            to_cgmath(&r.o.coords),
+            to_cgmath(&r.d),
+            0.0,
+            r.t_max,
+        ));
+        let mut ctx = embree_rs::IntersectContext::incoherent();
+        self.scene.intersect(&mut ctx, &mut ray_hit);
+
+        let mut hit = false;
+        if ray_hit.hit.geomID != INVALID_GEOMETRY_ID {
+            let instance = &self.instances[ray_hit.hit.geomID as usize];
+            let t_max = r.t_max;
+            r.t_max = t_max.min(ray_hit.ray.tfar * (1.0 + RETRACE_MARGIN));
+            hit = instance.intersect(r, isect);
+            if !hit {
+                // rounded differently than embree, the instance is still the closest
+                r.t_max = t_max;
+                hit = instance.intersect(r, isect);
+            }
+        }
+
+        Primitive::intersect(&self.rest, r, isect) || hit
+    }
+
+    fn intersect_p(&self, r: &Ray) -> bool {
+        let mut ray =
+            embree_rs::Ray::segment(to_cgmath(&r.o.coords), to_cgmath(&r.d), 0.0, r.t_max);
+        let mut ctx = embree_rs::IntersectContext::incoherent();
+        self.scene.occluded(&mut ctx, &mut ray);
+
+        // occluded rays come back with a negative infinite far distance
+        ray.tfar < 0.0 || Primitive::intersect_p(&self.rest, r)
+    }
+}
@@ -0,0 +1,190 @@
+use std::{fs::File, io::BufReader};
+
+use crate::common::{
+    math::{spherical_phi, spherical_theta, INV_2_PI},
+    spectrum::Spectrum,
+};
+
+/// equirect coordinates of the direction `w`, longitude along u and the polar angle from +z along
+/// v
+pub fn direction_to_equirect(w: &na::Vector3<f32>) -> na::Point2<f32> {
+    na::Point2::new(
+        spherical_phi(&w) * INV_2_PI,
+        spherical_theta(&w) * std::f32::consts::FRAC_1_PI,
+    )
+}
+
+pub fn equirect_to_direction(st: &na::Point2<f32>) -> na::Vector3<f32> {
+    let theta = st[1] * std::f32::consts::PI;
+    let phi = st[0] * 2.0 * std::f32::consts::PI;
+    let sin_theta = theta.sin();
+    na::Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), theta.cos())
+}
+
+/// cube face of the direction `w` and the coordinates on it, faces are ordered +x, -x, +y, -y,
+/// +z, -z and oriented like opengl cube maps
+fn direction_to_cube(w: &na::Vector3<f32>) -> (usize, na::Point2<f32>) {
+    let abs = w.abs();
+    let (face, ma, sc, tc) = if abs.x >= abs.y && abs.x >= abs.z {
+        if w.x > 0.0 {
+            (0, abs.x, -w.z, -w.y)
+        } else {
+            (1, abs.x, w.z, -w.y)
+        }
+    } else if abs.y >= abs.z {
+        if w.y > 0.0 {
+            (2, abs.y, w.x, w.z)
+        } else {
+            (3, abs.y, w.x, -w.z)
+        }
+    } else if w.z > 0.0 {
+        (4, abs.z, w.x, -w.y)
+    } else {
+        (5, abs.z, -w.x, -w.y)
+    };
+
+    (
+        face,
+        na::Point2::new(0.5 * (sc / ma + 1.0), 0.5 * (tc / ma + 1.0)),
+    )
+}
+
+/// unnormalized direction through `st` on `face`. coordinates outside of [0, 1] give directions
+/// through the neighbouring faces
+fn cube_to_direction(face: usize, st: &na::Point2<f32>) -> na::Vector3<f32> {
+    let (a, b) = (2.0 * st[0] - 1.0, 2.0 * st[1] - 1.0);
+    match face {
+        0 => na::Vector3::new(1.0, -b, -a),
+        1 => na::Vector3::new(-1.0, -b, a),
+        2 => na::Vector3::new(a, 1.0, b),
+        3 => na::Vector3::new(a, -1.0, -b),
+        4 => na::Vector3::new(a, -b, 1.0),
+        _ => na::Vector3::new(-a, -b, -1.0),
+    }
+}
+
+/// radiance arriving from every direction, bilinearly filtered. lookups next to a seam, the
+/// longitude wrap of equirect maps or the edges of cube faces, blend the texels across it
+pub enum EnvironmentTexture {
+    Equirect(na::DMatrix<Spectrum>),
+    /// square faces in the order of `direction_to_cube`
+    Cube(Vec<na::DMatrix<Spectrum>>),
+}
+
+impl EnvironmentTexture {
+    /// the same radiance `l` from everywhere
+    pub fn constant(l: Spectrum) -> Self {
+        EnvironmentTexture::Equirect(na::DMatrix::from_element(1, 1, l))
+    }
+
+    /// radiance texels of an hdr image scaled by `l`, see `from_texels` for the layouts
+    pub fn from_hdr(log: &slog::Logger, path: &str, l: Spectrum) -> anyhow::Result<Self> {
+        let file = File::open(path)?;
+        let file = BufReader::new(file);
+        let decoder = image::hdr::HdrDecoder::new(file)?;
+        let metadata = decoder.metadata();
+        let image = decoder.read_image_hdr()?;
+        let texels = na::DMatrix::from_fn(
+            metadata.height as usize,
+            metadata.width as usize,
+            |row, col| {
+                let rgb = &image[row * metadata.width as usize + col];
+
+                l * Spectrum::from_image_rgb_f32(rgb)
+            },
+        );
+
+        Ok(Self::from_texels(log, texels))
+    }
+
+    /// picks the layout from the aspect ratio of the image. a 6:1 strip holds the cube faces side
+    /// by side, a 4:3 horizontal cross unfolds them around +z as seen from the inside, anything
+    /// else is taken as equirect
+    pub fn from_texels(log: &slog::Logger, texels: na::DMatrix<Spectrum>) -> Self {
+        let (height, width) = texels.shape();
+        let face = |row: usize, col: usize, size: usize| {
+            texels
+                .slice((row * size, col * size), (size, size))
+                .into_owned()
+        };
+
+        if width == 6 * height {
+            debug!(log, "environment map is a cube map strip");
+            EnvironmentTexture::Cube((0..6).map(|col| face(0, col, height)).collect())
+        } else if 3 * width == 4 * height && width % 4 == 0 {
+            debug!(log, "environment map is a cube map cross");
+            let size = width / 4;
+            EnvironmentTexture::Cube(vec![
+                face(1, 2, size),
+                face(1, 0, size),
+                face(0, 1, size),
+                face(2, 1, size),
+                face(1, 1, size),
+                face(1, 3, size),
+            ])
+        } else {
+            EnvironmentTexture::Equirect(texels)
+        }
+    }
+
+    /// size of an equirect map with about the detail of this one
+    pub fn resolution(&self) -> (usize, usize) {
+        match self {
+            EnvironmentTexture::Equirect(texels) => (texels.ncols(), texels.nrows()),
+            EnvironmentTexture::Cube(faces) => (4 * faces[0].nrows(), 2 * faces[0].nrows()),
+        }
+    }
+
+    /// radiance arriving from the direction `w`
+    pub fn lookup(&self, w: &na::Vector3<f32>) -> Spectrum {
+        match self {
+            EnvironmentTexture::Equirect(texels) => {
+                let st = direction_to_equirect(&w);
+                let (height, width) = texels.shape();
+                bilinear(
+                    st[0] * width as f32 - 0.5,
+                    st[1] * height as f32 - 0.5,
+                    |x, y| {
+                        // longitude wraps around, the poles are clamped
+                        let col = x.rem_euclid(width as i64) as usize;
+                        let row = y.max(0).min(height as i64 - 1) as usize;
+                        texels[(row, col)]
+                    },
+                )
+            }
+            EnvironmentTexture::Cube(faces) => {
+                let (face, st) = direction_to_cube(&w);
+                let size = faces[face].nrows();
+                bilinear(
+                    st[0] * size as f32 - 0.5,
+                    st[1] * size as f32 - 0.5,
+                    |x, y| {
+                        // taps off the face are looked up on the face their direction hits
+                        let st = na::Point2::new(
+                            (x as f32 + 0.5) / size as f32,
+                            (y as f32 + 0.5) / size as f32,
+                        );
+                        let (face, st) = direction_to_cube(&cube_to_direction(face, &st));
+                        let texel = |x: f32| ((x * size as f32) as usize).min(size - 1);
+                        faces[face][(texel(st[1]), texel(st[0]))]
+                    },
+                )
+            }
+        }
+    }
+}
+
+/// blends the four texels around the continuous texel coordinates `x`, `y`
+fn bilinear<F>(x: f32, y: f32, texel: F) -> Spectrum
+where
+    F: Fn(i64, i64) -> Spectrum,
+{
+    let (x0, y0) = (x.floor(), y.floor());
+    let (dx, dy) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+
+    (1.0 - dx) * (1.0 - dy) * texel(x0, y0)
+        + dx * (1.0 - dy) * texel(x0 + 1, y0)
+        + (1.0 - dx) * dy * texel(x0, y0 + 1)
+        + dx * dy * texel(x0 + 1, y0 + 1)
+}
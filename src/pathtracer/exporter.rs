@@ -0,0 +1,141 @@
+use super::{
+    light::{Light, LightFlags},
+    material::Material,
+    primitive::Primitive,
+    shape::TriangleMesh,
+    RenderScene, SurfaceMediumInteraction,
+};
+use crate::common::{
+    exporter::{ExportedMaterial, ExportedMesh, SceneExport},
+    spectrum::Spectrum,
+};
+use std::{collections::HashMap, sync::Arc};
+
+fn exported_mesh(
+    mesh: &TriangleMesh,
+    material: Option<usize>,
+    instances: Vec<na::Projective3<f32>>,
+) -> ExportedMesh {
+    ExportedMesh {
+        indices: mesh
+            .indices
+            .iter()
+            .flat_map(|triangle| triangle.as_slice().to_vec())
+            .collect(),
+        pos: mesh.pos.clone(),
+        normal: mesh.normal.clone(),
+        uv: mesh.uv.clone(),
+        colors: mesh.colors.clone(),
+        material,
+        instances,
+    }
+}
+
+impl RenderScene {
+    /// the meshes of the scene with their materials for writing to gltf, instanced meshes
+    /// keeping their copies. textures are taken at the middle of the uv square. analytic shapes,
+    /// curves and the lights besides emissive meshes are left out
+    pub fn to_export(&self, log: &slog::Logger) -> SceneExport {
+        let si = SurfaceMediumInteraction {
+            uv: na::Point2::new(0.5, 0.5),
+            ..Default::default()
+        };
+
+        // world meshes are only tied to their material and light by their triangles
+        let mut mesh_materials: HashMap<
+            *const TriangleMesh,
+            (&Material, Option<(Spectrum, bool)>),
+        > = HashMap::new();
+        let mut triangles = 0;
+        for primitive in self.scene.primitives() {
+            if let Some(mesh) = primitive.get_mesh() {
+                triangles += 1;
+                let (_, emitted) = mesh_materials
+                    .entry(Arc::as_ptr(mesh))
+                    .or_insert((primitive.get_material(), None));
+                // textured emission only makes lights of the triangles it reaches
+                if emitted.is_none() {
+                    *emitted = primitive
+                        .get_area_light()
+                        .map(|area_light| area_light.emitted(&si));
+                }
+            }
+        }
+
+        let mut export = SceneExport::default();
+        let mut material_indices: HashMap<*const Material, usize> = HashMap::new();
+        let mut material_index =
+            |material: &Material,
+             emitted: Option<(Spectrum, bool)>,
+             materials: &mut Vec<ExportedMaterial>| {
+                match emitted {
+                    // emission belongs to the mesh, so emissive meshes get their own material
+                    Some((emission, two_sided)) => {
+                        let mut exported = material.to_exported(&si);
+                        exported.emission = emission;
+                        exported.double_sided |= two_sided;
+                        materials.push(exported);
+                        materials.len() - 1
+                    }
+                    None => *material_indices
+                        .entry(material as *const Material)
+                        .or_insert_with(|| {
+                            materials.push(material.to_exported(&si));
+                            materials.len() - 1
+                        }),
+                }
+            };
+
+        for mesh in &self.meshes {
+            let material = mesh_materials
+                .get(&Arc::as_ptr(mesh))
+                .map(|&(material, emitted)| {
+                    material_index(material, emitted, &mut export.materials)
+                });
+            export.meshes.push(exported_mesh(
+                mesh,
+                material,
+                vec![na::Projective3::identity()],
+            ));
+        }
+        for instanced in &self.instanced_meshes {
+            let material = material_index(instanced.material.as_ref(), None, &mut export.materials);
+            export.meshes.push(exported_mesh(
+                &instanced.mesh,
+                Some(material),
+                instanced.instances.clone(),
+            ));
+        }
+
+        let copies: usize = self
+            .instanced_meshes
+            .iter()
+            .map(|instanced| instanced.instances.len())
+            .sum();
+        let skipped = self
+            .scene
+            .primitives()
+            .len()
+            .saturating_sub(triangles + copies);
+        if skipped > 0 {
+            warn!(
+                log,
+                "{} analytic shapes and curves can't be exported to gltf, leaving them out",
+                skipped
+            );
+        }
+        let lights = self
+            .lights
+            .iter()
+            .filter(|light| !light.flags().contains(LightFlags::AREA))
+            .count();
+        if lights > 0 {
+            warn!(
+                log,
+                "only emissive meshes are exported, leaving out {} other lights", lights
+            );
+        }
+
+        export
+    }
+}
@@ -51,15 +51,14 @@ fn build_optix_as(
     stream: &cu::Stream,
 ) -> anyhow::Result<(optix::TraversableHandle, optix::Buffer)> {
     // create geometry and accels
-    let buf_vertex: Vec<optix::TypedBuffer<na::Point3<f32>, cu::DefaultDeviceAlloc>> = scene
-        .meshes
+    let meshes = scene.world_meshes();
+    let buf_vertex: Vec<optix::TypedBuffer<na::Point3<f32>, cu::DefaultDeviceAlloc>> = meshes
         .iter()
         .map(|m| optix::TypedBuffer::from_slice(&m.pos))
         .collect::<Result<Vec<_>, optix::Error>>()
         .context("allocating vertex buffer")?;
 
-    let buf_index: Vec<optix::TypedBuffer<na::Vector3<u32>, cu::DefaultDeviceAlloc>> = scene
-        .meshes
+    let buf_index: Vec<optix::TypedBuffer<na::Vector3<u32>, cu::DefaultDeviceAlloc>> = meshes
         .iter()
         .map(|m| optix::TypedBuffer::from_slice(&m.indices))
         .collect::<Result<Vec<_>, optix::Error>>()
@@ -0,0 +1,185 @@
+use crate::{
+    common::{
+        importer::{
+            description::{LightDescription, PlacedMesh},
+            library::MaterialLibrary,
+            AssetResolver,
+        },
+        spectrum::Spectrum,
+    },
+    pathtracer::{
+        accelerator,
+        importer::gltf::{default_environment, default_material},
+        light::{DirectionalLight, InfiniteAreaLight, Light, LightFlags, PointLight, SyncLight},
+        material::Material,
+        primitive::{GeometricPrimitive, SyncPrimitive},
+        shape::{displacement::DicingRate, swaps_handedness, triangles_from_mesh, TriangleMesh},
+        texture_cache::{TextureCache, DEFAULT_BUDGET},
+        Primitive, RenderScene,
+    },
+};
+use std::{collections::HashMap, sync::Arc};
+
+impl RenderScene {
+    pub fn from_description(
+        log: &slog::Logger,
+        meshes: &[PlacedMesh],
+        material_library: Option<&MaterialLibrary>,
+        default_lights: bool,
+        bvh_cache: &accelerator::BVHCache,
+    ) -> Self {
+        let log = log.new(o!("module" => "scene"));
+        let mut primitives: Vec<Arc<dyn SyncPrimitive>> = Vec::new();
+        let mut world_meshes: Vec<Arc<TriangleMesh>> = Vec::new();
+        let default = Arc::new(default_material(&log));
+        let mut materials: HashMap<&str, Arc<Material>> = HashMap::new();
+        // there's no camera yet to dice displaced meshes for
+        let dicing_rate = DicingRate::relative();
+
+        for placed in meshes {
+            let material = match &placed.material {
+                Some(name) => Arc::clone(materials.entry(name.as_str()).or_insert_with(|| {
+                    match material_library.and_then(|library| library.material(&log, name)) {
+                        Some(material) => Arc::new(material),
+                        None => {
+                            warn!(
+                                log,
+                                "no material {:?} in the library, using the default material", name
+                            );
+                            Arc::clone(&default)
+                        }
+                    }
+                })),
+                None => Arc::clone(&default),
+            };
+
+            let mesh = &placed.mesh;
+            let world_mesh = Arc::new(TriangleMesh::new_with_transform(
+                mesh.indices.clone(),
+                mesh.pos.clone(),
+                mesh.normal.clone(),
+                vec![],
+                mesh.uv.clone(),
+                mesh.colors.clone(),
+                None,
+                &placed.obj_to_world,
+            ));
+            let displacement = placed.material.as_ref().and_then(|name| {
+                material_library.and_then(|library| library.displacement(&log, name))
+            });
+            let world_mesh = match displacement {
+                Some(displacement) => displacement
+                    .displace(&log, &world_mesh, &dicing_rate)
+                    .map_or(world_mesh, Arc::new),
+                None => world_mesh,
+            };
+            world_meshes.push(Arc::clone(&world_mesh));
+
+            let transform_swaps_handedness = swaps_handedness(&placed.obj_to_world);
+            for triangle in triangles_from_mesh(&world_mesh, transform_swaps_handedness) {
+                primitives.push(Arc::new(GeometricPrimitive::new(
+                    triangle,
+                    Arc::clone(&material),
+                    None,
+                )) as Arc<dyn SyncPrimitive>);
+            }
+        }
+        debug!(log, "placed {} meshes", world_meshes.len());
+
+        let bvh = Box::new(accelerator::BVH::with_cache(
+            &log, primitives, &4, bvh_cache,
+        ));
+
+        let mut lights: Vec<Arc<dyn SyncLight>> = Vec::new();
+        let mut infinite_lights: Vec<Arc<dyn SyncLight>> = Vec::new();
+        if default_lights {
+            let mut env_light = default_environment(&log);
+            env_light.preprocess(&bvh.world_bound());
+            let env_light = Arc::new(env_light) as Arc<dyn SyncLight>;
+            lights.push(Arc::clone(&env_light));
+            infinite_lights.push(env_light);
+        }
+
+        Self {
+            scene: bvh,
+            lights,
+            infinite_lights,
+            meshes: world_meshes,
+            instanced_meshes: vec![],
+            texture_cache: Arc::new(TextureCache::new(&log, DEFAULT_BUDGET)),
+            accelerator: None,
+        }
+    }
+
+    /// adds the lights of a scene description, whose files are looked up by `resolver`
+    pub fn add_described_lights(
+        &mut self,
+        log: &slog::Logger,
+        lights: &[LightDescription],
+        resolver: &AssetResolver,
+    ) {
+        let world_bound = self.world_bound();
+        for light in lights {
+            let light: Arc<dyn SyncLight> = match light {
+                LightDescription::Environment {
+                    file,
+                    color,
+                    intensity,
+                    rotation,
+                } => {
+                    let file_path = file.as_ref().map(|file| resolver.resolve(file));
+                    // env light is z up by default, turned y up before turning about y
+                    let rotation = na::UnitQuaternion::from_axis_angle(
+                        &na::Vector3::y_axis(),
+                        rotation.to_radians(),
+                    ) * na::UnitQuaternion::from_euler_angles(
+                        -std::f32::consts::FRAC_PI_2,
+                        0.0,
+                        0.0,
+                    );
+                    let mut env_light = InfiniteAreaLight::new(
+                        log,
+                        na::convert(na::Isometry3::from_parts(
+                            na::Translation3::identity(),
+                            rotation,
+                        )),
+                        *intensity * Spectrum::from_slice_3(color, false),
+                        file_path
+                            .as_ref()
+                            .and_then(|path| path.to_str())
+                            .unwrap_or_default(),
+                    );
+                    env_light.preprocess(&world_bound);
+                    Arc::new(env_light)
+                }
+                LightDescription::Point {
+                    position,
+                    color,
+                    intensity,
+                } => Arc::new(PointLight::new(
+                    &(na::Projective3::identity()
+                        * na::Translation3::new(position[0], position[1], position[2])),
+                    *intensity * Spectrum::from_slice_3(color, false),
+                )),
+                LightDescription::Directional {
+                    direction,
+                    color,
+                    intensity,
+                } => {
+                    let mut directional_light = DirectionalLight::new(
+                        &na::Projective3::identity(),
+                        *intensity * Spectrum::from_slice_3(color, false),
+                        -glm::make_vec3(direction),
+                    );
+                    directional_light.preprocess(&world_bound);
+                    Arc::new(directional_light)
+                }
+            };
+
+            if light.flags().contains(LightFlags::INFINITE) {
+                self.infinite_lights.push(Arc::clone(&light));
+            }
+            self.lights.push(light);
+        }
+    }
+}
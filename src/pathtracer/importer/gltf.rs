@@ -1,29 +1,71 @@
 use crate::{
-    common::{importer::gltf::trans_from_gltf, spectrum::Spectrum, WrapMode},
+    common::{
+        importer::{
+            gltf::{animated_trans_from_gltf, NodeAnimations},
+            library::MaterialLibrary,
+        },
+        spectrum::Spectrum,
+        transform::AnimatedTransform,
+        Camera, Shutter, WrapMode,
+    },
     pathtracer::light::InfiniteAreaLight,
     pathtracer::{
         accelerator,
+        bxdf::microfacet::MicrofacetModel,
         light::{DiffuseAreaLight, DirectionalLight, LightFlags, PointLight, SyncLight},
         material::{
-            disney::DisneyMaterial, with_normal, GlassMaterial, Material, MatteMaterial,
-            MirrorMaterial,
+            disney::{Clearcoat, DisneyMaterial, Specular, ThinFilm},
+            translucent::TranslucentMaterial,
+            with_normal, GlassMaterial, Material, MatteMaterial, MirrorMaterial,
+        },
+        primitive::{GeometricPrimitive, SyncPrimitive, TransformedPrimitive},
+        shape::{
+            displacement::{DicingRate, Displacement, DEFAULT_MAX_EDGE_PIXELS},
+            triangles_from_mesh, Shape, ShapeInterface, TriangleMesh,
+        },
+        texture::{
+            ConstantTexture, ImageTexture, Mapping3D, NormalMap, RampTexture, ScaleTexture,
+            SyncTexture, UVMap, VertexColorTexture,
         },
-        primitive::{GeometricPrimitive, SyncPrimitive},
-        shape::{triangles_from_mesh, Triangle, TriangleMesh},
-        texture::{ConstantTexture, ImageTexture, NormalMap, SyncTexture, UVMap},
-        Primitive, RenderScene,
+        texture_cache::{Texel, TextureCache, DEFAULT_BUDGET},
+        MeshInstances, Primitive, RenderScene,
     },
 };
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 impl ImageTexture<f32> {}
 
+/// gltf base colors are multiplied by the vertex colors of the meshes that have them
+fn with_vertex_color(color: Box<dyn SyncTexture<Spectrum>>) -> Box<dyn SyncTexture<Spectrum>> {
+    Box::new(ScaleTexture::new(
+        Box::new(VertexColorTexture) as Box<dyn SyncTexture<Spectrum>>,
+        color,
+    ))
+}
+
 pub fn default_material(log: &slog::Logger) -> Material {
     let color_factor = Spectrum::new(1.0);
-    let color_texture =
-        Box::new(ConstantTexture::<Spectrum>::new(color_factor)) as Box<dyn SyncTexture<Spectrum>>;
+    let color_texture = with_vertex_color(Box::new(ConstantTexture::<Spectrum>::new(color_factor)));
+
+    Material::Matte(MatteMaterial::new(log, color_texture, None))
+}
 
-    Material::Matte(MatteMaterial::new(log, color_texture))
+/// the bundled environment map lighting scenes loaded with `--default_lights`
+pub fn default_environment(log: &slog::Logger) -> InfiniteAreaLight {
+    let hdr_map_path =
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("data/abandoned_tank_farm_04_1k.hdr");
+    let hdr_map_path = hdr_map_path.to_str().unwrap();
+    // env light is z up by default, our default coordinate is y up
+    InfiniteAreaLight::new(
+        &log,
+        na::convert(na::Isometry3::from_parts(
+            na::Translation3::identity(),
+            na::UnitQuaternion::from_euler_angles(-std::f32::consts::FRAC_PI_2, 0., 0.0),
+        )),
+        Spectrum::new(1.0),
+        hdr_map_path,
+    )
 }
 
 fn wrap_mode_from_gtlf(gltf_wrap: gltf::texture::WrappingMode) -> WrapMode {
@@ -34,141 +76,488 @@ fn wrap_mode_from_gtlf(gltf_wrap: gltf::texture::WrappingMode) -> WrapMode {
     }
 }
 
-pub fn color_texture_from_gltf(
-    log: &slog::Logger,
-    texture: &gltf::texture::Info,
-    factor: Spectrum,
-    images: &[gltf::image::Data],
-) -> Option<ImageTexture<Spectrum>> {
-    let image = &images[texture.texture().source().index()];
-    let sampler = &texture.texture().sampler();
-    assert_eq!(sampler.wrap_s(), sampler.wrap_t());
-    let wrap_mode = wrap_mode_from_gtlf(sampler.wrap_s());
-
-    match image.format {
-        gltf::image::Format::R8G8B8 => {
-            if let Some(image) =
-                image::RgbImage::from_raw(image.width, image.height, image.pixels.clone())
-            {
-                Some(ImageTexture::<Spectrum>::new(
-                    log,
-                    &image,
-                    factor,
-                    wrap_mode,
-                    UVMap::new(1.0, 1.0, 0.0, 0.0),
-                    true,
-                ))
-            } else {
-                None
-            }
+/// `KHR_texture_transform` of a raw texture info, the identity when it has none. a `texCoord`
+/// override is ignored, only the first uv set is imported
+fn uv_map_from_gltf(texture_info: &serde_json::Value) -> UVMap {
+    let transform = &texture_info["extensions"]["KHR_texture_transform"];
+    let pair = |name: &str, default: f32| {
+        let value = |i: usize| transform[name][i].as_f64().map_or(default, |v| v as f32);
+        na::Vector2::new(value(0), value(1))
+    };
+
+    UVMap::from_transform(
+        pair("scale", 1.0),
+        transform["rotation"].as_f64().unwrap_or(0.0) as f32,
+        pair("offset", 0.0),
+    )
+}
+
+/// boxes an image texture of a material, projected triplanar in world space when `projected`
+/// is set for meshes without uvs
+fn image_texture<T: Texel + 'static>(
+    texture: ImageTexture<T>,
+    projected: bool,
+) -> Box<dyn SyncTexture<T>> {
+    if projected {
+        Box::new(texture.into_triplanar(Mapping3D::new(na::Projective3::identity())))
+    } else {
+        Box::new(texture)
+    }
+}
+
+/// decoded gltf image as floats in [0, 1], `channels` per pixel in rgba order
+struct GltfPixels {
+    width: usize,
+    height: usize,
+    channels: usize,
+    values: Vec<f32>,
+}
+
+impl GltfPixels {
+    /// channels per pixel, whether they are in bgr order and whether they are 16 bit. the gltf
+    /// crate only decodes 8 and 16 bit images, float images never make it here
+    fn layout(format: gltf::image::Format) -> (usize, bool, bool) {
+        use gltf::image::Format;
+
+        match format {
+            Format::R8 => (1, false, false),
+            Format::R8G8 => (2, false, false),
+            Format::R8G8B8 => (3, false, false),
+            Format::R8G8B8A8 => (4, false, false),
+            Format::B8G8R8 => (3, true, false),
+            Format::B8G8R8A8 => (4, true, false),
+            Format::R16 => (1, false, true),
+            Format::R16G16 => (2, false, true),
+            Format::R16G16B16 => (3, false, true),
+            Format::R16G16B16A16 => (4, false, true),
         }
-        gltf::image::Format::R8G8B8A8 => {
-            if let Some(image) = image::RgbImage::from_raw(
-                image.width,
-                image.height,
-                image
-                    .pixels
-                    .iter()
-                    .enumerate()
-                    .filter(|&(i, _)| i % 4 != 3)
-                    .map(|(_, v)| *v)
-                    .collect(),
-            ) {
-                Some(ImageTexture::<Spectrum>::new(
-                    log,
-                    &image,
-                    factor,
-                    wrap_mode,
-                    UVMap::new(1.0, 1.0, 0.0, 0.0),
-                    true,
-                ))
-            } else {
-                None
+    }
+
+    /// whether the pixel data matches the image dimensions
+    fn fits(image: &gltf::image::Data) -> bool {
+        let (channels, _, wide) = Self::layout(image.format);
+        let bytes = if wide { 2 } else { 1 };
+        image.pixels.len() == (image.width * image.height) as usize * channels * bytes
+    }
+
+    fn has_alpha(image: &gltf::image::Data) -> bool {
+        match Self::layout(image.format).0 {
+            2 | 4 => true,
+            _ => false,
+        }
+    }
+
+    /// `None` when the pixel data doesn't match the image dimensions
+    fn new(image: &gltf::image::Data) -> Option<Self> {
+        let (channels, bgr, wide) = Self::layout(image.format);
+
+        let mut values: Vec<f32> = if wide {
+            image
+                .pixels
+                .chunks_exact(2)
+                .map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]]) as f32 / 65535.0)
+                .collect()
+        } else {
+            image.pixels.iter().map(|v| *v as f32 / 255.0).collect()
+        };
+        if values.len() != (image.width * image.height) as usize * channels {
+            return None;
+        }
+        if bgr {
+            for pixel in values.chunks_exact_mut(channels) {
+                pixel.swap(0, 2);
             }
         }
-        _ => {
+
+        Some(Self {
+            width: image.width as usize,
+            height: image.height as usize,
+            channels,
+            values,
+        })
+    }
+
+    fn pixel(&self, row: usize, col: usize) -> &[f32] {
+        let idx = (row * self.width + col) * self.channels;
+        &self.values[idx..idx + self.channels]
+    }
+
+    /// color of a pixel, grayscale images are spread over all three channels
+    fn rgb(&self, row: usize, col: usize) -> [f32; 3] {
+        let pixel = self.pixel(row, col);
+        if self.channels < 3 {
+            [pixel[0]; 3]
+        } else {
+            [pixel[0], pixel[1], pixel[2]]
+        }
+    }
+
+    /// `None` for images without an alpha channel
+    fn alpha(&self, row: usize, col: usize) -> Option<f32> {
+        match self.channels {
+            2 | 4 => Some(self.pixel(row, col)[self.channels - 1]),
+            _ => None,
+        }
+    }
+
+    fn texels<T, F>(&self, texel: F) -> na::DMatrix<T>
+    where
+        T: na::Scalar,
+        F: Fn(usize, usize) -> T,
+    {
+        na::DMatrix::from_fn(self.height, self.width, texel)
+    }
+}
+
+/// the images of a gltf file, their textures are paged in from `cache` and only decoded when
+/// first looked up
+pub struct GltfImages {
+    images: Vec<Arc<gltf::image::Data>>,
+    cache: Arc<TextureCache>,
+}
+
+impl GltfImages {
+    pub fn new(images: Vec<gltf::image::Data>, cache: &Arc<TextureCache>) -> Self {
+        Self {
+            images: images.into_iter().map(Arc::new).collect(),
+            cache: Arc::clone(cache),
+        }
+    }
+
+    /// the image of `texture`, `None` when its pixels don't match its size
+    fn image(
+        &self,
+        log: &slog::Logger,
+        texture: &gltf::texture::Texture,
+    ) -> Option<Arc<gltf::image::Data>> {
+        let image = &self.images[texture.source().index()];
+        if GltfPixels::fits(image) {
+            Some(Arc::clone(image))
+        } else {
             error!(
                 log,
-                "unsupported image format {:?} for color texture", image.format
+                "pixel data of {:?} image does not match its size {}x{}",
+                image.format,
+                image.width,
+                image.height
             );
             None
         }
     }
+
+    /// texture of the texels `texel` computes from the pixels of `texture`
+    fn texture<T, F>(
+        &self,
+        log: &slog::Logger,
+        texture: &gltf::texture::Texture,
+        mapping: UVMap,
+        texel: F,
+    ) -> Option<ImageTexture<T>>
+    where
+        T: Texel,
+        F: Fn(&GltfPixels, usize, usize) -> T + Send + 'static,
+    {
+        let image = self.image(log, texture)?;
+        Some(ImageTexture::from_cache(
+            log,
+            &self.cache,
+            move || {
+                // the size was checked on import
+                let pixels = GltfPixels::new(&image).unwrap();
+                pixels.texels(|row, col| texel(&pixels, row, col))
+            },
+            wrap_mode_from_sampler(&texture.sampler()),
+            mapping,
+        ))
+    }
+}
+
+fn wrap_mode_from_sampler(sampler: &gltf::texture::Sampler) -> WrapMode {
+    assert_eq!(sampler.wrap_s(), sampler.wrap_t());
+    wrap_mode_from_gtlf(sampler.wrap_s())
 }
 
+/// base color and emissive textures, their colors are srgb encoded
+pub fn color_texture_from_gltf(
+    log: &slog::Logger,
+    texture: &gltf::texture::Info,
+    factor: Spectrum,
+    mapping: UVMap,
+    images: &GltfImages,
+) -> Option<ImageTexture<Spectrum>> {
+    images.texture(log, &texture.texture(), mapping, move |pixels, row, col| {
+        factor * Spectrum::from_slice_3(&pixels.rgb(row, col), true)
+    })
+}
+
+/// metalness from the linear blue and roughness from the linear green channel
 pub fn metallic_roughness_texture_from_gltf(
     log: &slog::Logger,
     texture: &gltf::texture::Info,
     metallic_factor: f32,
     roughness_factor: f32,
-    images: &[gltf::image::Data],
+    mapping: UVMap,
+    images: &GltfImages,
 ) -> Option<(ImageTexture<f32>, ImageTexture<f32>)> {
-    let image = &images[texture.texture().source().index()];
-    let sampler = &texture.texture().sampler();
-    assert_eq!(sampler.wrap_s(), sampler.wrap_t());
-    let wrap_mode = wrap_mode_from_gtlf(sampler.wrap_s());
-    let metallic_image;
-    let roughness_image;
-    match image.format {
-        gltf::image::Format::R8G8B8 => {
-            metallic_image = image::GrayImage::from_raw(
-                image.width,
-                image.height,
-                image.pixels.iter().skip(2).step_by(3).map(|v| *v).collect(),
-            )
-            .unwrap();
-            roughness_image = image::GrayImage::from_raw(
-                image.width,
-                image.height,
-                image.pixels.iter().skip(1).step_by(3).map(|v| *v).collect(),
-            )
-            .unwrap();
-        }
-        gltf::image::Format::R8G8B8A8 => {
-            metallic_image = image::GrayImage::from_raw(
-                image.width,
-                image.height,
-                image.pixels.iter().skip(2).step_by(4).map(|v| *v).collect(),
-            )
-            .unwrap();
-            roughness_image = image::GrayImage::from_raw(
-                image.width,
-                image.height,
-                image.pixels.iter().skip(1).step_by(4).map(|v| *v).collect(),
-            )
-            .unwrap();
-        }
-        _ => {
-            error!(
-                log,
-                "unsupported image format {:?} for metallic roughness texture", image.format
-            );
-            return None;
-        }
+    let metallic = images.texture(log, &texture.texture(), mapping, move |pixels, row, col| {
+        metallic_factor * pixels.rgb(row, col)[2]
+    })?;
+    let roughness = images.texture(log, &texture.texture(), mapping, move |pixels, row, col| {
+        roughness_factor * pixels.rgb(row, col)[1]
+    })?;
+
+    Some((metallic, roughness))
+}
+
+/// tangent space normals from a linear rgb image, `scale` applies to x and y
+fn normal_map_from_gltf(
+    log: &slog::Logger,
+    texture: &gltf::material::NormalTexture,
+    mapping: UVMap,
+    images: &GltfImages,
+) -> Option<NormalMap> {
+    let scale = texture.scale();
+    images.texture(log, &texture.texture(), mapping, move |pixels, row, col| {
+        let [x, y, z] = pixels.rgb(row, col);
+        na::Vector3::new(
+            scale * (2.0 * x - 1.0),
+            scale * (2.0 * y - 1.0),
+            2.0 * z - 1.0,
+        )
+    })
+}
+
+/// KHR_materials_iridescence, the iridescence and thickness textures are not supported yet
+fn thin_film_from_gltf(log: &slog::Logger, extensions: &serde_json::Value) -> Option<ThinFilm> {
+    let iridescence = extensions.get("KHR_materials_iridescence")?;
+    let param = |name: &str, default: f32| {
+        iridescence
+            .get(name)
+            .and_then(|value| value.as_f64())
+            .map_or(default, |value| value as f32)
+    };
+
+    let factor = param("iridescenceFactor", 0.0);
+    if factor == 0.0 {
+        return None;
     }
-    Some((
-        ImageTexture::<f32>::new(
+    if iridescence.get("iridescenceTexture").is_some()
+        || iridescence.get("iridescenceThicknessTexture").is_some()
+    {
+        warn!(
             log,
-            &metallic_image,
-            metallic_factor,
-            wrap_mode,
-            UVMap::new(1.0, 1.0, 0.0, 0.0),
-        ),
-        ImageTexture::<f32>::new(
+            "iridescence textures not supported, using constant factor and maximum thickness"
+        );
+    }
+
+    Some(ThinFilm {
+        weight: Box::new(ConstantTexture::<f32>::new(factor)),
+        eta: Box::new(ConstantTexture::<f32>::new(param("iridescenceIor", 1.3))),
+        thickness: Box::new(ConstantTexture::<f32>::new(param(
+            "iridescenceThicknessMaximum",
+            400.0,
+        ))),
+    })
+}
+
+fn extension_float(extension: &serde_json::Value, name: &str, default: f32) -> f32 {
+    extension
+        .get(name)
+        .and_then(|value| value.as_f64())
+        .map_or(default, |value| value as f32)
+}
+
+fn extension_color(extension: &serde_json::Value, name: &str, default: f32) -> Spectrum {
+    extension
+        .get(name)
+        .and_then(|value| value.as_array())
+        .filter(|rgb| rgb.len() == 3)
+        .map_or(Spectrum::new(default), |rgb| {
+            let rgb: Vec<f32> = rgb
+                .iter()
+                .map(|v| v.as_f64().unwrap_or(default as f64) as f32)
+                .collect();
+            Spectrum::from_floats(rgb[0], rgb[1], rgb[2])
+        })
+}
+
+/// the material extensions below only read their factors so far
+fn warn_unsupported_textures(
+    log: &slog::Logger,
+    name: &str,
+    extension: &serde_json::Value,
+    textures: &[&str],
+) {
+    if textures
+        .iter()
+        .any(|texture| extension.get(texture).is_some())
+    {
+        warn!(
             log,
-            &roughness_image,
-            roughness_factor,
-            wrap_mode,
-            UVMap::new(1.0, 1.0, 0.0, 0.0),
-        ),
+            "{} textures not supported, using constant factors", name
+        );
+    }
+}
+
+/// KHR_materials_specular, scaling and tinting the dielectric reflection
+fn specular_from_gltf(log: &slog::Logger, extensions: &serde_json::Value) -> Option<Specular> {
+    let specular = extensions.get("KHR_materials_specular")?;
+    warn_unsupported_textures(
+        log,
+        "specular",
+        specular,
+        &["specularTexture", "specularColorTexture"],
+    );
+
+    Some(Specular {
+        weight: Box::new(ConstantTexture::<f32>::new(extension_float(
+            specular,
+            "specularFactor",
+            1.0,
+        ))),
+        color: Box::new(ConstantTexture::<Spectrum>::new(extension_color(
+            specular,
+            "specularColorFactor",
+            1.0,
+        ))),
+    })
+}
+
+/// KHR_materials_sheen, the sheen of the disney model has no roughness so
+/// `sheenRoughnessFactor` is ignored
+fn sheen_from_gltf(
+    log: &slog::Logger,
+    extensions: &serde_json::Value,
+) -> Option<Box<dyn SyncTexture<Spectrum>>> {
+    let sheen = extensions.get("KHR_materials_sheen")?;
+    let color = extension_color(sheen, "sheenColorFactor", 0.0);
+    if color.is_black() {
+        return None;
+    }
+    warn_unsupported_textures(
+        log,
+        "sheen",
+        sheen,
+        &["sheenColorTexture", "sheenRoughnessTexture"],
+    );
+
+    Some(Box::new(ConstantTexture::<Spectrum>::new(color)))
+}
+
+/// KHR_materials_clearcoat, the coat shares the normals of the base
+fn clearcoat_from_gltf(log: &slog::Logger, extensions: &serde_json::Value) -> Option<Clearcoat> {
+    let clearcoat = extensions.get("KHR_materials_clearcoat")?;
+    let factor = extension_float(clearcoat, "clearcoatFactor", 0.0);
+    if factor == 0.0 {
+        return None;
+    }
+    warn_unsupported_textures(
+        log,
+        "clearcoat",
+        clearcoat,
+        &[
+            "clearcoatTexture",
+            "clearcoatRoughnessTexture",
+            "clearcoatNormalTexture",
+        ],
+    );
+
+    Some(Clearcoat {
+        weight: Box::new(ConstantTexture::<f32>::new(factor)),
+        roughness: Box::new(ConstantTexture::<f32>::new(extension_float(
+            clearcoat,
+            "clearcoatRoughnessFactor",
+            0.0,
+        ))),
+    })
+}
+
+/// KHR_materials_volume, the absorption coefficient of the medium inside transmissive meshes.
+/// thin walled materials (zero thickness) and volumes without an attenuation distance don't
+/// absorb anything
+fn absorption_from_gltf(log: &slog::Logger, extensions: &serde_json::Value) -> Option<Spectrum> {
+    let volume = extensions.get("KHR_materials_volume")?;
+    let distance = extension_float(volume, "attenuationDistance", std::f32::INFINITY);
+    if extension_float(volume, "thicknessFactor", 0.0) <= 0.0 || !distance.is_finite() {
+        return None;
+    }
+    // the thickness only stands in for the geometry rasterizers can't trace through
+    warn_unsupported_textures(log, "volume", volume, &["thicknessTexture"]);
+
+    // light keeps the attenuation color after traveling the attenuation distance
+    let color = extension_color(volume, "attenuationColor", 1.0);
+    Some(Spectrum::from_floats(
+        -color.r().max(1e-4).ln() / distance,
+        -color.g().max(1e-4).ln() / distance,
+        -color.b().max(1e-4).ln() / distance,
     ))
 }
 
+// fraction of light transmitted by double sided alpha masked materials (usually foliage cards)
+// that don't specify any translucency
+const THIN_SURFACE_TRANSLUCENCY: f32 = 0.3;
+
+/// KHR_materials_diffuse_transmission or the older KHR_materials_translucency, returns the
+/// transmitted fraction and its tint. textures are not supported yet
+fn translucency_from_gltf(
+    log: &slog::Logger,
+    gltf_material: &gltf::Material,
+    extensions: &serde_json::Value,
+) -> Option<(f32, Spectrum)> {
+    let float_param = |extension: &serde_json::Value, name: &str| {
+        extension
+            .get(name)
+            .and_then(|value| value.as_f64())
+            .map_or(0.0, |value| value as f32)
+    };
+
+    if let Some(extension) = extensions.get("KHR_materials_diffuse_transmission") {
+        let color = extension
+            .get("diffuseTransmissionColorFactor")
+            .and_then(|value| value.as_array())
+            .map_or(Spectrum::new(1.0), |rgb| {
+                let rgb: Vec<f32> = rgb
+                    .iter()
+                    .map(|v| v.as_f64().unwrap_or(1.0) as f32)
+                    .collect();
+                Spectrum::from_floats(rgb[0], rgb[1], rgb[2])
+            });
+        if extension.get("diffuseTransmissionTexture").is_some()
+            || extension.get("diffuseTransmissionColorTexture").is_some()
+        {
+            warn!(log, "diffuse transmission textures not supported");
+        }
+        return Some((float_param(extension, "diffuseTransmissionFactor"), color));
+    }
+
+    if let Some(extension) = extensions.get("KHR_materials_translucency") {
+        if extension.get("translucencyTexture").is_some() {
+            warn!(log, "translucency textures not supported");
+        }
+        return Some((
+            float_param(extension, "translucencyFactor"),
+            Spectrum::new(1.0),
+        ));
+    }
+
+    if gltf_material.double_sided() && gltf_material.alpha_mode() == gltf::material::AlphaMode::Mask
+    {
+        return Some((THIN_SURFACE_TRANSLUCENCY, Spectrum::new(1.0)));
+    }
+
+    None
+}
+
+/// `json` is the raw material, for the extensions the gltf crate does not parse. `projected`
+/// materials are for meshes without uvs, their textures are projected triplanar
 pub fn material_from_gltf(
     log: &slog::Logger,
     gltf_material: &gltf::Material,
-    images: &[gltf::image::Data],
+    json: &serde_json::Value,
+    images: &GltfImages,
+    projected: bool,
 ) -> Material {
+    let extensions = &json["extensions"];
     let pbr = &gltf_material.pbr_metallic_roughness();
     let color_factor = Spectrum::from_slice_4(&pbr.base_color_factor(), true);
     let mut color_texture =
@@ -176,26 +565,29 @@ pub fn material_from_gltf(
     let mut normal_map = None;
 
     if let Some(info) = pbr.base_color_texture() {
-        if let Some(texture) = color_texture_from_gltf(&log, &info, color_factor, &images) {
-            color_texture = Box::new(texture) as Box<dyn SyncTexture<Spectrum>>;
+        if let Some(texture) = color_texture_from_gltf(
+            &log,
+            &info,
+            color_factor,
+            uv_map_from_gltf(&json["pbrMetallicRoughness"]["baseColorTexture"]),
+            &images,
+        ) {
+            color_texture = image_texture(texture, projected);
         }
     }
+    let color_texture = with_vertex_color(color_texture);
 
-    if let Some(texture) = gltf_material.normal_texture().as_ref() {
-        let image = &images[texture.texture().source().index()];
-        let sampler = &texture.texture().sampler();
-        assert_eq!(sampler.wrap_s(), sampler.wrap_t());
-        let wrap_mode = wrap_mode_from_gtlf(sampler.wrap_s());
-
-        let image =
-            image::RgbImage::from_raw(image.width, image.height, image.pixels.clone()).unwrap();
-        normal_map = Some(Box::new(NormalMap::new(
+    if projected && gltf_material.normal_texture().is_some() {
+        // tangent space needs uvs
+        warn!(log, "normal map ignored on mesh without uvs");
+    } else if let Some(texture) = gltf_material.normal_texture().as_ref() {
+        normal_map = normal_map_from_gltf(
             log,
-            &image,
-            na::Vector2::new(texture.scale(), texture.scale()),
-            wrap_mode,
-            UVMap::new(1.0, 1.0, 0.0, 0.0),
-        )) as Box<dyn SyncTexture<na::Vector3<f32>>>);
+            texture,
+            uv_map_from_gltf(&json["normalTexture"]),
+            images,
+        )
+        .map(|normal_map| Box::new(normal_map) as Box<dyn SyncTexture<na::Vector3<f32>>>);
     }
 
     let mut transmission_factor = 0.0;
@@ -216,16 +608,22 @@ pub fn material_from_gltf(
             as Box<dyn SyncTexture<Spectrum>>;
         let transmit_color = Box::new(ConstantTexture::<Spectrum>::new(Spectrum::new(1.0)))
             as Box<dyn SyncTexture<Spectrum>>;
-        return with_normal(
+        let mut glass = GlassMaterial::new(
             log,
-            Material::Glass(GlassMaterial::new(
-                log,
-                reflect_color,
-                transmit_color,
-                index,
-            )),
-            normal_map,
+            reflect_color,
+            transmit_color,
+            index,
+            // gltf roughness is perceptual, the microfacet alpha is its square
+            Some(Box::new(ConstantTexture::<f32>::new(
+                pbr.roughness_factor() * pbr.roughness_factor(),
+            ))),
+            false,
+            MicrofacetModel::TrowbridgeReitz,
         );
+        if let Some(absorption) = absorption_from_gltf(log, extensions) {
+            glass = glass.with_absorption(absorption);
+        }
+        return with_normal(log, Material::Glass(glass), normal_map);
     }
 
     // alpha below 1.0, use glass material
@@ -245,11 +643,32 @@ pub fn material_from_gltf(
                 reflect_color,
                 transmit_color,
                 index,
+                None,
+                false,
+                MicrofacetModel::TrowbridgeReitz,
             )),
             normal_map,
         );
     }
 
+    // thin diffuse surface scattering to both sides
+    if let Some((translucency, transmit_color)) =
+        translucency_from_gltf(log, gltf_material, extensions)
+    {
+        if translucency > 0.0 {
+            return with_normal(
+                log,
+                Material::Translucent(TranslucentMaterial::new(
+                    log,
+                    color_texture,
+                    Box::new(ConstantTexture::new(Spectrum::new(1.0 - translucency))),
+                    Box::new(ConstantTexture::new(translucency * transmit_color)),
+                )),
+                normal_map,
+            );
+        }
+    }
+
     // perfect metallic, use mirror
     if pbr.metallic_factor() == 1.0 && pbr.roughness_factor() == 0.0 {
         return Material::Mirror(MirrorMaterial::new(log));
@@ -268,60 +687,78 @@ pub fn material_from_gltf(
             &info,
             metallic_factor,
             roughness_factor,
+            uv_map_from_gltf(&json["pbrMetallicRoughness"]["metallicRoughnessTexture"]),
             &images,
         ) {
-            metallic_texture = Box::new(metallic) as Box<dyn SyncTexture<f32>>;
-            roughness_texture = Box::new(roughness) as Box<dyn SyncTexture<f32>>;
+            metallic_texture = image_texture(metallic, projected);
+            roughness_texture = image_texture(roughness, projected);
         }
     }
 
-    with_normal(
+    let mut disney = DisneyMaterial::new(
         log,
-        Material::Disney(DisneyMaterial::new(
-            log,
-            color_texture,
-            metallic_texture,
-            index,
-            roughness_texture,
-        )),
-        normal_map,
-    )
+        color_texture,
+        metallic_texture,
+        index,
+        roughness_texture,
+        thin_film_from_gltf(log, extensions),
+    );
+    if let Some(specular) = specular_from_gltf(log, extensions) {
+        disney = disney.with_specular(specular);
+    }
+    if let Some(sheen) = sheen_from_gltf(log, extensions) {
+        disney = disney.with_sheen(sheen);
+    }
+    if let Some(clearcoat) = clearcoat_from_gltf(log, extensions) {
+        disney = disney.with_clearcoat(clearcoat);
+    }
+
+    with_normal(log, Material::Disney(disney), normal_map)
 }
 
 pub fn shapes_from_gltf_prim(
     log: &slog::Logger,
     gltf_prim: &gltf::Primitive,
+    gltf_material: &gltf::Material,
+    material_json: &serde_json::Value,
     obj_to_world: &na::Projective3<f32>,
-    images: &[gltf::image::Data],
+    images: &GltfImages,
     buffers: &[gltf::buffer::Data],
+    displacement: Option<&Displacement>,
+    dicing_rate: &DicingRate,
     meshes: &mut Vec<Arc<TriangleMesh>>,
-) -> Vec<Arc<Triangle>> {
+) -> Vec<Arc<Shape>> {
+    let reader = gltf_prim.reader(|buffer| Some(&buffers[buffer.index()]));
+    let projected = reader.read_tex_coords(0).is_none();
     let mut alpha_mask_texture = None;
 
-    if let Some(texture) = gltf_prim
-        .material()
-        .pbr_metallic_roughness()
-        .base_color_texture()
-    {
-        let image = &images[texture.texture().source().index()];
-        let sampler = &texture.texture().sampler();
-        assert_eq!(sampler.wrap_s(), sampler.wrap_t());
-        let wrap_mode = wrap_mode_from_gtlf(sampler.wrap_s());
-
-        match gltf_prim.material().alpha_mode() {
+    if let Some(texture) = gltf_material.pbr_metallic_roughness().base_color_texture() {
+        match gltf_material.alpha_mode() {
             gltf::material::AlphaMode::Mask => {
-                assert!(image.format == gltf::image::Format::R8G8B8A8);
-                if let Some(image) = image::GrayImage::from_raw(
-                    image.width,
-                    image.height,
-                    image.pixels.iter().skip(3).step_by(4).map(|v| *v).collect(),
-                ) {
-                    alpha_mask_texture = Some(Arc::new(ImageTexture::<f32>::new(
-                        log,
-                        &image,
-                        1.0,
-                        wrap_mode,
-                        UVMap::new(1.0, 1.0, 0.0, 0.0),
+                // images without alpha are opaque and need no mask
+                let alpha = images
+                    .image(log, &texture.texture())
+                    .filter(|image| GltfPixels::has_alpha(image))
+                    .and_then(|_| {
+                        images.texture(
+                            log,
+                            &texture.texture(),
+                            uv_map_from_gltf(
+                                &material_json["pbrMetallicRoughness"]["baseColorTexture"],
+                            ),
+                            |pixels, row, col| pixels.alpha(row, col).unwrap(),
+                        )
+                    });
+                if let Some(alpha) = alpha {
+                    // hits and shadow rays pass wherever the alpha falls short of the cutoff. a
+                    // mask of the raw alpha would only cut where it's exactly zero, leaving the
+                    // filtered fringes of cutouts opaque
+                    let alpha_factor =
+                        gltf_material.pbr_metallic_roughness().base_color_factor()[3];
+                    let cutoff = gltf_material.alpha_cutoff() / alpha_factor.max(1e-4);
+                    alpha_mask_texture = Some(Arc::new(RampTexture::new(
+                        image_texture(alpha, projected),
+                        vec![(cutoff, 0.0), (cutoff, 1.0)],
                     )) as Arc<dyn SyncTexture<f32>>);
                 }
             }
@@ -329,8 +766,7 @@ pub fn shapes_from_gltf_prim(
         }
     }
 
-    let reader = gltf_prim.reader(|buffer| Some(&buffers[buffer.index()]));
-    let world_mesh = Arc::new(TriangleMesh::new_with_transform(
+    let world_mesh = TriangleMesh::new_with_transform(
         reader
             .read_indices()
             .unwrap()
@@ -368,7 +804,12 @@ pub fn shapes_from_gltf_prim(
         },
         alpha_mask_texture,
         &obj_to_world,
-    ));
+    );
+    let world_mesh = Arc::new(
+        displacement
+            .and_then(|displacement| displacement.displace(log, &world_mesh, dicing_rate))
+            .unwrap_or(world_mesh),
+    );
 
     meshes.push(world_mesh.clone());
 
@@ -377,48 +818,153 @@ pub fn shapes_from_gltf_prim(
 
 fn populate_scene(
     log: &slog::Logger,
-    parent_transform: &na::Projective3<f32>,
+    parent_transforms: &[na::Projective3<f32>; 2],
+    document: &gltf::Document,
     current_node: &gltf::Node,
+    animations: &NodeAnimations,
+    shutter: &Shutter,
     buffers: &[gltf::buffer::Data],
-    images: &[gltf::image::Data],
+    images: &GltfImages,
     materials: &Vec<Arc<Material>>,
+    projected_materials: &HashMap<usize, Arc<Material>>,
+    displacements: &HashMap<usize, Displacement>,
+    dicing_rate: &DicingRate,
+    materials_json: &[serde_json::Value],
+    variant_materials: &HashMap<(usize, usize), usize>,
+    instances: &mut HashMap<(usize, usize), usize>,
+    bvh_cache: &accelerator::BVHCache,
     primitives: &mut Vec<Arc<dyn SyncPrimitive>>,
     meshes: &mut Vec<Arc<TriangleMesh>>,
+    instanced_meshes: &mut Vec<MeshInstances>,
     lights: &mut Vec<Arc<dyn SyncLight>>,
     preprocess_lights: &mut Vec<Arc<dyn SyncLight>>,
 ) {
-    let current_transform = *parent_transform * trans_from_gltf(current_node.transform());
-    const EMISSIVE_SCALING_FACTOR: f32 = 10.0; // hack for gltf since it clamps emissive factor to 1.0
+    // where the node is when the shutter opens and closes
+    let current_transforms = [
+        parent_transforms[0] * animated_trans_from_gltf(current_node, animations, shutter.open),
+        parent_transforms[1] * animated_trans_from_gltf(current_node, animations, shutter.close),
+    ];
+    let current_transform = current_transforms[0];
     const SAMPLE_COUNT: usize = 10;
     const SAMPLE_STEP: f32 = 1.0 / SAMPLE_COUNT as f32;
     if let Some(gltf_mesh) = current_node.mesh() {
         for gltf_prim in gltf_mesh.primitives() {
-            let emissive_factor = gltf_prim.material().emissive_factor();
-            let emissive_factor = Spectrum::from_floats(
-                EMISSIVE_SCALING_FACTOR * emissive_factor[0],
-                EMISSIVE_SCALING_FACTOR * emissive_factor[0],
-                EMISSIVE_SCALING_FACTOR * emissive_factor[0],
+            let gltf_material = variant_materials
+                .get(&(gltf_mesh.index(), gltf_prim.index()))
+                .and_then(|&idx| document.materials().nth(idx))
+                .unwrap_or_else(|| gltf_prim.material());
+            let material_json = gltf_material
+                .index()
+                .and_then(|index| materials_json.get(index))
+                .unwrap_or(&serde_json::Value::Null);
+            let projected = gltf_prim.get(&gltf::Semantic::TexCoords(0)).is_none();
+            // KHR_materials_emissive_strength lifts the emissive factor above the 1.0 it's
+            // clamped to
+            let emissive_strength = extension_float(
+                &material_json["extensions"]["KHR_materials_emissive_strength"],
+                "emissiveStrength",
+                1.0,
             );
+            let emissive_factor =
+                emissive_strength * Spectrum::from_slice_3(&gltf_material.emissive_factor(), false);
             let mut ke = None;
 
             if !emissive_factor.is_black() {
                 ke = Some(Arc::new(ConstantTexture::<Spectrum>::new(emissive_factor))
                     as Arc<dyn SyncTexture<Spectrum>>);
-                if let Some(info) = gltf_prim.material().emissive_texture() {
-                    if let Some(texture) =
-                        color_texture_from_gltf(&log, &info, emissive_factor, &images)
-                    {
-                        ke = Some(Arc::new(texture) as Arc<dyn SyncTexture<Spectrum>>);
+                if let Some(info) = gltf_material.emissive_texture() {
+                    if let Some(texture) = color_texture_from_gltf(
+                        &log,
+                        &info,
+                        emissive_factor,
+                        uv_map_from_gltf(&material_json["emissiveTexture"]),
+                        &images,
+                    ) {
+                        ke = Some(Arc::from(image_texture(texture, projected)));
                     }
                 }
             }
 
+            let material = if let Some(idx) = gltf_material.index() {
+                match projected_materials.get(&idx) {
+                    Some(material) if projected => Arc::clone(material),
+                    _ => Arc::clone(&materials[idx + 1]), // default material on first idx
+                }
+            } else {
+                Arc::clone(&materials[0])
+            };
+            let displacement = gltf_material
+                .index()
+                .and_then(|index| displacements.get(&index));
+
+            // meshes get a bvh of their own in object space, built once however many nodes
+            // place them, and the scene bvh only holds their transforms. area lights need their
+            // shapes in world space and dicing depends on where the mesh is, so emissive and
+            // displaced meshes are copied out for each node, and stay where they are when the
+            // shutter opens
+            if emissive_factor.is_black() && displacement.is_none() {
+                let mesh_idx = *instances
+                    .entry((gltf_mesh.index(), gltf_prim.index()))
+                    .or_insert_with(|| {
+                        let mut object_meshes = vec![];
+                        let shape_primitives = shapes_from_gltf_prim(
+                            log,
+                            &gltf_prim,
+                            &gltf_material,
+                            &material_json,
+                            &na::Projective3::identity(),
+                            &images,
+                            buffers,
+                            None,
+                            dicing_rate,
+                            &mut object_meshes,
+                        )
+                        .into_iter()
+                        .map(|shape| {
+                            Arc::new(GeometricPrimitive::new(shape, Arc::clone(&material), None))
+                                as Arc<dyn SyncPrimitive>
+                        })
+                        .collect();
+                        instanced_meshes.push(MeshInstances {
+                            mesh: object_meshes.pop().unwrap(),
+                            blas: Arc::new(accelerator::BVH::with_cache(
+                                log,
+                                shape_primitives,
+                                &4,
+                                bvh_cache,
+                            )),
+                            material: Arc::clone(&material),
+                            instances: vec![],
+                            instance_primitives: vec![],
+                        });
+                        instanced_meshes.len() - 1
+                    });
+                let instanced = &mut instanced_meshes[mesh_idx];
+                let primitive: Arc<dyn SyncPrimitive> = Arc::new(TransformedPrimitive::new(
+                    Arc::clone(&instanced.blas),
+                    AnimatedTransform::new(
+                        &current_transforms[0],
+                        shutter.open,
+                        &current_transforms[1],
+                        shutter.close,
+                    ),
+                ));
+                instanced.instances.push(current_transform);
+                instanced.instance_primitives.push(Arc::clone(&primitive));
+                primitives.push(primitive);
+                continue;
+            }
+
             for shape in shapes_from_gltf_prim(
                 log,
                 &gltf_prim,
+                &gltf_material,
+                &material_json,
                 &current_transform,
                 &images,
                 buffers,
+                displacement,
+                dicing_rate,
                 meshes,
             ) {
                 let mut some_area_light = None;
@@ -442,8 +988,12 @@ fn populate_scene(
                     }
 
                     if has_emission {
-                        let area_light =
-                            Arc::new(DiffuseAreaLight::new(Arc::clone(ke), Arc::clone(&shape), 1));
+                        let area_light = Arc::new(DiffuseAreaLight::new(
+                            Arc::clone(ke),
+                            Arc::clone(&shape),
+                            1,
+                            gltf_material.double_sided(),
+                        ));
                         lights.push(Arc::clone(&area_light) as Arc<dyn SyncLight>);
                         some_area_light = Some(Arc::clone(&area_light));
                     }
@@ -451,11 +1001,7 @@ fn populate_scene(
 
                 primitives.push(Arc::new(GeometricPrimitive::new(
                     shape,
-                    if let Some(idx) = gltf_prim.material().index() {
-                        Arc::clone(&materials[idx + 1]) // default material on first idx
-                    } else {
-                        Arc::clone(&materials[0])
-                    },
+                    Arc::clone(&material),
                     some_area_light,
                 )) as Arc<dyn SyncPrimitive>)
             }
@@ -494,13 +1040,24 @@ fn populate_scene(
     for child in current_node.children() {
         populate_scene(
             &log,
-            &current_transform,
+            &current_transforms,
+            document,
             &child,
+            animations,
+            shutter,
             &buffers,
             &images,
             &materials,
+            projected_materials,
+            displacements,
+            dicing_rate,
+            materials_json,
+            variant_materials,
+            instances,
+            bvh_cache,
             primitives,
             meshes,
+            instanced_meshes,
             lights,
             preprocess_lights,
         );
@@ -512,56 +1069,129 @@ impl RenderScene {
         log: &slog::Logger,
         document: &gltf::Document,
         buffers: &[gltf::buffer::Data],
-        images: &[gltf::image::Data],
+        images: Vec<gltf::image::Data>,
+        materials_json: &[serde_json::Value],
+        variant_materials: &HashMap<(usize, usize), usize>,
+        material_library: Option<&MaterialLibrary>,
         default_lights: bool,
+        dicing_camera: Option<&Camera>,
+        animations: &NodeAnimations,
+        shutter: &Shutter,
+        bvh_cache: &accelerator::BVHCache,
     ) -> Self {
         let log = log.new(o!("module" => "scene"));
+        let texture_cache = Arc::new(TextureCache::new(&log, DEFAULT_BUDGET));
+        let images = GltfImages::new(images, &texture_cache);
         let mut primitives: Vec<Arc<dyn SyncPrimitive>> = Vec::new();
         let mut materials = vec![Arc::new(default_material(&log))];
         let mut lights: Vec<Arc<dyn SyncLight>> = Vec::new();
         let mut preprocess_lights: Vec<Arc<dyn SyncLight>> = Vec::new();
         let mut infinite_lights: Vec<Arc<dyn SyncLight>> = Vec::new();
         let mut meshes: Vec<Arc<TriangleMesh>> = Vec::new();
+        let mut instanced_meshes: Vec<MeshInstances> = Vec::new();
+        let mut instances = HashMap::new();
+        let mut library_materials = HashSet::new();
 
         for material in document.materials() {
-            materials.push(Arc::new(material_from_gltf(&log, &material, &images)));
+            let library_material = material_library.and_then(|library| {
+                material
+                    .name()
+                    .and_then(|name| library.material(&log, name))
+            });
+            if let Some(library_material) = library_material {
+                debug!(log, "material {:?} taken from library", material.name());
+                library_materials.extend(material.index());
+                materials.push(Arc::new(library_material));
+                continue;
+            }
+
+            let json = material
+                .index()
+                .and_then(|index| materials_json.get(index))
+                .unwrap_or(&serde_json::Value::Null);
+            materials.push(Arc::new(material_from_gltf(
+                &log, &material, &json, &images, false,
+            )));
+        }
+
+        // displaced meshes are diced for the camera of the scene, or relative to their size
+        // when there's none yet
+        let displacements: HashMap<usize, Displacement> = material_library
+            .map(|library| {
+                document
+                    .materials()
+                    .filter_map(|material| {
+                        let displacement = library.displacement(&log, material.name()?)?;
+                        Some((material.index()?, displacement))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let dicing_rate = dicing_camera.map_or_else(DicingRate::relative, |camera| {
+            DicingRate::from_camera(camera, DEFAULT_MAX_EDGE_PIXELS)
+        });
+
+        // textured materials of meshes without uvs get a variant with projected textures
+        let mut projected_materials = HashMap::new();
+        for gltf_mesh in document.meshes() {
+            for gltf_prim in gltf_mesh.primitives() {
+                if gltf_prim.get(&gltf::Semantic::TexCoords(0)).is_some() {
+                    continue;
+                }
+                let material = variant_materials
+                    .get(&(gltf_mesh.index(), gltf_prim.index()))
+                    .and_then(|&idx| document.materials().nth(idx))
+                    .unwrap_or_else(|| gltf_prim.material());
+                let index = match material.index() {
+                    Some(index) if !library_materials.contains(&index) => index,
+                    _ => continue,
+                };
+                projected_materials.entry(index).or_insert_with(|| {
+                    debug!(log, "projecting textures of material {:?}", material.name());
+                    let json = materials_json
+                        .get(index)
+                        .unwrap_or(&serde_json::Value::Null);
+                    Arc::new(material_from_gltf(&log, &material, json, &images, true))
+                });
+            }
         }
 
         for scene in document.scenes() {
             for node in scene.nodes() {
+                let identity = na::Projective3::identity();
                 populate_scene(
                     &log,
-                    &na::Projective3::identity(),
+                    &[identity, identity],
+                    &document,
                     &node,
+                    animations,
+                    shutter,
                     &buffers,
                     &images,
                     &materials,
+                    &projected_materials,
+                    &displacements,
+                    &dicing_rate,
+                    &materials_json,
+                    &variant_materials,
+                    &mut instances,
+                    bvh_cache,
                     &mut primitives,
                     &mut meshes,
+                    &mut instanced_meshes,
                     &mut lights,
                     &mut preprocess_lights,
                 );
             }
         }
 
-        let bvh = Box::new(accelerator::BVH::new(&log, primitives, &4));
+        let bvh = Box::new(accelerator::BVH::with_cache(
+            &log, primitives, &4, bvh_cache,
+        ));
         let world_bound = bvh.world_bound();
 
         if default_lights {
-            let hdr_map_path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
-                .join("data/abandoned_tank_farm_04_1k.hdr");
-            let hdr_map_path = hdr_map_path.to_str().unwrap();
-            // env light is z up by default, our default coordinate is y up
-            let default_env_light = Arc::new(InfiniteAreaLight::new(
-                &log,
-                na::convert(na::Isometry3::from_parts(
-                    na::Translation3::identity(),
-                    na::UnitQuaternion::from_euler_angles(-std::f32::consts::FRAC_PI_2, 0., 0.0),
-                )),
-                Spectrum::new(1.0),
-                hdr_map_path,
-            ));
-            preprocess_lights.push(default_env_light as Arc<dyn SyncLight>);
+            preprocess_lights.push(Arc::new(default_environment(&log)) as Arc<dyn SyncLight>);
         }
 
         // run preprocess for lights that need it
@@ -579,6 +1209,9 @@ impl RenderScene {
             lights,
             infinite_lights,
             meshes,
+            instanced_meshes,
+            texture_cache,
+            accelerator: None,
         }
     }
 }
@@ -0,0 +1,270 @@
+use crate::{
+    common::importer::library::{
+        FloatParam, FloatTextureDefinition, MaterialDefinition, MaterialLibrary, SpectrumParam,
+        SpectrumTextureDefinition,
+    },
+    common::{spectrum::Spectrum, WrapMode},
+    pathtracer::{
+        bxdf::microfacet::MicrofacetModel,
+        material::{
+            disney::DisneyMaterial,
+            metal::{metal_ior, MetalMaterial},
+            mix::MixMaterial,
+            shadow_catcher::ShadowCatcherMaterial,
+            substrate::SubstrateMaterial,
+            translucent::TranslucentMaterial,
+            with_rotation, GlassMaterial, Material, MatteMaterial, MirrorMaterial,
+        },
+        shape::displacement::Displacement,
+        texture::{
+            ConstantTexture, ImageTexture, MixTexture, RampTexture, ScaleTexture, SyncTexture,
+            UVMap, VertexColorTexture,
+        },
+    },
+};
+
+// mix materials reference each other by name, bail out of reference cycles
+const MAX_MIX_DEPTH: u32 = 16;
+
+fn distribution_from_name(name: &Option<String>) -> MicrofacetModel {
+    name.as_ref()
+        .and_then(|name| MicrofacetModel::from_name(name))
+        .unwrap_or_default()
+}
+
+impl MaterialLibrary {
+    fn spectrum_texture(
+        &self,
+        log: &slog::Logger,
+        param: &SpectrumParam,
+    ) -> Box<dyn SyncTexture<Spectrum>> {
+        match param {
+            SpectrumParam::Value(value) => Box::new(ConstantTexture::new(Spectrum::new(*value))),
+            SpectrumParam::Rgb(rgb) => {
+                Box::new(ConstantTexture::new(Spectrum::from_slice_3(rgb, false)))
+            }
+            SpectrumParam::Image(filename) => {
+                let image = image::open(self.resolve(filename)).unwrap();
+                Box::new(ImageTexture::<Spectrum>::new(
+                    log,
+                    &image.to_rgb(),
+                    Spectrum::new(1.0),
+                    WrapMode::Repeat,
+                    UVMap::new(1.0, 1.0, 0.0, 0.0),
+                    true,
+                ))
+            }
+            SpectrumParam::Texture(definition) => match definition.as_ref() {
+                SpectrumTextureDefinition::Scale { textures } => Box::new(ScaleTexture::new(
+                    self.spectrum_texture(log, &textures[0]),
+                    self.spectrum_texture(log, &textures[1]),
+                )),
+                SpectrumTextureDefinition::Mix { textures, amount } => Box::new(MixTexture::new(
+                    self.spectrum_texture(log, &textures[0]),
+                    self.spectrum_texture(log, &textures[1]),
+                    self.float_texture(log, amount),
+                )),
+                SpectrumTextureDefinition::Ramp { input, stops } => Box::new(RampTexture::new(
+                    self.float_texture(log, input),
+                    stops
+                        .iter()
+                        .map(|(position, rgb)| (*position, Spectrum::from_slice_3(rgb, false)))
+                        .collect(),
+                )),
+                SpectrumTextureDefinition::VertexColor => Box::new(VertexColorTexture),
+            },
+        }
+    }
+
+    fn float_texture(&self, log: &slog::Logger, param: &FloatParam) -> Box<dyn SyncTexture<f32>> {
+        match param {
+            FloatParam::Value(value) => Box::new(ConstantTexture::new(*value)),
+            FloatParam::Image(filename) => {
+                let image = image::open(self.resolve(filename)).unwrap();
+                Box::new(ImageTexture::<f32>::new(
+                    log,
+                    &image.to_luma(),
+                    1.0,
+                    WrapMode::Repeat,
+                    UVMap::new(1.0, 1.0, 0.0, 0.0),
+                ))
+            }
+            FloatParam::Texture(definition) => match definition.as_ref() {
+                FloatTextureDefinition::Scale { textures } => Box::new(ScaleTexture::new(
+                    self.float_texture(log, &textures[0]),
+                    self.float_texture(log, &textures[1]),
+                )),
+                FloatTextureDefinition::Mix { textures, amount } => Box::new(MixTexture::new(
+                    self.float_texture(log, &textures[0]),
+                    self.float_texture(log, &textures[1]),
+                    self.float_texture(log, amount),
+                )),
+                FloatTextureDefinition::Ramp { input, stops } => Box::new(RampTexture::new(
+                    self.float_texture(log, input),
+                    stops.clone(),
+                )),
+            },
+        }
+    }
+
+    /// displacement of the surfaces using the material `name`, if the library displaces them
+    pub fn displacement(&self, log: &slog::Logger, name: &str) -> Option<Displacement> {
+        let definition = self.displacements.get(name)?;
+        Some(Displacement::new(
+            self.float_texture(log, &definition.height),
+            definition.scale,
+        ))
+    }
+
+    /// builds the material defined under `name`, `None` if the library doesn't define it
+    pub fn material(&self, log: &slog::Logger, name: &str) -> Option<Material> {
+        self.material_with_depth(log, name, 0)
+    }
+
+    fn material_with_depth(&self, log: &slog::Logger, name: &str, depth: u32) -> Option<Material> {
+        let definition = self.materials.get(name)?;
+        let material = match definition {
+            MaterialDefinition::Matte { color, sigma } => Material::Matte(MatteMaterial::new(
+                log,
+                self.spectrum_texture(log, color),
+                sigma.as_ref().map(|sigma| self.float_texture(log, sigma)),
+            )),
+            MaterialDefinition::Mirror => Material::Mirror(MirrorMaterial::new(log)),
+            MaterialDefinition::Glass {
+                reflect,
+                transmit,
+                ior,
+                roughness,
+                distribution,
+            } => Material::Glass(GlassMaterial::new(
+                log,
+                self.spectrum_texture(log, reflect),
+                self.spectrum_texture(log, transmit),
+                self.float_texture(log, ior),
+                roughness
+                    .as_ref()
+                    .map(|roughness| self.float_texture(log, roughness)),
+                false,
+                distribution_from_name(distribution),
+            )),
+            MaterialDefinition::Metal {
+                preset,
+                eta,
+                k,
+                reflectance,
+                roughness,
+                roughness_u,
+                roughness_v,
+                rotation,
+                distribution,
+            } => {
+                let (eta, k): (
+                    Box<dyn SyncTexture<Spectrum>>,
+                    Box<dyn SyncTexture<Spectrum>>,
+                ) = match (preset, eta, k) {
+                    (_, Some(eta), Some(k)) => (
+                        self.spectrum_texture(log, eta),
+                        self.spectrum_texture(log, k),
+                    ),
+                    (Some(preset), _, _) => match metal_ior(preset) {
+                        Some((eta, k)) => (
+                            Box::new(ConstantTexture::new(eta)),
+                            Box::new(ConstantTexture::new(k)),
+                        ),
+                        None => {
+                            error!(log, "material {} uses unknown metal {}", name, preset);
+                            return None;
+                        }
+                    },
+                    _ => {
+                        error!(log, "material {} needs a preset or eta and k", name);
+                        return None;
+                    }
+                };
+                let metal = Material::Metal(MetalMaterial::new(
+                    log,
+                    eta,
+                    k,
+                    self.spectrum_texture(log, reflectance),
+                    Some(self.float_texture(log, roughness)),
+                    roughness_u.as_ref().map(|r| self.float_texture(log, r)),
+                    roughness_v.as_ref().map(|r| self.float_texture(log, r)),
+                    false,
+                    distribution_from_name(distribution),
+                ));
+                with_rotation(
+                    log,
+                    metal,
+                    rotation.as_ref().map(|r| self.float_texture(log, r)),
+                )
+            }
+            MaterialDefinition::Substrate {
+                diffuse,
+                specular,
+                roughness,
+                distribution,
+            } => Material::Substrate(SubstrateMaterial::new(
+                log,
+                self.spectrum_texture(log, diffuse),
+                self.spectrum_texture(log, specular),
+                self.float_texture(log, roughness),
+                self.float_texture(log, roughness),
+                false,
+                distribution_from_name(distribution),
+            )),
+            MaterialDefinition::Disney {
+                color,
+                metallic,
+                roughness,
+                ior,
+            } => Material::Disney(DisneyMaterial::new(
+                log,
+                self.spectrum_texture(log, color),
+                self.float_texture(log, metallic),
+                self.float_texture(log, ior),
+                self.float_texture(log, roughness),
+                None,
+            )),
+            MaterialDefinition::Translucent {
+                color,
+                reflect,
+                transmit,
+            } => Material::Translucent(TranslucentMaterial::new(
+                log,
+                self.spectrum_texture(log, color),
+                self.spectrum_texture(log, reflect),
+                self.spectrum_texture(log, transmit),
+            )),
+            MaterialDefinition::ShadowCatcher { color, reflect } => {
+                Material::ShadowCatcher(ShadowCatcherMaterial::new(
+                    log,
+                    self.spectrum_texture(log, color),
+                    self.float_texture(log, reflect),
+                ))
+            }
+            MaterialDefinition::Mix { materials, amount } => {
+                if depth >= MAX_MIX_DEPTH {
+                    error!(log, "material {} nests mix materials too deeply", name);
+                    return None;
+                }
+                let mut children = materials.iter().map(|child| {
+                    let material = self.material_with_depth(log, child, depth + 1);
+                    if material.is_none() {
+                        error!(log, "material {} mixes unknown material {}", name, child);
+                    }
+                    material
+                });
+                let m1 = children.next().unwrap()?;
+                let m2 = children.next().unwrap()?;
+                Material::Mix(MixMaterial::new(
+                    log,
+                    Box::new(m1),
+                    Box::new(m2),
+                    self.float_texture(log, amount),
+                ))
+            }
+        };
+
+        Some(material)
+    }
+}
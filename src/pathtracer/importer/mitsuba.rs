@@ -1,29 +1,64 @@
 use crate::{
+    common::importer::downsample_image,
     common::WrapMode,
-    common::{importer::mitsuba, spectrum::Spectrum},
+    common::{
+        importer::{library::MaterialLibrary, mitsuba, ply},
+        spectrum::Spectrum,
+        Camera,
+    },
     pathtracer::light::InfiniteAreaLight,
     pathtracer::light::Light,
     pathtracer::material::GlassMaterial,
-    pathtracer::texture::ImageTexture,
     pathtracer::texture::UVMap,
+    pathtracer::texture::{luma_texels, rgb_texels, FilterMode, ImageTexture},
     pathtracer::{
         accelerator,
-        light::{DiffuseAreaLight, SyncLight},
+        bxdf::microfacet::MicrofacetModel,
+        light::{DiffuseAreaLight, DirectionalLight, PointLight, SyncLight},
         material::{
-            metal::MetalMaterial, schlick_r0_from_eta, substrate::SubstrateMaterial, Material,
-            MatteMaterial, MirrorMaterial,
+            disney::DisneyMaterial,
+            hair::{HairAbsorption, HairMaterial},
+            layered::LayeredMaterial,
+            metal::{metal_ior, MetalMaterial},
+            mix::MixMaterial,
+            schlick_r0_from_eta,
+            substrate::SubstrateMaterial,
+            with_bump, Material, MatteMaterial, MirrorMaterial,
+        },
+        primitive::{CurvePrimitive, GeometricPrimitive, SyncPrimitive},
+        shape::{
+            curve::{curves_from_strand, CurveType},
+            cylinder::Cylinder,
+            disk::Disk,
+            displacement::{DicingRate, Displacement, DEFAULT_MAX_EDGE_PIXELS},
+            rectangle::Rectangle,
+            sphere::Sphere,
+            triangles_from_mesh, Shape, TriangleMesh,
         },
-        primitive::{GeometricPrimitive, SyncPrimitive},
-        shape::{triangles_from_mesh, TriangleMesh},
         texture::{CheckerTexture, ConstantTexture, SyncTexture},
         Primitive, RenderScene,
     },
 };
 use std::{collections::HashMap, sync::Arc};
 
+/// mitsuba's `filterType` of a bitmap, nearest lookups aren't supported and get filtered with ewa
+fn filter_from_mitsuba(log: &slog::Logger, string_params: &HashMap<String, String>) -> FilterMode {
+    match string_params.get("filterType").map(String::as_str) {
+        None | Some("ewa") => FilterMode::EWA,
+        Some("trilinear") => FilterMode::Trilinear,
+        Some(filter_type) => {
+            warn!(
+                log,
+                "unsupported filter type {} for bitmap, using ewa", filter_type
+            );
+            FilterMode::EWA
+        }
+    }
+}
+
 fn texture_from_mitsuba(
     log: &slog::Logger,
-    scene_path: &str,
+    scene: &mitsuba::Scene,
     texture: &mitsuba::Texture,
 ) -> Box<dyn SyncTexture<Spectrum>> {
     match texture {
@@ -42,38 +77,92 @@ fn texture_from_mitsuba(
             ),
         )),
         mitsuba::Texture::BitMap { string_params } => {
-            let file_path = std::path::Path::new(scene_path)
-                .parent()
-                .unwrap_or_else(|| std::path::Path::new(""))
-                .join(&string_params["filename"]);
-            let file_path = file_path.to_str().unwrap();
-            let image = image::open(file_path).unwrap();
-            match image {
-                image::DynamicImage::ImageRgb8(image) => Box::new(ImageTexture::<Spectrum>::new(
+            let file_path = scene.resolver.resolve(&string_params["filename"]);
+            let max_texture_size = scene.max_texture_size;
+            let loader_log = log.clone();
+            Box::new(
+                ImageTexture::<Spectrum>::from_cache(
                     log,
-                    &image,
-                    Spectrum::new(1.),
+                    &scene.texture_cache,
+                    move || {
+                        let image = downsample_image(
+                            &loader_log,
+                            image::open(file_path).unwrap(),
+                            max_texture_size,
+                        );
+                        match image {
+                            image::DynamicImage::ImageRgb8(image) => {
+                                rgb_texels(&image, Spectrum::new(1.), true)
+                            }
+                            _ => {
+                                panic!("unsupported image format for texture");
+                            }
+                        }
+                    },
                     WrapMode::Repeat,
                     // TODO: verify that this -1 on the v is actually a feature of mitsuba and not an error on pathtracer-rs
                     UVMap::new(1., -1., 0., 0.),
-                    true,
-                )),
-                _ => {
-                    panic!("unsupported image format for texture");
-                }
-            }
+                )
+                .with_filter(filter_from_mitsuba(log, string_params)),
+            )
+        }
+    }
+}
+
+fn float_texture_from_mitsuba(
+    log: &slog::Logger,
+    scene: &mitsuba::Scene,
+    texture: &mitsuba::Texture,
+    scale: f32,
+) -> Box<dyn SyncTexture<f32>> {
+    match texture {
+        mitsuba::Texture::Checkerboard {
+            rgb_params,
+            float_params,
+        } => Box::new(CheckerTexture::new(
+            log,
+            scale * Spectrum::from_slice_3(&rgb_params["color0"], false).y(),
+            scale * Spectrum::from_slice_3(&rgb_params["color1"], false).y(),
+            UVMap::new(
+                float_params["uscale"],
+                float_params["vscale"],
+                float_params["uoffset"],
+                float_params["voffset"],
+            ),
+        )),
+        mitsuba::Texture::BitMap { string_params } => {
+            let file_path = scene.resolver.resolve(&string_params["filename"]);
+            let max_texture_size = scene.max_texture_size;
+            let loader_log = log.clone();
+            Box::new(
+                ImageTexture::<f32>::from_cache(
+                    log,
+                    &scene.texture_cache,
+                    move || {
+                        let image = downsample_image(
+                            &loader_log,
+                            image::open(file_path).unwrap(),
+                            max_texture_size,
+                        );
+                        luma_texels(&image.to_luma(), scale)
+                    },
+                    WrapMode::Repeat,
+                    UVMap::new(1., -1., 0., 0.),
+                )
+                .with_filter(filter_from_mitsuba(log, string_params)),
+            )
         }
     }
 }
 
 fn texture_with_defaults(
     log: &slog::Logger,
-    scene_path: &str,
+    scene: &mitsuba::Scene,
     texture: Option<&mitsuba::Texture>,
     rgb: Option<&[f32; 3]>,
 ) -> Box<dyn SyncTexture<Spectrum>> {
     if let Some(texture) = texture {
-        texture_from_mitsuba(log, scene_path, texture)
+        texture_from_mitsuba(log, scene, texture)
     } else if let Some(rgb) = rgb {
         Box::new(ConstantTexture::new(Spectrum::from_slice_3(rgb, false)))
     } else {
@@ -81,34 +170,72 @@ fn texture_with_defaults(
     }
 }
 
-fn material_from_bsdf(log: &slog::Logger, scene_path: &str, bsdf: &mitsuba::BSDF) -> Material {
+/// mitsuba defaults to beckmann for its rough bsdfs
+fn distribution_from_bsdf(log: &slog::Logger, bsdf: &mitsuba::Material) -> MicrofacetModel {
+    match bsdf.material.as_ref() {
+        Some(param) if param.name == "distribution" => MicrofacetModel::from_name(&param.value)
+            .unwrap_or_else(|| {
+                warn!(log, "unsupported distribution {}, using ggx", param.value);
+                MicrofacetModel::TrowbridgeReitz
+            }),
+        _ => MicrofacetModel::Beckmann,
+    }
+}
+
+/// explicit eta and k take precedence over a named metal preset, mitsuba falls back to copper
+fn conductor_ior(bsdf: &mitsuba::Material) -> (Spectrum, Spectrum) {
+    if let (Some(eta), Some(k)) = (bsdf.rgb_params.get("eta"), bsdf.rgb_params.get("k")) {
+        return (
+            Spectrum::from_slice_3(eta, false),
+            Spectrum::from_slice_3(k, false),
+        );
+    }
+    let name = match bsdf.material.as_ref() {
+        Some(param) if param.name == "material" => param.value.as_str(),
+        _ => "Cu",
+    };
+    metal_ior(name).unwrap_or_else(|| panic!("unsupported conductor material {}", name))
+}
+
+fn material_from_bsdf(
+    log: &slog::Logger,
+    scene: &mitsuba::Scene,
+    bsdf: &mitsuba::BSDF,
+) -> Material {
     match bsdf {
-        mitsuba::BSDF::TwoSided(bsdf) => material_from_bsdf(&log, scene_path, &bsdf.bsdf),
+        mitsuba::BSDF::TwoSided(bsdf) => material_from_bsdf(&log, scene, &bsdf.bsdf),
         mitsuba::BSDF::Diffuse(bsdf) => Material::Matte(MatteMaterial::new(
             &log,
-            texture_with_defaults(log, scene_path, bsdf.texture.as_ref(), Some(&bsdf.rgb)),
+            texture_with_defaults(log, scene, bsdf.texture.as_ref(), Some(&bsdf.rgb)),
+            None,
         )),
-        mitsuba::BSDF::Conductor(bsdf) => {
-            if let Some(material) = bsdf.material.as_ref() {
-                if material.value == "none" {
-                    Material::Mirror(MirrorMaterial::new(log))
-                } else {
-                    panic!("other material values not supported yet!");
-                }
-            } else {
+        mitsuba::BSDF::RoughDiffuse(bsdf) => Material::Matte(MatteMaterial::new(
+            &log,
+            texture_with_defaults(
+                log,
+                scene,
+                bsdf.texture.as_ref(),
+                bsdf.rgb_params.get("reflectance"),
+            ),
+            // mitsuba's alpha is the rms slope, convert to the angle deviation in degrees
+            Some(Box::new(ConstantTexture::new(
+                (bsdf.float_params.get("alpha").unwrap_or(&0.2) * std::f32::consts::FRAC_1_SQRT_2)
+                    .to_degrees(),
+            ))),
+        )),
+        mitsuba::BSDF::Conductor(bsdf) => match bsdf.material.as_ref() {
+            Some(material) if material.value == "none" => {
+                Material::Mirror(MirrorMaterial::new(log))
+            }
+            _ => {
+                let (eta, k) = conductor_ior(bsdf);
                 Material::Metal(MetalMaterial::new(
                     &log,
-                    Box::new(ConstantTexture::new(Spectrum::from_slice_3(
-                        &bsdf.rgb_params["eta"],
-                        false,
-                    ))),
-                    Box::new(ConstantTexture::new(Spectrum::from_slice_3(
-                        &bsdf.rgb_params["k"],
-                        false,
-                    ))),
+                    Box::new(ConstantTexture::new(eta)),
+                    Box::new(ConstantTexture::new(k)),
                     texture_with_defaults(
                         log,
-                        scene_path,
+                        scene,
                         bsdf.texture.as_ref(),
                         bsdf.rgb_params.get("specular_reflectance"),
                     ),
@@ -116,42 +243,93 @@ fn material_from_bsdf(log: &slog::Logger, scene_path: &str, bsdf: &mitsuba::BSDF
                     None,
                     None,
                     false,
+                    MicrofacetModel::default(),
                 ))
             }
+        },
+        mitsuba::BSDF::RoughConductor(bsdf) => {
+            let (eta, k) = conductor_ior(bsdf);
+            // alpha_u and alpha_v make the lobe anisotropic, mitsuba's default alpha is 0.1
+            let alpha = |name: &str| -> Option<Box<dyn SyncTexture<f32>>> {
+                bsdf.float_params.get(name).map(|alpha| {
+                    Box::new(ConstantTexture::new(*alpha)) as Box<dyn SyncTexture<f32>>
+                })
+            };
+            Material::Metal(MetalMaterial::new(
+                &log,
+                Box::new(ConstantTexture::new(eta)),
+                Box::new(ConstantTexture::new(k)),
+                texture_with_defaults(
+                    log,
+                    scene,
+                    bsdf.texture.as_ref(),
+                    bsdf.rgb_params.get("specular_reflectance"),
+                ),
+                Some(alpha("alpha").unwrap_or_else(|| Box::new(ConstantTexture::new(0.1)))),
+                alpha("alpha_u"),
+                alpha("alpha_v"),
+                false,
+                distribution_from_bsdf(log, bsdf),
+            ))
         }
-        mitsuba::BSDF::RoughConductor(bsdf) => Material::Metal(MetalMaterial::new(
+        mitsuba::BSDF::Dielectric(bsdf) => Material::Glass(GlassMaterial::new(
+            &log,
+            Box::new(ConstantTexture::new(Spectrum::new(1.0))),
+            Box::new(ConstantTexture::new(Spectrum::new(1.0))),
+            Box::new(ConstantTexture::new(bsdf.float_params["int_ior"])),
+            None,
+            false,
+            MicrofacetModel::default(),
+        )),
+        // thin sheets refract twice, leaving rays unbent, which glass matching the outside does
+        mitsuba::BSDF::ThinDielectric(bsdf) => Material::Glass(GlassMaterial::new(
             &log,
             Box::new(ConstantTexture::new(Spectrum::from_slice_3(
-                &bsdf.rgb_params["eta"],
+                bsdf.rgb_params
+                    .get("specular_reflectance")
+                    .unwrap_or(&[1.0, 1.0, 1.0]),
                 false,
             ))),
             Box::new(ConstantTexture::new(Spectrum::from_slice_3(
-                &bsdf.rgb_params["k"],
+                bsdf.rgb_params
+                    .get("specular_transmittance")
+                    .unwrap_or(&[1.0, 1.0, 1.0]),
                 false,
             ))),
-            texture_with_defaults(
-                log,
-                scene_path,
-                bsdf.texture.as_ref(),
-                bsdf.rgb_params.get("specular_reflectance"),
-            ),
-            Some(Box::new(ConstantTexture::new(bsdf.float_params["alpha"]))),
-            None,
+            Box::new(ConstantTexture::new(1.0)),
             None,
             false,
+            MicrofacetModel::default(),
         )),
-        mitsuba::BSDF::Dielectric(bsdf) => Material::Glass(GlassMaterial::new(
+        mitsuba::BSDF::RoughDielectric(bsdf) => Material::Glass(GlassMaterial::new(
             &log,
-            Box::new(ConstantTexture::new(Spectrum::new(1.0))),
-            Box::new(ConstantTexture::new(Spectrum::new(1.0))),
-            Box::new(ConstantTexture::new(bsdf.float_params["int_ior"])),
+            Box::new(ConstantTexture::new(Spectrum::from_slice_3(
+                bsdf.rgb_params
+                    .get("specular_reflectance")
+                    .unwrap_or(&[1.0, 1.0, 1.0]),
+                false,
+            ))),
+            Box::new(ConstantTexture::new(Spectrum::from_slice_3(
+                bsdf.rgb_params
+                    .get("specular_transmittance")
+                    .unwrap_or(&[1.0, 1.0, 1.0]),
+                false,
+            ))),
+            Box::new(ConstantTexture::new(
+                *bsdf.float_params.get("int_ior").unwrap_or(&1.5046),
+            )),
+            Some(Box::new(ConstantTexture::new(
+                *bsdf.float_params.get("alpha").unwrap_or(&0.1),
+            ))),
+            false,
+            distribution_from_bsdf(log, bsdf),
         )),
         // plastic -> substrate is not a perfect match
         mitsuba::BSDF::Plastic(bsdf) => Material::Substrate(SubstrateMaterial::new(
             log,
             texture_with_defaults(
                 log,
-                scene_path,
+                scene,
                 bsdf.texture.as_ref(),
                 bsdf.rgb_params.get("diffuse_reflectance"),
             ),
@@ -161,12 +339,13 @@ fn material_from_bsdf(log: &slog::Logger, scene_path: &str, bsdf: &mitsuba::BSDF
             Box::new(ConstantTexture::new(0.001)),
             Box::new(ConstantTexture::new(0.001)),
             false,
+            MicrofacetModel::default(),
         )),
         mitsuba::BSDF::RoughPlastic(bsdf) => Material::Substrate(SubstrateMaterial::new(
             log,
             texture_with_defaults(
                 log,
-                scene_path,
+                scene,
                 bsdf.texture.as_ref(),
                 bsdf.rgb_params.get("diffuse_reflectance"),
             ),
@@ -176,47 +355,274 @@ fn material_from_bsdf(log: &slog::Logger, scene_path: &str, bsdf: &mitsuba::BSDF
             Box::new(ConstantTexture::new(bsdf.float_params["alpha"])),
             Box::new(ConstantTexture::new(bsdf.float_params["alpha"])),
             false,
+            distribution_from_bsdf(log, bsdf),
+        )),
+        mitsuba::BSDF::Coating(bsdf) => Material::Layered(LayeredMaterial::new(
+            log,
+            Box::new(material_from_bsdf(&log, scene, &bsdf.bsdf)),
+            Box::new(ConstantTexture::new(
+                *bsdf.float_params.get("int_ior").unwrap_or(&1.49),
+            )),
+            Box::new(ConstantTexture::new(0.01)),
+            10,
+            1,
         )),
+        mitsuba::BSDF::Blend(bsdf) => {
+            assert_eq!(bsdf.bsdf.len(), 2, "blendbsdf requires exactly two bsdfs");
+            let amount = if let Some(texture) = bsdf.texture.as_ref() {
+                float_texture_from_mitsuba(log, scene, texture, 1.0)
+            } else {
+                Box::new(ConstantTexture::new(
+                    *bsdf.float_params.get("weight").unwrap_or(&0.5),
+                ))
+            };
+            Material::Mix(MixMaterial::new(
+                log,
+                Box::new(material_from_bsdf(&log, scene, &bsdf.bsdf[0])),
+                Box::new(material_from_bsdf(&log, scene, &bsdf.bsdf[1])),
+                amount,
+            ))
+        }
+        mitsuba::BSDF::Hair(bsdf) => {
+            let absorption = if let Some(sigma_a) = bsdf.rgb_params.get("sigma_a") {
+                HairAbsorption::SigmaA(Box::new(ConstantTexture::new(Spectrum::from_slice_3(
+                    sigma_a, false,
+                ))))
+            } else if let Some(reflectance) = bsdf.rgb_params.get("reflectance") {
+                HairAbsorption::Color(Box::new(ConstantTexture::new(Spectrum::from_slice_3(
+                    reflectance,
+                    false,
+                ))))
+            } else {
+                HairAbsorption::Melanin {
+                    eumelanin: Box::new(ConstantTexture::new(
+                        *bsdf.float_params.get("eumelanin").unwrap_or(&1.3),
+                    )),
+                    pheomelanin: Box::new(ConstantTexture::new(
+                        *bsdf.float_params.get("pheomelanin").unwrap_or(&0.0),
+                    )),
+                }
+            };
+            Material::Hair(HairMaterial::new(
+                log,
+                absorption,
+                Box::new(ConstantTexture::new(
+                    *bsdf.float_params.get("int_ior").unwrap_or(&1.55),
+                )),
+                Box::new(ConstantTexture::new(
+                    *bsdf
+                        .float_params
+                        .get("longitudinal_roughness")
+                        .unwrap_or(&0.3),
+                )),
+                Box::new(ConstantTexture::new(
+                    *bsdf.float_params.get("azimuthal_roughness").unwrap_or(&0.3),
+                )),
+                Box::new(ConstantTexture::new(
+                    *bsdf.float_params.get("scale_tilt").unwrap_or(&2.0),
+                )),
+            ))
+        }
+        // principled -> disney, the specular tint, sheen and clearcoat lobes are dropped
+        mitsuba::BSDF::Principled(bsdf) => Material::Disney(DisneyMaterial::new(
+            log,
+            texture_with_defaults(
+                log,
+                scene,
+                bsdf.texture.as_ref(),
+                Some(
+                    bsdf.rgb_params
+                        .get("base_color")
+                        .unwrap_or(&[0.5, 0.5, 0.5]),
+                ),
+            ),
+            Box::new(ConstantTexture::new(
+                *bsdf.float_params.get("metallic").unwrap_or(&0.0),
+            )),
+            Box::new(ConstantTexture::new(
+                *bsdf.float_params.get("eta").unwrap_or(&1.5),
+            )),
+            Box::new(ConstantTexture::new(
+                *bsdf.float_params.get("roughness").unwrap_or(&0.5),
+            )),
+            None,
+        )),
+        mitsuba::BSDF::BumpMap(bsdf) => with_bump(
+            log,
+            material_from_bsdf(&log, scene, &bsdf.bsdf),
+            Some(float_texture_from_mitsuba(
+                log,
+                scene,
+                &bsdf.texture,
+                *bsdf.float_params.get("scale").unwrap_or(&1.0),
+            )),
+        ),
+    }
+}
+
+fn shape_material(
+    log: &slog::Logger,
+    scene: &mitsuba::Scene,
+    material_ref: &Option<mitsuba::Reference>,
+    material_embed: &Option<mitsuba::BSDF>,
+    materials: &HashMap<String, Arc<Material>>,
+) -> Arc<Material> {
+    if let Some(material_ref) = material_ref {
+        Arc::clone(&materials[&material_ref.id])
+    } else if let Some(material_embed) = material_embed {
+        Arc::new(material_from_bsdf(&log, scene, material_embed))
+    } else {
+        panic!("either ref exists or embedded bsdf exists");
+    }
+}
+
+fn area_light_from_mitsuba(
+    emitter: &Option<mitsuba::Emitter>,
+    shape: &Arc<Shape>,
+    lights: &mut Vec<Arc<dyn SyncLight>>,
+) -> Option<Arc<DiffuseAreaLight>> {
+    if let Some(mitsuba::Emitter::Area { rgb }) = emitter {
+        let ke = Arc::new(ConstantTexture::<Spectrum>::new(Spectrum::from_slice_3(
+            rgb, false,
+        ))) as Arc<dyn SyncTexture<Spectrum>>;
+        let light = Arc::new(DiffuseAreaLight::new(ke, Arc::clone(shape), 1, false));
+        lights.push(Arc::clone(&light) as Arc<dyn SyncLight>);
+        Some(light)
+    } else {
+        None
     }
 }
 
 fn parse_shape(
     log: &slog::Logger,
-    scene_path: &str,
+    scene: &mitsuba::Scene,
     shape: &mitsuba::Shape,
     materials: &HashMap<String, Arc<Material>>,
+    displacements: &HashMap<String, Displacement>,
+    dicing_rate: &DicingRate,
     primitives: &mut Vec<Arc<dyn SyncPrimitive>>,
     meshes: &mut Vec<Arc<TriangleMesh>>,
     lights: &mut Vec<Arc<dyn SyncLight>>,
 ) {
+    if let mitsuba::Shape::Hair {
+        transform,
+        radius,
+        material,
+        bsdf,
+        filename,
+    } = shape
+    {
+        let material = shape_material(log, scene, material, bsdf, materials);
+
+        let strands = mitsuba::load_hair(&scene.resolver.resolve(filename));
+        let mut count = 0;
+        for strand in strands {
+            let strand: Vec<_> = strand.iter().map(|p| transform * p).collect();
+            for curve in curves_from_strand(&strand, 2.0 * radius.value, CurveType::Cylinder, 2) {
+                primitives.push(Arc::new(CurvePrimitive::new(curve, Arc::clone(&material)))
+                    as Arc<dyn SyncPrimitive>);
+                count += 1;
+            }
+        }
+        debug!(log, "loaded hair"; "filename" => filename, "curves" => count);
+        return;
+    }
+
+    if let mitsuba::Shape::Unsupported = shape {
+        warn!(log, "skipping shape of an unsupported type");
+        return;
+    }
+
+    // the tessellations of the analytic shapes only feed the preview, renders intersect the
+    // exact surfaces
+    let analytic = match shape {
+        mitsuba::Shape::Sphere {
+            point,
+            radius,
+            material,
+            bsdf,
+            emitter,
+        } => Some((
+            mitsuba::gen_sphere(point, radius.value),
+            na::Projective3::identity(),
+            Shape::Sphere(Sphere::new(
+                &na::convert(na::Translation3::from(point.coords)),
+                radius.value,
+                false,
+            )),
+            material,
+            bsdf,
+            emitter,
+        )),
+        mitsuba::Shape::Rectangle {
+            transform,
+            material,
+            bsdf,
+            emitter,
+        } => Some((
+            mitsuba::gen_rectangle(),
+            *transform,
+            Shape::Rectangle(Rectangle::new(transform, false)),
+            material,
+            bsdf,
+            emitter,
+        )),
+        mitsuba::Shape::Disk {
+            transform,
+            material,
+            bsdf,
+            emitter,
+        } => Some((
+            mitsuba::gen_disk(),
+            *transform,
+            Shape::Disk(Disk::new(transform, 0.0, 1.0, false)),
+            material,
+            bsdf,
+            emitter,
+        )),
+        mitsuba::Shape::Cylinder {
+            transform,
+            radius,
+            material,
+            bsdf,
+            emitter,
+        } => Some((
+            mitsuba::gen_cylinder(radius.value),
+            *transform,
+            Shape::Cylinder(Cylinder::new(transform, radius.value, 0.0, 1.0, false)),
+            material,
+            bsdf,
+            emitter,
+        )),
+        _ => None,
+    };
+    if let Some((mesh, preview_transform, analytic_shape, material, bsdf, emitter)) = analytic {
+        meshes.push(Arc::new(TriangleMesh::new_with_transform(
+            mesh.indices,
+            mesh.pos,
+            mesh.normal,
+            vec![],
+            mesh.uv,
+            vec![],
+            None,
+            &preview_transform,
+        )));
+
+        let analytic_shape = Arc::new(analytic_shape);
+        primitives.push(Arc::new(GeometricPrimitive::new(
+            Arc::clone(&analytic_shape),
+            shape_material(log, scene, material, bsdf, materials),
+            area_light_from_mitsuba(emitter, &analytic_shape, lights),
+        )) as Arc<dyn SyncPrimitive>);
+        return;
+    }
+
     let mut obj_to_world = na::Projective3::identity();
     let world_mesh;
     let light_info;
     let material_ref;
     let material_embed;
     match shape {
-        mitsuba::Shape::Rectangle {
-            transform,
-            material,
-            bsdf,
-            emitter,
-        } => {
-            let mesh = mitsuba::gen_rectangle();
-            obj_to_world = *transform;
-            light_info = emitter;
-            material_ref = material;
-            material_embed = bsdf;
-            world_mesh = Arc::new(TriangleMesh::new_with_transform(
-                mesh.indices,
-                mesh.pos,
-                mesh.normal,
-                vec![],
-                mesh.uv,
-                vec![],
-                None,
-                &obj_to_world,
-            ));
-        }
         mitsuba::Shape::Cube {
             transform,
             material,
@@ -239,29 +645,15 @@ fn parse_shape(
                 &obj_to_world,
             ));
         }
-        mitsuba::Shape::Sphere {
-            point,
-            radius,
+        mitsuba::Shape::Obj {
+            transform,
+            face_normals,
             material,
             bsdf,
             emitter,
-        } => {
-            let mesh = mitsuba::gen_sphere(point, radius.value);
-            light_info = emitter;
-            material_ref = material;
-            material_embed = bsdf;
-            world_mesh = Arc::new(TriangleMesh::new_with_transform(
-                mesh.indices,
-                mesh.pos,
-                mesh.normal,
-                vec![],
-                mesh.uv,
-                vec![],
-                None,
-                &obj_to_world,
-            ));
+            filename,
         }
-        mitsuba::Shape::Obj {
+        | mitsuba::Shape::Ply {
             transform,
             face_normals,
             material,
@@ -269,7 +661,18 @@ fn parse_shape(
             emitter,
             filename,
         } => {
-            let mesh = mitsuba::load_obj(scene_path, filename);
+            let file_path = scene.resolver.resolve(filename);
+            let mesh = if let mitsuba::Shape::Ply { .. } = shape {
+                match ply::load_ply(&file_path) {
+                    Ok(mesh) => mesh,
+                    Err(err) => {
+                        warn!(log, "skipping ply mesh: {:?}", err);
+                        return;
+                    }
+                }
+            } else {
+                mitsuba::load_obj(&file_path)
+            };
             obj_to_world = *transform;
             light_info = emitter;
             material_ref = material;
@@ -278,7 +681,7 @@ fn parse_shape(
             if *face_normals {
                 warn!(
                     log,
-                    "face normals on for obj, vertex normals will be disregarded"
+                    "face normals on for mesh, vertex normals will be disregarded"
                 );
             }
 
@@ -288,39 +691,35 @@ fn parse_shape(
                 if *face_normals { vec![] } else { mesh.normal },
                 vec![],
                 mesh.uv,
-                vec![],
+                mesh.colors,
                 None,
                 &obj_to_world,
             ));
         }
+        mitsuba::Shape::Hair { .. }
+        | mitsuba::Shape::Sphere { .. }
+        | mitsuba::Shape::Rectangle { .. }
+        | mitsuba::Shape::Disk { .. }
+        | mitsuba::Shape::Cylinder { .. }
+        | mitsuba::Shape::Unsupported => unreachable!(),
     }
 
-    let material;
-    if let Some(material_ref) = material_ref {
-        material = Arc::clone(&materials[&material_ref.id]);
-    } else if let Some(material_embed) = material_embed {
-        material = Arc::new(material_from_bsdf(&log, scene_path, material_embed));
-    } else {
-        panic!("either ref exists or embedded bsdf exists");
-    }
+    let material = shape_material(log, scene, material_ref, material_embed, materials);
+
+    let displacement = material_ref
+        .as_ref()
+        .and_then(|material_ref| displacements.get(&material_ref.id));
+    let world_mesh = match displacement {
+        Some(displacement) => displacement
+            .displace(log, &world_mesh, dicing_rate)
+            .map_or(world_mesh, Arc::new),
+        None => world_mesh,
+    };
 
     meshes.push(world_mesh.clone());
 
     for shape in triangles_from_mesh(&world_mesh, false) {
-        let area_light = if let Some(light_info) = light_info {
-            if let mitsuba::Emitter::Area { rgb } = light_info {
-                let ke = Arc::new(ConstantTexture::<Spectrum>::new(Spectrum::from_slice_3(
-                    rgb, false,
-                ))) as Arc<dyn SyncTexture<Spectrum>>;
-                let light = Arc::new(DiffuseAreaLight::new(ke, Arc::clone(&shape), 1));
-                lights.push(Arc::clone(&light) as Arc<dyn SyncLight>);
-                Some(light)
-            } else {
-                None
-            }
-        } else {
-            None
-        };
+        let area_light = area_light_from_mitsuba(light_info, &shape, lights);
 
         primitives.push(Arc::new(GeometricPrimitive::new(
             Arc::clone(&shape),
@@ -331,7 +730,13 @@ fn parse_shape(
 }
 
 impl RenderScene {
-    pub fn from_mitsuba(log: &slog::Logger, scene: &mitsuba::Scene) -> Self {
+    pub fn from_mitsuba(
+        log: &slog::Logger,
+        scene: &mitsuba::Scene,
+        material_library: Option<&MaterialLibrary>,
+        dicing_camera: &Camera,
+        bvh_cache: &accelerator::BVHCache,
+    ) -> Self {
         let log = log.new(o!("module" => "scene"));
         let mut materials = HashMap::new();
         let mut primitives: Vec<Arc<dyn SyncPrimitive>> = Vec::new();
@@ -340,25 +745,40 @@ impl RenderScene {
         let mut meshes: Vec<Arc<TriangleMesh>> = Vec::new();
 
         for (id, bsdf) in &scene.bsdfs {
-            materials.insert(
-                id.clone(),
-                Arc::new(material_from_bsdf(&log, &scene.path, &bsdf)),
-            );
+            let material = material_library
+                .and_then(|library| library.material(&log, id))
+                .unwrap_or_else(|| material_from_bsdf(&log, &scene, &bsdf));
+            materials.insert(id.clone(), Arc::new(material));
         }
 
+        let displacements: HashMap<String, Displacement> = material_library
+            .map(|library| {
+                scene
+                    .bsdfs
+                    .keys()
+                    .filter_map(|id| Some((id.clone(), library.displacement(&log, id)?)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let dicing_rate = DicingRate::from_camera(dicing_camera, DEFAULT_MAX_EDGE_PIXELS);
+
         for shape in &scene.shapes {
             parse_shape(
                 &log,
-                &scene.path,
+                &scene,
                 &shape,
                 &materials,
+                &displacements,
+                &dicing_rate,
                 &mut primitives,
                 &mut meshes,
                 &mut lights,
             );
         }
 
-        let bvh = Box::new(accelerator::BVH::new(&log, primitives, &4));
+        let bvh = Box::new(accelerator::BVH::with_cache(
+            &log, primitives, &4, bvh_cache,
+        ));
         let world_bound = bvh.world_bound();
 
         // FIXME: should probably figure out what's wrong with the overall transformation
@@ -376,15 +796,61 @@ impl RenderScene {
                 mitsuba::Emitter::Area { rgb: _ } => {
                     error!(log, "area lights should not be standalone!");
                 }
-                mitsuba::Emitter::Point => {} // TODO: support mitsuba point light
+                mitsuba::Emitter::Point {
+                    transform,
+                    point,
+                    rgb,
+                } => {
+                    let position = point
+                        .as_ref()
+                        .map_or(na::Point3::origin(), |p| na::Point3::new(p.x, p.y, p.z));
+                    let light_to_world: na::Projective3<f32> =
+                        transform * na::Translation3::from(position.coords);
+                    lights.push(Arc::new(PointLight::new(
+                        &light_to_world,
+                        Spectrum::from_slice_3(rgb, false),
+                    )));
+                }
+                mitsuba::Emitter::Spot { transform, rgb } => {
+                    warn!(log, "spot light is approximated by a point light");
+                    lights.push(Arc::new(PointLight::new(
+                        transform,
+                        Spectrum::from_slice_3(rgb, false),
+                    )));
+                }
+                mitsuba::Emitter::Directional {
+                    transform,
+                    vector,
+                    rgb,
+                } => {
+                    let direction = vector
+                        .as_ref()
+                        .map_or(na::Vector3::z(), |v| na::Vector3::new(v.x, v.y, v.z));
+                    let mut light = DirectionalLight::new(
+                        transform,
+                        Spectrum::from_slice_3(rgb, false),
+                        -direction,
+                    );
+                    light.preprocess(&world_bound);
+                    lights.push(Arc::new(light));
+                }
+                mitsuba::Emitter::Constant { rgb } => {
+                    let mut env_light = InfiniteAreaLight::new(
+                        &log,
+                        na::Projective3::identity(),
+                        Spectrum::from_slice_3(rgb, false),
+                        "",
+                    );
+                    env_light.preprocess(&world_bound);
+                    let env_light = Arc::new(env_light) as Arc<dyn SyncLight>;
+                    lights.push(Arc::clone(&env_light));
+                    infinite_lights.push(Arc::clone(&env_light));
+                }
                 mitsuba::Emitter::EnvMap {
                     transform,
                     filename,
                 } => {
-                    let file_path = std::path::Path::new(&scene.path)
-                        .parent()
-                        .unwrap_or_else(|| std::path::Path::new(""))
-                        .join(filename);
+                    let file_path = scene.resolver.resolve(filename);
                     let file_path = file_path.to_str().unwrap();
                     let mut env_light = InfiniteAreaLight::new(
                         &log,
@@ -416,6 +882,9 @@ impl RenderScene {
                     lights.push(Arc::clone(&env_light));
                     infinite_lights.push(Arc::clone(&env_light));
                 } // TODO: support mitsuba sunsky emitter
+                mitsuba::Emitter::Unsupported => {
+                    warn!(log, "skipping emitter of an unsupported type");
+                }
             }
         }
 
@@ -424,6 +893,9 @@ impl RenderScene {
             lights,
             infinite_lights,
             meshes,
+            instanced_meshes: vec![],
+            texture_cache: Arc::clone(&scene.texture_cache),
+            accelerator: None,
         }
     }
 }
@@ -1,2 +1,5 @@
+pub mod description;
 pub mod gltf;
+pub mod library;
 pub mod mitsuba;
+pub mod pbrt;
@@ -0,0 +1,955 @@
+use crate::{
+    common::importer::downsample_image,
+    common::WrapMode,
+    common::{
+        importer::{library::MaterialLibrary, pbrt},
+        spectrum::Spectrum,
+        Camera,
+    },
+    pathtracer::{
+        accelerator,
+        light::{
+            DiffuseAreaLight, DirectionalLight, EnvMapping, InfiniteAreaLight, Light, PointLight,
+            SyncLight,
+        },
+        material::{
+            disney::DisneyMaterial,
+            hair::{HairAbsorption, HairMaterial},
+            layered::LayeredMaterial,
+            metal::{metal_ior, MetalMaterial},
+            mix::MixMaterial,
+            substrate::SubstrateMaterial,
+            translucent::TranslucentMaterial,
+            with_bump, GlassMaterial, Material, MatteMaterial, MirrorMaterial,
+        },
+        primitive::{CurvePrimitive, GeometricPrimitive, SyncPrimitive},
+        shape::{
+            curve::{curves_from_common, CurveCommon, CurveType},
+            cylinder::Cylinder,
+            disk::Disk,
+            displacement::{DicingRate, Displacement, DEFAULT_MAX_EDGE_PIXELS},
+            sphere::Sphere,
+            swaps_handedness, Shape, Triangle, TriangleMesh,
+        },
+        texture::{
+            luma_texels, rgb_texels, CheckerboardTexture, ConstantTexture, FilterMode,
+            ImageTexture, MixTexture, ScaleTexture, SyncTexture, UVMap,
+        },
+        Primitive, RenderScene,
+    },
+};
+use std::{collections::HashMap, sync::Arc};
+
+/// index of refraction of the glasses pbrt-v4 names, at the wavelength of the green channel
+const GLASS_PRESETS: [(&str, f32); 7] = [
+    ("glass-BK7", 1.5168),
+    ("glass-BAF10", 1.6700),
+    ("glass-FK51A", 1.4866),
+    ("glass-LASF9", 1.8503),
+    ("glass-F5", 1.6034),
+    ("glass-F10", 1.6200),
+    ("glass-F11", 1.6209),
+];
+
+/// pbrt's uvs start at the bottom left of images while their rows are stored top down
+fn image_uv_map(params: &pbrt::ParamSet) -> UVMap {
+    UVMap::new(
+        params.float("uscale").unwrap_or(1.0),
+        -params.float("vscale").unwrap_or(1.0),
+        params.float("udelta").unwrap_or(0.0),
+        1.0 - params.float("vdelta").unwrap_or(0.0),
+    )
+}
+
+fn wrap_from_pbrt(params: &pbrt::ParamSet) -> WrapMode {
+    match params.string("wrap") {
+        Some("black") => WrapMode::Black,
+        Some("clamp") => WrapMode::Clamp,
+        _ => WrapMode::Repeat,
+    }
+}
+
+/// pbrt-v3 filters with ewa unless `trilinear` is set, pbrt-v4 names its filter, only ewa and
+/// trilinear filtering are supported so the others are filtered trilinearly
+fn filter_from_pbrt(params: &pbrt::ParamSet) -> FilterMode {
+    match params.string("filter") {
+        Some("ewa") | Some("EWA") => FilterMode::EWA,
+        Some(_) => FilterMode::Trilinear,
+        None if params.bool("trilinear").unwrap_or(false) => FilterMode::Trilinear,
+        None => FilterMode::EWA,
+    }
+}
+
+/// images are loaded on the first lookup of their texture, those that fail to load are warned
+/// about and the texture is left at its scale
+fn load_image(
+    log: &slog::Logger,
+    file_path: &std::path::Path,
+    max_texture_size: Option<u32>,
+) -> Option<image::DynamicImage> {
+    match image::open(file_path) {
+        Ok(image) => Some(downsample_image(log, image, max_texture_size)),
+        Err(err) => {
+            warn!(
+                log,
+                "failed loading texture {:?}, using white instead: {}", file_path, err
+            );
+            None
+        }
+    }
+}
+
+fn texture_from_pbrt(
+    log: &slog::Logger,
+    scene: &pbrt::Scene,
+    texture: &pbrt::Texture,
+) -> Box<dyn SyncTexture<Spectrum>> {
+    let params = &texture.params;
+    match texture.class.as_str() {
+        "imagemap" => {
+            let scale = params.float("scale").unwrap_or(1.0);
+            // 8 bit images are srgb encoded unless said otherwise
+            let gamma = match params.string("encoding") {
+                Some("linear") => false,
+                Some(_) => true,
+                None => params.bool("gamma").unwrap_or(true),
+            };
+            let file_path = scene
+                .resolver
+                .resolve(params.string("filename").unwrap_or_default());
+            let max_texture_size = scene.max_texture_size;
+            let loader_log = log.clone();
+            Box::new(
+                ImageTexture::<Spectrum>::from_cache(
+                    log,
+                    &scene.texture_cache,
+                    move || match load_image(&loader_log, &file_path, max_texture_size) {
+                        Some(image) => rgb_texels(&image.to_rgb(), Spectrum::new(scale), gamma),
+                        None => na::DMatrix::from_element(1, 1, Spectrum::new(scale)),
+                    },
+                    wrap_from_pbrt(params),
+                    image_uv_map(params),
+                )
+                .with_filter(filter_from_pbrt(params)),
+            )
+        }
+        "checkerboard" => Box::new(
+            CheckerboardTexture::new(
+                spectrum_param(log, scene, params, &["tex1"], 1.0),
+                spectrum_param(log, scene, params, &["tex2"], 0.0),
+                1.0,
+            )
+            .with_mapping(Box::new(UVMap::new(
+                params.float("uscale").unwrap_or(1.0),
+                params.float("vscale").unwrap_or(1.0),
+                params.float("udelta").unwrap_or(0.0),
+                params.float("vdelta").unwrap_or(0.0),
+            ))),
+        ),
+        "constant" => spectrum_param(log, scene, params, &["value"], 1.0),
+        // pbrt-v4 scales by a float texture, pbrt-v3 multiplies two textures of the same type
+        "scale" if params.contains("scale") => Box::new(ScaleTexture::new(
+            float_param(log, scene, params, &["scale"], 1.0),
+            spectrum_param(log, scene, params, &["tex"], 1.0),
+        )),
+        "scale" => Box::new(ScaleTexture::new(
+            spectrum_param(log, scene, params, &["tex1"], 1.0),
+            spectrum_param(log, scene, params, &["tex2"], 1.0),
+        )),
+        "mix" => Box::new(MixTexture::new(
+            spectrum_param(log, scene, params, &["tex1"], 0.0),
+            spectrum_param(log, scene, params, &["tex2"], 1.0),
+            float_param(log, scene, params, &["amount"], 0.5),
+        )),
+        class => {
+            warn!(log, "unsupported texture {:?}, using white", class);
+            Box::new(ConstantTexture::new(Spectrum::new(1.0)))
+        }
+    }
+}
+
+fn float_texture_from_pbrt(
+    log: &slog::Logger,
+    scene: &pbrt::Scene,
+    texture: &pbrt::Texture,
+) -> Box<dyn SyncTexture<f32>> {
+    let params = &texture.params;
+    match texture.class.as_str() {
+        "imagemap" => {
+            let scale = params.float("scale").unwrap_or(1.0);
+            let file_path = scene
+                .resolver
+                .resolve(params.string("filename").unwrap_or_default());
+            let max_texture_size = scene.max_texture_size;
+            let loader_log = log.clone();
+            Box::new(
+                ImageTexture::<f32>::from_cache(
+                    log,
+                    &scene.texture_cache,
+                    move || match load_image(&loader_log, &file_path, max_texture_size) {
+                        Some(image) => luma_texels(&image.to_luma(), scale),
+                        None => na::DMatrix::from_element(1, 1, scale),
+                    },
+                    wrap_from_pbrt(params),
+                    image_uv_map(params),
+                )
+                .with_filter(filter_from_pbrt(params)),
+            )
+        }
+        "checkerboard" => Box::new(
+            CheckerboardTexture::new(
+                float_param(log, scene, params, &["tex1"], 1.0),
+                float_param(log, scene, params, &["tex2"], 0.0),
+                1.0,
+            )
+            .with_mapping(Box::new(UVMap::new(
+                params.float("uscale").unwrap_or(1.0),
+                params.float("vscale").unwrap_or(1.0),
+                params.float("udelta").unwrap_or(0.0),
+                params.float("vdelta").unwrap_or(0.0),
+            ))),
+        ),
+        "constant" => float_param(log, scene, params, &["value"], 1.0),
+        "scale" if params.contains("scale") => Box::new(ScaleTexture::new(
+            float_param(log, scene, params, &["scale"], 1.0),
+            float_param(log, scene, params, &["tex"], 1.0),
+        )),
+        "scale" => Box::new(ScaleTexture::new(
+            float_param(log, scene, params, &["tex1"], 1.0),
+            float_param(log, scene, params, &["tex2"], 1.0),
+        )),
+        "mix" => Box::new(MixTexture::new(
+            float_param(log, scene, params, &["tex1"], 0.0),
+            float_param(log, scene, params, &["tex2"], 1.0),
+            float_param(log, scene, params, &["amount"], 0.5),
+        )),
+        class => {
+            warn!(log, "unsupported float texture {:?}, using one", class);
+            Box::new(ConstantTexture::new(1.0))
+        }
+    }
+}
+
+/// the texture or color of the first of `names` the parameters hold, pbrt-v3 and pbrt-v4 name
+/// the same parameters differently
+fn spectrum_param_opt(
+    log: &slog::Logger,
+    scene: &pbrt::Scene,
+    params: &pbrt::ParamSet,
+    names: &[&str],
+) -> Option<Box<dyn SyncTexture<Spectrum>>> {
+    for name in names {
+        if let Some(texture) = params.texture(name) {
+            match scene.spectrum_textures.get(texture) {
+                Some(texture) => return Some(texture_from_pbrt(log, scene, texture)),
+                None => warn!(log, "unknown texture {:?}", texture),
+            }
+        } else if let Some(rgb) = params.rgb(name) {
+            return Some(Box::new(ConstantTexture::new(Spectrum::from_slice_3(
+                &rgb, false,
+            ))));
+        }
+    }
+
+    None
+}
+
+fn spectrum_param(
+    log: &slog::Logger,
+    scene: &pbrt::Scene,
+    params: &pbrt::ParamSet,
+    names: &[&str],
+    default: f32,
+) -> Box<dyn SyncTexture<Spectrum>> {
+    spectrum_param_opt(log, scene, params, names)
+        .unwrap_or_else(|| Box::new(ConstantTexture::new(Spectrum::new(default))))
+}
+
+fn float_param_opt(
+    log: &slog::Logger,
+    scene: &pbrt::Scene,
+    params: &pbrt::ParamSet,
+    names: &[&str],
+) -> Option<Box<dyn SyncTexture<f32>>> {
+    for name in names {
+        if let Some(texture) = params.texture(name) {
+            match scene.float_textures.get(texture) {
+                Some(texture) => return Some(float_texture_from_pbrt(log, scene, texture)),
+                None => warn!(log, "unknown float texture {:?}", texture),
+            }
+        } else if let Some(value) = params.float(name) {
+            return Some(Box::new(ConstantTexture::new(value)));
+        }
+    }
+
+    None
+}
+
+fn float_param(
+    log: &slog::Logger,
+    scene: &pbrt::Scene,
+    params: &pbrt::ParamSet,
+    names: &[&str],
+    default: f32,
+) -> Box<dyn SyncTexture<f32>> {
+    float_param_opt(log, scene, params, names)
+        .unwrap_or_else(|| Box::new(ConstantTexture::new(default)))
+}
+
+/// `(eta, k)` of a conductor, from pbrt's named metal spectra, explicit values or the
+/// reflectance pbrt-v4 takes instead, falling back to copper like pbrt
+fn conductor_from_pbrt(
+    log: &slog::Logger,
+    scene: &pbrt::Scene,
+    params: &pbrt::ParamSet,
+    prefix: &str,
+) -> (
+    Box<dyn SyncTexture<Spectrum>>,
+    Box<dyn SyncTexture<Spectrum>>,
+    Box<dyn SyncTexture<Spectrum>>,
+) {
+    let name = |name: &str| format!("{}{}", prefix, name);
+    let named_metal = |name: &str| {
+        let spectrum = params.named_spectrum(name)?;
+        let metal = spectrum
+            .strip_prefix("metal-")
+            .and_then(|metal| metal.split('-').next());
+        metal.and_then(metal_ior).or_else(|| {
+            warn!(log, "unsupported spectrum {:?}, using copper", spectrum);
+            None
+        })
+    };
+    let (cu_eta, cu_k) = metal_ior("cu").unwrap();
+    let constant =
+        |s: Spectrum| Box::new(ConstantTexture::new(s)) as Box<dyn SyncTexture<Spectrum>>;
+    let white = constant(Spectrum::new(1.0));
+
+    let eta_name = name("eta");
+    let k_name = name("k");
+    let reflectance_name = name("reflectance");
+    if !params.contains(&eta_name) && !params.contains(&k_name) {
+        // a reflectance r is matched at normal incidence by eta 1 and k = 2 sqrt(r / (1 - r))
+        if let Some(r) = params.rgb(&reflectance_name) {
+            let r = Spectrum::from_slice_3(
+                &[r[0].min(0.9999), r[1].min(0.9999), r[2].min(0.9999)],
+                false,
+            );
+            let k = 2.0 * (r / (Spectrum::new(1.0) - r)).sqrt();
+            return (constant(Spectrum::new(1.0)), constant(k), white);
+        }
+        if let Some(r) = spectrum_param_opt(log, scene, params, &[&reflectance_name]) {
+            // textured reflectances tint the nearly white reflection of silver
+            let (ag_eta, ag_k) = metal_ior("ag").unwrap();
+            return (constant(ag_eta), constant(ag_k), r);
+        }
+    }
+
+    let eta = match named_metal(&eta_name) {
+        Some((eta, _)) => constant(eta),
+        None => {
+            spectrum_param_opt(log, scene, params, &[&eta_name]).unwrap_or_else(|| constant(cu_eta))
+        }
+    };
+    let k = match named_metal(&k_name) {
+        Some((_, k)) => constant(k),
+        None => {
+            spectrum_param_opt(log, scene, params, &[&k_name]).unwrap_or_else(|| constant(cu_k))
+        }
+    };
+    (eta, k, white)
+}
+
+/// index of refraction of a dielectric, given as a float or a named glass
+fn eta_from_pbrt(
+    log: &slog::Logger,
+    scene: &pbrt::Scene,
+    params: &pbrt::ParamSet,
+    name: &str,
+) -> Box<dyn SyncTexture<f32>> {
+    if let Some(eta) = float_param_opt(log, scene, params, &[name, "index"]) {
+        return eta;
+    }
+    let eta = match params.named_spectrum(name) {
+        Some(glass) => GLASS_PRESETS
+            .iter()
+            .find(|(preset, _)| *preset == glass)
+            .map(|(_, eta)| *eta)
+            .unwrap_or_else(|| {
+                warn!(log, "unsupported spectrum {:?}, using an eta of 1.5", glass);
+                1.5
+            }),
+        None => params.rgb(name).map_or(1.5, |rgb| rgb[1]),
+    };
+    Box::new(ConstantTexture::new(eta))
+}
+
+fn material_from_pbrt(
+    log: &slog::Logger,
+    scene: &pbrt::Scene,
+    material: &pbrt::Material,
+) -> Material {
+    let params = &material.params;
+    let spectrum =
+        |names: &[&str], default: f32| spectrum_param(log, scene, params, names, default);
+    let float = |names: &[&str], default: f32| float_param(log, scene, params, names, default);
+    let float_opt = |names: &[&str]| float_param_opt(log, scene, params, names);
+    let remap_roughness = params.bool("remaproughness").unwrap_or(true);
+    let named = |name: &str| match scene.material_index(name) {
+        Some(index) => material_from_pbrt(log, scene, &scene.materials[index]),
+        None => {
+            warn!(log, "unknown material {:?}, using gray diffuse", name);
+            Material::Matte(MatteMaterial::new(
+                log,
+                Box::new(ConstantTexture::new(Spectrum::new(0.5))),
+                None,
+            ))
+        }
+    };
+
+    let result = match material.kind.as_str() {
+        "matte" | "diffuse" => Material::Matte(MatteMaterial::new(
+            log,
+            spectrum(&["Kd", "reflectance"], 0.5),
+            float_opt(&["sigma"]),
+        )),
+        // plastic -> substrate is not a perfect match
+        "plastic" | "uber" => {
+            if material.kind == "uber" {
+                warn!(log, "uber material is approximated by a substrate");
+            }
+            Material::Substrate(SubstrateMaterial::new(
+                log,
+                spectrum(&["Kd"], 0.25),
+                spectrum(&["Ks"], 0.25),
+                float(&["uroughness", "roughness"], 0.1),
+                float(&["vroughness", "roughness"], 0.1),
+                remap_roughness,
+                Default::default(),
+            ))
+        }
+        "substrate" => Material::Substrate(SubstrateMaterial::new(
+            log,
+            spectrum(&["Kd"], 0.5),
+            spectrum(&["Ks"], 0.5),
+            float(&["uroughness", "roughness"], 0.1),
+            float(&["vroughness", "roughness"], 0.1),
+            remap_roughness,
+            Default::default(),
+        )),
+        "metal" | "conductor" => {
+            let (eta, k, r) = conductor_from_pbrt(log, scene, params, "");
+            Material::Metal(MetalMaterial::new(
+                log,
+                eta,
+                k,
+                r,
+                // smooth conductors are kept barely rough
+                Some(float(&["roughness"], 0.001)),
+                float_opt(&["uroughness"]),
+                float_opt(&["vroughness"]),
+                remap_roughness,
+                Default::default(),
+            ))
+        }
+        "mirror" => Material::Mirror(MirrorMaterial::new(log)),
+        "glass" | "dielectric" | "thindielectric" => {
+            if material.kind == "thindielectric" {
+                warn!(log, "thin dielectric is approximated by a solid one");
+            }
+            let roughness = float_opt(&["uroughness", "roughness"]);
+            Material::Glass(GlassMaterial::new(
+                log,
+                spectrum(&["Kr"], 1.0),
+                spectrum(&["Kt"], 1.0),
+                eta_from_pbrt(log, scene, params, "eta"),
+                roughness,
+                remap_roughness,
+                Default::default(),
+            ))
+        }
+        "translucent" => Material::Translucent(TranslucentMaterial::new(
+            log,
+            spectrum(&["Kd"], 0.25),
+            spectrum(&["reflect"], 0.5),
+            spectrum(&["transmit"], 0.5),
+        )),
+        "diffusetransmission" => Material::Translucent(TranslucentMaterial::new(
+            log,
+            Box::new(ConstantTexture::new(Spectrum::new(1.0))),
+            spectrum(&["reflectance"], 0.25),
+            spectrum(&["transmittance"], 0.25),
+        )),
+        "disney" => Material::Disney(DisneyMaterial::new(
+            log,
+            spectrum(&["color"], 0.5),
+            float(&["metallic"], 0.0),
+            float(&["eta"], 1.5),
+            float(&["roughness"], 0.5),
+            None,
+        )),
+        "coateddiffuse" => Material::Layered(LayeredMaterial::new(
+            log,
+            Box::new(Material::Matte(MatteMaterial::new(
+                log,
+                spectrum(&["reflectance"], 0.5),
+                None,
+            ))),
+            eta_from_pbrt(log, scene, params, "eta"),
+            float(&["thickness"], 0.01),
+            params.integer("maxdepth").unwrap_or(10) as usize,
+            params.integer("nsamples").unwrap_or(1) as usize,
+        )),
+        "coatedconductor" => {
+            let (eta, k, r) = conductor_from_pbrt(log, scene, params, "conductor.");
+            Material::Layered(LayeredMaterial::new(
+                log,
+                Box::new(Material::Metal(MetalMaterial::new(
+                    log,
+                    eta,
+                    k,
+                    r,
+                    Some(float(&["conductor.roughness"], 0.001)),
+                    float_opt(&["conductor.uroughness"]),
+                    float_opt(&["conductor.vroughness"]),
+                    remap_roughness,
+                    Default::default(),
+                ))),
+                eta_from_pbrt(log, scene, params, "interface.eta"),
+                float(&["thickness"], 0.01),
+                params.integer("maxdepth").unwrap_or(10) as usize,
+                params.integer("nsamples").unwrap_or(1) as usize,
+            ))
+        }
+        "hair" => {
+            let absorption =
+                if let Some(sigma_a) = spectrum_param_opt(log, scene, params, &["sigma_a"]) {
+                    HairAbsorption::SigmaA(sigma_a)
+                } else if let Some(color) =
+                    spectrum_param_opt(log, scene, params, &["color", "reflectance"])
+                {
+                    HairAbsorption::Color(color)
+                } else {
+                    HairAbsorption::Melanin {
+                        eumelanin: float(&["eumelanin"], 1.3),
+                        pheomelanin: float(&["pheomelanin"], 0.0),
+                    }
+                };
+            Material::Hair(HairMaterial::new(
+                log,
+                absorption,
+                float(&["eta"], 1.55),
+                float(&["beta_m"], 0.3),
+                float(&["beta_n"], 0.3),
+                float(&["alpha"], 2.0),
+            ))
+        }
+        // pbrt-v4 lists the two materials, pbrt-v3 names them separately
+        "mix" => {
+            let names = match params.strings("materials") {
+                Some(names) if names.len() == 2 => [names[0].clone(), names[1].clone()],
+                _ => [
+                    params
+                        .string("namedmaterial1")
+                        .unwrap_or_default()
+                        .to_string(),
+                    params
+                        .string("namedmaterial2")
+                        .unwrap_or_default()
+                        .to_string(),
+                ],
+            };
+            Material::Mix(MixMaterial::new(
+                log,
+                Box::new(named(&names[0])),
+                Box::new(named(&names[1])),
+                float(&["amount"], 0.5),
+            ))
+        }
+        // surfaces only bounding media are invisible, which glass matching the outside does
+        "interface" | "none" | "" => Material::Glass(GlassMaterial::new(
+            log,
+            Box::new(ConstantTexture::new(Spectrum::new(0.0))),
+            Box::new(ConstantTexture::new(Spectrum::new(1.0))),
+            Box::new(ConstantTexture::new(1.0)),
+            None,
+            false,
+            Default::default(),
+        )),
+        kind => {
+            warn!(log, "unsupported material {:?}, using diffuse", kind);
+            Material::Matte(MatteMaterial::new(
+                log,
+                spectrum(&["Kd", "reflectance"], 0.5),
+                None,
+            ))
+        }
+    };
+
+    if params.contains("normalmap") {
+        warn!(
+            log,
+            "ignoring normal map, pbrt normal maps aren't supported"
+        );
+    }
+    with_bump(log, result, float_opt(&["bumpmap", "displacement"]))
+}
+
+fn area_light_from_pbrt(
+    shape: &pbrt::Shape,
+    ke: &Option<Arc<dyn SyncTexture<Spectrum>>>,
+    primitive_shape: &Arc<Shape>,
+    lights: &mut Vec<Arc<dyn SyncLight>>,
+) -> Option<Arc<DiffuseAreaLight>> {
+    let params = shape.area_light.as_ref()?;
+    let light = Arc::new(DiffuseAreaLight::new(
+        Arc::clone(ke.as_ref()?),
+        Arc::clone(primitive_shape),
+        params.integer("nsamples").unwrap_or(1).max(1) as usize,
+        params.bool("twosided").unwrap_or(false),
+    ));
+    lights.push(Arc::clone(&light) as Arc<dyn SyncLight>);
+    Some(light)
+}
+
+/// cubic bezier curves of `P`, consecutive segments sharing their end points
+fn parse_curve(
+    log: &slog::Logger,
+    shape: &pbrt::Shape,
+    material: &Arc<Material>,
+    primitives: &mut Vec<Arc<dyn SyncPrimitive>>,
+) {
+    let params = &shape.params;
+    let basis = params.string("basis").unwrap_or("bezier");
+    let degree = params.integer("degree").unwrap_or(3);
+    if basis != "bezier" || degree != 3 {
+        warn!(
+            log,
+            "skipping curve, only cubic bezier curves are supported";
+            "basis" => basis,
+            "degree" => degree
+        );
+        return;
+    }
+    let cp = params.points("P").unwrap_or_default();
+    if cp.len() < 4 || (cp.len() - 1) % 3 != 0 {
+        warn!(log, "skipping curve with {} control points", cp.len());
+        return;
+    }
+
+    let curve_type = match params.string("type") {
+        Some("cylinder") => CurveType::Cylinder,
+        Some("ribbon") => CurveType::Ribbon,
+        _ => CurveType::Flat,
+    };
+    let width_0 = params
+        .float("width0")
+        .or_else(|| params.float("width"))
+        .unwrap_or(1.0);
+    let width_1 = params
+        .float("width1")
+        .or_else(|| params.float("width"))
+        .unwrap_or(1.0);
+    let normals = params.vectors("N").unwrap_or_default();
+    let split_depth = params.integer("splitdepth").unwrap_or(3).max(0) as u32;
+
+    let n_segments = (cp.len() - 1) / 3;
+    for segment in 0..n_segments {
+        let p = |i: usize| shape.obj_to_world * cp[3 * segment + i];
+        let width = |u: f32| width_0 + (width_1 - width_0) * u;
+        let u_0 = segment as f32 / n_segments as f32;
+        let u_1 = (segment + 1) as f32 / n_segments as f32;
+        let segment_normals = if curve_type == CurveType::Ribbon && normals.len() > n_segments {
+            Some([
+                shape.obj_to_world * normals[segment],
+                shape.obj_to_world * normals[segment + 1],
+            ])
+        } else {
+            None
+        };
+        let common = Arc::new(CurveCommon::new(
+            [p(0), p(1), p(2), p(3)],
+            width(u_0),
+            width(u_1),
+            curve_type,
+            segment_normals,
+        ));
+        for curve in curves_from_common(&common, split_depth) {
+            primitives.push(Arc::new(CurvePrimitive::new(curve, Arc::clone(material)))
+                as Arc<dyn SyncPrimitive>);
+        }
+    }
+}
+
+fn parse_shape(
+    log: &slog::Logger,
+    scene: &pbrt::Scene,
+    shape: &pbrt::Shape,
+    materials: &[Arc<Material>],
+    displacements: &HashMap<String, Displacement>,
+    dicing_rate: &DicingRate,
+    primitives: &mut Vec<Arc<dyn SyncPrimitive>>,
+    meshes: &mut Vec<Arc<TriangleMesh>>,
+    lights: &mut Vec<Arc<dyn SyncLight>>,
+) {
+    let material = Arc::clone(&materials[shape.material]);
+    if shape.kind == "curve" {
+        parse_curve(log, shape, &material, primitives);
+        return;
+    }
+
+    let params = &shape.params;
+    let obj_to_world = &shape.obj_to_world;
+    let reverse_orientation = shape.reverse_orientation;
+    let ke = shape.area_light.as_ref().map(|area_light| {
+        let l = area_light.rgb("L").unwrap_or([1.0; 3]);
+        Arc::new(ConstantTexture::new(
+            area_light.float("scale").unwrap_or(1.0) * Spectrum::from_slice_3(&l, false),
+        )) as Arc<dyn SyncTexture<Spectrum>>
+    });
+
+    // the tessellations of the analytic shapes only feed the preview, renders intersect the
+    // exact surfaces
+    let analytic = match shape.kind.as_str() {
+        "sphere" => {
+            let radius = params.float("radius").unwrap_or(1.0);
+            Some(Shape::Sphere(
+                Sphere::new(obj_to_world, radius, reverse_orientation).with_clipping(
+                    params.float("zmin").unwrap_or(-radius),
+                    params.float("zmax").unwrap_or(radius),
+                    params.float("phimax").unwrap_or(360.0),
+                ),
+            ))
+        }
+        "disk" => Some(Shape::Disk(
+            Disk::new(
+                obj_to_world,
+                params.float("height").unwrap_or(0.0),
+                params.float("radius").unwrap_or(1.0),
+                reverse_orientation,
+            )
+            .with_clipping(
+                params.float("innerradius").unwrap_or(0.0),
+                params.float("phimax").unwrap_or(360.0),
+            ),
+        )),
+        "cylinder" => Some(Shape::Cylinder(
+            Cylinder::new(
+                obj_to_world,
+                params.float("radius").unwrap_or(1.0),
+                params.float("zmin").unwrap_or(-1.0),
+                params.float("zmax").unwrap_or(1.0),
+                reverse_orientation,
+            )
+            .with_clipping(params.float("phimax").unwrap_or(360.0)),
+        )),
+        _ => None,
+    };
+
+    let mesh = match pbrt::shape_mesh(log, &scene.resolver, shape) {
+        Some(mesh) => mesh,
+        None => return,
+    };
+
+    if let Some(analytic) = analytic {
+        meshes.push(Arc::new(TriangleMesh::new_with_transform(
+            mesh.indices,
+            mesh.pos,
+            mesh.normal,
+            vec![],
+            mesh.uv,
+            vec![],
+            None,
+            obj_to_world,
+        )));
+
+        let analytic = Arc::new(analytic);
+        primitives.push(Arc::new(GeometricPrimitive::new(
+            Arc::clone(&analytic),
+            material,
+            area_light_from_pbrt(shape, &ke, &analytic, lights),
+        )) as Arc<dyn SyncPrimitive>);
+        return;
+    }
+
+    let alpha_mask = match params.texture("alpha") {
+        Some(_) => float_param_opt(log, scene, params, &["alpha"]).map(Arc::from),
+        None => params
+            .float("alpha")
+            .filter(|alpha| *alpha < 1.0)
+            .map(|alpha| Arc::new(ConstantTexture::new(alpha)) as Arc<dyn SyncTexture<f32>>),
+    };
+    let tangents = params
+        .vectors("S")
+        .filter(|s| s.len() == mesh.pos.len())
+        .unwrap_or_default();
+    let world_mesh = Arc::new(TriangleMesh::new_with_transform(
+        mesh.indices,
+        mesh.pos,
+        mesh.normal,
+        tangents,
+        mesh.uv,
+        mesh.colors,
+        alpha_mask,
+        obj_to_world,
+    ));
+
+    let displacement = scene.materials[shape.material]
+        .name
+        .as_ref()
+        .and_then(|name| displacements.get(name));
+    let world_mesh = match displacement {
+        Some(displacement) => displacement
+            .displace(log, &world_mesh, dicing_rate)
+            .map_or(world_mesh, Arc::new),
+        None => world_mesh,
+    };
+
+    meshes.push(world_mesh.clone());
+
+    let transform_swaps_handedness = swaps_handedness(obj_to_world);
+    for indices in &world_mesh.indices {
+        let triangle = Arc::new(Shape::Triangle(Triangle::new(
+            Arc::clone(&world_mesh),
+            [indices[0], indices[1], indices[2]],
+            reverse_orientation,
+            transform_swaps_handedness,
+        )));
+        let area_light = area_light_from_pbrt(shape, &ke, &triangle, lights);
+
+        primitives.push(Arc::new(GeometricPrimitive::new(
+            triangle,
+            Arc::clone(&material),
+            area_light,
+        )) as Arc<dyn SyncPrimitive>);
+    }
+}
+
+/// equal area octahedral maps are square, pbrt-v3's lat-long ones twice as wide as they're high
+fn env_mapping(path: &std::path::Path) -> EnvMapping {
+    match image::image_dimensions(path) {
+        Ok((width, height)) if width == height => EnvMapping::Octahedral,
+        _ => EnvMapping::LatLong,
+    }
+}
+
+impl RenderScene {
+    pub fn from_pbrt(
+        log: &slog::Logger,
+        scene: &pbrt::Scene,
+        material_library: Option<&MaterialLibrary>,
+        dicing_camera: &Camera,
+        bvh_cache: &accelerator::BVHCache,
+    ) -> Self {
+        let log = log.new(o!("module" => "scene"));
+        let mut primitives: Vec<Arc<dyn SyncPrimitive>> = Vec::new();
+        let mut lights: Vec<Arc<dyn SyncLight>> = Vec::new();
+        let mut infinite_lights: Vec<Arc<dyn SyncLight>> = Vec::new();
+        let mut meshes: Vec<Arc<TriangleMesh>> = Vec::new();
+
+        let materials: Vec<Arc<Material>> = scene
+            .materials
+            .iter()
+            .map(|material| {
+                let library_material = material.name.as_ref().and_then(|name| {
+                    material_library.and_then(|library| library.material(&log, name))
+                });
+                Arc::new(
+                    library_material.unwrap_or_else(|| material_from_pbrt(&log, scene, material)),
+                )
+            })
+            .collect();
+
+        let displacements: HashMap<String, Displacement> = material_library
+            .map(|library| {
+                scene
+                    .named_materials
+                    .keys()
+                    .filter_map(|name| Some((name.clone(), library.displacement(&log, name)?)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let dicing_rate = DicingRate::from_camera(dicing_camera, DEFAULT_MAX_EDGE_PIXELS);
+
+        for shape in &scene.shapes {
+            parse_shape(
+                &log,
+                scene,
+                shape,
+                &materials,
+                &displacements,
+                &dicing_rate,
+                &mut primitives,
+                &mut meshes,
+                &mut lights,
+            );
+        }
+
+        let bvh = Box::new(accelerator::BVH::with_cache(
+            &log, primitives, &4, bvh_cache,
+        ));
+        let world_bound = bvh.world_bound();
+
+        for light in &scene.lights {
+            let params = &light.params;
+            let scale = params.float("scale").unwrap_or(1.0);
+            let spectrum = |name: &str| {
+                scale * Spectrum::from_slice_3(&params.rgb(name).unwrap_or([1.0; 3]), false)
+            };
+            match light.kind.as_str() {
+                "point" | "spot" | "goniometric" | "projection" => {
+                    if light.kind != "point" {
+                        warn!(log, "{} light is approximated by a point light", light.kind);
+                    }
+                    let from = params.point("from").unwrap_or_else(na::Point3::origin);
+                    let light_to_world: na::Projective3<f32> =
+                        light.light_to_world * na::Translation3::from(from.coords);
+                    lights.push(Arc::new(PointLight::new(&light_to_world, spectrum("I"))));
+                }
+                "distant" => {
+                    let from = params.point("from").unwrap_or_else(na::Point3::origin);
+                    let to = params
+                        .point("to")
+                        .unwrap_or_else(|| na::Point3::new(0.0, 0.0, 1.0));
+                    let mut distant_light =
+                        DirectionalLight::new(&light.light_to_world, spectrum("L"), from - to);
+                    distant_light.preprocess(&world_bound);
+                    lights.push(Arc::new(distant_light));
+                }
+                "infinite" => {
+                    let file_path = params
+                        .string("filename")
+                        .or_else(|| params.string("mapname"))
+                        .map(|filename| scene.resolver.resolve(filename));
+                    let mapping = file_path
+                        .as_ref()
+                        .map_or(EnvMapping::LatLong, |path| env_mapping(path));
+                    let mut env_light = InfiniteAreaLight::with_mapping(
+                        &log,
+                        light.light_to_world,
+                        spectrum("L"),
+                        file_path
+                            .as_ref()
+                            .and_then(|path| path.to_str())
+                            .unwrap_or_default(),
+                        mapping,
+                    );
+                    env_light.preprocess(&world_bound);
+                    let env_light = Arc::new(env_light) as Arc<dyn SyncLight>;
+                    lights.push(Arc::clone(&env_light));
+                    infinite_lights.push(Arc::clone(&env_light));
+                }
+                kind => warn!(log, "skipping unsupported light {:?}", kind),
+            }
+        }
+
+        Self {
+            scene: bvh,
+            lights,
+            infinite_lights,
+            meshes,
+            instanced_meshes: vec![],
+            texture_cache: Arc::clone(&scene.texture_cache),
+            accelerator: None,
+        }
+    }
+}
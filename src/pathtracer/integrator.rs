@@ -1,8 +1,17 @@
+use super::accelerator::{take_traversal_stats, Hit};
+use super::arena::MemoryArena;
 use super::interaction::SurfaceMediumInteraction;
-use super::sampler::{Sampler, SamplerBuilder};
-use super::{bxdf::BxDFType, light::is_delta_light};
+use super::material::{shadow_catcher::ShadowCatcherMaterial, Material};
+use super::primitive::{GeometricPrimitive, TransformedPrimitive};
+use super::sampler::{Sampler, SamplerBuilder, SamplerInterface};
+use super::shape::{Triangle, TriangleMesh};
+use super::{
+    bxdf::{reflect, BxDFType},
+    light::is_delta_light,
+};
 use super::{light::SyncLight, RenderScene, TransportMode};
-use crate::common::ray::RayDifferential;
+use crate::common::film::Film;
+use crate::common::ray::{Ray, RayDifferential};
 use crate::common::spectrum::Spectrum;
 use crate::common::Camera;
 use crate::common::{bounds::Bounds2i, math::power_heuristic};
@@ -12,7 +21,32 @@ use itertools::Itertools;
 #[cfg(not(feature = "disable_rayon"))]
 use {indicatif::ParallelProgressIterator, rayon::prelude::*};
 
-use std::time::Instant;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    time::Instant,
+};
+
+/// edge length in pixels of the tiles the film is split into for rendering
+pub const TILE_SIZE: i32 = 16;
+
+/// what a render thread keeps across the tiles it renders
+type TileState = (Sampler, MemoryArena);
+
+const EXPOSURE_GRID_SIZE: usize = 16;
+const MIDDLE_GRAY: f32 = 0.18;
+
+// samples used to estimate the reflectance of lobes without a cached table
+const ALBEDO_SAMPLES: usize = 16;
+
+// depth window around a g-buffer hit searched for the surface, relative to its distance
+const GBUFFER_DEPTH_TOLERANCE: f32 = 1e-2;
+
+// sampler dimensions taken by the camera sample, the film position, time and lens position
+const CAMERA_DIMENSIONS: usize = 5;
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum LightStrategy {
@@ -20,68 +54,89 @@ pub enum LightStrategy {
     UniformSampleOne,
 }
 
+/// Estimates direct lighting from `light` with one light sample per entry of `u_light` and one
+/// bsdf sample per entry of `u_scattering`, combined with multi-sample MIS
 fn estimate_direct(
     it: &SurfaceMediumInteraction,
-    u_scattering: &na::Point2<f32>,
+    u_scattering: &[na::Point2<f32>],
     light: &dyn SyncLight,
-    u_light: &na::Point2<f32>,
+    u_light: &[na::Point2<f32>],
     scene: &RenderScene,
     sampler: &Sampler,
     handle_media: bool,
     specular: bool,
+    stats: &mut RayStats,
 ) -> Spectrum {
     let bsdf_flags = if specular {
         BxDFType::BSDF_ALL
     } else {
         BxDFType::BSDF_ALL - BxDFType::BSDF_SPECULAR
     };
+    let n_light = u_light.len() as i32;
+    let n_scattering = u_scattering.len() as i32;
     let mut ld = Spectrum::new(0.0);
 
-    let mut wi = na::Vector3::zeros();
-    let mut light_pdf = 0.0;
-    let mut scattering_pdf = 0.0;
-    let mut visibility = None;
-    let mut li = light.sample_li(
-        &it.general,
-        &u_light,
-        &mut wi,
-        &mut light_pdf,
-        &mut visibility,
-    );
-    let visibility = visibility.unwrap();
-    if light_pdf > 0.0 && !li.is_black() {
-        let f: Spectrum;
-        if it.is_surface_interaction() {
-            let bsdf = it.bsdf.as_ref().unwrap();
-            f = bsdf.f(&it.general.wo, &wi, bsdf_flags) * wi.dot(&it.shading.n).abs();
-            scattering_pdf = bsdf.pdf(&it.general.wo, &wi, bsdf_flags);
-        } else {
-            panic!("medium interaction not supported!");
-        }
-
-        if !f.is_black() {
-            if handle_media {
-                panic!("media not supported");
+    // the shadow rays of all the light samples are traced together once they're known
+    let mut shadow_rays = Vec::with_capacity(u_light.len());
+    let mut unoccluded_ld = Vec::with_capacity(u_light.len());
+    for u_light in u_light {
+        let mut wi = na::Vector3::zeros();
+        let mut light_pdf = 0.0;
+        let scattering_pdf;
+        let mut visibility = None;
+        let li = light.sample_li(
+            &it.general,
+            &u_light,
+            &mut wi,
+            &mut light_pdf,
+            &mut visibility,
+        );
+        let visibility = visibility.unwrap();
+        if light_pdf > 0.0 && !li.is_black() {
+            let f: Spectrum;
+            if it.is_surface_interaction() {
+                let bsdf = it.bsdf.as_ref().unwrap();
+                f = bsdf.f(&it.general.wo, &wi, bsdf_flags) * wi.dot(&it.shading.n).abs();
+                scattering_pdf = bsdf.pdf(&it.general.wo, &wi, bsdf_flags);
             } else {
-                if !visibility.unoccluded(&scene) {
-                    li = Spectrum::new(0.0);
-                }
+                panic!("medium interaction not supported!");
             }
 
-            if !li.is_black() {
+            if !f.is_black() {
+                if handle_media {
+                    panic!("media not supported");
+                } else {
+                    stats.shadow_rays += 1;
+                    shadow_rays.push(visibility.ray());
+                }
+
                 if is_delta_light(&light.flags()) {
-                    ld += f * li / light_pdf;
+                    unoccluded_ld.push(f * li / (light_pdf * n_light as f32));
                 } else {
-                    let weight = power_heuristic(1, light_pdf, 1, scattering_pdf);
-                    ld += f * li * weight / light_pdf;
+                    let weight = power_heuristic(n_light, light_pdf, n_scattering, scattering_pdf);
+                    unoccluded_ld.push(f * li * weight / (light_pdf * n_light as f32));
                 }
             }
         }
     }
 
-    if !is_delta_light(&light.flags()) {
+    let mut occluded = vec![false; shadow_rays.len()];
+    scene.intersect_p_n(&shadow_rays, &mut occluded);
+    for (sample_ld, occluded) in unoccluded_ld.iter().zip(&occluded) {
+        if !occluded {
+            ld += *sample_ld;
+        }
+    }
+
+    if is_delta_light(&light.flags()) {
+        return ld;
+    }
+
+    for u_scattering in u_scattering {
+        let mut wi = na::Vector3::zeros();
+        let mut scattering_pdf = 0.0;
         let mut f;
-        let mut sampled_specular = false;
+        let sampled_specular;
 
         if it.is_surface_interaction() {
             let mut sampled_type = Some(BxDFType::BSDF_ALL);
@@ -103,11 +158,11 @@ fn estimate_direct(
         if !f.is_black() && scattering_pdf > 0.0 {
             let mut weight = 1.0;
             if !sampled_specular {
-                light_pdf = light.pdf_li(&it.general, &wi);
+                let light_pdf = light.pdf_li(&it.general, &wi);
                 if light_pdf == 0.0 {
-                    return ld;
+                    continue;
                 }
-                weight = power_heuristic(1, scattering_pdf, 1, light_pdf);
+                weight = power_heuristic(n_scattering, scattering_pdf, n_light, light_pdf);
             }
 
             let mut light_isect = SurfaceMediumInteraction::default();
@@ -116,6 +171,7 @@ fn estimate_direct(
             let found_surface_interaction = if handle_media {
                 panic!("medium interaction not supported!")
             } else {
+                stats.shadow_rays += 1;
                 scene.intersect(&mut ray, &mut light_isect)
             };
 
@@ -130,7 +186,7 @@ fn estimate_direct(
                 li = light.le(&RayDifferential::new(ray));
             }
             if !li.is_black() {
-                ld += f * li * tr * weight / scattering_pdf;
+                ld += f * li * tr * weight / (scattering_pdf * n_scattering as f32);
             }
         }
     }
@@ -143,6 +199,7 @@ fn uniform_sample_all_lights(
     scene: &RenderScene,
     sampler: &mut Sampler,
     num_light_samples: &Vec<usize>,
+    stats: &mut RayStats,
 ) -> Spectrum {
     let mut l = Spectrum::new(0.0);
 
@@ -157,13 +214,14 @@ fn uniform_sample_all_lights(
             let u_scattering = sampler.get_2d();
             l += estimate_direct(
                 &it,
-                &u_scattering,
+                std::slice::from_ref(&u_scattering),
                 light,
-                &u_light,
+                std::slice::from_ref(&u_light),
                 &scene,
                 &sampler,
                 false,
                 false,
+                stats,
             );
         } else {
             let mut ld = Spectrum::new(0.0);
@@ -172,13 +230,14 @@ fn uniform_sample_all_lights(
             for k in 0..num_samples {
                 ld += estimate_direct(
                     &it,
-                    &u_scattering_array[k],
+                    std::slice::from_ref(&u_scattering_array[k]),
                     light,
-                    &u_light_array[k],
+                    std::slice::from_ref(&u_light_array[k]),
                     &scene,
                     &sampler,
                     false,
                     false,
+                    stats,
                 );
             }
             l += ld / num_samples as f32;
@@ -193,14 +252,23 @@ fn uniform_sample_one_light(
     it: &SurfaceMediumInteraction,
     scene: &RenderScene,
     sampler: &mut Sampler,
+    n_light_samples: usize,
+    n_bsdf_samples: usize,
+    stats: &mut RayStats,
 ) -> Spectrum {
     let num_lights = scene.lights.len();
     if num_lights == 0 {
         return Spectrum::new(0.0);
     }
 
-    let u_light = sampler.get_2d();
-    let u_scattering = sampler.get_2d();
+    // the first shading point of a path takes the arrays requested for it, stratified across
+    // the samples of the pixel
+    let samples_2d = |n: usize| match sampler.get_2d_array(n) {
+        Some(array) => array[..n].to_vec(),
+        None => (0..n).map(|_| sampler.get_2d()).collect::<Vec<_>>(),
+    };
+    let u_light = samples_2d(n_light_samples);
+    let u_scattering = samples_2d(n_bsdf_samples);
     let light_idx = ((sampler.get_1d() * num_lights as f32).floor() as usize).min(num_lights - 1);
     let light = scene.lights[light_idx].as_ref();
     num_lights as f32
@@ -213,16 +281,497 @@ fn uniform_sample_one_light(
             &sampler,
             false,
             false,
+            stats,
         )
 }
 
+/// fraction of the light reaching `it` from a randomly picked light that the scene blocks,
+/// estimated by light sampling alone. it's the shadow a shadow catcher shows, independent of the
+/// color of the catcher. the light samples come from the dimensions of the bounce, the arrays are
+/// left to the first shading point the catcher mirrors
+fn shadow_catcher_occlusion(
+    it: &SurfaceMediumInteraction,
+    scene: &RenderScene,
+    sampler: &mut Sampler,
+    n_light_samples: usize,
+    stats: &mut RayStats,
+) -> f32 {
+    let num_lights = scene.lights.len();
+    if num_lights == 0 {
+        return 0.0;
+    }
+
+    let u_light = (0..n_light_samples)
+        .map(|_| sampler.get_2d())
+        .collect::<Vec<_>>();
+    let light_idx = ((sampler.get_1d() * num_lights as f32).floor() as usize).min(num_lights - 1);
+    let light = scene.lights[light_idx].as_ref();
+
+    // lit from the side the catcher is seen from
+    let n = it.shading.n * it.general.wo.dot(&it.shading.n).signum();
+    let mut shadow_rays = Vec::with_capacity(u_light.len());
+    let mut unoccluded_y = Vec::with_capacity(u_light.len());
+    for u_light in &u_light {
+        let mut wi = na::Vector3::zeros();
+        let mut light_pdf = 0.0;
+        let mut visibility = None;
+        let li = light.sample_li(
+            &it.general,
+            &u_light,
+            &mut wi,
+            &mut light_pdf,
+            &mut visibility,
+        );
+        let cos = wi.dot(&n);
+        if light_pdf > 0.0 && !li.is_black() && cos > 0.0 {
+            stats.shadow_rays += 1;
+            shadow_rays.push(visibility.unwrap().ray());
+            unoccluded_y.push((li * cos / light_pdf).y());
+        }
+    }
+
+    let mut occluded = vec![false; shadow_rays.len()];
+    scene.intersect_p_n(&shadow_rays, &mut occluded);
+    let total: f32 = unoccluded_y.iter().sum();
+    if total <= 0.0 {
+        return 0.0;
+    }
+    let blocked: f32 = unoccluded_y
+        .iter()
+        .zip(&occluded)
+        .filter(|(_, &occluded)| occluded)
+        .map(|(y, _)| y)
+        .sum();
+
+    (blocked / total).clamp(0.0, 1.0)
+}
+
+/// the shadow catcher material of the surface at `isect`, if it has one
+fn shadow_catcher<'a>(isect: &SurfaceMediumInteraction<'a>) -> Option<&'a ShadowCatcherMaterial> {
+    match isect.primitive?.get_material() {
+        Material::ShadowCatcher(catcher) => Some(catcher),
+        _ => None,
+    }
+}
+
+/// a camera sample as handed to the sample callbacks
+#[derive(Clone, Debug)]
+pub struct PixelSample {
+    pub pixel: na::Point2<i32>,
+    pub p_film: na::Point2<f32>,
+    pub sample_index: usize,
+    pub radiance: Spectrum,
+    /// first hit attributes, only computed when a callback asks for them
+    pub aux: Option<SampleAux>,
+}
+
+/// attributes of the first surface hit by a camera ray, zero with infinite depth on a miss
+#[derive(Clone, Debug)]
+pub struct SampleAux {
+    pub albedo: Spectrum,
+    pub normal: na::Vector3<f32>,
+    pub depth: f32,
+}
+
+/// hook for consuming the samples of a render directly instead of through the film, e.g. to
+/// write training datasets. callbacks are invoked from the render threads
+pub trait SampleCallback: Send + Sync {
+    /// whether samples should carry first hit attributes, which costs extra rays per sample
+    fn wants_aux(&self) -> bool {
+        false
+    }
+
+    fn on_sample(&self, _sample: &PixelSample) {}
+
+    /// called after a tile was merged into the film, with every sample taken inside it
+    fn on_tile(&self, _tile_bounds: &Bounds2i, _samples: &[PixelSample]) {}
+}
+
+/// first visible surface of a pixel as rasterized by the viewer
+#[derive(Clone, Copy, Debug)]
+pub struct GBufferTexel {
+    pub position: na::Point3<f32>,
+    pub normal: na::Vector3<f32>,
+    /// index into `RenderScene::gbuffer_meshes`, one based
+    pub mesh_id: u32,
+    pub instance: u32,
+    pub triangle: u32,
+    /// weights of the second and third corner of the triangle
+    pub barycentrics: na::Point2<f32>,
+}
+
+/// primary visibility rasterized at film resolution, in row major order. mesh ids start at one,
+/// zero marks pixels where no geometry was drawn. meshes are numbered like
+/// `RenderScene::gbuffer_meshes`, along with the copy and the triangle of the mesh drawn
+pub struct GBuffer {
+    pub resolution: glm::UVec2,
+    pub position: Vec<na::Point3<f32>>,
+    pub normal: Vec<na::Vector3<f32>>,
+    pub mesh_id: Vec<u32>,
+    pub instance: Vec<u32>,
+    pub triangle: Vec<u32>,
+    pub barycentrics: Vec<na::Point2<f32>>,
+}
+
+impl GBuffer {
+    /// `None` for background pixels and pixels outside of the film
+    pub fn texel(&self, pixel: &na::Point2<i32>) -> Option<GBufferTexel> {
+        if pixel.x < 0
+            || pixel.y < 0
+            || pixel.x >= self.resolution.x as i32
+            || pixel.y >= self.resolution.y as i32
+        {
+            return None;
+        }
+
+        let idx = (pixel.y * self.resolution.x as i32 + pixel.x) as usize;
+        if self.mesh_id[idx] == 0 {
+            None
+        } else {
+            Some(GBufferTexel {
+                position: self.position[idx],
+                normal: self.normal[idx],
+                mesh_id: self.mesh_id[idx],
+                instance: self.instance[idx],
+                triangle: self.triangle[idx],
+                barycentrics: self.barycentrics[idx],
+            })
+        }
+    }
+}
+
+/// a triangle a g-buffer drew, for the copy of its mesh it was drawn for
+#[derive(Clone, Copy)]
+struct GBufferSurface<'a> {
+    primitive: &'a GeometricPrimitive,
+    triangle: &'a Triangle,
+    /// to and from the world, `None` for meshes in world space
+    transforms: Option<(na::Projective3<f32>, na::Projective3<f32>)>,
+}
+
+impl<'a> GBufferSurface<'a> {
+    /// the hit of `r` on the triangle, built without tracing the scene. `false` when the texel
+    /// doesn't lie on the triangle its ids name, e.g. for copies moved after the g-buffer was
+    /// drawn, or when the jittered ray passes beside the triangle
+    fn intersect(
+        &self,
+        r: &mut Ray,
+        texel: &GBufferTexel,
+        isect: &mut SurfaceMediumInteraction<'a>,
+    ) -> bool {
+        let b = texel.barycentrics;
+        let p = self.triangle.point(&[1.0 - b.x - b.y, b.x, b.y]);
+        let p = match &self.transforms {
+            Some((prim_to_world, _)) => prim_to_world * p,
+            None => p,
+        };
+        if (p - texel.position).norm() > GBUFFER_DEPTH_TOLERANCE * (texel.position - r.o).norm() {
+            return false;
+        }
+
+        let local = match &self.transforms {
+            Some((_, world_to_prim)) => TransformedPrimitive::ray_to_primitive(world_to_prim, r),
+            None => r.clone(),
+        };
+        let (b, t) = match self.triangle.ray_hit(&local) {
+            Some(hit) => hit,
+            None => return false,
+        };
+        if !self.triangle.interaction(&local, b, isect) {
+            return false;
+        }
+
+        r.t_max = t;
+        isect.general.time = r.time;
+        isect.primitive = Some(self.primitive);
+        if let Some((prim_to_world, world_to_prim)) = &self.transforms {
+            TransformedPrimitive::interaction_to_world(prim_to_world, world_to_prim, r, isect);
+        }
+        true
+    }
+}
+
+/// looks up the triangles g-buffer ids name
+struct GBufferSurfaces<'a> {
+    /// meshes in the order of the mesh ids, with the transforms of their copies when instanced
+    meshes: Vec<(
+        &'a Arc<TriangleMesh>,
+        Option<Vec<(na::Projective3<f32>, na::Projective3<f32>)>>,
+    )>,
+    /// primitives by the address of their mesh and the vertex indices of their triangle
+    primitives: HashMap<(usize, [u32; 3]), &'a GeometricPrimitive>,
+}
+
+impl<'a> GBufferSurfaces<'a> {
+    fn new(scene: &'a RenderScene) -> Self {
+        let meshes = scene
+            .gbuffer_meshes()
+            .into_iter()
+            .enumerate()
+            .map(|(i, (mesh, instances))| {
+                let transforms = if i < scene.meshes.len() {
+                    None
+                } else {
+                    Some(
+                        instances
+                            .iter()
+                            .map(|obj_to_world| (*obj_to_world, obj_to_world.inverse()))
+                            .collect(),
+                    )
+                };
+                (mesh, transforms)
+            })
+            .collect();
+
+        let mut primitives = HashMap::new();
+        scene.for_each_geometric(&mut |primitive| {
+            if let Some(triangle) = primitive.triangle() {
+                let mesh = Arc::as_ptr(triangle.mesh()) as usize;
+                primitives.insert((mesh, triangle.indices()), primitive);
+            }
+        });
+
+        Self { meshes, primitives }
+    }
+
+    /// `None` when the ids don't name a triangle of the scene
+    fn surface(&self, texel: &GBufferTexel) -> Option<GBufferSurface<'a>> {
+        let (mesh, transforms) = self.meshes.get(texel.mesh_id.checked_sub(1)? as usize)?;
+        let indices = mesh.indices.get(texel.triangle as usize)?;
+        let primitive = *self.primitives.get(&(
+            Arc::as_ptr(mesh) as usize,
+            [indices.x, indices.y, indices.z],
+        ))?;
+        let transforms = match transforms {
+            Some(transforms) => Some(*transforms.get(texel.instance as usize)?),
+            None => None,
+        };
+
+        Some(GBufferSurface {
+            primitive,
+            triangle: primitive.triangle()?,
+            transforms,
+        })
+    }
+}
+
+/// how a path finds its first surface
+enum PrimaryHit<'a> {
+    Trace,
+    Background,
+    /// rasterized, with the triangle its ids name when they're found in the scene
+    Surface(GBufferTexel, Option<GBufferSurface<'a>>),
+    /// traced already, along with the other camera rays of its pixel
+    Traced(Hit<'a>),
+}
+
+/// work done by the path of a single camera sample
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RayStats {
+    pub bounces: u32,
+    pub shadow_rays: u32,
+    pub nodes_visited: u64,
+    pub primitives_tested: u64,
+}
+
+#[derive(Clone, Copy, Default)]
+struct PixelRayStats {
+    samples: u32,
+    bounces: u64,
+    shadow_rays: u64,
+    nodes_visited: u64,
+    primitives_tested: u64,
+}
+
+/// colors of the heat maps from cold to hot, evenly spaced
+const HEAT_MAP_COLORS: [[f32; 3]; 5] = [
+    [0.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0],
+    [0.0, 1.0, 0.0],
+    [1.0, 1.0, 0.0],
+    [1.0, 0.0, 0.0],
+];
+
+/// per pixel averages of the bounces, shadow rays and bvh traversal of the paths traced during
+/// the last render, to see where render time goes and correlate noise with path depth
+pub struct RayStatsAov {
+    resolution: glm::UVec2,
+    pixels: RwLock<Vec<PixelRayStats>>,
+}
+
+impl RayStatsAov {
+    pub fn new(resolution: &glm::UVec2) -> Self {
+        Self {
+            resolution: *resolution,
+            pixels: RwLock::new(vec![
+                PixelRayStats::default();
+                (resolution.x * resolution.y) as usize
+            ]),
+        }
+    }
+
+    fn merge(&self, samples: &[(na::Point2<i32>, RayStats)]) {
+        let mut pixels = self.pixels.write().unwrap();
+        for (pixel, stats) in samples {
+            // samples in the filter border around the film belong to no pixel
+            if pixel.x < 0
+                || pixel.y < 0
+                || pixel.x >= self.resolution.x as i32
+                || pixel.y >= self.resolution.y as i32
+            {
+                continue;
+            }
+            let p = &mut pixels[(pixel.y * self.resolution.x as i32 + pixel.x) as usize];
+            p.samples += 1;
+            p.bounces += stats.bounces as u64;
+            p.shadow_rays += stats.shadow_rays as u64;
+            p.nodes_visited += stats.nodes_visited;
+            p.primitives_tested += stats.primitives_tested;
+        }
+    }
+
+    fn clear(&self) {
+        for p in self.pixels.write().unwrap().iter_mut() {
+            *p = PixelRayStats::default();
+        }
+    }
+
+    fn averages<F>(&self, count: F) -> Vec<f32>
+    where
+        F: Fn(&PixelRayStats) -> u64,
+    {
+        self.pixels
+            .read()
+            .unwrap()
+            .iter()
+            .map(|p| {
+                if p.samples > 0 {
+                    count(p) as f32 / p.samples as f32
+                } else {
+                    0.0
+                }
+            })
+            .collect()
+    }
+
+    /// average bounces per sample in row major order
+    pub fn average_bounces(&self) -> Vec<f32> {
+        self.averages(|p| p.bounces)
+    }
+
+    /// average shadow rays per sample in row major order
+    pub fn average_shadow_rays(&self) -> Vec<f32> {
+        self.averages(|p| p.shadow_rays)
+    }
+
+    /// average bvh nodes visited per sample in row major order
+    pub fn average_nodes_visited(&self) -> Vec<f32> {
+        self.averages(|p| p.nodes_visited)
+    }
+
+    /// average primitives tested in bvh leaves per sample in row major order
+    pub fn average_primitives_tested(&self) -> Vec<f32> {
+        self.averages(|p| p.primitives_tested)
+    }
+
+    /// grayscale image of per pixel `values`, scaled so the largest value is white
+    pub fn to_image(&self, values: &[f32]) -> image::GrayImage {
+        let max = values.iter().cloned().fold(0.0, f32::max).max(1e-6);
+        image::GrayImage::from_fn(self.resolution.x, self.resolution.y, |x, y| {
+            let v = values[(y * self.resolution.x + x) as usize] / max;
+            image::Luma([(v * 255.0).round() as u8])
+        })
+    }
+
+    /// per pixel `values` colored from black through blue, green and yellow to red for the
+    /// largest value
+    pub fn to_heat_map(&self, values: &[f32]) -> image::RgbaImage {
+        let max = values.iter().cloned().fold(0.0, f32::max).max(1e-6);
+        let last = HEAT_MAP_COLORS.len() - 1;
+        image::RgbaImage::from_fn(self.resolution.x, self.resolution.y, |x, y| {
+            let v = values[(y * self.resolution.x + x) as usize] / max * last as f32;
+            let i = (v.floor() as usize).min(last - 1);
+            let t = v - i as f32;
+            let mut rgba = [255u8; 4];
+            for c in 0..3 {
+                let color = (1.0 - t) * HEAT_MAP_COLORS[i][c] + t * HEAT_MAP_COLORS[i + 1][c];
+                rgba[c] = (color * 255.0).round() as u8;
+            }
+            image::Rgba(rgba)
+        })
+    }
+}
+
+/// debug bookkeeping of the sampler dimensions each bounce of a path draws. a bounce drawing past
+/// the dimensions set aside for it shares dimensions with the next bounce, and differing draws
+/// between pixels hint at a consumer missing from the layout. each is logged once per bounce
+struct DimensionCheck {
+    /// dimensions drawn by each bounce on the first path reaching it
+    drawn: Vec<AtomicUsize>,
+    differs: Vec<AtomicBool>,
+    overflows: Vec<AtomicBool>,
+}
+
+impl DimensionCheck {
+    fn new(max_depth: i32) -> Self {
+        let bounces = max_depth.max(0) as usize + 1;
+        Self {
+            drawn: (0..bounces)
+                .map(|_| AtomicUsize::new(std::usize::MAX))
+                .collect(),
+            differs: (0..bounces).map(|_| AtomicBool::new(false)).collect(),
+            overflows: (0..bounces).map(|_| AtomicBool::new(false)).collect(),
+        }
+    }
+
+    fn record(&self, log: &slog::Logger, bounce: usize, drawn: usize, budget: usize) {
+        if bounce >= self.drawn.len() {
+            return;
+        }
+        if drawn > budget && !self.overflows[bounce].swap(true, Ordering::Relaxed) {
+            warn!(
+                log,
+                "bounce {:?} drew {:?} sampler dimensions, past the {:?} set aside for it",
+                bounce,
+                drawn,
+                budget
+            );
+        }
+        let first = match self.drawn[bounce].compare_exchange(
+            std::usize::MAX,
+            drawn,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => drawn,
+            Err(first) => first,
+        };
+        if first != drawn && !self.differs[bounce].swap(true, Ordering::Relaxed) {
+            warn!(
+                log,
+                "bounce {:?} drew {:?} sampler dimensions on one path and {:?} on another",
+                bounce,
+                first,
+                drawn
+            );
+        }
+    }
+}
+
 pub struct PathIntegrator {
     sampler_builder: SamplerBuilder,
     max_depth: i32,
+    n_light_samples: usize,
+    n_bsdf_samples: usize,
     rr_threshold: f32,
     rr_start_depth: i32,
     rr_enable: bool,
     show_progress_bar: bool,
+    callbacks: Vec<Arc<dyn SampleCallback>>,
+    ray_stats: Option<RayStatsAov>,
+    dimension_check: Option<DimensionCheck>,
+    /// camera rays missing the scene see nothing of the infinite lights, which still light it
+    transparent_background: bool,
     log: slog::Logger,
 }
 
@@ -232,10 +781,16 @@ impl PathIntegrator {
         Self {
             sampler_builder,
             max_depth,
+            n_light_samples: 1,
+            n_bsdf_samples: 1,
             rr_threshold: 1.0,
             rr_start_depth: 3,
             rr_enable: true,
             show_progress_bar: true,
+            callbacks: Vec::new(),
+            ray_stats: None,
+            dimension_check: None,
+            transparent_background: false,
             log,
         }
     }
@@ -252,16 +807,84 @@ impl PathIntegrator {
         }
     }
 
+    pub fn add_callback(&mut self, callback: Arc<dyn SampleCallback>) {
+        self.callbacks.push(callback);
+    }
+
+    /// starts recording bounce, shadow ray and bvh traversal counts of the rendered paths per
+    /// pixel
+    pub fn enable_ray_stats(&mut self, resolution: &glm::UVec2) {
+        self.ray_stats = Some(RayStatsAov::new(resolution));
+    }
+
+    pub fn ray_stats(&self) -> Option<&RayStatsAov> {
+        self.ray_stats.as_ref()
+    }
+
+    /// logs bounces drawing more sampler dimensions than set aside for them, or a different
+    /// number on different paths
+    pub fn enable_dimension_check(&mut self) {
+        self.dimension_check = Some(DimensionCheck::new(self.max_depth));
+    }
+
     pub fn toggle_progress_bar(&mut self) {
         self.show_progress_bar = !self.show_progress_bar;
     }
 
+    // number of light and bsdf samples taken for direct lighting at each shading point
+    pub fn set_sample_splitting(&mut self, n_light_samples: usize, n_bsdf_samples: usize) {
+        self.n_light_samples = n_light_samples.max(1);
+        self.n_bsdf_samples = n_bsdf_samples.max(1);
+    }
+
+    pub fn get_sample_splitting(&self) -> (usize, usize) {
+        (self.n_light_samples, self.n_bsdf_samples)
+    }
+
+    /// hides the infinite lights from the camera, leaving the background to be filled in when
+    /// compositing over another, the reflections and lighting they give stay
+    pub fn set_transparent_background(&mut self, transparent_background: bool) {
+        self.transparent_background = transparent_background;
+    }
+
+    pub fn transparent_background(&self) -> bool {
+        self.transparent_background
+    }
+
+    pub fn sampler_builder(&self) -> &SamplerBuilder {
+        &self.sampler_builder
+    }
+
+    /// sampler dimensions set aside for each bounce of a path: the light and bsdf samples of
+    /// direct lighting (left unused where the first bounce takes them from the arrays) or the
+    /// light samples of a shadow catcher, the light choice, the bsdf sample and russian roulette
+    fn bounce_dimensions(&self) -> usize {
+        2 * (self.n_light_samples + self.n_bsdf_samples) + 4
+    }
+
+    /// the dimension `bounce` starts drawing from, the same on every path however many the
+    /// bounces before it drew
+    fn bounce_dimension(&self, bounce: i32) -> usize {
+        CAMERA_DIMENSIONS + bounce.max(0) as usize * self.bounce_dimensions()
+    }
+
+    /// the sampler with the arrays `uniform_sample_one_light` draws the light and bsdf samples
+    /// of the first shading point from
+    fn path_sampler_builder(&self) -> SamplerBuilder {
+        let mut sampler_builder = self.sampler_builder.clone();
+        sampler_builder
+            .request_2d_array(self.n_light_samples)
+            .request_2d_array(self.n_bsdf_samples);
+        sampler_builder
+    }
+
     fn specular_reflect(
         &self,
         r: &RayDifferential,
         isect: &SurfaceMediumInteraction,
         scene: &RenderScene,
         sampler: &mut Sampler,
+        arena: &MemoryArena,
         depth: u32,
     ) -> Spectrum {
         let wo = isect.general.wo;
@@ -297,7 +920,7 @@ impl PathIntegrator {
                 rd.rx_direction = wi - dwodx + 2.0 * (wo.dot(&ns) * dndx + d_dndx * ns);
                 rd.ry_direction = wi - dwody + 2.0 * (wo.dot(&ns) * dndy + d_dndy * ns);
             }
-            l = f * self.li(&rd, &scene, sampler, depth + 1) * wi.dot(&ns).abs() / pdf;
+            l = f * self.li(&rd, &scene, sampler, arena, depth + 1) * wi.dot(&ns).abs() / pdf;
         } else {
             l = Spectrum::new(0.0);
         }
@@ -318,6 +941,7 @@ impl PathIntegrator {
         isect: &SurfaceMediumInteraction,
         scene: &RenderScene,
         sampler: &mut Sampler,
+        arena: &MemoryArena,
         depth: u32,
     ) -> Spectrum {
         let wo = isect.general.wo;
@@ -367,7 +991,7 @@ impl PathIntegrator {
                 rd.rx_direction = wi - eta * dwodx + (mu * dndx + dmudx * ns);
                 rd.ry_direction = wi - eta * dwody + (mu * dndy + dmudy * ns);
             }
-            l = f * self.li(&rd, &scene, sampler, depth + 1) * wi.dot(&ns).abs() / pdf
+            l = f * self.li(&rd, &scene, sampler, arena, depth + 1) * wi.dot(&ns).abs() / pdf
         }
 
         trace!(
@@ -389,8 +1013,87 @@ impl PathIntegrator {
         ray: &RayDifferential,
         scene: &RenderScene,
         sampler: &mut Sampler,
+        arena: &MemoryArena,
+        depth: u32,
+    ) -> Spectrum {
+        self.li_with_stats(ray, scene, sampler, arena, depth, &mut RayStats::default())
+    }
+
+    /// radiance along `ray`, also counting the bounces and shadow rays of the path into `stats`
+    fn li_with_stats(
+        &self,
+        ray: &RayDifferential,
+        scene: &RenderScene,
+        sampler: &mut Sampler,
+        arena: &MemoryArena,
         _depth: u32,
+        stats: &mut RayStats,
+    ) -> Spectrum {
+        self.li_path(
+            ray,
+            scene,
+            sampler,
+            arena,
+            PrimaryHit::Trace,
+            stats,
+            &mut 0.0,
+        )
+    }
+
+    /// intersects `ray` with the surface a g-buffer found for its pixel. the hit is built on the
+    /// triangle the texel names, texels that don't check out against it are probed in a short
+    /// window around the rasterized depth instead, falling back to tracing the whole ray when the
+    /// jittered ray misses the rasterized surface
+    fn intersect_gbuffer<'a>(
+        &self,
+        ray: &mut RayDifferential,
+        texel: &GBufferTexel,
+        surface: Option<&GBufferSurface<'a>>,
+        scene: &'a RenderScene,
+        isect: &mut SurfaceMediumInteraction<'a>,
+    ) -> bool {
+        if let Some(surface) = surface {
+            if surface.intersect(&mut ray.ray, texel, isect) {
+                return true;
+            }
+        }
+
+        let d_norm2 = ray.ray.d.norm_squared();
+        let t = (texel.position - ray.ray.o).dot(&ray.ray.d) / d_norm2;
+        // the depth changes faster across the pixel on surfaces seen at grazing angles
+        let cos = texel.normal.dot(&ray.ray.d).abs() / d_norm2.sqrt();
+        let slack = GBUFFER_DEPTH_TOLERANCE * t / cos.max(0.1);
+
+        if t > 0.0 && t - slack < ray.ray.t_max {
+            let t_min = (t - slack).max(0.0);
+            let mut probe = ray.ray.clone();
+            probe.o = ray.ray.o + ray.ray.d * t_min;
+            probe.t_max = (t + slack).min(ray.ray.t_max) - t_min;
+            if scene.intersect(&mut probe, isect) {
+                ray.ray.t_max = t_min + probe.t_max;
+                return true;
+            }
+        }
+
+        scene.intersect(&mut ray.ray, isect)
+    }
+
+    /// radiance along the camera ray `ray`, also setting `alpha` to the coverage of the scene
+    /// along it, which shadow catchers only cover where they're shadowed or mirror the scene
+    fn li_path<'a>(
+        &self,
+        ray: &RayDifferential,
+        scene: &'a RenderScene,
+        sampler: &mut Sampler,
+        arena: &'a MemoryArena,
+        primary: PrimaryHit<'a>,
+        stats: &mut RayStats,
+        alpha: &mut f32,
     ) -> Spectrum {
+        let mut primary = Some(primary);
+        // the shadowed background behind a shadow catcher the camera ray hit and the fraction of
+        // it the catcher mirrors, resolved once the mirrored ray is traced
+        let mut caught: Option<(Spectrum, f32)> = None;
         let mut l = Spectrum::new(0.0);
         let mut beta = Spectrum::new(1.0);
         let mut ray = ray.clone();
@@ -398,6 +1101,8 @@ impl PathIntegrator {
         let mut bounces: i32 = 0;
 
         let mut eta_scale = 1.0;
+        // absorption coefficient of the volume the path entered through a transmissive surface
+        let mut absorption: Option<Spectrum> = None;
         loop {
             trace!(
                 self.log,
@@ -407,14 +1112,76 @@ impl PathIntegrator {
                 beta
             );
 
+            sampler.set_dimension(self.bounce_dimension(bounces));
             let mut isect = Default::default();
-            let found_intersection = scene.intersect(&mut ray.ray, &mut isect);
+            let found_intersection = match primary.take().unwrap_or(PrimaryHit::Trace) {
+                PrimaryHit::Trace => scene.intersect(&mut ray.ray, &mut isect),
+                PrimaryHit::Background => false,
+                PrimaryHit::Surface(texel, surface) => {
+                    self.intersect_gbuffer(&mut ray, &texel, surface.as_ref(), scene, &mut isect)
+                }
+                PrimaryHit::Traced(hit) => {
+                    isect = hit.isect;
+                    hit.found
+                }
+            };
+
+            if let Some((background, mirrored)) = caught.take() {
+                // only the rest of the scene shows in the catcher, the background it mirrors is
+                // the one of the photograph
+                if !found_intersection || shadow_catcher(&isect).is_some() {
+                    l += background;
+                    break;
+                }
+                l += background * (1.0 - mirrored);
+                *alpha = 1.0 - (1.0 - *alpha) * (1.0 - mirrored);
+            }
+
+            if bounces == 0 {
+                *alpha = if found_intersection { 1.0 } else { 0.0 };
+                if let Some(catcher) = shadow_catcher(&isect).filter(|_| found_intersection) {
+                    let mut background = Spectrum::new(0.0);
+                    if !self.transparent_background {
+                        for light in &scene.infinite_lights {
+                            background += light.le(&ray);
+                        }
+                    }
+                    let occlusion = shadow_catcher_occlusion(
+                        &isect,
+                        &scene,
+                        sampler,
+                        self.n_light_samples,
+                        stats,
+                    );
+                    *alpha = occlusion;
+                    background *= 1.0 - occlusion;
+
+                    let mirrored = catcher.reflectance(&isect);
+                    if mirrored == 0.0 || self.max_depth == 0 {
+                        l += background;
+                        break;
+                    }
+                    let wi = reflect(&-ray.ray.d, &isect.shading.n);
+                    ray = RayDifferential::new(isect.general.spawn_ray(&wi));
+                    beta *= Spectrum::new(mirrored);
+                    specular_bounce = true;
+                    caught = Some((background, mirrored));
+                    bounces += 1;
+                    stats.bounces += 1;
+                    continue;
+                }
+            }
+
+            if let Some(sigma_a) = absorption.filter(|_| found_intersection) {
+                let distance = (isect.general.p - ray.ray.o).norm();
+                beta *= (sigma_a * -distance).exp();
+            }
 
             if bounces == 0 || specular_bounce {
                 if found_intersection {
                     l += beta * isect.le(&-ray.ray.d);
                     trace!(self.log, "added le to l: {:?}", l);
-                } else {
+                } else if bounces > 0 || !self.transparent_background {
                     for light in &scene.infinite_lights {
                         l += beta * light.le(&ray);
                     }
@@ -425,7 +1192,7 @@ impl PathIntegrator {
                 break;
             }
 
-            isect.compute_scattering_functions(&ray, TransportMode::Radiance);
+            isect.compute_scattering_functions(&ray, TransportMode::Radiance, arena);
             if isect.bsdf.is_none() {
                 trace!(self.log, "skipping intersection due to null bsdf");
                 ray = RayDifferential::new(isect.general.spawn_ray(&ray.ray.d));
@@ -436,7 +1203,15 @@ impl PathIntegrator {
             let bsdf = isect.bsdf.as_ref().unwrap();
 
             if bsdf.num_components(BxDFType::BSDF_ALL - BxDFType::BSDF_SPECULAR) > 0 {
-                let ld = beta * uniform_sample_one_light(&isect, &scene, sampler);
+                let ld = beta
+                    * uniform_sample_one_light(
+                        &isect,
+                        &scene,
+                        sampler,
+                        self.n_light_samples,
+                        self.n_bsdf_samples,
+                        stats,
+                    );
                 trace!(self.log, "sampled direct lighting ld: {:?}", ld);
                 l += ld;
             }
@@ -463,6 +1238,14 @@ impl PathIntegrator {
             trace!(self.log, "updated beta: {:?}", beta);
             let flags = flags.unwrap();
             specular_bounce = flags.contains(BxDFType::BSDF_SPECULAR);
+            if flags.contains(BxDFType::BSDF_TRANSMISSION) {
+                // entering through the front of the surface, leaving through its back
+                absorption = if wi.dot(&isect.general.n) < 0.0 {
+                    bsdf.absorption
+                } else {
+                    None
+                };
+            }
             if flags.contains(BxDFType::BSDF_SPECULAR)
                 && flags.contains(BxDFType::BSDF_TRANSMISSION)
             {
@@ -491,7 +1274,17 @@ impl PathIntegrator {
                 }
             }
 
+            if let Some(dimension_check) = &self.dimension_check {
+                dimension_check.record(
+                    &self.log,
+                    bounces as usize,
+                    sampler.get_dimension() - self.bounce_dimension(bounces),
+                    self.bounce_dimensions(),
+                );
+            }
+
             bounces += 1;
+            stats.bounces += 1;
         }
 
         l
@@ -509,19 +1302,21 @@ impl PathIntegrator {
             "camera at location: {:?}",
             camera.cam_to_world.translation
         );
-        let mut sampler_builder = self.sampler_builder.clone();
+        let mut sampler_builder = self.path_sampler_builder();
         let mut pixel_sampler = sampler_builder.with_seed(0).build();
         pixel_sampler.start_pixel(&pixel);
+        let mut arena = MemoryArena::new();
 
         loop {
             let camera_sample = pixel_sampler.get_camera_sample(&pixel);
             trace!(self.log, "generated camera sample: {:?}", camera_sample);
-            let mut ray = camera.generate_ray_differential(&camera_sample);
+            let (mut ray, _) = camera.generate_ray_differential(&camera_sample);
             ray.scale_differentials(1.0 / (pixel_sampler.samples_per_pixel() as f32).sqrt());
             trace!(self.log, "generated ray: {:?}", ray);
             let mut l = Spectrum::new(0.0);
-            l = self.li(&ray, &scene, &mut pixel_sampler, 0);
+            l = self.li(&ray, &scene, &mut pixel_sampler, &arena, 0);
             trace!(self.log, "output L: {:?}", l);
+            arena.reset();
 
             if !pixel_sampler.start_next_sample() {
                 break;
@@ -530,50 +1325,143 @@ impl PathIntegrator {
     }
 
     pub fn render(&self, camera: &Camera, scene: &RenderScene) {
+        self.render_tiles(camera, scene, None, 0);
+    }
+
+    /// renders pass `pass` of a progressive render into the film, taking the samples of each
+    /// pixel after the ones the passes before it took. the film then holds a render of `pass + 1`
+    /// times the samples per pixel, the first pass being the same as `render`
+    pub fn render_pass(&self, camera: &Camera, scene: &RenderScene, pass: usize) {
+        self.render_tiles(camera, scene, None, pass);
+    }
+
+    /// renders with the primary visibility taken from a rasterized `gbuffer`, only the
+    /// secondary bounces are traced. faster for previews, but silhouettes are only as accurate
+    /// as the rasterization
+    pub fn render_hybrid(&self, camera: &Camera, scene: &RenderScene, gbuffer: &GBuffer) {
+        self.render_tiles(camera, scene, Some(gbuffer), 0);
+    }
+
+    fn render_tiles(
+        &self,
+        camera: &Camera,
+        scene: &RenderScene,
+        gbuffer: Option<&GBuffer>,
+        pass: usize,
+    ) {
         debug!(
             self.log,
             "start rendering image of size: {:?}",
             camera.film.get_sample_bounds().diagonal(),
         );
         let start = Instant::now();
-        let sample_bounds = camera.film.get_sample_bounds();
-        let sample_extent = sample_bounds.diagonal();
-        const TILE_SIZE: i32 = 16;
-        let num_tiles = na::Point2::new(
-            (sample_extent.x + TILE_SIZE - 1) / TILE_SIZE,
-            (sample_extent.y + TILE_SIZE - 1) / TILE_SIZE,
-        );
-
-        let work_closure = |(x, y): &(i32, i32)| {
-            let tile = na::Point2::new(*x, *y);
-            let seed = (tile.y * num_tiles.x + tile.x) as u64;
-            let mut tile_sampler = self.sampler_builder.clone().with_seed(seed).build();
-
-            let x0 = sample_bounds.p_min.x + tile.x * TILE_SIZE;
-            let x1 = std::cmp::min(x0 + TILE_SIZE, sample_bounds.p_max.x);
-            let y0 = sample_bounds.p_min.y + tile.y * TILE_SIZE;
-            let y1 = std::cmp::min(y0 + TILE_SIZE, sample_bounds.p_max.y);
+        let tiles = camera.film.tiles(TILE_SIZE);
+        let surfaces = gbuffer.map(|_| GBufferSurfaces::new(scene));
+        let wants_aux = self.callbacks.iter().any(|callback| callback.wants_aux());
+        if let Some(ray_stats) = &self.ray_stats {
+            if pass == 0 {
+                ray_stats.clear();
+            }
+        }
+        // later passes draw from a sampler holding the samples of the passes before them too,
+        // rounded up so sobol samplers keep a power of two
+        let samples_per_pixel = self.sampler_builder.samples_per_pixel();
+        let first_sample = pass * samples_per_pixel;
+        let sampler_builder = if pass == 0 {
+            self.path_sampler_builder()
+        } else {
+            let mut sampler_builder = self
+                .sampler_builder
+                .with_samples_per_pixel(samples_per_pixel * (pass + 1).next_power_of_two());
+            sampler_builder
+                .request_2d_array(self.n_light_samples)
+                .request_2d_array(self.n_bsdf_samples);
+            sampler_builder
+        };
 
-            let tile_bounds = Bounds2i {
-                p_min: na::Point2::new(x0, y0),
-                p_max: na::Point2::new(x1, y1),
-            };
-            let mut film_tile = camera.film.get_film_tile(&tile_bounds);
+        // every worker builds its own sampler and arena once and reuses them for all the tiles it
+        // picks up, so nothing is shared between the threads. scattering functions only live for
+        // one camera sample, so their memory is recycled between samples instead of going through
+        // the allocator
+        let init = || (sampler_builder.build(), MemoryArena::new());
+        let work_closure = |state: &mut TileState, (tile_idx, tile_bounds): (usize, &Bounds2i)| {
+            let (tile_sampler, arena) = state;
+            // seeded by the tile rather than the thread, the image doesn't depend on which
+            // worker rendered which tile
+            tile_sampler.set_seed((pass * tiles.len() + tile_idx) as u64);
+
+            let mut film_tile = camera.film.get_film_tile(tile_bounds);
+            let mut tile_samples = Vec::new();
+            let mut tile_stats = Vec::new();
 
             for (x, y) in (tile_bounds.p_min.x..tile_bounds.p_max.x)
                 .cartesian_product(tile_bounds.p_min.y..tile_bounds.p_max.y)
             {
                 let pixel = na::Point2::new(x, y);
                 tile_sampler.start_pixel(&pixel);
+                // the traversal of the pixel's camera rays is counted into its first sample
+                take_traversal_stats();
+
+                // the camera rays of the pixel are coherent enough to share most of their
+                // traversal, so they're generated up front and traced as one batch
+                let rays = (0..samples_per_pixel)
+                    .map(|sample_num| {
+                        tile_sampler.set_sample_number(first_sample + sample_num);
+                        let camera_sample = tile_sampler.get_camera_sample(&pixel);
+                        let (mut ray, weight) = camera.generate_ray_differential(&camera_sample);
+                        ray.scale_differentials(1.0 / (samples_per_pixel as f32).sqrt());
+                        (ray, weight)
+                    })
+                    .collect::<Vec<_>>();
+                let mut primary_hits = Vec::new();
+                if gbuffer.is_none() {
+                    // traced on copies, the rays themselves still reach past their hits for the
+                    // aux passes
+                    let mut primary_rays = rays
+                        .iter()
+                        .map(|(ray, _)| ray.ray.clone())
+                        .collect::<Vec<_>>();
+                    primary_hits.resize_with(samples_per_pixel, Hit::default);
+                    scene.intersect_n(&mut primary_rays, &mut primary_hits);
+                }
+                let mut primary_hits = primary_hits.into_iter();
+                let mut rays = rays.into_iter();
+                tile_sampler.set_sample_number(first_sample);
 
                 loop {
+                    // drawn again to move the sampler past the dimensions of the camera sample
                     let camera_sample = tile_sampler.get_camera_sample(&pixel);
-
-                    let mut ray = camera.generate_ray_differential(&camera_sample);
-                    ray.scale_differentials(1.0 / (tile_sampler.samples_per_pixel() as f32).sqrt());
-
+                    let (ray, ray_weight) = rays.next().unwrap();
+
+                    let primary = match (gbuffer, &surfaces) {
+                        (Some(gbuffer), Some(surfaces)) => match gbuffer.texel(&pixel) {
+                            Some(texel) => PrimaryHit::Surface(texel, surfaces.surface(&texel)),
+                            None => PrimaryHit::Background,
+                        },
+                        _ => PrimaryHit::Traced(primary_hits.next().unwrap()),
+                    };
                     let mut l = Spectrum::new(0.0);
-                    l = self.li(&ray, &scene, &mut tile_sampler, 0);
+                    // how much the camera ray sees of the scene rather than the background
+                    let mut alpha = 0.0;
+                    let mut stats = RayStats::default();
+                    // rays blocked inside a realistic lens don't reach the film
+                    if ray_weight > 0.0 {
+                        l = self.li_path(
+                            &ray,
+                            &scene,
+                            tile_sampler,
+                            arena,
+                            primary,
+                            &mut stats,
+                            &mut alpha,
+                        ) * ray_weight;
+                    }
+                    if self.ray_stats.is_some() {
+                        let traversal = take_traversal_stats();
+                        stats.nodes_visited = traversal.nodes_visited;
+                        stats.primitives_tested = traversal.primitives_tested;
+                        tile_stats.push((pixel, stats));
+                    }
 
                     if l.has_nan() {
                         error!(
@@ -600,41 +1488,227 @@ impl PathIntegrator {
                         );
                     }
 
-                    film_tile.add_sample(&camera_sample.p_film, &l);
+                    film_tile.add_sample(&camera_sample.p_film, &l, alpha);
 
-                    if !tile_sampler.start_next_sample() {
+                    if !self.callbacks.is_empty() {
+                        let aux = if wants_aux {
+                            Some(self.sample_aux(&ray, &scene, tile_sampler, arena))
+                        } else {
+                            None
+                        };
+                        let sample = PixelSample {
+                            pixel,
+                            p_film: camera_sample.p_film,
+                            sample_index: tile_sampler.get_current_sample_number(),
+                            radiance: l,
+                            aux,
+                        };
+                        for callback in &self.callbacks {
+                            callback.on_sample(&sample);
+                        }
+                        tile_samples.push(sample);
+                    }
+                    // the aux passes aren't part of the path
+                    take_traversal_stats();
+                    arena.reset();
+
+                    if !tile_sampler.start_next_sample()
+                        || tile_sampler.get_current_sample_number()
+                            >= first_sample + samples_per_pixel
+                    {
                         break;
                     }
                 }
             }
 
-            camera.film.merge_film_tile(film_tile)
+            camera.film.merge_film_tile(film_tile);
+            if let Some(ray_stats) = &self.ray_stats {
+                ray_stats.merge(&tile_stats);
+            }
+            for callback in &self.callbacks {
+                callback.on_tile(tile_bounds, &tile_samples);
+            }
         };
 
-        let render_tile_vec = (0..num_tiles.x)
-            .cartesian_product(0..num_tiles.y)
-            .collect_vec();
-
-        let render_par_iter;
         #[cfg(feature = "disable_rayon")]
         {
-            render_par_iter = render_tile_vec.iter();
+            let mut state = init();
+            let render_iter = tiles.iter().enumerate();
+            if self.show_progress_bar {
+                render_iter
+                    .progress_count(tiles.len() as u64)
+                    .for_each(|tile| work_closure(&mut state, tile));
+            } else {
+                render_iter.for_each(|tile| work_closure(&mut state, tile));
+            }
         }
         #[cfg(not(feature = "disable_rayon"))]
         {
-            render_par_iter = render_tile_vec.par_iter();
-        }
-
-        if self.show_progress_bar {
-            render_par_iter
-                .progress_count((num_tiles.x * num_tiles.y) as u64)
-                .for_each(work_closure);
-        } else {
-            render_par_iter.for_each(work_closure);
+            let render_par_iter = tiles.par_iter().enumerate();
+            if self.show_progress_bar {
+                render_par_iter
+                    .progress_count(tiles.len() as u64)
+                    .for_each_init(init, work_closure);
+            } else {
+                render_par_iter.for_each_init(init, work_closure);
+            }
         }
 
         let duration = start.elapsed();
 
         info!(self.log, "rendering took: {:?}", duration);
     }
+
+    /// renders the albedo of the first visible surface into a separate film, the guide image
+    /// denoisers expect alongside the beauty pass. uses the cached reflectance tables of the
+    /// microfacet lobes so this costs little more than a primary ray per sample. filtered the
+    /// same as the render, so the two line up
+    pub fn render_albedo(&self, camera: &Camera, scene: &RenderScene) -> Film {
+        let start = Instant::now();
+        let film = Film::new(
+            &camera.film.resolution,
+            Box::new(camera.film.filter().clone()),
+        );
+        let tiles = film.tiles(TILE_SIZE);
+
+        let init = || (self.sampler_builder.build(), MemoryArena::new());
+        let work_closure = |state: &mut TileState, (tile_idx, tile_bounds): (usize, &Bounds2i)| {
+            let (tile_sampler, arena) = state;
+            tile_sampler.set_seed(tile_idx as u64);
+            let mut film_tile = film.get_film_tile(tile_bounds);
+
+            for (x, y) in (tile_bounds.p_min.x..tile_bounds.p_max.x)
+                .cartesian_product(tile_bounds.p_min.y..tile_bounds.p_max.y)
+            {
+                let pixel = na::Point2::new(x, y);
+                tile_sampler.start_pixel(&pixel);
+
+                loop {
+                    let camera_sample = tile_sampler.get_camera_sample(&pixel);
+                    let (mut ray, _) = camera.generate_ray_differential(&camera_sample);
+                    // filtered like the textures of the render it's denoising
+                    ray.scale_differentials(1.0 / (tile_sampler.samples_per_pixel() as f32).sqrt());
+                    let albedo = self.albedo(&ray, scene, tile_sampler, arena);
+                    film_tile.add_sample(&camera_sample.p_film, &albedo, 1.0);
+                    arena.reset();
+
+                    if !tile_sampler.start_next_sample() {
+                        break;
+                    }
+                }
+            }
+
+            film.merge_film_tile(film_tile)
+        };
+
+        #[cfg(feature = "disable_rayon")]
+        {
+            let mut state = init();
+            tiles
+                .iter()
+                .enumerate()
+                .for_each(|tile| work_closure(&mut state, tile));
+        }
+        #[cfg(not(feature = "disable_rayon"))]
+        tiles
+            .par_iter()
+            .enumerate()
+            .for_each_init(init, work_closure);
+
+        info!(self.log, "albedo rendering took: {:?}", start.elapsed());
+        film
+    }
+
+    /// estimates an exposure scale mapping the log average luminance of the scene to middle
+    /// gray, from a quick prepass of a coarse grid of camera rays
+    pub fn estimate_exposure(&self, camera: &Camera, scene: &RenderScene) -> f32 {
+        let mut sampler = self.sampler_builder.clone().with_seed(u64::MAX).build();
+        let resolution = camera.film.resolution;
+        let mut arena = MemoryArena::new();
+
+        let mut log_sum = 0.0;
+        let mut count = 0;
+        for (y, x) in (0..EXPOSURE_GRID_SIZE).cartesian_product(0..EXPOSURE_GRID_SIZE) {
+            let pixel = na::Point2::new(
+                ((x as f32 + 0.5) / EXPOSURE_GRID_SIZE as f32 * resolution.x as f32) as i32,
+                ((y as f32 + 0.5) / EXPOSURE_GRID_SIZE as f32 * resolution.y as f32) as i32,
+            );
+            sampler.start_pixel(&pixel);
+            let camera_sample = sampler.get_camera_sample(&pixel);
+            let (ray, weight) = camera.generate_ray_differential(&camera_sample);
+            let y = weight * self.li(&ray, &scene, &mut sampler, &arena, 0).y();
+            arena.reset();
+            if y.is_finite() && y >= 0.0 {
+                // offset keeps black pixels from sending the average to zero
+                log_sum += (y + 1e-4).ln();
+                count += 1;
+            }
+        }
+
+        if count == 0 {
+            return 1.0;
+        }
+        let log_average = (log_sum / count as f32).exp();
+        let exposure = (MIDDLE_GRAY / log_average).clamp(1.0 / 1024.0, 1024.0);
+        debug!(
+            self.log,
+            "log average luminance: {:?}, exposure: {:?}", log_average, exposure
+        );
+
+        exposure
+    }
+
+    fn sample_aux(
+        &self,
+        ray: &RayDifferential,
+        scene: &RenderScene,
+        sampler: &mut Sampler,
+        arena: &MemoryArena,
+    ) -> SampleAux {
+        let mut first_ray = ray.clone();
+        let mut isect = SurfaceMediumInteraction::default();
+        if !scene.intersect(&mut first_ray.ray, &mut isect) {
+            return SampleAux {
+                albedo: Spectrum::new(0.0),
+                normal: glm::zero(),
+                depth: f32::INFINITY,
+            };
+        }
+
+        SampleAux {
+            albedo: self.albedo(ray, scene, sampler, arena),
+            normal: isect.shading.n,
+            depth: first_ray.ray.t_max * first_ray.ray.d.norm(),
+        }
+    }
+
+    fn albedo(
+        &self,
+        ray: &RayDifferential,
+        scene: &RenderScene,
+        sampler: &mut Sampler,
+        arena: &MemoryArena,
+    ) -> Spectrum {
+        let mut ray = ray.clone();
+        loop {
+            let mut isect = Default::default();
+            if !scene.intersect(&mut ray.ray, &mut isect) {
+                return Spectrum::new(0.0);
+            }
+
+            isect.compute_scattering_functions(&ray, TransportMode::Radiance, arena);
+            match isect.bsdf.as_ref() {
+                Some(bsdf) => {
+                    let samples: Vec<_> = (0..ALBEDO_SAMPLES).map(|_| sampler.get_2d()).collect();
+                    return bsdf.rho(
+                        &isect.general.wo,
+                        ALBEDO_SAMPLES,
+                        &samples,
+                        BxDFType::BSDF_ALL,
+                    );
+                }
+                None => ray = RayDifferential::new(isect.general.spawn_ray(&ray.ray.d)),
+            }
+        }
+    }
 }
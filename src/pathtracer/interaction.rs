@@ -1,6 +1,6 @@
-use super::{bsdf::BSDF, primitive::Primitive, shape::Triangle, TransportMode};
+use super::{arena::MemoryArena, bsdf::BSDF, primitive::Primitive, shape::Triangle, TransportMode};
 use crate::common::{
-    math::{face_forward, offset_ray_origin, solve_linear_system_2x2},
+    math::{face_forward, offset_ray_origin, solve_linear_system_2x2, ONE_MINUS_EPSILON},
     ray::{Ray, RayDifferential},
     spectrum::Spectrum,
 };
@@ -26,7 +26,12 @@ impl Default for Interaction {
     }
 }
 
-const SHADOW_EPSILON: f32 = 0.0001;
+/// parametric end of a shadow ray along `d` that stops `margin` short of its target. the clearance
+/// follows the error bounds of the target, a fixed fraction of the length let long rays skip thin
+/// occluders right in front of it
+fn shadow_t_max(d: &na::Vector3<f32>, margin: f32) -> f32 {
+    (1.0 - margin / d.norm()).min(ONE_MINUS_EPSILON)
+}
 
 impl Interaction {
     pub fn spawn_ray(&self, d: &na::Vector3<f32>) -> Ray {
@@ -35,15 +40,18 @@ impl Interaction {
             o,
             d: *d,
             t_max: f32::INFINITY,
+            time: self.time,
         }
     }
     pub fn spawn_ray_to(&self, p2: &na::Point3<f32>) -> Ray {
         let origin = offset_ray_origin(&self.p, &self.p_error, &self.n, &(p2 - self.p));
         let d = p2 - origin;
+        // a bare point has no surface to self intersect with
         Ray {
             o: origin,
             d: d,
-            t_max: 1.0 - SHADOW_EPSILON,
+            t_max: shadow_t_max(&d, 0.0),
+            time: self.time,
         }
     }
 
@@ -51,10 +59,13 @@ impl Interaction {
         let origin = offset_ray_origin(&self.p, &self.p_error, &self.n, &(it2.p - self.p));
         let target = offset_ray_origin(&it2.p, &it2.p_error, &it2.n, &(origin - it2.p));
         let d = target - origin;
+        // the target is offset off its surface already, stopping as far short again keeps the
+        // ray clear of it
         return Ray {
             o: origin,
             d,
-            t_max: 1.0 - SHADOW_EPSILON,
+            t_max: shadow_t_max(&d, (target - it2.p).norm()),
+            time: self.time,
         };
     }
 }
@@ -88,9 +99,11 @@ pub struct SurfaceMediumInteraction<'a> {
     pub dndu: na::Vector3<f32>,
     pub dndv: na::Vector3<f32>,
     pub shading: SurfaceInteractionShading,
+    /// interpolated color of the vertices around the hit, on meshes that have them
+    pub color: Option<Spectrum>,
     pub shape: Option<&'a Triangle>,
     pub primitive: Option<&'a dyn Primitive>,
-    pub bsdf: Option<BSDF>,
+    pub bsdf: Option<BSDF<'a>>,
 
     pub dpdx: na::Vector3<f32>,
     pub dpdy: na::Vector3<f32>,
@@ -116,6 +129,7 @@ impl<'a> Default for SurfaceMediumInteraction<'a> {
             dndu: glm::zero(),
             dndv: glm::zero(),
             shading: Default::default(),
+            color: None,
             shape: None,
             primitive: None,
             bsdf: None,
@@ -140,7 +154,7 @@ impl<'a> SurfaceMediumInteraction<'a> {
         dndu: &na::Vector3<f32>,
         dndv: &na::Vector3<f32>,
         time: f32,
-        shape: &'a Triangle,
+        shape: Option<&'a Triangle>,
     ) -> Self {
         let n = dpdu.cross(dpdv).normalize();
         let shading = SurfaceInteractionShading {
@@ -164,7 +178,7 @@ impl<'a> SurfaceMediumInteraction<'a> {
             dndu: *dndu,
             dndv: *dndv,
             shading,
-            shape: Some(shape),
+            shape,
             primitive: None,
             bsdf: None,
             ..Default::default()
@@ -176,6 +190,7 @@ impl<'a> SurfaceMediumInteraction<'a> {
             general: self.general.clone(),
             uv: self.uv.clone(),
             shading: self.shading.clone(),
+            color: self.color,
             dpdu: self.dpdu,
             dpdv: self.dpdv,
             dndu: self.dndu,
@@ -238,7 +253,7 @@ impl<'a> SurfaceMediumInteraction<'a> {
 
             // Choose two dimensions to use for ray offset computation
             let mut dim = [0, 0];
-            if n.x.abs() > n.y.abs() && n.x.abs() > n.y.abs() {
+            if n.x.abs() > n.y.abs() && n.x.abs() > n.z.abs() {
                 dim[0] = 1;
                 dim[1] = 2;
             } else if n.y.abs() > n.z.abs() {
@@ -280,7 +295,12 @@ impl<'a> SurfaceMediumInteraction<'a> {
         }
     }
 
-    pub fn compute_scattering_functions(&mut self, r: &RayDifferential, mode: TransportMode) {
+    pub fn compute_scattering_functions(
+        &mut self,
+        r: &RayDifferential,
+        mode: TransportMode,
+        arena: &'a MemoryArena,
+    ) {
         if !self.compute_differentials(&r) {
             self.dudx = 0.0;
             self.dvdx = 0.0;
@@ -291,7 +311,7 @@ impl<'a> SurfaceMediumInteraction<'a> {
         }
         self.primitive
             .unwrap()
-            .compute_scattering_functions(self, mode);
+            .compute_scattering_functions(self, mode, arena);
     }
 
     pub fn le(&self, w: &na::Vector3<f32>) -> Spectrum {
@@ -0,0 +1,281 @@
+use super::{bxdf::refract, sampling::concentric_sample_disk};
+use crate::common::math::face_forward;
+use anyhow::{anyhow, Context, Result};
+use std::path::Path;
+
+/// one spherical interface of a lens system, or the aperture stop. lengths are in meters
+#[derive(Clone, Copy, Debug)]
+struct LensElement {
+    /// radius of the sphere the interface lies on, positive when it bulges towards the scene and
+    /// 0 for the aperture stop
+    curvature_radius: f32,
+    /// distance along the axis to the next interface towards the film, or to the film itself
+    thickness: f32,
+    /// index of refraction of the medium between this interface and the next towards the film
+    eta: f32,
+    aperture_radius: f32,
+}
+
+/// a stack of lens elements in front of the film, rays are traced through every interface so the
+/// vignetting, distortion and bokeh of the lens come out of its geometry (kolb et al. 1995). the
+/// lens sits along -z of camera space with the film at the origin
+#[derive(Debug)]
+pub struct LensSystem {
+    /// from the scene side towards the film
+    elements: Vec<LensElement>,
+    film_diagonal: f32,
+}
+
+impl LensSystem {
+    /// reads a lens description in the format of pbrt's lens files: one interface per line from
+    /// the scene side on, each its curvature radius, thickness, index of refraction and aperture
+    /// diameter in millimeters, with a radius of 0 for the aperture stop and `#` starting a
+    /// comment. the stop is narrowed to `aperture_diameter` when given, and the film moved so the
+    /// lens focuses at `focus_distance` meters
+    pub fn load(
+        path: &Path,
+        aperture_diameter: Option<f32>,
+        film_diagonal: f32,
+        focus_distance: f32,
+    ) -> Result<Self> {
+        let description = std::fs::read_to_string(path)
+            .with_context(|| format!("reading lens description {:?}", path))?;
+        let values = description
+            .lines()
+            .map(|line| line.split('#').next().unwrap())
+            .flat_map(|line| line.split_whitespace())
+            .map(|value| value.parse::<f32>())
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .with_context(|| format!("parsing lens description {:?}", path))?;
+        if values.is_empty() || values.len() % 4 != 0 {
+            return Err(anyhow!(
+                "lens description {:?} doesn't list four values per interface",
+                path
+            ));
+        }
+
+        let elements = values
+            .chunks(4)
+            .map(|element| {
+                let mut diameter = element[3];
+                if element[0] == 0.0 {
+                    if let Some(aperture_diameter) = aperture_diameter {
+                        diameter = diameter.min(aperture_diameter);
+                    }
+                }
+                LensElement {
+                    curvature_radius: element[0] * 0.001,
+                    thickness: element[1] * 0.001,
+                    // the stop doesn't refract, whatever index it's given
+                    eta: if element[2] == 0.0 { 1.0 } else { element[2] },
+                    aperture_radius: diameter * 0.001 / 2.0,
+                }
+            })
+            .collect();
+
+        let mut lens = Self {
+            elements,
+            film_diagonal: film_diagonal * 0.001,
+        };
+        let film_distance = lens.focus_thick_lens(focus_distance)?;
+        lens.elements.last_mut().unwrap().thickness = film_distance;
+        Ok(lens)
+    }
+
+    /// distance from the film to the rear element
+    fn rear_z(&self) -> f32 {
+        self.elements.last().unwrap().thickness
+    }
+
+    /// distance from the film to the front element
+    fn front_z(&self) -> f32 {
+        self.elements.iter().map(|element| element.thickness).sum()
+    }
+
+    /// ray in camera space leaving the front of the lens for the raster position `p_film` on a
+    /// film of `resolution`, through the point `u` picks on the rear element. `None` when the ray
+    /// is blocked inside the lens, otherwise weighted by the falloff of the light reaching the
+    /// film at an angle
+    pub fn generate_ray(
+        &self,
+        p_film: &na::Point2<f32>,
+        resolution: &na::Vector2<f32>,
+        u: &na::Point2<f32>,
+    ) -> Option<(na::Point3<f32>, na::Vector3<f32>, f32)> {
+        // the lens flips the image, the film is flipped back across
+        let aspect = resolution.y / resolution.x;
+        let width = (self.film_diagonal * self.film_diagonal / (1.0 + aspect * aspect)).sqrt();
+        let extent = na::Vector2::new(width, aspect * width);
+        let p_film = na::Point3::new(
+            -(p_film.x / resolution.x - 0.5) * extent.x,
+            (p_film.y / resolution.y - 0.5) * extent.y,
+            0.0,
+        );
+
+        let p_rear = concentric_sample_disk(u) * self.elements.last().unwrap().aperture_radius;
+        let p_rear = na::Point3::new(p_rear.x, p_rear.y, -self.rear_z());
+        let d = p_rear - p_film;
+        let (o, w) = self.trace_from_film(&p_film, &d)?;
+
+        let cos_theta = d.normalize().z.abs();
+        Some((o, w, cos_theta * cos_theta * cos_theta * cos_theta))
+    }
+
+    /// the ray from `o` along `d` on the film side, traced out of the front of the lens
+    fn trace_from_film(
+        &self,
+        o: &na::Point3<f32>,
+        d: &na::Vector3<f32>,
+    ) -> Option<(na::Point3<f32>, na::Vector3<f32>)> {
+        let (mut o, mut d) = (*o, *d);
+        let mut element_z = 0.0;
+        for i in (0..self.elements.len()).rev() {
+            let element = &self.elements[i];
+            element_z -= element.thickness;
+
+            let (t, n) = if element.curvature_radius == 0.0 {
+                ((element_z - o.z) / d.z, None)
+            } else {
+                let z_center = element_z + element.curvature_radius;
+                let (t, n) =
+                    intersect_spherical_element(element.curvature_radius, z_center, &o, &d)?;
+                (t, Some(n))
+            };
+            o += d * t;
+            if o.x * o.x + o.y * o.y > element.aperture_radius * element.aperture_radius {
+                return None;
+            }
+
+            if let Some(n) = n {
+                let eta_i = element.eta;
+                let eta_t = if i > 0 { self.elements[i - 1].eta } else { 1.0 };
+                let mut w = na::Vector3::zeros();
+                if !refract(&-d.normalize(), &n, eta_i / eta_t, &mut w) {
+                    return None;
+                }
+                d = w;
+            }
+        }
+
+        Some((o, d))
+    }
+
+    /// the ray from `o` along `d` on the scene side, traced through to the film side
+    fn trace_from_scene(
+        &self,
+        o: &na::Point3<f32>,
+        d: &na::Vector3<f32>,
+    ) -> Option<(na::Point3<f32>, na::Vector3<f32>)> {
+        let (mut o, mut d) = (*o, *d);
+        let mut element_z = -self.front_z();
+        for (i, element) in self.elements.iter().enumerate() {
+            let (t, n) = if element.curvature_radius == 0.0 {
+                ((element_z - o.z) / d.z, None)
+            } else {
+                let z_center = element_z + element.curvature_radius;
+                let (t, n) =
+                    intersect_spherical_element(element.curvature_radius, z_center, &o, &d)?;
+                (t, Some(n))
+            };
+            o += d * t;
+            if o.x * o.x + o.y * o.y > element.aperture_radius * element.aperture_radius {
+                return None;
+            }
+
+            if let Some(n) = n {
+                let eta_i = if i > 0 { self.elements[i - 1].eta } else { 1.0 };
+                let eta_t = element.eta;
+                let mut w = na::Vector3::zeros();
+                if !refract(&-d.normalize(), &n, eta_i / eta_t, &mut w) {
+                    return None;
+                }
+                d = w;
+            }
+            element_z += element.thickness;
+        }
+
+        Some((o, d))
+    }
+
+    /// depths of the principal plane and focal point on the scene side and the film side of the
+    /// thick lens the system acts like, found by tracing rays parallel to the axis through it
+    fn thick_lens_approximation(&self) -> Result<([f32; 2], [f32; 2])> {
+        let x = 0.001 * self.film_diagonal;
+        let cardinal_points =
+            |o_in: &na::Point3<f32>, o_out: &na::Point3<f32>, d_out: &na::Vector3<f32>| {
+                let t_focus = -o_out.x / d_out.x;
+                let t_principal = (o_in.x - o_out.x) / d_out.x;
+                ((o_out + d_out * t_principal).z, (o_out + d_out * t_focus).z)
+            };
+
+        let o_scene = na::Point3::new(x, 0.0, -(self.front_z() + 1.0));
+        let (o_film, d_film) = self
+            .trace_from_scene(&o_scene, &na::Vector3::z())
+            .ok_or_else(|| anyhow!("ray parallel to the axis is blocked by the lens"))?;
+        let (pz0, fz0) = cardinal_points(&o_scene, &o_film, &d_film);
+
+        let o_film = na::Point3::new(x, 0.0, 1.0 - self.rear_z());
+        let (o_scene, d_scene) = self
+            .trace_from_film(&o_film, &-na::Vector3::z())
+            .ok_or_else(|| anyhow!("ray parallel to the axis is blocked by the lens"))?;
+        let (pz1, fz1) = cardinal_points(&o_film, &o_scene, &d_scene);
+
+        Ok(([pz0, pz1], [fz0, fz1]))
+    }
+
+    /// distance from the rear element to the film bringing `focus_distance` into focus, by the
+    /// thin lens equation applied to the thick lens approximation
+    fn focus_thick_lens(&self, focus_distance: f32) -> Result<f32> {
+        let (pz, fz) = self.thick_lens_approximation()?;
+        let f = fz[0] - pz[0];
+        let z = -focus_distance;
+        let c = (pz[1] - z - pz[0]) * (pz[1] - z - 4.0 * f - pz[0]);
+        if c <= 0.0 {
+            return Err(anyhow!(
+                "the lens can't focus at {:?} meters, the focal length is {:?} meters",
+                focus_distance,
+                f
+            ));
+        }
+        let delta = 0.5 * (pz[1] - z + pz[0] - c.sqrt());
+        Ok(self.rear_z() + delta)
+    }
+}
+
+/// distance along `d` from `o` to the spherical interface of `radius` centered at `z_center` on
+/// the axis, and the normal there facing back along the ray
+fn intersect_spherical_element(
+    radius: f32,
+    z_center: f32,
+    o: &na::Point3<f32>,
+    d: &na::Vector3<f32>,
+) -> Option<(f32, na::Vector3<f32>)> {
+    let o = o - na::Vector3::new(0.0, 0.0, z_center);
+    let (ox, oy, oz) = (o.x as f64, o.y as f64, o.z as f64);
+    let (dx, dy, dz) = (d.x as f64, d.y as f64, d.z as f64);
+    let a = dx * dx + dy * dy + dz * dz;
+    let b = 2.0 * (dx * ox + dy * oy + dz * oz);
+    let c = ox * ox + oy * oy + oz * oz - radius as f64 * radius as f64;
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    let q = if b < 0.0 {
+        -0.5 * (b - root)
+    } else {
+        -0.5 * (b + root)
+    };
+    let (t0, t1) = (q / a, c / q);
+    let (t0, t1) = (t0.min(t1), t0.max(t1));
+
+    // the part of the sphere the lens is made of depends on which way it bulges
+    let use_closer_t = (d.z > 0.0) ^ (radius < 0.0);
+    let t = if use_closer_t { t0 } else { t1 } as f32;
+    if t < 0.0 {
+        return None;
+    }
+
+    let n = (o.coords + d * t).normalize();
+    Some((t, face_forward(&n, &-d)))
+}
@@ -1,20 +1,19 @@
-use std::{fs::File, io::BufReader, sync::Arc};
+use std::sync::Arc;
 
 use super::{
+    environment::{direction_to_equirect, equirect_to_direction, EnvironmentTexture},
     interaction::{Interaction, SurfaceMediumInteraction},
     sampling::Distribution2D,
-    shape::Triangle,
-    texture::{MIPMap, SyncTexture},
+    shape::{Shape, ShapeInterface},
+    texture::SyncTexture,
     RenderScene,
 };
 use crate::common::{
     bounds::Bounds3,
-    math::spherical_phi,
-    math::spherical_theta,
-    math::INV_2_PI,
+    math::equal_area_sphere_to_square,
+    math::equal_area_square_to_sphere,
     ray::{Ray, RayDifferential},
     spectrum::Spectrum,
-    WrapMode,
 };
 
 bitflags! {
@@ -37,7 +36,12 @@ pub struct VisibilityTester {
 
 impl<'a> VisibilityTester {
     pub fn unoccluded(&self, scene: &RenderScene) -> bool {
-        !scene.intersect_p(&self.p0.spawn_ray_to_it(&self.p1))
+        !scene.intersect_p(&self.ray())
+    }
+
+    /// shadow ray between the two points, for testing many of them at once
+    pub fn ray(&self) -> Ray {
+        self.p0.spawn_ray_to_it(&self.p1)
     }
 }
 
@@ -230,32 +234,42 @@ impl Light for DirectionalLight {
 
 pub struct DiffuseAreaLight {
     ke: Arc<dyn SyncTexture<Spectrum>>,
-    shape: Arc<Triangle>,
+    shape: Arc<Shape>,
     num_samples: usize,
     area: f32,
+    two_sided: bool,
 }
 
 impl DiffuseAreaLight {
+    /// `two_sided` lights emit from the back of the surface as well, e.g. lamp shades with
+    /// transmissive materials
     pub fn new(
         ke: Arc<dyn SyncTexture<Spectrum>>,
-        shape: Arc<Triangle>,
+        shape: Arc<Shape>,
         num_samples: usize,
+        two_sided: bool,
     ) -> Self {
         Self {
             ke,
             area: shape.area(),
             num_samples,
             shape,
+            two_sided,
         }
     }
 
     pub fn l(&self, inter: &SurfaceMediumInteraction, w: &na::Vector3<f32>) -> Spectrum {
-        if inter.general.n.dot(&w) > 0.0 {
+        if self.two_sided || inter.general.n.dot(&w) > 0.0 {
             self.ke.evaluate(&inter)
         } else {
             Spectrum::new(0.0)
         }
     }
+
+    /// radiance leaving the front of the surface at `inter`, and the back of two sided lights
+    pub fn emitted(&self, inter: &SurfaceMediumInteraction) -> (Spectrum, bool) {
+        (self.ke.evaluate(&inter), self.two_sided)
+    }
 }
 
 impl Light for DiffuseAreaLight {
@@ -318,8 +332,20 @@ impl Light for DiffuseAreaLight {
     }
 }
 
+/// parameterization the environment map is stored and sampled in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EnvMapping {
+    /// the map as read from the file, texels shrink towards the poles
+    LatLong,
+    /// equal-area octahedral map resampled from the file. every texel covers the same solid
+    /// angle, so the sampling distribution needs no `sin_theta` correction and stays well behaved
+    /// near the poles
+    Octahedral,
+}
+
 pub struct InfiniteAreaLight {
-    l_map: Box<MIPMap<Spectrum>>,
+    environment: EnvironmentTexture,
+    mapping: EnvMapping,
     light_to_world: na::Projective3<f32>,
     world_to_light: na::Projective3<f32>,
     world_center: na::Point3<f32>,
@@ -328,35 +354,28 @@ pub struct InfiniteAreaLight {
     log: slog::Logger,
 }
 
-fn read_hdr_image_to_mat(path: &str, l: Spectrum) -> anyhow::Result<na::DMatrix<Spectrum>> {
-    let file = File::open(path)?;
-    let file = BufReader::new(file);
-    let decoder = image::hdr::HdrDecoder::new(file)?;
-    let metadata = decoder.metadata();
-    let image = decoder.read_image_hdr()?;
-    Ok(na::DMatrix::from_fn(
-        metadata.height as usize,
-        metadata.width as usize,
-        |row, col| {
-            let rgb = &image[row * metadata.width as usize + col];
-
-            l * Spectrum::from_image_rgb_f32(rgb)
-        },
-    ))
-}
-
 impl InfiniteAreaLight {
     pub fn new(
         log: &slog::Logger,
         light_to_world: na::Projective3<f32>,
         l: Spectrum,
         hdr_map_path: &str,
+    ) -> Self {
+        Self::with_mapping(log, light_to_world, l, hdr_map_path, EnvMapping::LatLong)
+    }
+
+    pub fn with_mapping(
+        log: &slog::Logger,
+        light_to_world: na::Projective3<f32>,
+        l: Spectrum,
+        hdr_map_path: &str,
+        mapping: EnvMapping,
     ) -> Self {
         let log = log.new(o!());
-        let mut texels: Option<na::DMatrix<Spectrum>> = None;
+        let mut environment = None;
         if !hdr_map_path.is_empty() {
-            match read_hdr_image_to_mat(hdr_map_path, l) {
-                Ok(mat) => texels = Some(mat),
+            match EnvironmentTexture::from_hdr(&log, hdr_map_path, l) {
+                Ok(texture) => environment = Some(texture),
                 Err(error) => {
                     error!(
                         log,
@@ -365,37 +384,99 @@ impl InfiniteAreaLight {
                 }
             }
         }
+        let environment = environment.unwrap_or_else(|| EnvironmentTexture::constant(l));
 
-        let texels = if let Some(texels) = texels {
-            texels
-        } else {
-            na::DMatrix::<Spectrum>::from_element(1, 1, l)
+        let distribution = match mapping {
+            EnvMapping::LatLong => lat_long_distribution(&environment),
+            EnvMapping::Octahedral => octahedral_distribution(&log, &environment),
         };
 
-        let width = 2 * texels.ncols();
-        let height = 2 * texels.nrows();
-        let f_width = 0.5 / width.min(height) as f32;
-        let mut img = Vec::with_capacity(width * height);
-        let l_map = Box::new(MIPMap::new(&log, texels, true, WrapMode::Repeat));
-        for v in 0..height {
-            let vp = (v as f32 + 0.5) / (height as f32);
-            let sin_theta = (std::f32::consts::PI * vp).sin();
-            for u in 0..width {
-                let up = (u as f32 + 0.5) / (width as f32);
-                img.push(sin_theta * l_map.lookup_width(&na::Point2::new(up, vp), f_width).y());
-            }
-        }
-
         Self {
-            l_map,
+            environment,
+            mapping,
             light_to_world,
             world_to_light: light_to_world.inverse(),
             world_center: na::Point3::origin(),
             world_radius: 0.0,
-            distribution: Box::new(Distribution2D::new(&img[..], width, height)),
+            distribution,
             log,
         }
     }
+
+    /// map coordinates of the light space direction `w`
+    fn direction_to_uv(&self, w: &na::Vector3<f32>) -> na::Point2<f32> {
+        match self.mapping {
+            EnvMapping::LatLong => direction_to_equirect(&w),
+            EnvMapping::Octahedral => equal_area_sphere_to_square(&w),
+        }
+    }
+
+    /// light space direction at the map coordinates `uv`, along with the solid angle density
+    /// matching the density `map_pdf` over the map
+    fn uv_to_direction(&self, uv: &na::Point2<f32>, map_pdf: f32) -> (na::Vector3<f32>, f32) {
+        match self.mapping {
+            EnvMapping::LatLong => {
+                let sin_theta = (uv[1] * std::f32::consts::PI).sin();
+                let pdf = if sin_theta == 0.0 {
+                    0.0
+                } else {
+                    map_pdf / (2.0 * std::f32::consts::PI * std::f32::consts::PI * sin_theta)
+                };
+                (equirect_to_direction(&uv), pdf)
+            }
+            EnvMapping::Octahedral => (
+                equal_area_square_to_sphere(&uv),
+                map_pdf / (4.0 * std::f32::consts::PI),
+            ),
+        }
+    }
+}
+
+/// luminance of the environment over a lat-long map at twice its resolution, weighted by the
+/// solid angle of the texels
+fn lat_long_distribution(environment: &EnvironmentTexture) -> Box<Distribution2D> {
+    let (width, height) = environment.resolution();
+    let (width, height) = (2 * width, 2 * height);
+    let mut img = Vec::with_capacity(width * height);
+    for v in 0..height {
+        let vp = (v as f32 + 0.5) / (height as f32);
+        let sin_theta = (std::f32::consts::PI * vp).sin();
+        for u in 0..width {
+            let up = (u as f32 + 0.5) / (width as f32);
+            let w = equirect_to_direction(&na::Point2::new(up, vp));
+            img.push(sin_theta * environment.lookup(&w).y());
+        }
+    }
+
+    Box::new(Distribution2D::new(&img[..], width, height))
+}
+
+/// luminance of the environment over a square octahedral map with about as many texels
+fn octahedral_distribution(
+    log: &slog::Logger,
+    environment: &EnvironmentTexture,
+) -> Box<Distribution2D> {
+    let (width, height) = environment.resolution();
+    let size = ((width * height) as f32).sqrt().ceil() as usize;
+    debug!(
+        log,
+        "sampling environment map with a {}x{} octahedral map", size, size
+    );
+
+    // all texels subtend the same solid angle, the marginal and conditional cdfs follow the
+    // texel luminance directly
+    let mut img = Vec::with_capacity(size * size);
+    for row in 0..size {
+        for col in 0..size {
+            let w = equal_area_square_to_sphere(&na::Point2::new(
+                (col as f32 + 0.5) / size as f32,
+                (row as f32 + 0.5) / size as f32,
+            ));
+            img.push(environment.lookup(&w).y());
+        }
+    }
+
+    Box::new(Distribution2D::new(&img[..], size, size))
 }
 
 impl Light for InfiniteAreaLight {
@@ -413,20 +494,10 @@ impl Light for InfiniteAreaLight {
             return Spectrum::new(0.0);
         }
 
-        let theta = uv[1] * std::f32::consts::PI;
-        let phi = uv[0] * 2.0 * std::f32::consts::PI;
-        let cos_theta = theta.cos();
-        let sin_theta = theta.sin();
-        let sin_phi = phi.sin();
-        let cos_phi = phi.cos();
-
-        *wi = self.light_to_world
-            * na::Vector3::new(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta);
-        if sin_theta == 0.0 {
-            *pdf = 0.0;
-        } else {
-            *pdf = map_pdf / (2.0 * std::f32::consts::PI * std::f32::consts::PI * sin_theta);
-        }
+        let (w, uv_pdf) = self.uv_to_direction(&uv, map_pdf);
+        *wi = self.light_to_world * w;
+        *pdf = uv_pdf;
+        let l = self.environment.lookup(&w);
 
         *vis = Some(VisibilityTester {
             p0: *reference,
@@ -437,7 +508,7 @@ impl Light for InfiniteAreaLight {
             },
         });
 
-        self.l_map.lookup_width(&uv, 0.0)
+        l
     }
 
     fn power(&self) -> Spectrum {
@@ -445,18 +516,10 @@ impl Light for InfiniteAreaLight {
     }
 
     fn pdf_li(&self, _reference: &Interaction, w: &nalgebra::Vector3<f32>) -> f32 {
-        let wi = self.world_to_light * w;
-        let theta = spherical_theta(&wi);
-        let phi = spherical_phi(&wi);
-        let sin_theta = theta.sin();
-        if sin_theta == 0.0 {
-            0.0
-        } else {
-            self.distribution.pdf(&na::Point2::new(
-                phi * INV_2_PI,
-                theta * std::f32::consts::FRAC_1_PI,
-            )) / (2.0 * std::f32::consts::PI * std::f32::consts::PI * sin_theta)
-        }
+        let wi = (self.world_to_light * w).normalize();
+        let uv = self.direction_to_uv(&wi);
+        let map_pdf = self.distribution.pdf(&uv);
+        self.uv_to_direction(&uv, map_pdf).1
     }
 
     fn sample_le(
@@ -487,14 +550,10 @@ impl Light for InfiniteAreaLight {
 
     fn le(&self, r: &RayDifferential) -> Spectrum {
         let w = (self.world_to_light * r.ray.d).normalize();
-        let st = na::Point2::new(
-            spherical_phi(&w) * INV_2_PI,
-            spherical_theta(&w) * std::f32::consts::FRAC_1_PI,
-        );
 
-        trace!(self.log, "lookup env map with st: {:?}", st);
+        trace!(self.log, "lookup env map in direction: {:?}", w);
 
-        self.l_map.lookup_width(&st, 0.0)
+        self.environment.lookup(&w)
     }
 
     fn flags(&self) -> LightFlags {
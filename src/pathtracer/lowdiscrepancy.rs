@@ -1,8 +1,9 @@
+use super::sampling::{shuffle, Random};
 use super::sobolmatrices::{
     NUM_SOBOL_DIMENSIONS, SOBOL_MATRICES_32, SOBOL_MATRIX_SIZE, VD_C_SOBOL_MATRICES,
     VD_C_SOBOL_MATRICES_INV,
 };
-use crate::common::math::ONE_MINUS_EPSILON;
+use crate::common::math::{abs_mod, ONE_MINUS_EPSILON};
 
 const INV_1_2_32: f32 = hexf32!("0x1.p-32");
 
@@ -55,3 +56,271 @@ pub fn sobol_sample(mut a: i64, dimension: usize, scramble: u64) -> f32 {
 
     ONE_MINUS_EPSILON.min(v as f32 * INV_1_2_32)
 }
+
+/// dimensions the halton sampler can draw, one prime base each
+pub const PRIME_TABLE_SIZE: usize = 1000;
+
+lazy_static::lazy_static! {
+    /// the first `PRIME_TABLE_SIZE` primes, sieved once
+    pub static ref PRIMES: Vec<u64> = {
+        // the 1000th prime is 7919
+        let mut composite = vec![false; 8000];
+        let mut primes = Vec::with_capacity(PRIME_TABLE_SIZE);
+        for n in 2..composite.len() {
+            if !composite[n] {
+                primes.push(n as u64);
+                for multiple in (n * n..composite.len()).step_by(n) {
+                    composite[multiple] = true;
+                }
+            }
+        }
+        primes.truncate(PRIME_TABLE_SIZE);
+        primes
+    };
+}
+
+/// the digits of `a` in the `base_index`th prime base mirrored around the radix point
+pub fn radical_inverse(base_index: usize, mut a: u64) -> f32 {
+    let base = PRIMES[base_index];
+    let inv_base = 1.0 / base as f64;
+    let mut reversed_digits = 0u64;
+    let mut inv_base_n = 1.0f64;
+    while a != 0 {
+        let next = a / base;
+        let digit = a - next * base;
+        reversed_digits = reversed_digits * base + digit;
+        inv_base_n *= inv_base;
+        a = next;
+    }
+
+    ONE_MINUS_EPSILON.min((reversed_digits as f64 * inv_base_n) as f32)
+}
+
+/// radical inverse with the digits, including the infinitely many leading zeros, mapped through
+/// the permutation `perm` of the base's digits
+pub fn scrambled_radical_inverse(base_index: usize, mut a: u64, perm: &[u16]) -> f32 {
+    let base = PRIMES[base_index];
+    let inv_base = 1.0 / base as f64;
+    let mut reversed_digits = 0u64;
+    let mut inv_base_n = 1.0f64;
+    while a != 0 {
+        let next = a / base;
+        let digit = a - next * base;
+        reversed_digits = reversed_digits * base + perm[digit as usize] as u64;
+        inv_base_n *= inv_base;
+        a = next;
+    }
+
+    // the permuted zeros past the last digit sum up to a geometric series
+    let tail = inv_base * perm[0] as f64 / (1.0 - inv_base);
+    ONE_MINUS_EPSILON.min((inv_base_n * (reversed_digits as f64 + tail)) as f32)
+}
+
+/// radical inverse with every digit permuted by a hash of `hash` and the digits before it, an
+/// owen scrambling that keeps the stratification of the sequence
+pub fn owen_scrambled_radical_inverse(base_index: usize, mut a: u64, hash: u64) -> f32 {
+    let base = PRIMES[base_index];
+    let inv_base = 1.0 / base as f64;
+    let mut reversed_digits = 0u64;
+    let mut inv_base_m = 1.0f64;
+    // digits past the precision of the result can't change it
+    while 1.0 - (base - 1) as f32 * (inv_base_m as f32) < 1.0 {
+        let next = a / base;
+        let digit = a - next * base;
+        let digit_hash = mix_bits(hash ^ reversed_digits) as u32;
+        let digit = permutation_element(digit as u32, base as u32, digit_hash) as u64;
+        reversed_digits = reversed_digits * base + digit;
+        inv_base_m *= inv_base;
+        a = next;
+    }
+
+    // rounded down, rounding to the nearest float could carry points over into the next stratum
+    let inverse = inv_base_m * reversed_digits as f64;
+    let mut rounded = inverse as f32;
+    if rounded as f64 > inverse {
+        rounded = f32::from_bits(rounded.to_bits() - 1);
+    }
+    ONE_MINUS_EPSILON.min(rounded)
+}
+
+/// the number whose radical inverse in base `base` has the `n_digits` digits of `inverse`
+pub fn inverse_radical_inverse(base: u64, mut inverse: u64, n_digits: u32) -> u64 {
+    let mut index = 0;
+    for _ in 0..n_digits {
+        let digit = inverse % base;
+        inverse /= base;
+        index = index * base + digit;
+    }
+    index
+}
+
+fn extended_gcd(a: i64, b: i64) -> (i64, i64) {
+    if b == 0 {
+        return (1, 0);
+    }
+    let (x, y) = extended_gcd(b, a % b);
+    (y, x - (a / b) * y)
+}
+
+/// `x` with `a * x = 1 (mod n)`, for coprime `a` and `n`
+pub fn multiplicative_inverse(a: i64, n: i64) -> u64 {
+    let (x, _) = extended_gcd(a, n);
+    abs_mod(x, n) as u64
+}
+
+/// a random permutation of the digits of each prime base, for `scrambled_radical_inverse`
+pub fn radical_inverse_permutations(rng: &mut Random) -> Vec<Vec<u16>> {
+    PRIMES
+        .iter()
+        .map(|&base| {
+            let mut perm = (0..base as u16).collect::<Vec<_>>();
+            shuffle(&mut perm, base as usize, 1, rng);
+            perm
+        })
+        .collect()
+}
+
+/// finalizer of murmurhash, spreading the bits of `v` across the whole word
+pub fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5d329728ea185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81dadef4bc2dd44d);
+    v ^= v >> 33;
+    v
+}
+
+/// element `i` of the permutation of `0..l` picked by `p`, computed without storing it (kensler's
+/// hashed permutations)
+pub fn permutation_element(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    i.wrapping_add(p) % l
+}
+
+/// sample `a` of the first two sobol dimensions as 32 bit fractions, together a (0,2) sequence
+pub fn sobol_02(mut a: u32) -> (u32, u32) {
+    let x = a.reverse_bits();
+    let mut y = 0;
+    let mut v = 1 << 31;
+    while a != 0 {
+        if a & 1 != 0 {
+            y ^= v;
+        }
+        v ^= v >> 1;
+        a >>= 1;
+    }
+    (x, y)
+}
+
+/// owen scrambling of the 32 bit fraction `v`, every bit flipped by a hash of `seed` and the bits
+/// above it
+pub fn owen_scramble(v: u32, seed: u64) -> u32 {
+    let mut scrambled = v;
+    for bit in 0..32 {
+        let prefix = v.checked_shr(32 - bit).unwrap_or(0) as u64;
+        let flip = mix_bits(seed ^ (prefix << 6 | bit as u64)) as u32 & 1;
+        scrambled ^= flip << (31 - bit);
+    }
+    scrambled
+}
+
+/// the first `n` points of a progressive multi-jittered (0,2) sequence, every power of 2 long
+/// prefix stratified over all the base 2 elementary intervals. that's exactly the stratification
+/// of an owen scrambled (0,2) sequence (helmer et al. 2021), which is drawn instead of placing
+/// the points by rejection
+pub fn pmj02_points(n: usize, seed: u64) -> Vec<na::Point2<f32>> {
+    // truncated to the bits of an f32, rounding could carry points over into the next stratum
+    let to_f32 = |v: u32| (v & !0xff) as f32 * INV_1_2_32;
+    let y_seed = mix_bits(seed);
+    (0..n as u32)
+        .map(|i| {
+            let (x, y) = sobol_02(i);
+            na::Point2::new(
+                to_f32(owen_scramble(x, seed)),
+                to_f32(owen_scramble(y, y_seed)),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// whether `values` fall one into each of as many equal intervals
+    fn assert_stratified(values: &[f32]) {
+        let mut seen = vec![false; values.len()];
+        for &v in values {
+            let stratum = (v * values.len() as f32) as usize;
+            assert!(stratum < values.len() && !seen[stratum]);
+            seen[stratum] = true;
+        }
+    }
+
+    #[test]
+    fn test_radical_inverse_stratification() {
+        // the base 2 dimension of halton points is stratified at every power of two
+        for k in 0..12 {
+            let n = 1u64 << k;
+            let values = (0..n).map(|a| radical_inverse(0, a)).collect::<Vec<_>>();
+            assert_stratified(&values);
+
+            for &hash in [0, 1, 0x9e3779b97f4a7c15, mix_bits(7)].iter() {
+                let values = (0..n)
+                    .map(|a| owen_scrambled_radical_inverse(0, a, hash))
+                    .collect::<Vec<_>>();
+                assert_stratified(&values);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pmj02_elementary_intervals() {
+        for &seed in [0, 1, 42, mix_bits(3)].iter() {
+            let points = pmj02_points(1 << 10, seed);
+            // every power of two long prefix has one point in each of the base 2 elementary
+            // intervals of its area, from 1 by n to n by 1
+            for k in 0..=10 {
+                let n = 1usize << k;
+                for a in 0..=k {
+                    let (nx, ny) = (1usize << a, 1usize << (k - a));
+                    let mut seen = vec![false; n];
+                    for p in &points[..n] {
+                        let x = (p.x * nx as f32) as usize;
+                        let y = (p.y * ny as f32) as usize;
+                        assert!(x < nx && y < ny && !seen[y * nx + x]);
+                        seen[y * nx + x] = true;
+                    }
+                }
+            }
+        }
+    }
+}
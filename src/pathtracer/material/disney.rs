@@ -1,9 +1,10 @@
 use crate::{
-    common::{math::lerp, spectrum::Spectrum},
+    common::{exporter::ExportedMaterial, math::lerp, spectrum::Spectrum},
+    pathtracer::arena::MemoryArena,
     pathtracer::bsdf::BSDF,
     pathtracer::bxdf::{
         abs_cos_theta,
-        fresnel::{fr_dielectric, Fresnel, FresnelInterface},
+        fresnel::{fr_dielectric, Fresnel, FresnelDielectric, FresnelInterface, FresnelThinFilm},
         microfacet::{
             MicrofacetDistribution, MicrofacetReflection, MicrofacetTransmission,
             TrowbridgeReitzDistribution,
@@ -22,16 +23,37 @@ pub struct DisneyMaterial {
     metallic: Box<dyn SyncTexture<f32>>,
     eta: Box<dyn SyncTexture<f32>>,
     roughness: Box<dyn SyncTexture<f32>>,
+    thin_film: Option<ThinFilm>,
+    specular: Option<Specular>,
+    sheen: Option<Box<dyn SyncTexture<Spectrum>>>,
+    clearcoat: Option<Clearcoat>,
     thin: bool,
     log: slog::Logger,
 }
 
-// TODO: specular tint
+/// iridescent film on top of the specular lobe, thickness is in nanometers
+pub struct ThinFilm {
+    pub weight: Box<dyn SyncTexture<f32>>,
+    pub eta: Box<dyn SyncTexture<f32>>,
+    pub thickness: Box<dyn SyncTexture<f32>>,
+}
+
+/// strength and tint of the dielectric reflection, the tint fades to white at grazing angles
+pub struct Specular {
+    pub weight: Box<dyn SyncTexture<f32>>,
+    pub color: Box<dyn SyncTexture<Spectrum>>,
+}
+
+/// smooth dielectric coat over the whole material, roughness is perceptual
+pub struct Clearcoat {
+    pub weight: Box<dyn SyncTexture<f32>>,
+    pub roughness: Box<dyn SyncTexture<f32>>,
+}
+
+// index of refraction of the clear coat, fixed like in the disney model
+const CLEARCOAT_ETA: f32 = 1.5;
+
 // TODO: anisotropic
-// TODO: sheen
-// TODO: sheen tint
-// TODO: clear coat
-// TODO: clear coat gloss
 // TODO: specular trans
 // TODO: scatter distance
 // TODO: thin
@@ -44,6 +66,7 @@ impl DisneyMaterial {
         metallic: Box<dyn SyncTexture<f32>>,
         eta: Box<dyn SyncTexture<f32>>,
         roughness: Box<dyn SyncTexture<f32>>,
+        thin_film: Option<ThinFilm>,
     ) -> Self {
         let log = log.new(o!());
         Self {
@@ -51,10 +74,52 @@ impl DisneyMaterial {
             metallic,
             eta,
             roughness,
+            thin_film,
+            specular: None,
+            sheen: None,
+            clearcoat: None,
             thin: false,
             log,
         }
     }
+
+    pub fn with_specular(mut self, specular: Specular) -> Self {
+        self.specular = Some(specular);
+        self
+    }
+
+    /// `color` of the retro reflective sheen of cloth like surfaces at grazing angles
+    pub fn with_sheen(mut self, color: Box<dyn SyncTexture<Spectrum>>) -> Self {
+        self.sheen = Some(color);
+        self
+    }
+
+    pub fn with_clearcoat(mut self, clearcoat: Clearcoat) -> Self {
+        self.clearcoat = Some(clearcoat);
+        self
+    }
+
+    /// the iridescent thin film is left out
+    pub fn to_exported(&self, si: &SurfaceMediumInteraction) -> ExportedMaterial {
+        ExportedMaterial {
+            base_color: self.color.evaluate(si),
+            metallic: self.metallic.evaluate(si),
+            roughness: self.roughness.evaluate(si),
+            ior: self.eta.evaluate(si),
+            specular: self
+                .specular
+                .as_ref()
+                .map(|specular| (specular.weight.evaluate(si), specular.color.evaluate(si))),
+            sheen: self.sheen.as_ref().map(|sheen| sheen.evaluate(si)),
+            clearcoat: self.clearcoat.as_ref().map(|clearcoat| {
+                (
+                    clearcoat.weight.evaluate(si),
+                    clearcoat.roughness.evaluate(si),
+                )
+            }),
+            ..Default::default()
+        }
+    }
 }
 
 fn schlick_weight(cos_theta: f32) -> f32 {
@@ -113,25 +178,113 @@ impl BxDFInterface for DisneyDiffuse {
     }
 }
 
+/// the sheen of the disney model, strongest where the half vector is far from both directions
+pub struct DisneySheen {
+    r: Spectrum,
+}
+
+impl DisneySheen {
+    pub fn new(r: Spectrum) -> Self {
+        Self { r }
+    }
+}
+
+impl BxDFInterface for DisneySheen {
+    fn f(&self, wo: &na::Vector3<f32>, wi: &na::Vector3<f32>) -> Spectrum {
+        let wh = wi + wo;
+        if wh.x == 0.0 && wh.y == 0.0 && wh.z == 0.0 {
+            return Spectrum::new(0.0);
+        }
+        let cos_theta_d = wi.dot(&wh.normalize());
+
+        self.r * schlick_weight(cos_theta_d)
+    }
+
+    fn get_type(&self) -> BxDFType {
+        BxDFType::BSDF_REFLECTION | BxDFType::BSDF_DIFFUSE
+    }
+}
+
+struct DisneyThinFilm {
+    weight: f32,
+    dielectric: FresnelThinFilm,
+    conductor: FresnelThinFilm,
+}
+
 pub struct DisneyFresnel {
     r0: Spectrum,
     metallic: f32,
     eta: f32,
+    thin_film: Option<DisneyThinFilm>,
+    /// weight and normal incidence tint of the dielectric reflection
+    specular: Option<(f32, Spectrum)>,
+}
+
+/// real ior that gives reflectance `r0` at normal incidence
+fn eta_from_schlick_r0(r0: f32) -> f32 {
+    let sqrt_r0 = r0.clamp(0.0, 0.99).sqrt();
+    (1.0 + sqrt_r0) / (1.0 - sqrt_r0)
 }
 
 impl DisneyFresnel {
     pub fn new(r0: Spectrum, metallic: f32, eta: f32) -> Self {
-        Self { r0, metallic, eta }
+        Self {
+            r0,
+            metallic,
+            eta,
+            thin_film: None,
+            specular: None,
+        }
+    }
+
+    pub fn with_specular(mut self, weight: f32, color: Spectrum) -> Self {
+        self.specular = Some((weight, color));
+        self
+    }
+
+    pub fn with_thin_film(mut self, weight: f32, film_eta: f32, thickness: f32) -> Self {
+        // the metallic base is approximated by a dielectric matching its normal reflectance
+        let conductor_eta = Spectrum::from_floats(
+            eta_from_schlick_r0(self.r0.r()),
+            eta_from_schlick_r0(self.r0.g()),
+            eta_from_schlick_r0(self.r0.b()),
+        );
+        self.thin_film = Some(DisneyThinFilm {
+            weight,
+            dielectric: FresnelThinFilm::dielectric(1.0, film_eta, thickness, self.eta),
+            conductor: FresnelThinFilm::conductor(
+                1.0,
+                film_eta,
+                thickness,
+                conductor_eta,
+                Spectrum::new(0.0),
+            ),
+        });
+        self
     }
 }
 
 impl FresnelInterface for DisneyFresnel {
     fn evaluate(&self, cos_i: f32) -> Spectrum {
-        lerp(
-            Spectrum::new(fr_dielectric(cos_i, 1., self.eta)),
-            fr_schlick_spectrum(&self.r0, cos_i),
-            self.metallic,
-        )
+        let mut dielectric = Spectrum::new(fr_dielectric(cos_i, 1., self.eta));
+        let mut conductor = fr_schlick_spectrum(&self.r0, cos_i);
+        if let Some(thin_film) = &self.thin_film {
+            dielectric = lerp(
+                dielectric,
+                thin_film.dielectric.evaluate(cos_i),
+                thin_film.weight,
+            );
+            conductor = lerp(
+                conductor,
+                thin_film.conductor.evaluate(cos_i),
+                thin_film.weight,
+            );
+        }
+        if let Some((weight, color)) = self.specular {
+            dielectric *= weight * lerp(color, Spectrum::new(1.0), schlick_weight(cos_i.abs()));
+        }
+
+        lerp(dielectric, conductor, self.metallic)
     }
 }
 
@@ -170,7 +323,12 @@ impl MicrofacetDistribution for DisneyMicrofacetDistribution {
 }
 
 impl MaterialInterface for DisneyMaterial {
-    fn compute_scattering_functions(&self, si: &mut SurfaceMediumInteraction, mode: TransportMode) {
+    fn compute_scattering_functions<'a>(
+        &self,
+        si: &mut SurfaceMediumInteraction<'a>,
+        mode: TransportMode,
+        arena: &'a MemoryArena,
+    ) {
         let mut bsdf = BSDF::new(&self.log, &si, 1.0);
 
         let c = self.color.evaluate(&si);
@@ -187,14 +345,6 @@ impl MaterialInterface for DisneyMaterial {
             Spectrum::new(1.0)
         };
 
-        let sheen_weight = 0.0;
-        let c_sheen = if sheen_weight > 0.0 {
-            let stint = 0.0;
-            lerp(Spectrum::new(1.), c_tint, stint)
-        } else {
-            Spectrum::new(0.0)
-        };
-
         if diffuse_weight > 0.0 {
             // TODO: thin
             if self.thin {
@@ -210,9 +360,13 @@ impl MaterialInterface for DisneyMaterial {
             }
 
             //TODO: retro-reflection
-            //TODO: sheen
-            if sheen_weight > 0.0 {
-                panic!("sheen not supported!");
+            if let Some(sheen) = &self.sheen {
+                let c_sheen = sheen.evaluate(&si);
+                if !c_sheen.is_black() {
+                    bsdf.add(BxDF::DisneySheen(DisneySheen::new(
+                        diffuse_weight * c_sheen,
+                    )));
+                }
             }
         }
 
@@ -220,7 +374,8 @@ impl MaterialInterface for DisneyMaterial {
         let aspect = 1.0;
         let ax = 0.001f32.max(sqr(rough) / aspect);
         let ay = 0.001f32.max(sqr(rough) * aspect);
-        // TODO: think about using Arc instead of Box to save memory allocations, or even ref
+        let distribution: &dyn MicrofacetDistribution =
+            arena.alloc(DisneyMicrofacetDistribution::new(ax, ay));
 
         // TODO: specular tint
         let spec_tint = 0.0;
@@ -229,17 +384,42 @@ impl MaterialInterface for DisneyMaterial {
             c,
             metallic_weight,
         );
+        let mut fresnel = DisneyFresnel::new(c_spec_0, metallic_weight, e);
+        if let Some(thin_film) = &self.thin_film {
+            let weight = thin_film.weight.evaluate(&si).clamp(0.0, 1.0);
+            if weight > 0.0 {
+                fresnel = fresnel.with_thin_film(
+                    weight,
+                    thin_film.eta.evaluate(&si),
+                    thin_film.thickness.evaluate(&si),
+                );
+            }
+        }
+        if let Some(specular) = &self.specular {
+            fresnel = fresnel.with_specular(
+                specular.weight.evaluate(&si).max(0.0),
+                specular.color.evaluate(&si),
+            );
+        }
         bsdf.add(BxDF::MicrofacetReflection(MicrofacetReflection::new(
             Spectrum::new(1.),
-            Box::new(DisneyMicrofacetDistribution::new(ax, ay)),
-            Box::new(Fresnel::Disney(DisneyFresnel::new(
-                c_spec_0,
-                metallic_weight,
-                e,
-            ))),
+            distribution,
+            Fresnel::Disney(fresnel),
         )));
 
-        // clear coat
+        if let Some(clearcoat) = &self.clearcoat {
+            let weight = clearcoat.weight.evaluate(&si).clamp(0.0, 1.0);
+            if weight > 0.0 {
+                let alpha = 0.001f32.max(sqr(clearcoat.roughness.evaluate(&si)));
+                let distribution: &dyn MicrofacetDistribution =
+                    arena.alloc(TrowbridgeReitzDistribution::new(alpha, alpha));
+                bsdf.add(BxDF::MicrofacetReflection(MicrofacetReflection::new(
+                    Spectrum::new(weight),
+                    distribution,
+                    Fresnel::Dielectric(FresnelDielectric::new(1.0, CLEARCOAT_ETA)),
+                )));
+            }
+        }
 
         if strans > 0.0 {
             let t = strans * c.sqrt();
@@ -248,7 +428,7 @@ impl MaterialInterface for DisneyMaterial {
             } else {
                 bsdf.add(BxDF::MicrofacetTransmission(MicrofacetTransmission::new(
                     t,
-                    Box::new(DisneyMicrofacetDistribution::new(ax, ay)),
+                    distribution,
                     1.0,
                     e,
                     mode,
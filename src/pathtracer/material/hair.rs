@@ -0,0 +1,119 @@
+use super::MaterialInterface;
+use crate::common::{exporter::ExportedMaterial, spectrum::Spectrum};
+use crate::pathtracer::{
+    arena::MemoryArena,
+    bsdf::BSDF,
+    bxdf::{
+        hair::{sigma_a_from_concentration, sigma_a_from_reflectance, HairBSDF},
+        BxDF,
+    },
+    texture::SyncTexture,
+    SurfaceMediumInteraction, TransportMode,
+};
+
+/// the different ways of specifying how much light the hair fiber absorbs
+pub enum HairAbsorption {
+    SigmaA(Box<dyn SyncTexture<Spectrum>>),
+    Color(Box<dyn SyncTexture<Spectrum>>),
+    Melanin {
+        eumelanin: Box<dyn SyncTexture<f32>>,
+        pheomelanin: Box<dyn SyncTexture<f32>>,
+    },
+}
+
+pub struct HairMaterial {
+    absorption: HairAbsorption,
+    eta: Box<dyn SyncTexture<f32>>,
+    beta_m: Box<dyn SyncTexture<f32>>,
+    beta_n: Box<dyn SyncTexture<f32>>,
+    alpha: Box<dyn SyncTexture<f32>>,
+    log: slog::Logger,
+}
+
+impl HairMaterial {
+    /// `beta_m` and `beta_n` are the longitudinal and azimuthal roughness, `alpha` the scale
+    /// angle in degrees
+    pub fn new(
+        log: &slog::Logger,
+        absorption: HairAbsorption,
+        eta: Box<dyn SyncTexture<f32>>,
+        beta_m: Box<dyn SyncTexture<f32>>,
+        beta_n: Box<dyn SyncTexture<f32>>,
+        alpha: Box<dyn SyncTexture<f32>>,
+    ) -> Self {
+        let log = log.new(o!());
+        Self {
+            absorption,
+            eta,
+            beta_m,
+            beta_n,
+            alpha,
+            log,
+        }
+    }
+
+    /// hair is made of curves, which aren't exported, but a color is picked all the same
+    pub fn to_exported(&self, si: &SurfaceMediumInteraction) -> ExportedMaterial {
+        let base_color = match &self.absorption {
+            HairAbsorption::Color(color) => clamp_spectrum(color.evaluate(si)),
+            HairAbsorption::SigmaA(sigma_a) => (clamp_spectrum(sigma_a.evaluate(si)) * -1.0).exp(),
+            HairAbsorption::Melanin {
+                eumelanin,
+                pheomelanin,
+            } => (sigma_a_from_concentration(
+                eumelanin.evaluate(si).max(0.0),
+                pheomelanin.evaluate(si).max(0.0),
+            ) * -1.0)
+                .exp(),
+        };
+        ExportedMaterial {
+            base_color,
+            roughness: self.beta_m.evaluate(si).clamp(0.0, 1.0),
+            ior: self.eta.evaluate(si),
+            ..Default::default()
+        }
+    }
+}
+
+fn clamp_spectrum(s: Spectrum) -> Spectrum {
+    Spectrum::from_floats(s.r().max(0.0), s.g().max(0.0), s.b().max(0.0))
+}
+
+impl MaterialInterface for HairMaterial {
+    fn compute_scattering_functions<'a>(
+        &self,
+        si: &mut SurfaceMediumInteraction<'a>,
+        _mode: TransportMode,
+        _arena: &'a MemoryArena,
+    ) {
+        let beta_m = self.beta_m.evaluate(si);
+        let beta_n = self.beta_n.evaluate(si);
+        let alpha = self.alpha.evaluate(si);
+        let eta = self.eta.evaluate(si);
+
+        let sigma_a = match &self.absorption {
+            HairAbsorption::SigmaA(sigma_a) => clamp_spectrum(sigma_a.evaluate(si)),
+            HairAbsorption::Color(color) => {
+                let c = clamp_spectrum(color.evaluate(si));
+                sigma_a_from_reflectance(&c, beta_n)
+            }
+            HairAbsorption::Melanin {
+                eumelanin,
+                pheomelanin,
+            } => sigma_a_from_concentration(
+                eumelanin.evaluate(si).max(0.0),
+                pheomelanin.evaluate(si).max(0.0),
+            ),
+        };
+
+        // curves parameterize v across their width
+        let h = -1.0 + 2.0 * si.uv[1];
+
+        let mut bsdf = BSDF::new(&self.log, si, eta);
+        bsdf.add(BxDF::Hair(HairBSDF::new(
+            h, eta, sigma_a, beta_m, beta_n, alpha,
+        )));
+
+        si.bsdf = Some(bsdf);
+    }
+}
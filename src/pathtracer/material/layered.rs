@@ -0,0 +1,80 @@
+use super::{Material, MaterialInterface};
+use crate::common::exporter::ExportedMaterial;
+use crate::pathtracer::{
+    arena::MemoryArena,
+    bsdf::BSDF,
+    bxdf::{layered::LayeredBxDF, BxDF},
+    texture::SyncTexture,
+    SurfaceMediumInteraction, TransportMode,
+};
+
+pub struct LayeredMaterial {
+    base: Box<Material>,
+    eta: Box<dyn SyncTexture<f32>>,
+    thickness: Box<dyn SyncTexture<f32>>,
+    max_depth: usize,
+    n_samples: usize,
+    log: slog::Logger,
+}
+
+impl LayeredMaterial {
+    pub fn new(
+        log: &slog::Logger,
+        base: Box<Material>,
+        eta: Box<dyn SyncTexture<f32>>,
+        thickness: Box<dyn SyncTexture<f32>>,
+        max_depth: usize,
+        n_samples: usize,
+    ) -> Self {
+        let log = log.new(o!());
+        Self {
+            base,
+            eta,
+            thickness,
+            max_depth,
+            n_samples,
+            log,
+        }
+    }
+
+    /// the coating becomes a smooth clear coat over the base
+    pub fn to_exported(&self, si: &SurfaceMediumInteraction) -> ExportedMaterial {
+        let mut exported = self.base.to_exported(si);
+        if self.thickness.evaluate(si) > 0.0 {
+            exported.clearcoat = Some((1.0, 0.0));
+        }
+        exported
+    }
+}
+
+impl MaterialInterface for LayeredMaterial {
+    fn compute_scattering_functions<'a>(
+        &self,
+        si: &mut SurfaceMediumInteraction<'a>,
+        mode: TransportMode,
+        arena: &'a MemoryArena,
+    ) {
+        self.base.compute_scattering_functions(si, mode, arena);
+        let base_bsdf = if let Some(base_bsdf) = si.bsdf.take() {
+            base_bsdf
+        } else {
+            return;
+        };
+
+        let eta = self.eta.evaluate(si);
+        let thickness = self.thickness.evaluate(si);
+
+        // the base may have perturbed the shading frame, so the coating is built after it
+        let mut bsdf = BSDF::new(&self.log, si, base_bsdf.eta);
+        bsdf.add(BxDF::Layered(LayeredBxDF::new(
+            base_bsdf.into_bxdfs(arena),
+            eta,
+            thickness,
+            self.max_depth,
+            self.n_samples,
+            mode,
+        )));
+
+        si.bsdf = Some(bsdf);
+    }
+}
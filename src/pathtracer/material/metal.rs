@@ -1,15 +1,62 @@
-use super::MaterialInterface;
-use crate::common::spectrum::Spectrum;
+use super::{exported_roughness, MaterialInterface};
+use crate::common::{exporter::ExportedMaterial, spectrum::Spectrum};
 use crate::pathtracer::{
+    arena::MemoryArena,
     bsdf::BSDF,
     bxdf::{
         fresnel::{Fresnel, FresnelConductor},
-        microfacet::{MicrofacetReflection, TrowbridgeReitzDistribution},
+        microfacet::{MicrofacetModel, MicrofacetReflection},
         BxDF,
     },
     texture::SyncTexture,
     SurfaceMediumInteraction, TransportMode,
 };
+
+/// measured complex index of refraction of common metals, `(names, eta, k)` sampled at the
+/// wavelengths representing the r, g and b channels. names include the chemical symbols mitsuba
+/// uses for its conductor presets
+const METAL_PRESETS: [(&[&str], [f32; 3], [f32; 3]); 5] = [
+    (
+        &["gold", "au"],
+        [0.143, 0.374, 1.442],
+        [3.983, 2.385, 1.603],
+    ),
+    (
+        &["copper", "cu"],
+        [0.200, 0.924, 1.102],
+        [3.912, 2.452, 2.142],
+    ),
+    (
+        &["aluminum", "aluminium", "al"],
+        [1.657, 0.880, 0.521],
+        [9.224, 6.270, 4.837],
+    ),
+    (
+        &["iron", "fe"],
+        [2.912, 2.950, 2.585],
+        [3.077, 2.932, 2.767],
+    ),
+    (
+        &["silver", "ag"],
+        [0.155, 0.117, 0.138],
+        [4.828, 3.122, 2.147],
+    ),
+];
+
+/// looks up the `(eta, k)` of a metal preset by name, case insensitive
+pub fn metal_ior(name: &str) -> Option<(Spectrum, Spectrum)> {
+    let name = name.to_lowercase();
+    METAL_PRESETS
+        .iter()
+        .find(|(names, _, _)| names.contains(&name.as_str()))
+        .map(|(_, eta, k)| {
+            (
+                Spectrum::from_slice_3(eta, false),
+                Spectrum::from_slice_3(k, false),
+            )
+        })
+}
+
 pub struct MetalMaterial {
     eta: Box<dyn SyncTexture<Spectrum>>,
     k: Box<dyn SyncTexture<Spectrum>>,
@@ -18,6 +65,7 @@ pub struct MetalMaterial {
     u_roughness: Option<Box<dyn SyncTexture<f32>>>,
     v_roughness: Option<Box<dyn SyncTexture<f32>>>,
     remap_roughness: bool,
+    distribution: MicrofacetModel,
     log: slog::Logger,
 }
 
@@ -31,6 +79,7 @@ impl MetalMaterial {
         u_roughness: Option<Box<dyn SyncTexture<f32>>>,
         v_roughness: Option<Box<dyn SyncTexture<f32>>>,
         remap_roughness: bool,
+        distribution: MicrofacetModel,
     ) -> Self {
         let log = log.new(o!());
         Self {
@@ -41,16 +90,49 @@ impl MetalMaterial {
             u_roughness,
             v_roughness,
             remap_roughness,
+            distribution,
             log,
         }
     }
+
+    /// the reflectance of the conductor at normal incidence is its base color
+    pub fn to_exported(&self, si: &SurfaceMediumInteraction) -> ExportedMaterial {
+        let eta = self.eta.evaluate(si);
+        let k = self.k.evaluate(si);
+        let reflectance =
+            |eta: f32, k: f32| ((eta - 1.0).powi(2) + k * k) / ((eta + 1.0).powi(2) + k * k);
+        // gltf metals are isotropic, so the roughnesses along u and v are averaged
+        let roughness = |roughness: &Option<Box<dyn SyncTexture<f32>>>| {
+            roughness
+                .as_ref()
+                .or_else(|| self.roughness.as_ref())
+                .map_or(0.0, |roughness| roughness.evaluate(si))
+        };
+
+        ExportedMaterial {
+            base_color: self.r.evaluate(si)
+                * Spectrum::from_floats(
+                    reflectance(eta.r(), k.r()),
+                    reflectance(eta.g(), k.g()),
+                    reflectance(eta.b(), k.b()),
+                ),
+            metallic: 1.0,
+            roughness: exported_roughness(
+                0.5 * (roughness(&self.u_roughness) + roughness(&self.v_roughness)),
+                self.remap_roughness,
+                &self.distribution,
+            ),
+            ..Default::default()
+        }
+    }
 }
 
 impl MaterialInterface for MetalMaterial {
-    fn compute_scattering_functions(
+    fn compute_scattering_functions<'a>(
         &self,
-        si: &mut SurfaceMediumInteraction,
+        si: &mut SurfaceMediumInteraction<'a>,
         _mode: TransportMode,
+        arena: &'a MemoryArena,
     ) {
         let mut bsdf = BSDF::new(&self.log, &si, 1.0);
 
@@ -75,19 +157,22 @@ impl MaterialInterface for MetalMaterial {
         };
 
         if self.remap_roughness {
-            u_rough = TrowbridgeReitzDistribution::roughness_to_alpha(u_rough);
-            v_rough = TrowbridgeReitzDistribution::roughness_to_alpha(v_rough);
+            u_rough = self.distribution.roughness_to_alpha(u_rough);
+            v_rough = self.distribution.roughness_to_alpha(v_rough);
         }
 
-        bsdf.add(BxDF::MicrofacetReflection(MicrofacetReflection::new(
-            self.r.evaluate(&si),
-            Box::new(TrowbridgeReitzDistribution::new(u_rough, v_rough)),
-            Box::new(Fresnel::Conductor(FresnelConductor::new(
-                Spectrum::new(1.),
-                self.eta.evaluate(&si),
-                self.k.evaluate(&si),
-            ))),
-        )));
+        bsdf.add(BxDF::MicrofacetReflection(
+            MicrofacetReflection::new(
+                self.r.evaluate(&si),
+                self.distribution.distribution(arena, u_rough, v_rough),
+                Fresnel::Conductor(FresnelConductor::new(
+                    Spectrum::new(1.),
+                    self.eta.evaluate(&si),
+                    self.k.evaluate(&si),
+                )),
+            )
+            .with_energy_compensation(),
+        ));
 
         si.bsdf = Some(bsdf);
     }
@@ -0,0 +1,81 @@
+use super::{Material, MaterialInterface};
+use crate::common::{exporter::ExportedMaterial, spectrum::Spectrum};
+use crate::pathtracer::{
+    arena::MemoryArena,
+    bsdf::BSDF,
+    bxdf::{BxDF, ScaledBxDF},
+    texture::SyncTexture,
+    SurfaceMediumInteraction, TransportMode,
+};
+
+pub struct MixMaterial {
+    m1: Box<Material>,
+    m2: Box<Material>,
+    amount: Box<dyn SyncTexture<f32>>,
+    log: slog::Logger,
+}
+
+impl MixMaterial {
+    pub fn new(
+        log: &slog::Logger,
+        m1: Box<Material>,
+        m2: Box<Material>,
+        amount: Box<dyn SyncTexture<f32>>,
+    ) -> Self {
+        let log = log.new(o!());
+        Self {
+            m1,
+            m2,
+            amount,
+            log,
+        }
+    }
+
+    pub fn to_exported(&self, si: &SurfaceMediumInteraction) -> ExportedMaterial {
+        let amount = self.amount.evaluate(si).clamp(0.0, 1.0);
+        self.m1
+            .to_exported(si)
+            .lerp(&self.m2.to_exported(si), amount)
+    }
+}
+
+impl MaterialInterface for MixMaterial {
+    fn compute_scattering_functions<'a>(
+        &self,
+        si: &mut SurfaceMediumInteraction<'a>,
+        mode: TransportMode,
+        arena: &'a MemoryArena,
+    ) {
+        let amount = self.amount.evaluate(si).clamp(0.0, 1.0);
+        let s1 = Spectrum::new(1.0 - amount);
+        let s2 = Spectrum::new(amount);
+
+        // both children have to be built against the same shading frame, so undo any
+        // perturbation (normal/bump maps) the first child applied before evaluating the second
+        let shading = si.shading.clone();
+        self.m1.compute_scattering_functions(si, mode, arena);
+        let bsdf_1 = si.bsdf.take();
+        si.shading = shading.clone();
+        self.m2.compute_scattering_functions(si, mode, arena);
+        let bsdf_2 = si.bsdf.take();
+        si.shading = shading;
+
+        let eta = bsdf_1
+            .as_ref()
+            .or_else(|| bsdf_2.as_ref())
+            .map_or(1.0, |bsdf| bsdf.eta);
+        let mut bsdf = BSDF::new(&self.log, si, eta);
+        for (child, scale) in vec![(bsdf_1, s1), (bsdf_2, s2)] {
+            if let Some(child) = child {
+                if scale.is_black() {
+                    continue;
+                }
+                for bxdf in child.into_bxdfs(arena) {
+                    bsdf.add(BxDF::Scaled(ScaledBxDF::new(bxdf, scale)));
+                }
+            }
+        }
+
+        si.bsdf = Some(bsdf);
+    }
+}
@@ -1,26 +1,36 @@
 pub mod disney;
+pub mod hair;
+pub mod layered;
 pub mod metal;
+pub mod mix;
+pub mod shadow_catcher;
 pub mod substrate;
+pub mod translucent;
 
 use super::{
+    arena::MemoryArena,
     bsdf::BSDF,
     bxdf::{
-        fresnel::{
-            Fresnel, FresnelDielectric, FresnelNoOp, FresnelSpecular, SpecularReflection,
-            SpecularTransmission,
-        },
-        BxDF, LambertianReflection,
+        fresnel::{Fresnel, FresnelDielectric, FresnelNoOp, FresnelSpecular, SpecularReflection},
+        microfacet::{MicrofacetModel, MicrofacetReflection, MicrofacetTransmission},
+        BxDF, LambertianReflection, OrenNayar,
     },
     texture::SyncTexture,
     SurfaceMediumInteraction, TransportMode,
 };
-use crate::common::{self, spectrum::Spectrum};
+use crate::common::{self, exporter::ExportedMaterial, spectrum::Spectrum};
 use ambassador::{delegatable_trait, Delegate};
 use common::math::coordinate_system;
 
 #[delegatable_trait]
 pub trait MaterialInterface {
-    fn compute_scattering_functions(&self, si: &mut SurfaceMediumInteraction, mode: TransportMode);
+    /// builds the bsdf at `si`, lobe data is allocated from `arena`
+    fn compute_scattering_functions<'a>(
+        &self,
+        si: &mut SurfaceMediumInteraction<'a>,
+        mode: TransportMode,
+        arena: &'a MemoryArena,
+    );
 }
 
 #[derive(Delegate)]
@@ -33,6 +43,58 @@ pub enum Material {
     Disney(disney::DisneyMaterial),
     Substrate(substrate::SubstrateMaterial),
     Normal(NormalMaterial),
+    Bump(BumpMaterial),
+    Rotation(RotationMaterial),
+    Layered(layered::LayeredMaterial),
+    Mix(mix::MixMaterial),
+    Hair(hair::HairMaterial),
+    Translucent(translucent::TranslucentMaterial),
+    ShadowCatcher(shadow_catcher::ShadowCatcherMaterial),
+}
+
+impl Material {
+    /// metallic roughness parameters approximating the material, with its textures taken at
+    /// `si`, for exporting the scene to gltf
+    pub fn to_exported(&self, si: &SurfaceMediumInteraction) -> ExportedMaterial {
+        match self {
+            Material::Matte(matte) => ExportedMaterial {
+                base_color: matte.kd.evaluate(si),
+                ..Default::default()
+            },
+            Material::Metal(metal) => metal.to_exported(si),
+            Material::Mirror(_) => ExportedMaterial {
+                metallic: 1.0,
+                roughness: 0.0,
+                ..Default::default()
+            },
+            Material::Glass(glass) => glass.to_exported(si),
+            Material::Disney(disney) => disney.to_exported(si),
+            Material::Substrate(substrate) => substrate.to_exported(si),
+            Material::Normal(normal) => normal.material.to_exported(si),
+            Material::Bump(bump) => bump.material.to_exported(si),
+            Material::Rotation(rotation) => rotation.material.to_exported(si),
+            Material::Layered(layered) => layered.to_exported(si),
+            Material::Mix(mix) => mix.to_exported(si),
+            Material::Hair(hair) => hair.to_exported(si),
+            Material::Translucent(translucent) => translucent.to_exported(si),
+            Material::ShadowCatcher(shadow_catcher) => shadow_catcher.to_exported(si),
+        }
+    }
+}
+
+/// perceptual gltf roughness of a microfacet `roughness`, which is remapped to alpha first by
+/// materials doing so
+fn exported_roughness(
+    roughness: f32,
+    remap_roughness: bool,
+    distribution: &MicrofacetModel,
+) -> f32 {
+    let alpha = if remap_roughness {
+        distribution.roughness_to_alpha(roughness)
+    } else {
+        roughness
+    };
+    alpha.max(0.0).sqrt().min(1.0)
 }
 
 // FIXME: definitely something wrong with the TBN calculations, normals not correct
@@ -83,7 +145,67 @@ pub fn bump_mapping(
     d: &Box<dyn SyncTexture<f32>>,
     si: &mut SurfaceMediumInteraction,
 ) {
-    let si_eval = si.clone_lite();
+    let mut si_eval = si.clone_lite();
+
+    // shift in u by half the screen space footprint, falling back to a small constant
+    let mut du = 0.5 * (si.dudx.abs() + si.dudy.abs());
+    if du == 0.0 {
+        du = 0.0005;
+    }
+    si_eval.general.p = si.general.p + du * si.shading.dpdu;
+    si_eval.uv = si.uv + na::Vector2::new(du, 0.0);
+    si_eval.general.n = (si.shading.dpdu.cross(&si.shading.dpdv) + du * si.dndu).normalize();
+    let u_displace = d.evaluate(&si_eval);
+
+    // shift in v
+    let mut dv = 0.5 * (si.dvdx.abs() + si.dvdy.abs());
+    if dv == 0.0 {
+        dv = 0.0005;
+    }
+    si_eval.general.p = si.general.p + dv * si.shading.dpdv;
+    si_eval.uv = si.uv + na::Vector2::new(0.0, dv);
+    si_eval.general.n = (si.shading.dpdu.cross(&si.shading.dpdv) + dv * si.dndv).normalize();
+    let v_displace = d.evaluate(&si_eval);
+
+    let displace = d.evaluate(&si);
+
+    let dpdu =
+        si.shading.dpdu + (u_displace - displace) / du * si.shading.n + displace * si.shading.dndu;
+    let dpdv =
+        si.shading.dpdv + (v_displace - displace) / dv * si.shading.n + displace * si.shading.dndv;
+    trace!(
+        log,
+        "bumped dpdu: {:?} -> {:?}, dpdv: {:?} -> {:?}",
+        si.shading.dpdu,
+        dpdu,
+        si.shading.dpdv,
+        dpdv
+    );
+
+    let dndu = si.shading.dndu;
+    let dndv = si.shading.dndv;
+    si.set_shading_geometry(&dpdu, &dpdv, &dndu, &dndv, false);
+}
+
+/// rotates the shading tangents around the shading normal by `rotation` turns, which turns the
+/// direction anisotropic lobes stretch along, e.g. for brushed metal following a texture
+pub fn tangent_rotation(
+    log: &slog::Logger,
+    rotation: &Box<dyn SyncTexture<f32>>,
+    si: &mut SurfaceMediumInteraction,
+) {
+    let angle = 2.0 * std::f32::consts::PI * rotation.evaluate(&si);
+    let r = na::Rotation3::from_axis_angle(&na::Unit::new_normalize(si.shading.n), angle);
+    trace!(
+        log,
+        "rotating tangents by {:?} radians, dpdu: {:?} -> {:?}",
+        angle,
+        si.shading.dpdu,
+        r * si.shading.dpdu
+    );
+
+    si.shading.dpdu = r * si.shading.dpdu;
+    si.shading.dpdv = r * si.shading.dpdv;
 }
 
 fn sqr(x: f32) -> f32 {
@@ -128,9 +250,14 @@ pub fn with_normal(
 }
 
 impl MaterialInterface for NormalMaterial {
-    fn compute_scattering_functions(&self, si: &mut SurfaceMediumInteraction, mode: TransportMode) {
+    fn compute_scattering_functions<'a>(
+        &self,
+        si: &mut SurfaceMediumInteraction<'a>,
+        mode: TransportMode,
+        arena: &'a MemoryArena,
+    ) {
         normal_mapping(&self.log, &self.normal_map, si);
-        self.material.compute_scattering_functions(si, mode);
+        self.material.compute_scattering_functions(si, mode, arena);
     }
 }
 
@@ -140,27 +267,127 @@ pub struct BumpMaterial {
     log: slog::Logger,
 }
 
+impl BumpMaterial {
+    pub fn new(
+        log: &slog::Logger,
+        bump_map: Box<dyn SyncTexture<f32>>,
+        material: Box<Material>,
+    ) -> Self {
+        let log = log.new(o!());
+        Self {
+            bump_map,
+            material,
+            log,
+        }
+    }
+}
+
+pub fn with_bump(
+    log: &slog::Logger,
+    material: Material,
+    bump_map: Option<Box<dyn SyncTexture<f32>>>,
+) -> Material {
+    if let Some(bump_map) = bump_map {
+        Material::Bump(BumpMaterial::new(log, bump_map, Box::new(material)))
+    } else {
+        material
+    }
+}
+
+impl MaterialInterface for BumpMaterial {
+    fn compute_scattering_functions<'a>(
+        &self,
+        si: &mut SurfaceMediumInteraction<'a>,
+        mode: TransportMode,
+        arena: &'a MemoryArena,
+    ) {
+        bump_mapping(&self.log, &self.bump_map, si);
+        self.material.compute_scattering_functions(si, mode, arena);
+    }
+}
+
+pub struct RotationMaterial {
+    rotation: Box<dyn SyncTexture<f32>>,
+    material: Box<Material>,
+    log: slog::Logger,
+}
+
+impl RotationMaterial {
+    pub fn new(
+        log: &slog::Logger,
+        rotation: Box<dyn SyncTexture<f32>>,
+        material: Box<Material>,
+    ) -> Self {
+        let log = log.new(o!());
+        Self {
+            rotation,
+            material,
+            log,
+        }
+    }
+}
+
+pub fn with_rotation(
+    log: &slog::Logger,
+    material: Material,
+    rotation: Option<Box<dyn SyncTexture<f32>>>,
+) -> Material {
+    if let Some(rotation) = rotation {
+        Material::Rotation(RotationMaterial::new(log, rotation, Box::new(material)))
+    } else {
+        material
+    }
+}
+
+impl MaterialInterface for RotationMaterial {
+    fn compute_scattering_functions<'a>(
+        &self,
+        si: &mut SurfaceMediumInteraction<'a>,
+        mode: TransportMode,
+        arena: &'a MemoryArena,
+    ) {
+        tangent_rotation(&self.log, &self.rotation, si);
+        self.material.compute_scattering_functions(si, mode, arena);
+    }
+}
+
 pub struct MatteMaterial {
     kd: Box<dyn SyncTexture<Spectrum>>,
+    sigma: Option<Box<dyn SyncTexture<f32>>>,
     log: slog::Logger,
 }
 
 impl MatteMaterial {
-    pub fn new(log: &slog::Logger, kd: Box<dyn SyncTexture<Spectrum>>) -> Self {
+    pub fn new(
+        log: &slog::Logger,
+        kd: Box<dyn SyncTexture<Spectrum>>,
+        sigma: Option<Box<dyn SyncTexture<f32>>>,
+    ) -> Self {
         let log = log.new(o!());
-        Self { kd, log }
+        Self { kd, sigma, log }
     }
 }
 
 impl MaterialInterface for MatteMaterial {
-    fn compute_scattering_functions(
+    fn compute_scattering_functions<'a>(
         &self,
-        si: &mut SurfaceMediumInteraction,
+        si: &mut SurfaceMediumInteraction<'a>,
         _mode: TransportMode,
+        _arena: &'a MemoryArena,
     ) {
         let mut bsdf = BSDF::new(&self.log, si, 1.0);
         let r = self.kd.evaluate(si);
-        bsdf.add(BxDF::Lambertian(LambertianReflection::new(r)));
+        let sigma = if let Some(sigma) = self.sigma.as_ref() {
+            sigma.evaluate(si).clamp(0.0, 90.0)
+        } else {
+            0.0
+        };
+
+        if sigma == 0.0 {
+            bsdf.add(BxDF::Lambertian(LambertianReflection::new(r)));
+        } else {
+            bsdf.add(BxDF::OrenNayar(OrenNayar::new(r, sigma)));
+        }
 
         si.bsdf = Some(bsdf);
     }
@@ -178,10 +405,11 @@ impl MirrorMaterial {
 }
 
 impl MaterialInterface for MirrorMaterial {
-    fn compute_scattering_functions(
+    fn compute_scattering_functions<'a>(
         &self,
-        si: &mut SurfaceMediumInteraction,
+        si: &mut SurfaceMediumInteraction<'a>,
         _mode: TransportMode,
+        _arena: &'a MemoryArena,
     ) {
         let mut bsdf = BSDF::new(&self.log, &si, 1.0);
         let r = Spectrum::new(1.0);
@@ -198,56 +426,111 @@ pub struct GlassMaterial {
     kr: Box<dyn SyncTexture<Spectrum>>,
     kt: Box<dyn SyncTexture<Spectrum>>,
     index: Box<dyn SyncTexture<f32>>,
+    roughness: Option<Box<dyn SyncTexture<f32>>>,
+    remap_roughness: bool,
+    distribution: MicrofacetModel,
+    absorption: Option<Spectrum>,
     log: slog::Logger,
 }
 
 impl GlassMaterial {
+    /// smooth glass when `roughness` is `None` or evaluates to zero
     pub fn new(
         log: &slog::Logger,
         kr: Box<dyn SyncTexture<Spectrum>>,
         kt: Box<dyn SyncTexture<Spectrum>>,
         index: Box<dyn SyncTexture<f32>>,
+        roughness: Option<Box<dyn SyncTexture<f32>>>,
+        remap_roughness: bool,
+        distribution: MicrofacetModel,
     ) -> Self {
         let log = log.new(o!());
-        Self { kr, kt, index, log }
+        Self {
+            kr,
+            kt,
+            index,
+            roughness,
+            remap_roughness,
+            distribution,
+            absorption: None,
+            log,
+        }
+    }
+
+    /// fills the inside of the glass with a medium absorbing `absorption` per world unit, for
+    /// closed meshes whose normals face outwards
+    pub fn with_absorption(mut self, absorption: Spectrum) -> Self {
+        self.absorption = Some(absorption);
+        self
+    }
+
+    fn to_exported(&self, si: &SurfaceMediumInteraction) -> ExportedMaterial {
+        ExportedMaterial {
+            base_color: self.kt.evaluate(si),
+            roughness: self.roughness.as_ref().map_or(0.0, |roughness| {
+                exported_roughness(
+                    roughness.evaluate(si),
+                    self.remap_roughness,
+                    &self.distribution,
+                )
+            }),
+            ior: self.index.evaluate(si),
+            transmission: 1.0,
+            absorption: self.absorption,
+            ..Default::default()
+        }
     }
 }
 
 impl MaterialInterface for GlassMaterial {
-    fn compute_scattering_functions(&self, si: &mut SurfaceMediumInteraction, mode: TransportMode) {
+    fn compute_scattering_functions<'a>(
+        &self,
+        si: &mut SurfaceMediumInteraction<'a>,
+        mode: TransportMode,
+        arena: &'a MemoryArena,
+    ) {
         let eta = self.index.evaluate(si);
         let r = self.kr.evaluate(si);
         let t = self.kt.evaluate(si);
+        let mut rough = self
+            .roughness
+            .as_ref()
+            .map_or(0.0, |roughness| roughness.evaluate(si));
 
         let mut bsdf = BSDF::new(&self.log, si, eta);
         if r.is_black() && t.is_black() {
             return;
         }
+        bsdf.absorption = self.absorption;
 
-        let is_specular = true; // TODO: add roughness factors
+        let is_specular = rough == 0.0;
 
         if is_specular {
             bsdf.add(BxDF::FresnelSpecular(FresnelSpecular::new(
                 r, t, 1.0, eta, mode,
             )));
         } else {
+            if self.remap_roughness {
+                rough = self.distribution.roughness_to_alpha(rough);
+            }
+
             if !r.is_black() {
                 let fresnel = Fresnel::Dielectric(FresnelDielectric::new(1.0, eta));
-                if is_specular {
-                    bsdf.add(BxDF::SpecularReflection(SpecularReflection::new(
-                        r, fresnel,
-                    )));
-                } else {
-                }
+                bsdf.add(BxDF::MicrofacetReflection(MicrofacetReflection::new(
+                    r,
+                    self.distribution.distribution(arena, rough, rough),
+                    fresnel,
+                )));
             }
 
             if !t.is_black() {
-                if is_specular {
-                    bsdf.add(BxDF::SpecularTransmission(SpecularTransmission::new(
-                        t, 1.0, eta, mode,
-                    )));
-                } else {
-                }
+                bsdf.add(BxDF::MicrofacetTransmission(MicrofacetTransmission::new(
+                    t,
+                    self.distribution.distribution(arena, rough, rough),
+                    1.0,
+                    eta,
+                    mode,
+                )));
             }
         }
 
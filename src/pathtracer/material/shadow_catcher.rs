@@ -0,0 +1,69 @@
+use super::MaterialInterface;
+use crate::common::{exporter::ExportedMaterial, spectrum::Spectrum};
+use crate::pathtracer::{
+    arena::MemoryArena,
+    bsdf::BSDF,
+    bxdf::{
+        fresnel::{Fresnel, FresnelNoOp, SpecularReflection},
+        BxDF, LambertianReflection,
+    },
+    texture::SyncTexture,
+    SurfaceMediumInteraction, TransportMode,
+};
+
+/// ground surfaces for compositing renders onto photographs. camera rays hitting them see the
+/// background darkened by the shadows falling on them and the scene mirrored in them, while the
+/// paths of the rest of the scene bounce off them as a diffuse surface of `kd`
+pub struct ShadowCatcherMaterial {
+    kd: Box<dyn SyncTexture<Spectrum>>,
+    reflect: Box<dyn SyncTexture<f32>>,
+    log: slog::Logger,
+}
+
+impl ShadowCatcherMaterial {
+    /// `reflect` is the fraction of the scene mirrored by the ground, zero for shadows only
+    pub fn new(
+        log: &slog::Logger,
+        kd: Box<dyn SyncTexture<Spectrum>>,
+        reflect: Box<dyn SyncTexture<f32>>,
+    ) -> Self {
+        let log = log.new(o!());
+        Self { kd, reflect, log }
+    }
+
+    pub fn reflectance(&self, si: &SurfaceMediumInteraction) -> f32 {
+        self.reflect.evaluate(si).clamp(0.0, 1.0)
+    }
+
+    pub fn to_exported(&self, si: &SurfaceMediumInteraction) -> ExportedMaterial {
+        ExportedMaterial {
+            base_color: self.kd.evaluate(si),
+            ..Default::default()
+        }
+    }
+}
+
+impl MaterialInterface for ShadowCatcherMaterial {
+    fn compute_scattering_functions<'a>(
+        &self,
+        si: &mut SurfaceMediumInteraction<'a>,
+        _mode: TransportMode,
+        _arena: &'a MemoryArena,
+    ) {
+        let mut bsdf = BSDF::new(&self.log, si, 1.0);
+        let r = self.kd.evaluate(si);
+        let reflect = self.reflectance(si);
+
+        if !r.is_black() {
+            bsdf.add(BxDF::Lambertian(LambertianReflection::new(r)));
+        }
+        if reflect > 0.0 {
+            bsdf.add(BxDF::SpecularReflection(SpecularReflection::new(
+                Spectrum::new(reflect),
+                Fresnel::NoOp(FresnelNoOp {}),
+            )));
+        }
+
+        si.bsdf = Some(bsdf);
+    }
+}
@@ -1,20 +1,24 @@
-use super::MaterialInterface;
-use crate::common::spectrum::Spectrum;
+use super::{exported_roughness, schlick_r0_from_eta, MaterialInterface};
+use crate::common::{exporter::ExportedMaterial, spectrum::Spectrum};
 use crate::pathtracer::{
+    arena::MemoryArena,
     bsdf::BSDF,
     bxdf::{
-        microfacet::{FresnelBlend, TrowbridgeReitzDistribution},
+        microfacet::{FresnelBlend, MicrofacetModel},
         BxDF,
     },
     texture::SyncTexture,
     SurfaceMediumInteraction, TransportMode,
 };
+
+/// ashikhmin-shirley fresnel blend of a diffuse base under a glossy coat
 pub struct SubstrateMaterial {
     kd: Box<dyn SyncTexture<Spectrum>>,
     ks: Box<dyn SyncTexture<Spectrum>>,
     nu: Box<dyn SyncTexture<f32>>,
     nv: Box<dyn SyncTexture<f32>>,
     remap_roughness: bool,
+    distribution: MicrofacetModel,
     log: slog::Logger,
 }
 
@@ -26,6 +30,7 @@ impl SubstrateMaterial {
         nu: Box<dyn SyncTexture<f32>>,
         nv: Box<dyn SyncTexture<f32>>,
         remap_roughness: bool,
+        distribution: MicrofacetModel,
     ) -> Self {
         let log = log.new(o!());
         Self {
@@ -34,16 +39,29 @@ impl SubstrateMaterial {
             nu,
             nv,
             remap_roughness,
+            distribution,
             log,
         }
     }
+
+    pub fn to_exported(&self, si: &SurfaceMediumInteraction) -> ExportedMaterial {
+        let roughness = 0.5 * (self.nu.evaluate(si) + self.nv.evaluate(si));
+        ExportedMaterial {
+            base_color: self.kd.evaluate(si),
+            roughness: exported_roughness(roughness, self.remap_roughness, &self.distribution),
+            // the specular color of gltf tints the reflectance of its default ior
+            specular: Some((1.0, self.ks.evaluate(si) / schlick_r0_from_eta(1.5))),
+            ..Default::default()
+        }
+    }
 }
 
 impl MaterialInterface for SubstrateMaterial {
-    fn compute_scattering_functions(
+    fn compute_scattering_functions<'a>(
         &self,
-        si: &mut SurfaceMediumInteraction,
+        si: &mut SurfaceMediumInteraction<'a>,
         _mode: TransportMode,
+        arena: &'a MemoryArena,
     ) {
         let mut bsdf = BSDF::new(&self.log, si, 1.0);
 
@@ -52,16 +70,16 @@ impl MaterialInterface for SubstrateMaterial {
         let mut rough_u = self.nu.evaluate(si);
         let mut rough_v = self.nv.evaluate(si);
 
-        if !d.is_black() || s.is_black() {
+        if !d.is_black() || !s.is_black() {
             if self.remap_roughness {
-                rough_u = TrowbridgeReitzDistribution::roughness_to_alpha(rough_u);
-                rough_v = TrowbridgeReitzDistribution::roughness_to_alpha(rough_v);
+                rough_u = self.distribution.roughness_to_alpha(rough_u);
+                rough_v = self.distribution.roughness_to_alpha(rough_v);
             }
 
             bsdf.add(BxDF::FresnelBlend(FresnelBlend::new(
                 d,
                 s,
-                Box::new(TrowbridgeReitzDistribution::new(rough_u, rough_v)),
+                self.distribution.distribution(arena, rough_u, rough_v),
             )));
         }
         si.bsdf = Some(bsdf);
@@ -0,0 +1,67 @@
+use super::MaterialInterface;
+use crate::common::{exporter::ExportedMaterial, spectrum::Spectrum};
+use crate::pathtracer::{
+    arena::MemoryArena,
+    bsdf::BSDF,
+    bxdf::{BxDF, LambertianReflection, LambertianTransmission},
+    texture::SyncTexture,
+    SurfaceMediumInteraction, TransportMode,
+};
+
+/// thin diffuse surfaces such as leaves and paper, which scatter light to both sides
+pub struct TranslucentMaterial {
+    kd: Box<dyn SyncTexture<Spectrum>>,
+    reflect: Box<dyn SyncTexture<Spectrum>>,
+    transmit: Box<dyn SyncTexture<Spectrum>>,
+    log: slog::Logger,
+}
+
+impl TranslucentMaterial {
+    /// `reflect` and `transmit` scale `kd` for the light scattered to the same and opposite side
+    pub fn new(
+        log: &slog::Logger,
+        kd: Box<dyn SyncTexture<Spectrum>>,
+        reflect: Box<dyn SyncTexture<Spectrum>>,
+        transmit: Box<dyn SyncTexture<Spectrum>>,
+    ) -> Self {
+        let log = log.new(o!());
+        Self {
+            kd,
+            reflect,
+            transmit,
+            log,
+        }
+    }
+
+    pub fn to_exported(&self, si: &SurfaceMediumInteraction) -> ExportedMaterial {
+        ExportedMaterial {
+            base_color: self.kd.evaluate(si),
+            diffuse_transmission: Some(self.transmit.evaluate(si)),
+            double_sided: true,
+            ..Default::default()
+        }
+    }
+}
+
+impl MaterialInterface for TranslucentMaterial {
+    fn compute_scattering_functions<'a>(
+        &self,
+        si: &mut SurfaceMediumInteraction<'a>,
+        _mode: TransportMode,
+        _arena: &'a MemoryArena,
+    ) {
+        let mut bsdf = BSDF::new(&self.log, si, 1.0);
+        let kd = self.kd.evaluate(si);
+        let r = self.reflect.evaluate(si) * kd;
+        let t = self.transmit.evaluate(si) * kd;
+
+        if !r.is_black() {
+            bsdf.add(BxDF::Lambertian(LambertianReflection::new(r)));
+        }
+        if !t.is_black() {
+            bsdf.add(BxDF::LambertianTransmission(LambertianTransmission::new(t)));
+        }
+
+        si.bsdf = Some(bsdf);
+    }
+}
@@ -1,35 +1,43 @@
 pub mod accelerator;
+mod arena;
 mod bsdf;
 mod bxdf;
+#[cfg(feature = "embree")]
+mod embree;
+mod environment;
+mod exporter;
 #[cfg(feature = "enable_optix")]
 pub mod gpu;
 pub mod importer;
 pub mod integrator;
 mod interaction;
+pub mod lens;
 pub mod light;
 mod lowdiscrepancy;
 mod material;
+mod noise;
 mod primitive;
 pub mod sampler;
 pub mod sampling;
 mod shape;
 mod sobolmatrices;
 mod texture;
+pub mod texture_cache;
 
 use crate::common::{
     bounds::Bounds3,
     ray::{Ray, RayDifferential},
 };
 
-use crate::common::Camera;
+use crate::common::{transform::AnimatedTransform, Camera, Projection};
 use interaction::SurfaceMediumInteraction;
 use light::SyncLight;
 use material::{Material, MaterialInterface};
-use primitive::Primitive;
+use primitive::{GeometricPrimitive, Primitive, SyncPrimitive, TransformedPrimitive};
 use shape::TriangleMesh;
 use std::sync::Arc;
 
-#[derive(PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum TransportMode {
     Radiance,
     Importance,
@@ -38,70 +46,291 @@ pub enum TransportMode {
 #[derive(Debug)]
 pub struct CameraSample {
     p_film: na::Point2<f32>,
+    /// fraction of the exposure the ray is traced at
+    time: f32,
+    /// where on the lens the ray passes through
+    p_lens: na::Point2<f32>,
 }
 
 impl Camera {
-    pub fn generate_ray(&self, sample: &CameraSample) -> Ray {
-        let p_camera = self.cam_to_screen.unproject_point(
-            &(self.raster_to_screen * na::Point3::new(sample.p_film.x, sample.p_film.y, 0.0)),
-        );
-
-        let cam_orig = na::Point3::<f32>::new(0.0, 0.0, 0.0);
-        let world_orig = self.cam_to_world * cam_orig;
-        let world_dir = self.cam_to_world * p_camera.coords;
-        Ray {
-            o: world_orig,
-            d: world_dir.normalize(),
+    /// the ray `sample` picks and its weight, 0 when a realistic lens blocks it
+    pub fn generate_ray(&self, sample: &CameraSample) -> (Ray, f32) {
+        let cam_to_world = self.cam_to_world_at(sample.time);
+        let (cam_orig, cam_dir, weight) = self
+            .camera_ray(&sample.p_film, &sample.p_lens)
+            .unwrap_or((na::Point3::origin(), -na::Vector3::z(), 0.0));
+        let ray = Ray {
+            o: cam_to_world * cam_orig,
+            d: (cam_to_world * cam_dir).normalize(),
             t_max: f32::INFINITY,
-        }
+            time: self.shutter.time(sample.time),
+        };
+        (ray, weight)
     }
 
-    pub fn generate_ray_differential(&self, sample: &CameraSample) -> RayDifferential {
-        let p_camera = self.cam_to_screen.unproject_point(
-            &(self.raster_to_screen * na::Point3::new(sample.p_film.x, sample.p_film.y, 0.0)),
-        );
-
-        let cam_orig = na::Point3::<f32>::new(0.0, 0.0, 0.0);
-        let world_orig = self.cam_to_world * cam_orig;
-        let world_dir = self.cam_to_world * p_camera.coords;
-        let rx_world_dir = self.cam_to_world * (p_camera.coords + self.dx_camera);
-        let ry_world_dir = self.cam_to_world * (p_camera.coords + self.dy_camera);
-        RayDifferential {
+    pub fn generate_ray_differential(&self, sample: &CameraSample) -> (RayDifferential, f32) {
+        let cam_to_world = self.cam_to_world_at(sample.time);
+        let (cam_orig, cam_dir, weight) = self
+            .camera_ray(&sample.p_film, &sample.p_lens)
+            .unwrap_or((na::Point3::origin(), -na::Vector3::z(), 0.0));
+        // the rays of the neighbouring pixels, passing through the same point of the lens
+        let rx = self.camera_ray(&(sample.p_film + na::Vector2::x()), &sample.p_lens);
+        let ry = self.camera_ray(&(sample.p_film + na::Vector2::y()), &sample.p_lens);
+        let has_differentials = rx.is_some() && ry.is_some();
+        let (rx_cam_orig, rx_cam_dir, _) = rx.unwrap_or((cam_orig, cam_dir, weight));
+        let (ry_cam_orig, ry_cam_dir, _) = ry.unwrap_or((cam_orig, cam_dir, weight));
+        let ray = RayDifferential {
             ray: Ray {
-                o: world_orig,
-                d: world_dir.normalize(),
+                o: cam_to_world * cam_orig,
+                d: (cam_to_world * cam_dir).normalize(),
                 t_max: f32::INFINITY,
+                time: self.shutter.time(sample.time),
             },
-            has_differentials: true,
-            rx_origin: world_orig,
-            ry_origin: world_orig,
-            rx_direction: rx_world_dir.normalize(),
-            ry_direction: ry_world_dir.normalize(),
+            has_differentials,
+            rx_origin: cam_to_world * rx_cam_orig,
+            ry_origin: cam_to_world * ry_cam_orig,
+            rx_direction: (cam_to_world * rx_cam_dir).normalize(),
+            ry_direction: (cam_to_world * ry_cam_dir).normalize(),
+        };
+        (ray, weight)
+    }
+
+    /// the point in camera space `u` lands on the lens at, the origin for a pinhole
+    fn point_on_lens(&self, u: &na::Point2<f32>) -> na::Point3<f32> {
+        if self.lens_radius > 0.0 {
+            let p_lens = sampling::concentric_sample_disk(u) * self.lens_radius;
+            na::Point3::new(p_lens.x, p_lens.y, 0.0)
+        } else {
+            na::Point3::origin()
         }
     }
+
+    /// origin, direction in camera space and weight of the ray through `p_film` and the point `u`
+    /// picks on the lens, `None` when a realistic lens blocks it. through a thin lens it passes
+    /// that point towards where the pinhole ray meets the plane in focus, the panoramic
+    /// projections are always pinholes
+    fn camera_ray(
+        &self,
+        p_film: &na::Point2<f32>,
+        u: &na::Point2<f32>,
+    ) -> Option<(na::Point3<f32>, na::Vector3<f32>, f32)> {
+        let resolution =
+            na::Vector2::new(self.film.resolution.x as f32, self.film.resolution.y as f32);
+        let p_camera = || {
+            self.cam_to_screen.unproject_point(
+                &(self.raster_to_screen * na::Point3::new(p_film.x, p_film.y, 0.0)),
+            )
+        };
+        let (orig, dir) = match &self.cam_to_screen {
+            Projection::Perspective(_) => (na::Point3::origin(), p_camera().coords),
+            Projection::Orthographic(_) => {
+                let p_camera = p_camera();
+                (
+                    na::Point3::new(p_camera.x, p_camera.y, 0.0),
+                    -na::Vector3::z(),
+                )
+            }
+            Projection::Spherical { .. } => {
+                let theta = std::f32::consts::PI * p_film.y / resolution.y;
+                let phi = 2.0 * std::f32::consts::PI * (p_film.x / resolution.x - 0.5);
+                let dir = na::Vector3::new(
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                    -theta.sin() * phi.cos(),
+                );
+                return Some((na::Point3::origin(), dir, 1.0));
+            }
+            Projection::Fisheye { fov, .. } => {
+                // y up from the center of the film
+                let offset =
+                    na::Vector2::new(p_film.x - 0.5 * resolution.x, 0.5 * resolution.y - p_film.y);
+                let r = offset.norm();
+                if r == 0.0 {
+                    return Some((na::Point3::origin(), -na::Vector3::z(), 1.0));
+                }
+                let theta = 0.5 * *fov * r / (0.5 * resolution.norm());
+                let dir = na::Vector3::new(
+                    theta.sin() * offset.x / r,
+                    theta.sin() * offset.y / r,
+                    -theta.cos(),
+                );
+                return Some((na::Point3::origin(), dir, 1.0));
+            }
+            Projection::Realistic { lens, .. } => return lens.generate_ray(p_film, &resolution, u),
+        };
+        if self.lens_radius > 0.0 {
+            // the plane in focus passes the view direction at the focal distance, tilted or not
+            let n = self.focal_plane_normal();
+            let ft = (-self.focal_distance * n.z - orig.coords.dot(&n)) / dir.dot(&n);
+            let p_focus = orig + dir * ft;
+            let orig = orig + self.point_on_lens(u).coords;
+            Some((orig, p_focus - orig, 1.0))
+        } else {
+            Some((orig, dir, 1.0))
+        }
+    }
+}
+
+/// a mesh in its object space with the transforms of its copies
+pub struct MeshInstances {
+    pub mesh: Arc<TriangleMesh>,
+    /// bottom level bvh over the triangles of the mesh, shared by all its copies
+    pub blas: Arc<dyn SyncPrimitive>,
+    /// the material every copy is shaded with
+    pub material: Arc<Material>,
+    pub instances: Vec<na::Projective3<f32>>,
+    /// the copies in the top level bvh, alongside `instances`
+    instance_primitives: Vec<Arc<dyn SyncPrimitive>>,
 }
 
 pub struct RenderScene {
     scene: Box<accelerator::BVH>,
     pub lights: Vec<Arc<dyn SyncLight>>,
     pub infinite_lights: Vec<Arc<dyn SyncLight>>,
+    /// meshes in world space
     pub meshes: Vec<Arc<TriangleMesh>>,
+    /// meshes in their object space, shared by all their copies, the top level bvh only holds
+    /// their transforms
+    pub instanced_meshes: Vec<MeshInstances>,
+    /// pages in the image textures that are loaded lazily
+    pub texture_cache: Arc<texture_cache::TextureCache>,
+    /// traces the scene instead of `scene` when set
+    accelerator: Option<Box<dyn accelerator::SyncAccelerator>>,
 }
 
 impl RenderScene {
     pub fn intersect<'a>(&'a self, r: &mut Ray, isect: &mut SurfaceMediumInteraction<'a>) -> bool {
-        self.scene.intersect(r, isect)
+        match &self.accelerator {
+            Some(accelerator) => accelerator.intersect(r, isect),
+            None => self.scene.intersect(r, isect),
+        }
     }
 
     pub fn intersect_p(&self, r: &Ray) -> bool {
-        self.scene.intersect_p(r)
+        match &self.accelerator {
+            Some(accelerator) => accelerator.intersect_p(r),
+            None => self.scene.intersect_p(r),
+        }
+    }
+
+    /// closest hits of a batch of coherent rays, like camera rays of neighbouring samples
+    pub fn intersect_n<'a>(&'a self, rays: &mut [Ray], hits: &mut [accelerator::Hit<'a>]) {
+        match &self.accelerator {
+            Some(accelerator) => accelerator.intersect_n(rays, hits),
+            None => accelerator::Accelerator::intersect_n(self.scene.as_ref(), rays, hits),
+        }
+    }
+
+    /// whether each of a batch of shadow rays is blocked
+    pub fn intersect_p_n(&self, rays: &[Ray], occluded: &mut [bool]) {
+        match &self.accelerator {
+            Some(accelerator) => accelerator.intersect_p_n(rays, occluded),
+            None => accelerator::Accelerator::intersect_p_n(self.scene.as_ref(), rays, occluded),
+        }
+    }
+
+    /// traces the opaque instanced meshes with embree, the rest of the scene keeps a bvh of its
+    /// own. instances are traced where they rest, so this is only for scenes without motion blur
+    #[cfg(feature = "embree")]
+    pub fn with_embree(mut self, log: &slog::Logger) -> Self {
+        self.accelerator = Some(Box::new(embree::EmbreeAccelerator::new(log, &self)));
+        self
     }
 
     pub fn world_bound(&self) -> Bounds3 {
         self.scene.world_bound()
     }
 
+    /// every mesh in world space, instanced meshes copied out for each of their instances
+    pub fn world_meshes(&self) -> Vec<Arc<TriangleMesh>> {
+        let mut meshes = self.meshes.clone();
+        for instanced in &self.instanced_meshes {
+            let mesh = &instanced.mesh;
+            meshes.extend(instanced.instances.iter().map(|obj_to_world| {
+                Arc::new(TriangleMesh::new_with_transform(
+                    mesh.indices.clone(),
+                    mesh.pos.clone(),
+                    mesh.normal.clone(),
+                    mesh.s.clone(),
+                    mesh.uv.clone(),
+                    mesh.colors.clone(),
+                    mesh.alpha_mask.clone(),
+                    obj_to_world,
+                ))
+            }));
+        }
+
+        meshes
+    }
+
+    /// the meshes a g-buffer draws with the transforms of their copies, world space meshes first.
+    /// g-buffer mesh ids count from one into these
+    pub fn gbuffer_meshes(&self) -> Vec<(&Arc<TriangleMesh>, Vec<na::Projective3<f32>>)> {
+        self.meshes
+            .iter()
+            .map(|mesh| (mesh, vec![na::Projective3::identity()]))
+            .chain(
+                self.instanced_meshes
+                    .iter()
+                    .map(|instanced| (&instanced.mesh, instanced.instances.clone())),
+            )
+            .collect()
+    }
+
+    /// calls `f` with every geometric primitive of the scene, the ones of instanced meshes in
+    /// their object space
+    pub fn for_each_geometric<'a>(&'a self, f: &mut dyn FnMut(&'a GeometricPrimitive)) {
+        self.scene.for_each_geometric(f);
+        for instanced in &self.instanced_meshes {
+            instanced.blas.for_each_geometric(f);
+        }
+    }
+
     pub fn get_bounding_boxes(&self) -> Vec<Bounds3> {
         self.scene.get_bounding_boxes()
     }
+
+    /// see `BVH::replace_primitives`
+    /// drops the embree scene, which edits would leave behind
+    pub fn replace_primitives<I>(&mut self, edits: I) -> accelerator::BVHUpdate
+    where
+        I: IntoIterator<Item = (usize, Arc<dyn SyncPrimitive>)>,
+    {
+        self.accelerator = None;
+        self.scene.replace_primitives(edits)
+    }
+
+    /// moves copy `instance` of `instanced_meshes[mesh]` to `obj_to_world`. only the top level
+    /// bvh is refit or rebuilt, the bvh of the mesh itself stays as it is
+    pub fn set_instance_transform(
+        &mut self,
+        mesh: usize,
+        instance: usize,
+        obj_to_world: &na::Projective3<f32>,
+    ) -> accelerator::BVHUpdate {
+        let instanced = &mut self.instanced_meshes[mesh];
+        let old = Arc::as_ptr(&instanced.instance_primitives[instance]) as *const u8;
+        let index = self
+            .scene
+            .primitives()
+            .iter()
+            .position(|primitive| Arc::as_ptr(primitive) as *const u8 == old)
+            .unwrap();
+
+        let primitive: Arc<dyn SyncPrimitive> = Arc::new(TransformedPrimitive::new(
+            Arc::clone(&instanced.blas),
+            AnimatedTransform::fixed(obj_to_world),
+        ));
+        instanced.instances[instance] = *obj_to_world;
+        instanced.instance_primitives[instance] = Arc::clone(&primitive);
+
+        self.accelerator = None;
+        self.scene
+            .replace_primitives(std::iter::once((index, primitive)))
+    }
+
+    /// sah cost ratio to the last build past which edits rebuild the bvh instead of refitting it
+    pub fn set_bvh_quality_threshold(&mut self, quality_threshold: f32) {
+        self.scene.set_quality_threshold(quality_threshold);
+    }
 }
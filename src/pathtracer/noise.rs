@@ -0,0 +1,144 @@
+//! perlin's improved gradient noise and the fractal sums built from it, as used by the
+//! procedural solid textures
+
+use crate::common::math::lerp;
+
+const NOISE_PERM_SIZE: usize = 256;
+
+#[rustfmt::skip]
+const NOISE_PERM: [u8; NOISE_PERM_SIZE] = [
+    151, 160, 137, 91, 90, 15, 131, 13, 201, 95, 96, 53, 194, 233, 7, 225, 140, 36, 103, 30,
+    69, 142, 8, 99, 37, 240, 21, 10, 23, 190, 6, 148, 247, 120, 234, 75, 0, 26, 197, 62, 94,
+    252, 219, 203, 117, 35, 11, 32, 57, 177, 33, 88, 237, 149, 56, 87, 174, 20, 125, 136,
+    171, 168, 68, 175, 74, 165, 71, 134, 139, 48, 27, 166, 77, 146, 158, 231, 83, 111, 229,
+    122, 60, 211, 133, 230, 220, 105, 92, 41, 55, 46, 245, 40, 244, 102, 143, 54, 65, 25,
+    63, 161, 1, 216, 80, 73, 209, 76, 132, 187, 208, 89, 18, 169, 200, 196, 135, 130, 116,
+    188, 159, 86, 164, 100, 109, 198, 173, 186, 3, 64, 52, 217, 226, 250, 124, 123, 5, 202,
+    38, 147, 118, 126, 255, 82, 85, 212, 207, 206, 59, 227, 47, 16, 58, 17, 182, 189, 28,
+    42, 223, 183, 170, 213, 119, 248, 152, 2, 44, 154, 163, 70, 221, 153, 101, 155, 167, 43,
+    172, 9, 129, 22, 39, 253, 19, 98, 108, 110, 79, 113, 224, 232, 178, 185, 112, 104, 218,
+    246, 97, 228, 251, 34, 242, 193, 238, 210, 144, 12, 191, 179, 162, 241, 81, 51, 145,
+    235, 249, 14, 239, 107, 49, 192, 214, 31, 181, 199, 106, 157, 184, 84, 204, 176, 115,
+    121, 50, 45, 127, 4, 150, 254, 138, 236, 205, 93, 222, 114, 67, 29, 24, 72, 243, 141,
+    128, 195, 78, 66, 215, 61, 156, 180,
+];
+
+fn perm(i: usize) -> usize {
+    NOISE_PERM[i & (NOISE_PERM_SIZE - 1)] as usize
+}
+
+fn grad(x: usize, y: usize, z: usize, dx: f32, dy: f32, dz: f32) -> f32 {
+    let h = perm(perm(perm(x) + y) + z) & 15;
+    let u = if h < 8 || h == 12 || h == 13 { dx } else { dy };
+    let v = if h < 4 || h == 12 || h == 13 { dy } else { dz };
+    (if h & 1 != 0 { -u } else { u }) + (if h & 2 != 0 { -v } else { v })
+}
+
+/// quintic fade curve, continuous up to the second derivative at the lattice points
+fn noise_weight(t: f32) -> f32 {
+    let t3 = t * t * t;
+    6.0 * t3 * t * t - 15.0 * t3 * t + 10.0 * t3
+}
+
+fn smooth_step(min: f32, max: f32, value: f32) -> f32 {
+    let v = ((value - min) / (max - min)).clamp(0.0, 1.0);
+    v * v * (3.0 - 2.0 * v)
+}
+
+/// gradient noise in roughly [-1, 1], zero on the integer lattice
+pub fn noise(p: &na::Point3<f32>) -> f32 {
+    let (fx, fy, fz) = (p.x.floor(), p.y.floor(), p.z.floor());
+    let (dx, dy, dz) = (p.x - fx, p.y - fy, p.z - fz);
+    // the permutation wraps around, so only the low bits of the cell matter
+    let ix = (fx as i64 as usize) & (NOISE_PERM_SIZE - 1);
+    let iy = (fy as i64 as usize) & (NOISE_PERM_SIZE - 1);
+    let iz = (fz as i64 as usize) & (NOISE_PERM_SIZE - 1);
+
+    let w000 = grad(ix, iy, iz, dx, dy, dz);
+    let w100 = grad(ix + 1, iy, iz, dx - 1.0, dy, dz);
+    let w010 = grad(ix, iy + 1, iz, dx, dy - 1.0, dz);
+    let w110 = grad(ix + 1, iy + 1, iz, dx - 1.0, dy - 1.0, dz);
+    let w001 = grad(ix, iy, iz + 1, dx, dy, dz - 1.0);
+    let w101 = grad(ix + 1, iy, iz + 1, dx - 1.0, dy, dz - 1.0);
+    let w011 = grad(ix, iy + 1, iz + 1, dx, dy - 1.0, dz - 1.0);
+    let w111 = grad(ix + 1, iy + 1, iz + 1, dx - 1.0, dy - 1.0, dz - 1.0);
+
+    let (wx, wy, wz) = (noise_weight(dx), noise_weight(dy), noise_weight(dz));
+    let x00 = lerp(w000, w100, wx);
+    let x10 = lerp(w010, w110, wx);
+    let x01 = lerp(w001, w101, wx);
+    let x11 = lerp(w011, w111, wx);
+    let y0 = lerp(x00, x10, wy);
+    let y1 = lerp(x01, x11, wy);
+    lerp(y0, y1, wz)
+}
+
+/// number of octaves resolvable over the filter footprint `dpdx`, `dpdy`, higher ones would
+/// only alias
+fn octaves(dpdx: &na::Vector3<f32>, dpdy: &na::Vector3<f32>, max_octaves: usize) -> f32 {
+    let len2 = dpdx.norm_squared().max(dpdy.norm_squared());
+    (-1.0 - 0.5 * len2.log2()).clamp(0.0, max_octaves as f32)
+}
+
+/// fractional brownian motion, a sum of noise octaves each twice the frequency and `omega` times
+/// the amplitude of the previous one
+pub fn fbm(
+    p: &na::Point3<f32>,
+    dpdx: &na::Vector3<f32>,
+    dpdy: &na::Vector3<f32>,
+    omega: f32,
+    max_octaves: usize,
+) -> f32 {
+    let n = octaves(dpdx, dpdy, max_octaves);
+    let n_int = n.floor() as usize;
+
+    let mut sum = 0.0;
+    let mut lambda = 1.0;
+    let mut o = 1.0;
+    for _ in 0..n_int {
+        sum += o * noise(&(p * lambda));
+        // slightly off two so the lattices of the octaves don't line up
+        lambda *= 1.99;
+        o *= omega;
+    }
+
+    // fade the last octave in instead of popping
+    let n_partial = n - n_int as f32;
+    sum + o * smooth_step(0.3, 0.7, n_partial) * noise(&(p * lambda))
+}
+
+/// like `fbm` but summing the absolute value of each octave, which creases the noise where it
+/// crosses zero
+pub fn turbulence(
+    p: &na::Point3<f32>,
+    dpdx: &na::Vector3<f32>,
+    dpdy: &na::Vector3<f32>,
+    omega: f32,
+    max_octaves: usize,
+) -> f32 {
+    let n = octaves(dpdx, dpdy, max_octaves);
+    let n_int = n.floor() as usize;
+
+    let mut sum = 0.0;
+    let mut lambda = 1.0;
+    let mut o = 1.0;
+    for _ in 0..n_int {
+        sum += o * noise(&(p * lambda)).abs();
+        lambda *= 1.99;
+        o *= omega;
+    }
+
+    // octaves too fine for the footprint contribute their average instead
+    let n_partial = n - n_int as f32;
+    sum += o * lerp(
+        0.2,
+        noise(&(p * lambda)).abs(),
+        smooth_step(0.3, 0.7, n_partial),
+    );
+    for _ in n_int..max_octaves {
+        sum += o * 0.2;
+        o *= omega;
+    }
+
+    sum
+}
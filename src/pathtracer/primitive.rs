@@ -1,31 +1,48 @@
-use super::shape::Triangle;
+use super::arena::MemoryArena;
+use super::shape::{curve::Curve, Shape, ShapeInterface, Triangle, TriangleMesh};
 use super::{
     light::DiffuseAreaLight, Material, MaterialInterface, SurfaceMediumInteraction, TransportMode,
 };
 use crate::common::bounds::Bounds3;
+use crate::common::math::{face_forward, gamma};
 use crate::common::ray::Ray;
+use crate::common::transform::AnimatedTransform;
 use std::sync::Arc;
 pub trait Primitive {
     fn intersect<'a>(&'a self, r: &mut Ray, isect: &mut SurfaceMediumInteraction<'a>) -> bool;
     fn intersect_p(&self, r: &Ray) -> bool;
     fn world_bound(&self) -> Bounds3;
     fn get_material(&self) -> &Material;
-    fn compute_scattering_functions(&self, si: &mut SurfaceMediumInteraction, mode: TransportMode);
+    fn compute_scattering_functions<'a>(
+        &self,
+        si: &mut SurfaceMediumInteraction<'a>,
+        mode: TransportMode,
+        arena: &'a MemoryArena,
+    );
     fn get_area_light(&self) -> Option<&DiffuseAreaLight>;
+    /// the mesh the primitive is a triangle of, for finding the materials of meshes when
+    /// exporting the scene
+    fn get_mesh(&self) -> Option<&Arc<TriangleMesh>> {
+        None
+    }
+    /// calls `f` with the geometric primitives the primitive is built from, for finding the
+    /// triangles of meshes by their indices. ones placed by a transform are left out, their
+    /// triangles aren't in world space
+    fn for_each_geometric<'a>(&'a self, _f: &mut dyn FnMut(&'a GeometricPrimitive)) {}
 }
 
 pub trait SyncPrimitive: Primitive + Send + Sync {}
 impl<T> SyncPrimitive for T where T: Primitive + Send + Sync {}
 
 pub struct GeometricPrimitive {
-    shape: Arc<Triangle>,
+    shape: Arc<Shape>,
     material: Arc<Material>,
     area_light: Option<Arc<DiffuseAreaLight>>,
 }
 
 impl GeometricPrimitive {
     pub fn new(
-        shape: Arc<Triangle>,
+        shape: Arc<Shape>,
         material: Arc<Material>,
         area_light: Option<Arc<DiffuseAreaLight>>,
     ) -> Self {
@@ -35,6 +52,13 @@ impl GeometricPrimitive {
             area_light,
         }
     }
+
+    pub fn triangle(&self) -> Option<&Triangle> {
+        match self.shape.as_ref() {
+            Shape::Triangle(triangle) => Some(triangle),
+            _ => None,
+        }
+    }
 }
 
 impl Primitive for GeometricPrimitive {
@@ -45,6 +69,7 @@ impl Primitive for GeometricPrimitive {
         }
 
         r.t_max = t_hit;
+        isect.general.time = r.time;
         isect.primitive = Some(self);
 
         return true;
@@ -62,11 +87,200 @@ impl Primitive for GeometricPrimitive {
         self.material.as_ref()
     }
 
-    fn compute_scattering_functions(&self, si: &mut SurfaceMediumInteraction, mode: TransportMode) {
-        self.material.compute_scattering_functions(si, mode);
+    fn compute_scattering_functions<'a>(
+        &self,
+        si: &mut SurfaceMediumInteraction<'a>,
+        mode: TransportMode,
+        arena: &'a MemoryArena,
+    ) {
+        self.material.compute_scattering_functions(si, mode, arena);
     }
 
     fn get_area_light(&self) -> Option<&DiffuseAreaLight> {
         self.area_light.as_deref()
     }
+
+    fn get_mesh(&self) -> Option<&Arc<TriangleMesh>> {
+        match self.shape.as_ref() {
+            Shape::Triangle(triangle) => Some(triangle.mesh()),
+            _ => None,
+        }
+    }
+
+    fn for_each_geometric<'a>(&'a self, f: &mut dyn FnMut(&'a GeometricPrimitive)) {
+        f(self)
+    }
+}
+
+pub struct CurvePrimitive {
+    shape: Arc<Curve>,
+    material: Arc<Material>,
+}
+
+impl CurvePrimitive {
+    pub fn new(shape: Arc<Curve>, material: Arc<Material>) -> Self {
+        Self { shape, material }
+    }
+}
+
+impl Primitive for CurvePrimitive {
+    fn intersect<'si>(&'si self, r: &mut Ray, isect: &mut SurfaceMediumInteraction<'si>) -> bool {
+        let mut t_hit = 0.0f32;
+        if !self.shape.intersect(r, &mut t_hit, isect) {
+            return false;
+        }
+
+        r.t_max = t_hit;
+        isect.general.time = r.time;
+        isect.primitive = Some(self);
+
+        return true;
+    }
+
+    fn intersect_p(&self, r: &Ray) -> bool {
+        self.shape.intersect_p(r)
+    }
+
+    fn world_bound(&self) -> Bounds3 {
+        self.shape.world_bound()
+    }
+
+    fn get_material(&self) -> &Material {
+        self.material.as_ref()
+    }
+
+    fn compute_scattering_functions<'a>(
+        &self,
+        si: &mut SurfaceMediumInteraction<'a>,
+        mode: TransportMode,
+        arena: &'a MemoryArena,
+    ) {
+        self.material.compute_scattering_functions(si, mode, arena);
+    }
+
+    // curves are too thin to be worth sampling as emitters
+    fn get_area_light(&self) -> Option<&DiffuseAreaLight> {
+        None
+    }
+}
+
+/// a shared primitive, usually the bvh of a whole mesh, placed in the world by `prim_to_world`.
+/// copies of a mesh only cost their transform this way, and moving ones are blurred by the times
+/// of the rays hitting them
+pub struct TransformedPrimitive {
+    primitive: Arc<dyn SyncPrimitive>,
+    prim_to_world: AnimatedTransform,
+    /// inverse of the transform at rest, animated ones are inverted for each ray
+    world_to_prim: na::Projective3<f32>,
+}
+
+impl TransformedPrimitive {
+    pub fn new(primitive: Arc<dyn SyncPrimitive>, prim_to_world: AnimatedTransform) -> Self {
+        Self {
+            primitive,
+            world_to_prim: prim_to_world.interpolate(0.0).inverse(),
+            prim_to_world,
+        }
+    }
+
+    /// the transforms to and from the world at `time`
+    fn transforms(&self, time: f32) -> (na::Projective3<f32>, na::Projective3<f32>) {
+        if self.prim_to_world.is_animated() {
+            let prim_to_world = self.prim_to_world.interpolate(time);
+            (prim_to_world, prim_to_world.inverse())
+        } else {
+            (self.prim_to_world.interpolate(time), self.world_to_prim)
+        }
+    }
+
+    /// the direction isn't normalized, so distances along the ray stay the same in both spaces
+    pub fn ray_to_primitive(world_to_prim: &na::Projective3<f32>, r: &Ray) -> Ray {
+        Ray {
+            o: world_to_prim * r.o,
+            d: world_to_prim * r.d,
+            t_max: r.t_max,
+            time: r.time,
+        }
+    }
+
+    /// moves a hit found with `ray_to_primitive` back to the world
+    pub fn interaction_to_world(
+        prim_to_world: &na::Projective3<f32>,
+        world_to_prim: &na::Projective3<f32>,
+        r: &Ray,
+        isect: &mut SurfaceMediumInteraction,
+    ) {
+        // normals go to world space by the inverse transpose
+        let normal_to_world = world_to_prim
+            .matrix()
+            .fixed_slice::<na::U3, na::U3>(0, 0)
+            .transpose();
+
+        // the error bounds grow by the rounding of the transform itself
+        let m = prim_to_world.matrix();
+        let linear = m.fixed_slice::<na::U3, na::U3>(0, 0).abs();
+        let translation = m.fixed_slice::<na::U3, na::U1>(0, 3).abs();
+        let p = isect.general.p;
+        isect.general.p_error = (gamma(3) + 1.0) * linear * isect.general.p_error
+            + gamma(3) * (linear * p.coords.abs() + translation);
+        isect.general.p = prim_to_world * p;
+        isect.general.wo = -r.d;
+        isect.general.n = (normal_to_world * isect.general.n).normalize();
+
+        isect.dpdu = prim_to_world * isect.dpdu;
+        isect.dpdv = prim_to_world * isect.dpdv;
+        isect.dndu = normal_to_world * isect.dndu;
+        isect.dndv = normal_to_world * isect.dndv;
+
+        let shading = &mut isect.shading;
+        shading.n = face_forward(&(normal_to_world * shading.n).normalize(), &isect.general.n);
+        shading.dpdu = prim_to_world * shading.dpdu;
+        shading.dpdv = prim_to_world * shading.dpdv;
+        shading.dndu = normal_to_world * shading.dndu;
+        shading.dndv = normal_to_world * shading.dndv;
+    }
+}
+
+impl Primitive for TransformedPrimitive {
+    fn intersect<'si>(&'si self, r: &mut Ray, isect: &mut SurfaceMediumInteraction<'si>) -> bool {
+        let (prim_to_world, world_to_prim) = self.transforms(r.time);
+        let mut ray = Self::ray_to_primitive(&world_to_prim, r);
+        if !self.primitive.intersect(&mut ray, isect) {
+            return false;
+        }
+
+        r.t_max = ray.t_max;
+        Self::interaction_to_world(&prim_to_world, &world_to_prim, r, isect);
+
+        return true;
+    }
+
+    fn intersect_p(&self, r: &Ray) -> bool {
+        let (_, world_to_prim) = self.transforms(r.time);
+        self.primitive
+            .intersect_p(&Self::ray_to_primitive(&world_to_prim, r))
+    }
+
+    fn world_bound(&self) -> Bounds3 {
+        self.prim_to_world
+            .motion_bounds(&self.primitive.world_bound())
+    }
+
+    // hits record the primitive of the instance that was hit, so these are never asked for
+    fn get_material(&self) -> &Material {
+        unreachable!("hits on transformed primitives record the primitive inside of them")
+    }
+
+    fn compute_scattering_functions<'a>(
+        &self,
+        _si: &mut SurfaceMediumInteraction<'a>,
+        _mode: TransportMode,
+        _arena: &'a MemoryArena,
+    ) {
+        unreachable!("hits on transformed primitives record the primitive inside of them")
+    }
+
+    fn get_area_light(&self) -> Option<&DiffuseAreaLight> {
+        unreachable!("instances aren't sampled as area lights, their emitters are in world space")
+    }
 }
@@ -0,0 +1,254 @@
+use std::cell::Cell;
+
+use crate::pathtracer::CameraSample;
+
+use super::{Sampler, SamplerBuilder, SamplerInterface};
+use crate::common::math::ONE_MINUS_EPSILON;
+use crate::pathtracer::lowdiscrepancy::mix_bits;
+use crate::pathtracer::sampling::Random;
+use rand::{Rng, SeedableRng};
+/// side of the square mask, tiled across the image
+const BLUE_NOISE_RESOLUTION: usize = 64;
+/// spread of the gaussian the void and cluster energy is measured with
+const BLUE_NOISE_SIGMA: f32 = 1.5;
+
+lazy_static::lazy_static! {
+    static ref BLUE_NOISE: BlueNoiseMask = BlueNoiseMask::void_and_cluster(BLUE_NOISE_RESOLUTION);
+}
+
+/// tileable mask of values in [0, 1) whose neighbouring pixels differ as much as they can, the
+/// ranks of a void and cluster dither matrix (ulichney)
+struct BlueNoiseMask {
+    resolution: usize,
+    values: Vec<f32>,
+}
+
+impl BlueNoiseMask {
+    fn void_and_cluster(resolution: usize) -> Self {
+        let n = resolution * resolution;
+        // falloff of a point's energy over toroidal offsets, so the mask tiles
+        let kernel = (0..n)
+            .map(|i| {
+                let (x, y) = (i % resolution, i / resolution);
+                let dx = x.min(resolution - x) as f32;
+                let dy = y.min(resolution - y) as f32;
+                (-(dx * dx + dy * dy) / (2.0 * BLUE_NOISE_SIGMA * BLUE_NOISE_SIGMA)).exp()
+            })
+            .collect::<Vec<_>>();
+        let splat = |energy: &mut [f32], p: usize, sign: f32| {
+            let (px, py) = (p % resolution, p / resolution);
+            for (i, e) in energy.iter_mut().enumerate() {
+                let dx = (i % resolution + resolution - px) % resolution;
+                let dy = (i / resolution + resolution - py) % resolution;
+                *e += sign * kernel[dy * resolution + dx];
+            }
+        };
+        // the set pixel with the most energy around it, or the unset one with the least
+        let tightest_cluster = |pattern: &[bool], energy: &[f32]| {
+            (0..n)
+                .filter(|&i| pattern[i])
+                .max_by(|&a, &b| energy[a].partial_cmp(&energy[b]).unwrap())
+                .unwrap()
+        };
+        let largest_void = |pattern: &[bool], energy: &[f32]| {
+            (0..n)
+                .filter(|&i| !pattern[i])
+                .min_by(|&a, &b| energy[a].partial_cmp(&energy[b]).unwrap())
+                .unwrap()
+        };
+
+        // a tenth of the pixels set at random, then moved from clusters to voids until even
+        let mut rng = Random::seed_from_u64(0);
+        let mut pattern = vec![false; n];
+        let mut energy = vec![0.0; n];
+        let initial = (n / 10).max(1);
+        let mut set = 0;
+        while set < initial {
+            let p = rng.gen_range(0, n);
+            if !pattern[p] {
+                pattern[p] = true;
+                splat(&mut energy, p, 1.0);
+                set += 1;
+            }
+        }
+        loop {
+            let cluster = tightest_cluster(&pattern, &energy);
+            pattern[cluster] = false;
+            splat(&mut energy, cluster, -1.0);
+            let void = largest_void(&pattern, &energy);
+            pattern[void] = true;
+            splat(&mut energy, void, 1.0);
+            if void == cluster {
+                break;
+            }
+        }
+
+        // the initial points are ranked by removing them cluster first, the rest by filling the
+        // voids. past half the pixels the largest void of the set pixels is also the tightest
+        // cluster of the unset ones, so filling on ranks those the same way
+        let mut ranks = vec![0; n];
+        let (mut removing, mut removing_energy) = (pattern.clone(), energy.clone());
+        for rank in (0..initial).rev() {
+            let cluster = tightest_cluster(&removing, &removing_energy);
+            removing[cluster] = false;
+            splat(&mut removing_energy, cluster, -1.0);
+            ranks[cluster] = rank;
+        }
+        for rank in initial..n {
+            let void = largest_void(&pattern, &energy);
+            pattern[void] = true;
+            splat(&mut energy, void, 1.0);
+            ranks[void] = rank;
+        }
+
+        Self {
+            resolution,
+            values: ranks
+                .into_iter()
+                .map(|rank| (rank as f32 + 0.5) / n as f32)
+                .collect(),
+        }
+    }
+
+    /// value at `p` of the mask moved by an offset picked by `dimension`, so the dimensions of a
+    /// pixel are shifted apart
+    fn value(&self, p: &na::Point2<i32>, dimension: usize) -> f32 {
+        let offset = mix_bits(dimension as u64);
+        let resolution = self.resolution as u64;
+        let x = (p.x as u32 as u64 + offset % resolution) % resolution;
+        let y = (p.y as u32 as u64 + (offset >> 32) % resolution) % resolution;
+        self.values[(y * resolution + x) as usize]
+    }
+}
+
+/// shifts the samples of every pixel around the unit interval by a blue noise mask, one offset
+/// per pixel and dimension. the samples of a pixel keep their stratification while the error of
+/// neighbouring pixels decorrelates, so low sample counts look like fine grain instead of blotches.
+/// the requested arrays are left to the wrapped sampler
+pub struct BlueNoiseSampler {
+    sampler: Box<Sampler>,
+    pixel: na::Point2<i32>,
+    dimension: Cell<usize>,
+}
+
+#[derive(Clone)]
+pub struct BlueNoiseSamplerBuilder {
+    sampler: Box<SamplerBuilder>,
+}
+
+impl BlueNoiseSamplerBuilder {
+    pub fn new(sampler: SamplerBuilder) -> Self {
+        // generated up front rather than by the first tile to render
+        lazy_static::initialize(&BLUE_NOISE);
+        Self {
+            sampler: Box::new(sampler),
+        }
+    }
+
+    pub fn build(&self) -> BlueNoiseSampler {
+        BlueNoiseSampler {
+            sampler: Box::new(self.sampler.build()),
+            pixel: na::Point2::new(0, 0),
+            dimension: Cell::new(0),
+        }
+    }
+
+    pub fn with_seed(&mut self, seed: u64) -> &mut Self {
+        self.sampler.with_seed(seed);
+        self
+    }
+
+    pub fn request_1d_array(&mut self, n: usize) -> &mut Self {
+        self.sampler.request_1d_array(n);
+        self
+    }
+
+    pub fn request_2d_array(&mut self, n: usize) -> &mut Self {
+        self.sampler.request_2d_array(n);
+        self
+    }
+
+    pub fn samples_per_pixel(&self) -> usize {
+        self.sampler.samples_per_pixel()
+    }
+
+    pub fn with_samples_per_pixel(&self, samples_per_pixel: usize) -> Self {
+        Self::new(self.sampler.with_samples_per_pixel(samples_per_pixel))
+    }
+}
+
+impl SamplerInterface for BlueNoiseSampler {
+    fn start_pixel(&mut self, p: &na::Point2<i32>) {
+        self.sampler.start_pixel(p);
+        self.pixel = *p;
+        self.dimension.set(0);
+    }
+
+    fn get_camera_sample(&mut self, p_raster: &na::Point2<i32>) -> CameraSample {
+        CameraSample {
+            p_film: na::Point2::new(p_raster.x as f32, p_raster.y as f32) + self.get_2d().coords,
+            time: self.get_1d(),
+            p_lens: self.get_2d(),
+        }
+    }
+
+    fn start_next_sample(&mut self) -> bool {
+        self.dimension.set(0);
+        self.sampler.start_next_sample()
+    }
+
+    fn set_sample_number(&mut self, sample_num: usize) -> bool {
+        self.dimension.set(0);
+        self.sampler.set_sample_number(sample_num)
+    }
+
+    fn get_1d(&self) -> f32 {
+        let u = self.sampler.get_1d();
+        let dimension = self.dimension.get();
+        self.dimension.set(dimension + 1);
+        self.shift(u, dimension)
+    }
+
+    fn get_2d(&self) -> na::Point2<f32> {
+        let u = self.sampler.get_2d();
+        let dimension = self.dimension.get();
+        self.dimension.set(dimension + 2);
+        na::Point2::new(self.shift(u.x, dimension), self.shift(u.y, dimension + 1))
+    }
+
+    fn samples_per_pixel(&self) -> usize {
+        self.sampler.samples_per_pixel()
+    }
+
+    fn get_1d_array(&self, n: usize) -> Option<&[f32]> {
+        self.sampler.get_1d_array(n)
+    }
+
+    fn get_2d_array(&self, n: usize) -> Option<&[na::Point2<f32>]> {
+        self.sampler.get_2d_array(n)
+    }
+
+    fn get_current_sample_number(&self) -> usize {
+        self.sampler.get_current_sample_number()
+    }
+
+    fn get_dimension(&self) -> usize {
+        self.dimension.get()
+    }
+
+    fn set_dimension(&self, dimension: usize) {
+        self.dimension.set(dimension);
+        self.sampler.set_dimension(dimension);
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        self.sampler.set_seed(seed);
+    }
+}
+
+impl BlueNoiseSampler {
+    fn shift(&self, u: f32, dimension: usize) -> f32 {
+        let u = u + BLUE_NOISE.value(&self.pixel, dimension);
+        ONE_MINUS_EPSILON.min(if u >= 1.0 { u - 1.0 } else { u })
+    }
+}
@@ -0,0 +1,268 @@
+use std::cell::Cell;
+
+use crate::pathtracer::CameraSample;
+
+use super::{CoreSampler, SamplerInterface};
+use crate::common::math::ONE_MINUS_EPSILON;
+use crate::pathtracer::lowdiscrepancy::{mix_bits, permutation_element};
+/// dimensions the requested arrays are hashed by, apart from the ones drawn per sample
+const ARRAY_1D_DIMENSION: u64 = 1 << 32;
+const ARRAY_2D_DIMENSION: u64 = 1 << 33;
+
+/// kensler's correlated multi-jittered sampling: the samples of a pixel sit in the cells of an
+/// `m` by `n` grid, one per row and column of the finer grid within it, with the same shuffle for
+/// every row and column. every pattern is computed from its index and a hash, nothing is stored
+pub struct CMJSampler {
+    sampler: CoreSampler,
+    dimension: Cell<usize>,
+    pixel_hash: u64,
+}
+
+#[derive(Clone)]
+pub struct CMJSamplerBuilder {
+    samples_per_pixel: usize,
+    sample_1d_array_sizes: Vec<usize>,
+    sample_2d_array_sizes: Vec<usize>,
+    log: slog::Logger,
+}
+
+impl CMJSamplerBuilder {
+    pub fn new(log: &slog::Logger, samples_per_pixel: usize) -> Self {
+        let log = log.new(o!("module" => "sampler"));
+        Self {
+            samples_per_pixel,
+            sample_1d_array_sizes: vec![],
+            sample_2d_array_sizes: vec![],
+            log,
+        }
+    }
+
+    pub fn build(&self) -> CMJSampler {
+        CMJSampler {
+            sampler: CoreSampler::new(
+                self.samples_per_pixel,
+                self.sample_1d_array_sizes.clone(),
+                self.sample_2d_array_sizes.clone(),
+                self.sample_1d_array_sizes
+                    .iter()
+                    .map(|n| vec![0.0; n * self.samples_per_pixel])
+                    .collect(),
+                self.sample_2d_array_sizes
+                    .iter()
+                    .map(|n| vec![na::Point2::new(0.0, 0.0); n * self.samples_per_pixel])
+                    .collect(),
+            ),
+            dimension: Cell::new(0),
+            pixel_hash: 0,
+        }
+    }
+
+    /// the pixels are decorrelated by hashing, the tiles don't need seeding apart
+    pub fn with_seed(&mut self, _seed: u64) -> &mut Self {
+        self
+    }
+
+    pub fn request_1d_array(&mut self, n: usize) -> &mut Self {
+        self.sample_1d_array_sizes.push(n);
+        self
+    }
+
+    pub fn request_2d_array(&mut self, n: usize) -> &mut Self {
+        self.sample_2d_array_sizes.push(n);
+        self
+    }
+
+    pub fn samples_per_pixel(&self) -> usize {
+        self.samples_per_pixel
+    }
+
+    /// the same sampler taking `samples_per_pixel` samples, without the requested arrays
+    pub fn with_samples_per_pixel(&self, samples_per_pixel: usize) -> Self {
+        Self::new(&self.log, samples_per_pixel)
+    }
+}
+
+impl SamplerInterface for CMJSampler {
+    fn start_pixel(&mut self, p: &na::Point2<i32>) {
+        self.sampler.start_pixel(p);
+        self.dimension.set(0);
+        self.pixel_hash = mix_bits((p.x as u32 as u64) << 32 | p.y as u32 as u64);
+
+        // every pixel sample gets a pattern of its own, sized to the array
+        let samples_per_pixel = self.sampler.samples_per_pixel;
+        for i in 0..self.sampler.sample_1d_array_sizes.len() {
+            let count = self.sampler.sample_1d_array_sizes[i];
+            for j in 0..samples_per_pixel {
+                let pattern = self.pattern(ARRAY_1D_DIMENSION + i as u64, j);
+                for k in 0..count {
+                    self.sampler.sample_array_1d[i][j * count + k] = cmj_1d(k, count, pattern);
+                }
+            }
+        }
+        for i in 0..self.sampler.sample_2d_array_sizes.len() {
+            let count = self.sampler.sample_2d_array_sizes[i];
+            for j in 0..samples_per_pixel {
+                let pattern = self.pattern(ARRAY_2D_DIMENSION + i as u64, j);
+                for k in 0..count {
+                    self.sampler.sample_array_2d[i][j * count + k] = cmj_2d(k, count, pattern);
+                }
+            }
+        }
+    }
+
+    fn get_camera_sample(&mut self, p_raster: &na::Point2<i32>) -> CameraSample {
+        CameraSample {
+            p_film: na::Point2::new(p_raster.x as f32, p_raster.y as f32) + self.get_2d().coords,
+            time: self.get_1d(),
+            p_lens: self.get_2d(),
+        }
+    }
+
+    fn start_next_sample(&mut self) -> bool {
+        self.dimension.set(0);
+        self.sampler.start_next_sample()
+    }
+
+    fn set_sample_number(&mut self, sample_num: usize) -> bool {
+        self.dimension.set(0);
+        self.sampler.set_sample_number(sample_num)
+    }
+
+    fn get_1d(&self) -> f32 {
+        let dimension = self.dimension.get();
+        self.dimension.set(dimension + 1);
+        cmj_1d(
+            self.sampler.current_pixel_sample_index,
+            self.sampler.samples_per_pixel,
+            self.pattern(dimension as u64, 0),
+        )
+    }
+
+    fn get_2d(&self) -> na::Point2<f32> {
+        let dimension = self.dimension.get();
+        self.dimension.set(dimension + 2);
+        cmj_2d(
+            self.sampler.current_pixel_sample_index,
+            self.sampler.samples_per_pixel,
+            self.pattern(dimension as u64, 0),
+        )
+    }
+
+    fn samples_per_pixel(&self) -> usize {
+        self.sampler.samples_per_pixel
+    }
+
+    fn get_1d_array(&self, n: usize) -> Option<&[f32]> {
+        self.sampler.get_1d_array(n)
+    }
+
+    fn get_2d_array(&self, n: usize) -> Option<&[na::Point2<f32>]> {
+        self.sampler.get_2d_array(n)
+    }
+
+    fn get_current_sample_number(&self) -> usize {
+        self.sampler.current_pixel_sample_index
+    }
+
+    fn get_dimension(&self) -> usize {
+        self.dimension.get()
+    }
+
+    fn set_dimension(&self, dimension: usize) {
+        self.dimension.set(dimension);
+    }
+
+    /// the samples only depend on the pixel, there's no stream to restart
+    fn set_seed(&mut self, _seed: u64) {}
+}
+
+impl CMJSampler {
+    /// pattern of `dimension` in the current pixel, and of `sample` for the arrays
+    fn pattern(&self, dimension: u64, sample: usize) -> u32 {
+        mix_bits(self.pixel_hash ^ mix_bits(dimension) ^ (sample as u64) << 40) as u32
+    }
+}
+
+/// sample `s` of `count` jittered strata, in the order shuffled by `pattern`
+fn cmj_1d(s: usize, count: usize, pattern: u32) -> f32 {
+    let stratum = permutation_element(s as u32, count as u32, pattern.wrapping_mul(0x68bc21eb));
+    let jitter = rand_float(s as u32, pattern.wrapping_mul(0x967a889b));
+    ONE_MINUS_EPSILON.min((stratum as f32 + jitter) / count as f32)
+}
+
+/// sample `s` of a correlated multi-jittered pattern of `count` samples on a grid of about
+/// square cells, for counts that don't fill the grid the last row is left partly empty
+fn cmj_2d(s: usize, count: usize, pattern: u32) -> na::Point2<f32> {
+    let count = count as u32;
+    let m = ((count as f32).sqrt() as u32).max(1);
+    let n = (count + m - 1) / m;
+    let s = permutation_element(s as u32, count, pattern.wrapping_mul(0x51633e2d));
+    let sx = permutation_element(s % m, m, pattern.wrapping_mul(0x68bc21eb));
+    let sy = permutation_element(s / m, n, pattern.wrapping_mul(0x02e5be93));
+    let jx = rand_float(s, pattern.wrapping_mul(0x967a889b));
+    let jy = rand_float(s, pattern.wrapping_mul(0x368cc8b7));
+    na::Point2::new(
+        ONE_MINUS_EPSILON.min((sx as f32 + (sy as f32 + jx) / n as f32) / m as f32),
+        ONE_MINUS_EPSILON.min((s as f32 + jy) / count as f32),
+    )
+}
+
+/// hash of `i` by `p` as a float in [0, 1)
+fn rand_float(mut i: u32, p: u32) -> f32 {
+    i ^= p;
+    i ^= i >> 17;
+    i ^= i >> 10;
+    i = i.wrapping_mul(0xb36534e5);
+    i ^= i >> 12;
+    i ^= i >> 21;
+    i = i.wrapping_mul(0x93fc4795);
+    i ^= 0xdf6e307f;
+    i ^= i >> 17;
+    i = i.wrapping_mul(1 | p >> 18);
+    i as f32 * (1.0 / 4294967808.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// whether no two of `values` fall into the same of `strata` equal intervals
+    fn assert_stratified(values: impl Iterator<Item = f32>, strata: usize) {
+        let mut seen = vec![false; strata];
+        for v in values {
+            let stratum = (v * strata as f32) as usize;
+            assert!(stratum < strata && !seen[stratum]);
+            seen[stratum] = true;
+        }
+    }
+
+    #[test]
+    fn test_cmj_stratification() {
+        for k in 0..=8 {
+            let count = 1usize << k;
+            for &pattern in [0, 1, 0x12345678, mix_bits(5) as u32].iter() {
+                assert_stratified((0..count).map(|s| cmj_1d(s, count, pattern)), count);
+
+                let points = (0..count)
+                    .map(|s| cmj_2d(s, count, pattern))
+                    .collect::<Vec<_>>();
+                let m = ((count as f32).sqrt() as usize).max(1);
+                let n = (count + m - 1) / m;
+                // one sample per row of the count strata, and per column of the finer grid
+                assert_stratified(points.iter().map(|p| p.y), count);
+                assert_stratified(points.iter().map(|p| p.x), m * n);
+
+                // counts that fill the grid also have one sample in each of its cells
+                if m * n == count {
+                    assert_stratified(points.iter().map(|p| p.x), count);
+                    let mut seen = vec![false; count];
+                    for p in &points {
+                        let cell = (p.y * n as f32) as usize * m + (p.x * m as f32) as usize;
+                        assert!(!seen[cell]);
+                        seen[cell] = true;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use std::{cell::Cell, sync::Arc};
+
+use crate::{common::bounds::Bounds2i, pathtracer::CameraSample};
+
+use super::{CoreSampler, SamplerInterface};
+use crate::common::math::abs_mod;
+use crate::pathtracer::lowdiscrepancy::{
+    inverse_radical_inverse, mix_bits, multiplicative_inverse, owen_scrambled_radical_inverse,
+    radical_inverse, radical_inverse_permutations, scrambled_radical_inverse, PRIMES,
+    PRIME_TABLE_SIZE,
+};
+use crate::pathtracer::sampling::Random;
+use rand::SeedableRng;
+const ARRAY_START_DIM: usize = 5;
+/// the first two dimensions of the sequence are mapped onto blocks of at most this many pixels,
+/// larger images repeat the blocks
+const MAX_RESOLUTION: i32 = 128;
+
+/// how the digits of the higher dimensions are scrambled, breaking up the patterns neighbouring
+/// prime bases make together
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HaltonScrambling {
+    None,
+    /// one random permutation of the digits per dimension (faure)
+    Permutations,
+    /// every digit permuted by a hash of the digits before it (owen)
+    Owen,
+}
+
+pub struct HaltonSampler {
+    sampler: CoreSampler,
+    dimension: Cell<usize>,
+    interval_sample_index: u64,
+    array_end_dim: usize,
+
+    scrambling: HaltonScrambling,
+    permutations: Arc<Vec<Vec<u16>>>,
+    base_scales: [u64; 2],
+    base_exponents: [u32; 2],
+    sample_stride: u64,
+    mult_inverse: [u64; 2],
+    offset_for_current_pixel: u64,
+}
+
+#[derive(Clone)]
+pub struct HaltonSamplerBuilder {
+    samples_per_pixel: usize,
+    sample_bounds: Bounds2i,
+    scrambling: HaltonScrambling,
+    permutations: Arc<Vec<Vec<u16>>>,
+    base_scales: [u64; 2],
+    base_exponents: [u32; 2],
+    sample_1d_array_sizes: Vec<usize>,
+    sample_2d_array_sizes: Vec<usize>,
+    log: slog::Logger,
+}
+
+impl HaltonSamplerBuilder {
+    pub fn new(
+        log: &slog::Logger,
+        samples_per_pixel: usize,
+        sample_bounds: &Bounds2i,
+        scrambling: HaltonScrambling,
+    ) -> Self {
+        let log = log.new(o!("module" => "sampler"));
+
+        // the pixel block is scaled up to the next power of 2 across and of 3 down, so the
+        // first two dimensions land one sample in each pixel every `sample_stride` samples
+        let diag = sample_bounds.diagonal();
+        let mut base_scales = [1u64; 2];
+        let mut base_exponents = [0u32; 2];
+        for i in 0..2 {
+            let scale = diag[i].min(MAX_RESOLUTION) as u64;
+            while base_scales[i] < scale {
+                base_scales[i] *= PRIMES[i];
+                base_exponents[i] += 1;
+            }
+        }
+
+        // the permutations are the same for every render, the sequence is deterministic
+        let permutations = if scrambling == HaltonScrambling::Permutations {
+            radical_inverse_permutations(&mut Random::seed_from_u64(0))
+        } else {
+            vec![]
+        };
+
+        Self {
+            samples_per_pixel,
+            sample_bounds: *sample_bounds,
+            scrambling,
+            permutations: Arc::new(permutations),
+            base_scales,
+            base_exponents,
+            sample_1d_array_sizes: vec![],
+            sample_2d_array_sizes: vec![],
+            log,
+        }
+    }
+
+    pub fn build(&self) -> HaltonSampler {
+        let sample_stride = self.base_scales[0] * self.base_scales[1];
+        HaltonSampler {
+            sampler: CoreSampler::new(
+                self.samples_per_pixel,
+                self.sample_1d_array_sizes.clone(),
+                self.sample_2d_array_sizes.clone(),
+                self.sample_1d_array_sizes
+                    .iter()
+                    .map(|n| vec![0.0; n * self.samples_per_pixel])
+                    .collect(),
+                self.sample_2d_array_sizes
+                    .iter()
+                    .map(|n| vec![na::Point2::new(0.0, 0.0); n * self.samples_per_pixel])
+                    .collect(),
+            ),
+            dimension: Cell::new(0),
+            interval_sample_index: 0,
+            array_end_dim: 0,
+            scrambling: self.scrambling,
+            permutations: Arc::clone(&self.permutations),
+            base_scales: self.base_scales,
+            base_exponents: self.base_exponents,
+            sample_stride,
+            mult_inverse: [
+                multiplicative_inverse(self.base_scales[1] as i64, self.base_scales[0] as i64),
+                multiplicative_inverse(self.base_scales[0] as i64, self.base_scales[1] as i64),
+            ],
+            offset_for_current_pixel: 0,
+        }
+    }
+
+    /// the sequence runs across the whole image, the tiles can't be seeded apart
+    pub fn with_seed(&mut self, _seed: u64) -> &mut Self {
+        self
+    }
+
+    pub fn request_1d_array(&mut self, n: usize) -> &mut Self {
+        self.sample_1d_array_sizes.push(n);
+        self
+    }
+
+    pub fn request_2d_array(&mut self, n: usize) -> &mut Self {
+        self.sample_2d_array_sizes.push(n);
+        self
+    }
+
+    pub fn samples_per_pixel(&self) -> usize {
+        self.samples_per_pixel
+    }
+
+    /// the same sampler taking `samples_per_pixel` samples, without the requested arrays
+    pub fn with_samples_per_pixel(&self, samples_per_pixel: usize) -> Self {
+        Self::new(
+            &self.log,
+            samples_per_pixel,
+            &self.sample_bounds,
+            self.scrambling,
+        )
+    }
+}
+
+impl SamplerInterface for HaltonSampler {
+    fn start_pixel(&mut self, p: &na::Point2<i32>) {
+        self.sampler.start_pixel(p);
+        self.offset_for_current_pixel = self.offset_for_pixel(p);
+        self.dimension = Cell::new(0);
+        self.interval_sample_index = self.get_index_for_sample(0);
+        self.array_end_dim = ARRAY_START_DIM
+            + self.sampler.sample_array_1d.len()
+            + 2 * self.sampler.sample_array_2d.len();
+
+        for i in 0..self.sampler.sample_1d_array_sizes.len() {
+            let n_samples = self.sampler.sample_1d_array_sizes[i] * self.sampler.samples_per_pixel;
+            for j in 0..n_samples {
+                let index = self.get_index_for_sample(j as u64);
+                self.sampler.sample_array_1d[i][j] =
+                    self.sample_dimension(index, ARRAY_START_DIM + i);
+            }
+        }
+
+        let mut dim = ARRAY_START_DIM + self.sampler.sample_1d_array_sizes.len();
+        for i in 0..self.sampler.sample_2d_array_sizes.len() {
+            let n_samples = self.sampler.sample_2d_array_sizes[i] * self.sampler.samples_per_pixel;
+            for j in 0..n_samples {
+                let index = self.get_index_for_sample(j as u64);
+                self.sampler.sample_array_2d[i][j].x = self.sample_dimension(index, dim);
+                self.sampler.sample_array_2d[i][j].y = self.sample_dimension(index, dim + 1);
+            }
+            dim += 2;
+        }
+
+        debug_assert_eq!(self.array_end_dim, dim);
+    }
+
+    fn get_camera_sample(&mut self, p_raster: &na::Point2<i32>) -> CameraSample {
+        CameraSample {
+            p_film: na::Point2::new(p_raster.x as f32, p_raster.y as f32) + self.get_2d().coords,
+            time: self.get_1d(),
+            p_lens: self.get_2d(),
+        }
+    }
+
+    fn start_next_sample(&mut self) -> bool {
+        self.dimension.set(0);
+        self.interval_sample_index =
+            self.get_index_for_sample((self.sampler.current_pixel_sample_index + 1) as u64);
+        self.sampler.start_next_sample()
+    }
+
+    fn set_sample_number(&mut self, sample_num: usize) -> bool {
+        self.dimension.set(0);
+        self.interval_sample_index = self.get_index_for_sample(sample_num as u64);
+        self.sampler.set_sample_number(sample_num)
+    }
+
+    fn get_1d(&self) -> f32 {
+        if self.dimension.get() >= ARRAY_START_DIM && self.dimension.get() < self.array_end_dim {
+            self.dimension.set(self.array_end_dim);
+        }
+
+        let sample = self.sample_dimension(self.interval_sample_index, self.dimension.get());
+        self.dimension.set(self.dimension.get() + 1);
+        sample
+    }
+
+    fn get_2d(&self) -> na::Point2<f32> {
+        if self.dimension.get() + 1 >= ARRAY_START_DIM && self.dimension.get() < self.array_end_dim
+        {
+            self.dimension.set(self.array_end_dim);
+        }
+
+        let sample = na::Point2::new(
+            self.sample_dimension(self.interval_sample_index, self.dimension.get()),
+            self.sample_dimension(self.interval_sample_index, self.dimension.get() + 1),
+        );
+        self.dimension.set(self.dimension.get() + 2);
+        sample
+    }
+
+    fn samples_per_pixel(&self) -> usize {
+        self.sampler.samples_per_pixel
+    }
+
+    fn get_1d_array(&self, n: usize) -> Option<&[f32]> {
+        self.sampler.get_1d_array(n)
+    }
+
+    fn get_2d_array(&self, n: usize) -> Option<&[na::Point2<f32>]> {
+        self.sampler.get_2d_array(n)
+    }
+
+    fn get_current_sample_number(&self) -> usize {
+        self.sampler.current_pixel_sample_index
+    }
+
+    fn get_dimension(&self) -> usize {
+        // the dimensions of the arrays are skipped over, they aren't counted
+        let dimension = self.dimension.get();
+        if dimension >= self.array_end_dim {
+            dimension - (self.array_end_dim - ARRAY_START_DIM)
+        } else {
+            dimension.min(ARRAY_START_DIM)
+        }
+    }
+
+    fn set_dimension(&self, dimension: usize) {
+        self.dimension.set(if dimension >= ARRAY_START_DIM {
+            dimension + self.array_end_dim - ARRAY_START_DIM
+        } else {
+            dimension
+        });
+    }
+
+    /// the samples only depend on the pixel, there's no stream to restart
+    fn set_seed(&mut self, _seed: u64) {}
+}
+
+impl HaltonSampler {
+    /// index of the first sample of the sequence landing in `p`, by the chinese remainder
+    /// theorem over the digits the first two dimensions place it with
+    fn offset_for_pixel(&self, p: &na::Point2<i32>) -> u64 {
+        if self.sample_stride == 1 {
+            return 0;
+        }
+
+        let mut offset = 0;
+        for i in 0..2 {
+            let pm = abs_mod(p[i], MAX_RESOLUTION) as u64;
+            let dim_offset = inverse_radical_inverse(PRIMES[i], pm, self.base_exponents[i]);
+            offset +=
+                dim_offset * (self.sample_stride / self.base_scales[i]) * self.mult_inverse[i];
+        }
+        offset % self.sample_stride
+    }
+
+    pub fn get_index_for_sample(&self, sample_num: u64) -> u64 {
+        self.offset_for_current_pixel + sample_num * self.sample_stride
+    }
+
+    pub fn sample_dimension(&self, index: u64, dimension: usize) -> f32 {
+        if dimension >= PRIME_TABLE_SIZE {
+            panic!(
+                "halton sampler can only sample up to {:?} dimensions.",
+                PRIME_TABLE_SIZE
+            );
+        }
+
+        // the digits placing the sample in its pixel are shifted out, leaving where it lands in
+        // the pixel. scrambling these would move samples to other pixels
+        match dimension {
+            0 => radical_inverse(0, index >> self.base_exponents[0]),
+            1 => radical_inverse(1, index / self.base_scales[1]),
+            _ => match self.scrambling {
+                HaltonScrambling::None => radical_inverse(dimension, index),
+                HaltonScrambling::Permutations => {
+                    scrambled_radical_inverse(dimension, index, &self.permutations[dimension])
+                }
+                HaltonScrambling::Owen => {
+                    owen_scrambled_radical_inverse(dimension, index, mix_bits(dimension as u64))
+                }
+            },
+        }
+    }
+}
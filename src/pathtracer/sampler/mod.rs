@@ -1,9 +1,243 @@
+pub mod bluenoise;
+pub mod cmj;
+pub mod halton;
+pub mod plot;
+pub mod pmj02;
 pub mod sobol;
 pub mod stratified;
 
 use super::{sampling::Random, CameraSample};
+use crate::common::bounds::Bounds2i;
+use ambassador::{delegatable_trait, Delegate};
+use bluenoise::{BlueNoiseSampler, BlueNoiseSamplerBuilder};
+use cmj::{CMJSampler, CMJSamplerBuilder};
+use halton::{HaltonSampler, HaltonSamplerBuilder, HaltonScrambling};
+use pmj02::{PMJ02Sampler, PMJ02SamplerBuilder};
 use rand::Rng;
+use sobol::{SobolSampler, SobolSamplerBuilder};
 use std::cell::{Cell, RefCell};
+use stratified::{StratifiedSampler, StratifiedSamplerBuilder};
+
+#[delegatable_trait]
+pub trait SamplerInterface {
+    fn start_pixel(&mut self, p: &na::Point2<i32>);
+    fn get_camera_sample(&mut self, p_raster: &na::Point2<i32>) -> CameraSample;
+    fn start_next_sample(&mut self) -> bool;
+    /// jumps to sample `sample_num` of the current pixel, its dimensions drawn from the start
+    fn set_sample_number(&mut self, sample_num: usize) -> bool;
+    fn get_1d(&self) -> f32;
+    fn get_2d(&self) -> na::Point2<f32>;
+    fn samples_per_pixel(&self) -> usize;
+    /// the next of the arrays requested from the builder, `None` once they're used up
+    fn get_1d_array(&self, n: usize) -> Option<&[f32]>;
+    fn get_2d_array(&self, n: usize) -> Option<&[na::Point2<f32>]>;
+    fn get_current_sample_number(&self) -> usize;
+    /// the dimension the next sample comes from, a 1d sample takes one and a 2d sample two
+    fn get_dimension(&self) -> usize;
+    /// continues drawing from `dimension`, so a consumer can start its samples at the same
+    /// dimension however many were drawn before it
+    fn set_dimension(&self, dimension: usize);
+    /// restarts the random stream at `seed`, a sampler reused for another tile then draws what a
+    /// fresh one seeded the same would
+    fn set_seed(&mut self, seed: u64);
+}
+
+#[derive(Delegate)]
+#[delegate(SamplerInterface)]
+pub enum Sampler {
+    Sobol(SobolSampler),
+    Stratified(StratifiedSampler),
+    Halton(HaltonSampler),
+    PMJ02(PMJ02Sampler),
+    CMJ(CMJSampler),
+    BlueNoise(BlueNoiseSampler),
+}
+
+#[derive(Clone)]
+pub enum SamplerBuilder {
+    Sobol(SobolSamplerBuilder),
+    Stratified(StratifiedSamplerBuilder),
+    Halton(HaltonSamplerBuilder),
+    PMJ02(PMJ02SamplerBuilder),
+    CMJ(CMJSamplerBuilder),
+    BlueNoise(BlueNoiseSamplerBuilder),
+}
+
+impl SamplerBuilder {
+    /// the default, low discrepancy sobol sampler
+    pub fn new(log: &slog::Logger, samples_per_pixel: usize, sample_bounds: &Bounds2i) -> Self {
+        SamplerBuilder::Sobol(SobolSamplerBuilder::new(
+            log,
+            samples_per_pixel,
+            sample_bounds,
+        ))
+    }
+
+    /// stratified sampler of `x_pixel_samples` by `y_pixel_samples` strata per pixel
+    pub fn stratified(
+        log: &slog::Logger,
+        x_pixel_samples: usize,
+        y_pixel_samples: usize,
+        jitter: bool,
+    ) -> Self {
+        // enough stratified dimensions for the camera and the first bounce or so of a path, the
+        // rest are uniform random
+        let mut builder = StratifiedSamplerBuilder::new(log, x_pixel_samples, y_pixel_samples, 16);
+        builder.with_jitter(jitter);
+        SamplerBuilder::Stratified(builder)
+    }
+
+    /// halton sampler running across the whole image, its higher dimensions scrambled by
+    /// `scrambling`
+    pub fn halton(
+        log: &slog::Logger,
+        samples_per_pixel: usize,
+        sample_bounds: &Bounds2i,
+        scrambling: HaltonScrambling,
+    ) -> Self {
+        SamplerBuilder::Halton(HaltonSamplerBuilder::new(
+            log,
+            samples_per_pixel,
+            sample_bounds,
+            scrambling,
+        ))
+    }
+
+    /// progressive multi-jittered (0,2) sampler, well stratified at the low sample counts of
+    /// previews
+    pub fn pmj02(log: &slog::Logger, samples_per_pixel: usize) -> Self {
+        SamplerBuilder::PMJ02(PMJ02SamplerBuilder::new(log, samples_per_pixel))
+    }
+
+    /// correlated multi-jittered sampler, computing its patterns on the fly
+    pub fn cmj(log: &slog::Logger, samples_per_pixel: usize) -> Self {
+        SamplerBuilder::CMJ(CMJSamplerBuilder::new(log, samples_per_pixel))
+    }
+
+    /// `sampler` with the samples of each pixel dithered by a blue noise mask
+    pub fn blue_noise(sampler: SamplerBuilder) -> Self {
+        SamplerBuilder::BlueNoise(BlueNoiseSamplerBuilder::new(sampler))
+    }
+
+    pub fn build(&self) -> Sampler {
+        match self {
+            SamplerBuilder::Sobol(builder) => Sampler::Sobol(builder.build()),
+            SamplerBuilder::Stratified(builder) => Sampler::Stratified(builder.build()),
+            SamplerBuilder::Halton(builder) => Sampler::Halton(builder.build()),
+            SamplerBuilder::PMJ02(builder) => Sampler::PMJ02(builder.build()),
+            SamplerBuilder::CMJ(builder) => Sampler::CMJ(builder.build()),
+            SamplerBuilder::BlueNoise(builder) => Sampler::BlueNoise(builder.build()),
+        }
+    }
+
+    pub fn with_seed(&mut self, seed: u64) -> &mut Self {
+        match self {
+            SamplerBuilder::Sobol(builder) => {
+                builder.with_seed(seed);
+            }
+            SamplerBuilder::Stratified(builder) => {
+                builder.with_seed(seed);
+            }
+            SamplerBuilder::Halton(builder) => {
+                builder.with_seed(seed);
+            }
+            SamplerBuilder::PMJ02(builder) => {
+                builder.with_seed(seed);
+            }
+            SamplerBuilder::CMJ(builder) => {
+                builder.with_seed(seed);
+            }
+            SamplerBuilder::BlueNoise(builder) => {
+                builder.with_seed(seed);
+            }
+        }
+        self
+    }
+
+    /// asks for an array of `n` samples per pixel sample, handed out in the order requested
+    pub fn request_1d_array(&mut self, n: usize) -> &mut Self {
+        match self {
+            SamplerBuilder::Sobol(builder) => {
+                builder.request_1d_array(n);
+            }
+            SamplerBuilder::Stratified(builder) => {
+                builder.request_1d_array(n);
+            }
+            SamplerBuilder::Halton(builder) => {
+                builder.request_1d_array(n);
+            }
+            SamplerBuilder::PMJ02(builder) => {
+                builder.request_1d_array(n);
+            }
+            SamplerBuilder::CMJ(builder) => {
+                builder.request_1d_array(n);
+            }
+            SamplerBuilder::BlueNoise(builder) => {
+                builder.request_1d_array(n);
+            }
+        }
+        self
+    }
+
+    pub fn request_2d_array(&mut self, n: usize) -> &mut Self {
+        match self {
+            SamplerBuilder::Sobol(builder) => {
+                builder.request_2d_array(n);
+            }
+            SamplerBuilder::Stratified(builder) => {
+                builder.request_2d_array(n);
+            }
+            SamplerBuilder::Halton(builder) => {
+                builder.request_2d_array(n);
+            }
+            SamplerBuilder::PMJ02(builder) => {
+                builder.request_2d_array(n);
+            }
+            SamplerBuilder::CMJ(builder) => {
+                builder.request_2d_array(n);
+            }
+            SamplerBuilder::BlueNoise(builder) => {
+                builder.request_2d_array(n);
+            }
+        }
+        self
+    }
+
+    pub fn samples_per_pixel(&self) -> usize {
+        match self {
+            SamplerBuilder::Sobol(builder) => builder.samples_per_pixel(),
+            SamplerBuilder::Stratified(builder) => builder.samples_per_pixel(),
+            SamplerBuilder::Halton(builder) => builder.samples_per_pixel(),
+            SamplerBuilder::PMJ02(builder) => builder.samples_per_pixel(),
+            SamplerBuilder::CMJ(builder) => builder.samples_per_pixel(),
+            SamplerBuilder::BlueNoise(builder) => builder.samples_per_pixel(),
+        }
+    }
+
+    /// the same kind of sampler taking `samples_per_pixel` samples instead
+    pub fn with_samples_per_pixel(&self, samples_per_pixel: usize) -> Self {
+        match self {
+            SamplerBuilder::Sobol(builder) => {
+                SamplerBuilder::Sobol(builder.with_samples_per_pixel(samples_per_pixel))
+            }
+            SamplerBuilder::Stratified(builder) => {
+                SamplerBuilder::Stratified(builder.with_samples_per_pixel(samples_per_pixel))
+            }
+            SamplerBuilder::Halton(builder) => {
+                SamplerBuilder::Halton(builder.with_samples_per_pixel(samples_per_pixel))
+            }
+            SamplerBuilder::PMJ02(builder) => {
+                SamplerBuilder::PMJ02(builder.with_samples_per_pixel(samples_per_pixel))
+            }
+            SamplerBuilder::CMJ(builder) => {
+                SamplerBuilder::CMJ(builder.with_samples_per_pixel(samples_per_pixel))
+            }
+            SamplerBuilder::BlueNoise(builder) => {
+                SamplerBuilder::BlueNoise(builder.with_samples_per_pixel(samples_per_pixel))
+            }
+        }
+    }
+}
 
 #[derive(Clone)]
 struct CoreSampler {
@@ -54,6 +288,11 @@ impl CoreSampler {
         if self.array_1d_offset.get() == self.sample_array_1d.len() {
             None
         } else {
+            assert_eq!(
+                n,
+                self.sample_1d_array_sizes[self.array_1d_offset.get()],
+                "1d sample arrays are taken in the order and sizes they were requested"
+            );
             let ret = &self.sample_array_1d[self.array_1d_offset.get()]
                 [self.current_pixel_sample_index * n..];
             self.array_1d_offset.set(self.array_1d_offset.get() + 1);
@@ -66,6 +305,11 @@ impl CoreSampler {
         if self.array_2d_offset.get() == self.sample_array_2d.len() {
             None
         } else {
+            assert_eq!(
+                n,
+                self.sample_2d_array_sizes[self.array_2d_offset.get()],
+                "2d sample arrays are taken in the order and sizes they were requested"
+            );
             let ret = &self.sample_array_2d[self.array_2d_offset.get()]
                 [self.current_pixel_sample_index * n..];
             self.array_2d_offset.set(self.array_2d_offset.get() + 1);
@@ -95,8 +339,8 @@ struct PixelSampler {
     sampler: CoreSampler,
     samples_1d: Vec<Vec<f32>>,
     samples_2d: Vec<Vec<na::Point2<f32>>>,
-    current_1d_dimension: Cell<usize>,
-    current_2d_dimension: Cell<usize>,
+    /// 1d and 2d samples are looked up by the dimension they're drawn at, a 2d sample taking two
+    dimension: Cell<usize>,
 
     rng: RefCell<Random>,
 }
@@ -116,43 +360,36 @@ impl PixelSampler {
             sampler,
             samples_1d,
             samples_2d,
-            current_1d_dimension: Cell::new(0),
-            current_2d_dimension: Cell::new(0),
+            dimension: Cell::new(0),
             rng: RefCell::new(rng),
         }
     }
 
     fn start_next_sample(&mut self) -> bool {
-        self.current_1d_dimension.set(0);
-        self.current_2d_dimension.set(0);
+        self.dimension.set(0);
         self.sampler.start_next_sample()
     }
 
     fn set_sample_number(&mut self, sample_num: usize) -> bool {
-        self.current_1d_dimension.set(0);
-        self.current_2d_dimension.set(0);
+        self.dimension.set(0);
         self.sampler.set_sample_number(sample_num)
     }
 
     fn get_1d(&self) -> f32 {
-        if self.current_1d_dimension.get() < self.samples_1d.len() {
-            let ret = self.samples_1d[self.current_1d_dimension.get()]
-                [self.sampler.current_pixel_sample_index];
-            self.current_1d_dimension
-                .set(self.current_1d_dimension.get() + 1);
-            ret
+        let dimension = self.dimension.get();
+        self.dimension.set(dimension + 1);
+        if dimension < self.samples_1d.len() {
+            self.samples_1d[dimension][self.sampler.current_pixel_sample_index]
         } else {
             self.rng.borrow_mut().gen_range(0.0, 1.0)
         }
     }
 
     fn get_2d(&self) -> na::Point2<f32> {
-        if self.current_2d_dimension.get() < self.samples_2d.len() {
-            let ret = self.samples_2d[self.current_2d_dimension.get()]
-                [self.sampler.current_pixel_sample_index];
-            self.current_2d_dimension
-                .set(self.current_2d_dimension.get() + 1);
-            ret
+        let dimension = self.dimension.get();
+        self.dimension.set(dimension + 2);
+        if dimension < self.samples_2d.len() {
+            self.samples_2d[dimension][self.sampler.current_pixel_sample_index]
         } else {
             let mut rng = self.rng.borrow_mut();
             na::Point2::new(rng.gen_range(0.0, 1.0), rng.gen_range(0.0, 1.0))
@@ -162,9 +399,8 @@ impl PixelSampler {
     fn get_camera_sample(&mut self, p_raster: &na::Point2<i32>) -> CameraSample {
         CameraSample {
             p_film: na::Point2::new(p_raster.x as f32, p_raster.y as f32) + self.get_2d().coords,
+            time: self.get_1d(),
+            p_lens: self.get_2d(),
         }
     }
 }
-
-pub type Sampler = sobol::SobolSampler; // for now, since dealing with sampler inheritance is annoying
-pub type SamplerBuilder = sobol::SobolSamplerBuilder;
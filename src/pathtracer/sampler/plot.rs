@@ -0,0 +1,95 @@
+use super::{stratified::square_strata, Sampler, SamplerInterface};
+use std::fmt::Write;
+
+/// edge length in svg units of the plotted unit square
+const PLOT_SIZE: f32 = 512.0;
+const PLOT_MARGIN: f32 = 16.0;
+
+/// the first `n_samples` samples `sampler` takes in `pixel` of the dimensions `dimension` and
+/// `dimension + 1`, drawn as they would be by the bounce starting there
+pub fn pixel_samples(
+    sampler: &mut Sampler,
+    pixel: &na::Point2<i32>,
+    dimension: usize,
+    n_samples: usize,
+) -> Vec<na::Point2<f32>> {
+    sampler.start_pixel(pixel);
+    (0..n_samples.min(sampler.samples_per_pixel()))
+        .map(|sample_num| {
+            sampler.set_sample_number(sample_num);
+            sampler.set_dimension(dimension);
+            sampler.get_2d()
+        })
+        .collect()
+}
+
+/// scatter plot of `samples` over the unit square as an svg, on the squarest grid of as many
+/// strata as there are samples so gaps and clumps in the stratification stand out
+pub fn scatter_svg(samples: &[na::Point2<f32>]) -> String {
+    let size = PLOT_SIZE + 2.0 * PLOT_MARGIN;
+    let (x_strata, y_strata) = square_strata(samples.len());
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{0}" viewBox="0 0 {0} {0}">"#,
+        size
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        r#"<rect width="{0}" height="{0}" fill="white"/>"#,
+        size
+    )
+    .unwrap();
+
+    let to_plot = |v: f32| PLOT_MARGIN + v * PLOT_SIZE;
+    for i in 1..x_strata {
+        let x = to_plot(i as f32 / x_strata as f32);
+        writeln!(
+            svg,
+            r#"<line x1="{0}" y1="{1}" x2="{0}" y2="{2}" stroke="lightgray"/>"#,
+            x,
+            to_plot(0.0),
+            to_plot(1.0)
+        )
+        .unwrap();
+    }
+    for i in 1..y_strata {
+        let y = to_plot(i as f32 / y_strata as f32);
+        writeln!(
+            svg,
+            r#"<line x1="{1}" y1="{0}" x2="{2}" y2="{0}" stroke="lightgray"/>"#,
+            y,
+            to_plot(0.0),
+            to_plot(1.0)
+        )
+        .unwrap();
+    }
+    writeln!(
+        svg,
+        r#"<rect x="{0}" y="{0}" width="{1}" height="{1}" fill="none" stroke="black"/>"#,
+        PLOT_MARGIN, PLOT_SIZE
+    )
+    .unwrap();
+
+    // later samples are drawn lighter, showing how the prefixes of progressive sequences fill in
+    let radius = (PLOT_SIZE / (samples.len() as f32).sqrt() / 8.0)
+        .max(1.0)
+        .min(4.0);
+    for (i, p) in samples.iter().enumerate() {
+        let shade = (192.0 * i as f32 / samples.len() as f32) as u8;
+        writeln!(
+            svg,
+            r#"<circle cx="{}" cy="{}" r="{}" fill="rgb({},{},255)"/>"#,
+            to_plot(p.x),
+            to_plot(p.y),
+            radius,
+            shade,
+            shade
+        )
+        .unwrap();
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
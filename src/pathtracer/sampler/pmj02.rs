@@ -0,0 +1,236 @@
+use std::{cell::Cell, sync::Arc};
+
+use crate::pathtracer::CameraSample;
+
+use super::{CoreSampler, SamplerInterface};
+use crate::common::math::ONE_MINUS_EPSILON;
+use crate::pathtracer::lowdiscrepancy::{mix_bits, permutation_element, pmj02_points};
+/// distinct pmj02 tables, the dimension pairs past them are hashed onto them
+const PMJ02_SETS: usize = 8;
+/// dimensions the requested arrays are hashed by, apart from the ones drawn per sample
+const ARRAY_1D_DIMENSION: u64 = 1 << 32;
+const ARRAY_2D_DIMENSION: u64 = 1 << 33;
+
+pub struct PMJ02Sampler {
+    sampler: CoreSampler,
+    dimension: Cell<usize>,
+    pixel_hash: u64,
+    tables: Arc<Vec<Vec<na::Point2<f32>>>>,
+}
+
+#[derive(Clone)]
+pub struct PMJ02SamplerBuilder {
+    samples_per_pixel: usize,
+    sample_1d_array_sizes: Vec<usize>,
+    sample_2d_array_sizes: Vec<usize>,
+    tables: Arc<Vec<Vec<na::Point2<f32>>>>,
+    log: slog::Logger,
+}
+
+impl PMJ02SamplerBuilder {
+    pub fn new(log: &slog::Logger, samples_per_pixel: usize) -> Self {
+        let log = log.new(o!("module" => "sampler"));
+        Self {
+            samples_per_pixel,
+            sample_1d_array_sizes: vec![],
+            sample_2d_array_sizes: vec![],
+            tables: Self::generate_tables(&log, samples_per_pixel),
+            log,
+        }
+    }
+
+    /// tables of at least `n` points, the same for every render
+    fn generate_tables(log: &slog::Logger, n: usize) -> Arc<Vec<Vec<na::Point2<f32>>>> {
+        let n = n.max(1).next_power_of_two();
+        debug!(
+            log,
+            "generating {:?} pmj02 tables of {:?} points", PMJ02_SETS, n
+        );
+        Arc::new(
+            (0..PMJ02_SETS)
+                .map(|set| pmj02_points(n, mix_bits(set as u64)))
+                .collect(),
+        )
+    }
+
+    pub fn build(&self) -> PMJ02Sampler {
+        PMJ02Sampler {
+            sampler: CoreSampler::new(
+                self.samples_per_pixel,
+                self.sample_1d_array_sizes.clone(),
+                self.sample_2d_array_sizes.clone(),
+                self.sample_1d_array_sizes
+                    .iter()
+                    .map(|n| vec![0.0; n * self.samples_per_pixel])
+                    .collect(),
+                self.sample_2d_array_sizes
+                    .iter()
+                    .map(|n| vec![na::Point2::new(0.0, 0.0); n * self.samples_per_pixel])
+                    .collect(),
+            ),
+            dimension: Cell::new(0),
+            pixel_hash: 0,
+            tables: Arc::clone(&self.tables),
+        }
+    }
+
+    /// the pixels are decorrelated by hashing, the tiles don't need seeding apart
+    pub fn with_seed(&mut self, _seed: u64) -> &mut Self {
+        self
+    }
+
+    pub fn request_1d_array(&mut self, n: usize) -> &mut Self {
+        self.sample_1d_array_sizes.push(n);
+        self
+    }
+
+    /// the tables grow to hold the `n` points of every pixel sample, so the whole array of a pixel
+    /// is stratified together
+    pub fn request_2d_array(&mut self, n: usize) -> &mut Self {
+        self.sample_2d_array_sizes.push(n);
+        if n * self.samples_per_pixel > self.tables[0].len() {
+            self.tables = Self::generate_tables(&self.log, n * self.samples_per_pixel);
+        }
+        self
+    }
+
+    pub fn samples_per_pixel(&self) -> usize {
+        self.samples_per_pixel
+    }
+
+    /// the same sampler taking `samples_per_pixel` samples, without the requested arrays
+    pub fn with_samples_per_pixel(&self, samples_per_pixel: usize) -> Self {
+        Self::new(&self.log, samples_per_pixel)
+    }
+}
+
+impl SamplerInterface for PMJ02Sampler {
+    fn start_pixel(&mut self, p: &na::Point2<i32>) {
+        self.sampler.start_pixel(p);
+        self.dimension.set(0);
+        self.pixel_hash = mix_bits((p.x as u32 as u64) << 32 | p.y as u32 as u64);
+
+        for i in 0..self.sampler.sample_1d_array_sizes.len() {
+            let n_samples = self.sampler.sample_1d_array_sizes[i] * self.sampler.samples_per_pixel;
+            let hash = self.hash(ARRAY_1D_DIMENSION + i as u64);
+            for j in 0..n_samples {
+                self.sampler.sample_array_1d[i][j] = Self::sample_1d(hash, j, n_samples);
+            }
+        }
+
+        for i in 0..self.sampler.sample_2d_array_sizes.len() {
+            let n_samples = self.sampler.sample_2d_array_sizes[i] * self.sampler.samples_per_pixel;
+            let hash = self.hash(ARRAY_2D_DIMENSION + i as u64);
+            for j in 0..n_samples {
+                self.sampler.sample_array_2d[i][j] =
+                    self.sample_2d(hash, hash as usize % PMJ02_SETS, j, n_samples);
+            }
+        }
+    }
+
+    fn get_camera_sample(&mut self, p_raster: &na::Point2<i32>) -> CameraSample {
+        CameraSample {
+            p_film: na::Point2::new(p_raster.x as f32, p_raster.y as f32) + self.get_2d().coords,
+            time: self.get_1d(),
+            p_lens: self.get_2d(),
+        }
+    }
+
+    fn start_next_sample(&mut self) -> bool {
+        self.dimension.set(0);
+        self.sampler.start_next_sample()
+    }
+
+    fn set_sample_number(&mut self, sample_num: usize) -> bool {
+        self.dimension.set(0);
+        self.sampler.set_sample_number(sample_num)
+    }
+
+    fn get_1d(&self) -> f32 {
+        let hash = self.hash(self.dimension.get() as u64);
+        self.dimension.set(self.dimension.get() + 1);
+        Self::sample_1d(
+            hash,
+            self.sampler.current_pixel_sample_index,
+            self.sampler.samples_per_pixel,
+        )
+    }
+
+    fn get_2d(&self) -> na::Point2<f32> {
+        let dimension = self.dimension.get();
+        let hash = self.hash(dimension as u64);
+        self.dimension.set(dimension + 2);
+        let set = if dimension / 2 < PMJ02_SETS {
+            dimension / 2
+        } else {
+            hash as usize % PMJ02_SETS
+        };
+        self.sample_2d(
+            hash,
+            set,
+            self.sampler.current_pixel_sample_index,
+            self.sampler.samples_per_pixel,
+        )
+    }
+
+    fn samples_per_pixel(&self) -> usize {
+        self.sampler.samples_per_pixel
+    }
+
+    fn get_1d_array(&self, n: usize) -> Option<&[f32]> {
+        self.sampler.get_1d_array(n)
+    }
+
+    fn get_2d_array(&self, n: usize) -> Option<&[na::Point2<f32>]> {
+        self.sampler.get_2d_array(n)
+    }
+
+    fn get_current_sample_number(&self) -> usize {
+        self.sampler.current_pixel_sample_index
+    }
+
+    fn get_dimension(&self) -> usize {
+        self.dimension.get()
+    }
+
+    fn set_dimension(&self, dimension: usize) {
+        self.dimension.set(dimension);
+    }
+
+    /// the samples only depend on the pixel, there's no stream to restart
+    fn set_seed(&mut self, _seed: u64) {}
+}
+
+impl PMJ02Sampler {
+    fn hash(&self, dimension: u64) -> u64 {
+        mix_bits(self.pixel_hash ^ mix_bits(dimension))
+    }
+
+    /// sample `index` of `count` jittered strata, visited in an order picked by `hash`
+    fn sample_1d(hash: u64, index: usize, count: usize) -> f32 {
+        let stratum = permutation_element(index as u32, count as u32, hash as u32);
+        let jitter = unit_float(mix_bits(hash ^ index as u64));
+        ONE_MINUS_EPSILON.min((stratum as f32 + jitter) / count as f32)
+    }
+
+    /// point `index` of the first `count` points of table `set`, visited in an order picked by
+    /// `hash` and shifted around the unit square by it so the pixels sharing the table differ
+    fn sample_2d(&self, hash: u64, set: usize, index: usize, count: usize) -> na::Point2<f32> {
+        let index = permutation_element(index as u32, count as u32, hash as u32);
+        let p = self.tables[set][index as usize];
+        let shift = mix_bits(hash);
+        let wrap = |v: f32| {
+            let v = if v >= 1.0 { v - 1.0 } else { v };
+            ONE_MINUS_EPSILON.min(v)
+        };
+        na::Point2::new(
+            wrap(p.x + unit_float(shift)),
+            wrap(p.y + unit_float(shift >> 32)),
+        )
+    }
+}
+
+/// the low 24 bits of `bits` as a float in [0, 1)
+fn unit_float(bits: u64) -> f32 {
+    (bits & 0xffffff) as f32 / (1 << 24) as f32
+}
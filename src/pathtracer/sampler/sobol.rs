@@ -2,7 +2,7 @@ use std::cell::Cell;
 
 use crate::{common::bounds::Bounds2i, pathtracer::CameraSample};
 
-use super::CoreSampler;
+use super::{CoreSampler, SamplerInterface};
 use crate::common::math::{
     cantor_pairing, log2_int, RoundUpPow2, HALF_MAX_I_32, ONE_MINUS_EPSILON,
 };
@@ -28,6 +28,8 @@ pub struct SobolSamplerBuilder {
     sample_bounds: Bounds2i,
     resolution: i32,
     log_2_resolution: u32,
+    sample_1d_array_sizes: Vec<usize>,
+    sample_2d_array_sizes: Vec<usize>,
     log: slog::Logger,
 }
 
@@ -55,13 +57,27 @@ impl SobolSamplerBuilder {
             sample_bounds: *sample_bounds,
             resolution,
             log_2_resolution,
+            sample_1d_array_sizes: vec![],
+            sample_2d_array_sizes: vec![],
             log,
         }
     }
 
     pub fn build(&self) -> SobolSampler {
         SobolSampler {
-            sampler: CoreSampler::new(self.samples_per_pixel, vec![], vec![], vec![], vec![]),
+            sampler: CoreSampler::new(
+                self.samples_per_pixel,
+                self.sample_1d_array_sizes.clone(),
+                self.sample_2d_array_sizes.clone(),
+                self.sample_1d_array_sizes
+                    .iter()
+                    .map(|n| vec![0.0; n * self.samples_per_pixel])
+                    .collect(),
+                self.sample_2d_array_sizes
+                    .iter()
+                    .map(|n| vec![na::Point2::new(0.0, 0.0); n * self.samples_per_pixel])
+                    .collect(),
+            ),
             dimension: Cell::new(0),
             interval_sample_index: 0,
             array_end_dim: 0,
@@ -75,10 +91,29 @@ impl SobolSamplerBuilder {
     pub fn with_seed(&mut self, _seed: u64) -> &mut Self {
         self
     }
+
+    pub fn request_1d_array(&mut self, n: usize) -> &mut Self {
+        self.sample_1d_array_sizes.push(n);
+        self
+    }
+
+    pub fn request_2d_array(&mut self, n: usize) -> &mut Self {
+        self.sample_2d_array_sizes.push(n);
+        self
+    }
+
+    pub fn samples_per_pixel(&self) -> usize {
+        self.samples_per_pixel
+    }
+
+    /// the same sampler taking `samples_per_pixel` samples, without the requested arrays
+    pub fn with_samples_per_pixel(&self, samples_per_pixel: usize) -> Self {
+        Self::new(&self.log, samples_per_pixel, &self.sample_bounds)
+    }
 }
 
-impl SobolSampler {
-    pub fn start_pixel(&mut self, p: &na::Point2<i32>) {
+impl SamplerInterface for SobolSampler {
+    fn start_pixel(&mut self, p: &na::Point2<i32>) {
         self.sampler.start_pixel(p);
         self.current_scramble_index = cantor_pairing(
             (self.sampler.current_pixel.x + HALF_MAX_I_32) as usize,
@@ -95,7 +130,7 @@ impl SobolSampler {
             for j in 0..n_samples {
                 let index = self.get_index_for_sample(j as u64);
                 self.sampler.sample_array_1d[i][j] =
-                    self.sample_dimension(index, ARRAY_START_DIM + 1);
+                    self.sample_dimension(index, ARRAY_START_DIM + i);
             }
         }
 
@@ -105,7 +140,7 @@ impl SobolSampler {
             for j in 0..n_samples {
                 let index = self.get_index_for_sample(j as u64);
                 self.sampler.sample_array_2d[i][j].x = self.sample_dimension(index, dim);
-                self.sampler.sample_array_2d[i][j].x = self.sample_dimension(index, dim + 1);
+                self.sampler.sample_array_2d[i][j].y = self.sample_dimension(index, dim + 1);
             }
             dim += 2;
         }
@@ -113,20 +148,28 @@ impl SobolSampler {
         debug_assert_eq!(self.array_end_dim, dim);
     }
 
-    pub fn get_camera_sample(&mut self, p_raster: &na::Point2<i32>) -> CameraSample {
+    fn get_camera_sample(&mut self, p_raster: &na::Point2<i32>) -> CameraSample {
         CameraSample {
             p_film: na::Point2::new(p_raster.x as f32, p_raster.y as f32) + self.get_2d().coords,
+            time: self.get_1d(),
+            p_lens: self.get_2d(),
         }
     }
 
-    pub fn start_next_sample(&mut self) -> bool {
+    fn start_next_sample(&mut self) -> bool {
         self.dimension.set(0);
         self.interval_sample_index =
             self.get_index_for_sample((self.sampler.current_pixel_sample_index + 1) as u64);
         self.sampler.start_next_sample()
     }
 
-    pub fn get_1d(&self) -> f32 {
+    fn set_sample_number(&mut self, sample_num: usize) -> bool {
+        self.dimension.set(0);
+        self.interval_sample_index = self.get_index_for_sample(sample_num as u64);
+        self.sampler.set_sample_number(sample_num)
+    }
+
+    fn get_1d(&self) -> f32 {
         if self.dimension.get() >= ARRAY_START_DIM && self.dimension.get() < self.array_end_dim {
             self.dimension.set(self.array_end_dim);
         }
@@ -136,7 +179,7 @@ impl SobolSampler {
         sample
     }
 
-    pub fn get_2d(&self) -> na::Point2<f32> {
+    fn get_2d(&self) -> na::Point2<f32> {
         if self.dimension.get() + 1 >= ARRAY_START_DIM && self.dimension.get() < self.array_end_dim
         {
             self.dimension.set(self.array_end_dim);
@@ -150,22 +193,45 @@ impl SobolSampler {
         sample
     }
 
-    pub fn samples_per_pixel(&self) -> usize {
+    fn samples_per_pixel(&self) -> usize {
         self.sampler.samples_per_pixel
     }
 
-    pub fn get_1d_array(&self, n: usize) -> Option<&[f32]> {
+    fn get_1d_array(&self, n: usize) -> Option<&[f32]> {
         self.sampler.get_1d_array(n)
     }
 
-    pub fn get_2d_array(&self, n: usize) -> Option<&[na::Point2<f32>]> {
+    fn get_2d_array(&self, n: usize) -> Option<&[na::Point2<f32>]> {
         self.sampler.get_2d_array(n)
     }
 
-    pub fn get_current_sample_number(&self) -> usize {
+    fn get_current_sample_number(&self) -> usize {
         self.sampler.current_pixel_sample_index
     }
 
+    fn get_dimension(&self) -> usize {
+        // the dimensions of the arrays are skipped over, they aren't counted
+        let dimension = self.dimension.get();
+        if dimension >= self.array_end_dim {
+            dimension - (self.array_end_dim - ARRAY_START_DIM)
+        } else {
+            dimension.min(ARRAY_START_DIM)
+        }
+    }
+
+    fn set_dimension(&self, dimension: usize) {
+        self.dimension.set(if dimension >= ARRAY_START_DIM {
+            dimension + self.array_end_dim - ARRAY_START_DIM
+        } else {
+            dimension
+        });
+    }
+
+    /// the samples only depend on the pixel, there's no stream to restart
+    fn set_seed(&mut self, _seed: u64) {}
+}
+
+impl SobolSampler {
     pub fn get_index_for_sample(&self, sample_num: u64) -> i64 {
         sobol_interval_to_index(
             self.log_2_resolution,
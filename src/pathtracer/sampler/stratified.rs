@@ -1,13 +1,16 @@
-use super::{CoreSampler, PixelSampler};
+use super::{CoreSampler, PixelSampler, SamplerInterface};
 use crate::pathtracer::{
     sampling::latin_hyper_cube_2d, sampling::shuffle, sampling::stratified_sample_1d,
     sampling::stratified_sample_2d, sampling::Random, CameraSample,
 };
 use rand::SeedableRng;
 
+/// pixel samples spread over a grid of `x_pixel_samples` by `y_pixel_samples` strata, each dimension
+/// shuffled separately. without jitter the samples sit at the centers of their strata
 #[derive(Clone)]
 pub struct StratifiedSamplerBuilder {
-    dim_pixel_samples: usize,
+    x_pixel_samples: usize,
+    y_pixel_samples: usize,
     jitter_samples: bool,
     n_sampled_dimensions: usize,
     rng: Random,
@@ -19,10 +22,16 @@ pub struct StratifiedSamplerBuilder {
 }
 
 impl StratifiedSamplerBuilder {
-    pub fn new(log: &slog::Logger, dim_pixel_samples: usize, n_sampled_dimensions: usize) -> Self {
+    pub fn new(
+        log: &slog::Logger,
+        x_pixel_samples: usize,
+        y_pixel_samples: usize,
+        n_sampled_dimensions: usize,
+    ) -> Self {
         let log = log.new(o!("module" => "sampler"));
         Self {
-            dim_pixel_samples,
+            x_pixel_samples: x_pixel_samples.max(1),
+            y_pixel_samples: y_pixel_samples.max(1),
             jitter_samples: true,
             n_sampled_dimensions,
             rng: Random::from_entropy(),
@@ -35,7 +44,7 @@ impl StratifiedSamplerBuilder {
     }
 
     pub fn build(&self) -> StratifiedSampler {
-        let samples_per_pixel = self.dim_pixel_samples * self.dim_pixel_samples;
+        let samples_per_pixel = self.samples_per_pixel();
         StratifiedSampler {
             pixel_sampler: PixelSampler::new(
                 CoreSampler::new(
@@ -49,14 +58,15 @@ impl StratifiedSamplerBuilder {
                 self.n_sampled_dimensions,
                 self.rng.clone(),
             ),
-            dim_pixel_samples: self.dim_pixel_samples,
+            x_pixel_samples: self.x_pixel_samples,
+            y_pixel_samples: self.y_pixel_samples,
             jitter_samples: self.jitter_samples,
             log: self.log.clone(),
         }
     }
 
     pub fn request_1d_array(&mut self, n: usize) -> &mut Self {
-        let samples_per_pixel = self.dim_pixel_samples * self.dim_pixel_samples;
+        let samples_per_pixel = self.samples_per_pixel();
 
         self.sample_1d_array_sizes.push(n);
         self.sample_array_1d.push(vec![0.0; n * samples_per_pixel]);
@@ -65,7 +75,7 @@ impl StratifiedSamplerBuilder {
     }
 
     pub fn request_2d_array(&mut self, n: usize) -> &mut Self {
-        let samples_per_pixel = self.dim_pixel_samples * self.dim_pixel_samples;
+        let samples_per_pixel = self.samples_per_pixel();
 
         self.sample_2d_array_sizes.push(n);
         self.sample_array_2d
@@ -79,29 +89,60 @@ impl StratifiedSamplerBuilder {
 
         self
     }
+
+    pub fn with_jitter(&mut self, jitter_samples: bool) -> &mut Self {
+        self.jitter_samples = jitter_samples;
+
+        self
+    }
+
+    pub fn samples_per_pixel(&self) -> usize {
+        self.x_pixel_samples * self.y_pixel_samples
+    }
+
+    /// the same sampler taking about `samples_per_pixel` samples on a square grid, without the
+    /// requested arrays
+    pub fn with_samples_per_pixel(&self, samples_per_pixel: usize) -> Self {
+        let (x_pixel_samples, y_pixel_samples) = square_strata(samples_per_pixel);
+        let mut builder = Self::new(
+            &self.log,
+            x_pixel_samples,
+            y_pixel_samples,
+            self.n_sampled_dimensions,
+        );
+        builder.with_jitter(self.jitter_samples);
+        builder
+    }
+}
+
+/// strata of the squarest grid holding at least `samples_per_pixel` samples
+pub fn square_strata(samples_per_pixel: usize) -> (usize, usize) {
+    let x = ((samples_per_pixel as f32).sqrt().ceil() as usize).max(1);
+    (x, (samples_per_pixel + x - 1) / x)
 }
 
 pub struct StratifiedSampler {
     pixel_sampler: PixelSampler,
-    dim_pixel_samples: usize,
+    x_pixel_samples: usize,
+    y_pixel_samples: usize,
     jitter_samples: bool,
     log: slog::Logger,
 }
 
-impl StratifiedSampler {
-    pub fn start_pixel(&mut self, p: &na::Point2<i32>) {
+impl SamplerInterface for StratifiedSampler {
+    fn start_pixel(&mut self, p: &na::Point2<i32>) {
         let pixel_sampler = &mut self.pixel_sampler;
         let sampler = &mut pixel_sampler.sampler;
         for i in 0..pixel_sampler.samples_1d.len() {
             stratified_sample_1d(
                 &mut pixel_sampler.samples_1d[i][..],
-                self.dim_pixel_samples * self.dim_pixel_samples,
+                sampler.samples_per_pixel,
                 pixel_sampler.rng.get_mut(),
                 self.jitter_samples,
             );
             shuffle(
                 &mut pixel_sampler.samples_1d[i][..],
-                self.dim_pixel_samples * self.dim_pixel_samples,
+                sampler.samples_per_pixel,
                 1,
                 pixel_sampler.rng.get_mut(),
             );
@@ -109,14 +150,14 @@ impl StratifiedSampler {
         for i in 0..pixel_sampler.samples_2d.len() {
             stratified_sample_2d(
                 &mut pixel_sampler.samples_2d[i][..],
-                self.dim_pixel_samples,
-                self.dim_pixel_samples,
+                self.x_pixel_samples,
+                self.y_pixel_samples,
                 pixel_sampler.rng.get_mut(),
                 self.jitter_samples,
             );
             shuffle(
                 &mut pixel_sampler.samples_2d[i][..],
-                self.dim_pixel_samples * self.dim_pixel_samples,
+                sampler.samples_per_pixel,
                 1,
                 pixel_sampler.rng.get_mut(),
             );
@@ -142,12 +183,30 @@ impl StratifiedSampler {
         for i in 0..sampler.sample_2d_array_sizes.len() {
             for j in 0..sampler.samples_per_pixel {
                 let count = sampler.sample_2d_array_sizes[i];
-                latin_hyper_cube_2d(
-                    &mut sampler.sample_array_2d[i][j * count..],
-                    count,
-                    2,
-                    pixel_sampler.rng.get_mut(),
-                );
+                // square counts are jittered on a grid, the rest only stratified by row and column
+                let strata = (count as f32).sqrt().round() as usize;
+                if strata * strata == count {
+                    stratified_sample_2d(
+                        &mut sampler.sample_array_2d[i][j * count..],
+                        strata,
+                        strata,
+                        pixel_sampler.rng.get_mut(),
+                        self.jitter_samples,
+                    );
+                    shuffle(
+                        &mut sampler.sample_array_2d[i][j * count..],
+                        count,
+                        1,
+                        pixel_sampler.rng.get_mut(),
+                    );
+                } else {
+                    latin_hyper_cube_2d(
+                        &mut sampler.sample_array_2d[i][j * count..],
+                        count,
+                        2,
+                        pixel_sampler.rng.get_mut(),
+                    );
+                }
             }
         }
 
@@ -159,24 +218,28 @@ impl StratifiedSampler {
         sampler.start_pixel(&p);
     }
 
-    pub fn get_camera_sample(&mut self, p_raster: &na::Point2<i32>) -> CameraSample {
+    fn get_camera_sample(&mut self, p_raster: &na::Point2<i32>) -> CameraSample {
         self.pixel_sampler.get_camera_sample(&p_raster)
     }
 
-    pub fn start_next_sample(&mut self) -> bool {
+    fn start_next_sample(&mut self) -> bool {
         trace!(self.log, "starting next sample");
         self.pixel_sampler.start_next_sample()
     }
 
-    pub fn get_1d(&self) -> f32 {
+    fn set_sample_number(&mut self, sample_num: usize) -> bool {
+        self.pixel_sampler.set_sample_number(sample_num)
+    }
+
+    fn get_1d(&self) -> f32 {
         self.pixel_sampler.get_1d()
     }
 
-    pub fn get_2d(&self) -> na::Point2<f32> {
+    fn get_2d(&self) -> na::Point2<f32> {
         trace!(
             self.log,
-            "curr_2d_dim: {:?}, curr_pixel_sample_idx: {:?}",
-            self.pixel_sampler.current_2d_dimension,
+            "curr_dim: {:?}, curr_pixel_sample_idx: {:?}",
+            self.pixel_sampler.dimension,
             self.pixel_sampler.sampler.current_pixel_sample_index
         );
         let sample = self.pixel_sampler.get_2d();
@@ -184,19 +247,31 @@ impl StratifiedSampler {
         sample
     }
 
-    pub fn samples_per_pixel(&self) -> usize {
+    fn samples_per_pixel(&self) -> usize {
         self.pixel_sampler.sampler.samples_per_pixel
     }
 
-    pub fn get_1d_array(&self, n: usize) -> Option<&[f32]> {
+    fn get_1d_array(&self, n: usize) -> Option<&[f32]> {
         self.pixel_sampler.sampler.get_1d_array(n)
     }
 
-    pub fn get_2d_array(&self, n: usize) -> Option<&[na::Point2<f32>]> {
+    fn get_2d_array(&self, n: usize) -> Option<&[na::Point2<f32>]> {
         self.pixel_sampler.sampler.get_2d_array(n)
     }
 
-    pub fn get_current_sample_number(&self) -> usize {
+    fn get_current_sample_number(&self) -> usize {
         self.pixel_sampler.sampler.current_pixel_sample_index
     }
+
+    fn get_dimension(&self) -> usize {
+        self.pixel_sampler.dimension.get()
+    }
+
+    fn set_dimension(&self, dimension: usize) {
+        self.pixel_sampler.dimension.set(dimension);
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        *self.pixel_sampler.rng.get_mut() = Random::seed_from_u64(seed);
+    }
 }
@@ -93,6 +93,17 @@ pub const fn uniform_hemisphere_pdf() -> f32 {
     INV_2_PI
 }
 
+pub fn uniform_sample_sphere(u: &na::Point2<f32>) -> na::Vector3<f32> {
+    let z = 1.0 - 2.0 * u[0];
+    let r = 0.0f32.max(1.0 - z * z).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * u[1];
+    na::Vector3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+pub fn uniform_cone_pdf(cos_theta_max: f32) -> f32 {
+    1.0 / (2.0 * std::f32::consts::PI * (1.0 - cos_theta_max))
+}
+
 pub fn concentric_sample_disk(u: &na::Point2<f32>) -> na::Point2<f32> {
     let u_offset = 2.0 * u - na::Vector2::new(1.0, 1.0);
 
@@ -0,0 +1,411 @@
+use crate::common::bounds::Bounds3;
+use crate::common::math::{coordinate_system, float_to_bits, lerp};
+use crate::common::ray::Ray;
+use crate::pathtracer::SurfaceMediumInteraction;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CurveType {
+    /// always faces the incoming ray
+    Flat,
+    /// flat but shaded as if it were a cylinder
+    Cylinder,
+    /// oriented by normals interpolated along the curve
+    Ribbon,
+}
+
+/// data shared by all segments split off of one cubic bezier curve, in world space
+pub struct CurveCommon {
+    curve_type: CurveType,
+    cp: [na::Point3<f32>; 4],
+    width: [f32; 2],
+    n: [na::Vector3<f32>; 2],
+    normal_angle: f32,
+    inv_sin_normal_angle: f32,
+}
+
+impl CurveCommon {
+    pub fn new(
+        cp: [na::Point3<f32>; 4],
+        width_0: f32,
+        width_1: f32,
+        curve_type: CurveType,
+        normals: Option<[na::Vector3<f32>; 2]>,
+    ) -> Self {
+        let (n, normal_angle, inv_sin_normal_angle) = if let Some(normals) = normals {
+            let n = [normals[0].normalize(), normals[1].normalize()];
+            let normal_angle = n[0].dot(&n[1]).clamp(0.0, 1.0).acos();
+            (n, normal_angle, 1.0 / normal_angle.sin())
+        } else {
+            ([glm::zero(), glm::zero()], 0.0, 0.0)
+        };
+
+        Self {
+            curve_type,
+            cp,
+            width: [width_0, width_1],
+            n,
+            normal_angle,
+            inv_sin_normal_angle,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct CurveHit {
+    t: f32,
+    u: f32,
+    v: f32,
+    hit_width: f32,
+    dpdu: na::Vector3<f32>,
+    dpdv: na::Vector3<f32>,
+}
+
+fn lerp_p(t: f32, p0: &na::Point3<f32>, p1: &na::Point3<f32>) -> na::Point3<f32> {
+    na::Point3::from(lerp(p0.coords, p1.coords, t))
+}
+
+fn blossom_bezier(p: &[na::Point3<f32>; 4], u0: f32, u1: f32, u2: f32) -> na::Point3<f32> {
+    let a = [
+        lerp_p(u0, &p[0], &p[1]),
+        lerp_p(u0, &p[1], &p[2]),
+        lerp_p(u0, &p[2], &p[3]),
+    ];
+    let b = [lerp_p(u1, &a[0], &a[1]), lerp_p(u1, &a[1], &a[2])];
+    lerp_p(u2, &b[0], &b[1])
+}
+
+fn subdivide_bezier(cp: &[na::Point3<f32>; 4]) -> [na::Point3<f32>; 7] {
+    [
+        cp[0],
+        na::Point3::from((cp[0].coords + cp[1].coords) / 2.0),
+        na::Point3::from((cp[0].coords + 2.0 * cp[1].coords + cp[2].coords) / 4.0),
+        na::Point3::from(
+            (cp[0].coords + 3.0 * cp[1].coords + 3.0 * cp[2].coords + cp[3].coords) / 8.0,
+        ),
+        na::Point3::from((cp[1].coords + 2.0 * cp[2].coords + cp[3].coords) / 4.0),
+        na::Point3::from((cp[2].coords + cp[3].coords) / 2.0),
+        cp[3],
+    ]
+}
+
+/// point on the curve at `u` and the derivative there
+fn eval_bezier(cp: &[na::Point3<f32>; 4], u: f32) -> (na::Point3<f32>, na::Vector3<f32>) {
+    let cp1 = [
+        lerp_p(u, &cp[0], &cp[1]),
+        lerp_p(u, &cp[1], &cp[2]),
+        lerp_p(u, &cp[2], &cp[3]),
+    ];
+    let cp2 = [lerp_p(u, &cp1[0], &cp1[1]), lerp_p(u, &cp1[1], &cp1[2])];
+    let deriv = if (cp2[1] - cp2[0]).norm_squared() > 0.0 {
+        3.0 * (cp2[1] - cp2[0])
+    } else {
+        // first and last control points coincide with the middle ones
+        cp[3] - cp[0]
+    };
+    (lerp_p(u, &cp2[0], &cp2[1]), deriv)
+}
+
+fn log_2(v: f32) -> i32 {
+    if v < 1.0 {
+        return 0;
+    }
+    let bits = float_to_bits(v);
+    // round to the nearest power of two instead of flooring
+    (bits >> 23) as i32 - 127 + if bits & (1 << 22) != 0 { 1 } else { 0 }
+}
+
+/// whether the bounds of the control points, padded by half the width, may contain the ray
+fn overlaps_ray(cp: &[na::Point3<f32>], half_width: f32, z_max: f32) -> bool {
+    let min = |i: usize| cp.iter().map(|p| p[i]).fold(f32::INFINITY, f32::min);
+    let max = |i: usize| cp.iter().map(|p| p[i]).fold(f32::NEG_INFINITY, f32::max);
+
+    !(max(0) + half_width < 0.0
+        || min(0) - half_width > 0.0
+        || max(1) + half_width < 0.0
+        || min(1) - half_width > 0.0
+        || max(2) + half_width < 0.0
+        || min(2) - half_width > z_max)
+}
+
+/// the `[u_min, u_max]` segment of a cubic bezier curve with linearly varying width
+pub struct Curve {
+    common: Arc<CurveCommon>,
+    u_min: f32,
+    u_max: f32,
+}
+
+impl Curve {
+    pub fn new(common: Arc<CurveCommon>, u_min: f32, u_max: f32) -> Self {
+        Self {
+            common,
+            u_min,
+            u_max,
+        }
+    }
+
+    fn segment_cp(&self) -> [na::Point3<f32>; 4] {
+        let cp = &self.common.cp;
+        [
+            blossom_bezier(cp, self.u_min, self.u_min, self.u_min),
+            blossom_bezier(cp, self.u_min, self.u_min, self.u_max),
+            blossom_bezier(cp, self.u_min, self.u_max, self.u_max),
+            blossom_bezier(cp, self.u_max, self.u_max, self.u_max),
+        ]
+    }
+
+    fn width_at(&self, u: f32) -> f32 {
+        lerp(self.common.width[0], self.common.width[1], u)
+    }
+
+    pub fn world_bound(&self) -> Bounds3 {
+        let cp = self.segment_cp();
+        let bounds = Bounds3::union(&Bounds3::new(cp[0], cp[1]), &Bounds3::new(cp[2], cp[3]));
+        let half_width =
+            na::Vector3::repeat(0.5 * self.width_at(self.u_min).max(self.width_at(self.u_max)));
+        Bounds3::new(bounds.p_min - half_width, bounds.p_max + half_width)
+    }
+
+    fn intersect_curve(&self, r: &Ray, any_hit: bool) -> Option<CurveHit> {
+        // transform the control points into a space where the ray runs down +z from the origin
+        let cp_world = self.segment_cp();
+        let mut dx = r.d.cross(&(cp_world[3] - cp_world[0]));
+        if dx.norm_squared() == 0.0 {
+            let mut dy = glm::zero();
+            coordinate_system(&r.d.normalize(), &mut dx, &mut dy);
+        }
+        let world_to_ray = na::Isometry3::look_at_lh(&r.o, &(r.o + r.d), &dx);
+        let cp = [
+            world_to_ray * cp_world[0],
+            world_to_ray * cp_world[1],
+            world_to_ray * cp_world[2],
+            world_to_ray * cp_world[3],
+        ];
+
+        let max_width = self.width_at(self.u_min).max(self.width_at(self.u_max));
+        let z_max = r.d.norm() * r.t_max;
+        if !overlaps_ray(&cp, 0.5 * max_width, z_max) {
+            return None;
+        }
+
+        // pick a subdivision depth that makes the segments close enough to straight lines
+        let mut l0 = 0.0f32;
+        for i in 0..2 {
+            let d = glm::abs(&(cp[i].coords - 2.0 * cp[i + 1].coords + cp[i + 2].coords));
+            l0 = l0.max(d.x.max(d.y).max(d.z));
+        }
+        let eps = self.common.width[0].max(self.common.width[1]) * 0.05;
+        let r0 = log_2(1.41421356237 * 6.0 * l0 / (8.0 * eps)) / 2;
+        let max_depth = r0.clamp(0, 10);
+
+        self.recursive_intersect(
+            r,
+            &cp,
+            &world_to_ray,
+            self.u_min,
+            self.u_max,
+            max_depth,
+            any_hit,
+        )
+    }
+
+    fn recursive_intersect(
+        &self,
+        r: &Ray,
+        cp: &[na::Point3<f32>; 4],
+        world_to_ray: &na::Isometry3<f32>,
+        u0: f32,
+        u1: f32,
+        depth: i32,
+        any_hit: bool,
+    ) -> Option<CurveHit> {
+        let ray_length = r.d.norm();
+        let z_max = ray_length * r.t_max;
+
+        if depth > 0 {
+            let cp_split = subdivide_bezier(cp);
+            let u = [u0, (u0 + u1) / 2.0, u1];
+            let mut closest: Option<CurveHit> = None;
+            for seg in 0..2 {
+                let cps = [
+                    cp_split[3 * seg],
+                    cp_split[3 * seg + 1],
+                    cp_split[3 * seg + 2],
+                    cp_split[3 * seg + 3],
+                ];
+                let max_width = self.width_at(u[seg]).max(self.width_at(u[seg + 1]));
+                if !overlaps_ray(&cps, 0.5 * max_width, z_max) {
+                    continue;
+                }
+
+                if let Some(hit) = self.recursive_intersect(
+                    r,
+                    &cps,
+                    world_to_ray,
+                    u[seg],
+                    u[seg + 1],
+                    depth - 1,
+                    any_hit,
+                ) {
+                    if any_hit {
+                        return Some(hit);
+                    }
+                    if closest.map_or(true, |closest| hit.t < closest.t) {
+                        closest = Some(hit);
+                    }
+                }
+            }
+            return closest;
+        }
+
+        // test the ray against the segment's edge functions at both ends
+        let edge = (cp[1].y - cp[0].y) * -cp[0].y + cp[0].x * (cp[0].x - cp[1].x);
+        if edge < 0.0 {
+            return None;
+        }
+        let edge = (cp[2].y - cp[3].y) * -cp[3].y + cp[3].x * (cp[3].x - cp[2].x);
+        if edge < 0.0 {
+            return None;
+        }
+
+        // find the closest point on the segment's line to the ray
+        let segment_direction = na::Vector2::new(cp[3].x - cp[0].x, cp[3].y - cp[0].y);
+        let denom = segment_direction.norm_squared();
+        if denom == 0.0 {
+            return None;
+        }
+        let w = na::Vector2::new(-cp[0].x, -cp[0].y).dot(&segment_direction) / denom;
+
+        let u = lerp(u0, u1, w).clamp(u0, u1);
+        let mut hit_width = self.width_at(u);
+        let mut n_hit = glm::zero();
+        if self.common.curve_type == CurveType::Ribbon {
+            n_hit = if self.common.normal_angle > 0.0 {
+                let sin_0 =
+                    ((1.0 - u) * self.common.normal_angle).sin() * self.common.inv_sin_normal_angle;
+                let sin_1 = (u * self.common.normal_angle).sin() * self.common.inv_sin_normal_angle;
+                sin_0 * self.common.n[0] + sin_1 * self.common.n[1]
+            } else {
+                self.common.n[0]
+            };
+            hit_width *= n_hit.dot(&r.d).abs() / ray_length;
+        }
+
+        let (pc, dpcdw) = eval_bezier(cp, w.clamp(0.0, 1.0));
+        let pt_curve_dist_2 = pc.x * pc.x + pc.y * pc.y;
+        if pt_curve_dist_2 > hit_width * hit_width * 0.25 {
+            return None;
+        }
+        if pc.z < 0.0 || pc.z > z_max {
+            return None;
+        }
+
+        // v runs across the width of the curve
+        let pt_curve_dist = pt_curve_dist_2.sqrt();
+        let edge_func = dpcdw.x * -pc.y + pc.x * dpcdw.y;
+        let v = if edge_func > 0.0 {
+            0.5 + pt_curve_dist / hit_width
+        } else {
+            0.5 - pt_curve_dist / hit_width
+        };
+
+        let (_, dpdu) = eval_bezier(&self.common.cp, u);
+        let dpdv = if self.common.curve_type == CurveType::Ribbon {
+            n_hit.cross(&dpdu).normalize() * hit_width
+        } else {
+            let dpdu_plane = world_to_ray * dpdu;
+            let mut dpdv_plane =
+                na::Vector3::new(-dpdu_plane.y, dpdu_plane.x, 0.0).normalize() * hit_width;
+            if self.common.curve_type == CurveType::Cylinder {
+                // rotate dpdv around dpdu so the normal follows a cylinder
+                let theta = lerp(-90.0f32, 90.0, v);
+                let rotation = na::Rotation3::from_axis_angle(
+                    &na::Unit::new_normalize(dpdu_plane),
+                    (-theta).to_radians(),
+                );
+                dpdv_plane = rotation * dpdv_plane;
+            }
+            world_to_ray.inverse() * dpdv_plane
+        };
+
+        Some(CurveHit {
+            t: pc.z / ray_length,
+            u,
+            v,
+            hit_width,
+            dpdu,
+            dpdv,
+        })
+    }
+
+    pub fn intersect<'a>(
+        &'a self,
+        r: &Ray,
+        t_hit: &mut f32,
+        isect: &mut SurfaceMediumInteraction<'a>,
+    ) -> bool {
+        if let Some(hit) = self.intersect_curve(r, false) {
+            *isect = SurfaceMediumInteraction::new(
+                &(r.o + r.d * hit.t),
+                &na::Vector3::repeat(2.0 * hit.hit_width),
+                &na::Point2::new(hit.u, hit.v),
+                &-r.d,
+                &hit.dpdu,
+                &hit.dpdv,
+                &glm::zero(),
+                &glm::zero(),
+                0.0,
+                None,
+            );
+            *t_hit = hit.t;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn intersect_p(&self, r: &Ray) -> bool {
+        self.intersect_curve(r, true).is_some()
+    }
+}
+
+/// splits a bezier curve into `2^split_depth` segments so each gets a tighter bound
+pub fn curves_from_common(common: &Arc<CurveCommon>, split_depth: u32) -> Vec<Arc<Curve>> {
+    let n_segments = 1 << split_depth;
+    (0..n_segments)
+        .map(|i| {
+            Arc::new(Curve::new(
+                Arc::clone(common),
+                i as f32 / n_segments as f32,
+                (i + 1) as f32 / n_segments as f32,
+            ))
+        })
+        .collect()
+}
+
+/// converts a polyline strand into a chain of bezier curves passing through every point,
+/// using catmull-rom tangents
+pub fn curves_from_strand(
+    strand: &[na::Point3<f32>],
+    width: f32,
+    curve_type: CurveType,
+    split_depth: u32,
+) -> Vec<Arc<Curve>> {
+    let mut curves = vec![];
+    if strand.len() < 2 {
+        return curves;
+    }
+
+    let last = strand.len() - 1;
+    for i in 0..last {
+        let p0 = strand[i.saturating_sub(1)];
+        let p1 = strand[i];
+        let p2 = strand[i + 1];
+        let p3 = strand[(i + 2).min(last)];
+        let cp = [p1, p1 + (p2 - p0) / 6.0, p2 - (p3 - p1) / 6.0, p2];
+        let common = Arc::new(CurveCommon::new(cp, width, width, curve_type, None));
+        curves.append(&mut curves_from_common(&common, split_depth));
+    }
+
+    curves
+}
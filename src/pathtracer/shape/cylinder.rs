@@ -0,0 +1,194 @@
+use super::{normal_to_world, swaps_handedness, ShapeInterface};
+use crate::common::bounds::Bounds3;
+use crate::common::math::gamma;
+use crate::common::ray::Ray;
+use crate::pathtracer::{interaction::Interaction, SurfaceMediumInteraction};
+
+/// open tube of `radius` around the z axis of its object space between the heights `z_min` and
+/// `z_max`, swept around z up to `phi_max` when partial. sampling by area assumes `obj_to_world`
+/// scales uniformly, if at all
+pub struct Cylinder {
+    obj_to_world: na::Projective3<f32>,
+    world_to_obj: na::Projective3<f32>,
+    reverse_orientation: bool,
+    transform_swaps_handedness: bool,
+    radius: f32,
+    z_min: f32,
+    z_max: f32,
+    phi_max: f32,
+    /// ratio of world to object space area
+    area_scale: f32,
+}
+
+impl Cylinder {
+    pub fn new(
+        obj_to_world: &na::Projective3<f32>,
+        radius: f32,
+        z_min: f32,
+        z_max: f32,
+        reverse_orientation: bool,
+    ) -> Self {
+        Self {
+            obj_to_world: *obj_to_world,
+            world_to_obj: obj_to_world.inverse(),
+            reverse_orientation,
+            transform_swaps_handedness: swaps_handedness(obj_to_world),
+            radius,
+            z_min: z_min.min(z_max),
+            z_max: z_min.max(z_max),
+            phi_max: 2.0 * std::f32::consts::PI,
+            area_scale: (obj_to_world * na::Vector3::x())
+                .cross(&(obj_to_world * na::Vector3::y()))
+                .norm(),
+        }
+    }
+
+    /// partial tube swept up to `phi_max` degrees
+    pub fn with_clipping(mut self, phi_max: f32) -> Self {
+        self.phi_max = phi_max.clamp(0.0, 360.0).to_radians();
+        self
+    }
+
+    fn flip_normals(&self) -> bool {
+        self.reverse_orientation ^ self.transform_swaps_handedness
+    }
+
+    fn phi(p: &na::Point3<f32>) -> f32 {
+        let phi = p.y.atan2(p.x);
+        if phi < 0.0 {
+            phi + 2.0 * std::f32::consts::PI
+        } else {
+            phi
+        }
+    }
+
+    fn uv(&self, p: &na::Point3<f32>, phi: f32) -> na::Point2<f32> {
+        na::Point2::new(
+            phi / self.phi_max,
+            (p.z - self.z_min) / (self.z_max - self.z_min),
+        )
+    }
+
+    /// closest unclipped hit of the ray in object space, its distance, position and angle
+    fn hit(&self, r: &Ray) -> Option<(f32, na::Point3<f32>, f32)> {
+        let o = (self.world_to_obj * r.o).coords.cast::<f64>();
+        let d = (self.world_to_obj * r.d).cast::<f64>();
+        let radius = self.radius as f64;
+
+        // only the distance from the axis matters, solved in double precision like spheres
+        let a = d.x * d.x + d.y * d.y;
+        if a == 0.0 {
+            return None;
+        }
+        let b = 2.0 * (d.x * o.x + d.y * o.y);
+        let c = o.x * o.x + o.y * o.y - radius * radius;
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let root = discriminant.sqrt();
+        let q = if b < 0.0 {
+            -0.5 * (b - root)
+        } else {
+            -0.5 * (b + root)
+        };
+        let (mut t0, mut t1) = (q / a, c / q);
+        if t0 > t1 {
+            std::mem::swap(&mut t0, &mut t1);
+        }
+
+        for &t in [t0, t1].iter() {
+            let t = t as f32;
+            if t <= 0.0 || t >= r.t_max {
+                continue;
+            }
+
+            // refine the hit onto the surface
+            let mut p = self.world_to_obj * (r.o + t * r.d);
+            let dist = (p.x * p.x + p.y * p.y).sqrt();
+            p.x *= self.radius / dist;
+            p.y *= self.radius / dist;
+            let phi = Self::phi(&p);
+            if p.z >= self.z_min && p.z <= self.z_max && phi <= self.phi_max {
+                return Some((t, p, phi));
+            }
+        }
+
+        None
+    }
+}
+
+impl ShapeInterface for Cylinder {
+    fn intersect<'a>(
+        &'a self,
+        r: &Ray,
+        t_hit: &mut f32,
+        isect: &mut SurfaceMediumInteraction<'a>,
+    ) -> bool {
+        let (t, p, phi) = match self.hit(r) {
+            Some(hit) => hit,
+            None => return false,
+        };
+
+        // the tube only curves around its axis, so the normal only changes along u
+        let dpdu = na::Vector3::new(-self.phi_max * p.y, self.phi_max * p.x, 0.0);
+        let dpdv = na::Vector3::new(0.0, 0.0, self.z_max - self.z_min);
+        let dndu = dpdu / self.radius;
+
+        let p_world = self.obj_to_world * p;
+        *isect = SurfaceMediumInteraction::new(
+            &p_world,
+            &(gamma(5) * p_world.coords.abs()),
+            &self.uv(&p, phi),
+            &-r.d,
+            &(self.obj_to_world * dpdu),
+            &(self.obj_to_world * dpdv),
+            &(normal_to_world(&self.world_to_obj) * dndu),
+            &glm::zero(),
+            0.0,
+            None,
+        );
+        if self.flip_normals() {
+            isect.general.n *= -1.0;
+            isect.shading.n *= -1.0;
+        }
+
+        *t_hit = t;
+        true
+    }
+
+    fn intersect_p(&self, r: &Ray) -> bool {
+        self.hit(r).is_some()
+    }
+
+    fn world_bound(&self) -> Bounds3 {
+        Bounds3::new(
+            na::Point3::new(-self.radius, -self.radius, self.z_min),
+            na::Point3::new(self.radius, self.radius, self.z_max),
+        )
+        .transform(&self.obj_to_world)
+    }
+
+    fn area(&self) -> f32 {
+        self.area_scale * (self.z_max - self.z_min) * self.radius * self.phi_max
+    }
+
+    fn sample(&self, u: &na::Point2<f32>) -> SurfaceMediumInteraction {
+        let z = self.z_min + u[0] * (self.z_max - self.z_min);
+        let phi = u[1] * self.phi_max;
+        let p = na::Point3::new(self.radius * phi.cos(), self.radius * phi.sin(), z);
+        let mut it = Interaction::default();
+        it.p = self.obj_to_world * p;
+        it.n = (normal_to_world(&self.world_to_obj) * na::Vector3::new(p.x, p.y, 0.0)).normalize();
+        if self.reverse_orientation {
+            it.n *= -1.0;
+        }
+        it.p_error = gamma(5) * it.p.coords.abs();
+
+        SurfaceMediumInteraction {
+            general: it,
+            uv: self.uv(&p, phi),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use super::{normal_to_world, swaps_handedness, ShapeInterface};
+use crate::common::bounds::Bounds3;
+use crate::common::math::gamma;
+use crate::common::ray::Ray;
+use crate::pathtracer::{
+    interaction::Interaction, sampling::concentric_sample_disk, SurfaceMediumInteraction,
+};
+
+/// disk of `radius` in the plane z = `height` of its object space facing +z, with a hole of
+/// `inner_radius` and swept around z up to `phi_max` when partial. any affine `obj_to_world` keeps
+/// the sampling uniform by area
+pub struct Disk {
+    obj_to_world: na::Projective3<f32>,
+    world_to_obj: na::Projective3<f32>,
+    reverse_orientation: bool,
+    transform_swaps_handedness: bool,
+    height: f32,
+    radius: f32,
+    inner_radius: f32,
+    phi_max: f32,
+    /// ratio of world to object space area
+    area_scale: f32,
+}
+
+impl Disk {
+    pub fn new(
+        obj_to_world: &na::Projective3<f32>,
+        height: f32,
+        radius: f32,
+        reverse_orientation: bool,
+    ) -> Self {
+        Self {
+            obj_to_world: *obj_to_world,
+            world_to_obj: obj_to_world.inverse(),
+            reverse_orientation,
+            transform_swaps_handedness: swaps_handedness(obj_to_world),
+            height,
+            radius,
+            inner_radius: 0.0,
+            phi_max: 2.0 * std::f32::consts::PI,
+            area_scale: (obj_to_world * na::Vector3::x())
+                .cross(&(obj_to_world * na::Vector3::y()))
+                .norm(),
+        }
+    }
+
+    /// annulus from `inner_radius` out, swept up to `phi_max` degrees
+    pub fn with_clipping(mut self, inner_radius: f32, phi_max: f32) -> Self {
+        self.inner_radius = inner_radius.clamp(0.0, self.radius);
+        self.phi_max = phi_max.clamp(0.0, 360.0).to_radians();
+        self
+    }
+
+    fn flip_normals(&self) -> bool {
+        self.reverse_orientation ^ self.transform_swaps_handedness
+    }
+
+    fn phi(p: &na::Point3<f32>) -> f32 {
+        let phi = p.y.atan2(p.x);
+        if phi < 0.0 {
+            phi + 2.0 * std::f32::consts::PI
+        } else {
+            phi
+        }
+    }
+
+    fn uv(&self, p: &na::Point3<f32>) -> na::Point2<f32> {
+        let dist = (p.x * p.x + p.y * p.y).sqrt();
+        na::Point2::new(
+            Self::phi(p) / self.phi_max,
+            (self.radius - dist) / (self.radius - self.inner_radius),
+        )
+    }
+
+    /// unclipped hit of the ray in object space, its distance and position
+    fn hit(&self, r: &Ray) -> Option<(f32, na::Point3<f32>)> {
+        let o = self.world_to_obj * r.o;
+        let d = self.world_to_obj * r.d;
+        if d.z == 0.0 {
+            return None;
+        }
+
+        let t = (self.height - o.z) / d.z;
+        if t <= 0.0 || t >= r.t_max {
+            return None;
+        }
+
+        let mut p = o + t * d;
+        p.z = self.height;
+        let dist2 = p.x * p.x + p.y * p.y;
+        if dist2 > self.radius * self.radius
+            || dist2 < self.inner_radius * self.inner_radius
+            || Self::phi(&p) > self.phi_max
+        {
+            return None;
+        }
+
+        Some((t, p))
+    }
+}
+
+impl ShapeInterface for Disk {
+    fn intersect<'a>(
+        &'a self,
+        r: &Ray,
+        t_hit: &mut f32,
+        isect: &mut SurfaceMediumInteraction<'a>,
+    ) -> bool {
+        let (t, p) = match self.hit(r) {
+            Some(hit) => hit,
+            None => return false,
+        };
+
+        // the center has no well defined direction of increasing phi
+        let dist = (p.x * p.x + p.y * p.y).sqrt().max(1e-5 * self.radius);
+        let dpdu = na::Vector3::new(-self.phi_max * p.y, self.phi_max * p.x, 0.0);
+        let dpdv = na::Vector3::new(p.x, p.y, 0.0) * (self.inner_radius - self.radius) / dist;
+        let p_world = self.obj_to_world * p;
+
+        *isect = SurfaceMediumInteraction::new(
+            &p_world,
+            &(gamma(5) * p_world.coords.abs()),
+            &self.uv(&p),
+            &-r.d,
+            &(self.obj_to_world * dpdu),
+            &(self.obj_to_world * dpdv),
+            &glm::zero(),
+            &glm::zero(),
+            0.0,
+            None,
+        );
+        if self.flip_normals() {
+            isect.general.n *= -1.0;
+            isect.shading.n *= -1.0;
+        }
+
+        *t_hit = t;
+        true
+    }
+
+    fn intersect_p(&self, r: &Ray) -> bool {
+        self.hit(r).is_some()
+    }
+
+    fn world_bound(&self) -> Bounds3 {
+        Bounds3::new(
+            na::Point3::new(-self.radius, -self.radius, self.height),
+            na::Point3::new(self.radius, self.radius, self.height),
+        )
+        .transform(&self.obj_to_world)
+    }
+
+    fn area(&self) -> f32 {
+        self.area_scale
+            * 0.5
+            * self.phi_max
+            * (self.radius * self.radius - self.inner_radius * self.inner_radius)
+    }
+
+    /// samples the whole disk, clipped disks are only sampled approximately
+    fn sample(&self, u: &na::Point2<f32>) -> SurfaceMediumInteraction {
+        let pd = concentric_sample_disk(&u);
+        let p = na::Point3::new(pd.x * self.radius, pd.y * self.radius, self.height);
+        let mut it = Interaction::default();
+        it.p = self.obj_to_world * p;
+        it.n = (normal_to_world(&self.world_to_obj) * na::Vector3::z()).normalize();
+        if self.reverse_orientation {
+            it.n *= -1.0;
+        }
+        it.p_error = gamma(5) * it.p.coords.abs();
+
+        SurfaceMediumInteraction {
+            general: it,
+            uv: self.uv(&p),
+            ..Default::default()
+        }
+    }
+}
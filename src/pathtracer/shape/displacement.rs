@@ -0,0 +1,301 @@
+use super::TriangleMesh;
+use crate::common::bounds::Bounds3;
+use crate::common::{Camera, Projection};
+use crate::pathtracer::{interaction::Interaction, texture::SyncTexture, SurfaceMediumInteraction};
+use std::collections::HashMap;
+
+/// edges on screen are diced down to about this many pixels
+pub const DEFAULT_MAX_EDGE_PIXELS: f32 = 2.0;
+/// edges are diced down to this fraction of the mesh size when there's no camera to dice for
+const DEFAULT_MAX_EDGE_FRACTION: f32 = 1.0 / 256.0;
+/// edges shorter than this fraction of the mesh size are never split, bounding the dicing of
+/// meshes right in front of the camera
+const MIN_EDGE_FRACTION: f32 = 1.0 / 4096.0;
+/// safety net for the recursion, the edge lengths normally stop it long before
+const MAX_DICING_DEPTH: u32 = 24;
+
+/// how finely displaced meshes are diced before their vertices are moved
+pub enum DicingRate {
+    /// edges are split until they span at most `max_pixels` as seen from `eye`
+    Screen {
+        eye: na::Point3<f32>,
+        /// pixels spanned by a unit length at unit distance
+        pixels_per_unit: f32,
+        max_pixels: f32,
+    },
+    /// edges are split until they span at most `max_pixels` on an orthographic film, however far
+    /// away they are
+    Parallel {
+        /// pixels spanned by a unit length
+        pixels_per_unit: f32,
+        max_pixels: f32,
+    },
+    /// edges are split until they're at most `max_fraction` of the diagonal of their mesh
+    Relative { max_fraction: f32 },
+}
+
+impl DicingRate {
+    pub fn from_camera(camera: &Camera, max_pixels: f32) -> Self {
+        match &camera.cam_to_screen {
+            Projection::Perspective(perspective) => DicingRate::Screen {
+                eye: camera.cam_to_world * na::Point3::origin(),
+                pixels_per_unit: camera.film.resolution.y as f32
+                    / (2.0 * (0.5 * perspective.fovy()).tan()),
+                max_pixels,
+            },
+            Projection::Orthographic(orthographic) => DicingRate::Parallel {
+                pixels_per_unit: camera.film.resolution.y as f32
+                    / (orthographic.top() - orthographic.bottom()),
+                max_pixels,
+            },
+            // a unit length at unit distance spans about a radian
+            Projection::Spherical { .. } => DicingRate::Screen {
+                eye: camera.cam_to_world * na::Point3::origin(),
+                pixels_per_unit: camera.film.resolution.x as f32 / (2.0 * std::f32::consts::PI),
+                max_pixels,
+            },
+            Projection::Fisheye { fov, .. } => DicingRate::Screen {
+                eye: camera.cam_to_world * na::Point3::origin(),
+                pixels_per_unit: (camera.film.resolution.x as f32)
+                    .hypot(camera.film.resolution.y as f32)
+                    / fov,
+                max_pixels,
+            },
+            Projection::Realistic { preview, .. } => DicingRate::Screen {
+                eye: camera.cam_to_world * na::Point3::origin(),
+                pixels_per_unit: camera.film.resolution.y as f32
+                    / (2.0 * (0.5 * preview.fovy()).tan()),
+                max_pixels,
+            },
+        }
+    }
+
+    /// dicing for scenes without a camera to dice for
+    pub fn relative() -> Self {
+        DicingRate::Relative {
+            max_fraction: DEFAULT_MAX_EDGE_FRACTION,
+        }
+    }
+
+    fn split(&self, p0: &na::Point3<f32>, p1: &na::Point3<f32>, mesh_size: f32) -> bool {
+        let length = (p1 - p0).norm();
+        if length < MIN_EDGE_FRACTION * mesh_size {
+            return false;
+        }
+
+        match self {
+            DicingRate::Screen {
+                eye,
+                pixels_per_unit,
+                max_pixels,
+            } => {
+                let distance = (na::center(p0, p1) - eye).norm();
+                length * pixels_per_unit > max_pixels * distance
+            }
+            DicingRate::Parallel {
+                pixels_per_unit,
+                max_pixels,
+            } => length * pixels_per_unit > *max_pixels,
+            DicingRate::Relative { max_fraction } => length > max_fraction * mesh_size,
+        }
+    }
+}
+
+/// height field moving the vertices of diced meshes along their normals, by `scale` times the
+/// height
+pub struct Displacement {
+    height: Box<dyn SyncTexture<f32>>,
+    scale: f32,
+}
+
+impl Displacement {
+    pub fn new(height: Box<dyn SyncTexture<f32>>, scale: f32) -> Self {
+        Self { height, scale }
+    }
+
+    /// dices `mesh` at `rate` and displaces the new vertices, the shading normals are rebuilt
+    /// from the displaced surface. vertices split along uv seams move apart if the height differs
+    /// across the seam. `None` for meshes without uvs to look up the height with
+    pub fn displace(
+        &self,
+        log: &slog::Logger,
+        mesh: &TriangleMesh,
+        rate: &DicingRate,
+    ) -> Option<TriangleMesh> {
+        if mesh.uv.is_empty() {
+            warn!(
+                log,
+                "mesh has no uvs to look up displacement with, skipping"
+            );
+            return None;
+        }
+
+        let mut dicer = Dicer {
+            rate,
+            mesh_size: mesh
+                .pos
+                .iter()
+                .fold(Bounds3::empty(), |bounds, p| Bounds3::union_p(&bounds, p))
+                .diagonal()
+                .norm(),
+            pos: mesh.pos.clone(),
+            normal: if mesh.normal.is_empty() {
+                vertex_normals(&mesh.indices, &mesh.pos)
+            } else {
+                mesh.normal.iter().map(|n| n.normalize()).collect()
+            },
+            s: mesh.s.clone(),
+            uv: mesh.uv.clone(),
+            colors: mesh.colors.clone(),
+            midpoints: HashMap::new(),
+            indices: vec![],
+        };
+        for indices in &mesh.indices {
+            dicer.dice([indices[0], indices[1], indices[2]], 0);
+        }
+
+        let Dicer {
+            indices,
+            mut pos,
+            normal,
+            s,
+            uv,
+            colors,
+            ..
+        } = dicer;
+        for (p, (n, uv)) in pos.iter_mut().zip(normal.iter().zip(&uv)) {
+            let it = SurfaceMediumInteraction {
+                general: Interaction {
+                    p: *p,
+                    n: *n,
+                    ..Default::default()
+                },
+                uv: *uv,
+                ..Default::default()
+            };
+            *p += self.scale * self.height.evaluate(&it) * *n;
+        }
+        debug!(log, "diced displaced mesh";
+            "triangles" => mesh.indices.len(),
+            "diced_triangles" => indices.len());
+
+        Some(TriangleMesh {
+            normal: vertex_normals(&indices, &pos),
+            indices,
+            pos,
+            s,
+            uv,
+            colors,
+            alpha_mask: mesh.alpha_mask.clone(),
+        })
+    }
+}
+
+/// area weighted average of the normals of the triangles around each vertex
+fn vertex_normals(indices: &[na::Vector3<u32>], pos: &[na::Point3<f32>]) -> Vec<na::Vector3<f32>> {
+    let mut normal = vec![na::Vector3::zeros(); pos.len()];
+    for indices in indices {
+        let (p0, p1, p2) = (
+            pos[indices[0] as usize],
+            pos[indices[1] as usize],
+            pos[indices[2] as usize],
+        );
+        let n = (p1 - p0).cross(&(p2 - p0));
+        for &idx in indices.iter() {
+            normal[idx as usize] += n;
+        }
+    }
+
+    normal
+        .into_iter()
+        .map(|n| n.try_normalize(0.0).unwrap_or_else(na::Vector3::z))
+        .collect()
+}
+
+/// splits triangles along the edges `rate` asks to split. the decision only depends on the edge,
+/// so neighbouring triangles split their shared edges alike and the diced mesh stays watertight
+struct Dicer<'a> {
+    rate: &'a DicingRate,
+    mesh_size: f32,
+    pos: Vec<na::Point3<f32>>,
+    normal: Vec<na::Vector3<f32>>,
+    s: Vec<na::Vector3<f32>>,
+    uv: Vec<na::Point2<f32>>,
+    colors: Vec<na::Vector3<f32>>,
+    midpoints: HashMap<(u32, u32), u32>,
+    indices: Vec<na::Vector3<u32>>,
+}
+
+impl<'a> Dicer<'a> {
+    fn midpoint(&mut self, a: u32, b: u32) -> u32 {
+        let key = (a.min(b), a.max(b));
+        if let Some(&idx) = self.midpoints.get(&key) {
+            return idx;
+        }
+
+        let (a, b) = (a as usize, b as usize);
+        self.pos.push(na::center(&self.pos[a], &self.pos[b]));
+        let n = (self.normal[a] + self.normal[b])
+            .try_normalize(0.0)
+            .unwrap_or(self.normal[a]);
+        self.normal.push(n);
+        if !self.s.is_empty() {
+            self.s.push(0.5 * (self.s[a] + self.s[b]));
+        }
+        self.uv.push(na::center(&self.uv[a], &self.uv[b]));
+        if !self.colors.is_empty() {
+            self.colors.push(0.5 * (self.colors[a] + self.colors[b]));
+        }
+
+        let idx = self.pos.len() as u32 - 1;
+        self.midpoints.insert(key, idx);
+        idx
+    }
+
+    fn dice(&mut self, tri: [u32; 3], depth: u32) {
+        let split: Vec<bool> = (0..3)
+            .map(|i| {
+                let (a, b) = (tri[i] as usize, tri[(i + 1) % 3] as usize);
+                depth < MAX_DICING_DEPTH
+                    && self.rate.split(&self.pos[a], &self.pos[b], self.mesh_size)
+            })
+            .collect();
+
+        match split.iter().filter(|&&split| split).count() {
+            0 => self.indices.push(na::Vector3::new(tri[0], tri[1], tri[2])),
+            1 => {
+                // rotated so the split edge runs from v0 to v1
+                let first = split.iter().position(|&split| split).unwrap();
+                let [v0, v1, v2] = rotate(tri, first);
+                let m = self.midpoint(v0, v1);
+                self.dice([v0, m, v2], depth + 1);
+                self.dice([m, v1, v2], depth + 1);
+            }
+            2 => {
+                // rotated so the edge left whole runs from v2 to v0
+                let whole = split.iter().position(|&split| !split).unwrap();
+                let [v0, v1, v2] = rotate(tri, (whole + 1) % 3);
+                let m01 = self.midpoint(v0, v1);
+                let m12 = self.midpoint(v1, v2);
+                self.dice([m01, v1, m12], depth + 1);
+                self.dice([v0, m01, m12], depth + 1);
+                self.dice([v0, m12, v2], depth + 1);
+            }
+            _ => {
+                let [v0, v1, v2] = tri;
+                let m01 = self.midpoint(v0, v1);
+                let m12 = self.midpoint(v1, v2);
+                let m20 = self.midpoint(v2, v0);
+                self.dice([v0, m01, m20], depth + 1);
+                self.dice([m01, v1, m12], depth + 1);
+                self.dice([m20, m12, v2], depth + 1);
+                self.dice([m01, m12, m20], depth + 1);
+            }
+        }
+    }
+}
+
+/// the vertices of `tri` starting from its `first`, keeping the winding
+fn rotate(tri: [u32; 3], first: usize) -> [u32; 3] {
+    [tri[first], tri[(first + 1) % 3], tri[(first + 2) % 3]]
+}
@@ -1,9 +1,97 @@
+pub mod curve;
+pub mod cylinder;
+pub mod disk;
+pub mod displacement;
+pub mod rectangle;
+pub mod sphere;
+
 use super::{interaction::Interaction, texture::SyncTexture, SurfaceMediumInteraction};
 use crate::common::bounds::Bounds3;
 use crate::common::math::*;
 use crate::common::ray::Ray;
+use crate::common::spectrum::Spectrum;
+use ambassador::{delegatable_trait, Delegate};
+use cylinder::Cylinder;
+use disk::Disk;
+use rectangle::Rectangle;
+use sphere::Sphere;
 use std::sync::Arc;
 
+#[delegatable_trait]
+pub trait ShapeInterface {
+    fn intersect<'a>(
+        &'a self,
+        r: &Ray,
+        t_hit: &mut f32,
+        isect: &mut SurfaceMediumInteraction<'a>,
+    ) -> bool;
+    fn intersect_p(&self, r: &Ray) -> bool;
+    fn world_bound(&self) -> Bounds3;
+    fn area(&self) -> f32;
+    /// point sampled uniformly by area
+    fn sample(&self, u: &na::Point2<f32>) -> SurfaceMediumInteraction;
+
+    /// point sampled as seen from `reference`, with the solid angle density of `pdf_at_point`
+    fn sample_at_point(
+        &self,
+        _reference: &Interaction,
+        u: &na::Point2<f32>,
+    ) -> SurfaceMediumInteraction {
+        self.sample(&u)
+    }
+
+    fn pdf(&self, _it: &Interaction) -> f32 {
+        1.0 / self.area()
+    }
+
+    fn pdf_at_point(&self, reference: &Interaction, wi: &na::Vector3<f32>) -> f32 {
+        area_pdf_at_point(self, reference, wi)
+    }
+}
+
+#[derive(Delegate)]
+#[delegate(ShapeInterface)]
+pub enum Shape {
+    Triangle(Triangle),
+    Sphere(Sphere),
+    Disk(Disk),
+    Cylinder(Cylinder),
+    Rectangle(Rectangle),
+}
+
+pub fn swaps_handedness(obj_to_world: &na::Projective3<f32>) -> bool {
+    obj_to_world
+        .matrix()
+        .fixed_slice::<na::U3, na::U3>(0, 0)
+        .determinant()
+        < 0.0
+}
+
+/// normals, and their derivatives, go to world space by the inverse transpose
+fn normal_to_world(world_to_obj: &na::Projective3<f32>) -> na::Matrix3<f32> {
+    world_to_obj
+        .matrix()
+        .fixed_slice::<na::U3, na::U3>(0, 0)
+        .transpose()
+}
+
+/// solid angle density of sampling the shape uniformly by area, as seen from `reference`
+fn area_pdf_at_point<S: ShapeInterface + ?Sized>(
+    shape: &S,
+    reference: &Interaction,
+    wi: &na::Vector3<f32>,
+) -> f32 {
+    let ray = reference.spawn_ray(&wi);
+    let mut t_hit = 0.0;
+    let mut isect_light = SurfaceMediumInteraction::default();
+    if !shape.intersect(&ray, &mut t_hit, &mut isect_light) {
+        return 0.0;
+    }
+
+    (reference.p - isect_light.general.p).norm_squared()
+        / (isect_light.general.n.dot(&-wi).abs() * shape.area())
+}
+
 pub struct Triangle {
     mesh: Arc<TriangleMesh>,
     indices: [u32; 3],
@@ -31,6 +119,15 @@ impl Triangle {
         }
     }
 
+    pub fn mesh(&self) -> &Arc<TriangleMesh> {
+        &self.mesh
+    }
+
+    /// indices of the corners in the vertices of the mesh
+    pub fn indices(&self) -> [u32; 3] {
+        self.indices
+    }
+
     pub fn get_uvs(&self) -> [na::Point2<f32>; 3] {
         if !self.mesh.uv.is_empty() {
             [
@@ -47,36 +144,10 @@ impl Triangle {
         }
     }
 
-    pub fn sample_at_point(
-        &self,
-        _reference: &Interaction,
-        u: &na::Point2<f32>,
-    ) -> SurfaceMediumInteraction {
-        self.sample(&u)
-    }
-
-    pub fn pdf(&self, _it: &Interaction) -> f32 {
-        1.0 / self.area()
-    }
-
-    pub fn pdf_at_point(&self, reference: &Interaction, wi: &na::Vector3<f32>) -> f32 {
-        let ray = reference.spawn_ray(&wi);
-        let mut t_hit = 0.0;
-        let mut isect_light = SurfaceMediumInteraction::default();
-        if !self.intersect(&ray, &mut t_hit, &mut isect_light) {
-            return 0.0;
-        }
-
-        (reference.p - isect_light.general.p).norm_squared()
-            / (isect_light.general.n.dot(&-wi).abs() * self.area())
-    }
-
-    pub fn intersect<'a>(
-        &'a self,
-        r: &Ray,
-        t_hit: &mut f32,
-        isect: &mut SurfaceMediumInteraction<'a>,
-    ) -> bool {
+    /// barycentrics and distance of the hit of the ray, by the watertight test of woop et al. the
+    /// ray is sheared to run down +z so that edges shared by neighbouring triangles evaluate
+    /// exactly alike, and hits closer than the error bound of the distance are rejected
+    fn watertight_hit(&self, r: &Ray) -> Option<(f32, f32, f32, f32)> {
         // get triangle vertices
         let p0 = &self.mesh.pos[self.indices[0] as usize];
         let p1 = &self.mesh.pos[self.indices[1] as usize];
@@ -135,11 +206,11 @@ impl Triangle {
 
         // Perform triangle edge and determinant tests
         if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
-            return false;
+            return None;
         }
         let det = e0 + e1 + e2;
         if det == 0.0 {
-            return false;
+            return None;
         }
 
         // Compute scaled hit distance to triangle and test against ray t range
@@ -148,9 +219,9 @@ impl Triangle {
         p2t.z *= sz;
         let t_scaled = e0 * p0t.z + e1 * p1t.z + e2 * p2t.z;
         if det < 0.0 && (t_scaled >= 0.0 || t_scaled < r.t_max * det) {
-            return false;
+            return None;
         } else if det > 0.0 && (t_scaled <= 0.0 || t_scaled > r.t_max * det) {
-            return false;
+            return None;
         }
 
         // Compute barycentric coordinates and t value for triangle intersection
@@ -181,9 +252,19 @@ impl Triangle {
             * (gamma(3) * max_e * max_z_t + delta_e * max_z_t + delta_z * max_e)
             * inv_det.abs();
         if t <= delta_t {
-            return false;
+            return None;
         }
 
+        Some((b0, b1, b2, t))
+    }
+
+    /// partial derivatives of the position by the uvs, or an arbitrary frame of the triangle
+    /// plane when the uvs are degenerate. `None` for degenerate triangles
+    fn partial_derivatives(&self) -> Option<(na::Vector3<f32>, na::Vector3<f32>)> {
+        let p0 = &self.mesh.pos[self.indices[0] as usize];
+        let p1 = &self.mesh.pos[self.indices[1] as usize];
+        let p2 = &self.mesh.pos[self.indices[2] as usize];
+
         // Compute triangle partial derivatives
         let mut dpdu = na::Vector3::new(0.0, 0.0, 0.0);
         let mut dpdv = na::Vector3::new(0.0, 0.0, 0.0);
@@ -208,12 +289,85 @@ impl Triangle {
             if ng.norm_squared() == 0.0 {
                 // The triangle is actually degenerate; the intersection is
                 // bogus.
-                return false;
+                return None;
             }
 
             coordinate_system(&ng.normalize(), &mut dpdu, &mut dpdv);
         }
 
+        Some((dpdu, dpdv))
+    }
+
+    /// whether the alpha mask cuts the surface away at the barycentrics `b`
+    fn alpha_masked(
+        &self,
+        r: &Ray,
+        b: [f32; 3],
+        dpdu: &na::Vector3<f32>,
+        dpdv: &na::Vector3<f32>,
+    ) -> bool {
+        let alpha_mask = match self.mesh.alpha_mask.as_ref() {
+            Some(alpha_mask) => alpha_mask,
+            None => return false,
+        };
+        let p0 = &self.mesh.pos[self.indices[0] as usize];
+        let p1 = &self.mesh.pos[self.indices[1] as usize];
+        let p2 = &self.mesh.pos[self.indices[2] as usize];
+        let uv = self.get_uvs();
+        let p_hit = b[0] * p0.coords + b[1] * p1.coords + b[2] * p2.coords;
+        let uv_hit = b[0] * uv[0].coords + b[1] * uv[1].coords + b[2] * uv[2].coords;
+
+        let isect_local = SurfaceMediumInteraction::new(
+            &na::Point3::from(p_hit),
+            &glm::zero(),
+            &na::Point2::from(uv_hit),
+            &-r.d,
+            dpdu,
+            dpdv,
+            &glm::zero(),
+            &glm::zero(),
+            0.0,
+            Some(self),
+        );
+        alpha_mask.evaluate(&isect_local) == 0.0
+    }
+
+    /// barycentrics and distance where `r` crosses the triangle, `None` when it misses
+    pub fn ray_hit(&self, r: &Ray) -> Option<([f32; 3], f32)> {
+        self.watertight_hit(r)
+            .map(|(b0, b1, b2, t)| ([b0, b1, b2], t))
+    }
+
+    /// point at barycentrics `b`
+    pub fn point(&self, b: &[f32; 3]) -> na::Point3<f32> {
+        let p0 = &self.mesh.pos[self.indices[0] as usize];
+        let p1 = &self.mesh.pos[self.indices[1] as usize];
+        let p2 = &self.mesh.pos[self.indices[2] as usize];
+        na::Point3::from(b[0] * p0.coords + b[1] * p1.coords + b[2] * p2.coords)
+    }
+
+    /// surface at barycentrics `b` as seen along `r`, `false` when the triangle is degenerate or
+    /// the alpha mask cuts it away there
+    pub fn interaction<'a>(
+        &'a self,
+        r: &Ray,
+        b: [f32; 3],
+        isect: &mut SurfaceMediumInteraction<'a>,
+    ) -> bool {
+        let [b0, b1, b2] = b;
+        let p0 = &self.mesh.pos[self.indices[0] as usize];
+        let p1 = &self.mesh.pos[self.indices[1] as usize];
+        let p2 = &self.mesh.pos[self.indices[2] as usize];
+        let dp02 = p0 - p2;
+        let dp12 = p1 - p2;
+        let uv = self.get_uvs();
+
+        let (dpdu, dpdv) = match self.partial_derivatives() {
+            Some(derivatives) => derivatives,
+            // the triangle is actually degenerate, the intersection is bogus
+            None => return false,
+        };
+
         // Compute error bounds for triangle intersection
         let x_abs_sum = (b0 * p0.x).abs() + (b1 * p1.x).abs() + (b2 * p2.x).abs();
         let y_abs_sum = (b0 * p0.y).abs() + (b1 * p1.y).abs() + (b2 * p2.y).abs();
@@ -224,23 +378,8 @@ impl Triangle {
         let p_hit = b0 * p0.coords + b1 * p1.coords + b2 * p2.coords;
         let uv_hit = b0 * uv[0].coords + b1 * uv[1].coords + b2 * uv[2].coords;
 
-        // Test intersection against alpha texture, if present
-        if let Some(alpha_mask) = self.mesh.alpha_mask.as_ref() {
-            let isect_local = SurfaceMediumInteraction::new(
-                &na::Point3::from(p_hit),
-                &glm::zero(),
-                &na::Point2::from(uv_hit),
-                &-r.d,
-                &dpdu,
-                &dpdv,
-                &glm::zero(),
-                &glm::zero(),
-                0.0,
-                self,
-            );
-            if alpha_mask.evaluate(&isect_local) == 0.0 {
-                return false;
-            }
+        if self.alpha_masked(r, [b0, b1, b2], &dpdu, &dpdv) {
+            return false;
         }
 
         // Fill in SurfaceInteraction from triangle hit
@@ -254,9 +393,17 @@ impl Triangle {
             &glm::zero(),
             &glm::zero(),
             0.0,
-            self,
+            Some(self),
         );
 
+        if !self.mesh.colors.is_empty() {
+            let c0 = &self.mesh.colors[self.indices[0] as usize];
+            let c1 = &self.mesh.colors[self.indices[1] as usize];
+            let c2 = &self.mesh.colors[self.indices[2] as usize];
+            let color = b0 * c0 + b1 * c1 + b2 * c2;
+            isect.color = Some(Spectrum::from_floats(color.x, color.y, color.z));
+        }
+
         // Override surface normal in isect for triangle
         (*isect).general.n = glm::normalize(&glm::cross(&dp02, &dp12));
         (*isect).shading.n = (*isect).general.n;
@@ -355,182 +502,57 @@ impl Triangle {
             isect.set_shading_geometry(&ss, &ts, &dndu, &dndv, true);
         }
 
-        *t_hit = t;
-        return true;
+        true
     }
+}
 
-    pub fn intersect_p(&self, r: &Ray) -> bool {
-        // get triangle vertices
-        let p0 = &self.mesh.pos[self.indices[0] as usize];
-        let p1 = &self.mesh.pos[self.indices[1] as usize];
-        let p2 = &self.mesh.pos[self.indices[2] as usize];
-
-        // perform ray-triangle intersection test
-
-        // transform triangle vertices to ray coordinate space
-
-        // translate vertices based on ray origin
-        let mut p0t = p0 - r.o;
-        let mut p1t = p1 - r.o;
-        let mut p2t = p2 - r.o;
-        // permute components of triangle vertices and ray direction
-        let kz = max_dimension(&glm::abs(&r.d));
-        let mut kx = kz + 1;
-        if kx == 3 {
-            kx = 0;
-        }
-        let mut ky = kx + 1;
-        if ky == 3 {
-            ky = 0;
-        }
-        let d = permute(&r.d, kx, ky, kz);
-        p0t = permute(&p0t, kx, ky, kz);
-        p1t = permute(&p1t, kx, ky, kz);
-        p2t = permute(&p2t, kx, ky, kz);
-        // apply shear transformation to translated vertex positions
-        let sx = -d.x / d.z;
-        let sy = -d.y / d.z;
-        let sz = 1.0f32 / d.z;
-
-        p0t.x += sx * p0t.z;
-        p0t.y += sy * p0t.z;
-        p1t.x += sx * p1t.z;
-        p1t.y += sy * p1t.z;
-        p2t.x += sx * p2t.z;
-        p2t.y += sy * p2t.z;
-
-        // compute edge function coefficients e0, e1, and e2
-        let mut e0 = p1t.x * p2t.y - p1t.y * p2t.x;
-        let mut e1 = p2t.x * p0t.y - p2t.y * p0t.x;
-        let mut e2 = p0t.x * p1t.y - p0t.y * p1t.x;
-
-        if e0 == 0.0 || e1 == 0.0 || e2 == 0.0 {
-            let p2txp1ty = p2t.x as f64 * p1t.y as f64;
-            let p2typ1tx = p2t.y as f64 * p1t.x as f64;
-            e0 = (p2typ1tx - p2txp1ty) as f32;
-            let p0txp2ty = p0t.x as f64 * p2t.y as f64;
-            let p0typ2tx = p0t.y as f64 * p2t.x as f64;
-            e1 = (p0typ2tx - p0txp2ty) as f32;
-            let p1txp0ty = p1t.x as f64 * p0t.y as f64;
-            let p1typ0tx = p1t.y as f64 * p0t.x as f64;
-            e2 = (p1typ0tx - p1txp0ty) as f32;
-        }
-
-        // Perform triangle edge and determinant tests
-        if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
-            return false;
-        }
-        let det = e0 + e1 + e2;
-        if det == 0.0 {
-            return false;
-        }
-
-        // Compute scaled hit distance to triangle and test against ray t range
-        p0t.z *= sz;
-        p1t.z *= sz;
-        p2t.z *= sz;
-        let t_scaled = e0 * p0t.z + e1 * p1t.z + e2 * p2t.z;
-        if det < 0.0 && (t_scaled >= 0.0 || t_scaled < r.t_max * det) {
-            return false;
-        } else if det > 0.0 && (t_scaled <= 0.0 || t_scaled > r.t_max * det) {
-            return false;
-        }
-
-        // Compute barycentric coordinates and t value for triangle intersection
-        let inv_det = 1.0 / det;
-        let b0 = e0 * inv_det;
-        let b1 = e1 * inv_det;
-        let b2 = e2 * inv_det;
-        let t = t_scaled * inv_det;
-
-        // ensure that computed triangle t is conservatively greater than zero
-
-        // compute delta_z term for triangle t error bounds
-        let max_z_t = glm::comp_max(&glm::abs(&glm::vec3(p0t.z, p1t.z, p2t.z)));
-        let delta_z = gamma(3) * max_z_t;
-
-        // compute delta_x and delta_y terms for triangle t error bounds
-        let max_x_t = glm::comp_max(&glm::abs(&glm::vec3(p0t.x, p1t.x, p2t.x)));
-        let max_y_t = glm::comp_max(&glm::abs(&glm::vec3(p0t.y, p1t.y, p2t.y)));
-        let delta_x = gamma(5) * (max_x_t + max_z_t);
-        let delta_y = gamma(5) * (max_y_t + max_z_t);
-
-        // compute delta_e term for triangle t error bounds
-        let delta_e = 2.0 * (gamma(2) * max_x_t * max_y_t + delta_y * max_x_t + delta_x * max_y_t);
-
-        // compute delta_t term for triangle t error bounds and check t
-        let max_e = glm::comp_max(&glm::abs(&glm::vec3(e0, e1, e2)));
-        let delta_t = 3.0
-            * (gamma(3) * max_e * max_z_t + delta_e * max_z_t + delta_z * max_e)
-            * inv_det.abs();
-        if t <= delta_t {
+impl ShapeInterface for Triangle {
+    fn intersect<'a>(
+        &'a self,
+        r: &Ray,
+        t_hit: &mut f32,
+        isect: &mut SurfaceMediumInteraction<'a>,
+    ) -> bool {
+        let (b, t) = match self.ray_hit(r) {
+            Some(hit) => hit,
+            None => return false,
+        };
+        if !self.interaction(r, b, isect) {
             return false;
         }
 
-        // Test shadow ray intersection against alpha texture, if present
-        if let Some(alpha_mask) = self.mesh.alpha_mask.as_ref() {
-            // Compute triangle partial derivatives
-            let mut dpdu = na::Vector3::new(0.0, 0.0, 0.0);
-            let mut dpdv = na::Vector3::new(0.0, 0.0, 0.0);
-            let uv = self.get_uvs();
-
-            // Compute deltas for triangle partial derivatives
-            let duv02 = uv[0] - uv[2];
-            let duv12 = uv[1] - uv[2];
-            let dp02 = p0 - p2;
-            let dp12 = p1 - p2;
-            let determinant = duv02[0] * duv12[1] - duv02[1] * duv12[0];
-            let degenerate_uv = determinant.abs() < 1e-8;
-
-            if !degenerate_uv {
-                let invdet = 1.0 / determinant;
-                dpdu = (duv12[1] * dp02 - duv02[1] * dp12) * invdet;
-                dpdv = (-duv12[0] * dp02 + duv02[0] * dp12) * invdet;
-            }
-            if degenerate_uv || dpdu.cross(&dpdv).norm_squared() == 0.0 {
-                // Handle zero determinant for triangle partial derivative matrix
-                let ng = (p2 - p0).cross(&(p1 - p0));
-                if ng.norm_squared() == 0.0 {
-                    // The triangle is actually degenerate; the intersection is
-                    // bogus.
-                    return false;
-                }
-
-                coordinate_system(&ng.normalize(), &mut dpdu, &mut dpdv);
-            }
+        *t_hit = t;
+        return true;
+    }
 
-            // Interpolate (u,v) parametric coordinates and hit point
-            let p_hit = b0 * p0.coords + b1 * p1.coords + b2 * p2.coords;
-            let uv_hit = b0 * uv[0].coords + b1 * uv[1].coords + b2 * uv[2].coords;
-
-            let isect_local = SurfaceMediumInteraction::new(
-                &na::Point3::from(p_hit),
-                &glm::zero(),
-                &na::Point2::from(uv_hit),
-                &-r.d,
-                &dpdu,
-                &dpdv,
-                &glm::zero(),
-                &glm::zero(),
-                0.0,
-                self,
-            );
-            if alpha_mask.evaluate(&isect_local) == 0.0 {
+    fn intersect_p(&self, r: &Ray) -> bool {
+        let (b0, b1, b2, _) = match self.watertight_hit(r) {
+            Some(hit) => hit,
+            None => return false,
+        };
+
+        // shadow rays only need the derivatives for the alpha mask lookup
+        if self.mesh.alpha_mask.is_some() {
+            let (dpdu, dpdv) = match self.partial_derivatives() {
+                Some(derivatives) => derivatives,
+                None => return false,
+            };
+            if self.alpha_masked(r, [b0, b1, b2], &dpdu, &dpdv) {
                 return false;
             }
         }
 
-        return true;
+        true
     }
 
-    pub fn world_bound(&self) -> Bounds3 {
+    fn world_bound(&self) -> Bounds3 {
         let p0 = self.mesh.pos[self.indices[0] as usize];
         let p1 = self.mesh.pos[self.indices[1] as usize];
         let p2 = self.mesh.pos[self.indices[2] as usize];
         Bounds3::union_p(&Bounds3::new(p0, p1), &p2)
     }
 
-    pub fn area(&self) -> f32 {
+    fn area(&self) -> f32 {
         let p0 = self.mesh.pos[self.indices[0] as usize];
         let p1 = self.mesh.pos[self.indices[1] as usize];
         let p2 = self.mesh.pos[self.indices[2] as usize];
@@ -538,7 +560,7 @@ impl Triangle {
         0.5 * (p1 - p0).cross(&(p2 - p0)).norm()
     }
 
-    pub fn sample(&self, u: &na::Point2<f32>) -> SurfaceMediumInteraction {
+    fn sample(&self, u: &na::Point2<f32>) -> SurfaceMediumInteraction {
         let b = uniform_sample_triangle(&u);
         let p0 = &self.mesh.pos[self.indices[0] as usize];
         let p1 = &self.mesh.pos[self.indices[1] as usize];
@@ -626,15 +648,15 @@ impl TriangleMesh {
 pub fn triangles_from_mesh(
     mesh: &Arc<TriangleMesh>,
     transform_swaps_handedness: bool,
-) -> Vec<Arc<Triangle>> {
+) -> Vec<Arc<Shape>> {
     let mut shapes = Vec::new();
     for indices in &mesh.indices {
-        shapes.push(Arc::new(Triangle::new(
+        shapes.push(Arc::new(Shape::Triangle(Triangle::new(
             mesh.clone(),
             [indices[0], indices[1], indices[2]],
             false,
             transform_swaps_handedness,
-        )));
+        ))));
     }
 
     shapes
@@ -0,0 +1,127 @@
+use super::{normal_to_world, swaps_handedness, ShapeInterface};
+use crate::common::bounds::Bounds3;
+use crate::common::math::gamma;
+use crate::common::ray::Ray;
+use crate::pathtracer::{interaction::Interaction, SurfaceMediumInteraction};
+
+/// the square [-1, 1] x [-1, 1] in the plane z = 0 of its object space facing +z, like mitsuba's
+/// rectangle. any affine `obj_to_world` keeps the sampling uniform by area
+pub struct Rectangle {
+    obj_to_world: na::Projective3<f32>,
+    world_to_obj: na::Projective3<f32>,
+    reverse_orientation: bool,
+    transform_swaps_handedness: bool,
+    area: f32,
+}
+
+impl Rectangle {
+    pub fn new(obj_to_world: &na::Projective3<f32>, reverse_orientation: bool) -> Self {
+        Self {
+            obj_to_world: *obj_to_world,
+            world_to_obj: obj_to_world.inverse(),
+            reverse_orientation,
+            transform_swaps_handedness: swaps_handedness(obj_to_world),
+            area: (obj_to_world * na::Vector3::new(2.0, 0.0, 0.0))
+                .cross(&(obj_to_world * na::Vector3::new(0.0, 2.0, 0.0)))
+                .norm(),
+        }
+    }
+
+    fn flip_normals(&self) -> bool {
+        self.reverse_orientation ^ self.transform_swaps_handedness
+    }
+
+    fn uv(p: &na::Point3<f32>) -> na::Point2<f32> {
+        na::Point2::new(0.5 * (p.x + 1.0), 0.5 * (p.y + 1.0))
+    }
+
+    /// hit of the ray in object space, its distance and position
+    fn hit(&self, r: &Ray) -> Option<(f32, na::Point3<f32>)> {
+        let o = self.world_to_obj * r.o;
+        let d = self.world_to_obj * r.d;
+        if d.z == 0.0 {
+            return None;
+        }
+
+        let t = -o.z / d.z;
+        if t <= 0.0 || t >= r.t_max {
+            return None;
+        }
+
+        let mut p = o + t * d;
+        p.z = 0.0;
+        if p.x.abs() > 1.0 || p.y.abs() > 1.0 {
+            return None;
+        }
+
+        Some((t, p))
+    }
+}
+
+impl ShapeInterface for Rectangle {
+    fn intersect<'a>(
+        &'a self,
+        r: &Ray,
+        t_hit: &mut f32,
+        isect: &mut SurfaceMediumInteraction<'a>,
+    ) -> bool {
+        let (t, p) = match self.hit(r) {
+            Some(hit) => hit,
+            None => return false,
+        };
+
+        let p_world = self.obj_to_world * p;
+        *isect = SurfaceMediumInteraction::new(
+            &p_world,
+            &(gamma(5) * p_world.coords.abs()),
+            &Self::uv(&p),
+            &-r.d,
+            &(self.obj_to_world * na::Vector3::new(2.0, 0.0, 0.0)),
+            &(self.obj_to_world * na::Vector3::new(0.0, 2.0, 0.0)),
+            &glm::zero(),
+            &glm::zero(),
+            0.0,
+            None,
+        );
+        if self.flip_normals() {
+            isect.general.n *= -1.0;
+            isect.shading.n *= -1.0;
+        }
+
+        *t_hit = t;
+        true
+    }
+
+    fn intersect_p(&self, r: &Ray) -> bool {
+        self.hit(r).is_some()
+    }
+
+    fn world_bound(&self) -> Bounds3 {
+        Bounds3::new(
+            na::Point3::new(-1.0, -1.0, 0.0),
+            na::Point3::new(1.0, 1.0, 0.0),
+        )
+        .transform(&self.obj_to_world)
+    }
+
+    fn area(&self) -> f32 {
+        self.area
+    }
+
+    fn sample(&self, u: &na::Point2<f32>) -> SurfaceMediumInteraction {
+        let p = na::Point3::new(2.0 * u[0] - 1.0, 2.0 * u[1] - 1.0, 0.0);
+        let mut it = Interaction::default();
+        it.p = self.obj_to_world * p;
+        it.n = (normal_to_world(&self.world_to_obj) * na::Vector3::z()).normalize();
+        if self.reverse_orientation {
+            it.n *= -1.0;
+        }
+        it.p_error = gamma(5) * it.p.coords.abs();
+
+        SurfaceMediumInteraction {
+            general: it,
+            uv: Self::uv(&p),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,326 @@
+use super::{area_pdf_at_point, normal_to_world, swaps_handedness, ShapeInterface};
+use crate::common::bounds::Bounds3;
+use crate::common::math::{coordinate_system, gamma, offset_ray_origin, spherical_direction};
+use crate::common::ray::Ray;
+use crate::pathtracer::{
+    interaction::Interaction,
+    sampling::{uniform_cone_pdf, uniform_sample_sphere},
+    SurfaceMediumInteraction,
+};
+
+/// below this the cone of directions towards a sphere is too narrow for the usual sampling in
+/// single precision, it is approximated by a small angle expansion
+const SMALL_CONE_SIN2: f32 = 0.00068523;
+
+/// sphere of `radius` around the origin of its object space, clipped to `z_min..z_max` and swept
+/// around z up to `phi_max` when partial. sampling by area assumes `obj_to_world` doesn't scale
+pub struct Sphere {
+    obj_to_world: na::Projective3<f32>,
+    world_to_obj: na::Projective3<f32>,
+    reverse_orientation: bool,
+    transform_swaps_handedness: bool,
+    radius: f32,
+    z_min: f32,
+    z_max: f32,
+    theta_z_min: f32,
+    theta_z_max: f32,
+    phi_max: f32,
+}
+
+impl Sphere {
+    pub fn new(
+        obj_to_world: &na::Projective3<f32>,
+        radius: f32,
+        reverse_orientation: bool,
+    ) -> Self {
+        Self {
+            obj_to_world: *obj_to_world,
+            world_to_obj: obj_to_world.inverse(),
+            reverse_orientation,
+            transform_swaps_handedness: swaps_handedness(obj_to_world),
+            radius,
+            z_min: -radius,
+            z_max: radius,
+            theta_z_min: std::f32::consts::PI,
+            theta_z_max: 0.0,
+            phi_max: 2.0 * std::f32::consts::PI,
+        }
+    }
+
+    /// partial sphere between the heights `z_min` and `z_max`, swept up to `phi_max` degrees
+    pub fn with_clipping(mut self, z_min: f32, z_max: f32, phi_max: f32) -> Self {
+        let (z_min, z_max) = (
+            z_min.min(z_max).clamp(-self.radius, self.radius),
+            z_min.max(z_max).clamp(-self.radius, self.radius),
+        );
+        self.z_min = z_min;
+        self.z_max = z_max;
+        self.theta_z_min = (z_min / self.radius).clamp(-1.0, 1.0).acos();
+        self.theta_z_max = (z_max / self.radius).clamp(-1.0, 1.0).acos();
+        self.phi_max = phi_max.clamp(0.0, 360.0).to_radians();
+        self
+    }
+
+    fn center(&self) -> na::Point3<f32> {
+        self.obj_to_world * na::Point3::origin()
+    }
+
+    fn is_partial(&self) -> bool {
+        self.z_min > -self.radius
+            || self.z_max < self.radius
+            || self.phi_max < 2.0 * std::f32::consts::PI
+    }
+
+    fn flip_normals(&self) -> bool {
+        self.reverse_orientation ^ self.transform_swaps_handedness
+    }
+
+    fn phi(p: &na::Point3<f32>) -> f32 {
+        let phi = p.y.atan2(p.x);
+        if phi < 0.0 {
+            phi + 2.0 * std::f32::consts::PI
+        } else {
+            phi
+        }
+    }
+
+    fn uv(&self, p: &na::Point3<f32>) -> na::Point2<f32> {
+        let theta = (p.z / self.radius).clamp(-1.0, 1.0).acos();
+        na::Point2::new(
+            Self::phi(p) / self.phi_max,
+            (theta - self.theta_z_min) / (self.theta_z_max - self.theta_z_min),
+        )
+    }
+
+    fn clipped(&self, p: &na::Point3<f32>, phi: f32) -> bool {
+        (self.z_min > -self.radius && p.z < self.z_min)
+            || (self.z_max < self.radius && p.z > self.z_max)
+            || phi > self.phi_max
+    }
+
+    /// closest unclipped hit of the ray in object space, its distance, position and angle
+    fn hit(&self, r: &Ray) -> Option<(f32, na::Point3<f32>, f32)> {
+        let o = (self.world_to_obj * r.o).coords.cast::<f64>();
+        let d = (self.world_to_obj * r.d).cast::<f64>();
+        let radius = self.radius as f64;
+
+        // solved in double precision so rounding in the discriminant doesn't let grazing rays
+        // slip through
+        let a = d.norm_squared();
+        let b = 2.0 * d.dot(&o);
+        let c = o.norm_squared() - radius * radius;
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let root = discriminant.sqrt();
+        let q = if b < 0.0 {
+            -0.5 * (b - root)
+        } else {
+            -0.5 * (b + root)
+        };
+        let (mut t0, mut t1) = (q / a, c / q);
+        if t0 > t1 {
+            std::mem::swap(&mut t0, &mut t1);
+        }
+
+        for &t in [t0, t1].iter() {
+            let t = t as f32;
+            if t <= 0.0 || t >= r.t_max {
+                continue;
+            }
+
+            // refine the hit onto the surface
+            let p = self.world_to_obj * (r.o + t * r.d);
+            let mut p = na::Point3::from(p.coords * (self.radius / p.coords.norm()));
+            if p.x == 0.0 && p.y == 0.0 {
+                p.x = 1e-5 * self.radius;
+            }
+            let phi = Self::phi(&p);
+            if !self.clipped(&p, phi) {
+                return Some((t, p, phi));
+            }
+        }
+
+        None
+    }
+}
+
+impl ShapeInterface for Sphere {
+    fn intersect<'a>(
+        &'a self,
+        r: &Ray,
+        t_hit: &mut f32,
+        isect: &mut SurfaceMediumInteraction<'a>,
+    ) -> bool {
+        let (t, p, phi) = match self.hit(r) {
+            Some(hit) => hit,
+            None => return false,
+        };
+
+        // parametric derivatives of the position and of the normal by the weingarten equations
+        let theta_range = self.theta_z_max - self.theta_z_min;
+        let z_radius = (p.x * p.x + p.y * p.y).sqrt();
+        let (cos_phi, sin_phi) = (p.x / z_radius, p.y / z_radius);
+        let cos_theta = (p.z / self.radius).clamp(-1.0, 1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let dpdu = na::Vector3::new(-self.phi_max * p.y, self.phi_max * p.x, 0.0);
+        let dpdv =
+            theta_range * na::Vector3::new(p.z * cos_phi, p.z * sin_phi, -self.radius * sin_theta);
+        let d2pduu = -self.phi_max * self.phi_max * na::Vector3::new(p.x, p.y, 0.0);
+        let d2pduv = theta_range * p.z * self.phi_max * na::Vector3::new(-sin_phi, cos_phi, 0.0);
+        let d2pdvv = -theta_range * theta_range * p.coords;
+
+        let e1 = dpdu.dot(&dpdu);
+        let f1 = dpdu.dot(&dpdv);
+        let g1 = dpdv.dot(&dpdv);
+        let n = dpdu.cross(&dpdv).normalize();
+        let e2 = n.dot(&d2pduu);
+        let f2 = n.dot(&d2pduv);
+        let g2 = n.dot(&d2pdvv);
+        let inv_egf2 = 1.0 / (e1 * g1 - f1 * f1);
+        let dndu = (f2 * f1 - e2 * g1) * inv_egf2 * dpdu + (e2 * f1 - f2 * e1) * inv_egf2 * dpdv;
+        let dndv = (g2 * f1 - f2 * g1) * inv_egf2 * dpdu + (f2 * f1 - g2 * e1) * inv_egf2 * dpdv;
+
+        let normal_to_world = normal_to_world(&self.world_to_obj);
+        let p_world = self.obj_to_world * p;
+        let p_error = gamma(5) * p_world.coords.abs();
+
+        *isect = SurfaceMediumInteraction::new(
+            &p_world,
+            &p_error,
+            &na::Point2::new(
+                phi / self.phi_max,
+                (cos_theta.acos() - self.theta_z_min) / theta_range,
+            ),
+            &-r.d,
+            &(self.obj_to_world * dpdu),
+            &(self.obj_to_world * dpdv),
+            &(normal_to_world * dndu),
+            &(normal_to_world * dndv),
+            0.0,
+            None,
+        );
+        if self.flip_normals() {
+            isect.general.n *= -1.0;
+            isect.shading.n *= -1.0;
+        }
+
+        *t_hit = t;
+        true
+    }
+
+    fn intersect_p(&self, r: &Ray) -> bool {
+        self.hit(r).is_some()
+    }
+
+    fn world_bound(&self) -> Bounds3 {
+        Bounds3::new(
+            na::Point3::new(-self.radius, -self.radius, self.z_min),
+            na::Point3::new(self.radius, self.radius, self.z_max),
+        )
+        .transform(&self.obj_to_world)
+    }
+
+    fn area(&self) -> f32 {
+        self.phi_max * self.radius * (self.z_max - self.z_min)
+    }
+
+    /// partial spheres are sampled uniformly in height and angle, which is uniform in area on a
+    /// sphere as well
+    fn sample(&self, u: &na::Point2<f32>) -> SurfaceMediumInteraction {
+        let p = if self.is_partial() {
+            let z = self.z_min + u[0] * (self.z_max - self.z_min);
+            let z_radius = (self.radius * self.radius - z * z).max(0.0).sqrt();
+            let phi = u[1] * self.phi_max;
+            na::Point3::new(z_radius * phi.cos(), z_radius * phi.sin(), z)
+        } else {
+            na::Point3::from(self.radius * uniform_sample_sphere(&u))
+        };
+        let mut it = Interaction::default();
+        it.p = self.obj_to_world * p;
+        it.n = (self.obj_to_world * p.coords).normalize();
+        if self.reverse_orientation {
+            it.n *= -1.0;
+        }
+        it.p_error = gamma(5) * it.p.coords.abs();
+
+        SurfaceMediumInteraction {
+            general: it,
+            uv: self.uv(&p),
+            ..Default::default()
+        }
+    }
+
+    /// samples the cone of directions the sphere subtends from outside, which wastes no samples
+    /// on the far side of the sphere. the cone covers the clipped away parts of partial spheres
+    /// too, so those are sampled by area
+    fn sample_at_point(
+        &self,
+        reference: &Interaction,
+        u: &na::Point2<f32>,
+    ) -> SurfaceMediumInteraction {
+        let p_center = self.center();
+        let p_origin = offset_ray_origin(
+            &reference.p,
+            &reference.p_error,
+            &reference.n,
+            &(p_center - reference.p),
+        );
+        if self.is_partial() || (p_origin - p_center).norm_squared() <= self.radius * self.radius {
+            return self.sample(&u);
+        }
+
+        let dc = (reference.p - p_center).norm();
+        let wc = (p_center - reference.p) / dc;
+        let mut wc_x = glm::zero();
+        let mut wc_y = glm::zero();
+        coordinate_system(&wc, &mut wc_x, &mut wc_y);
+
+        // angle of the sampled direction from the cone axis
+        let sin_theta_max = self.radius / dc;
+        let sin2_theta_max = sin_theta_max * sin_theta_max;
+        let cos_theta_max = (1.0 - sin2_theta_max).max(0.0).sqrt();
+        let mut cos_theta = (cos_theta_max - 1.0) * u[0] + 1.0;
+        let mut sin2_theta = 1.0 - cos_theta * cos_theta;
+        if sin2_theta_max < SMALL_CONE_SIN2 {
+            sin2_theta = sin2_theta_max * u[0];
+            cos_theta = (1.0 - sin2_theta).sqrt();
+        }
+
+        // angle of the point hit on the sphere from its center
+        let cos_alpha = sin2_theta / sin_theta_max
+            + cos_theta * (1.0 - sin2_theta / sin2_theta_max).max(0.0).sqrt();
+        let sin_alpha = (1.0 - cos_alpha * cos_alpha).max(0.0).sqrt();
+        let phi = u[1] * 2.0 * std::f32::consts::PI;
+        let n = spherical_direction(sin_alpha, cos_alpha, phi, &-wc_x, &-wc_y, &-wc);
+
+        let mut it = Interaction::default();
+        it.p = p_center + self.radius * n;
+        it.n = if self.reverse_orientation { -n } else { n };
+        it.p_error = gamma(5) * it.p.coords.abs();
+
+        SurfaceMediumInteraction {
+            uv: self.uv(&(self.world_to_obj * it.p)),
+            general: it,
+            ..Default::default()
+        }
+    }
+
+    fn pdf_at_point(&self, reference: &Interaction, wi: &na::Vector3<f32>) -> f32 {
+        let p_center = self.center();
+        let p_origin = offset_ray_origin(
+            &reference.p,
+            &reference.p_error,
+            &reference.n,
+            &(p_center - reference.p),
+        );
+        if self.is_partial() || (p_origin - p_center).norm_squared() <= self.radius * self.radius {
+            return area_pdf_at_point(self, reference, wi);
+        }
+
+        let sin2_theta_max = self.radius * self.radius / (reference.p - p_center).norm_squared();
+        let cos_theta_max = (1.0 - sin2_theta_max).max(0.0).sqrt();
+        uniform_cone_pdf(cos_theta_max)
+    }
+}
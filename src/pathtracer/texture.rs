@@ -1,10 +1,19 @@
-use std::ops::{AddAssign, Mul};
+use std::ops::{Add, Mul};
+use std::sync::Arc;
 
-use super::interaction::SurfaceMediumInteraction;
+use super::{
+    interaction::SurfaceMediumInteraction,
+    noise::{fbm, turbulence},
+    texture_cache::{Texel, TextureCache, TextureTiles},
+};
 use crate::common::{
-    math::abs_mod, math::lerp, math::log2_int, math::RoundUpPow2, spectrum::Spectrum, WrapMode,
+    math::abs_mod, math::lerp, math::log2_int, math::spherical_phi, math::spherical_theta,
+    math::RoundUpPow2, spectrum::Spectrum, WrapMode,
 };
 
+/// exponent sharpening the blend between the three projections of a triplanar texture
+const TRIPLANAR_SHARPNESS: i32 = 4;
+
 pub trait Texture<T> {
     fn evaluate(&self, it: &SurfaceMediumInteraction) -> T;
 }
@@ -28,35 +37,257 @@ impl<T: Copy> Texture<T> for ConstantTexture<T> {
     }
 }
 
+/// color of the vertices around the hit, white on meshes without vertex colors
+pub struct VertexColorTexture;
+
+impl Texture<Spectrum> for VertexColorTexture {
+    fn evaluate(&self, it: &SurfaceMediumInteraction) -> Spectrum {
+        it.color.unwrap_or_else(|| Spectrum::new(1.0))
+    }
+}
+
+/// product of two textures, e.g. a color modulated by a scalar mask
+pub struct ScaleTexture<T1, T2> {
+    tex1: Box<dyn SyncTexture<T1>>,
+    tex2: Box<dyn SyncTexture<T2>>,
+}
+
+impl<T1, T2> ScaleTexture<T1, T2> {
+    pub fn new(tex1: Box<dyn SyncTexture<T1>>, tex2: Box<dyn SyncTexture<T2>>) -> Self {
+        Self { tex1, tex2 }
+    }
+}
+
+impl<T1, T2> Texture<T2> for ScaleTexture<T1, T2>
+where
+    T2: Mul<T1, Output = T2>,
+{
+    fn evaluate(&self, it: &SurfaceMediumInteraction) -> T2 {
+        self.tex2.evaluate(it) * self.tex1.evaluate(it)
+    }
+}
+
+/// blends from `tex1` to `tex2` by the scalar `amount` texture
+pub struct MixTexture<T> {
+    tex1: Box<dyn SyncTexture<T>>,
+    tex2: Box<dyn SyncTexture<T>>,
+    amount: Box<dyn SyncTexture<f32>>,
+}
+
+impl<T> MixTexture<T> {
+    pub fn new(
+        tex1: Box<dyn SyncTexture<T>>,
+        tex2: Box<dyn SyncTexture<T>>,
+        amount: Box<dyn SyncTexture<f32>>,
+    ) -> Self {
+        Self { tex1, tex2, amount }
+    }
+}
+
+impl<T> Texture<T> for MixTexture<T>
+where
+    T: Add<Output = T> + Mul<f32, Output = T>,
+{
+    fn evaluate(&self, it: &SurfaceMediumInteraction) -> T {
+        // masks are mostly zero or one, skip the texture that doesn't contribute
+        let amount = self.amount.evaluate(it);
+        if amount <= 0.0 {
+            self.tex1.evaluate(it)
+        } else if amount >= 1.0 {
+            self.tex2.evaluate(it)
+        } else {
+            self.tex1.evaluate(it) * (1.0 - amount) + self.tex2.evaluate(it) * amount
+        }
+    }
+}
+
+/// remaps a scalar texture through a piecewise linear gradient. `stops` are sorted by their
+/// position, inputs outside of them take the value of the closest stop
+pub struct RampTexture<T> {
+    input: Box<dyn SyncTexture<f32>>,
+    stops: Vec<(f32, T)>,
+}
+
+impl<T> RampTexture<T> {
+    pub fn new(input: Box<dyn SyncTexture<f32>>, mut stops: Vec<(f32, T)>) -> Self {
+        assert!(!stops.is_empty(), "ramp without stops");
+        stops.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        Self { input, stops }
+    }
+}
+
+impl<T> Texture<T> for RampTexture<T>
+where
+    T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+{
+    fn evaluate(&self, it: &SurfaceMediumInteraction) -> T {
+        let x = self.input.evaluate(it);
+        let next = self
+            .stops
+            .iter()
+            .position(|stop| stop.0 > x)
+            .unwrap_or_else(|| self.stops.len());
+        if next == 0 {
+            return self.stops[0].1;
+        }
+        if next == self.stops.len() {
+            return self.stops[next - 1].1;
+        }
+
+        let (x0, v0) = self.stops[next - 1];
+        let (x1, v1) = self.stops[next];
+        let t = (x - x0) / (x1 - x0);
+        v0 * (1.0 - t) + v1 * t
+    }
+}
+
+/// computes the 2d texture coordinates of a hit point and their screen space differentials
+pub trait TextureMapping2D: Send + Sync {
+    fn map(
+        &self,
+        it: &SurfaceMediumInteraction,
+        dst_dx: &mut na::Vector2<f32>,
+        dst_dy: &mut na::Vector2<f32>,
+    ) -> na::Point2<f32>;
+}
+
+/// computes the 3d texture space position of a hit point and its screen space differentials
+pub trait TextureMapping3D: Send + Sync {
+    fn map(
+        &self,
+        it: &SurfaceMediumInteraction,
+        dpdx: &mut na::Vector3<f32>,
+        dpdy: &mut na::Vector3<f32>,
+    ) -> na::Point3<f32>;
+}
+
+/// affine transform from the uvs of the mesh to texture coordinates
+#[derive(Clone, Copy, Debug)]
 pub struct UVMap {
-    su: f32,
-    sv: f32,
-    du: f32,
-    dv: f32,
+    transform: na::Matrix2<f32>,
+    offset: na::Vector2<f32>,
 }
 
 impl UVMap {
     pub fn new(su: f32, sv: f32, du: f32, dv: f32) -> Self {
-        Self { su, sv, du, dv }
+        Self {
+            transform: na::Matrix2::new(su, 0.0, 0.0, sv),
+            offset: na::Vector2::new(du, dv),
+        }
     }
 
-    pub fn map(
+    /// scales, then rotates by `rotation` radians around the origin and finally offsets the uvs,
+    /// the order of `KHR_texture_transform`
+    pub fn from_transform(
+        scale: na::Vector2<f32>,
+        rotation: f32,
+        offset: na::Vector2<f32>,
+    ) -> Self {
+        let (sin, cos) = rotation.sin_cos();
+        let rotation = na::Matrix2::new(cos, sin, -sin, cos);
+        Self {
+            transform: rotation * na::Matrix2::from_diagonal(&scale),
+            offset,
+        }
+    }
+}
+
+impl TextureMapping2D for UVMap {
+    fn map(
         &self,
         it: &SurfaceMediumInteraction,
         dst_dx: &mut na::Vector2<f32>,
         dst_dy: &mut na::Vector2<f32>,
     ) -> na::Point2<f32> {
-        *dst_dx = na::Vector2::new(self.su * it.dudx, self.sv * it.dvdx);
-        *dst_dy = na::Vector2::new(self.su * it.dudy, self.sv * it.dvdy);
+        *dst_dx = self.transform * na::Vector2::new(it.dudx, it.dvdx);
+        *dst_dy = self.transform * na::Vector2::new(it.dudy, it.dvdy);
+
+        na::Point2::from(self.transform * it.uv.coords + self.offset)
+    }
+}
+
+/// projects world space positions onto the plane spanned by `vs` and `vt`, the lengths of the
+/// vectors set the texture scale
+#[derive(Clone, Copy, Debug)]
+pub struct PlanarMapping {
+    vs: na::Vector3<f32>,
+    vt: na::Vector3<f32>,
+    ds: f32,
+    dt: f32,
+}
 
-        na::Point2::new(self.su * it.uv[0] + self.du, self.sv * it.uv[1] + self.dv)
+impl PlanarMapping {
+    pub fn new(vs: na::Vector3<f32>, vt: na::Vector3<f32>, ds: f32, dt: f32) -> Self {
+        Self { vs, vt, ds, dt }
+    }
+}
+
+impl TextureMapping2D for PlanarMapping {
+    fn map(
+        &self,
+        it: &SurfaceMediumInteraction,
+        dst_dx: &mut na::Vector2<f32>,
+        dst_dy: &mut na::Vector2<f32>,
+    ) -> na::Point2<f32> {
+        *dst_dx = na::Vector2::new(self.vs.dot(&it.dpdx), self.vt.dot(&it.dpdx));
+        *dst_dy = na::Vector2::new(self.vs.dot(&it.dpdy), self.vt.dot(&it.dpdy));
+        let p = it.general.p.coords;
+
+        na::Point2::new(self.ds + self.vs.dot(&p), self.dt + self.vt.dot(&p))
+    }
+}
+
+/// latitude and longitude of the direction from the texture space origin to the hit point
+#[derive(Clone, Copy, Debug)]
+pub struct SphericalMapping {
+    world_to_texture: na::Projective3<f32>,
+}
+
+impl SphericalMapping {
+    pub fn new(world_to_texture: na::Projective3<f32>) -> Self {
+        Self { world_to_texture }
+    }
+
+    fn sphere(&self, p: &na::Point3<f32>) -> na::Point2<f32> {
+        let v = (self.world_to_texture * p).coords.normalize();
+        na::Point2::new(
+            spherical_theta(&v) * std::f32::consts::FRAC_1_PI,
+            spherical_phi(&v) * 0.5 * std::f32::consts::FRAC_1_PI,
+        )
+    }
+}
+
+impl TextureMapping2D for SphericalMapping {
+    fn map(
+        &self,
+        it: &SurfaceMediumInteraction,
+        dst_dx: &mut na::Vector2<f32>,
+        dst_dy: &mut na::Vector2<f32>,
+    ) -> na::Point2<f32> {
+        // the differentials are estimated with forward differences, stepping over the seam of
+        // the longitude would make them span the whole texture
+        const DELTA: f32 = 0.1;
+        let st = self.sphere(&it.general.p);
+        let difference = |dp: &na::Vector3<f32>| {
+            let mut dst = (self.sphere(&(it.general.p + DELTA * dp)) - st) / DELTA;
+            if dst[1] > 0.5 {
+                dst[1] = 1.0 - dst[1];
+            } else if dst[1] < -0.5 {
+                dst[1] = -(dst[1] + 1.0);
+            }
+            dst
+        };
+        *dst_dx = difference(&it.dpdx);
+        *dst_dy = difference(&it.dpdy);
+
+        st
     }
 }
 
 pub struct CheckerTexture<T> {
     v1: T,
     v2: T,
-    mapping: UVMap,
+    mapping: Box<dyn TextureMapping2D>,
     log: slog::Logger,
 }
 
@@ -66,10 +297,15 @@ impl<T> CheckerTexture<T> {
         Self {
             v1,
             v2,
-            mapping,
+            mapping: Box::new(mapping),
             log,
         }
     }
+
+    pub fn with_mapping(mut self, mapping: Box<dyn TextureMapping2D>) -> Self {
+        self.mapping = mapping;
+        self
+    }
 }
 
 impl<T: Copy> Texture<T> for CheckerTexture<T> {
@@ -88,12 +324,350 @@ impl<T: Copy> Texture<T> for CheckerTexture<T> {
     }
 }
 
+/// checkerboard alternating between two textures, `scale` checks per unit of uv. the checks are
+/// box filtered over the texture footprint so they don't alias into moire patterns in the
+/// distance
+pub struct CheckerboardTexture<T> {
+    tex1: Box<dyn SyncTexture<T>>,
+    tex2: Box<dyn SyncTexture<T>>,
+    mapping: Box<dyn TextureMapping2D>,
+}
+
+impl<T> CheckerboardTexture<T> {
+    pub fn new(tex1: Box<dyn SyncTexture<T>>, tex2: Box<dyn SyncTexture<T>>, scale: f32) -> Self {
+        Self {
+            tex1,
+            tex2,
+            mapping: Box::new(UVMap::new(scale, scale, 0.0, 0.0)),
+        }
+    }
+
+    /// checks laid out by `mapping` instead of the scaled uvs
+    pub fn with_mapping(mut self, mapping: Box<dyn TextureMapping2D>) -> Self {
+        self.mapping = mapping;
+        self
+    }
+}
+
+/// integral of the square wave that is one on the odd checks, from zero to `x`
+fn bump_int(x: f32) -> f32 {
+    (x / 2.0).floor() + 2.0 * (x / 2.0 - (x / 2.0).floor() - 0.5).max(0.0)
+}
+
+impl<T> Texture<T> for CheckerboardTexture<T>
+where
+    T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+{
+    fn evaluate(&self, it: &SurfaceMediumInteraction) -> T {
+        let mut dst_dx = glm::zero();
+        let mut dst_dy = glm::zero();
+        let st = self.mapping.map(&it, &mut dst_dx, &mut dst_dy);
+
+        let ds = dst_dx[0].abs().max(dst_dy[0].abs());
+        let dt = dst_dx[1].abs().max(dst_dy[1].abs());
+        let (s0, s1) = (st[0] - ds, st[0] + ds);
+        let (t0, t1) = (st[1] - dt, st[1] + dt);
+
+        if s0.floor() == s1.floor() && t0.floor() == t1.floor() {
+            // the footprint is inside a single check
+            return if (st[0].floor() + st[1].floor()) as i32 % 2 == 0 {
+                self.tex1.evaluate(it)
+            } else {
+                self.tex2.evaluate(it)
+            };
+        }
+
+        // fraction of the footprint on odd checks along each axis
+        let odd_fraction = |x: f32, x0: f32, x1: f32, dx: f32| {
+            if dx > 0.0 {
+                (bump_int(x1) - bump_int(x0)) / (2.0 * dx)
+            } else {
+                abs_mod(x.floor() as i32, 2) as f32
+            }
+        };
+        let s_int = odd_fraction(st[0], s0, s1, ds);
+        let t_int = odd_fraction(st[1], t0, t1, dt);
+        let mut area2 = s_int + t_int - 2.0 * s_int * t_int;
+        if ds > 1.0 || dt > 1.0 {
+            area2 = 0.5;
+        }
+
+        self.tex1.evaluate(it) * (1.0 - area2) + self.tex2.evaluate(it) * area2
+    }
+}
+
+/// lines of `line` over `background`, one grid cell per `1 / scale` of uv and `line_width` the
+/// fraction of a cell covered by a line
+pub struct GridTexture<T> {
+    background: Box<dyn SyncTexture<T>>,
+    line: Box<dyn SyncTexture<T>>,
+    line_width: f32,
+    mapping: Box<dyn TextureMapping2D>,
+}
+
+impl<T> GridTexture<T> {
+    pub fn new(
+        background: Box<dyn SyncTexture<T>>,
+        line: Box<dyn SyncTexture<T>>,
+        scale: f32,
+        line_width: f32,
+    ) -> Self {
+        Self {
+            background,
+            line,
+            line_width,
+            mapping: Box::new(UVMap::new(scale, scale, 0.0, 0.0)),
+        }
+    }
+
+    /// cells laid out by `mapping` instead of the scaled uvs
+    pub fn with_mapping(mut self, mapping: Box<dyn TextureMapping2D>) -> Self {
+        self.mapping = mapping;
+        self
+    }
+}
+
+impl<T> Texture<T> for GridTexture<T> {
+    fn evaluate(&self, it: &SurfaceMediumInteraction) -> T {
+        let mut dst_dx = glm::zero();
+        let mut dst_dy = glm::zero();
+        let st = self.mapping.map(&it, &mut dst_dx, &mut dst_dy);
+
+        // distance to the closest cell border along each axis
+        let distance = |x: f32| (x - x.round()).abs();
+        if distance(st[0]).min(distance(st[1])) < 0.5 * self.line_width {
+            self.line.evaluate(it)
+        } else {
+            self.background.evaluate(it)
+        }
+    }
+}
+
+/// maps hit points into the space solid textures are evaluated in, usually the object space of
+/// the mesh so the texture sticks to it as it moves
+#[derive(Clone, Copy, Debug)]
+pub struct Mapping3D {
+    world_to_texture: na::Projective3<f32>,
+    normal_to_texture: na::Matrix3<f32>,
+}
+
+impl Mapping3D {
+    pub fn new(world_to_texture: na::Projective3<f32>) -> Self {
+        // normals transform with the inverse transpose
+        let normal_to_texture = world_to_texture
+            .inverse()
+            .matrix()
+            .fixed_slice::<na::U3, na::U3>(0, 0)
+            .transpose();
+        Self {
+            world_to_texture,
+            normal_to_texture,
+        }
+    }
+
+    pub fn map_normal(&self, n: &na::Vector3<f32>) -> na::Vector3<f32> {
+        (self.normal_to_texture * n).normalize()
+    }
+}
+
+impl TextureMapping3D for Mapping3D {
+    fn map(
+        &self,
+        it: &SurfaceMediumInteraction,
+        dpdx: &mut na::Vector3<f32>,
+        dpdy: &mut na::Vector3<f32>,
+    ) -> na::Point3<f32> {
+        *dpdx = self.world_to_texture * it.dpdx;
+        *dpdy = self.world_to_texture * it.dpdy;
+
+        self.world_to_texture * it.general.p
+    }
+}
+
+/// solid checks alternating between two textures, one check per unit cube of texture space
+pub struct SolidCheckerTexture<T> {
+    tex1: Box<dyn SyncTexture<T>>,
+    tex2: Box<dyn SyncTexture<T>>,
+    mapping: Box<dyn TextureMapping3D>,
+}
+
+impl<T> SolidCheckerTexture<T> {
+    pub fn new(
+        tex1: Box<dyn SyncTexture<T>>,
+        tex2: Box<dyn SyncTexture<T>>,
+        mapping: Box<dyn TextureMapping3D>,
+    ) -> Self {
+        Self {
+            tex1,
+            tex2,
+            mapping,
+        }
+    }
+}
+
+impl<T> Texture<T> for SolidCheckerTexture<T> {
+    fn evaluate(&self, it: &SurfaceMediumInteraction) -> T {
+        let mut dpdx = glm::zero();
+        let mut dpdy = glm::zero();
+        let p = self.mapping.map(&it, &mut dpdx, &mut dpdy);
+        if (p.x.floor() + p.y.floor() + p.z.floor()) as i32 % 2 == 0 {
+            self.tex1.evaluate(it)
+        } else {
+            self.tex2.evaluate(it)
+        }
+    }
+}
+
+/// marble veins from a sine wave along y perturbed by fbm noise, colored with a fixed palette
+pub struct MarbleTexture {
+    mapping: Box<dyn TextureMapping3D>,
+    octaves: usize,
+    omega: f32,
+    scale: f32,
+    variation: f32,
+}
+
+impl MarbleTexture {
+    pub fn new(
+        mapping: Box<dyn TextureMapping3D>,
+        octaves: usize,
+        omega: f32,
+        scale: f32,
+        variation: f32,
+    ) -> Self {
+        Self {
+            mapping,
+            octaves,
+            omega,
+            scale,
+            variation,
+        }
+    }
+}
+
+#[rustfmt::skip]
+const MARBLE_COLORS: [[f32; 3]; 9] = [
+    [0.58, 0.58, 0.6], [0.58, 0.58, 0.6], [0.58, 0.58, 0.6],
+    [0.5, 0.5, 0.5], [0.6, 0.59, 0.58], [0.58, 0.58, 0.6],
+    [0.58, 0.58, 0.6], [0.2, 0.2, 0.33], [0.58, 0.58, 0.6],
+];
+
+impl Texture<Spectrum> for MarbleTexture {
+    fn evaluate(&self, it: &SurfaceMediumInteraction) -> Spectrum {
+        let mut dpdx = glm::zero();
+        let mut dpdy = glm::zero();
+        let p = self.mapping.map(&it, &mut dpdx, &mut dpdy) * self.scale;
+        let marble = p.y
+            + self.variation
+                * fbm(
+                    &p,
+                    &(dpdx * self.scale),
+                    &(dpdy * self.scale),
+                    self.omega,
+                    self.octaves,
+                );
+        let t = 0.5 + 0.5 * marble.sin();
+
+        // cubic bezier through four consecutive palette entries
+        let n_segments = MARBLE_COLORS.len() - 3;
+        let first = ((t * n_segments as f32).floor() as usize).min(n_segments - 1);
+        let t = t * n_segments as f32 - first as f32;
+        let c = |i: usize| {
+            let c = MARBLE_COLORS[first + i];
+            Spectrum::from_floats(c[0], c[1], c[2])
+        };
+        let s0 = lerp(lerp(c(0), c(1), t), lerp(c(1), c(2), t), t);
+        let s1 = lerp(lerp(c(1), c(2), t), lerp(c(2), c(3), t), t);
+
+        1.5 * lerp(s0, s1, t)
+    }
+}
+
+/// growth rings around the texture space z axis, `rings` per unit of radius, wobbling with
+/// turbulence scaled by `variation`
+pub struct WoodTexture {
+    mapping: Box<dyn TextureMapping3D>,
+    light: Spectrum,
+    dark: Spectrum,
+    rings: f32,
+    variation: f32,
+    octaves: usize,
+    omega: f32,
+}
+
+impl WoodTexture {
+    pub fn new(
+        mapping: Box<dyn TextureMapping3D>,
+        light: Spectrum,
+        dark: Spectrum,
+        rings: f32,
+        variation: f32,
+        octaves: usize,
+        omega: f32,
+    ) -> Self {
+        Self {
+            mapping,
+            light,
+            dark,
+            rings,
+            variation,
+            octaves,
+            omega,
+        }
+    }
+}
+
+impl Texture<Spectrum> for WoodTexture {
+    fn evaluate(&self, it: &SurfaceMediumInteraction) -> Spectrum {
+        let mut dpdx = glm::zero();
+        let mut dpdy = glm::zero();
+        let p = self.mapping.map(&it, &mut dpdx, &mut dpdy);
+        let radius = (p.x * p.x + p.y * p.y).sqrt()
+            + self.variation * turbulence(&p, &dpdx, &dpdy, self.omega, self.octaves);
+        let ring = radius * self.rings;
+        let ring = ring - ring.floor();
+
+        // light early wood grows over most of the ring, the dark late wood is a thin band at
+        // its end
+        lerp(self.light, self.dark, ring.powi(4))
+    }
+}
+
+/// filter applied by the mipmap when looking up a footprint given by the uv differentials
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterMode {
+    /// isotropic, blends the two levels matching the longest axis of the footprint
+    Trilinear,
+    /// elliptically weighted average over the footprint, stays sharp at grazing angles
+    EWA,
+}
+
 pub struct ImageTexture<T: na::Scalar + num::Zero> {
     mip_map: MIPMap<T>,
-    mapping: UVMap,
+    mapping: Box<dyn TextureMapping2D>,
     log: slog::Logger,
 }
 
+/// texels of a grayscale image scaled to [0, `scale`]
+pub fn luma_texels(image: &image::GrayImage, scale: f32) -> na::DMatrix<f32> {
+    na::DMatrix::from_fn(
+        image.height() as usize,
+        image.width() as usize,
+        |row, col| scale * (image.get_pixel(col as u32, row as u32)[0] as f32 / 255.0),
+    )
+}
+
+/// texels of a color image, linearized from srgb when `gamma` is set
+pub fn rgb_texels(image: &image::RgbImage, scale: Spectrum, gamma: bool) -> na::DMatrix<Spectrum> {
+    na::DMatrix::from_fn(
+        image.height() as usize,
+        image.width() as usize,
+        |row, col| {
+            scale * Spectrum::from_image_rgb(&image.get_pixel(col as u32, row as u32), gamma)
+        },
+    )
+}
+
 impl ImageTexture<f32> {
     pub fn new(
         log: &slog::Logger,
@@ -102,19 +676,7 @@ impl ImageTexture<f32> {
         wrap_mode: WrapMode,
         mapping: UVMap,
     ) -> Self {
-        let matrix = na::DMatrix::from_fn(
-            image.height() as usize,
-            image.width() as usize,
-            |row, col| scale * (image.get_pixel(col as u32, row as u32)[0] as f32 / 255.0),
-        );
-
-        let log = log.new(o!());
-
-        Self {
-            mip_map: MIPMap::new(&log, matrix, true, wrap_mode),
-            mapping,
-            log,
-        }
+        Self::from_texels(log, luma_texels(image, scale), wrap_mode, mapping)
     }
 }
 
@@ -127,21 +689,7 @@ impl ImageTexture<Spectrum> {
         mapping: UVMap,
         gamma: bool,
     ) -> Self {
-        let matrix = na::DMatrix::from_fn(
-            image.height() as usize,
-            image.width() as usize,
-            |row, col| {
-                scale * Spectrum::from_image_rgb(&image.get_pixel(col as u32, row as u32), gamma)
-            },
-        );
-
-        let log = log.new(o!());
-
-        Self {
-            mip_map: MIPMap::new(&log, matrix, true, wrap_mode),
-            mapping,
-            log,
-        }
+        Self::from_texels(log, rgb_texels(image, scale, gamma), wrap_mode, mapping)
     }
 }
 
@@ -166,22 +714,70 @@ impl ImageTexture<na::Vector3<f32>> {
             },
         );
 
+        Self::from_texels(log, matrix, wrap_mode, mapping)
+    }
+}
+
+impl<T: Texel> ImageTexture<T> {
+    pub fn from_texels(
+        log: &slog::Logger,
+        texels: na::DMatrix<T>,
+        wrap_mode: WrapMode,
+        mapping: UVMap,
+    ) -> Self {
         let log = log.new(o!());
 
         Self {
-            mip_map: MIPMap::new(&log, matrix, true, wrap_mode),
-            mapping,
+            mip_map: MIPMap::new(&log, texels, FilterMode::EWA, wrap_mode),
+            mapping: Box::new(mapping),
             log,
         }
     }
+
+    /// texture paged in from `cache`, `texels` is only called on the first lookup
+    pub fn from_cache<F>(
+        log: &slog::Logger,
+        cache: &Arc<TextureCache>,
+        texels: F,
+        wrap_mode: WrapMode,
+        mapping: UVMap,
+    ) -> Self
+    where
+        F: FnOnce() -> na::DMatrix<T> + Send + 'static,
+    {
+        let log = log.new(o!());
+
+        Self {
+            mip_map: MIPMap::from_cache(&log, cache, texels, FilterMode::EWA, wrap_mode),
+            mapping: Box::new(mapping),
+            log,
+        }
+    }
+
+    /// textures are filtered with EWA unless set otherwise
+    pub fn with_filter(mut self, filter: FilterMode) -> Self {
+        self.mip_map.filter = filter;
+        self
+    }
+
+    /// texture coordinates from `mapping` instead of the uvs
+    pub fn with_mapping(mut self, mapping: Box<dyn TextureMapping2D>) -> Self {
+        self.mapping = mapping;
+        self
+    }
+
+    /// projects the image along the three axes of `mapping` instead, for meshes without uvs
+    pub fn into_triplanar(self, mapping: Mapping3D) -> TriplanarTexture<T> {
+        TriplanarTexture {
+            mip_map: self.mip_map,
+            mapping,
+        }
+    }
 }
 
 pub type NormalMap = ImageTexture<na::Vector3<f32>>;
 
-impl<T> Texture<T> for ImageTexture<T>
-where
-    T: na::Scalar + num::Zero + Copy + AddAssign + Mul<f32, Output = T>,
-{
+impl<T: Texel> Texture<T> for ImageTexture<T> {
     fn evaluate(&self, it: &SurfaceMediumInteraction) -> T {
         let mut dst_dx = glm::zero();
         let mut dst_dy = glm::zero();
@@ -191,6 +787,39 @@ where
     }
 }
 
+/// image projected along the x, y and z axes of texture space, blended by how much the surface
+/// faces each axis. needs no uvs, one image repeat per unit of texture space
+pub struct TriplanarTexture<T: na::Scalar + num::Zero> {
+    mip_map: MIPMap<T>,
+    mapping: Mapping3D,
+}
+
+impl<T: Texel> Texture<T> for TriplanarTexture<T> {
+    fn evaluate(&self, it: &SurfaceMediumInteraction) -> T {
+        let mut dpdx = glm::zero();
+        let mut dpdy = glm::zero();
+        let p = self.mapping.map(&it, &mut dpdx, &mut dpdy);
+        let weights = self
+            .mapping
+            .map_normal(&it.shading.n)
+            .map(|n| n.abs().powi(TRIPLANAR_SHARPNESS));
+        let weights = weights / weights.sum();
+
+        let mut value = T::zero();
+        for (axis, (s, t)) in [(1, 2), (0, 2), (0, 1)].iter().enumerate() {
+            if weights[axis] > 0.0 {
+                let project = |v: &na::Vector3<f32>| na::Vector2::new(v[*s], v[*t]);
+                value += self.mip_map.lookup(
+                    &na::Point2::from(project(&p.coords)),
+                    &project(&dpdx),
+                    &project(&dpdy),
+                ) * weights[axis];
+            }
+        }
+        value
+    }
+}
+
 struct ResampleWeight {
     pub first_texel: usize,
     pub weight: [f32; 4],
@@ -235,185 +864,290 @@ fn resample_weights(old_res: usize, new_res: usize) -> Vec<ResampleWeight> {
     wt
 }
 
+const WEIGHT_LUT_SIZE: usize = 128;
+const MAX_ANISOTROPY: f32 = 8.0;
+
+lazy_static::lazy_static! {
+    // gaussian falloff over the squared radius of the ewa ellipse, shifted to reach zero at its edge
+    static ref WEIGHT_LUT: [f32; WEIGHT_LUT_SIZE] = {
+        const ALPHA: f32 = 2.0;
+        let mut lut = [0.0; WEIGHT_LUT_SIZE];
+        for (i, weight) in lut.iter_mut().enumerate() {
+            let r2 = i as f32 / (WEIGHT_LUT_SIZE - 1) as f32;
+            *weight = (-ALPHA * r2).exp() - (-ALPHA).exp();
+        }
+        lut
+    };
+}
+
+enum Pyramid<T: na::Scalar> {
+    Resident(Vec<na::DMatrix<T>>),
+    /// levels paged in from a texture cache
+    Cached {
+        cache: Arc<TextureCache>,
+        id: usize,
+    },
+}
+
+/// the levels of a pyramid for the duration of a lookup
+enum Levels<'a, T: na::Scalar> {
+    Resident(&'a [na::DMatrix<T>]),
+    Cached(&'a TextureCache, TextureTiles),
+}
+
+impl<'a, T: na::Scalar> Levels<'a, T> {
+    fn len(&self) -> usize {
+        match self {
+            Levels::Resident(pyramid) => pyramid.len(),
+            Levels::Cached(_, tiles) => tiles.levels(),
+        }
+    }
+
+    /// width and height of `level`
+    fn resolution(&self, level: usize) -> (usize, usize) {
+        match self {
+            Levels::Resident(pyramid) => (pyramid[level].ncols(), pyramid[level].nrows()),
+            Levels::Cached(_, tiles) => tiles.resolution(level),
+        }
+    }
+}
+
 pub struct MIPMap<T: na::Scalar + num::Zero> {
-    pyramid: Vec<na::DMatrix<T>>,
+    pyramid: Pyramid<T>,
     wrap_mode: WrapMode,
-    do_trilinear: bool,
+    filter: FilterMode,
     log: slog::Logger,
 }
 
-fn texel<T: na::Scalar + num::Zero>(
-    level: &na::DMatrix<T>,
+/// texel coordinates inside a `width` by `height` level, `None` outside of it for black wrapping
+fn wrap_texel(
     s: i32,
     t: i32,
+    width: usize,
+    height: usize,
     wrap_mode: &WrapMode,
-) -> T {
-    let ret;
+) -> Option<(usize, usize)> {
     match wrap_mode {
-        WrapMode::Repeat => {
-            let s = abs_mod(s, level.ncols() as i32);
-            let t = abs_mod(t, level.nrows() as i32);
-            ret = level[(t as usize, s as usize)].clone();
-        }
+        WrapMode::Repeat => Some((
+            abs_mod(s, width as i32) as usize,
+            abs_mod(t, height as i32) as usize,
+        )),
         WrapMode::Black => {
-            if s < 0 || s >= level.ncols() as i32 || t < 0 || t >= level.nrows() as i32 {
-                ret = num::zero()
+            if s < 0 || s >= width as i32 || t < 0 || t >= height as i32 {
+                None
             } else {
-                ret = level[(t as usize, s as usize)].clone();
+                Some((s as usize, t as usize))
             }
         }
-        WrapMode::Clamp => {
-            let s = s.clamp(0, level.ncols() as i32 - 1);
-            let t = t.clamp(0, level.nrows() as i32 - 1);
-            ret = level[(t as usize, s as usize)].clone();
-        }
+        WrapMode::Clamp => Some((
+            s.clamp(0, width as i32 - 1) as usize,
+            t.clamp(0, height as i32 - 1) as usize,
+        )),
     }
+}
 
-    ret
+fn texel<T: na::Scalar + num::Zero>(
+    level: &na::DMatrix<T>,
+    s: i32,
+    t: i32,
+    wrap_mode: &WrapMode,
+) -> T {
+    match wrap_texel(s, t, level.ncols(), level.nrows(), wrap_mode) {
+        Some((s, t)) => level[(t, s)].clone(),
+        None => num::zero(),
+    }
 }
 
-impl<T> MIPMap<T>
-where
-    T: na::Scalar + num::Zero + Copy + AddAssign + Mul<f32, Output = T>,
-{
-    pub fn new(
-        log: &slog::Logger,
-        image: na::DMatrix<T>,
-        do_trilinear: bool,
-        wrap_mode: WrapMode,
-    ) -> Self {
-        let log = log.new(o!());
-        let mut resolution = na::Point2::new(image.ncols(), image.nrows());
-        let resampled_image = if !image.ncols().is_power_of_two()
-            || !image.nrows().is_power_of_two()
-        {
-            debug!(log, "image size not power of two, resampling");
-            let res_pow_2 = na::Point2::new(
-                (image.ncols() as i32).round_up_pow_2() as usize,
-                (image.nrows() as i32).round_up_pow_2() as usize,
-            );
-
-            info!(
-                log,
-                "Resampling MIPMap from {:?} to {:?}, ratio = {:?}",
-                resolution,
-                res_pow_2,
-                ((res_pow_2.x * res_pow_2.y) / (resolution.x * resolution.y))
-            );
-
-            let s_weights = resample_weights(resolution[0], res_pow_2[0]);
-            let mut resampled_image = na::DMatrix::<T>::zeros(res_pow_2[1], res_pow_2[0]);
-
-            for t in 0..resolution[1] {
-                for s in 0..res_pow_2[0] {
-                    for j in 0..4 {
-                        let mut orig_s = s_weights[s].first_texel + j;
-                        match wrap_mode {
-                            WrapMode::Repeat => {
-                                orig_s = abs_mod(orig_s, resolution[0]);
-                            }
-                            WrapMode::Clamp => {
-                                orig_s = orig_s.clamp(0, resolution[0] - 1);
-                            }
-                            _ => {}
+/// levels of the pyramid, the image is first resampled to power of two resolutions
+fn build_pyramid<T: Texel>(
+    log: &slog::Logger,
+    image: na::DMatrix<T>,
+    wrap_mode: WrapMode,
+) -> Vec<na::DMatrix<T>> {
+    let mut resolution = na::Point2::new(image.ncols(), image.nrows());
+    let resampled_image = if !image.ncols().is_power_of_two() || !image.nrows().is_power_of_two() {
+        debug!(log, "image size not power of two, resampling");
+        let res_pow_2 = na::Point2::new(
+            (image.ncols() as i32).round_up_pow_2() as usize,
+            (image.nrows() as i32).round_up_pow_2() as usize,
+        );
+
+        info!(
+            log,
+            "Resampling MIPMap from {:?} to {:?}, ratio = {:?}",
+            resolution,
+            res_pow_2,
+            ((res_pow_2.x * res_pow_2.y) / (resolution.x * resolution.y))
+        );
+
+        let s_weights = resample_weights(resolution[0], res_pow_2[0]);
+        let mut resampled_image = na::DMatrix::<T>::zeros(res_pow_2[1], res_pow_2[0]);
+
+        for t in 0..resolution[1] {
+            for s in 0..res_pow_2[0] {
+                for j in 0..4 {
+                    let mut orig_s = s_weights[s].first_texel + j;
+                    match wrap_mode {
+                        WrapMode::Repeat => {
+                            orig_s = abs_mod(orig_s, resolution[0]);
                         }
-                        if orig_s > 0 && orig_s < resolution[0] {
-                            resampled_image[(t, s)] += image[(t, orig_s)] * s_weights[s].weight[j];
+                        WrapMode::Clamp => {
+                            orig_s = orig_s.clamp(0, resolution[0] - 1);
                         }
+                        _ => {}
+                    }
+                    if orig_s > 0 && orig_s < resolution[0] {
+                        resampled_image[(t, s)] += image[(t, orig_s)] * s_weights[s].weight[j];
                     }
                 }
             }
+        }
 
-            let t_weights = resample_weights(resolution[1], res_pow_2[1]);
+        let t_weights = resample_weights(resolution[1], res_pow_2[1]);
 
-            for s in 0..res_pow_2[0] {
-                let mut work_data = vec![T::zero(); res_pow_2[1]];
-                for t in 0..res_pow_2[1] {
-                    for j in 0..4 {
-                        let mut offset = t_weights[t].first_texel + j;
-                        match wrap_mode {
-                            WrapMode::Repeat => {
-                                offset = abs_mod(offset, resolution[1]);
-                            }
-                            WrapMode::Clamp => {
-                                offset = offset.clamp(0, resolution[1] - 1);
-                            }
-                            _ => {}
+        for s in 0..res_pow_2[0] {
+            let mut work_data = vec![T::zero(); res_pow_2[1]];
+            for t in 0..res_pow_2[1] {
+                for j in 0..4 {
+                    let mut offset = t_weights[t].first_texel + j;
+                    match wrap_mode {
+                        WrapMode::Repeat => {
+                            offset = abs_mod(offset, resolution[1]);
                         }
-                        if offset >= 0 && offset < resolution[1] {
-                            work_data[t] += resampled_image[(offset, s)] * t_weights[t].weight[j];
+                        WrapMode::Clamp => {
+                            offset = offset.clamp(0, resolution[1] - 1);
                         }
+                        _ => {}
+                    }
+                    if offset >= 0 && offset < resolution[1] {
+                        work_data[t] += resampled_image[(offset, s)] * t_weights[t].weight[j];
                     }
-                }
-                for t in 0..res_pow_2[1] {
-                    resampled_image[(t, s)] = work_data[t];
                 }
             }
+            for t in 0..res_pow_2[1] {
+                resampled_image[(t, s)] = work_data[t];
+            }
+        }
 
-            resolution = res_pow_2;
+        resolution = res_pow_2;
 
-            Some(resampled_image)
-        } else {
-            None
-        };
+        Some(resampled_image)
+    } else {
+        None
+    };
 
-        let n_levels = 1 + log2_int(resolution[0].max(resolution[1])) as usize;
-        let mut pyramid = Vec::with_capacity(n_levels as usize);
+    let n_levels = 1 + log2_int(resolution[0].max(resolution[1])) as usize;
+    let mut pyramid = Vec::with_capacity(n_levels as usize);
 
-        if let Some(resampled_image) = resampled_image {
-            pyramid.push(resampled_image);
-        } else {
-            pyramid.push(image);
-        }
-
-        for i in 1..n_levels {
-            let s_res = 1usize.max(pyramid[i - 1].ncols() / 2);
-            let t_res = 1usize.max(pyramid[i - 1].nrows() / 2);
-
-            pyramid.push(na::DMatrix::from_fn(t_res, s_res, |t, s| {
-                (texel(&pyramid[i - 1], (2 * s) as i32, (2 * t) as i32, &wrap_mode)
-                    + texel(
-                        &pyramid[i - 1],
-                        (2 * s + 1) as i32,
-                        (2 * t) as i32,
-                        &wrap_mode,
-                    )
-                    + texel(
-                        &pyramid[i - 1],
-                        (2 * s) as i32,
-                        (2 * t + 1) as i32,
-                        &wrap_mode,
-                    )
-                    + texel(
-                        &pyramid[i - 1],
-                        (2 * s + 1) as i32,
-                        (2 * t + 1) as i32,
-                        &wrap_mode,
-                    ))
-                    * 0.25
-            }));
-        }
-
-        // TODO: EWA filters
+    if let Some(resampled_image) = resampled_image {
+        pyramid.push(resampled_image);
+    } else {
+        pyramid.push(image);
+    }
+
+    for i in 1..n_levels {
+        let s_res = 1usize.max(pyramid[i - 1].ncols() / 2);
+        let t_res = 1usize.max(pyramid[i - 1].nrows() / 2);
+
+        pyramid.push(na::DMatrix::from_fn(t_res, s_res, |t, s| {
+            (texel(&pyramid[i - 1], (2 * s) as i32, (2 * t) as i32, &wrap_mode)
+                + texel(
+                    &pyramid[i - 1],
+                    (2 * s + 1) as i32,
+                    (2 * t) as i32,
+                    &wrap_mode,
+                )
+                + texel(
+                    &pyramid[i - 1],
+                    (2 * s) as i32,
+                    (2 * t + 1) as i32,
+                    &wrap_mode,
+                )
+                + texel(
+                    &pyramid[i - 1],
+                    (2 * s + 1) as i32,
+                    (2 * t + 1) as i32,
+                    &wrap_mode,
+                ))
+                * 0.25
+        }));
+    }
+
+    pyramid
+}
+
+impl<T: Texel> MIPMap<T> {
+    pub fn new(
+        log: &slog::Logger,
+        image: na::DMatrix<T>,
+        filter: FilterMode,
+        wrap_mode: WrapMode,
+    ) -> Self {
+        let log = log.new(o!());
 
         Self {
-            pyramid,
-            do_trilinear,
+            pyramid: Pyramid::Resident(build_pyramid(&log, image, wrap_mode)),
+            filter,
             wrap_mode,
             log,
         }
     }
 
-    fn texel(&self, level: usize, s: i32, t: i32) -> T {
-        let ret = texel(&self.pyramid[level], s, t, &self.wrap_mode);
+    /// mipmap paged in from `cache`, the pyramid is only built from `image` on the first lookup
+    pub fn from_cache<F>(
+        log: &slog::Logger,
+        cache: &Arc<TextureCache>,
+        image: F,
+        filter: FilterMode,
+        wrap_mode: WrapMode,
+    ) -> Self
+    where
+        F: FnOnce() -> na::DMatrix<T> + Send + 'static,
+    {
+        let log = log.new(o!());
+        let pyramid_log = log.clone();
+        let id = cache.add(move || build_pyramid(&pyramid_log, image(), wrap_mode));
+
+        Self {
+            pyramid: Pyramid::Cached {
+                cache: Arc::clone(cache),
+                id,
+            },
+            filter,
+            wrap_mode,
+            log,
+        }
+    }
+
+    /// levels to look texels up in, a cached pyramid resolves its tiles once here rather than
+    /// for every texel
+    fn levels(&self) -> Levels<'_, T> {
+        match &self.pyramid {
+            Pyramid::Resident(pyramid) => Levels::Resident(pyramid),
+            Pyramid::Cached { cache, id } => Levels::Cached(cache, cache.tiles(*id)),
+        }
+    }
+
+    fn texel(&self, levels: &Levels<T>, level: usize, s: i32, t: i32) -> T {
+        let ret = match levels {
+            Levels::Resident(pyramid) => texel(&pyramid[level], s, t, &self.wrap_mode),
+            Levels::Cached(cache, tiles) => {
+                let (width, height) = tiles.resolution(level);
+                match wrap_texel(s, t, width, height, &self.wrap_mode) {
+                    Some((s, t)) => cache.texel(tiles, level, s, t),
+                    None => num::zero(),
+                }
+            }
+        };
         trace!(self.log, "sampled value: {:?}", ret);
         ret
     }
 
-    fn triangle(&self, level: usize, st: &na::Point2<f32>) -> T {
-        let level = level.clamp(0, self.pyramid.len() - 1);
-        let s = st[0] * self.pyramid[level].ncols() as f32 - 0.5;
-        let t = st[1] * self.pyramid[level].nrows() as f32 - 0.5;
+    fn triangle(&self, levels: &Levels<T>, level: usize, st: &na::Point2<f32>) -> T {
+        let level = level.clamp(0, levels.len() - 1);
+        let (width, height) = levels.resolution(level);
+        let s = st[0] * width as f32 - 0.5;
+        let t = st[1] * height as f32 - 0.5;
         let s0 = s.floor();
         let t0 = t.floor();
         let ds = s - s0;
@@ -421,10 +1155,10 @@ where
         let s0 = s0 as i32;
         let t0 = t0 as i32;
 
-        self.texel(level, s0, t0) * (1.0 - ds) * (1.0 - dt)
-            + self.texel(level, s0, t0 + 1) * (1.0 - ds) * dt
-            + self.texel(level, s0 + 1, t0) * ds * (1.0 - dt)
-            + self.texel(level, s0 + 1, t0 + 1) * ds * dt
+        self.texel(levels, level, s0, t0) * (1.0 - ds) * (1.0 - dt)
+            + self.texel(levels, level, s0, t0 + 1) * (1.0 - ds) * dt
+            + self.texel(levels, level, s0 + 1, t0) * ds * (1.0 - dt)
+            + self.texel(levels, level, s0 + 1, t0 + 1) * ds * dt
     }
 
     pub fn lookup(
@@ -433,31 +1167,127 @@ where
         dst_dx: &na::Vector2<f32>,
         dst_dy: &na::Vector2<f32>,
     ) -> T {
-        if self.do_trilinear {
+        let levels = self.levels();
+        if self.filter == FilterMode::Trilinear {
             let width = dst_dx[0]
                 .abs()
                 .max(dst_dx[1].abs())
                 .max(dst_dy[0].abs().max(dst_dy[1].abs()));
-            self.lookup_width(&st, width)
+            return self.triangle_width(&levels, &st, width);
+        }
+
+        // the major axis goes first
+        let (mut dst0, mut dst1) = if dst_dx.norm_squared() < dst_dy.norm_squared() {
+            (*dst_dy, *dst_dx)
+        } else {
+            (*dst_dx, *dst_dy)
+        };
+        let major_length = dst0.norm();
+        let mut minor_length = dst1.norm();
+
+        // very eccentric ellipses would cover too many texels at the level picked by the minor
+        // axis, widen them instead, trading a little blur for a bounded cost
+        if minor_length * MAX_ANISOTROPY < major_length && minor_length > 0.0 {
+            let scale = major_length / (minor_length * MAX_ANISOTROPY);
+            dst1 *= scale;
+            minor_length *= scale;
+        }
+        if minor_length == 0.0 {
+            return self.triangle(&levels, 0, &st);
+        }
+
+        let lod = (levels.len() as f32 - 1.0 + minor_length.log2()).max(0.0);
+        let i_lod = lod.floor();
+        let delta = lod - i_lod;
+        let i_lod = i_lod as usize;
+        lerp(
+            self.ewa(&levels, i_lod, &st, &dst0, &dst1),
+            self.ewa(&levels, i_lod + 1, &st, &dst0, &dst1),
+            delta,
+        )
+    }
+
+    fn ewa(
+        &self,
+        levels: &Levels<T>,
+        level: usize,
+        st: &na::Point2<f32>,
+        dst0: &na::Vector2<f32>,
+        dst1: &na::Vector2<f32>,
+    ) -> T {
+        if level >= levels.len() {
+            return self.texel(levels, levels.len() - 1, 0, 0);
+        }
+
+        // ellipse in the texel space of the level
+        let (width, height) = levels.resolution(level);
+        let (width, height) = (width as f32, height as f32);
+        let s = st[0] * width - 0.5;
+        let t = st[1] * height - 0.5;
+        let dst0 = na::Vector2::new(dst0[0] * width, dst0[1] * height);
+        let dst1 = na::Vector2::new(dst1[0] * width, dst1[1] * height);
+
+        // implicit equation of the ellipse, the ones keep it covering at least a texel
+        let mut a = dst0[1] * dst0[1] + dst1[1] * dst1[1] + 1.0;
+        let mut b = -2.0 * (dst0[0] * dst0[1] + dst1[0] * dst1[1]);
+        let mut c = dst0[0] * dst0[0] + dst1[0] * dst1[0] + 1.0;
+        let inv_f = 1.0 / (a * c - b * b * 0.25);
+        a *= inv_f;
+        b *= inv_f;
+        c *= inv_f;
+
+        // bounding box of the ellipse
+        let det = -b * b + 4.0 * a * c;
+        let inv_det = 1.0 / det;
+        let u_sqrt = (det * c).sqrt();
+        let v_sqrt = (a * det).sqrt();
+        let s0 = (s - 2.0 * inv_det * u_sqrt).ceil() as i32;
+        let s1 = (s + 2.0 * inv_det * u_sqrt).floor() as i32;
+        let t0 = (t - 2.0 * inv_det * v_sqrt).ceil() as i32;
+        let t1 = (t + 2.0 * inv_det * v_sqrt).floor() as i32;
+
+        let mut sum = T::zero();
+        let mut sum_weights = 0.0;
+        for it in t0..=t1 {
+            let tt = it as f32 - t;
+            for is in s0..=s1 {
+                let ss = is as f32 - s;
+                let r2 = a * ss * ss + b * ss * tt + c * tt * tt;
+                if r2 < 1.0 {
+                    let index = ((r2 * WEIGHT_LUT_SIZE as f32) as usize).min(WEIGHT_LUT_SIZE - 1);
+                    let weight = WEIGHT_LUT[index];
+                    sum += self.texel(levels, level, is, it) * weight;
+                    sum_weights += weight;
+                }
+            }
+        }
+
+        if sum_weights > 0.0 {
+            sum * (1.0 / sum_weights)
         } else {
-            panic!("ewa not supported yet!");
+            self.triangle(levels, level, &st)
         }
     }
 
     pub fn lookup_width(&self, st: &na::Point2<f32>, width: f32) -> T {
-        let level = self.pyramid.len() as f32 - 1.0 + width.max(1e-8).log2();
+        self.triangle_width(&self.levels(), st, width)
+    }
+
+    fn triangle_width(&self, levels: &Levels<T>, st: &na::Point2<f32>, width: f32) -> T {
+        let n_levels = levels.len();
+        let level = n_levels as f32 - 1.0 + width.max(1e-8).log2();
 
         if level < 0.0 {
-            self.triangle(0, &st)
-        } else if level >= (self.pyramid.len() - 1) as f32 {
-            self.triangle(self.pyramid.len() - 1, &st)
+            self.triangle(levels, 0, &st)
+        } else if level >= (n_levels - 1) as f32 {
+            self.triangle(levels, n_levels - 1, &st)
         } else {
             let i_level = level.floor();
             let delta = level - i_level;
             let i_level = i_level as usize;
             lerp(
-                self.triangle(i_level, st),
-                self.triangle(i_level + 1, st),
+                self.triangle(levels, i_level, st),
+                self.triangle(levels, i_level + 1, st),
                 delta,
             )
         }
@@ -0,0 +1,399 @@
+//! on demand storage for image textures, so scenes referencing many large textures don't need all
+//! of them resident. a texture is registered with a loader and only decoded the first time one of
+//! its texels is looked up, its mip pyramid is then split into tiles and spilled to a tile file.
+//! lookups page tiles back in, dropping the least recently used ones once the tiles held exceed
+//! the memory budget
+
+use crate::common::spectrum::Spectrum;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::{AddAssign, Mul};
+use std::path::PathBuf;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex, RwLock,
+};
+
+pub const DEFAULT_BUDGET: usize = 512 * 1024 * 1024;
+/// side of a tile in texels, levels smaller than this are a single tile
+const TILE_SIZE: usize = 64;
+
+static NEXT_CACHE_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// texel types a mip pyramid can hold, stored by the cache as a fixed number of floats
+pub trait Texel:
+    na::Scalar + num::Zero + Copy + AddAssign + Mul<f32, Output = Self> + Send + Sync
+{
+    const CHANNELS: usize;
+
+    fn from_channels(channels: &[f32]) -> Self;
+    fn to_channels(&self, channels: &mut Vec<f32>);
+}
+
+impl Texel for f32 {
+    const CHANNELS: usize = 1;
+
+    fn from_channels(channels: &[f32]) -> Self {
+        channels[0]
+    }
+
+    fn to_channels(&self, channels: &mut Vec<f32>) {
+        channels.push(*self);
+    }
+}
+
+impl Texel for Spectrum {
+    const CHANNELS: usize = 3;
+
+    fn from_channels(channels: &[f32]) -> Self {
+        Spectrum::from_floats(channels[0], channels[1], channels[2])
+    }
+
+    fn to_channels(&self, channels: &mut Vec<f32>) {
+        channels.extend_from_slice(&[self.r(), self.g(), self.b()]);
+    }
+}
+
+impl Texel for na::Vector3<f32> {
+    const CHANNELS: usize = 3;
+
+    fn from_channels(channels: &[f32]) -> Self {
+        na::Vector3::new(channels[0], channels[1], channels[2])
+    }
+
+    fn to_channels(&self, channels: &mut Vec<f32>) {
+        channels.extend_from_slice(self.as_slice());
+    }
+}
+
+/// builds the levels of a pyramid as (width, height, row major channels)
+type Loader = Box<dyn FnOnce() -> Vec<(usize, usize, Vec<f32>)> + Send>;
+
+/// texture, mip level, and tile column and row
+type TileKey = (usize, usize, usize, usize);
+
+/// where the tiles of a loaded texture live in its tile file
+struct TileLayout {
+    channels: usize,
+    resolutions: Vec<(usize, usize)>,
+    tile_sizes: Vec<(usize, usize)>,
+    level_offsets: Vec<u64>,
+    path: PathBuf,
+    file: Mutex<std::fs::File>,
+}
+
+impl TileLayout {
+    fn tile_len(&self, level: usize) -> usize {
+        let (tile_width, tile_height) = self.tile_sizes[level];
+        tile_width * tile_height * self.channels
+    }
+
+    fn read_tile(&self, level: usize, tile_s: usize, tile_t: usize) -> std::io::Result<Vec<f32>> {
+        let tiles_per_row = self.resolutions[level].0 / self.tile_sizes[level].0;
+        let tile_len = self.tile_len(level);
+        let offset = self.level_offsets[level]
+            + ((tile_t * tiles_per_row + tile_s) * tile_len * std::mem::size_of::<f32>()) as u64;
+
+        let mut bytes = vec![0u8; tile_len * std::mem::size_of::<f32>()];
+        {
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut bytes)?;
+        }
+
+        Ok(bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect())
+    }
+}
+
+/// layout of a loaded texture, resolved once per lookup so its texels are found without going
+/// through the locks of the cache
+pub struct TextureTiles {
+    id: usize,
+    layout: Arc<TileLayout>,
+}
+
+impl TextureTiles {
+    /// number of mip levels
+    pub fn levels(&self) -> usize {
+        self.layout.resolutions.len()
+    }
+
+    /// width and height of `level`
+    pub fn resolution(&self, level: usize) -> (usize, usize) {
+        self.layout.resolutions[level]
+    }
+}
+
+struct CachedTexture {
+    loader: Mutex<Option<Loader>>,
+    layout: RwLock<Option<Arc<TileLayout>>>,
+}
+
+/// resident tiles, ordered by the last time they were looked up
+#[derive(Default)]
+struct Tiles {
+    tiles: HashMap<TileKey, (Arc<Vec<f32>>, u64)>,
+    recency: BTreeMap<u64, TileKey>,
+    clock: u64,
+    size: usize,
+}
+
+thread_local! {
+    // last tile each thread looked up, neighbouring lookups mostly hit the same tile and skip
+    // locking the cache
+    static LAST_TILE: RefCell<Option<(usize, TileKey, Arc<Vec<f32>>)>> = RefCell::new(None);
+}
+
+pub struct TextureCache {
+    id: usize,
+    budget: AtomicUsize,
+    textures: RwLock<Vec<Arc<CachedTexture>>>,
+    tiles: Mutex<Tiles>,
+    log: slog::Logger,
+}
+
+impl TextureCache {
+    /// `budget` bounds the memory held by resident tiles, in bytes
+    pub fn new(log: &slog::Logger, budget: usize) -> Self {
+        let log = log.new(o!("module" => "texture_cache"));
+        Self {
+            id: NEXT_CACHE_ID.fetch_add(1, Ordering::Relaxed),
+            budget: AtomicUsize::new(budget),
+            textures: RwLock::new(Vec::new()),
+            tiles: Mutex::new(Tiles::default()),
+            log,
+        }
+    }
+
+    pub fn set_budget(&self, budget: usize) {
+        self.budget.store(budget, Ordering::Relaxed);
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget.load(Ordering::Relaxed)
+    }
+
+    /// memory held by the resident tiles, in bytes
+    pub fn resident_size(&self) -> usize {
+        self.tiles.lock().unwrap().size
+    }
+
+    /// registers a texture whose pyramid `loader` builds on first use, returns its id
+    pub fn add<T, F>(&self, loader: F) -> usize
+    where
+        T: Texel,
+        F: FnOnce() -> Vec<na::DMatrix<T>> + Send + 'static,
+    {
+        let loader = Box::new(move || {
+            loader()
+                .into_iter()
+                .map(|level| {
+                    let mut channels = Vec::with_capacity(level.len() * T::CHANNELS);
+                    for t in 0..level.nrows() {
+                        for s in 0..level.ncols() {
+                            level[(t, s)].to_channels(&mut channels);
+                        }
+                    }
+                    (level.ncols(), level.nrows(), channels)
+                })
+                .collect()
+        }) as Loader;
+
+        let mut textures = self.textures.write().unwrap();
+        textures.push(Arc::new(CachedTexture {
+            loader: Mutex::new(Some(loader)),
+            layout: RwLock::new(None),
+        }));
+        textures.len() - 1
+    }
+
+    /// tiles of the texture, loading it if this is its first use
+    pub fn tiles(&self, id: usize) -> TextureTiles {
+        TextureTiles {
+            id,
+            layout: self.layout(id),
+        }
+    }
+
+    fn layout(&self, id: usize) -> Arc<TileLayout> {
+        let texture = Arc::clone(&self.textures.read().unwrap()[id]);
+        if let Some(layout) = texture.layout.read().unwrap().as_ref() {
+            return Arc::clone(layout);
+        }
+
+        // the first thread to get here loads the texture, the others wait for it
+        let mut loader = texture.loader.lock().unwrap();
+        if let Some(loader) = loader.take() {
+            let layout = self
+                .write_tiles(id, loader())
+                .unwrap_or_else(|err| panic!("failed to write texture tiles: {:?}", err));
+            *texture.layout.write().unwrap() = Some(Arc::new(layout));
+        }
+        let layout = texture.layout.read().unwrap();
+        Arc::clone(layout.as_ref().unwrap())
+    }
+
+    fn write_tiles(
+        &self,
+        id: usize,
+        levels: Vec<(usize, usize, Vec<f32>)>,
+    ) -> std::io::Result<TileLayout> {
+        let dir = std::env::temp_dir().join("pathtracer-rs").join("tiles");
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{}-{}-{}.tiles", std::process::id(), self.id, id));
+        let mut file = std::io::BufWriter::new(
+            std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&path)?,
+        );
+
+        let channels = levels[0].2.len() / (levels[0].0 * levels[0].1);
+        let mut resolutions = Vec::with_capacity(levels.len());
+        let mut tile_sizes = Vec::with_capacity(levels.len());
+        let mut level_offsets = Vec::with_capacity(levels.len());
+        let mut offset = 0u64;
+        for (width, height, texels) in &levels {
+            let (tile_width, tile_height) = (TILE_SIZE.min(*width), TILE_SIZE.min(*height));
+            // the pyramid has power of two levels, tiles divide them evenly
+            for tile_t in 0..height / tile_height {
+                for tile_s in 0..width / tile_width {
+                    for t in tile_t * tile_height..(tile_t + 1) * tile_height {
+                        let row = (t * width + tile_s * tile_width) * channels;
+                        for v in &texels[row..row + tile_width * channels] {
+                            file.write_all(&v.to_le_bytes())?;
+                        }
+                    }
+                }
+            }
+
+            resolutions.push((*width, *height));
+            tile_sizes.push((tile_width, tile_height));
+            level_offsets.push(offset);
+            offset += (texels.len() * std::mem::size_of::<f32>()) as u64;
+        }
+        let file = file.into_inner()?;
+        debug!(
+            self.log,
+            "tiled texture {} into {:?}, {} levels",
+            id,
+            path,
+            resolutions.len()
+        );
+
+        Ok(TileLayout {
+            channels,
+            resolutions,
+            tile_sizes,
+            level_offsets,
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    fn tile(&self, layout: &TileLayout, key: TileKey) -> Arc<Vec<f32>> {
+        {
+            let mut guard = self.tiles.lock().unwrap();
+            let tiles = &mut *guard;
+            tiles.clock += 1;
+            let clock = tiles.clock;
+            if let Some((tile, last_used)) = tiles.tiles.get_mut(&key) {
+                let tile = Arc::clone(tile);
+                let last_used = std::mem::replace(last_used, clock);
+                tiles.recency.remove(&last_used);
+                tiles.recency.insert(clock, key);
+                return tile;
+            }
+        }
+
+        // read without holding the cache, another thread may page in the same tile meanwhile
+        let (_, level, tile_s, tile_t) = key;
+        let tile = Arc::new(
+            layout
+                .read_tile(level, tile_s, tile_t)
+                .unwrap_or_else(|err| panic!("failed to read texture tile: {:?}", err)),
+        );
+
+        let mut guard = self.tiles.lock().unwrap();
+        let tiles = &mut *guard;
+        tiles.clock += 1;
+        let clock = tiles.clock;
+        let size = tile.len() * std::mem::size_of::<f32>();
+        if let Some((_, last_used)) = tiles.tiles.insert(key, (Arc::clone(&tile), clock)) {
+            tiles.recency.remove(&last_used);
+            tiles.size -= size;
+        }
+        tiles.recency.insert(clock, key);
+        tiles.size += size;
+
+        // the tile just paged in always stays
+        let budget = self.budget();
+        while tiles.size > budget && tiles.recency.len() > 1 {
+            let (&last_used, &evicted) = tiles.recency.iter().next().unwrap();
+            tiles.recency.remove(&last_used);
+            if let Some((evicted, _)) = tiles.tiles.remove(&evicted) {
+                tiles.size -= evicted.len() * std::mem::size_of::<f32>();
+            }
+        }
+
+        tile
+    }
+
+    /// texel `(s, t)` of `level` of the texture `tiles` belong to, both within the level's
+    /// resolution
+    pub fn texel<T: Texel>(&self, tiles: &TextureTiles, level: usize, s: usize, t: usize) -> T {
+        let layout = &tiles.layout;
+        let (tile_width, tile_height) = layout.tile_sizes[level];
+        let key = (tiles.id, level, s / tile_width, t / tile_height);
+
+        let tile = LAST_TILE.with(|last_tile| {
+            let mut last_tile = last_tile.borrow_mut();
+            match last_tile.as_ref() {
+                Some((cache, last_key, tile)) if *cache == self.id && *last_key == key => {
+                    Arc::clone(tile)
+                }
+                _ => {
+                    let tile = self.tile(layout, key);
+                    *last_tile = Some((self.id, key, Arc::clone(&tile)));
+                    tile
+                }
+            }
+        });
+
+        let offset = ((t % tile_height) * tile_width + s % tile_width) * layout.channels;
+        T::from_channels(&tile[offset..offset + layout.channels])
+    }
+}
+
+impl std::fmt::Debug for TextureCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TextureCache")
+            .field("budget", &self.budget())
+            .field("textures", &self.textures.read().unwrap().len())
+            .field("resident_size", &self.resident_size())
+            .finish()
+    }
+}
+
+impl Default for TextureCache {
+    /// an empty cache with the default budget that doesn't log
+    fn default() -> Self {
+        Self::new(&slog::Logger::root(slog::Discard, o!()), DEFAULT_BUDGET)
+    }
+}
+
+impl Drop for TextureCache {
+    fn drop(&mut self) {
+        for texture in self.textures.get_mut().unwrap().iter() {
+            if let Some(layout) = texture.layout.read().unwrap().as_ref() {
+                let _ = std::fs::remove_file(&layout.path);
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use super::{
+    pipeline::create_render_pipeline,
+    quad::{DEPTH_INDICES, DEPTH_VERTICES},
+    shaders, texture,
+    vertex::VertexPosTex,
+};
+use crate::common::Camera;
+use wgpu::util::DeviceExt;
+
+/// ratio of convergence distance to eye separation, the usual comfortable depth budget
+const CONVERGENCE_TO_INTEROCULAR: f32 = 30.0;
+
+/// stereo rig previewed by the anaglyph mode. unset values follow the camera: the eyes converge
+/// on the origin the cameras orbit around and are spread by a thirtieth of that distance
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StereoParameters {
+    pub interocular_distance: Option<f32>,
+    pub convergence_distance: Option<f32>,
+}
+
+impl StereoParameters {
+    /// horizontal offset of each eye from the camera and the distance of zero parallax
+    pub fn eye_offsets(&self, camera: &Camera) -> ([f32; 2], f32) {
+        let convergence = self
+            .convergence_distance
+            .unwrap_or_else(|| camera.cam_to_world.translation.vector.norm())
+            .max(1e-3);
+        let interocular = self
+            .interocular_distance
+            .unwrap_or(convergence / CONVERGENCE_TO_INTEROCULAR);
+
+        ([-0.5 * interocular, 0.5 * interocular], convergence)
+    }
+}
+
+/// composites the scene rendered from the left and right eye into a red/cyan anaglyph
+pub struct AnaglyphRenderPass {
+    render_pipeline: wgpu::RenderPipeline,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    pub eye_textures: [texture::Texture; 2],
+    texture_bind_group: wgpu::BindGroup,
+}
+
+impl AnaglyphRenderPass {
+    pub fn new(
+        device: &wgpu::Device,
+        compiler: &mut shaderc::Compiler,
+        sc_desc: &wgpu::SwapChainDescriptor,
+    ) -> Self {
+        let (vs_module, fs_module) = shaders::anaglyph::compile_shaders(compiler, &device);
+
+        let eye_texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::SampledTexture {
+                multisampled: false,
+                dimension: wgpu::TextureViewDimension::D2,
+                component_type: wgpu::TextureComponentType::Float,
+            },
+            count: None,
+        };
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    eye_texture_entry(0),
+                    eye_texture_entry(1),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler { comparison: false },
+                        count: None,
+                    },
+                ],
+                label: Some("anaglyph_bind_group_layout"),
+            });
+
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[&texture_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        let render_pipeline = create_render_pipeline::<VertexPosTex>(
+            &device,
+            render_pipeline_layout,
+            &vs_module,
+            &fs_module,
+            wgpu::PrimitiveTopology::TriangleList,
+            false,
+        );
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(DEPTH_VERTICES),
+            usage: wgpu::BufferUsage::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(DEPTH_INDICES),
+            usage: wgpu::BufferUsage::INDEX,
+        });
+
+        let eye_textures = Self::create_eye_textures(&device, &sc_desc);
+        let texture_bind_group =
+            Self::create_bind_group(&device, &texture_bind_group_layout, &eye_textures);
+
+        Self {
+            render_pipeline,
+            texture_bind_group_layout,
+            vertex_buffer,
+            index_buffer,
+            eye_textures,
+            texture_bind_group,
+        }
+    }
+
+    fn create_eye_textures(
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+    ) -> [texture::Texture; 2] {
+        [
+            texture::Texture::create_color_texture(&device, &sc_desc, "left_eye_texture"),
+            texture::Texture::create_color_texture(&device, &sc_desc, "right_eye_texture"),
+        ]
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        eye_textures: &[texture::Texture; 2],
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&eye_textures[0].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&eye_textures[1].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&eye_textures[0].sampler),
+                },
+            ],
+            label: Some("anaglyph_bind_group"),
+        })
+    }
+
+    /// the eye targets follow the size of the swap chain
+    pub fn resize(&mut self, device: &wgpu::Device, sc_desc: &wgpu::SwapChainDescriptor) {
+        self.eye_textures = Self::create_eye_textures(&device, &sc_desc);
+        self.texture_bind_group =
+            Self::create_bind_group(&device, &self.texture_bind_group_layout, &self.eye_textures);
+    }
+}
+
+pub trait DrawAnaglyph<'a, 'b>
+where
+    'b: 'a,
+{
+    fn draw_anaglyph(&mut self, anaglyph: &'b AnaglyphRenderPass);
+}
+
+impl<'a, 'b> DrawAnaglyph<'a, 'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_anaglyph(&mut self, anaglyph: &'b AnaglyphRenderPass) {
+        self.set_pipeline(&anaglyph.render_pipeline);
+        self.set_bind_group(0, &anaglyph.texture_bind_group, &[]);
+        self.set_vertex_buffer(0, anaglyph.vertex_buffer.slice(..));
+        self.set_index_buffer(anaglyph.index_buffer.slice(..));
+        self.draw_indexed(0..DEPTH_INDICES.len() as u32, 0, 0..1);
+    }
+}
@@ -20,6 +20,16 @@ pub enum CameraController {
     FirstPerson(FirstPersonCameraController),
 }
 
+/// camera at `cam_pos` looking at `pivot`, kept upright
+pub fn look_at_pivot(cam_pos: &glm::Vec3, pivot: &glm::Vec3) -> na::Isometry3<f32> {
+    na::Isometry3::look_at_rh(
+        &na::Point3::from(*cam_pos),
+        &na::Point3::from(*pivot),
+        &glm::vec3(0.0, 1.0, 0.0),
+    )
+    .inverse()
+}
+
 pub struct OrbitalCameraController {
     pivot: glm::Vec3,
     orbit_speed: f32,
@@ -80,12 +90,7 @@ impl CameraControllerInterface for OrbitalCameraController {
             * 0.01_f32.max(glm::length(&cam_pos) * (1.0 + self.scroll * self.zoom_speed * dt));
 
         cam_pos += &self.pivot; // retransform back to global frame
-        camera.cam_to_world = na::Isometry3::look_at_rh(
-            &na::Point3::from(cam_pos),
-            &na::Point3::from(self.pivot),
-            &glm::vec3(0.0, 1.0, 0.0),
-        )
-        .inverse();
+        camera.cam_to_world = look_at_pivot(&cam_pos, &self.pivot);
 
         trace!(self.log, "camera is now at: {:?}", camera.cam_to_world);
 
@@ -0,0 +1,35 @@
+use super::renderer::ViewerScene;
+use crate::common::{
+    exporter::{ExportedMaterial, ExportedMesh, SceneExport},
+    spectrum::Spectrum,
+};
+
+impl ViewerScene {
+    /// the preview meshes for writing to gltf, their materials only keep the tint of
+    /// transmissive meshes, drawn as clear glass
+    pub fn to_export(&self) -> SceneExport {
+        let mut export = SceneExport::default();
+        for mesh in &self.meshes {
+            let material = mesh.transmission.map(|tint| {
+                export.materials.push(ExportedMaterial {
+                    base_color: Spectrum::from_floats(tint.x, tint.y, tint.z),
+                    roughness: 0.0,
+                    transmission: 1.0,
+                    ..Default::default()
+                });
+                export.materials.len() - 1
+            });
+            export.meshes.push(ExportedMesh {
+                indices: mesh.indices.clone(),
+                pos: mesh.pos.clone(),
+                normal: mesh.normal.clone(),
+                uv: mesh.uv.clone(),
+                colors: mesh.colors.clone(),
+                material,
+                instances: mesh.instances.clone(),
+            });
+        }
+
+        export
+    }
+}
@@ -0,0 +1,427 @@
+use super::{renderer::Instance, shaders, texture::Texture};
+use crate::pathtracer::{integrator::GBuffer, RenderScene};
+use itertools::Itertools;
+use wgpu::util::DeviceExt;
+
+const GBUFFER_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+const GBUFFER_ID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Uint;
+/// both formats have four 32 bit channels
+const GBUFFER_TEXEL_SIZE: u32 = 4 * std::mem::size_of::<f32>() as u32;
+/// dynamic uniform offsets have to be aligned to this
+const MESH_ID_STRIDE: wgpu::BufferAddress = 256;
+
+/// render targets of one g-buffer resolution
+struct GBufferTargets {
+    width: u32,
+    height: u32,
+    position: wgpu::Texture,
+    normal: wgpu::Texture,
+    ids: wgpu::Texture,
+    depth: wgpu::Texture,
+}
+
+impl GBufferTargets {
+    fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let create_target = |label, format| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+            })
+        };
+
+        Self {
+            width,
+            height,
+            position: create_target("gbuffer_position", GBUFFER_FORMAT),
+            normal: create_target("gbuffer_normal", GBUFFER_FORMAT),
+            ids: create_target("gbuffer_ids", GBUFFER_ID_FORMAT),
+            depth: create_target("gbuffer_depth", Texture::DEPTH_FORMAT),
+        }
+    }
+}
+
+/// a mesh of the render scene, read by the vertex shader from storage buffers so that it knows
+/// which triangle it's drawing
+struct GBufferMesh {
+    mesh_id: u32,
+    num_indices: u32,
+    num_instances: u32,
+    bind_group: wgpu::BindGroup,
+}
+
+/// rasterizes world space positions, normals and the ids of the triangles of the first visible
+/// surfaces along with barycentrics on them, the primary visibility of hybrid renders. the
+/// meshes of the render scene are drawn rather than the ones of the viewer, so the ids name the
+/// triangles the path tracer intersects
+pub struct GBufferRenderPass {
+    render_pipeline: wgpu::RenderPipeline,
+    mesh_id_bind_group: wgpu::BindGroup,
+    meshes: Vec<GBufferMesh>,
+    targets: Option<GBufferTargets>,
+}
+
+impl GBufferRenderPass {
+    pub fn new(
+        device: &wgpu::Device,
+        compiler: &mut shaderc::Compiler,
+        uniform_bind_group_layout: &wgpu::BindGroupLayout,
+        scene: &RenderScene,
+    ) -> Self {
+        let (vs_module, fs_module) = shaders::gbuffer::compile_shaders(compiler, &device);
+
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStage::VERTEX,
+            ty: wgpu::BindingType::StorageBuffer {
+                dynamic: false,
+                readonly: true,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let mesh_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[storage_entry(0), storage_entry(1), storage_entry(2)],
+                label: Some("gbuffer_mesh_bind_group_layout"),
+            });
+
+        let gbuffer_meshes = scene.gbuffer_meshes();
+        let meshes = gbuffer_meshes
+            .iter()
+            .enumerate()
+            .filter(|(_, (mesh, instances))| !mesh.indices.is_empty() && !instances.is_empty())
+            .map(|(idx, (mesh, instances))| {
+                let positions = mesh
+                    .pos
+                    .iter()
+                    .flat_map(|p| p.iter().copied())
+                    .collect_vec();
+                let indices = mesh
+                    .indices
+                    .iter()
+                    .flat_map(|indices| indices.iter().copied())
+                    .collect_vec();
+                let instance_data = instances
+                    .iter()
+                    .map(|obj_to_world| Instance {
+                        model: obj_to_world.to_homogeneous(),
+                    })
+                    .collect_vec();
+
+                let create_storage = |label, contents: &[u8]| {
+                    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                        label: Some(label),
+                        contents,
+                        usage: wgpu::BufferUsage::STORAGE,
+                    })
+                };
+                let position_buffer =
+                    create_storage("gbuffer_positions", bytemuck::cast_slice(&positions));
+                let index_buffer =
+                    create_storage("gbuffer_indices", bytemuck::cast_slice(&indices));
+                let instance_buffer =
+                    create_storage("gbuffer_instances", bytemuck::cast_slice(&instance_data));
+
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &mesh_bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::Buffer(position_buffer.slice(..)),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Buffer(index_buffer.slice(..)),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::Buffer(instance_buffer.slice(..)),
+                        },
+                    ],
+                    label: Some("gbuffer_mesh_bind_group"),
+                });
+
+                GBufferMesh {
+                    mesh_id: idx as u32 + 1,
+                    num_indices: indices.len() as u32,
+                    num_instances: instances.len() as u32,
+                    bind_group,
+                }
+            })
+            .collect_vec();
+
+        // one id per mesh, picked with a dynamic offset. ids start at one so that the cleared
+        // targets read as background
+        let num_meshes = gbuffer_meshes.len().max(1);
+        let mut mesh_ids = vec![0u8; num_meshes * MESH_ID_STRIDE as usize];
+        for (idx, mesh_id) in mesh_ids
+            .chunks_exact_mut(MESH_ID_STRIDE as usize)
+            .enumerate()
+        {
+            mesh_id[..4].copy_from_slice(&(idx as u32 + 1).to_ne_bytes());
+        }
+        let mesh_id_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("mesh_id_buffer"),
+            contents: &mesh_ids,
+            usage: wgpu::BufferUsage::UNIFORM,
+        });
+
+        let mesh_id_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::UniformBuffer {
+                        dynamic: true,
+                        min_binding_size: wgpu::BufferSize::new(4),
+                    },
+                    count: None,
+                }],
+                label: Some("mesh_id_bind_group_layout"),
+            });
+        let mesh_id_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &mesh_id_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(mesh_id_buffer.slice(0..4)),
+            }],
+            label: Some("mesh_id_bind_group"),
+        });
+
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[
+                    &uniform_bind_group_layout,
+                    &mesh_bind_group_layout,
+                    &mesh_id_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let gbuffer_state = wgpu::ColorStateDescriptor {
+            format: GBUFFER_FORMAT,
+            color_blend: wgpu::BlendDescriptor::REPLACE,
+            alpha_blend: wgpu::BlendDescriptor::REPLACE,
+            write_mask: wgpu::ColorWrite::ALL,
+        };
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("gbuffer_pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+                clamp_depth: false,
+            }),
+            color_states: &[
+                gbuffer_state.clone(),
+                gbuffer_state.clone(),
+                wgpu::ColorStateDescriptor {
+                    format: GBUFFER_ID_FORMAT,
+                    ..gbuffer_state
+                },
+            ],
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilStateDescriptor::default(),
+            }),
+            // the vertex shader reads the triangles itself
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint32,
+                vertex_buffers: &[],
+            },
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+
+        Self {
+            render_pipeline,
+            mesh_id_bind_group,
+            meshes,
+            targets: None,
+        }
+    }
+
+    /// rasterizes the meshes at `resolution` with the camera in `uniform_bind_group` and reads
+    /// the result back, blocking until the gpu is done
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        uniform_bind_group: &wgpu::BindGroup,
+        resolution: &glm::UVec2,
+    ) -> GBuffer {
+        let (width, height) = (resolution.x, resolution.y);
+        if self.targets.as_ref().map_or(true, |targets| {
+            targets.width != width || targets.height != height
+        }) {
+            self.targets = Some(GBufferTargets::new(device, width, height));
+        }
+        let targets = self.targets.as_ref().unwrap();
+        let position_view = targets
+            .position
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let normal_view = targets
+            .normal
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let ids_view = targets
+            .ids
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let depth_view = targets
+            .depth
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("GBuffer Encoder"),
+        });
+
+        {
+            let clear_target = |attachment| wgpu::RenderPassColorAttachmentDescriptor {
+                attachment,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            };
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[
+                    clear_target(&position_view),
+                    clear_target(&normal_view),
+                    clear_target(&ids_view),
+                ],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                    attachment: &depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+            render_pass.set_pipeline(&self.render_pipeline);
+            render_pass.set_bind_group(0, uniform_bind_group, &[]);
+            for mesh in &self.meshes {
+                render_pass.set_bind_group(1, &mesh.bind_group, &[]);
+                render_pass.set_bind_group(
+                    2,
+                    &self.mesh_id_bind_group,
+                    &[((mesh.mesh_id - 1) as wgpu::BufferAddress * MESH_ID_STRIDE) as u32],
+                );
+                render_pass.draw(0..mesh.num_indices, 0..mesh.num_instances);
+            }
+        }
+
+        // rows of buffer copies have to be aligned
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let bytes_per_row = (width * GBUFFER_TEXEL_SIZE + align - 1) / align * align;
+        let mut create_readback = |label, texture| {
+            let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: (bytes_per_row * height) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
+                mapped_at_creation: false,
+            });
+            encoder.copy_texture_to_buffer(
+                wgpu::TextureCopyView {
+                    texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                wgpu::BufferCopyView {
+                    buffer: &buffer,
+                    layout: wgpu::TextureDataLayout {
+                        offset: 0,
+                        bytes_per_row,
+                        rows_per_image: height,
+                    },
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth: 1,
+                },
+            );
+            buffer
+        };
+        let position_buffer = create_readback("gbuffer_position_readback", &targets.position);
+        let normal_buffer = create_readback("gbuffer_normal_readback", &targets.normal);
+        let ids_buffer = create_readback("gbuffer_ids_readback", &targets.ids);
+        queue.submit(Some(encoder.finish()));
+
+        let positions = read_texels::<[f32; 4]>(device, &position_buffer, width, bytes_per_row);
+        let normals = read_texels::<[f32; 4]>(device, &normal_buffer, width, bytes_per_row);
+        let ids = read_texels::<[u32; 4]>(device, &ids_buffer, width, bytes_per_row);
+
+        GBuffer {
+            resolution: *resolution,
+            position: positions
+                .iter()
+                .map(|p| na::Point3::new(p[0], p[1], p[2]))
+                .collect(),
+            normal: normals
+                .iter()
+                .map(|n| na::Vector3::new(n[0], n[1], n[2]))
+                .collect(),
+            mesh_id: ids.iter().map(|id| id[0]).collect(),
+            instance: ids.iter().map(|id| id[1]).collect(),
+            triangle: ids.iter().map(|id| id[2]).collect(),
+            // the barycentrics ride along in the w of the positions and normals
+            barycentrics: positions
+                .iter()
+                .zip(normals.iter())
+                .map(|(p, n)| na::Point2::new(p[3], n[3]))
+                .collect(),
+        }
+    }
+}
+
+/// the texels of a readback buffer, dropping the padding at the end of its rows
+fn read_texels<T: bytemuck::Pod>(
+    device: &wgpu::Device,
+    buffer: &wgpu::Buffer,
+    width: u32,
+    bytes_per_row: u32,
+) -> Vec<T> {
+    let slice = buffer.slice(..);
+    let mapping = slice.map_async(wgpu::MapMode::Read);
+    device.poll(wgpu::Maintain::Wait);
+    futures::executor::block_on(mapping).unwrap();
+
+    let texels = {
+        let data = slice.get_mapped_range();
+        data.chunks_exact(bytes_per_row as usize)
+            .flat_map(|row| {
+                bytemuck::cast_slice::<u8, T>(&row[..(width * GBUFFER_TEXEL_SIZE) as usize])
+                    .to_vec()
+            })
+            .collect::<Vec<_>>()
+    };
+    buffer.unmap();
+    texels
+}
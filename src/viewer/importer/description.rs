@@ -0,0 +1,35 @@
+use crate::common::importer::{description::PlacedMesh, pbrt};
+use crate::viewer::renderer::{Mesh, ViewerScene};
+
+impl ViewerScene {
+    pub fn from_description(meshes: Vec<PlacedMesh>) -> Self {
+        let meshes = meshes
+            .into_iter()
+            .map(|placed| {
+                let mesh = placed.mesh;
+                let normal = if mesh.normal.is_empty() {
+                    pbrt::vertex_normals(&mesh.indices, &mesh.pos)
+                } else {
+                    mesh.normal
+                };
+                Mesh {
+                    id: 0,
+                    indices: mesh
+                        .indices
+                        .iter()
+                        .flat_map(|s| vec![s[0], s[1], s[2]])
+                        .collect(),
+                    pos: mesh.pos,
+                    normal,
+                    s: vec![],
+                    uv: vec![],
+                    colors: mesh.colors,
+                    transmission: None,
+                    instances: vec![placed.obj_to_world],
+                }
+            })
+            .collect();
+
+        Self { meshes }
+    }
+}
@@ -4,6 +4,24 @@ use crate::{
 };
 use std::collections::HashMap;
 
+/// tint used by the preview for transmissive materials, mirroring when the pathtracer picks glass
+fn transmission_from_gltf(gltf_material: &gltf::Material) -> Option<na::Vector3<f32>> {
+    let pbr = gltf_material.pbr_metallic_roughness();
+    let base_color = glm::make_vec3(&pbr.base_color_factor()[..3]);
+    let alpha = pbr.base_color_factor()[3];
+
+    let transmission_factor = gltf_material
+        .transmission()
+        .map_or(0.0, |transmission| transmission.transmission_factor());
+    if transmission_factor > 0.0 {
+        Some(base_color)
+    } else if gltf_material.alpha_mode() == gltf::material::AlphaMode::Blend && alpha < 1.0 {
+        Some(glm::vec3(1.0, 1.0, 1.0) - alpha * base_color)
+    } else {
+        None
+    }
+}
+
 fn mesh_from_gltf(gltf_prim: &gltf::Primitive, buffers: &[gltf::buffer::Data]) -> Mesh {
     let prim_pos_accessor_idx = gltf_prim.get(&gltf::Semantic::Positions).unwrap().index();
 
@@ -38,6 +56,7 @@ fn mesh_from_gltf(gltf_prim: &gltf::Primitive, buffers: &[gltf::buffer::Data]) -
                 .collect(),
             None => vec![],
         },
+        transmission: transmission_from_gltf(&gltf_prim.material()),
         instances: vec![],
     }
 }
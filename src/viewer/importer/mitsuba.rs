@@ -1,6 +1,39 @@
-use crate::common::importer::mitsuba;
+use crate::common::importer::{mitsuba, pbrt, ply};
 use crate::viewer::renderer::{Mesh, ViewerScene};
 
+/// tint used by the preview for transmissive bsdfs, `None` for everything opaque
+fn transmission_from_bsdf(bsdf: &mitsuba::BSDF) -> Option<na::Vector3<f32>> {
+    match bsdf {
+        mitsuba::BSDF::Dielectric(bsdf) | mitsuba::BSDF::RoughDielectric(bsdf) => Some(
+            bsdf.rgb_params
+                .get("specular_transmittance")
+                .map_or(glm::vec3(1.0, 1.0, 1.0), |rgb| glm::make_vec3(rgb)),
+        ),
+        mitsuba::BSDF::TwoSided(bsdf) => transmission_from_bsdf(&bsdf.bsdf),
+        mitsuba::BSDF::BumpMap(bsdf) => transmission_from_bsdf(&bsdf.bsdf),
+        mitsuba::BSDF::Coating(bsdf) => transmission_from_bsdf(&bsdf.bsdf),
+        mitsuba::BSDF::Blend(bsdf) => bsdf.bsdf.iter().find_map(transmission_from_bsdf),
+        _ => None,
+    }
+}
+
+fn transmission_from_shape(
+    scene: &mitsuba::Scene,
+    material: &Option<mitsuba::Reference>,
+    bsdf: &Option<mitsuba::BSDF>,
+) -> Option<na::Vector3<f32>> {
+    if let Some(bsdf) = bsdf {
+        transmission_from_bsdf(bsdf)
+    } else if let Some(material) = material {
+        scene
+            .bsdfs
+            .get(&material.id)
+            .and_then(transmission_from_bsdf)
+    } else {
+        None
+    }
+}
+
 impl ViewerScene {
     pub fn from_mitsuba(scene: &mitsuba::Scene) -> Self {
         let mut meshes = vec![];
@@ -9,8 +42,8 @@ impl ViewerScene {
             match shape {
                 mitsuba::Shape::Rectangle {
                     transform,
-                    material: _,
-                    bsdf: _,
+                    material,
+                    bsdf,
                     emitter: _,
                 } => {
                     let generated_mesh = mitsuba::gen_rectangle();
@@ -26,13 +59,14 @@ impl ViewerScene {
                         s: vec![],
                         uv: vec![],
                         colors: vec![],
+                        transmission: transmission_from_shape(scene, material, bsdf),
                         instances: vec![*transform],
                     })
                 }
                 mitsuba::Shape::Cube {
                     transform,
-                    material: _,
-                    bsdf: _,
+                    material,
+                    bsdf,
                     emitter: _,
                 } => {
                     let generated_mesh = mitsuba::gen_cube();
@@ -48,14 +82,62 @@ impl ViewerScene {
                         s: vec![],
                         uv: vec![],
                         colors: vec![],
+                        transmission: transmission_from_shape(scene, material, bsdf),
+                        instances: vec![*transform],
+                    })
+                }
+                mitsuba::Shape::Disk {
+                    transform,
+                    material,
+                    bsdf,
+                    emitter: _,
+                } => {
+                    let generated_mesh = mitsuba::gen_disk();
+                    meshes.push(Mesh {
+                        id: 0,
+                        indices: generated_mesh
+                            .indices
+                            .iter()
+                            .flat_map(|s| vec![s[0], s[1], s[2]])
+                            .collect(),
+                        pos: generated_mesh.pos,
+                        normal: generated_mesh.normal,
+                        s: vec![],
+                        uv: vec![],
+                        colors: vec![],
+                        transmission: transmission_from_shape(scene, material, bsdf),
+                        instances: vec![*transform],
+                    })
+                }
+                mitsuba::Shape::Cylinder {
+                    transform,
+                    radius,
+                    material,
+                    bsdf,
+                    emitter: _,
+                } => {
+                    let generated_mesh = mitsuba::gen_cylinder(radius.value);
+                    meshes.push(Mesh {
+                        id: 0,
+                        indices: generated_mesh
+                            .indices
+                            .iter()
+                            .flat_map(|s| vec![s[0], s[1], s[2]])
+                            .collect(),
+                        pos: generated_mesh.pos,
+                        normal: generated_mesh.normal,
+                        s: vec![],
+                        uv: vec![],
+                        colors: vec![],
+                        transmission: transmission_from_shape(scene, material, bsdf),
                         instances: vec![*transform],
                     })
                 }
                 mitsuba::Shape::Sphere {
                     point,
                     radius,
-                    material: _,
-                    bsdf: _,
+                    material,
+                    bsdf,
                     emitter: _,
                 } => {
                     let generated_mesh = mitsuba::gen_sphere(&point, radius.value);
@@ -71,18 +153,41 @@ impl ViewerScene {
                         s: vec![],
                         uv: vec![],
                         colors: vec![],
+                        transmission: transmission_from_shape(scene, material, bsdf),
                         instances: vec![na::Projective3::identity()],
                     })
                 }
                 mitsuba::Shape::Obj {
                     transform,
                     face_normals: _,
-                    material: _,
-                    bsdf: _,
+                    material,
+                    bsdf,
+                    emitter: _,
+                    filename,
+                }
+                | mitsuba::Shape::Ply {
+                    transform,
+                    face_normals: _,
+                    material,
+                    bsdf,
                     emitter: _,
                     filename,
                 } => {
-                    let obj_mesh = mitsuba::load_obj(&scene.path, filename);
+                    let path = scene.resolver.resolve(filename);
+                    let obj_mesh = if let mitsuba::Shape::Ply { .. } = shape {
+                        // the render scene already warned about the meshes that can't be read
+                        match ply::load_ply(&path) {
+                            Ok(mesh) => mesh,
+                            Err(_) => continue,
+                        }
+                    } else {
+                        mitsuba::load_obj(&path)
+                    };
+                    let normal = if obj_mesh.normal.is_empty() {
+                        pbrt::vertex_normals(&obj_mesh.indices, &obj_mesh.pos)
+                    } else {
+                        obj_mesh.normal
+                    };
                     meshes.push(Mesh {
                         id: 0,
                         indices: obj_mesh
@@ -91,13 +196,17 @@ impl ViewerScene {
                             .flat_map(|s| vec![s[0], s[1], s[2]])
                             .collect(),
                         pos: obj_mesh.pos,
-                        normal: obj_mesh.normal,
+                        normal,
                         s: vec![],
                         uv: vec![],
-                        colors: vec![],
+                        colors: obj_mesh.colors,
+                        transmission: transmission_from_shape(scene, material, bsdf),
                         instances: vec![*transform],
                     })
                 }
+                // curves aren't previewed, the strands only show up in the rendered image
+                mitsuba::Shape::Hair { .. } => {}
+                mitsuba::Shape::Unsupported => {}
             }
         }
 
@@ -1,2 +1,4 @@
+pub mod description;
 pub mod gltf;
 pub mod mitsuba;
+pub mod pbrt;
@@ -0,0 +1,71 @@
+use crate::common::importer::pbrt;
+use crate::viewer::renderer::{Mesh, ViewerScene};
+
+/// tint used by the preview for transmissive materials, `None` for everything opaque
+fn transmission_from_material(
+    scene: &pbrt::Scene,
+    material: &pbrt::Material,
+) -> Option<na::Vector3<f32>> {
+    let params = &material.params;
+    match material.kind.as_str() {
+        "glass" | "dielectric" | "thindielectric" => Some(
+            params
+                .rgb("Kt")
+                .map_or(glm::vec3(1.0, 1.0, 1.0), |rgb| glm::make_vec3(&rgb)),
+        ),
+        "interface" | "none" | "" => Some(glm::vec3(1.0, 1.0, 1.0)),
+        "mix" => {
+            let names: Vec<&str> = match params.strings("materials") {
+                Some(names) => names.iter().map(String::as_str).collect(),
+                None => ["namedmaterial1", "namedmaterial2"]
+                    .iter()
+                    .filter_map(|name| params.string(name))
+                    .collect(),
+            };
+            names
+                .into_iter()
+                .filter_map(|name| scene.material_index(name))
+                .find_map(|index| transmission_from_material(scene, &scene.materials[index]))
+        }
+        _ => None,
+    }
+}
+
+impl ViewerScene {
+    pub fn from_pbrt(scene: &pbrt::Scene) -> Self {
+        // the render scene already warned about the shapes that can't be tessellated
+        let log = slog::Logger::root(slog::Discard, o!());
+        let mut meshes = vec![];
+
+        for shape in &scene.shapes {
+            // curves aren't previewed, the strands only show up in the rendered image
+            if let Some(mesh) = pbrt::shape_mesh(&log, &scene.resolver, shape) {
+                let normal = if mesh.normal.is_empty() {
+                    pbrt::vertex_normals(&mesh.indices, &mesh.pos)
+                } else {
+                    mesh.normal
+                };
+                meshes.push(Mesh {
+                    id: 0,
+                    indices: mesh
+                        .indices
+                        .iter()
+                        .flat_map(|s| vec![s[0], s[1], s[2]])
+                        .collect(),
+                    pos: mesh.pos,
+                    normal,
+                    s: vec![],
+                    uv: vec![],
+                    colors: mesh.colors,
+                    transmission: transmission_from_material(
+                        scene,
+                        &scene.materials[shape.material],
+                    ),
+                    instances: vec![shape.obj_to_world],
+                })
+            }
+        }
+
+        Self { meshes }
+    }
+}
@@ -1,8 +1,11 @@
 use super::vertex::VertexPosNorm;
 use super::{
-    pipeline::create_render_pipeline,
+    pipeline::{
+        create_blended_render_pipeline, create_depth_render_pipeline, create_render_pipeline,
+    },
     renderer::{Instance, Mesh, ViewerScene},
     shaders,
+    texture::Texture,
 };
 use itertools::{zip_eq, Itertools};
 use wgpu::util::DeviceExt;
@@ -89,9 +92,81 @@ impl MeshInstancesHandle {
     }
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct Translucency {
+    tint: glm::Vec4,
+}
+
+unsafe impl bytemuck::Zeroable for Translucency {}
+
+unsafe impl bytemuck::Pod for Translucency {}
+
+impl Translucency {
+    fn create_bind_group_layout_entry() -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::UniformBuffer {
+                dynamic: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    }
+}
+
+pub struct TranslucentMeshInstancesHandle {
+    pub mesh_instances: MeshInstancesHandle,
+    pub translucency_buffer: wgpu::Buffer,
+    pub translucency_bind_group: wgpu::BindGroup,
+}
+
+impl TranslucentMeshInstancesHandle {
+    pub fn new(
+        device: &wgpu::Device,
+        translucency_bind_group_layout: &wgpu::BindGroupLayout,
+        tint: &na::Vector3<f32>,
+        mesh_instances: MeshInstancesHandle,
+    ) -> Self {
+        let translucency = Translucency {
+            tint: glm::vec4(tint.x, tint.y, tint.z, 1.0),
+        };
+        let translucency_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&[translucency]),
+            usage: wgpu::BufferUsage::UNIFORM,
+        });
+
+        let translucency_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &translucency_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(translucency_buffer.slice(..)),
+            }],
+            label: Some("translucency_bind_group"),
+        });
+
+        TranslucentMeshInstancesHandle {
+            mesh_instances,
+            translucency_buffer,
+            translucency_bind_group,
+        }
+    }
+}
+
 pub struct MeshRenderPass {
+    pub instances_bind_group_layout: wgpu::BindGroupLayout,
     render_pipeline: wgpu::RenderPipeline,
     draw_mesh_instances: Vec<MeshInstancesHandle>,
+    translucent_render_pipeline: wgpu::RenderPipeline,
+    draw_translucent_mesh_instances: Vec<TranslucentMeshInstancesHandle>,
+    /// depth of the nearest translucent back faces, how far light travels through the meshes
+    pub back_depth: Texture,
+    back_depth_render_pipeline: wgpu::RenderPipeline,
+    back_depth_sampler: wgpu::Sampler,
+    back_depth_bind_group_layout: wgpu::BindGroupLayout,
+    back_depth_bind_group: wgpu::BindGroup,
 }
 
 impl MeshRenderPass {
@@ -100,6 +175,7 @@ impl MeshRenderPass {
         compiler: &mut shaderc::Compiler,
         uniform_bind_group_layout: &wgpu::BindGroupLayout,
         scene: &ViewerScene,
+        sc_desc: &wgpu::SwapChainDescriptor,
     ) -> Self {
         let (vs_module, fs_module) = shaders::phong::compile_shaders(compiler, &device);
 
@@ -109,18 +185,32 @@ impl MeshRenderPass {
                 label: Some("instances_bind_group_layout"),
             });
 
-        let draw_mesh_instances = scene
-            .meshes
-            .iter()
-            .map(|mesh| {
-                MeshInstancesHandle::new(
+        let translucency_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[Translucency::create_bind_group_layout_entry()],
+                label: Some("translucency_bind_group_layout"),
+            });
+
+        let mut draw_mesh_instances = vec![];
+        let mut draw_translucent_mesh_instances = vec![];
+        for mesh in &scene.meshes {
+            let mesh_instances = MeshInstancesHandle::new(
+                &device,
+                &instances_bind_group_layout,
+                &mesh.instances,
+                MeshHandle::from_mesh(&device, &mesh),
+            );
+            if let Some(tint) = &mesh.transmission {
+                draw_translucent_mesh_instances.push(TranslucentMeshInstancesHandle::new(
                     &device,
-                    &instances_bind_group_layout,
-                    &mesh.instances,
-                    MeshHandle::from_mesh(&device, &mesh),
-                )
-            })
-            .collect_vec();
+                    &translucency_bind_group_layout,
+                    tint,
+                    mesh_instances,
+                ));
+            } else {
+                draw_mesh_instances.push(mesh_instances);
+            }
+        }
 
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -138,11 +228,131 @@ impl MeshRenderPass {
             true,
         );
 
+        let (translucent_vs_module, translucent_fs_module) =
+            shaders::translucent::compile_shaders(compiler, &device);
+
+        let back_depth_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::SampledTexture {
+                            multisampled: false,
+                            dimension: wgpu::TextureViewDimension::D2,
+                            component_type: wgpu::TextureComponentType::Float,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler { comparison: false },
+                        count: None,
+                    },
+                ],
+                label: Some("back_depth_bind_group_layout"),
+            });
+
+        let translucent_render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[
+                    &uniform_bind_group_layout,
+                    &instances_bind_group_layout,
+                    &translucency_bind_group_layout,
+                    &back_depth_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        // only the front faces are drawn, each covering the whole path through the mesh
+        let translucent_render_pipeline = create_blended_render_pipeline::<VertexPosNorm>(
+            &device,
+            translucent_render_pipeline_layout,
+            &translucent_vs_module,
+            &translucent_fs_module,
+            wgpu::PrimitiveTopology::TriangleList,
+            wgpu::CullMode::Back,
+        );
+
+        let back_depth_render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[&uniform_bind_group_layout, &instances_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        let back_depth_render_pipeline = create_depth_render_pipeline::<VertexPosNorm>(
+            &device,
+            back_depth_render_pipeline_layout,
+            &translucent_vs_module,
+            wgpu::CullMode::Front,
+        );
+
+        // the depth is fetched texel by texel, the sampler only has to be there
+        let back_depth_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("back_depth_sampler"),
+            ..Default::default()
+        });
+        let back_depth = Texture::create_depth_texture(&device, &sc_desc, "back_depth_texture");
+        let back_depth_bind_group = Self::create_back_depth_bind_group(
+            &device,
+            &back_depth_bind_group_layout,
+            &back_depth,
+            &back_depth_sampler,
+        );
+
         MeshRenderPass {
+            instances_bind_group_layout,
             draw_mesh_instances,
             render_pipeline,
+            draw_translucent_mesh_instances,
+            translucent_render_pipeline,
+            back_depth,
+            back_depth_render_pipeline,
+            back_depth_sampler,
+            back_depth_bind_group_layout,
+            back_depth_bind_group,
         }
     }
+
+    fn create_back_depth_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        back_depth: &Texture,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&back_depth.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+            label: Some("back_depth_bind_group"),
+        })
+    }
+
+    /// the back depth follows the size of the swap chain
+    pub fn resize(&mut self, device: &wgpu::Device, sc_desc: &wgpu::SwapChainDescriptor) {
+        self.back_depth = Texture::create_depth_texture(&device, &sc_desc, "back_depth_texture");
+        self.back_depth_bind_group = Self::create_back_depth_bind_group(
+            &device,
+            &self.back_depth_bind_group_layout,
+            &self.back_depth,
+            &self.back_depth_sampler,
+        );
+    }
+
+    pub fn has_translucent(&self) -> bool {
+        !self.draw_translucent_mesh_instances.is_empty()
+    }
 }
 
 pub trait DrawMesh<'a, 'b>
@@ -151,6 +361,7 @@ where
 {
     fn draw_mesh_instances(&mut self, mesh: &'b MeshInstancesHandle);
     fn draw_all_mesh(&mut self, mesh: &'b MeshRenderPass);
+    fn draw_back_depth(&mut self, mesh: &'b MeshRenderPass);
 }
 
 impl<'a, 'b> DrawMesh<'a, 'b> for wgpu::RenderPass<'a>
@@ -174,5 +385,21 @@ where
         for mesh_instance in &meshes.draw_mesh_instances {
             self.draw_mesh_instances(&mesh_instance);
         }
+
+        // translucent meshes are blended over the opaque ones, so they have to come last
+        self.set_pipeline(&meshes.translucent_render_pipeline);
+        self.set_bind_group(3, &meshes.back_depth_bind_group, &[]);
+        for translucent_mesh_instance in &meshes.draw_translucent_mesh_instances {
+            self.set_bind_group(2, &translucent_mesh_instance.translucency_bind_group, &[]);
+            self.draw_mesh_instances(&translucent_mesh_instance.mesh_instances);
+        }
+    }
+
+    /// the depth pre-pass sampled by the translucent meshes, drawn into `meshes.back_depth`
+    fn draw_back_depth(&mut self, meshes: &'b MeshRenderPass) {
+        self.set_pipeline(&meshes.back_depth_render_pipeline);
+        for translucent_mesh_instance in &meshes.draw_translucent_mesh_instances {
+            self.draw_mesh_instances(&translucent_mesh_instance.mesh_instances);
+        }
     }
 }
@@ -1,5 +1,8 @@
+pub mod anaglyph;
 mod bounds;
 pub mod camera;
+mod exporter;
+mod gbuffer;
 pub mod importer;
 mod mesh;
 mod pipeline;
@@ -10,14 +13,18 @@ mod texture;
 mod vertex;
 mod wireframe;
 
-use crate::common::{new_drain, Camera};
-use crate::pathtracer::{integrator::PathIntegrator, sampler::SamplerBuilder, RenderScene};
+use crate::common::{bounds::Bounds2i, film::Film, new_drain, Camera};
+use crate::pathtracer::{
+    integrator::{PathIntegrator, TILE_SIZE},
+    RenderScene,
+};
 use crossbeam::scope;
 use renderer::{Renderer, ViewerScene};
-use std::sync::RwLock;
+use std::sync::{Mutex, RwLock};
 use std::{
     collections::{hash_map::RandomState, HashMap, HashSet},
     sync::atomic::AtomicBool,
+    sync::atomic::AtomicUsize,
     sync::atomic::Ordering,
 };
 use std::{path::PathBuf, time::Instant};
@@ -29,6 +36,64 @@ use winit::{
     window::WindowBuilder,
 };
 
+const TILE_GRID_COLOR: [u8; 3] = [96, 96, 96];
+const ACTIVE_TILE_COLOR: [u8; 3] = [255, 160, 0];
+/// heat maps cycled through with ctrl+m: none, bvh nodes visited and primitives tested
+const HEAT_MAPS: usize = 3;
+
+/// draws the outline of `bounds` onto the image, clipped to the image
+fn draw_tile_outline(image: &mut image::RgbaImage, bounds: &Bounds2i, color: [u8; 3]) {
+    let (width, height) = (image.width() as i32, image.height() as i32);
+    let x0 = bounds.p_min.x.max(0);
+    let x1 = (bounds.p_max.x - 1).min(width - 1);
+    let y0 = bounds.p_min.y.max(0);
+    let y1 = (bounds.p_max.y - 1).min(height - 1);
+    if x0 > x1 || y0 > y1 {
+        return;
+    }
+
+    let pixel = image::Rgba([color[0], color[1], color[2], 255]);
+    for x in x0..=x1 {
+        for &y in &[bounds.p_min.y, bounds.p_max.y - 1] {
+            if y >= 0 && y < height {
+                image.put_pixel(x as u32, y as u32, pixel);
+            }
+        }
+    }
+    for y in y0..=y1 {
+        for &x in &[bounds.p_min.x, bounds.p_max.x - 1] {
+            if x >= 0 && x < width {
+                image.put_pixel(x as u32, y as u32, pixel);
+            }
+        }
+    }
+}
+
+/// converts the film to an image with the tile grid drawn over it and the tiles currently
+/// being rendered highlighted
+fn film_image_with_tiles(film: &Film) -> image::RgbaImage {
+    let mut image = film.to_rgba_image();
+    for tile in film.tiles(TILE_SIZE) {
+        draw_tile_outline(&mut image, &tile, TILE_GRID_COLOR);
+    }
+    for tile in film.active_tiles() {
+        draw_tile_outline(&mut image, &tile, ACTIVE_TILE_COLOR);
+    }
+    image
+}
+
+/// heat map of the bvh traversal of the last render shown in place of the film, `None` for the
+/// film itself
+fn heat_map_image(integrator: &PathIntegrator, heat_map: usize) -> Option<image::RgbaImage> {
+    let ray_stats = integrator.ray_stats()?;
+    let values = match heat_map {
+        1 => ray_stats.average_nodes_visited(),
+        2 => ray_stats.average_primitives_tested(),
+        _ => return None,
+    };
+    Some(ray_stats.to_heat_map(&values))
+}
+
 pub fn run(
     log: slog::Logger,
     resolution: &na::Vector2<f32>,
@@ -43,6 +108,7 @@ pub fn run(
     max_depth: i32,
     init_log_level: slog::Level,
     allowed_modules: Option<HashMap<String, HashSet<String, RandomState>, RandomState>>,
+    stereo: anaglyph::StereoParameters,
 ) {
     let camera = RwLock::new(camera);
     let integrator = RwLock::new(integrator);
@@ -82,8 +148,10 @@ pub fn run(
             &log,
             &window,
             &viewer_scene,
+            &render_scene,
             &camera,
             camera_controller,
+            stereo,
         ));
     }
 
@@ -93,7 +161,13 @@ pub fn run(
     let mut trace_mode = false;
     let mut cursor_position: winit::dpi::PhysicalPosition<f64> =
         winit::dpi::PhysicalPosition::new(0.0, 0.0);
+    // where the left button went down over the render, dragging out a crop window
+    let mut crop_start: Option<winit::dpi::PhysicalPosition<f64>> = None;
     let (tx, rx) = crossbeam::channel::unbounded();
+    let draw_tiles = AtomicBool::new(false);
+    let heat_map = AtomicUsize::new(0);
+    // primary visibility for the next render, set for hybrid renders
+    let gbuffer = Mutex::new(None);
 
     scope(|s| {
         let render_closure = |_: &crossbeam::thread::Scope| {
@@ -101,18 +175,36 @@ pub fn run(
             scope(|s| {
                 s.spawn(|_| {
                     let camera = camera.read().unwrap();
+                    let integrator = integrator.read().unwrap();
                     while !rendering_done.load(Ordering::Relaxed) {
-                        tx.send(camera.film.to_rgba_image()).unwrap();
-                        std::thread::sleep(std::time::Duration::from_secs(2));
+                        if let Some(image) =
+                            heat_map_image(&integrator, heat_map.load(Ordering::Relaxed))
+                        {
+                            tx.send(image).unwrap();
+                            std::thread::sleep(std::time::Duration::from_secs(2));
+                        } else if draw_tiles.load(Ordering::Relaxed) {
+                            tx.send(film_image_with_tiles(&camera.film)).unwrap();
+                            std::thread::sleep(std::time::Duration::from_millis(250));
+                        } else {
+                            tx.send(camera.film.to_rgba_image()).unwrap();
+                            std::thread::sleep(std::time::Duration::from_secs(2));
+                        }
                     }
 
-                    tx.send(camera.film.to_rgba_image()).unwrap();
+                    tx.send(
+                        heat_map_image(&integrator, heat_map.load(Ordering::Relaxed))
+                            .unwrap_or_else(|| camera.film.to_rgba_image()),
+                    )
+                    .unwrap();
                 });
 
                 let camera = camera.read().unwrap();
                 let integrator = integrator.read().unwrap();
 
-                integrator.render(&camera, &render_scene);
+                match gbuffer.lock().unwrap().take() {
+                    Some(gbuffer) => integrator.render_hybrid(&camera, &render_scene, &gbuffer),
+                    None => integrator.render(&camera, &render_scene),
+                }
                 rendering_done.store(true, Ordering::Relaxed);
             })
             .unwrap();
@@ -148,6 +240,7 @@ pub fn run(
                                     ..
                                 } => {
                                     let camera = camera.read().unwrap();
+                                    camera.film.reset_crop_window();
                                     camera.film.clear();
                                     viewer.state = renderer::ViewerState::RenderImage;
                                     s.spawn(render_closure);
@@ -157,6 +250,21 @@ pub fn run(
                                     virtual_keycode: Some(VirtualKeyCode::C),
                                     ..
                                 } => viewer.state = renderer::ViewerState::RenderScene,
+                                KeyboardInput {
+                                    state: ElementState::Pressed,
+                                    virtual_keycode: Some(VirtualKeyCode::F),
+                                    ..
+                                } => {
+                                    if crtl_clicked {
+                                        let camera = camera.read().unwrap();
+                                        *gbuffer.lock().unwrap() =
+                                            Some(viewer.render_gbuffer(&camera));
+                                        camera.film.reset_crop_window();
+                                        camera.film.clear();
+                                        viewer.state = renderer::ViewerState::RenderImage;
+                                        s.spawn(render_closure);
+                                    }
+                                }
                                 KeyboardInput {
                                     state: ElementState::Pressed,
                                     virtual_keycode: Some(VirtualKeyCode::G),
@@ -166,6 +274,70 @@ pub fn run(
                                         viewer.draw_wireframe = !viewer.draw_wireframe;
                                     }
                                 }
+                                KeyboardInput {
+                                    state: ElementState::Pressed,
+                                    virtual_keycode: Some(VirtualKeyCode::O),
+                                    ..
+                                } => {
+                                    if crtl_clicked {
+                                        draw_tiles.fetch_xor(true, Ordering::Relaxed);
+                                    }
+                                }
+                                KeyboardInput {
+                                    state: ElementState::Pressed,
+                                    virtual_keycode: Some(VirtualKeyCode::M),
+                                    ..
+                                } => {
+                                    if crtl_clicked {
+                                        let mut integrator = integrator.write().unwrap();
+                                        let camera = camera.read().unwrap();
+                                        if integrator.ray_stats().is_none() {
+                                            integrator.enable_ray_stats(&camera.film.resolution);
+                                            info!(
+                                                log,
+                                                "recording bvh traversal, render again to see its heat maps"
+                                            );
+                                        }
+                                        let next = (heat_map.load(Ordering::Relaxed) + 1) % HEAT_MAPS;
+                                        heat_map.store(next, Ordering::Relaxed);
+                                        tx.send(
+                                            heat_map_image(&integrator, next)
+                                                .unwrap_or_else(|| camera.film.to_rgba_image()),
+                                        )
+                                        .unwrap();
+                                    }
+                                }
+                                KeyboardInput {
+                                    state: ElementState::Pressed,
+                                    virtual_keycode: Some(VirtualKeyCode::K),
+                                    ..
+                                } => {
+                                    if crtl_clicked {
+                                        let integrator = integrator.read().unwrap();
+                                        let camera = camera.read().unwrap();
+                                        let mut tone_map = camera.film.tone_map();
+                                        tone_map.mapper = tone_map.mapper.next();
+                                        info!(log, "tone mapping with {:?}", tone_map.mapper);
+                                        camera.film.set_tone_map(tone_map);
+                                        tx.send(
+                                            heat_map_image(
+                                                &integrator,
+                                                heat_map.load(Ordering::Relaxed),
+                                            )
+                                            .unwrap_or_else(|| camera.film.to_rgba_image()),
+                                        )
+                                        .unwrap();
+                                    }
+                                }
+                                KeyboardInput {
+                                    state: ElementState::Pressed,
+                                    virtual_keycode: Some(VirtualKeyCode::Key3),
+                                    ..
+                                } => {
+                                    if crtl_clicked {
+                                        viewer.draw_anaglyph = !viewer.draw_anaglyph;
+                                    }
+                                }
                                 KeyboardInput {
                                     state: ElementState::Pressed,
                                     virtual_keycode: Some(VirtualKeyCode::H),
@@ -193,7 +365,9 @@ pub fn run(
                                     if crtl_clicked {
                                         info!(log, "saving image to {:?}", &output_path);
                                         let camera = camera.read().unwrap();
-                                        camera.film.to_rgba_image().save(&output_path).unwrap();
+                                        if let Err(e) = camera.film.save_image(&output_path) {
+                                            error!(log, "failed saving image: {:?}", e);
+                                        }
                                     }
                                 }
                                 KeyboardInput {
@@ -232,17 +406,23 @@ pub fn run(
                                             log,
                                             "pixel sample increment now {:?}", pixel_samples
                                         );
-                                        let camera = camera.read().unwrap();
                                         let mut integrator = integrator.write().unwrap();
+                                        let (n_light_samples, n_bsdf_samples) =
+                                            integrator.get_sample_splitting();
+                                        let transparent_background =
+                                            integrator.transparent_background();
+                                        let sampler_builder = integrator
+                                            .sampler_builder()
+                                            .with_samples_per_pixel(pixel_samples);
                                         *integrator = PathIntegrator::new(
                                             &log,
-                                            SamplerBuilder::new(
-                                                &log,
-                                                pixel_samples,
-                                                &camera.film.get_sample_bounds(),
-                                            ),
+                                            sampler_builder,
                                             max_depth as i32,
                                         );
+                                        integrator
+                                            .set_sample_splitting(n_light_samples, n_bsdf_samples);
+                                        integrator
+                                            .set_transparent_background(transparent_background);
                                         integrator.preprocess(&render_scene);
                                     }
                                     renderer::ViewerState::RenderImage => {}
@@ -258,17 +438,23 @@ pub fn run(
                                             log,
                                             "pixel sample increment now {:?}", pixel_samples
                                         );
-                                        let camera = camera.read().unwrap();
                                         let mut integrator = integrator.write().unwrap();
+                                        let (n_light_samples, n_bsdf_samples) =
+                                            integrator.get_sample_splitting();
+                                        let transparent_background =
+                                            integrator.transparent_background();
+                                        let sampler_builder = integrator
+                                            .sampler_builder()
+                                            .with_samples_per_pixel(pixel_samples);
                                         *integrator = PathIntegrator::new(
                                             &log,
-                                            SamplerBuilder::new(
-                                                &log,
-                                                pixel_samples,
-                                                &camera.film.get_sample_bounds(),
-                                            ),
+                                            sampler_builder,
                                             max_depth as i32,
                                         );
+                                        integrator
+                                            .set_sample_splitting(n_light_samples, n_bsdf_samples);
+                                        integrator
+                                            .set_transparent_background(transparent_background);
                                         integrator.preprocess(&render_scene);
                                     }
                                     renderer::ViewerState::RenderImage => {}
@@ -299,6 +485,17 @@ pub fn run(
                                     crtl_clicked = false;
                                 }
                             },
+                            WindowEvent::MouseInput {
+                                state: ElementState::Pressed,
+                                button: MouseButton::Left,
+                                ..
+                            } => {
+                                if let renderer::ViewerState::RenderImage = viewer.state {
+                                    if !crtl_clicked {
+                                        crop_start = Some(cursor_position);
+                                    }
+                                }
+                            }
                             WindowEvent::MouseInput {
                                 state: ElementState::Released,
                                 button: MouseButton::Left,
@@ -312,6 +509,36 @@ pub fn run(
                                     let camera = camera.read().unwrap();
                                     let integrator = integrator.read().unwrap();
                                     integrator.render_single_pixel(&camera, pixel, &render_scene);
+                                } else if let Some(start) = crop_start.take().filter(|start| {
+                                    (start.x - cursor_position.x).abs() >= 2.0
+                                        && (start.y - cursor_position.y).abs() >= 2.0
+                                }) {
+                                    // the rectangle dragged over the render is rendered again,
+                                    // the rest of the image stays
+                                    let camera = camera.read().unwrap();
+                                    let size = window.inner_size();
+                                    let corner = |x: f64, y: f64| {
+                                        na::Point2::new(
+                                            (x / size.width as f64) as f32,
+                                            (y / size.height as f64) as f32,
+                                        )
+                                    };
+                                    let min = corner(
+                                        start.x.min(cursor_position.x),
+                                        start.y.min(cursor_position.y),
+                                    );
+                                    let max = corner(
+                                        start.x.max(cursor_position.x),
+                                        start.y.max(cursor_position.y),
+                                    );
+                                    camera.film.set_crop_window(&min, &max);
+                                    info!(
+                                        log,
+                                        "rendering crop window {:?}",
+                                        camera.film.crop_bounds()
+                                    );
+                                    camera.film.clear();
+                                    s.spawn(render_closure);
                                 }
                             }
                             WindowEvent::CursorMoved { position, .. } => {
@@ -9,6 +9,59 @@ pub fn create_render_pipeline<T: Vertex>(
     primitive_topology: wgpu::PrimitiveTopology,
     depth_test: bool,
 ) -> wgpu::RenderPipeline {
+    build_render_pipeline::<T>(
+        device,
+        render_pipeline_layout,
+        vs_module,
+        fs_module,
+        primitive_topology,
+        depth_test,
+        false,
+        wgpu::CullMode::None,
+    )
+}
+
+/// pipeline for premultiplied alpha output, depth tested against opaque geometry but not written
+pub fn create_blended_render_pipeline<T: Vertex>(
+    device: &wgpu::Device,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    vs_module: &wgpu::ShaderModule,
+    fs_module: &wgpu::ShaderModule,
+    primitive_topology: wgpu::PrimitiveTopology,
+    cull_mode: wgpu::CullMode,
+) -> wgpu::RenderPipeline {
+    build_render_pipeline::<T>(
+        device,
+        render_pipeline_layout,
+        vs_module,
+        fs_module,
+        primitive_topology,
+        true,
+        true,
+        cull_mode,
+    )
+}
+
+fn build_render_pipeline<T: Vertex>(
+    device: &wgpu::Device,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    vs_module: &wgpu::ShaderModule,
+    fs_module: &wgpu::ShaderModule,
+    primitive_topology: wgpu::PrimitiveTopology,
+    depth_test: bool,
+    blend: bool,
+    cull_mode: wgpu::CullMode,
+) -> wgpu::RenderPipeline {
+    let color_blend = if blend {
+        wgpu::BlendDescriptor {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+            operation: wgpu::BlendOperation::Add,
+        }
+    } else {
+        wgpu::BlendDescriptor::REPLACE
+    };
+
     device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
         label: None,
         layout: Some(&render_pipeline_layout),
@@ -22,7 +75,7 @@ pub fn create_render_pipeline<T: Vertex>(
         }),
         rasterization_state: Some(wgpu::RasterizationStateDescriptor {
             front_face: wgpu::FrontFace::Ccw,
-            cull_mode: wgpu::CullMode::None,
+            cull_mode,
             depth_bias: 0,
             depth_bias_slope_scale: 0.0,
             depth_bias_clamp: 0.0,
@@ -30,15 +83,15 @@ pub fn create_render_pipeline<T: Vertex>(
         }),
         color_states: &[wgpu::ColorStateDescriptor {
             format: Texture::COLOR_FORMAT,
-            color_blend: wgpu::BlendDescriptor::REPLACE,
-            alpha_blend: wgpu::BlendDescriptor::REPLACE,
+            color_blend: color_blend.clone(),
+            alpha_blend: color_blend,
             write_mask: wgpu::ColorWrite::ALL,
         }],
         primitive_topology,
         depth_stencil_state: if depth_test {
             Some(wgpu::DepthStencilStateDescriptor {
                 format: Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
+                depth_write_enabled: !blend,
                 depth_compare: wgpu::CompareFunction::Less, // 1.
                 stencil: wgpu::StencilStateDescriptor::default(),
             })
@@ -54,3 +107,44 @@ pub fn create_render_pipeline<T: Vertex>(
         alpha_to_coverage_enabled: false, // 7.
     })
 }
+
+/// pipeline writing only depth, e.g. the back faces of meshes when culling `Front`
+pub fn create_depth_render_pipeline<T: Vertex>(
+    device: &wgpu::Device,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    vs_module: &wgpu::ShaderModule,
+    cull_mode: wgpu::CullMode,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: None,
+        layout: Some(&render_pipeline_layout),
+        vertex_stage: wgpu::ProgrammableStageDescriptor {
+            module: vs_module,
+            entry_point: "main",
+        },
+        fragment_stage: None,
+        rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode,
+            depth_bias: 0,
+            depth_bias_slope_scale: 0.0,
+            depth_bias_clamp: 0.0,
+            clamp_depth: false,
+        }),
+        color_states: &[],
+        primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+        depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilStateDescriptor::default(),
+        }),
+        vertex_state: wgpu::VertexStateDescriptor {
+            index_format: wgpu::IndexFormat::Uint32,
+            vertex_buffers: &[T::desc()],
+        },
+        sample_count: 1,
+        sample_mask: !0,
+        alpha_to_coverage_enabled: false,
+    })
+}
@@ -1,7 +1,7 @@
 use super::{pipeline::create_render_pipeline, shaders, texture, vertex::VertexPosTex};
 use wgpu::util::DeviceExt;
 
-const DEPTH_VERTICES: &[VertexPosTex] = &[
+pub const DEPTH_VERTICES: &[VertexPosTex] = &[
     VertexPosTex {
         position: [-1.0, -1.0, 0.0],
         tex_coords: [0.0, 1.0],
@@ -20,7 +20,7 @@ const DEPTH_VERTICES: &[VertexPosTex] = &[
     },
 ];
 
-const DEPTH_INDICES: &[u32] = &[0, 1, 2, 0, 2, 3];
+pub const DEPTH_INDICES: &[u32] = &[0, 1, 2, 0, 2, 3];
 
 pub struct QuadHandle {
     pub vertex_buffer: wgpu::Buffer,
@@ -1,12 +1,15 @@
 use std::sync::RwLock;
 
+use super::anaglyph::{AnaglyphRenderPass, DrawAnaglyph, StereoParameters};
 use super::bounds::{BoundsRenderPass, DrawBounds};
 use super::camera::{CameraController, CameraControllerInterface};
+use super::gbuffer::GBufferRenderPass;
 use super::mesh::{DrawMesh, MeshRenderPass};
 use super::quad::{DrawQuad, QuadRenderPass};
 use super::texture::Texture;
 use super::wireframe::{DrawWireFrame, WireFrameRenderPass};
 use crate::common::{bounds::Bounds3, Camera};
+use crate::pathtracer::{integrator::GBuffer, RenderScene};
 use wgpu::util::DeviceExt;
 use winit::{event::*, window::Window};
 
@@ -28,6 +31,8 @@ pub struct Mesh {
     pub s: Vec<na::Vector3<f32>>,
     pub uv: Vec<na::Point2<f32>>,
     pub colors: Vec<na::Vector3<f32>>,
+    /// transmission tint for glass/sss-like materials, `None` draws the mesh opaque
+    pub transmission: Option<na::Vector3<f32>>,
 
     pub instances: Vec<na::Projective3<f32>>,
 }
@@ -39,6 +44,8 @@ pub struct ViewerScene {
 #[derive(Debug, Copy, Clone)] // This is so we can store this in a buffer
 struct Uniforms {
     view_proj: glm::Mat4,
+    eye: glm::Vec4,
+    inv_view_proj: glm::Mat4,
 }
 
 unsafe impl bytemuck::Zeroable for Uniforms {}
@@ -74,19 +81,55 @@ impl Uniforms {
     fn new() -> Self {
         Self {
             view_proj: glm::Mat4::identity(),
+            eye: glm::Vec4::new(0.0, 0.0, 0.0, 1.0),
+            inv_view_proj: glm::Mat4::identity(),
         }
     }
 
     fn update_view_proj(&mut self, camera: &Camera) {
         self.view_proj = *OPENGL_TO_WGPU_MATRIX
-            * (camera.cam_to_screen.to_projective() * camera.cam_to_world.inverse())
-                .to_homogeneous();
+            * camera.preview_projection()
+            * camera.cam_to_world.inverse().to_homogeneous();
+        self.eye = camera
+            .cam_to_world
+            .transform_point(&na::Point3::origin())
+            .to_homogeneous();
+        self.update_inverse();
+    }
+
+    /// view of one eye `eye_offset` along the camera's x axis. the projection is shifted instead
+    /// of rotating the eyes inwards, so both images line up at `convergence` without vertical
+    /// parallax
+    fn update_eye_view_proj(&mut self, camera: &Camera, eye_offset: f32, convergence: f32) {
+        let eye_to_world = camera.cam_to_world * na::Translation3::new(eye_offset, 0.0, 0.0);
+        let cam_to_screen = camera.preview_projection();
+        let shift = glm::translation(&glm::vec3(
+            eye_offset * cam_to_screen[(0, 0)] / convergence,
+            0.0,
+            0.0,
+        ));
+        self.view_proj = *OPENGL_TO_WGPU_MATRIX
+            * shift
+            * cam_to_screen
+            * eye_to_world.inverse().to_homogeneous();
+        self.eye = eye_to_world
+            .transform_point(&na::Point3::origin())
+            .to_homogeneous();
+        self.update_inverse();
+    }
+
+    /// lets shaders turn depths back into world space positions
+    fn update_inverse(&mut self) {
+        self.inv_view_proj = self
+            .view_proj
+            .try_inverse()
+            .unwrap_or_else(glm::Mat4::identity);
     }
 
     pub fn create_bind_group_layout_entry() -> wgpu::BindGroupLayoutEntry {
         wgpu::BindGroupLayoutEntry {
             binding: 0,
-            visibility: wgpu::ShaderStage::VERTEX,
+            visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
             ty: wgpu::BindingType::UniformBuffer {
                 dynamic: false,
                 min_binding_size: None,
@@ -111,9 +154,14 @@ pub struct Renderer {
     bounds_render_pass: BoundsRenderPass,
     quad_render_pass: QuadRenderPass,
     wireframe_render_pass: WireFrameRenderPass,
+    anaglyph_render_pass: AnaglyphRenderPass,
+    gbuffer_render_pass: GBufferRenderPass,
     uniforms: Uniforms,
     uniform_buffer: wgpu::Buffer,
     uniform_bind_group: wgpu::BindGroup,
+    eye_uniform_buffers: [wgpu::Buffer; 2],
+    eye_bind_groups: [wgpu::BindGroup; 2],
+    stereo: StereoParameters,
     depth_texture: Texture,
     size: winit::dpi::PhysicalSize<u32>,
     camera_controller: CameraController,
@@ -122,6 +170,7 @@ pub struct Renderer {
     pub draw_wireframe: bool,
     pub draw_mesh: bool,
     pub draw_bounds: bool,
+    pub draw_anaglyph: bool,
     pub bounds_loaded: bool,
 }
 
@@ -130,8 +179,10 @@ impl Renderer {
         log: &slog::Logger,
         window: &Window,
         scene: &ViewerScene,
+        render_scene: &RenderScene,
         camera: &Camera,
         camera_controller: CameraController,
+        stereo: StereoParameters,
     ) -> Self {
         let log = log.new(o!("module" => "viewer"));
 
@@ -196,8 +247,32 @@ impl Renderer {
             label: Some("uniform_bind_group"),
         });
 
-        let mesh_render_pass =
-            MeshRenderPass::from_scene(&device, &mut compiler, &uniform_bind_group_layout, &scene);
+        let create_eye_uniforms = |label| {
+            let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: bytemuck::cast_slice(&[uniforms]),
+                usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            });
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &uniform_bind_group_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(buffer.slice(..)),
+                }],
+                label: Some(label),
+            });
+            (buffer, bind_group)
+        };
+        let (left_uniform_buffer, left_bind_group) = create_eye_uniforms("left_eye_uniforms");
+        let (right_uniform_buffer, right_bind_group) = create_eye_uniforms("right_eye_uniforms");
+
+        let mesh_render_pass = MeshRenderPass::from_scene(
+            &device,
+            &mut compiler,
+            &uniform_bind_group_layout,
+            &scene,
+            &sc_desc,
+        );
 
         let bounds_render_pass = BoundsRenderPass::from_bounds(
             &device,
@@ -213,6 +288,15 @@ impl Renderer {
             &scene,
         );
 
+        let anaglyph_render_pass = AnaglyphRenderPass::new(&device, &mut compiler, &sc_desc);
+
+        let gbuffer_render_pass = GBufferRenderPass::new(
+            &device,
+            &mut compiler,
+            &uniform_bind_group_layout,
+            render_scene,
+        );
+
         let depth_texture = Texture::create_depth_texture(&device, &sc_desc, "depth_texture");
 
         let rendered_texture = Texture::from_image(
@@ -236,9 +320,14 @@ impl Renderer {
             bounds_render_pass,
             quad_render_pass,
             wireframe_render_pass,
+            anaglyph_render_pass,
+            gbuffer_render_pass,
             uniforms,
             uniform_buffer,
             uniform_bind_group,
+            eye_uniform_buffers: [left_uniform_buffer, right_uniform_buffer],
+            eye_bind_groups: [left_bind_group, right_bind_group],
+            stereo,
             depth_texture,
             size,
             camera_controller,
@@ -247,6 +336,7 @@ impl Renderer {
             draw_wireframe: false,
             draw_mesh: true,
             draw_bounds: false,
+            draw_anaglyph: false,
             bounds_loaded: false,
         }
     }
@@ -257,6 +347,9 @@ impl Renderer {
         self.sc_desc.height = new_size.height;
         self.depth_texture =
             Texture::create_depth_texture(&self.device, &self.sc_desc, "depth_texture");
+        self.anaglyph_render_pass
+            .resize(&self.device, &self.sc_desc);
+        self.mesh_render_pass.resize(&self.device, &self.sc_desc);
         self.swap_chain = self.device.create_swap_chain(&self.surface, &self.sc_desc);
     }
 
@@ -351,6 +444,16 @@ impl Renderer {
                     0,
                     &bytemuck::cast_slice(&[self.uniforms]),
                 );
+
+                if self.draw_anaglyph {
+                    let (eye_offsets, convergence) = self.stereo.eye_offsets(&camera);
+                    for (buffer, eye_offset) in self.eye_uniform_buffers.iter().zip(&eye_offsets) {
+                        let mut uniforms = Uniforms::new();
+                        uniforms.update_eye_view_proj(&camera, *eye_offset, convergence);
+                        self.queue
+                            .write_buffer(buffer, 0, &bytemuck::cast_slice(&[uniforms]));
+                    }
+                }
             }
             _ => {}
         }
@@ -363,6 +466,23 @@ impl Renderer {
         }
     }
 
+    /// rasterizes the primary visibility of `camera` at its film resolution
+    pub fn render_gbuffer(&mut self, camera: &Camera) -> GBuffer {
+        self.uniforms.update_view_proj(&camera);
+        self.queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            &bytemuck::cast_slice(&[self.uniforms]),
+        );
+
+        self.gbuffer_render_pass.render(
+            &self.device,
+            &self.queue,
+            &self.uniform_bind_group,
+            &camera.film.resolution,
+        )
+    }
+
     pub fn render(&mut self) {
         match self.state {
             ViewerState::RenderScene => {
@@ -418,23 +538,46 @@ impl Renderer {
                 label: Some("Render Encoder"),
             });
 
-        {
+        if self.draw_anaglyph {
+            for (eye_texture, bind_group) in self
+                .anaglyph_render_pass
+                .eye_textures
+                .iter()
+                .zip(&self.eye_bind_groups)
+            {
+                self.draw_scene(&mut encoder, &eye_texture.view, bind_group);
+            }
+
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
                     attachment: &frame.view,
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.5,
-                            g: 0.5,
-                            b: 0.5,
-                            a: 1.0,
-                        }),
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
                         store: true,
                     },
                 }],
+                depth_stencil_attachment: None,
+            });
+            render_pass.draw_anaglyph(&self.anaglyph_render_pass);
+        } else {
+            self.draw_scene(&mut encoder, &frame.view, &self.uniform_bind_group);
+        }
+
+        self.queue.submit(Some(encoder.finish()));
+    }
+
+    fn draw_scene(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        uniform_bind_group: &wgpu::BindGroup,
+    ) {
+        if self.draw_mesh && self.mesh_render_pass.has_translucent() {
+            let mut depth_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
-                    attachment: &self.depth_texture.view,
+                    attachment: &self.mesh_render_pass.back_depth.view,
                     depth_ops: Some(wgpu::Operations {
                         load: wgpu::LoadOp::Clear(1.0),
                         store: true,
@@ -442,19 +585,43 @@ impl Renderer {
                     stencil_ops: None,
                 }),
             });
-
-            render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
-            if self.draw_mesh {
-                render_pass.draw_all_mesh(&self.mesh_render_pass);
-            }
-            if self.draw_bounds {
-                render_pass.draw_all_bounds(&self.bounds_render_pass);
-            }
-            if self.draw_wireframe {
-                render_pass.draw_all_wire_frame(&self.wireframe_render_pass);
-            }
+            depth_pass.set_bind_group(0, uniform_bind_group, &[]);
+            depth_pass.draw_back_depth(&self.mesh_render_pass);
         }
 
-        self.queue.submit(Some(encoder.finish()));
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: 0.5,
+                        g: 0.5,
+                        b: 0.5,
+                        a: 1.0,
+                    }),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        render_pass.set_bind_group(0, uniform_bind_group, &[]);
+        if self.draw_mesh {
+            render_pass.draw_all_mesh(&self.mesh_render_pass);
+        }
+        if self.draw_bounds {
+            render_pass.draw_all_bounds(&self.bounds_render_pass);
+        }
+        if self.draw_wireframe {
+            render_pass.draw_all_wire_frame(&self.wireframe_render_pass);
+        }
     }
 }
@@ -0,0 +1,58 @@
+lazy_static::lazy_static! {
+    static ref VERTEX: String =
+    "
+#version 450
+
+layout(location=0) in vec3 a_position;
+layout(location=1) in vec2 a_tex_coords;
+
+layout(location=0) out vec2 v_tex_coords;
+
+void main() {
+    v_tex_coords = a_tex_coords;
+    gl_Position = vec4(a_position, 1.0);
+}
+    ".to_string();
+
+    // half color anaglyph, the left eye only contributes its luminance to the red channel which
+    // keeps saturated reds from showing in one eye only
+    static ref FRAGMENT: String =
+    "
+#version 450
+
+layout(location=0) in vec2 v_tex_coords;
+
+layout(location=0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform texture2D t_left;
+layout(set = 0, binding = 1) uniform texture2D t_right;
+layout(set = 0, binding = 2) uniform sampler s_eye;
+
+void main() {
+    vec3 left = texture(sampler2D(t_left, s_eye), v_tex_coords).rgb;
+    vec3 right = texture(sampler2D(t_right, s_eye), v_tex_coords).rgb;
+    f_color = vec4(dot(left, vec3(0.299, 0.587, 0.114)), right.g, right.b, 1.0);
+}
+    ".to_string();
+}
+
+pub fn compile_shaders(
+    compiler: &mut shaderc::Compiler,
+    device: &wgpu::Device,
+) -> (wgpu::ShaderModule, wgpu::ShaderModule) {
+    let vert = super::compile_shader(
+        &VERTEX,
+        "anaglyph.vert",
+        shaderc::ShaderKind::Vertex,
+        compiler,
+        device,
+    );
+    let frag = super::compile_shader(
+        &FRAGMENT,
+        "anaglyph.frag",
+        shaderc::ShaderKind::Fragment,
+        compiler,
+        device,
+    );
+    (vert, frag)
+}
@@ -0,0 +1,102 @@
+lazy_static::lazy_static! {
+    // the triangles are pulled from storage buffers rather than vertex buffers, each vertex
+    // knows the triangle it belongs to and which of its corners it is
+    static ref VERTEX: String =
+    "
+#version 450
+
+layout(binding=0)
+uniform Uniforms {
+    mat4 u_view_proj;
+};
+layout(set=1, binding=0)
+readonly buffer Positions {
+    float s_positions[];
+};
+layout(set=1, binding=1)
+readonly buffer Indices {
+    uint s_indices[];
+};
+layout(set=1, binding=2)
+readonly buffer Instances {
+    mat4 s_models[];
+};
+
+layout(location=0) out vec3 v_position;
+layout(location=1) flat out vec3 v_normal;
+layout(location=2) out vec2 v_barycentrics;
+layout(location=3) flat out uint v_instance;
+layout(location=4) flat out uint v_triangle;
+
+vec3 corner(uint idx) {
+    uint v = 3 * s_indices[idx];
+    return vec3(s_positions[v], s_positions[v + 1], s_positions[v + 2]);
+}
+
+void main() {
+    uint triangle = uint(gl_VertexIndex) / 3;
+    uint k = uint(gl_VertexIndex) % 3;
+    vec3 p0 = corner(3 * triangle);
+    vec3 p1 = corner(3 * triangle + 1);
+    vec3 p2 = corner(3 * triangle + 2);
+
+    mat4 model = s_models[gl_InstanceIndex];
+    vec4 world_position = model * vec4(k == 0 ? p0 : (k == 1 ? p1 : p2), 1.0);
+    v_position = world_position.xyz;
+    v_normal = transpose(inverse(mat3(model))) * cross(p1 - p0, p2 - p0);
+    v_barycentrics = vec2(k == 1 ? 1.0 : 0.0, k == 2 ? 1.0 : 0.0);
+    v_instance = uint(gl_InstanceIndex);
+    v_triangle = triangle;
+    gl_Position = u_view_proj * world_position;
+}
+    ".to_string();
+
+    // world space position and geometric normal with the barycentrics in their w, and the ids of
+    // the mesh, its copy and the triangle
+    static ref FRAGMENT: String =
+    "
+#version 450
+
+layout(location=0) in vec3 v_position;
+layout(location=1) flat in vec3 v_normal;
+layout(location=2) in vec2 v_barycentrics;
+layout(location=3) flat in uint v_instance;
+layout(location=4) flat in uint v_triangle;
+
+layout(set=2, binding=0)
+uniform MeshId {
+    uint u_mesh_id;
+};
+
+layout(location=0) out vec4 f_position;
+layout(location=1) out vec4 f_normal;
+layout(location=2) out uvec4 f_ids;
+
+void main() {
+    f_position = vec4(v_position, v_barycentrics.x);
+    f_normal = vec4(normalize(v_normal), v_barycentrics.y);
+    f_ids = uvec4(u_mesh_id, v_instance, v_triangle, 0);
+}
+    ".to_string();
+}
+
+pub fn compile_shaders(
+    compiler: &mut shaderc::Compiler,
+    device: &wgpu::Device,
+) -> (wgpu::ShaderModule, wgpu::ShaderModule) {
+    let vert = super::compile_shader(
+        &VERTEX,
+        "gbuffer.vert",
+        shaderc::ShaderKind::Vertex,
+        compiler,
+        device,
+    );
+    let frag = super::compile_shader(
+        &FRAGMENT,
+        "gbuffer.frag",
+        shaderc::ShaderKind::Fragment,
+        compiler,
+        device,
+    );
+    (vert, frag)
+}
@@ -1,7 +1,10 @@
+pub mod anaglyph;
 pub mod flat;
 pub mod flat_instance;
+pub mod gbuffer;
 pub mod phong;
 pub mod quad;
+pub mod translucent;
 
 fn compile_shader(
     source_text: &str,
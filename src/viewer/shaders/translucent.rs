@@ -0,0 +1,113 @@
+lazy_static::lazy_static! {
+    static ref VERTEX: String =
+    "
+#version 450
+
+layout(location=0) in vec3 a_position;
+layout(location=1) in vec3 a_normal;
+
+layout(binding=0)
+uniform Uniforms {
+    mat4 u_view_proj;
+    vec4 u_eye;
+};
+layout(set=1, binding=0)
+buffer Instances {
+    mat4 s_models[];
+};
+
+layout(location=0) out vec3 v_position;
+layout(location=1) out vec3 v_normal;
+
+void main() {
+    mat4 model = s_models[gl_InstanceIndex];
+    vec4 world_position = model * vec4(a_position, 1.0);
+    v_normal = mat3(model) * a_normal;
+    v_position = world_position.xyz;
+    gl_Position = u_view_proj * world_position;
+}
+    ".to_string();
+
+    static ref FRAGMENT: String =
+    "
+#version 450
+
+layout(location=0) in vec3 v_position;
+layout(location=1) in vec3 v_normal;
+
+layout(binding=0)
+uniform Uniforms {
+    mat4 u_view_proj;
+    vec4 u_eye;
+    mat4 u_inv_view_proj;
+};
+layout(set=2, binding=0)
+uniform Translucency {
+    vec4 u_tint;
+};
+layout(set=3, binding=0) uniform texture2D t_back_depth;
+layout(set=3, binding=1) uniform sampler s_back_depth;
+
+layout(location=0) out vec4 f_color;
+
+void main() {
+    vec3 light_color = vec3(1.0, 1.0, 1.0);
+    vec3 light_position = vec3(10.0, -10.0, 10.0);
+
+    vec3 normal = normalize(v_normal);
+    vec3 view_dir = normalize(u_eye.xyz - v_position);
+    vec3 light_dir = normalize(light_position - v_position);
+
+    // the path through the mesh runs to the nearest back face behind this pixel, taken from
+    // the depth pre-pass of the translucent back faces
+    float back_depth = texelFetch(sampler2D(t_back_depth, s_back_depth), ivec2(gl_FragCoord.xy), 0).r;
+    float thickness;
+    if (back_depth > gl_FragCoord.z && back_depth < 1.0) {
+        vec2 size = vec2(textureSize(sampler2D(t_back_depth, s_back_depth), 0));
+        vec2 ndc = vec2(2.0 * gl_FragCoord.x / size.x - 1.0, 1.0 - 2.0 * gl_FragCoord.y / size.y);
+        vec4 back_position = u_inv_view_proj * vec4(ndc, back_depth, 1.0);
+        thickness = distance(back_position.xyz / back_position.w, v_position);
+    } else {
+        // open surfaces have no back face, they're drawn as a slab of unit thickness
+        thickness = 1.0 / max(abs(dot(normal, view_dir)), 0.1);
+    }
+
+    // beer-lambert with the tint as the transmittance over unit distance, plus a floor so
+    // clear glass stays visible
+    vec3 sigma = -log(max(u_tint.rgb, vec3(0.01))) + vec3(0.1);
+    vec3 transmittance = exp(-sigma * thickness);
+    float opacity = 1.0 - (transmittance.r + transmittance.g + transmittance.b) / 3.0;
+
+    float diffuse_strength = abs(dot(normal, light_dir));
+    vec3 half_dir = normalize(light_dir + view_dir);
+    float specular_strength = pow(abs(dot(normal, half_dir)), 64.0);
+
+    vec3 result = (0.1 + 0.5 * diffuse_strength) * u_tint.rgb * opacity
+        + specular_strength * light_color;
+
+    // output is premultiplied, the background is attenuated by opacity when blended
+    f_color = vec4(result, clamp(opacity, 0.0, 1.0));
+}
+    ".to_string();
+}
+
+pub fn compile_shaders(
+    compiler: &mut shaderc::Compiler,
+    device: &wgpu::Device,
+) -> (wgpu::ShaderModule, wgpu::ShaderModule) {
+    let vert = super::compile_shader(
+        &VERTEX,
+        "translucent.vert",
+        shaderc::ShaderKind::Vertex,
+        compiler,
+        device,
+    );
+    let frag = super::compile_shader(
+        &FRAGMENT,
+        "translucent.frag",
+        shaderc::ShaderKind::Fragment,
+        compiler,
+        device,
+    );
+    (vert, frag)
+}